@@ -108,6 +108,8 @@ pub struct Process {
         skip_serializing_if = "Option::is_none"
     )]
     pub oom_score_adj: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheduler: Option<Scheduler>,
     #[serde(
         default,
         rename = "selinuxLabel",
@@ -130,6 +132,16 @@ pub struct LinuxCapabilities {
     pub ambient: Vec<String>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Scheduler {
+    #[serde(default)]
+    pub policy: String,
+    #[serde(default)]
+    pub nice: i32,
+    #[serde(default)]
+    pub priority: i32,
+}
+
 #[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
 pub struct Box {
     #[serde(default)]
@@ -388,6 +400,9 @@ pub struct LinuxMemory {
         rename = "disableOOMKiller"
     )]
     pub disable_oom_killer: Option<bool>,
+    // the throttling limit of cgroup v2, memory.high
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub high: Option<i64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
@@ -414,6 +429,16 @@ pub struct LinuxCpu {
     pub cpus: String,
     #[serde(default, skip_serializing_if = "String::is_empty")]
     pub mems: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub burst: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle: Option<i64>,
+    #[serde(
+        default,
+        skip_serializing_if = "String::is_empty",
+        rename = "cpusPartition"
+    )]
+    pub cpus_partition: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
@@ -468,6 +493,11 @@ pub struct LinuxResources {
     pub network: Option<LinuxNetwork>,
     #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     pub rdma: HashMap<String, LinuxRdma>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub unified: HashMap<String, String>,
+    // limits of the misc controller resources, e.g. "sev"
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub misc: HashMap<String, u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
@@ -790,12 +820,20 @@ pub struct LinuxSyscall {
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
 pub struct LinuxIntelRdt {
+    #[serde(default, skip_serializing_if = "String::is_empty", rename = "closID")]
+    pub clos_id: String,
     #[serde(
         default,
         skip_serializing_if = "String::is_empty",
         rename = "l3CacheSchema"
     )]
     pub l3_cache_schema: String,
+    #[serde(
+        default,
+        skip_serializing_if = "String::is_empty",
+        rename = "memBwSchema"
+    )]
+    pub mem_bw_schema: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq)]
@@ -919,6 +957,10 @@ mod tests {
                 ],
                 "apparmorProfile": "acme_secure_profile",
                 "oomScoreAdj": 100,
+                "scheduler": {
+                    "policy": "SCHED_BATCH",
+                    "nice": 10
+                },
                 "selinuxLabel": "system_u:system_r:svirt_lxc_net_t:s0:c124,c675",
                 "noNewPrivileges": true
             },
@@ -1285,6 +1327,11 @@ mod tests {
                 no_new_privileges: true,
                 apparmor_profile: "acme_secure_profile".to_string(),
                 oom_score_adj: Some(100),
+                scheduler: Some(crate::Scheduler {
+                    policy: "SCHED_BATCH".to_string(),
+                    nice: 10,
+                    priority: 0,
+                }),
                 selinux_label: "system_u:system_r:svirt_lxc_net_t:s0:c124,c675".to_string(),
             }),
             root: Some(crate::Root {
@@ -1457,6 +1504,7 @@ mod tests {
                         kernel_tcp: Some(-1),
                         swappiness: Some(0),
                         disable_oom_killer: Some(false),
+                        high: None,
                     }),
                     cpu: Some(crate::LinuxCpu {
                         shares: Some(1024),
@@ -1466,6 +1514,9 @@ mod tests {
                         realtime_period: Some(1000000),
                         cpus: "2-3".to_string(),
                         mems: "0-7".to_string(),
+                        burst: None,
+                        idle: None,
+                        cpus_partition: "".to_string(),
                     }),
                     pids: Some(crate::LinuxPids { limit: 32771 }),
                     block_io: Some(crate::LinuxBlockIo {
@@ -1524,6 +1575,8 @@ mod tests {
                         ],
                     }),
                     rdma: Default::default(),
+                    unified: Default::default(),
+                    misc: Default::default(),
                 }),
                 cgroups_path: "/myRuntime/myContainer".to_string(),
                 namespaces: vec![
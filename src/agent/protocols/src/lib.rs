@@ -12,3 +12,4 @@ pub mod health;
 pub mod health_ttrpc;
 pub mod oci;
 pub mod types;
+pub mod version;
@@ -0,0 +1,197 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Versioning of the agent API.
+//
+// The major version changes when the protocol breaks compatibility, the
+// minor one when it gains RPCs or fields. The optional capabilities are
+// also advertised as named features, so that a runtime can tell what the
+// agent it talks to supports and fall back to the older behaviour.
+
+use crate::agent::{APIVersion, NegotiateAPIVersionRequest, NegotiateAPIVersionResponse};
+use protobuf::RepeatedField;
+
+pub const API_VERSION_MAJOR: u32 = 1;
+pub const API_VERSION_MINOR: u32 = 1;
+
+// GetCgroupEvent reports OOM and memory pressure events.
+pub const FEATURE_CGROUP_EVENTS: &str = "cgroup_events";
+// StatsContainers returns the stats of several containers at once.
+pub const FEATURE_STATS_CONTAINERS: &str = "stats_containers";
+// StatsContainerResponse lists the resources which couldn't be applied.
+pub const FEATURE_UNAPPLIED_RESOURCES: &str = "unapplied_resources";
+// The linux.intelRdt section of the spec is applied.
+pub const FEATURE_INTEL_RDT: &str = "intel_rdt";
+// The vsock ports of the auxiliary channels are allocated by the agent.
+pub const FEATURE_VSOCK_PORTS: &str = "vsock_ports";
+// The readiness and liveness probes of the containers run in the guest.
+pub const FEATURE_PROBES: &str = "probes";
+// The misc cgroup resources are set and reported in the stats.
+pub const FEATURE_MISC_CGROUP: &str = "misc_cgroup";
+// The agent keeps a short history of the usage of the containers.
+pub const FEATURE_USAGE_HISTORY: &str = "usage_history";
+// Several containers can be started at once with StartContainers.
+pub const FEATURE_START_CONTAINERS: &str = "start_containers";
+// The mount table of a container can be listed.
+pub const FEATURE_CONTAINER_MOUNTS: &str = "container_mounts";
+// Paths inside the containers can be watched for changes.
+pub const FEATURE_FS_WATCH: &str = "fs_watch";
+// The containers are grouped under a sandbox cgroup, which can be limited.
+pub const FEATURE_SANDBOX_CGROUP: &str = "sandbox_cgroup";
+// The memory of the container processes can be dumped, if allowed.
+pub const FEATURE_MEMORY_DUMP: &str = "memory_dump";
+// The timezone and RTC mode of the guest can be set.
+pub const FEATURE_GUEST_CLOCK: &str = "guest_clock";
+// The pids limit hits are counted in the stats and sent as cgroup events.
+pub const FEATURE_PIDS_EVENTS: &str = "pids_events";
+// The degradations of the requests are sent as warnings.
+pub const FEATURE_WARNINGS: &str = "warnings";
+// The guest details tell the cgroup mode and controllers of the guest.
+pub const FEATURE_CGROUP_DETAILS: &str = "cgroup_details";
+// The loop storage driver attaches the image files to loop devices.
+pub const FEATURE_LOOP_STORAGE: &str = "loop_storage";
+// The overlayfs storage driver stacks the layers, which are checked against
+// their fs-verity digest if any.
+pub const FEATURE_LAYERED_ROOTFS: &str = "layered_rootfs";
+// The containers honor the scheduler of their spec and can be reniced.
+pub const FEATURE_CONTAINER_SCHEDULER: &str = "container_scheduler";
+// The container resources can be validated without applying them.
+pub const FEATURE_VALIDATE_RESOURCES: &str = "validate_resources";
+// The device cgroup rules of the containers can be updated at runtime.
+pub const FEATURE_DEVICE_RULES: &str = "device_rules";
+// Several logical sandboxes can share the guest, with agent.multi_sandbox.
+pub const FEATURE_MULTI_SANDBOX: &str = "multi_sandbox";
+// The early boot logs of the guest are served by GetBootLogs, in chunks.
+pub const FEATURE_BOOT_LOGS: &str = "boot_logs";
+// The metrics are sampled on a period and handed out by GetMetricsPush.
+pub const FEATURE_METRICS_PUSH: &str = "metrics_push";
+// GetMetrics takes the format and the collectors to return.
+pub const FEATURE_METRICS_FORMAT: &str = "metrics_format";
+// The memory protection and throttling limits are set and reported, i.e.
+// memory.low, memory.min and memory.high.
+pub const FEATURE_MEMORY_QOS: &str = "memory_qos";
+// The memory stats carry the memory event counters.
+pub const FEATURE_MEMORY_EVENTS: &str = "memory_events";
+// The OOM killer can kill the whole cgroup of a container at once.
+pub const FEATURE_OOM_GROUP: &str = "oom_group";
+// The CFS burst and idle cgroups are set and reported.
+pub const FEATURE_CPU_BURST: &str = "cpu_burst";
+// The cgroup v2 block IO weights go to BFQ, and iocost can be enabled.
+pub const FEATURE_IO_COST: &str = "io_cost";
+// The reserved huge pages are limited and reported.
+pub const FEATURE_HUGETLB_RESERVATIONS: &str = "hugetlb_reservations";
+// The stats of the exec'd processes are reported apart.
+pub const FEATURE_EXEC_CGROUP_STATS: &str = "exec_cgroup_stats";
+// The stats of the containers carry the ones of their network interfaces.
+pub const FEATURE_NETWORK_STATS: &str = "network_stats";
+// The cgroup errors are mapped to the ttrpc status codes, e.g. a frozen
+// container fails with FAILED_PRECONDITION.
+pub const FEATURE_CGROUP_ERROR_CODES: &str = "cgroup_error_codes";
+
+pub const FEATURES: &[&str] = &[
+    FEATURE_CGROUP_EVENTS,
+    FEATURE_STATS_CONTAINERS,
+    FEATURE_UNAPPLIED_RESOURCES,
+    FEATURE_INTEL_RDT,
+    FEATURE_VSOCK_PORTS,
+    FEATURE_PROBES,
+    FEATURE_MISC_CGROUP,
+    FEATURE_USAGE_HISTORY,
+    FEATURE_START_CONTAINERS,
+    FEATURE_CONTAINER_MOUNTS,
+    FEATURE_FS_WATCH,
+    FEATURE_SANDBOX_CGROUP,
+    FEATURE_MEMORY_DUMP,
+    FEATURE_GUEST_CLOCK,
+    FEATURE_PIDS_EVENTS,
+    FEATURE_WARNINGS,
+    FEATURE_CGROUP_DETAILS,
+    FEATURE_LOOP_STORAGE,
+    FEATURE_LAYERED_ROOTFS,
+    FEATURE_CONTAINER_SCHEDULER,
+    FEATURE_VALIDATE_RESOURCES,
+    FEATURE_DEVICE_RULES,
+    FEATURE_MULTI_SANDBOX,
+    FEATURE_BOOT_LOGS,
+    FEATURE_METRICS_PUSH,
+    FEATURE_METRICS_FORMAT,
+    FEATURE_MEMORY_QOS,
+    FEATURE_MEMORY_EVENTS,
+    FEATURE_OOM_GROUP,
+    FEATURE_CPU_BURST,
+    FEATURE_IO_COST,
+    FEATURE_HUGETLB_RESERVATIONS,
+    FEATURE_EXEC_CGROUP_STATS,
+    FEATURE_NETWORK_STATS,
+    FEATURE_CGROUP_ERROR_CODES,
+];
+
+pub fn api_version() -> APIVersion {
+    let mut v = APIVersion::new();
+    v.major = API_VERSION_MAJOR;
+    v.minor = API_VERSION_MINOR;
+    v
+}
+
+// negotiate returns the API version and the features both sides support,
+// or an error if the caller speaks another major version of the API.
+pub fn negotiate(req: &NegotiateAPIVersionRequest) -> Result<NegotiateAPIVersionResponse, String> {
+    let peer = req.get_version();
+    if peer.major != API_VERSION_MAJOR {
+        return Err(format!(
+            "unsupported API version {}.{}, the agent implements {}.{}",
+            peer.major, peer.minor, API_VERSION_MAJOR, API_VERSION_MINOR
+        ));
+    }
+
+    let mut version = api_version();
+    version.minor = version.minor.min(peer.minor);
+
+    let features = req
+        .features
+        .iter()
+        .filter(|f| FEATURES.contains(&f.as_str()))
+        .cloned()
+        .collect();
+
+    let mut resp = NegotiateAPIVersionResponse::new();
+    resp.set_agent_version(api_version());
+    resp.set_version(version);
+    resp.features = RepeatedField::from_vec(features);
+
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(major: u32, minor: u32, features: &[&str]) -> NegotiateAPIVersionRequest {
+        let mut v = APIVersion::new();
+        v.major = major;
+        v.minor = minor;
+
+        let mut req = NegotiateAPIVersionRequest::new();
+        req.set_version(v);
+        req.features = features.iter().map(|f| f.to_string()).collect();
+        req
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let resp = negotiate(&request(
+            API_VERSION_MAJOR,
+            API_VERSION_MINOR + 1,
+            &[FEATURE_CGROUP_EVENTS, "unknown"],
+        ))
+        .unwrap();
+
+        assert_eq!(resp.get_version().minor, API_VERSION_MINOR);
+        assert_eq!(resp.get_agent_version(), &api_version());
+        assert_eq!(resp.features.to_vec(), vec![FEATURE_CGROUP_EVENTS]);
+
+        assert!(negotiate(&request(API_VERSION_MAJOR + 1, 0, &[])).is_err());
+    }
+}
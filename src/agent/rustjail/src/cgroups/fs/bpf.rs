@@ -0,0 +1,294 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Minimal eBPF support to load the programs cgroup v2 uses in place of
+// some of the cgroup v1 controllers, attach them to a cgroup, and read
+// back the ones attached.
+
+use anyhow::{anyhow, Context, Result};
+use nix::errno::Errno;
+use std::ffi::CString;
+use std::fs::File;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::Path;
+
+// opcodes, see include/uapi/linux/bpf_common.h
+pub const BPF_LDX_MEM_W: u8 = 0x61;
+pub const BPF_STX_MEM_W: u8 = 0x63;
+pub const BPF_ALU_AND_K: u8 = 0x54;
+pub const BPF_ALU_RSH_K: u8 = 0x74;
+pub const BPF_ALU_MOV_K: u8 = 0xb4;
+pub const BPF_ALU_MOV_X: u8 = 0xbc;
+pub const BPF_JMP_JNE_K: u8 = 0x55;
+pub const BPF_JMP_JNE_X: u8 = 0x5d;
+pub const BPF_JMP_EXIT: u8 = 0x95;
+
+pub const R0: u8 = 0;
+pub const R1: u8 = 1;
+pub const R2: u8 = 2;
+pub const R3: u8 = 3;
+pub const R4: u8 = 4;
+pub const R5: u8 = 5;
+
+// bpf(2) commands and flags
+const BPF_PROG_LOAD: libc::c_int = 5;
+const BPF_PROG_ATTACH: libc::c_int = 8;
+const BPF_PROG_DETACH: libc::c_int = 9;
+const BPF_PROG_GET_FD_BY_ID: libc::c_int = 13;
+const BPF_OBJ_GET_INFO_BY_FD: libc::c_int = 15;
+const BPF_PROG_QUERY: libc::c_int = 16;
+const BPF_F_ALLOW_MULTI: u32 = 2;
+
+const MAX_ATTACHED_PROGS: usize = 64;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BpfInsn {
+    code: u8,
+    regs: u8,
+    pub off: i16,
+    imm: i32,
+}
+
+impl BpfInsn {
+    pub fn new(code: u8, dst: u8, src: u8, off: i16, imm: i32) -> Self {
+        BpfInsn {
+            code,
+            regs: (src << 4) | (dst & 0x0f),
+            off,
+            imm,
+        }
+    }
+
+    pub fn is_jump(&self) -> bool {
+        self.code == BPF_JMP_JNE_K || self.code == BPF_JMP_JNE_X
+    }
+}
+
+pub fn mov_imm(dst: u8, imm: i32) -> BpfInsn {
+    BpfInsn::new(BPF_ALU_MOV_K, dst, 0, 0, imm)
+}
+
+pub fn exit() -> BpfInsn {
+    BpfInsn::new(BPF_JMP_EXIT, 0, 0, 0, 0)
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct ProgLoadAttr {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct ProgAttachAttr {
+    target_fd: u32,
+    attach_bpf_fd: u32,
+    attach_type: u32,
+    attach_flags: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct ProgQueryAttr {
+    target_fd: u32,
+    attach_type: u32,
+    query_flags: u32,
+    attach_flags: u32,
+    prog_ids: u64,
+    prog_cnt: u32,
+    pad: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct ProgGetFdByIdAttr {
+    prog_id: u32,
+    next_id: u32,
+    open_flags: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct ObjGetInfoAttr {
+    bpf_fd: u32,
+    info_len: u32,
+    info: u64,
+}
+
+// the head of struct bpf_prog_info, the kernel fills what fits in info_len
+#[repr(C)]
+#[derive(Default)]
+struct ProgInfo {
+    prog_type: u32,
+    id: u32,
+    tag: [u8; 8],
+    jited_prog_len: u32,
+    xlated_prog_len: u32,
+    jited_prog_insns: u64,
+    xlated_prog_insns: u64,
+}
+
+fn bpf<T>(cmd: libc::c_int, attr: &mut T) -> Result<libc::c_long> {
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            cmd,
+            attr as *mut T as *mut libc::c_void,
+            mem::size_of::<T>(),
+        )
+    };
+
+    Errno::result(ret).map_err(|e| anyhow!("bpf command {} failed: {}", cmd, e))
+}
+
+fn load_program(prog_type: u32, insns: &[BpfInsn]) -> Result<File> {
+    let license = CString::new("Apache").unwrap();
+    let mut attr = ProgLoadAttr {
+        prog_type,
+        insn_cnt: insns.len() as u32,
+        insns: insns.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        ..Default::default()
+    };
+
+    let fd = bpf(BPF_PROG_LOAD, &mut attr)
+        .with_context(|| format!("load program of type {}", prog_type))?;
+
+    Ok(unsafe { File::from_raw_fd(fd as RawFd) })
+}
+
+fn query_programs(cgroup_fd: RawFd, attach_type: u32) -> Result<Vec<u32>> {
+    let mut ids = vec![0u32; MAX_ATTACHED_PROGS];
+    let mut attr = ProgQueryAttr {
+        target_fd: cgroup_fd as u32,
+        attach_type,
+        prog_ids: ids.as_mut_ptr() as u64,
+        prog_cnt: ids.len() as u32,
+        ..Default::default()
+    };
+
+    bpf(BPF_PROG_QUERY, &mut attr)
+        .with_context(|| format!("query programs attached as {}", attach_type))?;
+    ids.truncate(attr.prog_cnt as usize);
+
+    Ok(ids)
+}
+
+fn get_program(id: u32) -> Result<File> {
+    let mut attr = ProgGetFdByIdAttr {
+        prog_id: id,
+        ..Default::default()
+    };
+    let fd = bpf(BPF_PROG_GET_FD_BY_ID, &mut attr)?;
+
+    Ok(unsafe { File::from_raw_fd(fd as RawFd) })
+}
+
+fn get_program_info(prog: &File, info: &mut ProgInfo) -> Result<()> {
+    let mut attr = ObjGetInfoAttr {
+        bpf_fd: prog.as_raw_fd() as u32,
+        info_len: mem::size_of::<ProgInfo>() as u32,
+        info: info as *mut ProgInfo as u64,
+    };
+    bpf(BPF_OBJ_GET_INFO_BY_FD, &mut attr)?;
+
+    Ok(())
+}
+
+// program_insns returns the instructions of the loaded program `id`, as
+// translated by the kernel, which leaves the ones of the cgroup programs
+// generated here as they are.
+fn program_insns(id: u32) -> Result<Vec<BpfInsn>> {
+    let prog = get_program(id)?;
+
+    // the first call gets the size, the second one the instructions
+    let mut info = ProgInfo::default();
+    get_program_info(&prog, &mut info)?;
+
+    let mut insns =
+        vec![BpfInsn::default(); info.xlated_prog_len as usize / mem::size_of::<BpfInsn>()];
+    let mut info = ProgInfo {
+        xlated_prog_len: (insns.len() * mem::size_of::<BpfInsn>()) as u32,
+        xlated_prog_insns: insns.as_mut_ptr() as u64,
+        ..Default::default()
+    };
+    get_program_info(&prog, &mut info)?;
+
+    // the kernel holds the instructions back from the callers without
+    // the capabilities, leaving none or zeroes, which match no program
+    insns.truncate(info.xlated_prog_len as usize / mem::size_of::<BpfInsn>());
+
+    Ok(insns)
+}
+
+fn detach_program(cgroup_fd: RawFd, attach_type: u32, id: u32) -> Result<()> {
+    let prog = get_program(id)?;
+
+    let mut attr = ProgAttachAttr {
+        target_fd: cgroup_fd as u32,
+        attach_bpf_fd: prog.as_raw_fd() as u32,
+        attach_type,
+        ..Default::default()
+    };
+    bpf(BPF_PROG_DETACH, &mut attr)?;
+
+    Ok(())
+}
+
+// attached_programs returns the instructions of the programs attached to
+// the cgroup at `dir` with `attach_type`.
+pub fn attached_programs(dir: &Path, attach_type: u32) -> Result<Vec<Vec<BpfInsn>>> {
+    let cgroup = File::open(dir).with_context(|| format!("failed to open {:?}", dir))?;
+
+    query_programs(cgroup.as_raw_fd(), attach_type)?
+        .into_iter()
+        .map(|id| program_insns(id).with_context(|| format!("read program {}", id)))
+        .collect()
+}
+
+// replace_programs loads `insns` as a program of type `prog_type` and
+// attaches it to the cgroup at `dir`, replacing the programs attached
+// there before with the same `attach_type`.
+pub fn replace_programs(
+    dir: &Path,
+    prog_type: u32,
+    attach_type: u32,
+    insns: &[BpfInsn],
+) -> Result<()> {
+    let prog = load_program(prog_type, insns)?;
+
+    let cgroup = File::open(dir).with_context(|| format!("failed to open {:?}", dir))?;
+    let cgroup_fd = cgroup.as_raw_fd();
+
+    let old = query_programs(cgroup_fd, attach_type)?;
+
+    // with BPF_F_ALLOW_MULTI all the attached programs run, so the old
+    // ones are removed once the new one is in place
+    let mut attr = ProgAttachAttr {
+        target_fd: cgroup_fd as u32,
+        attach_bpf_fd: prog.as_raw_fd() as u32,
+        attach_type,
+        attach_flags: BPF_F_ALLOW_MULTI,
+    };
+    bpf(BPF_PROG_ATTACH, &mut attr)
+        .with_context(|| format!("attach program as {}", attach_type))?;
+
+    for id in old {
+        detach_program(cgroup_fd, attach_type, id)
+            .with_context(|| format!("detach program {}", id))?;
+    }
+
+    Ok(())
+}
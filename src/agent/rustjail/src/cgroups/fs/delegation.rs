@@ -0,0 +1,341 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Delegated cgroups, for the agent which can't write to the whole cgroup v2
+// hierarchy, e.g. when it runs in a subtree delegated by systemd, or in its
+// own cgroup set up by a sandboxing init. The container cgroups are then
+// created beneath the cgroup of the agent, the delegation root.
+//
+// This is only done when asked for, with agent.rootless_cgroups. As a cgroup
+// with processes can't hand controllers to its children, the agent is moved
+// to the leaf cgroup AGENT_LEAF first; the other processes of the delegation
+// root are left alone, which its owner has to move. The controllers the root
+// didn't get are logged once and their resources skipped.
+
+use anyhow::{anyhow, Context, Result};
+use nix::unistd::{access, AccessFlags};
+use oci::{LinuxBlockIo, LinuxCpu, LinuxMemory, LinuxResources};
+use std::fs;
+use std::path::Path;
+
+use super::v2::{get_controllers, write_cgroup_file};
+use super::ResourceReport;
+
+pub const PROC_CGROUP: &str = "/proc/self/cgroup";
+
+const AGENT_LEAF: &str = "agent";
+const CGROUP_PROCS: &str = "cgroup.procs";
+const CGROUP_SUBTREE_CONTROL: &str = "cgroup.subtree_control";
+
+// parse_delegation_root returns the cgroup of the agent in the cgroup v2
+// hierarchy, from its /proc/self/cgroup entry, e.g. "0::/agent.service".
+// The leaf the agent was moved to is left out.
+fn parse_delegation_root(proc_cgroup: &str) -> Result<String> {
+    let path = proc_cgroup
+        .lines()
+        .find_map(|l| l.strip_prefix("0::"))
+        .ok_or_else(|| anyhow!("no cgroup v2 entry in {:?}", proc_cgroup))?;
+
+    let path = path
+        .strip_suffix(&format!("/{}", AGENT_LEAF))
+        .unwrap_or(path);
+
+    if path.is_empty() || path == "/" {
+        return Err(anyhow!("the agent runs in the root cgroup"));
+    }
+
+    Ok(path.to_string())
+}
+
+// setup_delegation_root returns the delegation root below the cgroup v2
+// hierarchy at `root`, after making it ready to have children.
+pub fn setup_delegation_root(root: &Path) -> Result<String> {
+    let proc_cgroup = fs::read_to_string(PROC_CGROUP)
+        .with_context(|| format!("failed to read {}", PROC_CGROUP))?;
+    let path = parse_delegation_root(&proc_cgroup)?;
+    let dir = root.join(path.trim_start_matches('/'));
+
+    if access(&dir.join(CGROUP_SUBTREE_CONTROL), AccessFlags::W_OK).is_err() {
+        return Err(anyhow!("the cgroup {:?} isn't delegated to the agent", dir));
+    }
+
+    move_agent(&dir, &dir.join(AGENT_LEAF), std::process::id())?;
+
+    let delegated = get_controllers(&dir)?;
+    let missing: Vec<String> = get_controllers(root)?
+        .into_iter()
+        .filter(|c| !delegated.contains(c))
+        .collect();
+    if !missing.is_empty() {
+        warn!(sl!(), "cgroup controllers not delegated";
+            "cgroup" => &path,
+            "controllers" => missing.join(" "));
+    }
+
+    enable_controllers(&dir)?;
+
+    Ok(path)
+}
+
+// move_agent moves the agent, of pid `agent`, from the cgroup `from` to the
+// cgroup `to`, created if needed.
+fn move_agent(from: &Path, to: &Path, agent: u32) -> Result<()> {
+    let procs = fs::read_to_string(from.join(CGROUP_PROCS))?;
+    let agent = agent.to_string();
+    let (ours, others): (Vec<&str>, Vec<&str>) = procs
+        .lines()
+        .filter(|p| !p.is_empty())
+        .partition(|p| *p == agent);
+
+    if !others.is_empty() {
+        warn!(sl!(), "processes left in the delegation root, its controllers can't be enabled";
+            "cgroup" => format!("{:?}", from),
+            "pids" => others.join(" "));
+    }
+    if ours.is_empty() {
+        return Ok(());
+    }
+
+    fs::create_dir_all(to).with_context(|| format!("failed to create {:?}", to))?;
+    write_cgroup_file(to, CGROUP_PROCS, &agent)
+}
+
+// enable_controllers hands the controllers of the cgroup at `dir` to its
+// children, one at a time so that one failing doesn't prevent the others.
+fn enable_controllers(dir: &Path) -> Result<()> {
+    for c in get_controllers(dir)? {
+        if let Err(e) = write_cgroup_file(dir, CGROUP_SUBTREE_CONTROL, &format!("+{}", c)) {
+            warn!(sl!(), "failed to enable a cgroup controller";
+                "cgroup" => format!("{:?}", dir),
+                "controller" => c,
+                "error" => format!("{:?}", e));
+        }
+    }
+
+    Ok(())
+}
+
+// create_cgroup creates the cgroup `cpath` below the delegation root
+// `parent`, handing the controllers down, which cgroups-rs can't do as it
+// starts from the top of the hierarchy.
+pub fn create_cgroup(root: &Path, parent: &str, cpath: &str) -> Result<()> {
+    let mut dir = root.join(parent.trim_start_matches('/'));
+    let rel = cpath
+        .trim_start_matches('/')
+        .strip_prefix(parent.trim_start_matches('/'))
+        .ok_or_else(|| anyhow!("cgroup {} isn't below {}", cpath, parent))?;
+
+    let components: Vec<&str> = rel.split('/').filter(|c| !c.is_empty()).collect();
+    for (i, c) in components.iter().enumerate() {
+        dir.push(c);
+        if !dir.is_dir() {
+            fs::create_dir(&dir).with_context(|| format!("failed to create {:?}", dir))?;
+        }
+        if i + 1 < components.len() {
+            enable_controllers(&dir)?;
+        }
+    }
+
+    Ok(())
+}
+
+// delegated_resources returns the resources of the cgroup at `dir` which
+// its controllers can apply, the other ones being skipped.
+pub fn delegated_resources(
+    dir: &Path,
+    r: &LinuxResources,
+    report: &mut ResourceReport,
+) -> Result<LinuxResources> {
+    let controllers = get_controllers(dir)?;
+    let enabled = |c: &str| controllers.iter().any(|x| x == c);
+    let mut r = r.clone();
+
+    if let Some(cpu) = r.cpu.as_mut() {
+        if !enabled("cpuset") && cpuset_only(cpu) != LinuxCpu::default() {
+            report.skip("cpu.cpus", "cpuset controller not delegated")?;
+            cpu.cpus.clear();
+            cpu.mems.clear();
+            cpu.cpus_partition.clear();
+        }
+        if !enabled("cpu") && has_cpu_settings(cpu) {
+            report.skip("cpu", "cpu controller not delegated")?;
+            *cpu = cpuset_only(cpu);
+        }
+    }
+
+    if r.memory.as_ref().map_or(false, has_memory_settings) && !enabled("memory") {
+        report.skip("memory", "memory controller not delegated")?;
+        r.memory = None;
+    }
+
+    if r.pids.as_ref().map_or(false, |p| p.limit != 0) && !enabled("pids") {
+        report.skip("pids", "pids controller not delegated")?;
+        r.pids = None;
+    }
+
+    if r.block_io.as_ref().map_or(false, has_block_io_settings) && !enabled("io") {
+        report.skip("blockIO", "io controller not delegated")?;
+        r.block_io = None;
+    }
+
+    let mut unified = r.unified.clone();
+    for key in r.unified.keys() {
+        let controller = key.split('.').next().unwrap_or_default();
+        if controller != "cgroup" && !enabled(controller) {
+            report.skip(
+                &format!("unified.{}", key),
+                &format!("{} controller not delegated", controller),
+            )?;
+            unified.remove(key);
+        }
+    }
+    r.unified = unified;
+
+    Ok(r)
+}
+
+// cpuset_only returns the settings of the cpuset controller of cpu.
+fn cpuset_only(cpu: &LinuxCpu) -> LinuxCpu {
+    LinuxCpu {
+        cpus: cpu.cpus.clone(),
+        mems: cpu.mems.clone(),
+        cpus_partition: cpu.cpus_partition.clone(),
+        ..Default::default()
+    }
+}
+
+// The has_*_settings functions tell whether a section sets anything: the
+// resources from the host have every field set, to zero when unused, which
+// isn't worth a skip.
+
+fn has_cpu_settings(cpu: &LinuxCpu) -> bool {
+    cpu.shares.unwrap_or(0) != 0
+        || cpu.quota.unwrap_or(0) != 0
+        || cpu.period.unwrap_or(0) != 0
+        || cpu.realtime_runtime.unwrap_or(0) != 0
+        || cpu.realtime_period.unwrap_or(0) != 0
+        || cpu.burst.unwrap_or(0) != 0
+        || cpu.idle.unwrap_or(0) != 0
+}
+
+fn has_memory_settings(mem: &LinuxMemory) -> bool {
+    mem.limit.unwrap_or(0) != 0
+        || mem.reservation.unwrap_or(0) != 0
+        || mem.swap.unwrap_or(0) != 0
+        || mem.swappiness.is_some()
+}
+
+fn has_block_io_settings(blkio: &LinuxBlockIo) -> bool {
+    blkio.weight.unwrap_or(0) != 0
+        || blkio.leaf_weight.unwrap_or(0) != 0
+        || !blkio.weight_device.is_empty()
+        || !blkio.throttle_read_bps_device.is_empty()
+        || !blkio.throttle_write_bps_device.is_empty()
+        || !blkio.throttle_read_iops_device.is_empty()
+        || !blkio.throttle_write_iops_device.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci::LinuxPids;
+
+    #[test]
+    fn test_parse_delegation_root() {
+        assert_eq!(
+            parse_delegation_root("0::/system.slice/kata-agent.service\n").unwrap(),
+            "/system.slice/kata-agent.service"
+        );
+        // the agent was moved to its leaf already
+        assert_eq!(
+            parse_delegation_root("0::/system.slice/kata-agent.service/agent\n").unwrap(),
+            "/system.slice/kata-agent.service"
+        );
+        assert_eq!(
+            parse_delegation_root("12:cpu,cpuacct:/a\n0::/sandbox\n").unwrap(),
+            "/sandbox"
+        );
+
+        assert!(parse_delegation_root("0::/\n").is_err());
+        assert!(parse_delegation_root("0::/agent\n").is_err());
+        assert!(parse_delegation_root("12:cpu,cpuacct:/a\n").is_err());
+    }
+
+    #[test]
+    fn test_move_agent() {
+        let root = tempfile::tempdir().unwrap();
+        let leaf = root.path().join(AGENT_LEAF);
+        fs::write(root.path().join(CGROUP_PROCS), "10\n42\n").unwrap();
+
+        // only the agent is moved
+        move_agent(root.path(), &leaf, 42).unwrap();
+        assert_eq!(fs::read_to_string(leaf.join(CGROUP_PROCS)).unwrap(), "42");
+
+        // the agent was moved already
+        let other = root.path().join("other");
+        move_agent(root.path(), &other, 7).unwrap();
+        assert!(!other.exists());
+    }
+
+    #[test]
+    fn test_create_cgroup() {
+        let root = tempfile::tempdir().unwrap();
+        let parent = root.path().join("agent.service");
+        fs::create_dir_all(&parent).unwrap();
+
+        create_cgroup(root.path(), "/agent.service", "/agent.service/kata/c1").unwrap();
+        assert!(parent.join("kata/c1").is_dir());
+
+        assert!(create_cgroup(root.path(), "/agent.service", "/other/c1").is_err());
+    }
+
+    #[test]
+    fn test_delegated_resources() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("cgroup.controllers"), "cpu memory\n").unwrap();
+
+        let mut r = LinuxResources {
+            cpu: Some(LinuxCpu {
+                shares: Some(1024),
+                cpus: "0-1".to_string(),
+                ..Default::default()
+            }),
+            memory: Some(LinuxMemory {
+                limit: Some(1 << 30),
+                ..Default::default()
+            }),
+            pids: Some(LinuxPids { limit: 100 }),
+            ..Default::default()
+        };
+        r.unified
+            .insert("memory.high".to_string(), "max".to_string());
+        r.unified.insert("io.weight".to_string(), "100".to_string());
+
+        let mut report = ResourceReport::new(false);
+        let d = delegated_resources(dir.path(), &r, &mut report).unwrap();
+
+        assert_eq!(
+            d.cpu,
+            Some(LinuxCpu {
+                shares: Some(1024),
+                ..Default::default()
+            })
+        );
+        assert_eq!(d.memory, r.memory);
+        assert_eq!(d.pids, None);
+        assert_eq!(d.unified.len(), 1);
+        assert!(d.unified.contains_key("memory.high"));
+        assert_eq!(
+            report.unapplied,
+            vec![
+                "cpu.cpus: cpuset controller not delegated",
+                "pids: pids controller not delegated",
+                "unified.io.weight: io controller not delegated",
+            ]
+        );
+
+        assert!(delegated_resources(dir.path(), &r, &mut ResourceReport::new(true)).is_err());
+    }
+}
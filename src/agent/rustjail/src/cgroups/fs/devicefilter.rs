@@ -0,0 +1,213 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Device access control for the cgroup v2 unified hierarchy.
+//
+// cgroup v2 has no devices.allow/devices.deny files, the device rules are
+// enforced by a BPF_PROG_TYPE_CGROUP_DEVICE program attached to the cgroup
+// instead. As in runc, the program checks the rules from the last one to
+// the first one, the first matching rule decides, and anything that
+// doesn't match any rule is denied.
+
+use anyhow::{anyhow, Result};
+use oci::LinuxDeviceCgroup;
+use std::path::Path;
+
+use super::bpf::{
+    self, BpfInsn, BPF_ALU_AND_K, BPF_ALU_MOV_X, BPF_ALU_RSH_K, BPF_JMP_JNE_K, BPF_JMP_JNE_X,
+    BPF_LDX_MEM_W, R0, R1, R2, R3, R4, R5,
+};
+use super::WILDCARD;
+
+// struct bpf_cgroup_dev_ctx field offsets
+const CTX_ACCESS_TYPE: i16 = 0;
+const CTX_MAJOR: i16 = 4;
+const CTX_MINOR: i16 = 8;
+
+const BPF_DEVCG_DEV_BLOCK: i32 = 1;
+const BPF_DEVCG_DEV_CHAR: i32 = 2;
+const BPF_DEVCG_ACC_MKNOD: i32 = 1;
+const BPF_DEVCG_ACC_READ: i32 = 2;
+const BPF_DEVCG_ACC_WRITE: i32 = 4;
+const BPF_DEVCG_ACC_ALL: i32 = BPF_DEVCG_ACC_MKNOD | BPF_DEVCG_ACC_READ | BPF_DEVCG_ACC_WRITE;
+
+const BPF_PROG_TYPE_CGROUP_DEVICE: u32 = 15;
+const BPF_CGROUP_DEVICE: u32 = 6;
+
+// generate_program builds the device filter program for `rules`.
+pub fn generate_program(rules: &[LinuxDeviceCgroup]) -> Result<Vec<BpfInsn>> {
+    // r2 = device type, r3 = access, r4 = major, r5 = minor
+    let mut insns = vec![
+        BpfInsn::new(BPF_LDX_MEM_W, R2, R1, CTX_ACCESS_TYPE, 0),
+        BpfInsn::new(BPF_ALU_AND_K, R2, 0, 0, 0xffff),
+        BpfInsn::new(BPF_LDX_MEM_W, R3, R1, CTX_ACCESS_TYPE, 0),
+        BpfInsn::new(BPF_ALU_RSH_K, R3, 0, 0, 16),
+        BpfInsn::new(BPF_LDX_MEM_W, R4, R1, CTX_MAJOR, 0),
+        BpfInsn::new(BPF_LDX_MEM_W, R5, R1, CTX_MINOR, 0),
+    ];
+
+    for rule in rules.iter().rev() {
+        let (block, unconditional) = rule_to_block(rule)?;
+        insns.extend(block);
+
+        // the verifier refuses unreachable instructions, so nothing can
+        // follow a rule that matches every device
+        if unconditional {
+            return Ok(insns);
+        }
+    }
+
+    insns.push(bpf::mov_imm(R0, 0));
+    insns.push(bpf::exit());
+
+    Ok(insns)
+}
+
+// rule_to_block returns the instructions checking a single rule, and
+// whether the rule matches any device.
+fn rule_to_block(rule: &LinuxDeviceCgroup) -> Result<(Vec<BpfInsn>, bool)> {
+    let dev_type = match rule.r#type.as_str() {
+        "" | "a" => None,
+        "b" => Some(BPF_DEVCG_DEV_BLOCK),
+        "c" => Some(BPF_DEVCG_DEV_CHAR),
+        t => return Err(anyhow!("invalid device type {:?}", t)),
+    };
+
+    let mut access = 0;
+    for c in rule.access.chars() {
+        access |= match c {
+            'r' => BPF_DEVCG_ACC_READ,
+            'w' => BPF_DEVCG_ACC_WRITE,
+            'm' => BPF_DEVCG_ACC_MKNOD,
+            _ => return Err(anyhow!("invalid device access {:?}", rule.access)),
+        };
+    }
+
+    // every condition jumps past the end of the block when it doesn't match
+    let mut block = vec![];
+
+    if let Some(t) = dev_type {
+        block.push(BpfInsn::new(BPF_JMP_JNE_K, R2, 0, 0, t));
+    }
+
+    if access != 0 && access != BPF_DEVCG_ACC_ALL {
+        // the requested access must be a subset of the allowed one
+        block.push(BpfInsn::new(BPF_ALU_MOV_X, R1, R3, 0, 0));
+        block.push(BpfInsn::new(BPF_ALU_AND_K, R1, 0, 0, access));
+        block.push(BpfInsn::new(BPF_JMP_JNE_X, R1, R3, 0, 0));
+    }
+
+    if let Some(major) = rule.major.filter(|m| *m != WILDCARD) {
+        block.push(BpfInsn::new(BPF_JMP_JNE_K, R4, 0, 0, major as i32));
+    }
+
+    if let Some(minor) = rule.minor.filter(|m| *m != WILDCARD) {
+        block.push(BpfInsn::new(BPF_JMP_JNE_K, R5, 0, 0, minor as i32));
+    }
+
+    let unconditional = block.is_empty();
+
+    block.push(bpf::mov_imm(R0, rule.allow as i32));
+    block.push(bpf::exit());
+
+    let len = block.len();
+    for (i, insn) in block.iter_mut().enumerate() {
+        if insn.is_jump() {
+            insn.off = (len - i - 1) as i16;
+        }
+    }
+
+    Ok((block, unconditional))
+}
+
+// set_device_filter loads the device filter for `rules` and attaches it
+// to the cgroup at `dir`, replacing any filter installed previously.
+pub fn set_device_filter(dir: &Path, rules: &[LinuxDeviceCgroup]) -> Result<()> {
+    let insns = generate_program(rules)?;
+
+    bpf::replace_programs(dir, BPF_PROG_TYPE_CGROUP_DEVICE, BPF_CGROUP_DEVICE, &insns)
+}
+
+// device_filter_matches returns whether the device filter attached to the
+// cgroup at `dir` is the only one, and the one generated for `rules`.
+pub fn device_filter_matches(dir: &Path, rules: &[LinuxDeviceCgroup]) -> Result<bool> {
+    let insns = generate_program(rules)?;
+    let attached = bpf::attached_programs(dir, BPF_CGROUP_DEVICE)?;
+
+    Ok(attached.len() == 1 && attached[0] == insns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(
+        allow: bool,
+        t: &str,
+        major: Option<i64>,
+        minor: Option<i64>,
+        access: &str,
+    ) -> LinuxDeviceCgroup {
+        LinuxDeviceCgroup {
+            allow,
+            r#type: t.to_string(),
+            major,
+            minor,
+            access: access.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_generate_program() {
+        // prologue, then deny everything
+        let insns = generate_program(&[]).unwrap();
+        assert_eq!(insns.len(), 8);
+        assert_eq!(insns[6], bpf::mov_imm(R0, 0));
+        assert_eq!(insns[7], bpf::exit());
+
+        let rules = vec![
+            rule(false, "a", None, None, "rwm"),
+            rule(true, "c", Some(1), Some(3), "rw"),
+        ];
+        let insns = generate_program(&rules).unwrap();
+
+        // prologue, the /dev/null rule, then the deny all rule with no
+        // trailing default since it's unreachable
+        assert_eq!(insns.len(), 6 + 8 + 2);
+
+        let block = &insns[6..14];
+        assert_eq!(
+            block[0],
+            BpfInsn::new(BPF_JMP_JNE_K, R2, 0, 7, BPF_DEVCG_DEV_CHAR)
+        );
+        assert_eq!(block[1], BpfInsn::new(BPF_ALU_MOV_X, R1, R3, 0, 0));
+        assert_eq!(
+            block[2],
+            BpfInsn::new(
+                BPF_ALU_AND_K,
+                R1,
+                0,
+                0,
+                BPF_DEVCG_ACC_READ | BPF_DEVCG_ACC_WRITE
+            )
+        );
+        assert_eq!(block[3], BpfInsn::new(BPF_JMP_JNE_X, R1, R3, 4, 0));
+        assert_eq!(block[4], BpfInsn::new(BPF_JMP_JNE_K, R4, 0, 3, 1));
+        assert_eq!(block[5], BpfInsn::new(BPF_JMP_JNE_K, R5, 0, 2, 3));
+        assert_eq!(block[6], bpf::mov_imm(R0, 1));
+        assert_eq!(block[7], bpf::exit());
+
+        assert_eq!(insns[14], bpf::mov_imm(R0, 0));
+        assert_eq!(insns[15], bpf::exit());
+
+        // wildcards don't generate any check
+        let rules = vec![rule(true, "c", Some(WILDCARD), Some(WILDCARD), "m")];
+        let insns = generate_program(&rules).unwrap();
+        assert_eq!(insns.len(), 6 + 6 + 2);
+
+        assert!(generate_program(&[rule(true, "x", None, None, "rwm")]).is_err());
+        assert!(generate_program(&[rule(true, "c", None, None, "rwx")]).is_err());
+    }
+}
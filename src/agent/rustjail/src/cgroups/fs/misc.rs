@@ -0,0 +1,99 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// The misc controller accounts scalar resources which can't be abstracted
+// like the other ones, e.g. the SEV ASIDs or the TDX keys confidential
+// guests have to share between their containers. Its interface files are
+// the same on cgroup v1 and v2.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use super::lines_to_map;
+use super::v2::write_cgroup_file;
+use crate::cgroups::stats::MiscStats;
+
+pub const MISC: &str = "misc";
+
+const MISC_MAX: &str = "misc.max";
+const MISC_CURRENT: &str = "misc.current";
+const MISC_EVENTS: &str = "misc.events";
+
+const MAX: &str = "max";
+
+// set_misc_limits writes the limit of each misc resource, u64::MAX being
+// no limit. The kernel only takes one resource per write.
+pub fn set_misc_limits(dir: &Path, limits: &HashMap<String, u64>) -> Result<()> {
+    for (name, limit) in limits {
+        let value = if *limit == u64::MAX {
+            format!("{} {}", name, MAX)
+        } else {
+            format!("{} {}", name, limit)
+        };
+        write_cgroup_file(dir, MISC_MAX, &value)?;
+    }
+
+    Ok(())
+}
+
+fn read_nested_keyed(dir: &Path, file: &str) -> String {
+    fs::read_to_string(dir.join(file)).unwrap_or_default()
+}
+
+// get_misc_stats returns the usage of the misc resources available to the
+// cgroup at `dir`.
+pub fn get_misc_stats(dir: &Path) -> HashMap<String, MiscStats> {
+    let current = lines_to_map(&read_nested_keyed(dir, MISC_CURRENT));
+    let events = lines_to_map(&read_nested_keyed(dir, MISC_EVENTS));
+    let max = read_nested_keyed(dir, MISC_MAX);
+
+    let mut h = HashMap::new();
+
+    for line in max.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 2 {
+            continue;
+        }
+
+        let mut stats = MiscStats::default();
+        stats.usage = *current.get(fields[0]).unwrap_or(&0);
+        stats.limit = if fields[1] == MAX {
+            u64::MAX
+        } else {
+            fields[1].parse::<u64>().unwrap_or(0)
+        };
+        stats.failcnt = *events.get(&format!("{}.max", fields[0])).unwrap_or(&0);
+
+        h.insert(fields[0].to_string(), stats);
+    }
+
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_misc_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(MISC_MAX), "sev 16\nsev_es max\n").unwrap();
+        fs::write(dir.path().join(MISC_CURRENT), "sev 3\nsev_es 0\n").unwrap();
+        fs::write(dir.path().join(MISC_EVENTS), "sev.max 2\nsev_es.max 0\n").unwrap();
+
+        let stats = get_misc_stats(dir.path());
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats["sev"].usage, 3);
+        assert_eq!(stats["sev"].limit, 16);
+        assert_eq!(stats["sev"].failcnt, 2);
+        assert_eq!(stats["sev_es"].limit, u64::MAX);
+
+        // no misc controller
+        let dir = tempfile::tempdir().unwrap();
+        assert!(get_misc_stats(dir.path()).is_empty());
+    }
+}
@@ -12,32 +12,86 @@ use cgroups::devices::DeviceType;
 use cgroups::freezer::{FreezerController, FreezerState};
 use cgroups::hugetlb::HugeTlbController;
 use cgroups::memory::MemController;
+use cgroups::net_cls::NetClsController;
+use cgroups::net_prio::NetPrioController;
 use cgroups::pid::PidController;
 use cgroups::{
     BlkIoDeviceResource, BlkIoDeviceThrottleResource, Cgroup, CgroupPid, Controller,
     DeviceResource, HugePageResource, MaxValue, NetworkPriority,
 };
 
-use crate::cgroups::Manager as CgroupManager;
+use crate::cgroups::stats::{
+    BlkioStats, BlkioStatsEntry, CgroupStats, CpuStats, CpuUsage, HugetlbStats, MemoryData,
+    MemoryEvents, MemoryStats, PidsStats, ThrottlingData,
+};
+use crate::cgroups::{
+    CgroupError, DeviceMap, DeviceRules, FsManagerExt, Manager as CgroupManager, MemoryUsage,
+    Sample, Sampler,
+};
 use crate::container::DEFAULT_DEVICES;
 use anyhow::{anyhow, Context, Result};
 use libc::{self, pid_t};
 use nix::errno::Errno;
 use oci::{
-    LinuxBlockIo, LinuxCpu, LinuxDevice, LinuxDeviceCgroup, LinuxHugepageLimit, LinuxMemory,
-    LinuxNetwork, LinuxPids, LinuxResources,
+    LinuxBlockIo, LinuxCpu, LinuxDevice, LinuxDeviceCgroup, LinuxHugepageLimit,
+    LinuxInterfacePriority, LinuxMemory, LinuxNetwork, LinuxPids, LinuxResources,
 };
 
-use protobuf::{CachedSize, RepeatedField, SingularPtrField, UnknownFields};
-use protocols::agent::{
-    BlkioStats, BlkioStatsEntry, CgroupStats, CpuStats, CpuUsage, HugetlbStats, MemoryData,
-    MemoryStats, PidsStats, ThrottlingData,
-};
+use protocols::agent::{CgroupDetails, CgroupMode};
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::instrument;
+
+pub mod bpf;
+pub mod delegation;
+pub mod devicefilter;
+pub mod misc;
+pub mod netprio;
+pub mod psi;
+pub mod remove;
+pub mod rt;
+pub mod state;
+pub mod update;
+pub mod v2;
+pub mod validate;
 
 const GUEST_CPUS_PATH: &str = "/sys/devices/system/cpu/online";
+const KMEM_LIMIT_IN_BYTES: &str = "memory.kmem.limit_in_bytes";
+const CPU_RT_RUNTIME_US: &str = "cpu.rt_runtime_us";
+const CPU_RT_PERIOD_US: &str = "cpu.rt_period_us";
+const CPU_CFS_QUOTA_US: &str = "cpu.cfs_quota_us";
+const CPU_CFS_BURST_US: &str = "cpu.cfs_burst_us";
+const CPU_IDLE: &str = "cpu.idle";
+const CPUSET_CPUS: &str = "cpuset.cpus";
+const CPUSET_MEMS: &str = "cpuset.mems";
+const CPUSET_MEMS_EFFECTIVE: &str = "cpuset.mems.effective";
+const CGROUP_PROCS: &str = "cgroup.procs";
+const CGROUP_THREADS: &str = "cgroup.threads";
+const TASKS: &str = "tasks";
+const DEVICES_ALLOW: &str = "devices.allow";
+const DEVICES_DENY: &str = "devices.deny";
+const DEVICES_LIST: &str = "devices.list";
+const FREEZER_STATE: &str = "freezer.state";
+const MEMORY_LOW: &str = "memory.low";
+const MEMORY_USE_HIERARCHY: &str = "memory.use_hierarchy";
+const MEMORY_MIN: &str = "memory.min";
+const MEMORY_USAGE_IN_BYTES: &str = "memory.usage_in_bytes";
+const MEMORY_LIMIT_IN_BYTES: &str = "memory.limit_in_bytes";
+const MEMORY_MEMSW_USAGE_IN_BYTES: &str = "memory.memsw.usage_in_bytes";
+const MEMORY_MEMSW_LIMIT_IN_BYTES: &str = "memory.memsw.limit_in_bytes";
+const MEMORY_OOM_CONTROL: &str = "memory.oom_control";
+// without CONFIG_MEMCG_SWAP, or with swapaccount=0, there are no swap limits
+const NO_SWAP_ACCOUNTING: &str = "kernel has no swap accounting";
+// without CONFIG_CFS_BANDWIDTH, there are no CFS quota and period
+const NO_CFS_BANDWIDTH: &str = "kernel has no CFS bandwidth control";
+const PIDS_EVENTS: &str = "pids.events";
+const PROC_DEVICES: &str = "/proc/devices";
+const PROC_MISC: &str = "/proc/misc";
+// the cgroup v1 memory limits at or above PAGE_COUNTER_MAX pages, as read
+// with 4K pages, stand for none
+const MEMORY_V1_UNLIMITED: i64 = 0x7fff_ffff_ffff_f000;
 
 // Convenience macro to obtain the scope logger
 macro_rules! sl {
@@ -46,22 +100,112 @@ macro_rules! sl {
     };
 }
 
-macro_rules! get_controller_or_return_singular_none {
+// get_controller returns the controller of a cgroup, or fails if the
+// controller isn't mounted in the guest.
+macro_rules! get_controller {
+    ($cg:expr, $name:expr) => {
+        $cg.controller_of()
+            .ok_or_else(|| CgroupError::controller_missing($name))?
+    };
+}
+
+macro_rules! get_controller_or_return_none {
     ($cg:ident) => {
         match $cg.controller_of() {
             Some(c) => c,
-            None => return SingularPtrField::none(),
+            None => return None,
         }
     };
 }
 
+// Backend is the cgroup hierarchy a Manager drives.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    V1,
+    V2,
+}
+
+impl Backend {
+    // detect returns the backend matching the hierarchy mounted in the
+    // guest, which is only probed once.
+    pub fn detect() -> Self {
+        if *IS_CGROUP2_UNIFIED {
+            Backend::V2
+        } else {
+            Backend::V1
+        }
+    }
+
+    fn hierarchy(self) -> Box<dyn cgroups::Hierarchy> {
+        match self {
+            Backend::V1 => Box::new(cgroups::hierarchies::V1::new()),
+            Backend::V2 => Box::new(cgroups::hierarchies::V2::new()),
+        }
+    }
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Backend::detect()
+    }
+}
+
+fn default_unified_root() -> PathBuf {
+    PathBuf::from(v2::CGROUP2_ROOT)
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Manager {
     pub paths: HashMap<String, String>,
     pub mounts: HashMap<String, String>,
     pub cpath: String,
+    #[serde(default)]
+    backend: Backend,
+    // the mount point of the cgroup v2 hierarchy, or the directory standing
+    // for it, e.g. in the tests
+    #[serde(default = "default_unified_root")]
+    unified_root: PathBuf,
+    // resolved once in new() and dropped by destroy(), so that the
+    // hierarchy isn't parsed again by every operation. It isn't written
+    // through with a custom unified root, see ManagerBuilder::unified_root.
+    #[serde(skip)]
+    cgroup: Option<cgroups::Cgroup>,
+    #[serde(default)]
+    disable_kmem_accounting: bool,
+    #[serde(default)]
+    strict_resources: bool,
+    // the cgroup was created by someone else, e.g. by systemd in the
+    // guest, so it is only joined and configured
+    #[serde(default)]
+    precreated: bool,
+    // an OOM kills the whole cgroup rather than a single process
+    #[serde(default)]
+    oom_group: bool,
+    // the block device weights are enforced by iocost, cgroup v2 only
+    #[serde(default)]
+    io_cost: bool,
+    // the swappiness of the containers whose spec sets none, cgroup v1
+    // only
+    #[serde(default)]
+    default_swappiness: Option<u64>,
+    // host -> guest numbers of the block devices the blkio limits are
+    // translated with
     #[serde(skip)]
-    cgroup: cgroups::Cgroup,
+    device_map: DeviceMap,
+    // the cgroup is below the delegation root, the cgroup v2 subtree the
+    // agent was given, rather than the root of the hierarchy
+    #[serde(default)]
+    rootless: bool,
+    // the resources applied so far, which updates are merged into
+    #[serde(skip)]
+    applied: Arc<Mutex<LinuxResources>>,
+    // the settings the last create or update couldn't apply
+    #[serde(skip)]
+    unapplied: Arc<Mutex<Vec<String>>>,
+    // the cgroup events watched, e.g. "oom", to be watched again once
+    // restored
+    #[serde(skip)]
+    notifiers: Arc<Mutex<Vec<String>>>,
 }
 
 // set_resource is used to set reources by cgroup controller.
@@ -75,84 +219,114 @@ macro_rules! set_resource {
 }
 
 impl CgroupManager for Manager {
+    #[instrument(skip(self), fields(cgroup = %self.cpath))]
     fn apply(&self, pid: pid_t) -> Result<()> {
-        self.cgroup.add_task(CgroupPid::from(pid as u64))?;
+        if self.custom_root() {
+            self.cgroup()?;
+            return v2::write_cgroup_file(&self.unified_path(), CGROUP_PROCS, &pid.to_string());
+        }
+
+        self.cgroup()?.add_task(CgroupPid::from(pid as u64))?;
+
+        // cgroups-rs doesn't know about the misc controller
+        if let Some(dir) = self.misc_path() {
+            if self.precreated && !dir.exists() {
+                return Ok(());
+            }
+            fs::create_dir_all(&dir).with_context(|| format!("failed to create {:?}", dir))?;
+            fs::write(dir.join(CGROUP_PROCS), pid.to_string())
+                .with_context(|| format!("failed to add {} to {:?}", pid, dir))?;
+        }
+
         Ok(())
     }
 
+    #[instrument(skip(self, r), fields(cgroup = %self.cpath))]
     fn set(&self, r: &LinuxResources, update: bool) -> Result<()> {
         info!(
             sl!(),
             "cgroup manager set resources for container. Resources input {:?}", r
         );
 
-        let res = &mut cgroups::Resources::default();
-
-        // set cpuset and cpu reources
-        if let Some(cpu) = &r.cpu {
-            set_cpu_resources(&self.cgroup, cpu)?;
-        }
-
-        // set memory resources
-        if let Some(memory) = &r.memory {
-            set_memory_resources(&self.cgroup, memory, update)?;
-        }
-
-        // set pids resources
-        if let Some(pids_resources) = &r.pids {
-            set_pids_resources(&self.cgroup, pids_resources)?;
-        }
-
-        // set block_io resources
-        if let Some(blkio) = &r.block_io {
-            set_block_io_resources(&self.cgroup, blkio, res);
-        }
-
-        // set hugepages resources
-        if !r.hugepage_limits.is_empty() {
-            set_hugepages_resources(&self.cgroup, &r.hugepage_limits, res);
-        }
+        // nothing is written if the resources can't be applied whole
+        self.validate(r, update)?;
 
-        // set network resources
-        if let Some(network) = &r.network {
-            set_network_resources(&self.cgroup, network, res);
+        // the applied resources are kept with the guest devices, as the
+        // settings read back from the cgroup
+        let mut r = translate_block_devices(r, &self.device_map);
+        if !update && self.backend == Backend::V1 {
+            set_default_swappiness(&mut r, self.default_swappiness);
         }
+        let r = &r;
+
+        let mut applied = self.applied.lock().unwrap();
+
+        // an update only writes the settings it carries, anything else
+        // keeps the value applied before
+        let report = if update {
+            let delta = update::update_delta(&applied, r);
+            let report = match self.set_resources(&delta, true, self.strict_resources) {
+                Ok(report) => report,
+                Err(e) => {
+                    // the settings written before the failure are set back,
+                    // the container keeps running with what it had
+                    let rollback = update::rollback_delta(&applied, &delta);
+                    if let Err(err) = self.set_resources(&rollback, true, false) {
+                        warn!(sl!(), "failed to roll back the resources";
+                            "error" => format!("{:#}", err));
+                    }
+                    return Err(e);
+                }
+            };
+            *applied = update::merge_resources(&applied, r);
+            report
+        } else {
+            // a container which fails to be created has its cgroup removed
+            let report = self.set_resources(r, false, self.strict_resources)?;
+            *applied = r.clone();
+            report
+        };
 
-        // set devices resources
-        set_devices_resources(&self.cgroup, &r.devices, res);
-        info!(sl!(), "resources after processed {:?}", res);
-
-        // apply resources
-        self.cgroup.apply(res)?;
+        *self.unapplied.lock().unwrap() = report.unapplied;
 
         Ok(())
     }
 
+    #[instrument(skip(self), fields(cgroup = %self.cpath))]
     fn get_stats(&self) -> Result<CgroupStats> {
+        let cg = self.cgroup()?;
+        if self.backend == Backend::V2 {
+            return Ok(v2::get_stats(&self.unified_path()));
+        }
+
         // CpuStats
-        let cpu_usage = get_cpuacct_stats(&self.cgroup);
+        let cpu_usage = get_cpuacct_stats(cg);
 
-        let throttling_data = get_cpu_stats(&self.cgroup);
+        let throttling_data = get_cpu_stats(cg);
 
-        let cpu_stats = SingularPtrField::some(CpuStats {
+        let cpu_stats = Some(CpuStats {
             cpu_usage,
             throttling_data,
-            unknown_fields: UnknownFields::default(),
-            cached_size: CachedSize::default(),
         });
 
         // Memorystats
-        let memory_stats = get_memory_stats(&self.cgroup);
+        let memory_stats = get_memory_stats(cg);
 
         // PidsStats
-        let pids_stats = get_pids_stats(&self.cgroup);
+        let pids_stats = get_pids_stats(cg);
 
         // BlkioStats
         // note that virtiofs has no blkio stats
-        let blkio_stats = get_blkio_stats(&self.cgroup);
+        let blkio_stats = get_blkio_stats(cg);
 
         // HugetlbStats
-        let hugetlb_stats = get_hugetlb_stats(&self.cgroup);
+        let hugetlb_stats = get_hugetlb_stats(cg);
+
+        // MiscStats
+        let misc_stats = self
+            .misc_path()
+            .map(|dir| misc::get_misc_stats(&dir))
+            .unwrap_or_default();
 
         Ok(CgroupStats {
             cpu_stats,
@@ -160,13 +334,30 @@ impl CgroupManager for Manager {
             pids_stats,
             blkio_stats,
             hugetlb_stats,
-            unknown_fields: UnknownFields::default(),
-            cached_size: CachedSize::default(),
+            misc_stats,
         })
     }
 
+    fn get_memory_usage(&self) -> Result<MemoryUsage> {
+        if self.backend == Backend::V2 {
+            return v2::get_memory_usage(&self.unified_path());
+        }
+
+        let mem: &MemController = self
+            .cgroup()?
+            .controller_of()
+            .ok_or_else(|| CgroupError::controller_missing("memory"))?;
+
+        get_memory_usage(mem.path())
+    }
+
     fn freeze(&self, state: FreezerState) -> Result<()> {
-        let freezer_controller: &FreezerController = self.cgroup.controller_of().unwrap();
+        if self.custom_root() {
+            self.cgroup()?;
+            return v2::set_frozen(&self.unified_path(), state);
+        }
+
+        let freezer_controller: &FreezerController = get_controller!(self.cgroup()?, "freezer");
         match state {
             FreezerState::Thawed => {
                 freezer_controller.thaw()?;
@@ -182,45 +373,390 @@ impl CgroupManager for Manager {
         Ok(())
     }
 
+    fn state(&self) -> Result<FreezerState> {
+        self.cgroup()?;
+        if self.backend == Backend::V2 {
+            return Ok(v2::get_frozen(&self.unified_path()));
+        }
+
+        read_freezer_state(self.paths.get("freezer").map(Path::new))
+    }
+
+    #[instrument(skip(self), fields(cgroup = %self.cpath))]
     fn destroy(&mut self) -> Result<()> {
-        let _ = self.cgroup.delete();
+        // a precreated cgroup is removed by its owner
+        if self.precreated {
+            self.cgroup.take();
+            return Ok(());
+        }
+
+        // the killed processes of a paused container only exit once thawed
+        if self.cgroup.is_some() {
+            let _ = self.freeze(FreezerState::Thawed);
+        }
+        self.cgroup.take();
+
+        // the hierarchies mounted together share the directory
+        let mut dirs = self.cgroup_dirs();
+        dirs.sort();
+        dirs.dedup();
+
+        let errors: Vec<String> = dirs
+            .iter()
+            .filter_map(|dir| remove::remove_cgroup(dir).err())
+            .map(|e| format!("{:#}", e))
+            .collect();
+        if !errors.is_empty() {
+            return Err(anyhow!("{}", errors.join("; ")));
+        }
+
+        Ok(())
+    }
+
+    fn get_procs(&self) -> Result<Vec<pid_t>> {
+        read_members(&self.members_dir()?, CGROUP_PROCS)
+    }
+
+    fn get_threads(&self) -> Result<Vec<pid_t>> {
+        let file = match self.backend {
+            Backend::V2 => CGROUP_THREADS,
+            Backend::V1 => TASKS,
+        };
+
+        read_members(&self.members_dir()?, file)
+    }
+
+    // apply_exec adds an exec'd process to the cgroup, in the exec child
+    // when its usage can be accounted apart.
+    #[instrument(skip(self), fields(cgroup = %self.cpath))]
+    fn apply_exec(&self, pid: pid_t) -> Result<()> {
+        self.apply(pid)?;
+
+        // a precreated cgroup is left as its owner set it up
+        if self.precreated {
+            return Ok(());
+        }
+
+        if let Err(e) = self.join_exec_cgroup(pid) {
+            warn!(sl!(), "exec'd process accounted with the workload"; "pid" => pid, "error" => format!("{:?}", e));
+        }
+
         Ok(())
     }
 
-    fn get_pids(&self) -> Result<Vec<pid_t>> {
-        let mem_controller: &MemController = self.cgroup.controller_of().unwrap();
-        let pids = mem_controller.tasks();
-        let result = pids.iter().map(|x| x.pid as i32).collect::<Vec<i32>>();
+    // get_exec_stats returns the stats of the exec'd processes, which the
+    // stats of the cgroup include, None if none was accounted apart.
+    fn get_exec_stats(&self) -> Option<CgroupStats> {
+        let dir = self.exec_dir()?;
+        if self.backend == Backend::V2 {
+            return Some(v2::get_exec_stats(&dir, Path::new(PROC_ROOT)));
+        }
+
+        let cpath = format!("{}/{}", self.cpath, EXEC_CGROUP);
+        let mut exec = self.clone();
+        exec.paths = self
+            .paths
+            .iter()
+            .filter(|(k, _)| EXEC_CONTROLLERS.contains(&k.as_str()))
+            .map(|(k, v)| (k.clone(), format!("{}/{}", v, EXEC_CGROUP)))
+            .collect();
+        exec.cgroup = Some(load_cgroup(self.backend.hierarchy(), &cpath));
+        exec.cpath = cpath;
+
+        match exec.get_stats() {
+            Ok(stats) => Some(stats),
+            Err(e) => {
+                warn!(sl!(), "failed to get the stats of the exec'd processes"; "error" => format!("{:?}", e));
+                None
+            }
+        }
+    }
+
+    // unapplied_resources returns the resource settings the last create or
+    // update skipped, each as "resource: reason".
+    fn unapplied_resources(&self) -> Vec<String> {
+        self.unapplied.lock().unwrap().clone()
+    }
+
+    // validate checks the resources against what the guest supports,
+    // without writing anything, and returns the settings which would be
+    // skipped. An update is checked for the settings it writes.
+    fn validate(&self, r: &LinuxResources, update: bool) -> Result<Vec<String>> {
+        let r = translate_block_devices(r, &self.device_map);
+        let r = if update {
+            update::update_delta(&self.applied.lock().unwrap(), &r)
+        } else {
+            r
+        };
+
+        let mut report = ResourceReport::new(self.strict_resources);
+
+        // the controllers the agent wasn't given are skipped, as by set
+        let r = if self.backend == Backend::V2 && self.rootless {
+            delegation::delegated_resources(&self.unified_path(), &r, &mut report)?
+        } else {
+            r
+        };
+
+        validate::validate(&self.capabilities(), &r, &mut report)?;
+
+        Ok(report.unapplied)
+    }
+
+    // add_device_rule adds a rule to the device cgroup of a running
+    // container, e.g. for a device hot-plugged into it, which takes
+    // precedence over the rules applied before.
+    fn add_device_rule(&self, rule: &LinuxDeviceCgroup) -> Result<()> {
+        let mut applied = self.applied.lock().unwrap();
+        let mut rules = applied.devices.clone();
+        rules.push(rule.clone());
+
+        self.write_device_rule(rule, rule.allow, &rules)?;
+        applied.devices = rules;
+
+        Ok(())
+    }
+
+    // remove_device_rule removes a rule added before. cgroup v1 only keeps
+    // the resulting access, which reverting the rule would get wrong, e.g. a
+    // device denied by another rule would be allowed, so the remaining rules
+    // are written again from a cgroup denying everything.
+    fn remove_device_rule(&self, rule: &LinuxDeviceCgroup) -> Result<()> {
+        let mut applied = self.applied.lock().unwrap();
+        let i = applied
+            .devices
+            .iter()
+            .rposition(|d| d == rule)
+            .ok_or_else(|| anyhow!("no device rule {:?}", rule))?;
+        let mut rules = applied.devices.clone();
+        rules.remove(i);
+
+        if self.backend == Backend::V2 {
+            self.write_device_rule(rule, !rule.allow, &rules)?;
+        } else {
+            let dir = self
+                .paths
+                .get("devices")
+                .ok_or_else(|| CgroupError::controller_missing("devices"))?;
+            replay_device_rules(&rules, |file, line| {
+                v2::write_cgroup_file(Path::new(dir), file, line)
+            })?;
+        }
+        applied.devices = rules;
+
+        Ok(())
+    }
+
+    // list_device_rules returns the device rules in effect, for an audit of
+    // what the container can access. cgroup v1 has them read back from
+    // devices.list. cgroup v2 has nothing to read them from, so the rules
+    // applied are returned, verified against the device filter attached to
+    // the cgroup.
+    fn list_device_rules(&self) -> Result<DeviceRules> {
+        if self.backend == Backend::V2 {
+            let rules = v2::device_rules(&self.applied.lock().unwrap().devices);
+            let verified = devicefilter::device_filter_matches(&self.unified_path(), &rules)?;
+
+            return Ok(DeviceRules { rules, verified });
+        }
+
+        let dir = self
+            .paths
+            .get("devices")
+            .ok_or_else(|| CgroupError::controller_missing("devices"))?;
+        let path = Path::new(dir).join(DEVICES_LIST);
+        let content =
+            fs::read_to_string(&path).with_context(|| format!("failed to read {:?}", path))?;
+
+        Ok(DeviceRules {
+            rules: parse_devices_list(&content)?,
+            verified: true,
+        })
+    }
+
+    // update_cpuset_path widens the cpuset of the container cgroup and of its
+    // ancestors to the guest cpus, after a cpu hotplug, since a cgroup can't
+    // use more cpus than its parent. The memory nodes of the root are
+    // propagated along, and the cpuset of the container applied last.
+    fn update_cpuset_path(&self, guest_cpuset: &str, container_cpuset: &str) -> Result<()> {
+        if guest_cpuset.is_empty() {
+            return Ok(());
+        }
+        info!(sl!(), "update_cpuset_path to: {}", guest_cpuset);
+
+        // the cpuset hierarchy may be mounted anywhere, and comounted
+        let (root, mems_file) = match self.backend {
+            Backend::V2 => (self.unified_root.clone(), CPUSET_MEMS_EFFECTIVE),
+            Backend::V1 => match self.mounts.get("cpuset") {
+                Some(m) => (PathBuf::from(m), CPUSET_MEMS),
+                None => return Err(CgroupError::controller_missing("cpuset").into()),
+            },
+        };
+        let dir = root.join(self.cpath.trim_start_matches('/'));
+
+        if self.backend == Backend::V2 && !dir.join(CPUSET_CPUS).exists() {
+            info!(sl!(), "cpuset controller not enabled for {:?}", &dir);
+            return Ok(());
+        }
+
+        update_cpuset(&root, &dir, mems_file, guest_cpuset, container_cpuset)
+    }
+
+    fn get_cg_path(&self, cg: &str) -> Option<String> {
+        if self.backend == Backend::V2 {
+            return Some(self.unified_path().to_string_lossy().to_string());
+        }
+
+        // for cgroup v1
+        self.paths.get(cg).map(|s| s.to_string())
+    }
+
+    fn get_guest_cpuset(&self) -> Result<String> {
+        get_guest_cpuset()
+    }
+
+    fn as_fs(&self) -> Option<&dyn FsManagerExt> {
+        Some(self)
+    }
+}
+
+impl FsManagerExt for Manager {
+    // save persists the state of the manager of a container, to be
+    // restored after an agent restart.
+    fn save(&self, cid: &str) -> Result<()> {
+        state::save(Path::new(state::STATE_DIR), cid, self)
+    }
+
+    // add_notifier records a cgroup event watched for the container.
+    fn add_notifier(&self, name: &str) {
+        let mut notifiers = self.notifiers.lock().unwrap();
+        if !notifiers.iter().any(|n| n == name) {
+            notifiers.push(name.to_string());
+        }
+    }
+
+    // cgroup_dirs returns the directories of the cgroup, one per hierarchy
+    // on cgroup v1.
+    fn cgroup_dirs(&self) -> Vec<PathBuf> {
+        if self.backend == Backend::V2 {
+            return vec![self.unified_path()];
+        }
+
+        self.paths.values().map(PathBuf::from).collect()
+    }
+
+    fn get_paths(&self) -> HashMap<String, String> {
+        self.paths.clone()
+    }
+
+    fn get_mounts(&self) -> HashMap<String, String> {
+        self.mounts.clone()
+    }
+
+    // get_hugetlb_stats returns the hugetlb usage of the cgroup by page
+    // size, cheaper to read than the whole stats for the metrics.
+    fn get_hugetlb_stats(&self) -> Result<HashMap<String, HugetlbStats>> {
+        let cg = self.cgroup()?;
+        if self.backend == Backend::V2 {
+            return Ok(v2::get_hugetlb_stats(&self.unified_path()));
+        }
+
+        Ok(get_hugetlb_stats(cg))
+    }
+
+    // get_pressure returns the pressure stall information of the cgroup,
+    // for the resources the kernel has it for.
+    fn get_pressure(&self) -> Vec<(&'static str, psi::Pressure)> {
+        self.pressure_dir()
+            .map_or_else(Vec::new, |dir| read_pressure(&dir))
+    }
+
+    // sampler reads the same files as get_pressure and get_hugetlb_stats,
+    // by path.
+    fn sampler(&self) -> Sampler {
+        let pressure_dir = self.pressure_dir();
+        let backend = self.backend;
+        let hugetlb_dir = match backend {
+            Backend::V2 => Some(self.unified_path()),
+            Backend::V1 => self.paths.get("hugetlb").map(PathBuf::from),
+        };
 
-        Ok(result)
+        Box::new(move || Sample {
+            pressure: pressure_dir.map_or_else(Vec::new, |dir| read_pressure(&dir)),
+            hugetlb: match (backend, hugetlb_dir) {
+                (Backend::V2, Some(dir)) => v2::get_hugetlb_stats(&dir),
+                (Backend::V1, Some(dir)) => read_hugetlb_stats(&dir),
+                _ => HashMap::new(),
+            },
+        })
     }
 }
 
 fn set_network_resources(
-    _cg: &cgroups::Cgroup,
+    cg: &cgroups::Cgroup,
     network: &LinuxNetwork,
     res: &mut cgroups::Resources,
-) {
+    report: &mut ResourceReport,
+) -> Result<()> {
     info!(sl!(), "cgroup manager set network");
 
     // set classid
     // description can be found at https://www.kernel.org/doc/html/latest/admin-guide/cgroup-v1/net_cls.html
     let class_id = network.class_id.unwrap_or(0) as u64;
     if class_id != 0 {
-        res.network.class_id = Some(class_id);
+        let net_cls: Option<&NetClsController> = cg.controller_of();
+        if net_cls.is_some() {
+            res.network.class_id = Some(class_id);
+        } else {
+            report.skip("network.classID", "net_cls controller not available")?;
+        }
+    }
+
+    if network.priorities.is_empty() {
+        return Ok(());
+    }
+
+    let net_prio: Option<&NetPrioController> = cg.controller_of();
+    if net_prio.is_none() {
+        return report.skip("network.priorities", "net_prio controller not available");
     }
 
     // set network priorities
     // description can be found at https://www.kernel.org/doc/html/latest/admin-guide/cgroup-v1/net_prio.html
     let mut priorities = vec![];
-    for p in network.priorities.iter() {
+    for p in existing_interface_priorities(&network.priorities, report)? {
         priorities.push(NetworkPriority {
-            name: p.name.clone(),
+            name: p.name,
             priority: p.priority as u64,
         });
     }
 
     res.network.priorities = priorities;
+
+    Ok(())
+}
+
+// existing_interface_priorities returns the priorities of the interfaces
+// found in the network namespace of the agent, which the containers share,
+// the others being skipped: the kernel would fail all of them otherwise.
+pub fn existing_interface_priorities(
+    priorities: &[LinuxInterfacePriority],
+    report: &mut ResourceReport,
+) -> Result<Vec<LinuxInterfacePriority>> {
+    let mut existing = vec![];
+
+    for p in priorities {
+        if nix::net::if_::if_nametoindex(p.name.as_str()).is_err() {
+            report.skip(
+                &format!("network.priorities.{}", p.name),
+                "no such interface in the guest",
+            )?;
+            continue;
+        }
+        existing.push(p.clone());
+    }
+
+    Ok(existing)
 }
 
 fn set_devices_resources(
@@ -252,6 +788,24 @@ fn set_devices_resources(
     res.devices.devices = devices;
 }
 
+// replay_device_rules resets the device access of a cgroup v1 cgroup to
+// `rules`, the default devices being allowed, with `write` writing a line
+// to one of its device files. The processes of the cgroup can't open the
+// devices meanwhile, the ones open already being kept.
+fn replay_device_rules(
+    rules: &[LinuxDeviceCgroup],
+    mut write: impl FnMut(&str, &str) -> Result<()>,
+) -> Result<()> {
+    write(DEVICES_DENY, "a")?;
+
+    for r in v2::device_rules(rules) {
+        let file = if r.allow { DEVICES_ALLOW } else { DEVICES_DENY };
+        write(file, &device_rule_string(&r)?)?;
+    }
+
+    Ok(())
+}
+
 fn set_hugepages_resources(
     _cg: &cgroups::Cgroup,
     hugepage_limits: &[LinuxHugepageLimit],
@@ -270,6 +824,27 @@ fn set_hugepages_resources(
     res.hugepages.limits = limits;
 }
 
+// set_hugepages_rsvd_limits limits the reserved huge pages to the same
+// limits as the faulted ones, as runc does, where the kernel accounts the
+// reservations: the pages reserved by mmap otherwise escape the limits.
+// `limit_file` is the suffix of the limit files, "limit_in_bytes" on cgroup
+// v1 and "max" on cgroup v2.
+pub fn set_hugepages_rsvd_limits(
+    dir: &Path,
+    hugepage_limits: &[LinuxHugepageLimit],
+    limit_file: &str,
+) -> Result<()> {
+    for l in hugepage_limits.iter() {
+        let file = format!("{}{}.rsvd.{}", v2::HUGETLB_PREFIX, l.page_size, limit_file);
+        if !dir.join(&file).exists() {
+            continue;
+        }
+        v2::write_cgroup_file(dir, &file, &l.limit.to_string())?;
+    }
+
+    Ok(())
+}
+
 fn set_block_io_resources(
     _cg: &cgroups::Cgroup,
     blkio: &LinuxBlockIo,
@@ -302,22 +877,39 @@ fn set_block_io_resources(
         build_blk_io_device_throttle_resource(&blkio.throttle_write_iops_device);
 }
 
-fn set_cpu_resources(cg: &cgroups::Cgroup, cpu: &LinuxCpu) -> Result<()> {
+// set_cpu_resources sets the cpu and cpuset resources, `cpu_root` being
+// the root of the cpu hierarchy the RT bandwidth is propagated from.
+fn set_cpu_resources(
+    cg: &cgroups::Cgroup,
+    cpu: &LinuxCpu,
+    cpu_root: Option<&Path>,
+    report: &mut ResourceReport,
+) -> Result<()> {
     info!(sl!(), "cgroup manager set cpu");
 
-    let cpuset_controller: &CpuSetController = cg.controller_of().unwrap();
-
-    if !cpu.cpus.is_empty() {
-        if let Err(e) = cpuset_controller.set_cpus(&cpu.cpus) {
-            warn!(sl!(), "write cpuset failed: {:?}", e);
+    if !cpu.cpus.is_empty() || !cpu.mems.is_empty() {
+        let cpuset_controller: Option<&CpuSetController> = cg.controller_of();
+        match cpuset_controller {
+            Some(cpuset_controller) => {
+                if !cpu.cpus.is_empty() {
+                    if let Err(e) = cpuset_controller.set_cpus(&cpu.cpus) {
+                        report.record("cpu.cpus", &e.to_string());
+                    }
+                }
+
+                if !cpu.mems.is_empty() {
+                    cpuset_controller.set_mems(&cpu.mems)?;
+                }
+            }
+            None => report.skip("cpu.cpus", "cpuset controller not available")?,
         }
     }
 
-    if !cpu.mems.is_empty() {
-        cpuset_controller.set_mems(&cpu.mems)?;
+    if !cpu.cpus_partition.is_empty() {
+        report.skip("cpu.cpusPartition", "not supported by cgroup v1")?;
     }
 
-    let cpu_controller: &CpuController = cg.controller_of().unwrap();
+    let cpu_controller: &CpuController = get_controller!(cg, "cpu");
 
     if let Some(shares) = cpu.shares {
         let shares = if cg.v2() {
@@ -330,20 +922,80 @@ fn set_cpu_resources(cg: &cgroups::Cgroup, cpu: &LinuxCpu) -> Result<()> {
         }
     }
 
-    set_resource!(cpu_controller, set_cfs_quota, cpu, quota);
-    set_resource!(cpu_controller, set_cfs_period, cpu, period);
+    if cpu.quota.unwrap_or(0) != 0 || cpu.period.unwrap_or(0) != 0 {
+        if cpu_controller.path().join(CPU_CFS_QUOTA_US).exists() {
+            set_resource!(cpu_controller, set_cfs_quota, cpu, quota);
+            set_resource!(cpu_controller, set_cfs_period, cpu, period);
+        } else {
+            report.skip("cpu.quota", NO_CFS_BANDWIDTH)?;
+        }
+    }
+
+    if cpu.realtime_runtime.unwrap_or(0) != 0 || cpu.realtime_period.unwrap_or(0) != 0 {
+        // the rt files only exist when the kernel has CONFIG_RT_GROUP_SCHED
+        if cpu_controller.path().join(CPU_RT_RUNTIME_US).exists() {
+            // the ancestors are given the bandwidth first, and the period
+            // is set before the runtime, as runc does
+            if let Some(root) = cpu_root {
+                rt::propagate_rt_bandwidth(
+                    root,
+                    cpu_controller.path(),
+                    cpu.realtime_runtime,
+                    cpu.realtime_period,
+                )?;
+            }
+            set_resource!(cpu_controller, set_rt_period_us, cpu, realtime_period);
+            set_resource!(cpu_controller, set_rt_runtime, cpu, realtime_runtime);
+        } else {
+            report.skip("cpu.realtime", "kernel has no RT group scheduling")?;
+        }
+    }
 
-    set_resource!(cpu_controller, set_rt_runtime, cpu, realtime_runtime);
-    set_resource!(cpu_controller, set_rt_period_us, cpu, realtime_period);
+    let dir = cpu_controller.path();
+    set_cpu_burst_and_idle(dir, CPU_CFS_BURST_US, cpu, report)?;
+
+    Ok(())
+}
+
+// set_cpu_burst_and_idle sets the CFS burst, written to `burst_file`
+// which depends on the cgroup version, and the SCHED_IDLE flag. Both only
+// exist on recent kernels, where resetting them is a no-op.
+fn set_cpu_burst_and_idle(
+    dir: &Path,
+    burst_file: &str,
+    cpu: &LinuxCpu,
+    report: &mut ResourceReport,
+) -> Result<()> {
+    if let Some(burst) = cpu.burst {
+        if dir.join(burst_file).exists() {
+            v2::write_cgroup_file(dir, burst_file, &burst.to_string())?;
+        } else if burst != 0 {
+            report.skip("cpu.burst", "kernel has no CFS burst")?;
+        }
+    }
+
+    if let Some(idle) = cpu.idle {
+        if dir.join(CPU_IDLE).exists() {
+            v2::write_cgroup_file(dir, CPU_IDLE, &idle.to_string())?;
+        } else if idle != 0 {
+            report.skip("cpu.idle", "kernel has no idle cgroups")?;
+        }
+    }
 
     Ok(())
 }
 
-fn set_memory_resources(cg: &cgroups::Cgroup, memory: &LinuxMemory, update: bool) -> Result<()> {
+fn set_memory_resources(
+    cg: &cgroups::Cgroup,
+    memory: &LinuxMemory,
+    init_kmem: bool,
+    report: &mut ResourceReport,
+) -> Result<()> {
     info!(sl!(), "cgroup manager set memory");
-    let mem_controller: &MemController = cg.controller_of().unwrap();
+    let mem_controller: &MemController = get_controller!(cg, "memory");
+    let kmem_supported = is_kmem_limit_supported(mem_controller);
 
-    if !update {
+    if init_kmem && kmem_supported {
         // initialize kmem limits for accounting
         mem_controller.set_kmem_limit(1)?;
         mem_controller.set_kmem_limit(-1)?;
@@ -356,18 +1008,25 @@ fn set_memory_resources(cg: &cgroups::Cgroup, memory: &LinuxMemory, update: bool
         swap = -1;
     }
 
+    // an unlimited swap is all there is without swap accounting
+    if swap != 0 && !is_swap_accounting_supported(mem_controller) {
+        if swap > 0 {
+            report.skip("memory.swap", NO_SWAP_ACCOUNTING)?;
+        }
+        swap = 0;
+    }
+
     if memory.limit.is_some() && swap != 0 {
-        let memstat = get_memory_stats(cg)
-            .into_option()
-            .ok_or_else(|| anyhow!("failed to get the cgroup memory stats"))?;
-        let memusage = memstat.get_usage();
+        let memstat =
+            get_memory_stats(cg).ok_or_else(|| anyhow!("failed to get the cgroup memory stats"))?;
+        let memusage = memstat.usage.unwrap_or_default();
 
         // When update memory limit, the kernel would check the current memory limit
         // set against the new swap setting, if the current memory limit is large than
         // the new swap, then set limit first, otherwise the kernel would complain and
         // refused to set; on the other hand, if the current memory limit is smaller than
         // the new swap, then we should set the swap first and then set the memor limit.
-        if swap == -1 || memusage.get_limit() < swap as u64 {
+        if swap == -1 || memusage.limit < swap as u64 {
             mem_controller.set_memswap_limit(swap)?;
             set_resource!(mem_controller, set_limit, memory, limit);
         } else {
@@ -375,62 +1034,192 @@ fn set_memory_resources(cg: &cgroups::Cgroup, memory: &LinuxMemory, update: bool
             mem_controller.set_memswap_limit(swap)?;
         }
     } else {
+        // the cgroup v2 swap limit is converted by v2::set_resources
         set_resource!(mem_controller, set_limit, memory, limit);
-        swap = if cg.v2() {
-            convert_memory_swap_to_v2_value(swap, memory.limit.unwrap_or(0))?
-        } else {
-            swap
-        };
         if swap != 0 {
             mem_controller.set_memswap_limit(swap)?;
         }
     }
 
     set_resource!(mem_controller, set_soft_limit, memory, reservation);
-    set_resource!(mem_controller, set_kmem_limit, memory, kernel);
+    if kmem_supported {
+        set_resource!(mem_controller, set_kmem_limit, memory, kernel);
+    } else if memory.kernel.unwrap_or(0) != 0 {
+        report.skip("memory.kernel", "not supported by the kernel")?;
+    }
     set_resource!(mem_controller, set_tcp_limit, memory, kernel_tcp);
 
-    if let Some(swappiness) = memory.swappiness {
-        if (0..=100).contains(&swappiness) {
-            mem_controller.set_swappiness(swappiness as u64)?;
-        } else {
-            return Err(anyhow!(
-                "invalid value:{}. valid memory swappiness range is 0-100",
-                swappiness
-            ));
-        }
+    if let Some(swappiness) = validate_swappiness(memory.swappiness)? {
+        mem_controller.set_swappiness(swappiness)?;
     }
 
     if memory.disable_oom_killer.unwrap_or(false) {
         mem_controller.disable_oom_killer()?;
     }
 
+    if memory.high.unwrap_or(0) != 0 {
+        report.skip("memory.high", "not supported by cgroup v1")?;
+    }
+
     Ok(())
 }
 
-fn set_pids_resources(cg: &cgroups::Cgroup, pids: &LinuxPids) -> Result<()> {
-    info!(sl!(), "cgroup manager set pids");
-    let pid_controller: &PidController = cg.controller_of().unwrap();
-    let v = if pids.limit > 0 {
-        MaxValue::Value(pids.limit)
-    } else {
-        MaxValue::Max
-    };
-    pid_controller
-        .set_pid_max(v)
-        .context("failed to set pids resources")
+// ResourceReport collects the resource settings that couldn't be applied,
+// so that they can be reported back to the runtime.
+#[derive(Debug, Default)]
+pub struct ResourceReport {
+    strict: bool,
+    pub unapplied: Vec<String>,
 }
 
-fn build_blk_io_device_throttle_resource(
-    input: &[oci::LinuxThrottleDevice],
-) -> Vec<BlkIoDeviceThrottleResource> {
-    let mut blk_io_device_throttle_resources = vec![];
-    for d in input.iter() {
-        let tr = BlkIoDeviceThrottleResource {
-            major: d.blk.major as u64,
-            minor: d.blk.minor as u64,
-            rate: d.rate,
-        };
+impl ResourceReport {
+    pub fn new(strict: bool) -> Self {
+        ResourceReport {
+            strict,
+            unapplied: vec![],
+        }
+    }
+
+    // skip handles a resource setting that can't be applied: it fails the
+    // request in strict mode, otherwise the setting is skipped and recorded.
+    pub fn skip(&mut self, resource: &str, reason: &str) -> Result<()> {
+        if self.strict {
+            return Err(CgroupError::kernel_unsupported(resource, reason).into());
+        }
+
+        self.record(resource, reason);
+
+        Ok(())
+    }
+
+    // record notes a setting which failed without failing the request,
+    // whatever the mode.
+    pub fn record(&mut self, resource: &str, reason: &str) {
+        warn!(sl!(), "resource not applied"; "resource" => resource, "reason" => reason);
+        self.unapplied.push(format!("{}: {}", resource, reason));
+    }
+}
+
+// read_members returns the pids, or the thread ids, listed in `file` of
+// the cgroup at `dir` and of its exec child, the exec'd processes being
+// members of the container too.
+fn read_members(dir: &Path, file: &str) -> Result<Vec<pid_t>> {
+    let parse = |content: String| -> Vec<pid_t> {
+        content
+            .lines()
+            .filter_map(|l| l.trim().parse::<pid_t>().ok())
+            .collect()
+    };
+
+    let path = dir.join(file);
+    let mut members = parse(
+        fs::read_to_string(&path)
+            .map_err(|e| CgroupError::io(&path, e))
+            .with_context(|| format!("failed to read {:?}", path))?,
+    );
+
+    // the exec child may be removed meanwhile
+    if let Ok(content) = fs::read_to_string(dir.join(EXEC_CGROUP).join(file)) {
+        members.extend(parse(content));
+    }
+
+    Ok(members)
+}
+
+// unified_to_soft_limit takes the memory protection keys out of the
+// unified resources and returns the cgroup v1 soft limit standing for
+// them: memory.low, or memory.min without it.
+fn unified_to_soft_limit(unified: &mut HashMap<String, String>) -> Result<Option<i64>> {
+    let low = unified.remove(MEMORY_LOW);
+    let min = unified.remove(MEMORY_MIN);
+
+    match low.or(min) {
+        Some(v) if v.trim() == "max" => Ok(Some(-1)),
+        Some(v) => v.trim().parse::<i64>().map(Some).map_err(|_| {
+            CgroupError::invalid_value("memory protection", format!("{:?}", v)).into()
+        }),
+        None => Ok(None),
+    }
+}
+
+// Kernel memory limits are deprecated since Linux 5.4 and the interface
+// files are removed from newer kernels.
+fn is_kmem_limit_supported(mem_controller: &MemController) -> bool {
+    mem_controller.path().join(KMEM_LIMIT_IN_BYTES).exists()
+}
+
+fn is_swap_accounting_supported(mem_controller: &MemController) -> bool {
+    mem_controller
+        .path()
+        .join(MEMORY_MEMSW_LIMIT_IN_BYTES)
+        .exists()
+}
+
+fn set_pids_resources(cg: &cgroups::Cgroup, pids: &LinuxPids) -> Result<()> {
+    info!(sl!(), "cgroup manager set pids");
+    let pid_controller: &PidController = get_controller!(cg, "pids");
+    let v = if pids.limit > 0 {
+        MaxValue::Value(pids.limit)
+    } else {
+        MaxValue::Max
+    };
+    pid_controller
+        .set_pid_max(v)
+        .context("failed to set pids resources")
+}
+
+// set_default_swappiness gives the container the swappiness `swappiness`
+// when its spec sets none, rather than the one of the parent cgroup.
+fn set_default_swappiness(r: &mut LinuxResources, swappiness: Option<u64>) {
+    if let Some(swappiness) = swappiness {
+        let memory = r.memory.get_or_insert_with(LinuxMemory::default);
+        if memory.swappiness.is_none() {
+            memory.swappiness = Some(swappiness as i64);
+        }
+    }
+}
+
+// translate_block_devices returns the resources with the host block devices
+// of the blkio limits replaced by their guest devices, the devices which
+// aren't in the map being kept.
+fn translate_block_devices(r: &LinuxResources, device_map: &DeviceMap) -> LinuxResources {
+    let mut r = r.clone();
+    if device_map.is_empty() {
+        return r;
+    }
+
+    if let Some(blkio) = r.block_io.as_mut() {
+        let devices = blkio.weight_device.iter_mut().map(|d| &mut d.blk).chain(
+            blkio
+                .throttle_read_bps_device
+                .iter_mut()
+                .chain(blkio.throttle_write_bps_device.iter_mut())
+                .chain(blkio.throttle_read_iops_device.iter_mut())
+                .chain(blkio.throttle_write_iops_device.iter_mut())
+                .map(|d| &mut d.blk),
+        );
+
+        for blk in devices {
+            if let Some((major, minor)) = device_map.get(&(blk.major, blk.minor)) {
+                blk.major = *major;
+                blk.minor = *minor;
+            }
+        }
+    }
+
+    r
+}
+
+fn build_blk_io_device_throttle_resource(
+    input: &[oci::LinuxThrottleDevice],
+) -> Vec<BlkIoDeviceThrottleResource> {
+    let mut blk_io_device_throttle_resources = vec![];
+    for d in input.iter() {
+        let tr = BlkIoDeviceThrottleResource {
+            major: d.blk.major as u64,
+            minor: d.blk.minor as u64,
+            rate: d.rate,
+        };
         blk_io_device_throttle_resources.push(tr);
     }
 
@@ -458,6 +1247,94 @@ fn linux_device_to_cgroup_device(d: &LinuxDevice) -> Option<DeviceResource> {
     })
 }
 
+// read_freezer_state reads the state of the cgroup v1 freezer at `dir`.
+// Without the freezer, e.g. for a precreated cgroup which wasn't created in
+// its hierarchy, the cgroup can't be frozen.
+fn read_freezer_state(dir: Option<&Path>) -> Result<FreezerState> {
+    let path = match dir {
+        Some(dir) => dir.join(FREEZER_STATE),
+        None => return Ok(FreezerState::Thawed),
+    };
+
+    match fs::read_to_string(&path) {
+        Ok(state) => parse_freezer_state(&state),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(FreezerState::Thawed),
+        Err(e) => Err(e).with_context(|| format!("failed to read {:?}", path)),
+    }
+}
+
+// parse_freezer_state parses the content of freezer.state.
+fn parse_freezer_state(state: &str) -> Result<FreezerState> {
+    match state.trim() {
+        "THAWED" => Ok(FreezerState::Thawed),
+        "FREEZING" => Ok(FreezerState::Freezing),
+        "FROZEN" => Ok(FreezerState::Frozen),
+        s => Err(anyhow!("invalid freezer state {:?}", s)),
+    }
+}
+
+// parse_devices_list parses the content of devices.list, the devices a
+// cgroup v1 allows, one per line as written to devices.allow.
+fn parse_devices_list(content: &str) -> Result<Vec<LinuxDeviceCgroup>> {
+    content
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| {
+            let fields: Vec<&str> = l.split_whitespace().collect();
+            let numbers: Vec<&str> = fields.get(1).map_or(vec![], |n| n.split(':').collect());
+            if fields.len() != 3 || numbers.len() != 2 {
+                return Err(anyhow!("invalid device rule {:?}", l));
+            }
+
+            let number = |n: &str| -> Result<Option<i64>> {
+                match n {
+                    "*" => Ok(None),
+                    n => Ok(Some(
+                        n.parse()
+                            .with_context(|| format!("invalid device rule {:?}", l))?,
+                    )),
+                }
+            };
+
+            Ok(LinuxDeviceCgroup {
+                allow: true,
+                r#type: fields[0].to_string(),
+                major: number(numbers[0])?,
+                minor: number(numbers[1])?,
+                access: fields[2].to_string(),
+            })
+        })
+        .collect()
+}
+
+// device_rule_string returns a rule as written to devices.allow or
+// devices.deny, e.g. "c 1:3 rwm".
+fn device_rule_string(rule: &LinuxDeviceCgroup) -> Result<String> {
+    let dev_type = match rule.r#type.as_str() {
+        "" | "a" => "a",
+        "b" => "b",
+        "c" => "c",
+        t => return Err(anyhow!("invalid device type {:?}", t)),
+    };
+
+    if rule.access.is_empty() || !rule.access.chars().all(|c| "rwm".contains(c)) {
+        return Err(anyhow!("invalid device access {:?}", rule.access));
+    }
+
+    let number = |n: Option<i64>| match n {
+        Some(n) if n != WILDCARD => n.to_string(),
+        _ => "*".to_string(),
+    };
+
+    Ok(format!(
+        "{} {}:{} {}",
+        dev_type,
+        number(rule.major),
+        number(rule.minor),
+        rule.access
+    ))
+}
+
 fn linux_device_group_to_cgroup_device(d: &LinuxDeviceCgroup) -> Option<DeviceResource> {
     let dev_type = match DeviceType::from_char(d.r#type.chars().next()) {
         Some(t) => t,
@@ -502,145 +1379,156 @@ fn lines_to_map(content: &str) -> HashMap<String, u64> {
         })
 }
 
+// the child of the container cgroup the exec'd processes are moved into,
+// so that their usage is told apart from the one of the workload, the
+// limits of the container cgroup applying to them all the same
+pub const EXEC_CGROUP: &str = "exec";
+// the cgroup v1 controllers the exec child is created in, the ones the
+// stats of the exec'd processes are read from
+const EXEC_CONTROLLERS: &[&str] = &["cpu", "cpuacct", "memory", "blkio", "pids"];
+const PROC_ROOT: &str = "/proc";
+
 pub const NANO_PER_SECOND: u64 = 1000000000;
 pub const WILDCARD: i64 = -1;
 
 lazy_static! {
+    static ref IS_CGROUP2_UNIFIED: bool = cgroups::hierarchies::is_cgroup2_unified_mode();
     pub static ref CLOCK_TICKS: f64 = {
         let n = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
 
         n as f64
     };
+    pub static ref DEFAULT_ALLOWED_DEVICES: Vec<LinuxDeviceCgroup> = build_default_allowed_devices(
+        &fs::read_to_string(PROC_DEVICES).unwrap_or_default(),
+        &fs::read_to_string(PROC_MISC).unwrap_or_default(),
+    );
+}
 
-    pub static ref DEFAULT_ALLOWED_DEVICES: Vec<LinuxDeviceCgroup> = {
-        vec![
-            // all mknod to all char devices
-            LinuxDeviceCgroup {
-                allow: true,
-                r#type: "c".to_string(),
-                major: Some(WILDCARD),
-                minor: Some(WILDCARD),
-                access: "m".to_string(),
-            },
-
-            // all mknod to all block devices
-            LinuxDeviceCgroup {
-                allow: true,
-                r#type: "b".to_string(),
-                major: Some(WILDCARD),
-                minor: Some(WILDCARD),
-                access: "m".to_string(),
-            },
-
-            // all read/write/mknod to char device /dev/console
-            LinuxDeviceCgroup {
-                allow: true,
-                r#type: "c".to_string(),
-                major: Some(5),
-                minor: Some(1),
-                access: "rwm".to_string(),
-            },
-
-            // all read/write/mknod to char device /dev/pts/<N>
-            LinuxDeviceCgroup {
-                allow: true,
-                r#type: "c".to_string(),
-                major: Some(136),
-                minor: Some(WILDCARD),
-                access: "rwm".to_string(),
-            },
-
-            // all read/write/mknod to char device /dev/ptmx
-            LinuxDeviceCgroup {
-                allow: true,
-                r#type: "c".to_string(),
-                major: Some(5),
-                minor: Some(2),
-                access: "rwm".to_string(),
-            },
+// parse_device_numbers parses the "<number> <name>" lines of /proc/misc,
+// or of the character devices section of /proc/devices, into an hashmap
+// of <name, number>.
+fn parse_device_numbers(content: &str) -> HashMap<String, i64> {
+    content
+        .lines()
+        .take_while(|x| !x.starts_with("Block devices"))
+        .map(|x| x.split_whitespace().collect::<Vec<&str>>())
+        .filter(|x| x.len() == 2 && x[0].parse::<i64>().is_ok())
+        .fold(HashMap::new(), |mut hm, x| {
+            hm.entry(x[1].to_string())
+                .or_insert_with(|| x[0].parse::<i64>().unwrap());
+            hm
+        })
+}
 
-            // all read/write/mknod to char device /dev/net/tun
-            LinuxDeviceCgroup {
-                allow: true,
-                r#type: "c".to_string(),
-                major: Some(10),
-                minor: Some(200),
-                access: "rwm".to_string(),
-            },
-        ]
-    };
+// build_default_allowed_devices looks the device numbers up in the
+// contents of /proc/devices and /proc/misc, as they can be assigned
+// dynamically, and falls back to the numbers reserved in the kernel's
+// Documentation/admin-guide/devices.txt.
+fn build_default_allowed_devices(devices: &str, misc: &str) -> Vec<LinuxDeviceCgroup> {
+    let majors = parse_device_numbers(devices);
+    let misc_minors = parse_device_numbers(misc);
+
+    let major = |name: &str, default: i64| *majors.get(name).unwrap_or(&default);
+    let misc_minor = |name: &str, default: i64| *misc_minors.get(name).unwrap_or(&default);
+
+    vec![
+        // all mknod to all char devices
+        LinuxDeviceCgroup {
+            allow: true,
+            r#type: "c".to_string(),
+            major: Some(WILDCARD),
+            minor: Some(WILDCARD),
+            access: "m".to_string(),
+        },
+        // all mknod to all block devices
+        LinuxDeviceCgroup {
+            allow: true,
+            r#type: "b".to_string(),
+            major: Some(WILDCARD),
+            minor: Some(WILDCARD),
+            access: "m".to_string(),
+        },
+        // all read/write/mknod to char device /dev/console
+        LinuxDeviceCgroup {
+            allow: true,
+            r#type: "c".to_string(),
+            major: Some(major("/dev/console", 5)),
+            minor: Some(1),
+            access: "rwm".to_string(),
+        },
+        // all read/write/mknod to char device /dev/pts/<N>
+        LinuxDeviceCgroup {
+            allow: true,
+            r#type: "c".to_string(),
+            major: Some(major("pts", 136)),
+            minor: Some(WILDCARD),
+            access: "rwm".to_string(),
+        },
+        // all read/write/mknod to char device /dev/ptmx
+        LinuxDeviceCgroup {
+            allow: true,
+            r#type: "c".to_string(),
+            major: Some(major("/dev/ptmx", 5)),
+            minor: Some(2),
+            access: "rwm".to_string(),
+        },
+        // all read/write/mknod to char device /dev/net/tun
+        LinuxDeviceCgroup {
+            allow: true,
+            r#type: "c".to_string(),
+            major: Some(major("misc", 10)),
+            minor: Some(misc_minor("tun", 200)),
+            access: "rwm".to_string(),
+        },
+    ]
 }
 
-fn get_cpu_stats(cg: &cgroups::Cgroup) -> SingularPtrField<ThrottlingData> {
-    let cpu_controller: &CpuController = get_controller_or_return_singular_none!(cg);
+fn get_cpu_stats(cg: &cgroups::Cgroup) -> Option<ThrottlingData> {
+    let cpu_controller: &CpuController = get_controller_or_return_none!(cg);
     let stat = cpu_controller.cpu().stat;
     let h = lines_to_map(&stat);
 
-    SingularPtrField::some(ThrottlingData {
+    Some(ThrottlingData {
         periods: *h.get("nr_periods").unwrap_or(&0),
         throttled_periods: *h.get("nr_throttled").unwrap_or(&0),
         throttled_time: *h.get("throttled_time").unwrap_or(&0),
-        unknown_fields: UnknownFields::default(),
-        cached_size: CachedSize::default(),
+        nr_bursts: *h.get("nr_bursts").unwrap_or(&0),
+        burst_time: *h.get("burst_time").unwrap_or(&0),
     })
 }
 
-fn get_cpuacct_stats(cg: &cgroups::Cgroup) -> SingularPtrField<CpuUsage> {
-    if let Some(cpuacct_controller) = cg.controller_of::<CpuAcctController>() {
-        let cpuacct = cpuacct_controller.cpuacct();
-
-        let h = lines_to_map(&cpuacct.stat);
-        let usage_in_usermode =
-            (((*h.get("user").unwrap() * NANO_PER_SECOND) as f64) / *CLOCK_TICKS) as u64;
-        let usage_in_kernelmode =
-            (((*h.get("system").unwrap() * NANO_PER_SECOND) as f64) / *CLOCK_TICKS) as u64;
-
-        let total_usage = cpuacct.usage;
-
-        let percpu_usage = line_to_vec(&cpuacct.usage_percpu);
-
-        return SingularPtrField::some(CpuUsage {
-            total_usage,
-            percpu_usage,
-            usage_in_kernelmode,
-            usage_in_usermode,
-            unknown_fields: UnknownFields::default(),
-            cached_size: CachedSize::default(),
-        });
-    }
+// get_cpuacct_stats reads the cpu usage of the cgroup v1 cpuacct controller.
+// The cgroup v2 usage is read by v2::get_stats, without the per-cpu usage
+// which cgroup v2 doesn't account.
+fn get_cpuacct_stats(cg: &cgroups::Cgroup) -> Option<CpuUsage> {
+    let cpuacct_controller: &CpuAcctController = get_controller_or_return_none!(cg);
+    let cpuacct = cpuacct_controller.cpuacct();
+
+    Some(build_cpuacct_usage(
+        &cpuacct.stat,
+        cpuacct.usage,
+        &cpuacct.usage_percpu,
+    ))
+}
 
-    if cg.v2() {
-        return SingularPtrField::some(CpuUsage {
-            total_usage: 0,
-            percpu_usage: vec![],
-            usage_in_kernelmode: 0,
-            usage_in_usermode: 0,
-            unknown_fields: UnknownFields::default(),
-            cached_size: CachedSize::default(),
-        });
+// build_cpuacct_usage returns the cpu usage from the content of
+// cpuacct.stat, in clock ticks, cpuacct.usage and cpuacct.usage_percpu,
+// the missing fields being zero.
+fn build_cpuacct_usage(stat: &str, usage: u64, usage_percpu: &str) -> CpuUsage {
+    let h = lines_to_map(stat);
+    let ticks_to_ns =
+        |k: &str| ((*h.get(k).unwrap_or(&0) * NANO_PER_SECOND) as f64 / *CLOCK_TICKS) as u64;
+
+    CpuUsage {
+        total_usage: usage,
+        percpu_usage: line_to_vec(usage_percpu),
+        usage_in_kernelmode: ticks_to_ns("system"),
+        usage_in_usermode: ticks_to_ns("user"),
     }
-
-    // try to get from cpu controller
-    let cpu_controller: &CpuController = get_controller_or_return_singular_none!(cg);
-    let stat = cpu_controller.cpu().stat;
-    let h = lines_to_map(&stat);
-    let usage_in_usermode = *h.get("user_usec").unwrap();
-    let usage_in_kernelmode = *h.get("system_usec").unwrap();
-    let total_usage = *h.get("usage_usec").unwrap();
-    let percpu_usage = vec![];
-
-    SingularPtrField::some(CpuUsage {
-        total_usage,
-        percpu_usage,
-        usage_in_kernelmode,
-        usage_in_usermode,
-        unknown_fields: UnknownFields::default(),
-        cached_size: CachedSize::default(),
-    })
 }
 
-fn get_memory_stats(cg: &cgroups::Cgroup) -> SingularPtrField<MemoryStats> {
-    let memory_controller: &MemController = get_controller_or_return_singular_none!(cg);
+fn get_memory_stats(cg: &cgroups::Cgroup) -> Option<MemoryStats> {
+    let memory_controller: &MemController = get_controller_or_return_none!(cg);
 
     // cache from memory stat
     let memory = memory_controller.memory_stat();
@@ -651,70 +1539,124 @@ fn get_memory_stats(cg: &cgroups::Cgroup) -> SingularPtrField<MemoryStats> {
     let use_hierarchy = value == 1;
 
     // gte memory datas
-    let usage = SingularPtrField::some(MemoryData {
+    let usage = Some(MemoryData {
         usage: memory.usage_in_bytes,
         max_usage: memory.max_usage_in_bytes,
         failcnt: memory.fail_cnt,
-        limit: memory.limit_in_bytes as u64,
-        unknown_fields: UnknownFields::default(),
-        cached_size: CachedSize::default(),
+        limit: memory_v1_limit(memory.limit_in_bytes),
     });
 
     // get swap usage
     let memswap = memory_controller.memswap();
 
-    let swap_usage = SingularPtrField::some(MemoryData {
+    let swap_usage = Some(MemoryData {
         usage: memswap.usage_in_bytes,
         max_usage: memswap.max_usage_in_bytes,
         failcnt: memswap.fail_cnt,
-        limit: memswap.limit_in_bytes as u64,
-        unknown_fields: UnknownFields::default(),
-        cached_size: CachedSize::default(),
+        limit: memory_v1_limit(memswap.limit_in_bytes),
     });
 
     // get kernel usage
     let kmem_stat = memory_controller.kmem_stat();
 
-    let kernel_usage = SingularPtrField::some(MemoryData {
+    let kernel_usage = Some(MemoryData {
         usage: kmem_stat.usage_in_bytes,
         max_usage: kmem_stat.max_usage_in_bytes,
         failcnt: kmem_stat.fail_cnt,
-        limit: kmem_stat.limit_in_bytes as u64,
-        unknown_fields: UnknownFields::default(),
-        cached_size: CachedSize::default(),
+        limit: memory_v1_limit(kmem_stat.limit_in_bytes),
     });
 
-    SingularPtrField::some(MemoryStats {
+    Some(MemoryStats {
         cache,
         usage,
         swap_usage,
         kernel_usage,
         use_hierarchy,
         stats: memory.stat.raw,
-        unknown_fields: UnknownFields::default(),
-        cached_size: CachedSize::default(),
+        low: memory_v1_limit(memory.soft_limit_in_bytes),
+        min: 0,
+        // there is no throttling limit on cgroup v1, i.e. "max"
+        high: u64::MAX,
+        oom_group_kill: 0,
+        swap_supported: is_swap_accounting_supported(memory_controller),
+        events: Some(get_memory_events(memory_controller.path(), memory.fail_cnt)),
+    })
+}
+
+// memory_v1_limit returns a cgroup v1 memory limit as the v2 ones are
+// reported, u64::MAX standing for none as "max" does.
+fn memory_v1_limit(v: i64) -> u64 {
+    if v < 0 || v >= MEMORY_V1_UNLIMITED {
+        u64::MAX
+    } else {
+        v as u64
+    }
+}
+
+// get_memory_events returns the memory events of the cgroup v1 memory
+// controller located at `dir`, out of memory.oom_control, and the hits of
+// the limit `failcnt`. The OOMs aren't counted apart from the OOM kills on
+// cgroup v1, so the latter stand for both. There is no memory.high.
+fn get_memory_events(dir: &Path, failcnt: u64) -> MemoryEvents {
+    let oom_control = fs::read_to_string(dir.join(MEMORY_OOM_CONTROL))
+        .map(|c| lines_to_map(&c))
+        .unwrap_or_default();
+
+    let mut events = MemoryEvents::default();
+    events.oom_kill = *oom_control.get("oom_kill").unwrap_or(&0);
+    events.oom = events.oom_kill;
+    events.under_oom = oom_control.get("under_oom") == Some(&1);
+    events.max = failcnt;
+
+    events
+}
+
+// get_memory_usage reads the memory usage of the cgroup v1 memory
+// controller located at `dir`, without parsing memory.stat.
+fn get_memory_usage(dir: &Path) -> Result<MemoryUsage> {
+    let read = |file: &str| -> Result<u64> {
+        let path = dir.join(file);
+        fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {:?}", path))?
+            .trim()
+            .parse::<u64>()
+            .with_context(|| format!("invalid value in {:?}", path))
+    };
+
+    let usage = read(MEMORY_USAGE_IN_BYTES)?;
+    // memsw is there with swap accounting only, and counts the memory too
+    let swap_usage = read(MEMORY_MEMSW_USAGE_IN_BYTES)
+        .map(|v| v.saturating_sub(usage))
+        .unwrap_or(0);
+
+    Ok(MemoryUsage {
+        usage,
+        limit: memory_v1_limit(read(MEMORY_LIMIT_IN_BYTES)? as i64),
+        swap_usage,
     })
 }
 
-fn get_pids_stats(cg: &cgroups::Cgroup) -> SingularPtrField<PidsStats> {
-    let pid_controller: &PidController = get_controller_or_return_singular_none!(cg);
+fn get_pids_stats(cg: &cgroups::Cgroup) -> Option<PidsStats> {
+    let pid_controller: &PidController = get_controller_or_return_none!(cg);
 
     let current = pid_controller.get_pid_current().unwrap_or(0);
     let max = pid_controller.get_pid_max();
 
+    // as on cgroup v2, u64::MAX stands for no limit
     let limit = match max {
-        Err(_) => 0,
-        Ok(max) => match max {
-            MaxValue::Value(v) => v,
-            MaxValue::Max => 0,
-        },
-    } as u64;
+        Ok(MaxValue::Value(v)) if v >= 0 => v as u64,
+        _ => u64::MAX,
+    };
 
-    SingularPtrField::some(PidsStats {
+    // not known to cgroups-rs
+    let max_events = fs::read_to_string(pid_controller.path().join(PIDS_EVENTS))
+        .map(|c| *lines_to_map(&c).get("max").unwrap_or(&0))
+        .unwrap_or(0);
+
+    Some(PidsStats {
         current,
         limit,
-        unknown_fields: UnknownFields::default(),
-        cached_size: CachedSize::default(),
+        max_events,
     })
 }
 
@@ -750,8 +1692,8 @@ https://github.com/opencontainers/runc/blob/a5847db387ae28c0ca4ebe4beee1a76900c8
     Total 0
 */
 
-fn get_blkio_stat_blkiodata(blkiodata: &[BlkIoData]) -> RepeatedField<BlkioStatsEntry> {
-    let mut m = RepeatedField::new();
+fn get_blkio_stat_blkiodata(blkiodata: &[BlkIoData]) -> Vec<BlkioStatsEntry> {
+    let mut m = Vec::new();
     if blkiodata.is_empty() {
         return m;
     }
@@ -764,16 +1706,14 @@ fn get_blkio_stat_blkiodata(blkiodata: &[BlkIoData]) -> RepeatedField<BlkioStats
             minor: d.minor as u64,
             op: op.clone(),
             value: d.data,
-            unknown_fields: UnknownFields::default(),
-            cached_size: CachedSize::default(),
         });
     }
 
     m
 }
 
-fn get_blkio_stat_ioservice(services: &[IoService]) -> RepeatedField<BlkioStatsEntry> {
-    let mut m = RepeatedField::new();
+fn get_blkio_stat_ioservice(services: &[IoService]) -> Vec<BlkioStatsEntry> {
+    let mut m = Vec::new();
 
     if services.is_empty() {
         return m;
@@ -797,17 +1737,15 @@ fn build_blkio_stats_entry(major: i16, minor: i16, op: &str, value: u64) -> Blki
         minor: minor as u64,
         op: op.to_string(),
         value,
-        unknown_fields: UnknownFields::default(),
-        cached_size: CachedSize::default(),
     }
 }
 
-fn get_blkio_stats_v2(cg: &cgroups::Cgroup) -> SingularPtrField<BlkioStats> {
-    let blkio_controller: &BlkIoController = get_controller_or_return_singular_none!(cg);
+fn get_blkio_stats_v2(cg: &cgroups::Cgroup) -> Option<BlkioStats> {
+    let blkio_controller: &BlkIoController = get_controller_or_return_none!(cg);
     let blkio = blkio_controller.blkio();
 
-    let mut resp = BlkioStats::new();
-    let mut blkio_stats = RepeatedField::new();
+    let mut resp = BlkioStats::default();
+    let mut blkio_stats = Vec::new();
 
     let stat = blkio.io_stat;
     for s in stat {
@@ -823,18 +1761,18 @@ fn get_blkio_stats_v2(cg: &cgroups::Cgroup) -> SingularPtrField<BlkioStats> {
 
     resp.io_service_bytes_recursive = blkio_stats;
 
-    SingularPtrField::some(resp)
+    Some(resp)
 }
 
-fn get_blkio_stats(cg: &cgroups::Cgroup) -> SingularPtrField<BlkioStats> {
+fn get_blkio_stats(cg: &cgroups::Cgroup) -> Option<BlkioStats> {
     if cg.v2() {
         return get_blkio_stats_v2(&cg);
     }
 
-    let blkio_controller: &BlkIoController = get_controller_or_return_singular_none!(cg);
+    let blkio_controller: &BlkIoController = get_controller_or_return_none!(cg);
     let blkio = blkio_controller.blkio();
 
-    let mut m = BlkioStats::new();
+    let mut m = BlkioStats::default();
     let io_serviced_recursive = blkio.io_serviced_recursive;
 
     if io_serviced_recursive.is_empty() {
@@ -859,32 +1797,50 @@ fn get_blkio_stats(cg: &cgroups::Cgroup) -> SingularPtrField<BlkioStats> {
         m.sectors_recursive = get_blkio_stat_blkiodata(&blkio.sectors_recursive);
     }
 
-    SingularPtrField::some(m)
+    Some(m)
 }
 
 fn get_hugetlb_stats(cg: &cgroups::Cgroup) -> HashMap<String, HugetlbStats> {
+    let hugetlb_controller: Option<&HugeTlbController> = cg.controller_of();
+    hugetlb_controller.map_or_else(HashMap::new, |c| read_hugetlb_stats(c.path()))
+}
+
+// read_hugetlb_stats returns the hugetlb usage of the cgroup v1 at `dir` by
+// page size, from the files rather than cgroups-rs, which doesn't know the
+// reservations.
+fn read_hugetlb_stats(dir: &Path) -> HashMap<String, HugetlbStats> {
     let mut h = HashMap::new();
 
-    let hugetlb_controller: Option<&HugeTlbController> = cg.controller_of();
-    if hugetlb_controller.is_none() {
-        return h;
-    }
-    let hugetlb_controller = hugetlb_controller.unwrap();
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => return h,
+    };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        // hugetlb.<size>.rsvd.usage_in_bytes being the reservations of <size>
+        let size = match name
+            .strip_prefix(v2::HUGETLB_PREFIX)
+            .and_then(|n| n.strip_suffix(".usage_in_bytes"))
+        {
+            Some(s) if !s.ends_with(".rsvd") => s.to_string(),
+            _ => continue,
+        };
 
-    let sizes = hugetlb_controller.get_sizes();
-    for size in sizes {
-        let usage = hugetlb_controller.usage_in_bytes(&size).unwrap_or(0);
-        let max_usage = hugetlb_controller.max_usage_in_bytes(&size).unwrap_or(0);
-        let failcnt = hugetlb_controller.failcnt(&size).unwrap_or(0);
+        let value = |f: &str| {
+            v2::read_single_value(dir, &format!("{}{}.{}", v2::HUGETLB_PREFIX, size, f))
+                .unwrap_or(0)
+        };
 
         h.insert(
-            size.to_string(),
+            size.clone(),
             HugetlbStats {
-                usage,
-                max_usage,
-                failcnt,
-                unknown_fields: UnknownFields::default(),
-                cached_size: CachedSize::default(),
+                usage: value("usage_in_bytes"),
+                max_usage: value("max_usage_in_bytes"),
+                failcnt: value("failcnt"),
+                rsvd_usage: value("rsvd.usage_in_bytes"),
+                rsvd_max_usage: value("rsvd.max_usage_in_bytes"),
+                rsvd_failcnt: value("rsvd.failcnt"),
             },
         );
     }
@@ -892,12 +1848,27 @@ fn get_hugetlb_stats(cg: &cgroups::Cgroup) -> HashMap<String, HugetlbStats> {
     h
 }
 
+// read_pressure returns the pressure stall information of the cgroup at
+// `dir`, for the resources the kernel has it for.
+fn read_pressure(dir: &Path) -> Vec<(&'static str, psi::Pressure)> {
+    psi::PSI_RESOURCES
+        .iter()
+        .filter_map(|r| psi::read_pressure(dir, r).ok().map(|p| (*r, p)))
+        .collect()
+}
+
 pub const PATHS: &str = "/proc/self/cgroup";
 pub const MOUNTS: &str = "/proc/self/mountinfo";
 
 pub fn get_paths() -> Result<HashMap<String, String>> {
+    Ok(parse_paths(&fs::read_to_string(PATHS)?))
+}
+
+// parse_paths returns the cgroup of each hierarchy out of the content of
+// /proc/<pid>/cgroup, by controller or name.
+pub fn parse_paths(content: &str) -> HashMap<String, String> {
     let mut m = HashMap::new();
-    for l in fs::read_to_string(PATHS)?.lines() {
+    for l in content.lines() {
         let fl: Vec<&str> = l.split(':').collect();
         if fl.len() != 3 {
             info!(sl!(), "Corrupted cgroup data!");
@@ -915,12 +1886,15 @@ pub fn get_paths() -> Result<HashMap<String, String>> {
             }
         }
     }
-    Ok(m)
+    m
 }
 
 pub fn get_mounts() -> Result<HashMap<String, String>> {
+    get_mounts_of(&get_paths()?)
+}
+
+fn get_mounts_of(paths: &HashMap<String, String>) -> Result<HashMap<String, String>> {
     let mut m = HashMap::new();
-    let paths = get_paths()?;
 
     for l in fs::read_to_string(MOUNTS)?.lines() {
         let p: Vec<&str> = l.splitn(2, " - ").collect();
@@ -939,7 +1913,13 @@ pub fn get_mounts() -> Result<HashMap<String, String>> {
         let names: Vec<&str> = post[2].split(',').collect();
 
         for name in &names {
-            if paths.contains_key(*name) {
+            // named as in get_paths
+            let name = if *name == "name=systemd" {
+                "systemd"
+            } else {
+                name
+            };
+            if paths.contains_key(name) {
                 m.insert(name.to_string(), pre[4].to_string());
             }
         }
@@ -948,17 +1928,223 @@ pub fn get_mounts() -> Result<HashMap<String, String>> {
     Ok(m)
 }
 
+// get_cgroup_details returns the cgroup mode of the guest and the controllers
+// the containers can be limited with, so that the runtime knows beforehand
+// the resources the guest can't honor.
+pub fn get_cgroup_details() -> Result<CgroupDetails> {
+    let mut details = CgroupDetails::new();
+
+    if Backend::detect() == Backend::V2 {
+        details.mode = CgroupMode::CGROUP_V2;
+        details.controllers = v2::get_controllers(Path::new(v2::CGROUP2_ROOT))?.into();
+        return Ok(details);
+    }
+
+    let paths = get_paths()?;
+    // the cgroup v2 hierarchy is listed with an empty controller list
+    details.mode = if paths.contains_key("") {
+        CgroupMode::CGROUP_HYBRID
+    } else {
+        CgroupMode::CGROUP_V1
+    };
+    details.controllers = v1_controllers(&get_mounts_of(&paths)?).into();
+
+    Ok(details)
+}
+
+// v1_controllers returns the controllers of the mounted cgroup v1
+// hierarchies, leaving out the named ones such as systemd's.
+fn v1_controllers(mounts: &HashMap<String, String>) -> Vec<String> {
+    let mut controllers: Vec<String> = mounts
+        .keys()
+        .filter(|c| !c.is_empty() && *c != "systemd" && !c.starts_with("name="))
+        .cloned()
+        .collect();
+    controllers.sort();
+
+    controllers
+}
+
 fn new_cgroup(h: Box<dyn cgroups::Hierarchy>, path: &str) -> Cgroup {
     let valid_path = path.trim_start_matches('/').to_string();
     cgroups::Cgroup::new(h, valid_path.as_str())
 }
 
+// load_cgroup returns the handle of an existing cgroup, without creating
+// any directory.
+fn load_cgroup(h: Box<dyn cgroups::Hierarchy>, path: &str) -> Cgroup {
+    let valid_path = path.trim_start_matches('/').to_string();
+    cgroups::Cgroup::load(h, valid_path.as_str())
+}
+
+// ManagerBuilder configures and creates a Manager.
+#[derive(Debug, Default)]
+pub struct ManagerBuilder {
+    cpath: String,
+    cgroup_root: String,
+    backend: Option<Backend>,
+    disable_kmem_accounting: bool,
+    strict_resources: bool,
+    precreated: bool,
+    oom_group: bool,
+    io_cost: bool,
+    default_swappiness: Option<u64>,
+    device_map: DeviceMap,
+    rootless: bool,
+    unified_root: Option<PathBuf>,
+}
+
+impl ManagerBuilder {
+    pub fn new(cpath: &str) -> Self {
+        ManagerBuilder {
+            cpath: cpath.to_string(),
+            ..Default::default()
+        }
+    }
+
+    // cgroup_root creates the cgroup below `root` rather than at the top of
+    // the guest hierarchy, the path of the cgroup being taken relative to
+    // it. The precreated cgroups are looked up below it too, since the
+    // annotation asking for them comes from the spec.
+    pub fn cgroup_root(mut self, root: &str) -> Self {
+        self.cgroup_root = root.to_string();
+        self
+    }
+
+    // backend forces the cgroup backend, which is detected otherwise.
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    // disable_kmem_accounting skips the kernel memory accounting
+    // initialization when the container cgroup is first set up.
+    pub fn disable_kmem_accounting(mut self, disable: bool) -> Self {
+        self.disable_kmem_accounting = disable;
+        self
+    }
+
+    // strict_resources makes resource settings that can't be applied
+    // fail the request instead of being skipped.
+    pub fn strict_resources(mut self, strict: bool) -> Self {
+        self.strict_resources = strict;
+        self
+    }
+
+    // precreated joins the cgroup created beforehand by someone else,
+    // below the cgroup root, instead of creating it, and leaves it in
+    // place on destroy.
+    pub fn precreated(mut self, precreated: bool) -> Self {
+        self.precreated = precreated;
+        self
+    }
+
+    // oom_group makes an OOM kill all the processes of the cgroup, as
+    // runc does, rather than leaving the container half dead. This needs
+    // cgroup v2.
+    pub fn oom_group(mut self, oom_group: bool) -> Self {
+        self.oom_group = oom_group;
+        self
+    }
+
+    // io_cost turns on the iocost controller for the block devices the
+    // container has a weight for, so that the weights are enforced
+    // without the BFQ scheduler. This needs cgroup v2.
+    pub fn io_cost(mut self, io_cost: bool) -> Self {
+        self.io_cost = io_cost;
+        self
+    }
+
+    // default_swappiness sets the swappiness of the container when its spec
+    // sets none. This needs cgroup v1.
+    pub fn default_swappiness(mut self, swappiness: Option<u64>) -> Self {
+        self.default_swappiness = swappiness;
+        self
+    }
+
+    // device_map translates the block devices of the blkio limits, for the
+    // volumes the host hot plugs as block devices.
+    pub fn device_map(mut self, device_map: DeviceMap) -> Self {
+        self.device_map = device_map;
+        self
+    }
+
+    // rootless creates the cgroup below the delegation root, for the agent
+    // which can't write to the whole cgroup v2 hierarchy.
+    pub fn rootless(mut self, rootless: bool) -> Self {
+        self.rootless = rootless;
+        self
+    }
+
+    // unified_root runs the manager against the cgroup v2 hierarchy at
+    // `root` rather than the one of the guest, e.g. a plain directory the
+    // tests read and write the interface files in. The cgroup is created
+    // there as a directory, with cgroup v2, and the files cgroups-rs would
+    // write in the hierarchy of the guest are written directly. It doesn't
+    // cover cgroup v1 nor the notifier.
+    pub fn unified_root(mut self, root: &Path) -> Self {
+        self.unified_root = Some(root.to_path_buf());
+        self
+    }
+
+    pub fn build(self) -> Result<Manager> {
+        let cpath = super::cgroup_path(&self.cgroup_root, &self.cpath)?;
+
+        if let Some(root) = self.unified_root.clone() {
+            let m = Manager::new_at_root(&cpath, &root)?;
+            return Ok(self.configure(m));
+        }
+
+        let backend = self.backend.unwrap_or_else(Backend::detect);
+        let m = Manager::new_with_backend(&cpath, backend, self.precreated, self.rootless)?;
+
+        Ok(self.configure(m))
+    }
+
+    fn configure(self, mut m: Manager) -> Manager {
+        m.disable_kmem_accounting = self.disable_kmem_accounting;
+        m.strict_resources = self.strict_resources;
+        m.oom_group = self.oom_group;
+        m.io_cost = self.io_cost;
+        m.default_swappiness = self.default_swappiness;
+        m.device_map = self.device_map;
+
+        m
+    }
+}
+
 impl Manager {
     pub fn new(cpath: &str) -> Result<Self> {
+        ManagerBuilder::new(cpath).build()
+    }
+
+    fn new_with_backend(
+        cpath: &str,
+        backend: Backend,
+        precreated: bool,
+        rootless: bool,
+    ) -> Result<Self> {
         let mut m = HashMap::new();
 
+        if rootless && backend != Backend::V2 {
+            return Err(anyhow!("rootless cgroups need cgroup v2"));
+        }
+
+        // the precreated cgroups are taken as they are
+        let unified_root = Path::new(v2::CGROUP2_ROOT);
+        let delegation_root = if rootless && !precreated {
+            Some(delegation::setup_delegation_root(unified_root)?)
+        } else {
+            None
+        };
+        let cpath = match delegation_root.as_ref() {
+            Some(root) => format!("{}/{}", root, cpath.trim_start_matches('/')),
+            None => cpath.to_string(),
+        };
+        let cpath = cpath.as_str();
+
         let paths = get_paths()?;
-        let mounts = get_mounts()?;
+        let mounts = get_mounts_of(&paths)?;
 
         for key in paths.keys() {
             let mnt = mounts.get(key);
@@ -972,85 +2158,434 @@ impl Manager {
             m.insert(key.to_string(), p);
         }
 
+        let cgroup = if precreated {
+            let exists = match backend {
+                Backend::V2 => Path::new(v2::CGROUP2_ROOT)
+                    .join(cpath.trim_start_matches('/'))
+                    .is_dir(),
+                Backend::V1 => m.values().any(|p| Path::new(p).is_dir()),
+            };
+            if !exists {
+                return Err(anyhow!("precreated cgroup {} doesn't exist", cpath));
+            }
+
+            load_cgroup(backend.hierarchy(), cpath)
+        } else if let Some(root) = delegation_root.as_ref() {
+            delegation::create_cgroup(unified_root, root, cpath)?;
+            load_cgroup(backend.hierarchy(), cpath)
+        } else {
+            new_cgroup(backend.hierarchy(), cpath)
+        };
+
         Ok(Self {
             paths: m,
             mounts,
             // rels: paths,
             cpath: cpath.to_string(),
-            cgroup: new_cgroup(cgroups::hierarchies::auto(), cpath),
+            backend,
+            unified_root: default_unified_root(),
+            cgroup: Some(cgroup),
+            disable_kmem_accounting: false,
+            strict_resources: false,
+            precreated,
+            oom_group: false,
+            io_cost: false,
+            default_swappiness: None,
+            device_map: DeviceMap::new(),
+            rootless,
+            applied: Arc::new(Mutex::new(LinuxResources::default())),
+            unapplied: Arc::new(Mutex::new(vec![])),
+            notifiers: Arc::new(Mutex::new(vec![])),
         })
     }
 
-    pub fn update_cpuset_path(&self, guest_cpuset: &str, container_cpuset: &str) -> Result<()> {
-        if guest_cpuset.is_empty() {
-            return Ok(());
-        }
-        info!(sl!(), "update_cpuset_path to: {}", guest_cpuset);
+    // new_at_root returns the manager of a cgroup created in the cgroup v2
+    // hierarchy at `root`, see ManagerBuilder::unified_root.
+    fn new_at_root(cpath: &str, root: &Path) -> Result<Self> {
+        let dir = root.join(cpath.trim_start_matches('/'));
+        fs::create_dir_all(&dir).with_context(|| format!("failed to create {:?}", dir))?;
 
-        let h = cgroups::hierarchies::auto();
-        let root_cg = h.root_control_group();
+        Ok(Self {
+            paths: HashMap::new(),
+            mounts: HashMap::new(),
+            cpath: cpath.to_string(),
+            backend: Backend::V2,
+            unified_root: root.to_path_buf(),
+            // never written through, only set until destroy
+            cgroup: Some(load_cgroup(Backend::V2.hierarchy(), cpath)),
+            disable_kmem_accounting: false,
+            strict_resources: false,
+            precreated: false,
+            oom_group: false,
+            io_cost: false,
+            default_swappiness: None,
+            device_map: DeviceMap::new(),
+            rootless: false,
+            applied: Arc::new(Mutex::new(LinuxResources::default())),
+            unapplied: Arc::new(Mutex::new(vec![])),
+            notifiers: Arc::new(Mutex::new(vec![])),
+        })
+    }
 
-        let root_cpuset_controller: &CpuSetController = root_cg.controller_of().unwrap();
-        let path = root_cpuset_controller.path();
-        let root_path = Path::new(path);
-        info!(sl!(), "root cpuset path: {:?}", &path);
+    // restore returns the manager of a running container from the state
+    // saved by save(), re-attaching to its existing cgroup.
+    pub fn restore(cid: &str) -> Result<Self> {
+        state::restore(Path::new(state::STATE_DIR), cid)
+    }
 
-        let container_cpuset_controller: &CpuSetController = self.cgroup.controller_of().unwrap();
-        let path = container_cpuset_controller.path();
-        let container_path = Path::new(path);
-        info!(sl!(), "container cpuset path: {:?}", &path);
+    // remove_state removes the state of a container once it is gone.
+    pub fn remove_state(cid: &str) -> Result<()> {
+        state::remove(Path::new(state::STATE_DIR), cid)
+    }
 
-        let mut paths = vec![];
-        for ancestor in container_path.ancestors() {
-            if ancestor == root_path {
-                break;
-            }
-            paths.push(ancestor);
-        }
-        info!(sl!(), "parent paths to update cpuset: {:?}", &paths);
+    // saved_containers returns the containers with a saved state, to be
+    // restored after an agent restart.
+    pub fn saved_containers() -> Result<Vec<String>> {
+        state::list(Path::new(state::STATE_DIR))
+    }
 
-        let mut i = paths.len();
-        loop {
-            if i == 0 {
-                break;
-            }
-            i -= 1;
-
-            // remove cgroup root from path
-            let r_path = &paths[i]
-                .to_str()
-                .unwrap()
-                .trim_start_matches(root_path.to_str().unwrap());
-            info!(sl!(), "updating cpuset for parent path {:?}", &r_path);
-            let cg = new_cgroup(cgroups::hierarchies::auto(), &r_path);
-            let cpuset_controller: &CpuSetController = cg.controller_of().unwrap();
-            cpuset_controller.set_cpus(guest_cpuset)?;
-        }
-
-        if !container_cpuset.is_empty() {
-            info!(
-                sl!(),
-                "updating cpuset for container path: {:?} cpuset: {}",
-                &container_path,
-                container_cpuset
-            );
-            container_cpuset_controller.set_cpus(container_cpuset)?;
-        }
+    // remove_all_states removes the states of all the containers, once the
+    // sandbox is gone.
+    pub fn remove_all_states() -> Result<()> {
+        state::remove_all(Path::new(state::STATE_DIR))
+    }
 
-        Ok(())
+    pub fn notifiers(&self) -> Vec<String> {
+        self.notifiers.lock().unwrap().clone()
     }
 
-    pub fn get_cg_path(&self, cg: &str) -> Option<String> {
-        if cgroups::hierarchies::is_cgroup2_unified_mode() {
-            let cg_path = format!("/sys/fs/cgroup/{}", self.cpath);
-            return Some(cg_path);
-        }
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
 
-        // for cgroup v1
-        self.paths.get(cg).map(|s| s.to_string())
+    // pressure_dir returns the directory of the pressure files of the
+    // cgroup.
+    fn pressure_dir(&self) -> Option<PathBuf> {
+        match self.backend {
+            Backend::V2 => Some(self.unified_path()),
+            Backend::V1 => self.paths.get("cpuacct").map(PathBuf::from),
+        }
+    }
+
+    // custom_root tells if the manager runs against a cgroup v2 hierarchy
+    // other than the one of the guest, see ManagerBuilder::unified_root.
+    fn custom_root(&self) -> bool {
+        self.backend == Backend::V2 && self.unified_root != default_unified_root()
+    }
+
+    // unified_path returns the directory of the container cgroup
+    // in the cgroup v2 unified hierarchy.
+    pub fn unified_path(&self) -> PathBuf {
+        self.unified_root.join(self.cpath.trim_start_matches('/'))
+    }
+
+    fn capabilities(&self) -> validate::Capabilities {
+        if self.backend == Backend::V2 {
+            return validate::probe_v2(&self.unified_path());
+        }
+
+        validate::probe_v1(|c| self.paths.get(c).map(PathBuf::from))
+    }
+
+    // members_dir returns the directory the members of the cgroup are read
+    // from. On cgroup v1, every hierarchy has them all, the freezer one is
+    // preferred as the one the cgroup is frozen with.
+    fn members_dir(&self) -> Result<PathBuf> {
+        self.cgroup()?;
+
+        if self.backend == Backend::V2 {
+            return Ok(self.unified_path());
+        }
+
+        ["freezer", "memory", "pids"]
+            .iter()
+            .find_map(|c| self.paths.get(*c))
+            .or_else(|| {
+                self.paths
+                    .iter()
+                    .find(|(c, _)| c.as_str() != misc::MISC)
+                    .map(|(_, p)| p)
+            })
+            .map(PathBuf::from)
+            .ok_or_else(|| anyhow!("cgroup {} has no hierarchy", self.cpath))
+    }
+
+    // exec_dir returns the directory of the exec child of the cgroup, in the
+    // memory hierarchy on cgroup v1, if it was created.
+    fn exec_dir(&self) -> Option<PathBuf> {
+        let dir = match self.backend {
+            Backend::V2 => self.unified_path(),
+            Backend::V1 => PathBuf::from(self.paths.get("memory")?),
+        }
+        .join(EXEC_CGROUP);
+
+        if dir.is_dir() {
+            Some(dir)
+        } else {
+            None
+        }
+    }
+
+    fn join_exec_cgroup(&self, pid: pid_t) -> Result<()> {
+        if self.backend == Backend::V2 {
+            // no controller is enabled below the container cgroup, which
+            // has processes, see v2::get_exec_stats
+            let dir = self.unified_path().join(EXEC_CGROUP);
+            fs::create_dir_all(&dir).with_context(|| format!("failed to create {:?}", dir))?;
+            return fs::write(dir.join(CGROUP_PROCS), pid.to_string())
+                .with_context(|| format!("failed to add {} to {:?}", pid, dir));
+        }
+
+        // the child would escape the memory limit of the container cgroup
+        if let Some(dir) = self.paths.get("memory") {
+            let path = Path::new(dir).join(MEMORY_USE_HIERARCHY);
+            if fs::read_to_string(&path).map_or(false, |v| v.trim() == "0") {
+                return Err(anyhow!("{:?} is disabled", path));
+            }
+        }
+
+        // cpu and cpuacct are usually mounted together
+        let mut dirs: Vec<&String> = EXEC_CONTROLLERS
+            .iter()
+            .filter_map(|c| self.paths.get(*c))
+            .collect();
+        dirs.sort();
+        dirs.dedup();
+
+        for dir in dirs {
+            let dir = Path::new(dir).join(EXEC_CGROUP);
+            fs::create_dir_all(&dir).with_context(|| format!("failed to create {:?}", dir))?;
+            v2::write_cgroup_file(&dir, CGROUP_PROCS, &pid.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    // write_device_rule applies the change of a rule: it is written to
+    // devices.allow or devices.deny on cgroup v1, while the device filter
+    // is generated again from all the rules on cgroup v2.
+    fn write_device_rule(
+        &self,
+        rule: &LinuxDeviceCgroup,
+        allow: bool,
+        rules: &[LinuxDeviceCgroup],
+    ) -> Result<()> {
+        let line = device_rule_string(rule)?;
+
+        if self.backend == Backend::V2 {
+            return devicefilter::set_device_filter(&self.unified_path(), &v2::device_rules(rules));
+        }
+
+        let dir = self
+            .paths
+            .get("devices")
+            .ok_or_else(|| CgroupError::controller_missing("devices"))?;
+        let file = if allow { DEVICES_ALLOW } else { DEVICES_DENY };
+        v2::write_cgroup_file(Path::new(dir), file, &line)
+    }
+
+    // set_resources writes the resources to the cgroup, failing on the
+    // settings which can't be applied when `strict`.
+    fn set_resources(
+        &self,
+        r: &LinuxResources,
+        update: bool,
+        strict: bool,
+    ) -> Result<ResourceReport> {
+        let mut report = ResourceReport::new(strict);
+        if !r.rdma.is_empty() {
+            report.skip("rdma", validate::NO_RDMA)?;
+        }
+
+        let cg = self.cgroup()?;
+        if self.backend == Backend::V2 {
+            // the controllers the agent wasn't given are skipped
+            let delegated = if self.rootless {
+                Some(delegation::delegated_resources(
+                    &self.unified_path(),
+                    r,
+                    &mut report,
+                )?)
+            } else {
+                None
+            };
+            let r = delegated.as_ref().unwrap_or(r);
+
+            v2::set_resources(&self.unified_path(), r, &mut report)?;
+            if let Some(blkio) = r.block_io.as_ref().filter(|_| self.io_cost) {
+                v2::enable_io_cost(&self.unified_root, blkio, &mut report)?;
+            }
+            if self.oom_group && !update {
+                v2::set_oom_group(&self.unified_path(), &mut report)?;
+            }
+            return Ok(report);
+        }
+
+        if self.oom_group && !update {
+            report.skip("oomGroup", "not supported by cgroup v1")?;
+        }
+
+        let res = &mut cgroups::Resources::default();
+
+        // set cpuset and cpu reources
+        if let Some(cpu) = &r.cpu {
+            let cpu_root = self.mounts.get("cpu").map(Path::new);
+            set_cpu_resources(cg, cpu, cpu_root, &mut report)?;
+        }
+
+        // set memory resources
+        if let Some(memory) = &r.memory {
+            let init_kmem = !update && !self.disable_kmem_accounting;
+            set_memory_resources(cg, memory, init_kmem, &mut report)?;
+        }
+
+        // set pids resources
+        if let Some(pids_resources) = &r.pids {
+            set_pids_resources(cg, pids_resources)?;
+        }
+
+        // set block_io resources
+        if let Some(blkio) = &r.block_io {
+            set_block_io_resources(cg, blkio, res);
+        }
+
+        // set hugepages resources
+        if !r.hugepage_limits.is_empty() {
+            let hugetlb_controller: Option<&HugeTlbController> = cg.controller_of();
+            if hugetlb_controller.is_some() {
+                set_hugepages_resources(cg, &r.hugepage_limits, res);
+            } else {
+                report.skip("hugepageLimits", "hugetlb controller not available")?;
+            }
+        }
+
+        // set network resources
+        if let Some(network) = &r.network {
+            set_network_resources(cg, network, res, &mut report)?;
+        }
+
+        // set misc resources
+        if !r.misc.is_empty() {
+            match self.misc_path() {
+                Some(dir) if self.precreated && !dir.exists() => {
+                    report.skip("misc", "misc cgroup not precreated")?
+                }
+                Some(dir) => {
+                    fs::create_dir_all(&dir)
+                        .with_context(|| format!("failed to create {:?}", dir))?;
+                    misc::set_misc_limits(&dir, &r.misc)?;
+                }
+                None => report.skip("misc", "misc controller not available")?,
+            }
+        }
+
+        // set devices resources, an update without any device rule
+        // keeps the ones set up when the container was created
+        if !update || !r.devices.is_empty() {
+            set_devices_resources(cg, &r.devices, res);
+        }
+        info!(sl!(), "resources after processed {:?}", res);
+
+        // apply resources
+        cg.apply(res)?;
+
+        if !r.hugepage_limits.is_empty() {
+            if let Some(dir) = self.paths.get("hugetlb") {
+                set_hugepages_rsvd_limits(Path::new(dir), &r.hugepage_limits, "limit_in_bytes")?;
+            }
+        }
+
+        if !r.unified.is_empty() {
+            let mut unified = r.unified.clone();
+
+            // the memory protection of the Kubernetes memory QoS falls back
+            // to the soft limit, unless the spec has a reservation already
+            if let Some(soft_limit) = unified_to_soft_limit(&mut unified)? {
+                if r.memory.as_ref().and_then(|m| m.reservation).unwrap_or(0) == 0 {
+                    let mem_controller: &MemController = get_controller!(cg, "memory");
+                    mem_controller.set_soft_limit(soft_limit)?;
+                }
+            }
+
+            if !unified.is_empty() {
+                report.skip("unified", "not supported by cgroup v1")?;
+            }
+        }
+
+        Ok(report)
+    }
+
+    // misc_path returns the directory of the container cgroup in the
+    // cgroup v1 misc hierarchy, if it is mounted.
+    fn misc_path(&self) -> Option<PathBuf> {
+        if self.backend == Backend::V2 {
+            return None;
+        }
+
+        self.paths.get(misc::MISC).map(PathBuf::from)
+    }
+
+    // cgroup returns the handle of the container cgroup, which is gone
+    // once the cgroup is destroyed.
+    fn cgroup(&self) -> Result<&Cgroup> {
+        self.cgroup
+            .as_ref()
+            .ok_or_else(|| anyhow!("cgroup {} has been destroyed", self.cpath))
     }
 }
 
+// update_cpuset writes the guest cpus, and the memory nodes of root, to the
+// cgroups from the child of root down to dir, then the container cpuset to
+// dir.
+fn update_cpuset(
+    root: &Path,
+    dir: &Path,
+    mems_file: &str,
+    guest_cpuset: &str,
+    container_cpuset: &str,
+) -> Result<()> {
+    let rel = dir
+        .strip_prefix(root)
+        .map_err(|_| anyhow!("{:?} is not below {:?}", dir, root))?;
+
+    let mems = fs::read_to_string(root.join(mems_file))
+        .map(|m| m.trim().to_string())
+        .unwrap_or_default();
+
+    let mut path = root.to_path_buf();
+    for c in rel.components() {
+        path.push(c);
+        info!(sl!(), "updating cpuset for path {:?}", &path);
+
+        // the parents are only written to, so there is no need to
+        // load a cgroup handle for each of them
+        let file = path.join(CPUSET_CPUS);
+        fs::write(&file, guest_cpuset)
+            .with_context(|| format!("failed to write {} to {:?}", guest_cpuset, file))?;
+
+        // the memory nodes are only needed by the cgroups without any, so
+        // that the ones set from the spec are kept
+        let file = path.join(CPUSET_MEMS);
+        let unset = fs::read_to_string(&file).map_or(true, |m| m.trim().is_empty());
+        if !mems.is_empty() && unset {
+            fs::write(&file, &mems)
+                .with_context(|| format!("failed to write {} to {:?}", mems, file))?;
+        }
+    }
+
+    if !container_cpuset.is_empty() {
+        info!(
+            sl!(),
+            "updating cpuset for container path: {:?} cpuset: {}", dir, container_cpuset
+        );
+        let file = dir.join(CPUSET_CPUS);
+        fs::write(&file, container_cpuset)
+            .with_context(|| format!("failed to write {} to {:?}", container_cpuset, file))?;
+    }
+
+    Ok(())
+}
+
 // get the guest's online cpus.
 pub fn get_guest_cpuset() -> Result<String> {
     let c = fs::read_to_string(GUEST_CPUS_PATH)?;
@@ -1070,6 +2605,20 @@ pub fn convert_shares_to_v2_value(shares: u64) -> u64 {
     1 + ((shares - 2) * 9999) / 262142
 }
 
+// validate_swappiness returns the swappiness to write, if any. Like runc,
+// -1 is treated as "not set" so that the value inherited from the parent
+// cgroup is kept.
+pub fn validate_swappiness(swappiness: Option<i64>) -> Result<Option<u64>, CgroupError> {
+    match swappiness {
+        None | Some(-1) => Ok(None),
+        Some(v) if (0..=100).contains(&v) => Ok(Some(v as u64)),
+        Some(v) => Err(CgroupError::invalid_value(
+            "memory.swappiness",
+            format!("{}, the valid range is 0-100", v),
+        )),
+    }
+}
+
 // ConvertMemorySwapToCgroupV2Value converts MemorySwap value from OCI spec
 // for use by cgroup v2 drivers. A conversion is needed since Resources.MemorySwap
 // is defined as memory+swap combined, while in cgroup v2 swap is a separate value.
@@ -1086,13 +2635,21 @@ fn convert_memory_swap_to_v2_value(memory_swap: i64, memory: i64) -> Result<i64>
     }
     // sanity checks
     if memory == 0 || memory == -1 {
-        return Err(anyhow!("unable to set swap limit without memory limit"));
+        return Err(CgroupError::invalid_value(
+            "memory.swap",
+            "unable to set swap limit without memory limit".to_string(),
+        )
+        .into());
     }
     if memory < 0 {
-        return Err(anyhow!("invalid memory value: {}", memory));
+        return Err(CgroupError::invalid_value("memory.limit", memory.to_string()).into());
     }
     if memory_swap < memory {
-        return Err(anyhow!("memory+swap limit should be >= memory limit"));
+        return Err(CgroupError::invalid_value(
+            "memory.swap",
+            "memory+swap limit should be >= memory limit".to_string(),
+        )
+        .into());
     }
     Ok(memory_swap - memory)
 }
@@ -1101,6 +2658,663 @@ fn convert_memory_swap_to_v2_value(memory_swap: i64, memory: i64) -> Result<i64>
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_validate_swappiness() {
+        let test_cases = vec![
+            (None, Some(None)),
+            (Some(-1), Some(None)),
+            (Some(0), Some(Some(0))),
+            (Some(60), Some(Some(60))),
+            (Some(100), Some(Some(100))),
+            (Some(101), None),
+            (Some(-2), None),
+        ];
+
+        for (swappiness, expected) in test_cases {
+            assert_eq!(
+                validate_swappiness(swappiness).ok(),
+                expected,
+                "swappiness: {:?}",
+                swappiness
+            );
+        }
+
+        match validate_swappiness(Some(101)) {
+            Err(CgroupError::InvalidValue { field, .. }) => assert_eq!(field, "memory.swappiness"),
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_set_default_swappiness() {
+        let mut r = LinuxResources::default();
+        set_default_swappiness(&mut r, None);
+        assert!(r.memory.is_none());
+
+        set_default_swappiness(&mut r, Some(10));
+        assert_eq!(r.memory.as_ref().unwrap().swappiness, Some(10));
+
+        // an explicit 0 is kept
+        r.memory.as_mut().unwrap().swappiness = Some(0);
+        set_default_swappiness(&mut r, Some(10));
+        assert_eq!(r.memory.as_ref().unwrap().swappiness, Some(0));
+
+        // as sent by the runtime
+        let mut grpc_r = protocols::oci::LinuxResources::default();
+        grpc_r.set_Memory(protocols::oci::LinuxMemory {
+            SwappinessSet: true,
+            ..Default::default()
+        });
+        let mut r = crate::resources_grpc_to_oci(&grpc_r);
+        set_default_swappiness(&mut r, Some(10));
+        assert_eq!(r.memory.as_ref().unwrap().swappiness, Some(0));
+
+        grpc_r.mut_Memory().SwappinessSet = false;
+        let mut r = crate::resources_grpc_to_oci(&grpc_r);
+        set_default_swappiness(&mut r, Some(10));
+        assert_eq!(r.memory.as_ref().unwrap().swappiness, Some(10));
+    }
+
+    #[test]
+    fn test_manager_builder() {
+        let root = tempfile::tempdir().unwrap();
+        let m = ManagerBuilder::new("/kata/test")
+            .backend(Backend::V2)
+            .unified_root(root.path())
+            .strict_resources(true)
+            .build()
+            .unwrap();
+
+        assert_eq!(m.backend(), Backend::V2);
+        assert!(m.strict_resources);
+        assert!(!m.disable_kmem_accounting);
+        assert_eq!(
+            m.get_cg_path("memory"),
+            Some(root.path().join("kata/test").to_string_lossy().to_string())
+        );
+    }
+
+    #[test]
+    fn test_manager_unified_root() {
+        let root = tempfile::tempdir().unwrap();
+        let m = ManagerBuilder::new("/kata/c1")
+            .unified_root(root.path())
+            .build()
+            .unwrap();
+
+        let dir = root.path().join("kata/c1");
+        assert_eq!(m.backend(), Backend::V2);
+        assert_eq!(m.unified_path(), dir);
+        assert_eq!(
+            m.get_cg_path("memory"),
+            Some(dir.to_string_lossy().to_string())
+        );
+
+        // the interface files the kernel would provide
+        let write = |f: &str, c: &str| fs::write(dir.join(f), c).unwrap();
+        let read = |f: &str| fs::read_to_string(dir.join(f)).unwrap();
+        write(v2::CGROUP_CONTROLLERS, "cpu memory pids\n");
+        write(v2::CPU_MAX, "max 100000\n");
+        write(v2::MEMORY_SWAP_MAX, "max\n");
+
+        let r = LinuxResources {
+            memory: Some(LinuxMemory {
+                limit: Some(1 << 30),
+                swap: Some(2 << 30),
+                ..Default::default()
+            }),
+            pids: Some(LinuxPids { limit: 100 }),
+            ..Default::default()
+        };
+        m.set(&r, false).unwrap();
+        assert_eq!(read("memory.max"), (1 << 30).to_string());
+        assert_eq!(read(v2::MEMORY_SWAP_MAX), (1 << 30).to_string());
+        assert_eq!(read("pids.max"), "100");
+
+        m.apply(42).unwrap();
+        assert_eq!(read(CGROUP_PROCS), "42");
+        assert_eq!(m.get_procs().unwrap(), vec![42]);
+
+        m.freeze(FreezerState::Frozen).unwrap();
+        assert_eq!(read("cgroup.freeze"), "1");
+        assert_eq!(m.state().unwrap(), FreezerState::Freezing);
+        write(v2::CGROUP_EVENTS, "populated 1\nfrozen 1\n");
+        assert_eq!(m.state().unwrap(), FreezerState::Frozen);
+
+        // a frozen cgroup is still updated and read, the callers waiting
+        // for it to be thawed if asked to
+        let mut pids = r.clone();
+        pids.pids = Some(LinuxPids { limit: 200 });
+        m.set(&pids, true).unwrap();
+        assert_eq!(read("pids.max"), "200");
+        write("pids.current", "2\n");
+        let stats = m.get_stats().unwrap();
+        assert_eq!(stats.pids_stats.as_ref().unwrap().current, 2);
+        assert_eq!(stats.pids_stats.as_ref().unwrap().limit, 200);
+
+        m.freeze(FreezerState::Thawed).unwrap();
+        assert_eq!(read("cgroup.freeze"), "0");
+        write(v2::CGROUP_EVENTS, "populated 1\nfrozen 0\n");
+        assert_eq!(m.state().unwrap(), FreezerState::Thawed);
+
+        write("memory.current", "4096\n");
+        write("pids.current", "2\n");
+        let stats = m.get_stats().unwrap();
+        let memory = stats.memory_stats.as_ref().unwrap();
+        assert_eq!(memory.usage.as_ref().unwrap().usage, 4096);
+        assert_eq!(memory.usage.as_ref().unwrap().limit, 1 << 30);
+        assert!(memory.swap_supported);
+        assert_eq!(stats.pids_stats.as_ref().unwrap().current, 2);
+        assert_eq!(stats.pids_stats.as_ref().unwrap().limit, 200);
+    }
+
+    #[test]
+    fn test_build_cpuacct_usage() {
+        let usage = build_cpuacct_usage("user 100\nsystem 50\n", 2000, "1000 1000 \n");
+        assert_eq!(usage.total_usage, 2000);
+        assert_eq!(usage.percpu_usage, vec![1000, 1000]);
+        assert_eq!(
+            usage.usage_in_usermode,
+            ((100 * NANO_PER_SECOND) as f64 / *CLOCK_TICKS) as u64
+        );
+        assert_eq!(
+            usage.usage_in_kernelmode,
+            ((50 * NANO_PER_SECOND) as f64 / *CLOCK_TICKS) as u64
+        );
+
+        // a field missing is zero
+        let usage = build_cpuacct_usage("user 100\n", 0, "");
+        assert_eq!(usage.usage_in_kernelmode, 0);
+        assert!(usage.percpu_usage.is_empty());
+    }
+
+    #[test]
+    fn test_read_members() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(read_members(dir.path(), CGROUP_PROCS).is_err());
+
+        fs::write(dir.path().join(CGROUP_PROCS), "10\n20\n").unwrap();
+        fs::write(dir.path().join(TASKS), "10\n11\n20\n").unwrap();
+        assert_eq!(
+            read_members(dir.path(), CGROUP_PROCS).unwrap(),
+            vec![10, 20]
+        );
+        assert_eq!(read_members(dir.path(), TASKS).unwrap(), vec![10, 11, 20]);
+
+        // the exec'd processes are included
+        let exec = dir.path().join(EXEC_CGROUP);
+        fs::create_dir(&exec).unwrap();
+        fs::write(exec.join(CGROUP_PROCS), "30\n").unwrap();
+        assert_eq!(
+            read_members(dir.path(), CGROUP_PROCS).unwrap(),
+            vec![10, 20, 30]
+        );
+    }
+
+    #[test]
+    fn test_translate_block_devices() {
+        let blk = |major, minor| oci::LinuxBlockIoDevice { major, minor };
+        let r = LinuxResources {
+            block_io: Some(LinuxBlockIo {
+                weight_device: vec![oci::LinuxWeightDevice {
+                    blk: blk(8, 0),
+                    weight: Some(500),
+                    leaf_weight: None,
+                }],
+                throttle_read_bps_device: vec![
+                    oci::LinuxThrottleDevice {
+                        blk: blk(8, 0),
+                        rate: 1024,
+                    },
+                    oci::LinuxThrottleDevice {
+                        blk: blk(8, 16),
+                        rate: 2048,
+                    },
+                ],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let mut device_map = DeviceMap::new();
+        device_map.insert((8, 0), (254, 1));
+
+        let t = translate_block_devices(&r, &device_map);
+        let blkio = t.block_io.unwrap();
+        assert_eq!(blkio.weight_device[0].blk, blk(254, 1));
+        assert_eq!(blkio.throttle_read_bps_device[0].blk, blk(254, 1));
+        assert_eq!(blkio.throttle_read_bps_device[0].rate, 1024);
+        assert_eq!(blkio.throttle_read_bps_device[1].blk, blk(8, 16));
+
+        assert_eq!(translate_block_devices(&r, &DeviceMap::new()), r);
+    }
+
+    #[test]
+    fn test_manager_builder_precreated() {
+        let result = ManagerBuilder::new("/kata/not-precreated")
+            .backend(Backend::V2)
+            .precreated(true)
+            .build();
+        assert!(result.is_err());
+
+        // a precreated cgroup is still looked up below the cgroup root
+        let err = ManagerBuilder::new("/system.slice")
+            .cgroup_root("/kata")
+            .backend(Backend::V2)
+            .precreated(true)
+            .build()
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("/kata/system.slice"));
+
+        let err = ManagerBuilder::new("/../system.slice")
+            .cgroup_root("/kata")
+            .precreated(true)
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            CgroupError::find(&err),
+            Some(CgroupError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn test_manager_builder_cgroup_root() {
+        let root = tempfile::tempdir().unwrap();
+        let m = ManagerBuilder::new("/../c1")
+            .cgroup_root("/kata/sb1")
+            .unified_root(root.path());
+        assert!(m.build().is_err());
+
+        let m = ManagerBuilder::new("/c1")
+            .cgroup_root("/kata/sb1")
+            .unified_root(root.path())
+            .build()
+            .unwrap();
+        assert_eq!(m.cpath, "/kata/sb1/c1");
+        assert_eq!(m.unified_path(), root.path().join("kata/sb1/c1"));
+    }
+
+    #[test]
+    fn test_manager_builder_rootless() {
+        let result = ManagerBuilder::new("/kata/test")
+            .backend(Backend::V1)
+            .rootless(true)
+            .build();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_existing_interface_priorities() {
+        let priorities = vec![
+            LinuxInterfacePriority {
+                name: "lo".to_string(),
+                priority: 10,
+            },
+            LinuxInterfacePriority {
+                name: "nonexistent0".to_string(),
+                priority: 20,
+            },
+        ];
+
+        let mut report = ResourceReport::new(false);
+        assert_eq!(
+            existing_interface_priorities(&priorities, &mut report).unwrap(),
+            priorities[..1].to_vec()
+        );
+        assert_eq!(
+            report.unapplied,
+            vec!["network.priorities.nonexistent0: no such interface in the guest"]
+        );
+
+        let mut report = ResourceReport::new(true);
+        assert!(existing_interface_priorities(&priorities, &mut report).is_err());
+    }
+
+    #[test]
+    fn test_read_freezer_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let freezer = dir.path().join("freezer");
+
+        assert_eq!(read_freezer_state(None).unwrap(), FreezerState::Thawed);
+        assert_eq!(
+            read_freezer_state(Some(&freezer)).unwrap(),
+            FreezerState::Thawed
+        );
+
+        fs::create_dir(&freezer).unwrap();
+        fs::write(freezer.join(FREEZER_STATE), "FROZEN\n").unwrap();
+        assert_eq!(
+            read_freezer_state(Some(&freezer)).unwrap(),
+            FreezerState::Frozen
+        );
+    }
+
+    #[test]
+    fn test_parse_freezer_state() {
+        assert_eq!(
+            parse_freezer_state("THAWED\n").unwrap(),
+            FreezerState::Thawed
+        );
+        assert_eq!(
+            parse_freezer_state("FREEZING\n").unwrap(),
+            FreezerState::Freezing
+        );
+        assert_eq!(
+            parse_freezer_state("FROZEN\n").unwrap(),
+            FreezerState::Frozen
+        );
+        assert!(parse_freezer_state("").is_err());
+    }
+
+    #[test]
+    fn test_parse_devices_list() {
+        let rule = |t: &str, major, minor, access: &str| LinuxDeviceCgroup {
+            allow: true,
+            r#type: t.to_string(),
+            major,
+            minor,
+            access: access.to_string(),
+        };
+
+        assert_eq!(
+            parse_devices_list("a *:* rwm\n").unwrap(),
+            vec![rule("a", None, None, "rwm")]
+        );
+        assert_eq!(
+            parse_devices_list("c 1:3 rwm\nc 136:* rw\nb 8:0 r\n").unwrap(),
+            vec![
+                rule("c", Some(1), Some(3), "rwm"),
+                rule("c", Some(136), None, "rw"),
+                rule("b", Some(8), Some(0), "r"),
+            ]
+        );
+        // a cgroup denying everything lists nothing
+        assert!(parse_devices_list("").unwrap().is_empty());
+
+        assert!(parse_devices_list("c 1:3").is_err());
+        assert!(parse_devices_list("c 1 rwm").is_err());
+        assert!(parse_devices_list("c x:3 rwm").is_err());
+    }
+
+    #[test]
+    fn test_device_rule_string() {
+        let rule = |t: &str, major, minor, access: &str| LinuxDeviceCgroup {
+            allow: true,
+            r#type: t.to_string(),
+            major,
+            minor,
+            access: access.to_string(),
+        };
+
+        assert_eq!(
+            device_rule_string(&rule("c", Some(10), Some(200), "rwm")).unwrap(),
+            "c 10:200 rwm"
+        );
+        assert_eq!(
+            device_rule_string(&rule("b", Some(8), None, "r")).unwrap(),
+            "b 8:* r"
+        );
+        assert_eq!(
+            device_rule_string(&rule("", Some(WILDCARD), None, "m")).unwrap(),
+            "a *:* m"
+        );
+
+        assert!(device_rule_string(&rule("p", Some(1), Some(3), "rwm")).is_err());
+        assert!(device_rule_string(&rule("c", Some(1), Some(3), "")).is_err());
+        assert!(device_rule_string(&rule("c", Some(1), Some(3), "rx")).is_err());
+    }
+
+    #[test]
+    fn test_join_exec_cgroup_v1() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |c: &str| dir.path().join(c);
+        let mut paths = HashMap::new();
+        for (c, d) in &[
+            ("cpu", "cpu,cpuacct"),
+            ("cpuacct", "cpu,cpuacct"),
+            ("memory", "memory"),
+            ("cpuset", "cpuset"),
+            ("devices", "devices"),
+        ] {
+            fs::create_dir_all(path(d)).unwrap();
+            paths.insert(c.to_string(), path(d).to_string_lossy().to_string());
+        }
+
+        let m = Manager {
+            paths,
+            mounts: HashMap::new(),
+            cpath: "/kata/c1".to_string(),
+            backend: Backend::V1,
+            unified_root: default_unified_root(),
+            cgroup: None,
+            disable_kmem_accounting: false,
+            strict_resources: false,
+            precreated: false,
+            oom_group: false,
+            io_cost: false,
+            default_swappiness: None,
+            device_map: DeviceMap::new(),
+            rootless: false,
+            applied: Arc::new(Mutex::new(LinuxResources::default())),
+            unapplied: Arc::new(Mutex::new(vec![])),
+            notifiers: Arc::new(Mutex::new(vec![])),
+        };
+        m.join_exec_cgroup(42).unwrap();
+
+        // only the controllers the stats are read from
+        for d in &["cpu,cpuacct", "memory"] {
+            let procs = path(d).join(EXEC_CGROUP).join(CGROUP_PROCS);
+            assert_eq!(fs::read_to_string(procs).unwrap(), "42");
+        }
+        for d in &["cpuset", "devices"] {
+            assert!(!path(d).join(EXEC_CGROUP).exists());
+        }
+        assert_eq!(m.exec_dir(), Some(path("memory").join(EXEC_CGROUP)));
+    }
+
+    #[test]
+    fn test_sampler_v1() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut paths = HashMap::new();
+        for c in &["cpuacct", "hugetlb"] {
+            fs::create_dir_all(dir.path().join(c)).unwrap();
+            paths.insert(
+                c.to_string(),
+                dir.path().join(c).to_string_lossy().to_string(),
+            );
+        }
+
+        let write = |c: &str, f: &str, v: &str| fs::write(dir.path().join(c).join(f), v).unwrap();
+        write(
+            "cpuacct",
+            "cpu.pressure",
+            "some avg10=1.00 avg60=0.00 avg300=0.00 total=10\n",
+        );
+        write("hugetlb", "hugetlb.2MB.usage_in_bytes", "2097152\n");
+        write("hugetlb", "hugetlb.2MB.failcnt", "3\n");
+        write("hugetlb", "hugetlb.2MB.rsvd.usage_in_bytes", "4194304\n");
+
+        let m = Manager {
+            paths,
+            mounts: HashMap::new(),
+            cpath: "/kata/c1".to_string(),
+            backend: Backend::V1,
+            unified_root: default_unified_root(),
+            cgroup: None,
+            disable_kmem_accounting: false,
+            strict_resources: false,
+            precreated: false,
+            oom_group: false,
+            io_cost: false,
+            default_swappiness: None,
+            device_map: DeviceMap::new(),
+            rootless: false,
+            applied: Arc::new(Mutex::new(LinuxResources::default())),
+            unapplied: Arc::new(Mutex::new(vec![])),
+            notifiers: Arc::new(Mutex::new(vec![])),
+        };
+
+        // read once the manager is gone
+        let sampler = m.sampler();
+        drop(m);
+        let sample = sampler();
+
+        assert_eq!(sample.pressure.len(), 1);
+        assert_eq!(sample.pressure[0].0, "cpu");
+        assert_eq!(sample.pressure[0].1.some.total, 10);
+
+        assert_eq!(sample.hugetlb.len(), 1);
+        let stats = &sample.hugetlb["2MB"];
+        assert_eq!(stats.usage, 2 << 20);
+        assert_eq!(stats.failcnt, 3);
+        assert_eq!(stats.rsvd_usage, 4 << 20);
+        assert_eq!(stats.max_usage, 0);
+    }
+
+    #[test]
+    fn test_device_rules_v1() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut paths = HashMap::new();
+        paths.insert(
+            "devices".to_string(),
+            dir.path().to_string_lossy().to_string(),
+        );
+
+        let m = Manager {
+            paths,
+            mounts: HashMap::new(),
+            cpath: "/kata/c1".to_string(),
+            backend: Backend::V1,
+            unified_root: default_unified_root(),
+            cgroup: None,
+            disable_kmem_accounting: false,
+            strict_resources: false,
+            precreated: false,
+            oom_group: false,
+            io_cost: false,
+            default_swappiness: None,
+            device_map: DeviceMap::new(),
+            rootless: false,
+            applied: Arc::new(Mutex::new(LinuxResources::default())),
+            unapplied: Arc::new(Mutex::new(vec![])),
+            notifiers: Arc::new(Mutex::new(vec![])),
+        };
+        let read = |f: &str| fs::read_to_string(dir.path().join(f)).unwrap();
+
+        let rule = LinuxDeviceCgroup {
+            allow: true,
+            r#type: "c".to_string(),
+            major: Some(10),
+            minor: Some(200),
+            access: "rwm".to_string(),
+        };
+        m.add_device_rule(&rule).unwrap();
+        assert_eq!(read(DEVICES_ALLOW), "c 10:200 rwm");
+        assert_eq!(m.applied.lock().unwrap().devices, vec![rule.clone()]);
+
+        m.remove_device_rule(&rule).unwrap();
+        assert!(m.applied.lock().unwrap().devices.is_empty());
+
+        assert!(m.remove_device_rule(&rule).is_err());
+
+        // nothing is recorded when the rule can't be written
+        let invalid = LinuxDeviceCgroup {
+            access: "x".to_string(),
+            ..rule.clone()
+        };
+        assert!(m.add_device_rule(&invalid).is_err());
+        assert!(m.applied.lock().unwrap().devices.is_empty());
+
+        // the rules in effect are read back from the kernel
+        assert!(m.list_device_rules().is_err());
+        fs::write(dir.path().join(DEVICES_LIST), "c 10:200 rwm\n").unwrap();
+        assert_eq!(
+            m.list_device_rules().unwrap(),
+            DeviceRules {
+                rules: vec![rule.clone()],
+                verified: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_replay_device_rules() {
+        let rule = |allow, minor| LinuxDeviceCgroup {
+            allow,
+            r#type: "b".to_string(),
+            major: Some(8),
+            minor: Some(minor),
+            access: "rwm".to_string(),
+        };
+
+        // a device denied by the spec, then denied again and allowed by
+        // rules added after
+        let deny_all = LinuxDeviceCgroup {
+            allow: false,
+            access: "rwm".to_string(),
+            ..Default::default()
+        };
+        let rules = vec![deny_all, rule(true, 16), rule(false, 0)];
+
+        // removing the deny rule keeps the device denied
+        let mut lines = vec![];
+        replay_device_rules(&rules[..2], |file, line| {
+            lines.push(format!("{} {}", file, line));
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(lines[0], "devices.deny a");
+        assert_eq!(lines[1], "devices.deny a *:* rwm");
+        assert_eq!(lines[2], "devices.allow b 8:16 rwm");
+        assert!(!lines.iter().any(|l| l.contains("8:0 ")));
+
+        assert!(replay_device_rules(&rules, |_, _| Err(anyhow!("failed"))).is_err());
+    }
+
+    #[test]
+    fn test_build_default_allowed_devices() {
+        let x86_64_devices = "Character devices:\n  1 mem\n  4 /dev/vc/0\n  4 tty\n  5 /dev/tty\n  5 /dev/console\n  5 /dev/ptmx\n 10 misc\n136 pts\n\nBlock devices:\n  8 sd\n";
+        // arm64 guests use the PL011 and virtio consoles
+        let arm64_devices = "Character devices:\n  1 mem\n  5 /dev/tty\n  5 /dev/console\n  5 /dev/ptmx\n 10 misc\n136 pts\n204 ttyAMA\n229 hvc\n\nBlock devices:\n254 virtblk\n";
+        // s390x guests have no virtual terminals, and the sclp console
+        let s390x_devices = "Character devices:\n  1 mem\n  5 /dev/tty\n  5 /dev/console\n  5 /dev/ptmx\n 10 misc\n136 pts\n227 ttysclp\n\nBlock devices:\n 94 dasd\n";
+        // dynamically assigned majors and minors
+        let dynamic_devices = "Character devices:\n  5 /dev/console\n  5 /dev/ptmx\n 10 misc\n237 pts\n\nBlock devices:\n237 pts\n";
+        let misc = "200 tun\n 59 vsock\n";
+
+        let test_cases = vec![
+            (x86_64_devices, misc, (5, 136, 10, 200)),
+            (arm64_devices, misc, (5, 136, 10, 200)),
+            (s390x_devices, misc, (5, 136, 10, 200)),
+            (dynamic_devices, "58 tun\n", (5, 237, 10, 58)),
+            ("", "", (5, 136, 10, 200)),
+        ];
+
+        for (devices, misc, (tty, pts, misc_major, tun)) in test_cases {
+            let rules = build_default_allowed_devices(devices, misc);
+            let numbers: Vec<(i64, i64)> = rules
+                .iter()
+                .map(|r| (r.major.unwrap(), r.minor.unwrap()))
+                .collect();
+
+            assert_eq!(
+                numbers,
+                vec![
+                    (WILDCARD, WILDCARD),
+                    (WILDCARD, WILDCARD),
+                    (tty, 1),
+                    (pts, WILDCARD),
+                    (tty, 2),
+                    (misc_major, tun),
+                ],
+                "devices: {:?}, misc: {:?}",
+                devices,
+                misc
+            );
+        }
+    }
+
     #[test]
     fn test_line_to_vec() {
         let test_cases = vec![
@@ -1119,6 +3333,99 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_get_memory_usage() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |f: &str, c: &str| fs::write(dir.path().join(f), c).unwrap();
+
+        write(MEMORY_USAGE_IN_BYTES, "4096\n");
+        assert!(get_memory_usage(dir.path()).is_err());
+
+        write(MEMORY_LIMIT_IN_BYTES, "8192\n");
+        assert_eq!(
+            get_memory_usage(dir.path()).unwrap(),
+            MemoryUsage {
+                usage: 4096,
+                limit: 8192,
+                swap_usage: 0,
+            }
+        );
+
+        write(MEMORY_MEMSW_USAGE_IN_BYTES, "6144\n");
+        assert_eq!(get_memory_usage(dir.path()).unwrap().swap_usage, 2048);
+    }
+
+    #[test]
+    fn test_get_memory_events() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(get_memory_events(dir.path(), 0), MemoryEvents::default());
+
+        fs::write(
+            dir.path().join(MEMORY_OOM_CONTROL),
+            "oom_kill_disable 0\nunder_oom 1\noom_kill 3\n",
+        )
+        .unwrap();
+        let events = get_memory_events(dir.path(), 5);
+        assert_eq!((events.oom, events.oom_kill, events.max), (3, 3, 5));
+        assert!(events.under_oom);
+        assert_eq!(events.high, 0);
+    }
+
+    #[test]
+    fn test_v1_controllers() {
+        let mounts: HashMap<String, String> = [
+            ("memory", "/sys/fs/cgroup/memory"),
+            ("cpu", "/sys/fs/cgroup/cpu,cpuacct"),
+            ("cpuacct", "/sys/fs/cgroup/cpu,cpuacct"),
+            ("systemd", "/sys/fs/cgroup/systemd"),
+            ("", "/sys/fs/cgroup/unified"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+        assert_eq!(v1_controllers(&mounts), vec!["cpu", "cpuacct", "memory"]);
+    }
+
+    #[test]
+    fn test_update_cpuset() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("kata/c1");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(root.path().join(CPUSET_MEMS), "0-1\n").unwrap();
+        let parent = root.path().join("kata");
+        fs::write(parent.join(CPUSET_MEMS), "\n").unwrap();
+        // set from the spec
+        fs::write(dir.join(CPUSET_MEMS), "1\n").unwrap();
+
+        update_cpuset(root.path(), &dir, CPUSET_MEMS, "0-3", "1").unwrap();
+
+        let read = |p: &Path, f: &str| fs::read_to_string(p.join(f)).unwrap();
+        assert_eq!(read(&parent, CPUSET_CPUS), "0-3");
+        assert_eq!(read(&parent, CPUSET_MEMS), "0-1");
+        assert_eq!(read(&dir, CPUSET_CPUS), "1");
+        assert_eq!(read(&dir, CPUSET_MEMS), "1\n");
+        // the root is left alone
+        assert!(!root.path().join(CPUSET_CPUS).exists());
+
+        // no memory nodes to propagate
+        fs::remove_file(parent.join(CPUSET_MEMS)).unwrap();
+        update_cpuset(root.path(), &dir, CPUSET_MEMS_EFFECTIVE, "0-3", "").unwrap();
+        assert!(!parent.join(CPUSET_MEMS).exists());
+        assert_eq!(read(&dir, CPUSET_CPUS), "0-3");
+
+        assert!(update_cpuset(&dir, root.path(), CPUSET_MEMS, "0-3", "").is_err());
+    }
+
+    #[test]
+    fn test_memory_v1_limit() {
+        assert_eq!(memory_v1_limit(1 << 20), 1 << 20);
+        assert_eq!(memory_v1_limit(0), 0);
+        assert_eq!(memory_v1_limit(9223372036854771712), u64::MAX);
+        assert_eq!(memory_v1_limit(i64::MAX), u64::MAX);
+        assert_eq!(memory_v1_limit(-1), u64::MAX);
+    }
+
     #[test]
     fn test_lines_to_map() {
         let hm1: HashMap<String, u64> = [
@@ -1147,4 +3454,23 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_unified_to_soft_limit() {
+        let mut unified = HashMap::new();
+        assert_eq!(unified_to_soft_limit(&mut unified).unwrap(), None);
+
+        unified.insert(MEMORY_MIN.to_string(), "1048576".to_string());
+        unified.insert("memory.high".to_string(), "4194304".to_string());
+        assert_eq!(unified_to_soft_limit(&mut unified).unwrap(), Some(1048576));
+        // memory.high has no cgroup v1 equivalent
+        assert_eq!(unified.len(), 1);
+
+        unified.insert(MEMORY_MIN.to_string(), "1048576".to_string());
+        unified.insert(MEMORY_LOW.to_string(), "max".to_string());
+        assert_eq!(unified_to_soft_limit(&mut unified).unwrap(), Some(-1));
+
+        unified.insert(MEMORY_LOW.to_string(), "1M".to_string());
+        assert!(unified_to_soft_limit(&mut unified).is_err());
+    }
 }
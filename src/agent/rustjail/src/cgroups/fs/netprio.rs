@@ -0,0 +1,90 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Network priorities for the cgroup v2 unified hierarchy.
+//
+// net_prio doesn't exist on cgroup v2, the priorities are set instead by a
+// BPF_PROG_TYPE_CGROUP_SKB program attached on egress, which sets the
+// priority of the packets sent through each of the interfaces. There is no
+// such alternative for the net_cls class id, as only tc programs can set it.
+
+use anyhow::{Context, Result};
+use oci::LinuxInterfacePriority;
+use std::path::Path;
+
+use super::bpf::{self, BpfInsn, BPF_JMP_JNE_K, BPF_LDX_MEM_W, BPF_STX_MEM_W, R0, R1, R2, R3};
+
+// struct __sk_buff field offsets
+const SKB_PRIORITY: i16 = 32;
+const SKB_IFINDEX: i16 = 40;
+
+const BPF_PROG_TYPE_CGROUP_SKB: u32 = 8;
+const BPF_CGROUP_INET_EGRESS: u32 = 1;
+
+// generate_program builds the program setting the priority of the packets
+// sent through each interface index of `priorities`.
+pub fn generate_program(priorities: &[(u32, u32)]) -> Vec<BpfInsn> {
+    let mut insns = vec![BpfInsn::new(BPF_LDX_MEM_W, R2, R1, SKB_IFINDEX, 0)];
+
+    for (ifindex, priority) in priorities {
+        // skip the store unless the packet goes through this interface
+        insns.push(BpfInsn::new(BPF_JMP_JNE_K, R2, 0, 2, *ifindex as i32));
+        insns.push(bpf::mov_imm(R3, *priority as i32));
+        insns.push(BpfInsn::new(BPF_STX_MEM_W, R1, R3, SKB_PRIORITY, 0));
+    }
+
+    // never drop the packet
+    insns.push(bpf::mov_imm(R0, 1));
+    insns.push(bpf::exit());
+
+    insns
+}
+
+// set_network_priorities attaches the program setting `priorities` to the
+// cgroup at `dir`. The interfaces are looked up in the network namespace
+// of the agent, which the containers of the sandbox share.
+pub fn set_network_priorities(dir: &Path, priorities: &[LinuxInterfacePriority]) -> Result<()> {
+    let mut indexes = vec![];
+
+    for p in priorities {
+        let ifindex = nix::net::if_::if_nametoindex(p.name.as_str())
+            .with_context(|| format!("failed to find interface {}", p.name))?;
+        indexes.push((ifindex, p.priority));
+    }
+
+    let insns = generate_program(&indexes);
+
+    bpf::replace_programs(
+        dir,
+        BPF_PROG_TYPE_CGROUP_SKB,
+        BPF_CGROUP_INET_EGRESS,
+        &insns,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_program() {
+        let insns = generate_program(&[]);
+        assert_eq!(insns.len(), 3);
+
+        let insns = generate_program(&[(2, 1000), (3, 10)]);
+        assert_eq!(insns.len(), 1 + 2 * 3 + 2);
+
+        assert_eq!(insns[1], BpfInsn::new(BPF_JMP_JNE_K, R2, 0, 2, 2));
+        assert_eq!(insns[2], bpf::mov_imm(R3, 1000));
+        assert_eq!(
+            insns[3],
+            BpfInsn::new(BPF_STX_MEM_W, R1, R3, SKB_PRIORITY, 0)
+        );
+        assert_eq!(insns[4], BpfInsn::new(BPF_JMP_JNE_K, R2, 0, 2, 3));
+        assert_eq!(insns[5], bpf::mov_imm(R3, 10));
+        assert_eq!(insns[7], bpf::mov_imm(R0, 1));
+        assert_eq!(insns[8], bpf::exit());
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Pressure stall information of the cgroups: the share of time their tasks
+// were stalled waiting for the cpu, memory or io, from the
+// <resource>.pressure files. Those are in the unified cgroup on cgroup v2,
+// and in the cpuacct hierarchy on cgroup v1, with the kernels supporting
+// it there.
+
+use crate::cgroups::CgroupError;
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::Path;
+
+pub const PSI_RESOURCES: &[&str] = &["cpu", "memory", "io"];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PressureData {
+    // percentages over the last 10, 60 and 300 seconds
+    pub avg10: f64,
+    pub avg60: f64,
+    pub avg300: f64,
+    // total stall time in microseconds
+    pub total: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Pressure {
+    // some tasks stalled
+    pub some: PressureData,
+    // all the tasks stalled at once, None for the cpu before Linux 5.13
+    pub full: Option<PressureData>,
+}
+
+fn parse_pressure_data(fields: &[&str]) -> Result<PressureData> {
+    let mut data = PressureData::default();
+
+    for field in fields {
+        let (key, value) = match field.find('=') {
+            Some(i) => (&field[..i], &field[i + 1..]),
+            None => return Err(anyhow!("invalid pressure field {:?}", field)),
+        };
+
+        let invalid = || format!("invalid pressure field {:?}", field);
+        match key {
+            "avg10" => data.avg10 = value.parse().with_context(invalid)?,
+            "avg60" => data.avg60 = value.parse().with_context(invalid)?,
+            "avg300" => data.avg300 = value.parse().with_context(invalid)?,
+            "total" => data.total = value.parse().with_context(invalid)?,
+            // the fields of later kernels
+            _ => {}
+        }
+    }
+
+    Ok(data)
+}
+
+// parse_pressure parses the content of a pressure file, e.g.
+// "some avg10=0.00 avg60=0.00 avg300=0.00 total=0".
+pub fn parse_pressure(content: &str) -> Result<Pressure> {
+    let mut some = None;
+    let mut full = None;
+
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.split_first() {
+            Some((&"some", data)) => some = Some(parse_pressure_data(data)?),
+            Some((&"full", data)) => full = Some(parse_pressure_data(data)?),
+            _ => {}
+        }
+    }
+
+    Ok(Pressure {
+        some: some.ok_or_else(|| anyhow!("no \"some\" pressure"))?,
+        full,
+    })
+}
+
+// read_pressure returns the pressure of a resource of the cgroup at dir.
+pub fn read_pressure(dir: &Path, resource: &str) -> Result<Pressure> {
+    let path = dir.join(format!("{}.pressure", resource));
+    let content = fs::read_to_string(&path)
+        .map_err(|e| CgroupError::io(&path, e))
+        .with_context(|| format!("failed to read {:?}", path))?;
+
+    parse_pressure(&content).with_context(|| format!("failed to parse {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pressure() {
+        let p = parse_pressure(
+            "some avg10=1.50 avg60=0.25 avg300=0.00 total=123456\n\
+             full avg10=0.50 avg60=0.00 avg300=0.00 total=789\n",
+        )
+        .unwrap();
+        assert_eq!(
+            p.some,
+            PressureData {
+                avg10: 1.5,
+                avg60: 0.25,
+                avg300: 0.0,
+                total: 123456,
+            }
+        );
+        assert_eq!(p.full.unwrap().total, 789);
+
+        let p = parse_pressure("some avg10=0.00 avg60=0.00 avg300=0.00 total=5\n").unwrap();
+        assert_eq!(p.some.total, 5);
+        assert!(p.full.is_none());
+
+        for content in &[
+            "",
+            "full avg10=0.00 avg60=0.00 avg300=0.00 total=0",
+            "some avg10=x avg60=0.00 avg300=0.00 total=0",
+            "some avg10",
+        ] {
+            assert!(parse_pressure(content).is_err(), "{:?}", content);
+        }
+    }
+
+    #[test]
+    fn test_read_pressure() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("memory.pressure"),
+            "some avg10=2.00 avg60=1.00 avg300=0.50 total=42\n",
+        )
+        .unwrap();
+
+        assert_eq!(read_pressure(dir.path(), "memory").unwrap().some.total, 42);
+        assert!(read_pressure(dir.path(), "io").is_err());
+    }
+}
@@ -0,0 +1,161 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Removal of the container cgroups. A cgroup can't be removed while it has
+// processes or children, which the workload may leave behind, e.g. the
+// daemons it started or the cgroups it created beneath its own. The
+// remaining processes are killed, the children are removed bottom-up, and
+// the removals failing with EBUSY, while the killed processes exit or the
+// zombies are reaped, are retried with a backoff. The processes still
+// there on the last retry are moved to the parent of the container cgroup.
+// The removal blocks meanwhile, so the agent runs it out of the sandbox
+// lock.
+
+use anyhow::{anyhow, Context, Result};
+use nix::errno::Errno;
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+const CGROUP_PROCS: &str = "cgroup.procs";
+
+const RETRIES: u32 = 5;
+const RETRY_DELAY: Duration = Duration::from_millis(10);
+
+fn read_procs(dir: &Path) -> Vec<libc::pid_t> {
+    fs::read_to_string(dir.join(CGROUP_PROCS))
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|l| l.trim().parse().ok())
+        .collect()
+}
+
+fn kill_procs(dir: &Path) {
+    for pid in read_procs(dir) {
+        match signal::kill(Pid::from_raw(pid), Some(Signal::SIGKILL)) {
+            Ok(_) | Err(nix::Error::Sys(Errno::ESRCH)) => {}
+            Err(e) => {
+                warn!(sl!(), "failed to kill a cgroup member"; "pid" => pid, "error" => format!("{:?}", e))
+            }
+        }
+    }
+}
+
+// evict_procs moves the processes of the cgroup at `dir` to the one at
+// `to`, which fails for the zombies and on cgroup v2 when `to` has
+// controllers enabled for its children.
+fn evict_procs(dir: &Path, to: &Path) {
+    for pid in read_procs(dir) {
+        if let Err(e) = fs::write(to.join(CGROUP_PROCS), pid.to_string()) {
+            warn!(sl!(), "failed to move a cgroup member out"; "pid" => pid, "error" => format!("{:?}", e));
+        }
+    }
+}
+
+// subtree returns the cgroup at `dir` and its descendants, the children
+// coming before their parent.
+fn subtree(dir: &Path) -> Vec<PathBuf> {
+    let mut cgroups = vec![];
+
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            if entry.file_type().map_or(false, |t| t.is_dir()) {
+                cgroups.extend(subtree(&entry.path()));
+            }
+        }
+    }
+
+    cgroups.push(dir.to_path_buf());
+
+    cgroups
+}
+
+fn remove_one(dir: &Path, evict_to: Option<&Path>) -> Result<()> {
+    kill_procs(dir);
+
+    let mut delay = RETRY_DELAY;
+    let mut attempt = 0;
+
+    loop {
+        let e = match fs::remove_dir(dir) {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => e,
+        };
+
+        if e.raw_os_error() != Some(libc::EBUSY) || attempt == RETRIES {
+            return Err(e.into());
+        }
+
+        if attempt == RETRIES - 1 {
+            if let Some(to) = evict_to {
+                evict_procs(dir, to);
+            }
+        }
+
+        thread::sleep(delay);
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+// remove_cgroup removes the cgroup at `dir` along with its descendants,
+// the error listing the cgroups which couldn't be.
+pub fn remove_cgroup(dir: &Path) -> Result<()> {
+    let evict_to = dir.parent();
+    let mut failures = vec![];
+
+    for cgroup in subtree(dir) {
+        if let Err(e) = remove_one(&cgroup, evict_to) {
+            failures.push(format!("{:?}: {}", cgroup, e));
+        }
+    }
+
+    if failures.is_empty() {
+        return Ok(());
+    }
+
+    Err(anyhow!("{}", failures.join(", ")))
+        .with_context(|| format!("failed to remove the cgroup {:?}", dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remove_cgroup() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("kata");
+        fs::create_dir_all(dir.join("exec")).unwrap();
+        fs::create_dir_all(dir.join("workload/a")).unwrap();
+
+        let tree = subtree(&dir);
+        assert_eq!(tree.len(), 4);
+        assert_eq!(tree.last().unwrap(), &dir);
+        let pos = |p: &str| tree.iter().position(|d| d == &dir.join(p)).unwrap();
+        assert!(pos("workload/a") < pos("workload"));
+
+        remove_cgroup(&dir).unwrap();
+        assert!(!dir.exists());
+
+        // already removed
+        remove_cgroup(&dir).unwrap();
+
+        // what couldn't be removed is listed, the rest is
+        fs::create_dir_all(dir.join("busy")).unwrap();
+        fs::create_dir_all(dir.join("empty")).unwrap();
+        fs::write(dir.join("busy").join(CGROUP_PROCS), "").unwrap();
+
+        let err = format!("{:?}", remove_cgroup(&dir).unwrap_err());
+        assert!(err.contains("busy"), "{}", err);
+        assert!(!dir.join("empty").exists());
+        assert!(dir.join("busy").exists());
+    }
+}
@@ -0,0 +1,216 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Real-time bandwidth of the cgroup v1 cpu controller. With RT group
+// scheduling, the RT runtime of a cgroup over its period has to fit in the
+// one of its parent, along with the ones of its siblings, and the cgroups
+// are created with none. Setting the RT runtime of a container hence fails
+// with EINVAL unless its ancestors were given enough beforehand, which is
+// done here from the top down, out of the bandwidth of the root cgroup,
+// i.e. the kernel.sched_rt_runtime_us of the guest.
+
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::{CPU_RT_PERIOD_US, CPU_RT_RUNTIME_US};
+use crate::cgroups::CgroupError;
+
+// the fixed point the kernel compares the bandwidths in, BW_SHIFT
+const BW_SHIFT: u32 = 20;
+const BW_UNIT: u64 = 1 << BW_SHIFT;
+
+// to_ratio returns the bandwidth of `runtime` over `period` as the kernel
+// computes it, a negative runtime being unlimited.
+fn to_ratio(period: u64, runtime: i64) -> u64 {
+    if runtime < 0 {
+        return BW_UNIT;
+    }
+    if period == 0 {
+        return 0;
+    }
+
+    ((runtime as u64) << BW_SHIFT) / period
+}
+
+// runtime_of returns the smallest runtime over `period` with a bandwidth of
+// at least `ratio`.
+fn runtime_of(period: u64, ratio: u64) -> u64 {
+    (ratio.saturating_mul(period) + BW_UNIT - 1) >> BW_SHIFT
+}
+
+fn read_value<T: std::str::FromStr>(dir: &Path, file: &str) -> Result<T> {
+    let path = dir.join(file);
+    let content = fs::read_to_string(&path).map_err(|e| CgroupError::io(&path, e))?;
+
+    content
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("invalid {:?}: {:?}", path, content.trim()))
+}
+
+// read_bandwidth returns the (period, runtime) of the cgroup at `dir`.
+fn read_bandwidth(dir: &Path) -> Result<(u64, i64)> {
+    Ok((
+        read_value(dir, CPU_RT_PERIOD_US)?,
+        read_value(dir, CPU_RT_RUNTIME_US)?,
+    ))
+}
+
+// children_ratio returns the bandwidth given to the children of the cgroup
+// at `dir`, but the one at `skip`.
+fn children_ratio(dir: &Path, skip: &Path) -> Result<u64> {
+    let mut ratio = 0u64;
+
+    for entry in fs::read_dir(dir).map_err(|e| CgroupError::io(dir, e))? {
+        let entry = entry.map_err(|e| CgroupError::io(dir, e))?;
+        let child = entry.path();
+        if child == skip || !entry.file_type().map_or(false, |t| t.is_dir()) {
+            continue;
+        }
+
+        let (period, runtime) = read_bandwidth(&child)?;
+        ratio = ratio.saturating_add(to_ratio(period, runtime));
+    }
+
+    Ok(ratio)
+}
+
+// propagate_rt_bandwidth gives the ancestors of the cgroup at `dir`, below
+// the root of the hierarchy at `root`, the RT bandwidth the cgroup needs
+// for `runtime` over `period`, the current values of the cgroup standing
+// for the ones not set. The ancestors having enough already are left
+// alone, and nothing is written if the root can't provide it.
+pub fn propagate_rt_bandwidth(
+    root: &Path,
+    dir: &Path,
+    runtime: Option<i64>,
+    period: Option<u64>,
+) -> Result<()> {
+    let rel = dir
+        .strip_prefix(root)
+        .map_err(|_| anyhow!("{:?} is not below {:?}", dir, root))?;
+
+    let (cur_period, cur_runtime) = read_bandwidth(dir)?;
+    let period = period.filter(|p| *p != 0).unwrap_or(cur_period);
+    let runtime = runtime.filter(|r| *r != 0).unwrap_or(cur_runtime);
+    if runtime == 0 {
+        return Ok(());
+    }
+
+    // the cgroups from the root down to the one at `dir`
+    let mut path = root.to_path_buf();
+    let mut chain = vec![path.clone()];
+    for c in rel.components() {
+        path.push(c);
+        chain.push(path.clone());
+    }
+
+    // the bandwidth each ancestor needs, bottom-up, and the runtimes of the
+    // ones which have to be raised
+    let mut need = to_ratio(period, runtime);
+    let mut raised: Vec<(PathBuf, u64)> = vec![];
+    for pair in chain.windows(2).rev() {
+        let (parent, child) = (&pair[0], &pair[1]);
+        let (parent_period, parent_runtime) = read_bandwidth(parent)?;
+
+        need = need.saturating_add(children_ratio(parent, child)?);
+        let has = to_ratio(parent_period, parent_runtime);
+        if need <= has {
+            break;
+        }
+
+        if parent == root {
+            return Err(CgroupError::invalid_value(
+                "cpu.realtimeRuntime",
+                format!(
+                    "{} over {} exceeds the RT bandwidth left in {:?}",
+                    runtime, period, root
+                ),
+            )
+            .into());
+        }
+
+        raised.push((parent.clone(), runtime_of(parent_period, need)));
+    }
+
+    // a cgroup can only be given what its parent has already
+    for (dir, runtime) in raised.iter().rev() {
+        info!(sl!(), "raising the RT runtime of {:?} to {}", dir, runtime);
+
+        let file = dir.join(CPU_RT_RUNTIME_US);
+        fs::write(&file, runtime.to_string())
+            .with_context(|| format!("failed to write {} to {:?}", runtime, file))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_bandwidth(dir: &Path, period: u64, runtime: i64) {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join(CPU_RT_PERIOD_US), period.to_string()).unwrap();
+        fs::write(dir.join(CPU_RT_RUNTIME_US), runtime.to_string()).unwrap();
+    }
+
+    fn read_runtime(dir: &Path) -> i64 {
+        read_value(dir, CPU_RT_RUNTIME_US).unwrap()
+    }
+
+    #[test]
+    fn test_ratio() {
+        assert_eq!(to_ratio(1000000, 500000), BW_UNIT / 2);
+        assert_eq!(to_ratio(1000000, -1), BW_UNIT);
+        assert_eq!(to_ratio(0, 10), 0);
+
+        // the runtime is rounded up to reach the ratio
+        for (period, runtime) in &[(1000000, 950000), (100000, 333), (7, 3)] {
+            let ratio = to_ratio(*period, *runtime);
+            assert!(to_ratio(*period, runtime_of(*period, ratio) as i64) >= ratio);
+        }
+    }
+
+    #[test]
+    fn test_propagate_rt_bandwidth() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let kata = root.join("kata");
+        let sb = kata.join("sb1");
+        let c1 = sb.join("c1");
+
+        write_bandwidth(root, 1000000, 950000);
+        write_bandwidth(&kata, 1000000, 0);
+        write_bandwidth(&sb, 1000000, 0);
+        write_bandwidth(&c1, 1000000, 0);
+        write_bandwidth(&sb.join("c0"), 100000, 10000);
+
+        // the sandbox also keeps the bandwidth of c0
+        propagate_rt_bandwidth(root, &c1, Some(200000), None).unwrap();
+        assert_eq!(read_runtime(&sb), 300000);
+        assert_eq!(read_runtime(&kata), 300000);
+        assert_eq!(read_runtime(root), 950000);
+        assert_eq!(read_runtime(&c1), 0);
+
+        // enough already
+        propagate_rt_bandwidth(root, &c1, Some(50000), Some(500000)).unwrap();
+        assert_eq!(read_runtime(&sb), 300000);
+
+        // more than the root has
+        let err = propagate_rt_bandwidth(root, &c1, Some(950000), None).unwrap_err();
+        assert!(matches!(
+            CgroupError::find(&err),
+            Some(CgroupError::InvalidValue { .. })
+        ));
+        assert_eq!(read_runtime(&kata), 300000);
+
+        // nothing asked for
+        propagate_rt_bandwidth(root, &c1, None, None).unwrap();
+
+        assert!(propagate_rt_bandwidth(&c1, &kata, Some(1000), None).is_err());
+    }
+}
@@ -0,0 +1,241 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Persisted state of the cgroup managers, one file per container, so that
+// an agent restarted or live-updated can re-attach to the cgroups of the
+// running containers rather than losing track of them.
+//
+// Besides the Manager itself, the state holds what it doesn't serialize:
+// the resources applied so far, which the updates are merged into, the
+// block device map, and the cgroup events watched, to be watched again.
+// The files are written atomically, so a state is either the previous or
+// the new one if the agent dies meanwhile.
+
+use anyhow::{anyhow, Context, Result};
+use oci::LinuxResources;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use super::{load_cgroup, Backend, Manager};
+use crate::cgroups::DeviceMap;
+
+pub const STATE_DIR: &str = "/run/kata-containers/cgroups";
+
+#[derive(Serialize, Deserialize, Debug)]
+struct State {
+    manager: Manager,
+    #[serde(default)]
+    applied: LinuxResources,
+    #[serde(default)]
+    unapplied: Vec<String>,
+    // as a list, JSON keys being strings only
+    #[serde(default)]
+    device_map: Vec<((i64, i64), (i64, i64))>,
+    #[serde(default)]
+    notifiers: Vec<String>,
+}
+
+fn state_path(dir: &Path, cid: &str) -> Result<PathBuf> {
+    if cid.is_empty() || cid.contains('/') || cid.starts_with('.') {
+        return Err(anyhow!("invalid container id {:?}", cid));
+    }
+
+    Ok(dir.join(format!("{}.json", cid)))
+}
+
+// save writes the state of the manager of a container in dir.
+pub fn save(dir: &Path, cid: &str, m: &Manager) -> Result<()> {
+    let path = state_path(dir, cid)?;
+
+    let state = State {
+        manager: m.clone(),
+        applied: m.applied.lock().unwrap().clone(),
+        unapplied: m.unapplied.lock().unwrap().clone(),
+        device_map: m.device_map.iter().map(|(k, v)| (*k, *v)).collect(),
+        notifiers: m.notifiers.lock().unwrap().clone(),
+    };
+    let data = serde_json::to_vec(&state)?;
+
+    fs::create_dir_all(dir).with_context(|| format!("failed to create {:?}", dir))?;
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, data).with_context(|| format!("failed to write {:?}", tmp))?;
+    fs::rename(&tmp, &path).with_context(|| format!("failed to rename {:?}", tmp))
+}
+
+// restore returns the manager of a container from its state in dir, which
+// fails if its cgroup is gone.
+pub fn restore(dir: &Path, cid: &str) -> Result<Manager> {
+    let path = state_path(dir, cid)?;
+    let data = fs::read(&path).with_context(|| format!("failed to read {:?}", path))?;
+    let state: State =
+        serde_json::from_slice(&data).with_context(|| format!("invalid state {:?}", path))?;
+
+    let mut m = state.manager;
+    let exists = match m.backend {
+        Backend::V2 => m.unified_path().is_dir(),
+        Backend::V1 => m.paths.values().any(|p| Path::new(p).is_dir()),
+    };
+    if !exists {
+        return Err(anyhow!("cgroup {} of container {} is gone", m.cpath, cid));
+    }
+
+    m.cgroup = Some(load_cgroup(m.backend.hierarchy(), &m.cpath));
+    m.applied = Arc::new(Mutex::new(state.applied));
+    m.unapplied = Arc::new(Mutex::new(state.unapplied));
+    m.device_map = state.device_map.into_iter().collect::<DeviceMap>();
+    m.notifiers = Arc::new(Mutex::new(state.notifiers));
+
+    Ok(m)
+}
+
+// remove removes the state of a container, if any.
+pub fn remove(dir: &Path, cid: &str) -> Result<()> {
+    let path = state_path(dir, cid)?;
+
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(anyhow!(e).context(format!("failed to remove {:?}", path)))
+        }
+        _ => Ok(()),
+    }
+}
+
+// list returns the containers with a state in dir.
+pub fn list(dir: &Path) -> Result<Vec<String>> {
+    let entries = match fs::read_dir(dir) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        r => r.with_context(|| format!("failed to read {:?}", dir))?,
+    };
+
+    let mut cids = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().map_or(false, |e| e == "json") {
+            if let Some(cid) = path.file_stem().and_then(|s| s.to_str()) {
+                cids.push(cid.to_string());
+            }
+        }
+    }
+    cids.sort();
+
+    Ok(cids)
+}
+
+// remove_all removes the states of all the containers, with dir.
+pub fn remove_all(dir: &Path) -> Result<()> {
+    match fs::remove_dir_all(dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(anyhow!(e).context(format!("failed to remove {:?}", dir)))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::default_unified_root;
+    use super::*;
+    use crate::cgroups::FsManagerExt;
+    use oci::LinuxPids;
+    use std::collections::HashMap;
+
+    fn manager(cg_dir: &Path) -> Manager {
+        let mut paths = HashMap::new();
+        paths.insert("pids".to_string(), cg_dir.to_string_lossy().to_string());
+
+        Manager {
+            paths,
+            mounts: HashMap::new(),
+            cpath: "/kata/c1".to_string(),
+            backend: Backend::V1,
+            unified_root: default_unified_root(),
+            cgroup: None,
+            disable_kmem_accounting: false,
+            strict_resources: true,
+            precreated: false,
+            oom_group: false,
+            io_cost: false,
+            default_swappiness: None,
+            device_map: DeviceMap::new(),
+            rootless: false,
+            applied: Arc::new(Mutex::new(LinuxResources::default())),
+            unapplied: Arc::new(Mutex::new(vec![])),
+            notifiers: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    #[test]
+    fn test_save_restore() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_dir = dir.path().join("state");
+        let cg_dir = dir.path().join("pids/kata/c1");
+        fs::create_dir_all(&cg_dir).unwrap();
+
+        let mut m = manager(&cg_dir);
+        m.device_map.insert((8, 0), (254, 0));
+        m.applied.lock().unwrap().pids = Some(LinuxPids { limit: 100 });
+        m.unapplied
+            .lock()
+            .unwrap()
+            .push("hugepageLimits: hugetlb controller missing".to_string());
+        m.add_notifier("oom");
+
+        save(&state_dir, "c1", &m).unwrap();
+        let r = restore(&state_dir, "c1").unwrap();
+
+        assert_eq!(r.cpath, m.cpath);
+        assert_eq!(r.paths, m.paths);
+        assert_eq!(r.backend, Backend::V1);
+        assert!(r.strict_resources);
+        assert!(r.cgroup.is_some());
+        assert_eq!(r.device_map.get(&(8, 0)), Some(&(254, 0)));
+        assert_eq!(
+            r.applied.lock().unwrap().pids,
+            Some(LinuxPids { limit: 100 })
+        );
+        assert_eq!(r.unapplied_resources(), m.unapplied_resources());
+        assert_eq!(r.notifiers(), vec!["oom"]);
+
+        // the cgroup is gone
+        fs::remove_dir(&cg_dir).unwrap();
+        assert!(restore(&state_dir, "c1").is_err());
+
+        remove(&state_dir, "c1").unwrap();
+        assert!(!state_dir.join("c1.json").exists());
+        remove(&state_dir, "c1").unwrap();
+        assert!(restore(&state_dir, "c1").is_err());
+    }
+
+    #[test]
+    fn test_list_remove_all() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_dir = dir.path().join("state");
+        assert!(list(&state_dir).unwrap().is_empty());
+
+        let m = manager(dir.path());
+        save(&state_dir, "c2", &m).unwrap();
+        save(&state_dir, "c1", &m).unwrap();
+        fs::write(state_dir.join("c3.tmp"), "").unwrap();
+        assert_eq!(list(&state_dir).unwrap(), vec!["c1", "c2"]);
+
+        remove_all(&state_dir).unwrap();
+        assert!(!state_dir.exists());
+        remove_all(&state_dir).unwrap();
+    }
+
+    #[test]
+    fn test_state_path() {
+        let dir = Path::new(STATE_DIR);
+        assert_eq!(
+            state_path(dir, "c1").unwrap(),
+            Path::new("/run/kata-containers/cgroups/c1.json")
+        );
+
+        for cid in &["", "../c1", "a/b", ".hidden"] {
+            assert!(state_path(dir, cid).is_err(), "{}", cid);
+        }
+    }
+}
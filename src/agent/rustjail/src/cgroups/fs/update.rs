@@ -0,0 +1,348 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Update semantics for the container resources.
+//
+// An update request only carries the settings to change, every field left
+// unset (or 0, as the gRPC protocol can't tell the difference) keeps the
+// value applied before.
+
+use oci::{LinuxBlockIo, LinuxCpu, LinuxHugepageLimit, LinuxMemory, LinuxNetwork, LinuxResources};
+use std::collections::HashMap;
+
+// pick returns the new value if it is set, the old one otherwise.
+fn pick<T: Copy + Default + PartialEq>(new: Option<T>, old: Option<T>) -> Option<T> {
+    match new {
+        Some(v) if v != T::default() => Some(v),
+        _ => old,
+    }
+}
+
+fn pick_string(new: &str, old: &str) -> String {
+    if new.is_empty() {
+        old.to_string()
+    } else {
+        new.to_string()
+    }
+}
+
+fn pick_vec<T: Clone>(new: &[T], old: &[T]) -> Vec<T> {
+    if new.is_empty() {
+        old.to_vec()
+    } else {
+        new.to_vec()
+    }
+}
+
+fn merge_section<T: Clone>(old: &Option<T>, new: &Option<T>, merge: fn(&T, &T) -> T) -> Option<T> {
+    match (old, new) {
+        (Some(o), Some(n)) => Some(merge(o, n)),
+        (None, Some(n)) => Some(n.clone()),
+        (o, None) => o.clone(),
+    }
+}
+
+fn merge_cpu(old: &LinuxCpu, new: &LinuxCpu) -> LinuxCpu {
+    LinuxCpu {
+        shares: pick(new.shares, old.shares),
+        quota: pick(new.quota, old.quota),
+        period: pick(new.period, old.period),
+        realtime_runtime: pick(new.realtime_runtime, old.realtime_runtime),
+        realtime_period: pick(new.realtime_period, old.realtime_period),
+        cpus: pick_string(&new.cpus, &old.cpus),
+        mems: pick_string(&new.mems, &old.mems),
+        burst: pick(new.burst, old.burst),
+        idle: pick(new.idle, old.idle),
+        cpus_partition: pick_string(&new.cpus_partition, &old.cpus_partition),
+    }
+}
+
+fn merge_memory(old: &LinuxMemory, new: &LinuxMemory) -> LinuxMemory {
+    LinuxMemory {
+        limit: pick(new.limit, old.limit),
+        reservation: pick(new.reservation, old.reservation),
+        swap: pick(new.swap, old.swap),
+        kernel: pick(new.kernel, old.kernel),
+        kernel_tcp: pick(new.kernel_tcp, old.kernel_tcp),
+        swappiness: new.swappiness.or(old.swappiness),
+        disable_oom_killer: pick(new.disable_oom_killer, old.disable_oom_killer),
+        high: pick(new.high, old.high),
+    }
+}
+
+fn merge_block_io(old: &LinuxBlockIo, new: &LinuxBlockIo) -> LinuxBlockIo {
+    LinuxBlockIo {
+        weight: pick(new.weight, old.weight),
+        leaf_weight: pick(new.leaf_weight, old.leaf_weight),
+        weight_device: pick_vec(&new.weight_device, &old.weight_device),
+        throttle_read_bps_device: pick_vec(
+            &new.throttle_read_bps_device,
+            &old.throttle_read_bps_device,
+        ),
+        throttle_write_bps_device: pick_vec(
+            &new.throttle_write_bps_device,
+            &old.throttle_write_bps_device,
+        ),
+        throttle_read_iops_device: pick_vec(
+            &new.throttle_read_iops_device,
+            &old.throttle_read_iops_device,
+        ),
+        throttle_write_iops_device: pick_vec(
+            &new.throttle_write_iops_device,
+            &old.throttle_write_iops_device,
+        ),
+    }
+}
+
+fn merge_network(old: &LinuxNetwork, new: &LinuxNetwork) -> LinuxNetwork {
+    LinuxNetwork {
+        class_id: pick(new.class_id, old.class_id),
+        priorities: pick_vec(&new.priorities, &old.priorities),
+    }
+}
+
+fn merge_hugepage_limits(
+    old: &[LinuxHugepageLimit],
+    new: &[LinuxHugepageLimit],
+) -> Vec<LinuxHugepageLimit> {
+    let mut limits = old.to_vec();
+
+    for l in new {
+        match limits.iter_mut().find(|x| x.page_size == l.page_size) {
+            Some(x) => x.limit = l.limit,
+            None => limits.push(l.clone()),
+        }
+    }
+
+    limits
+}
+
+// merge_resources returns the resources of the container once `update`
+// has been applied on top of `applied`. The device rules are ordered, so
+// they are replaced rather than merged.
+pub fn merge_resources(applied: &LinuxResources, update: &LinuxResources) -> LinuxResources {
+    let mut unified = applied.unified.clone();
+    unified.extend(update.unified.clone());
+
+    let mut rdma = applied.rdma.clone();
+    rdma.extend(update.rdma.clone());
+
+    let mut misc = applied.misc.clone();
+    misc.extend(update.misc.clone());
+
+    LinuxResources {
+        devices: pick_vec(&update.devices, &applied.devices),
+        memory: merge_section(&applied.memory, &update.memory, merge_memory),
+        cpu: merge_section(&applied.cpu, &update.cpu, merge_cpu),
+        pids: match &update.pids {
+            Some(p) if p.limit != 0 => Some(p.clone()),
+            _ => applied.pids.clone(),
+        },
+        block_io: merge_section(&applied.block_io, &update.block_io, merge_block_io),
+        hugepage_limits: merge_hugepage_limits(&applied.hugepage_limits, &update.hugepage_limits),
+        network: merge_section(&applied.network, &update.network, merge_network),
+        rdma,
+        unified,
+        misc,
+    }
+}
+
+// update_delta returns the resources to write for `update`: only what the
+// request sets, plus the applied value of the fields which are written
+// together with them, i.e. the cfs quota and period which share cpu.max
+// on cgroup v2, and the memory limit the swap limit is checked against.
+pub fn update_delta(applied: &LinuxResources, update: &LinuxResources) -> LinuxResources {
+    let mut delta = update.clone();
+
+    if let (Some(new), Some(old)) = (delta.cpu.as_mut(), applied.cpu.as_ref()) {
+        if pick(new.quota, None).is_some() || pick(new.period, None).is_some() {
+            new.quota = pick(new.quota, old.quota);
+            new.period = pick(new.period, old.period);
+        }
+    }
+
+    if let (Some(new), Some(old)) = (delta.memory.as_mut(), applied.memory.as_ref()) {
+        if pick(new.swap, None).is_some() {
+            new.limit = pick(new.limit, old.limit);
+        }
+    }
+
+    delta
+}
+
+// rollback_delta returns the resources to write back once writing `delta`
+// failed halfway: the applied sections it went through, with their applied
+// values. A setting which was never applied has no value to go back to.
+pub fn rollback_delta(applied: &LinuxResources, delta: &LinuxResources) -> LinuxResources {
+    fn section<T: Clone>(old: &Option<T>, new: &Option<T>) -> Option<T> {
+        new.as_ref().and(old.clone())
+    }
+
+    fn keys<T: Clone>(old: &HashMap<String, T>, new: &HashMap<String, T>) -> HashMap<String, T> {
+        old.iter()
+            .filter(|(k, _)| new.contains_key(*k))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
+
+    LinuxResources {
+        devices: if delta.devices.is_empty() {
+            vec![]
+        } else {
+            applied.devices.clone()
+        },
+        memory: section(&applied.memory, &delta.memory),
+        cpu: section(&applied.cpu, &delta.cpu),
+        pids: section(&applied.pids, &delta.pids),
+        block_io: section(&applied.block_io, &delta.block_io),
+        hugepage_limits: applied
+            .hugepage_limits
+            .iter()
+            .filter(|l| {
+                delta
+                    .hugepage_limits
+                    .iter()
+                    .any(|d| d.page_size == l.page_size)
+            })
+            .cloned()
+            .collect(),
+        network: section(&applied.network, &delta.network),
+        rdma: keys(&applied.rdma, &delta.rdma),
+        unified: keys(&applied.unified, &delta.unified),
+        misc: keys(&applied.misc, &delta.misc),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci::{LinuxDeviceCgroup, LinuxPids};
+
+    fn applied() -> LinuxResources {
+        LinuxResources {
+            devices: vec![LinuxDeviceCgroup {
+                allow: false,
+                access: "rwm".to_string(),
+                ..Default::default()
+            }],
+            cpu: Some(LinuxCpu {
+                shares: Some(1024),
+                quota: Some(50000),
+                period: Some(100000),
+                cpus: "0-1".to_string(),
+                ..Default::default()
+            }),
+            memory: Some(LinuxMemory {
+                limit: Some(1 << 30),
+                swap: Some(2 << 30),
+                ..Default::default()
+            }),
+            pids: Some(LinuxPids { limit: 100 }),
+            hugepage_limits: vec![LinuxHugepageLimit {
+                page_size: "2MB".to_string(),
+                limit: 1 << 21,
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_merge_resources() {
+        let update = LinuxResources {
+            cpu: Some(LinuxCpu {
+                shares: Some(0),
+                quota: Some(20000),
+                period: Some(0),
+                ..Default::default()
+            }),
+            pids: Some(LinuxPids { limit: 0 }),
+            hugepage_limits: vec![LinuxHugepageLimit {
+                page_size: "1GB".to_string(),
+                limit: 1 << 30,
+            }],
+            ..Default::default()
+        };
+
+        let merged = merge_resources(&applied(), &update);
+
+        let cpu = merged.cpu.as_ref().unwrap();
+        assert_eq!(cpu.shares, Some(1024));
+        assert_eq!(cpu.quota, Some(20000));
+        assert_eq!(cpu.period, Some(100000));
+        assert_eq!(cpu.cpus, "0-1");
+        assert_eq!(merged.memory, applied().memory);
+        assert_eq!(merged.pids, Some(LinuxPids { limit: 100 }));
+        assert_eq!(merged.devices, applied().devices);
+        assert_eq!(merged.hugepage_limits.len(), 2);
+
+        assert_eq!(
+            merge_resources(&applied(), &LinuxResources::default()),
+            applied()
+        );
+    }
+
+    #[test]
+    fn test_update_delta() {
+        let update = LinuxResources {
+            cpu: Some(LinuxCpu {
+                period: Some(200000),
+                ..Default::default()
+            }),
+            memory: Some(LinuxMemory {
+                swap: Some(3 << 30),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let delta = update_delta(&applied(), &update);
+
+        let cpu = delta.cpu.as_ref().unwrap();
+        assert_eq!(cpu.shares, None);
+        assert_eq!(cpu.quota, Some(50000));
+        assert_eq!(cpu.period, Some(200000));
+        assert!(cpu.cpus.is_empty());
+
+        let memory = delta.memory.as_ref().unwrap();
+        assert_eq!(memory.limit, Some(1 << 30));
+        assert_eq!(memory.swap, Some(3 << 30));
+
+        assert!(delta.devices.is_empty());
+        assert!(delta.pids.is_none());
+        assert!(delta.hugepage_limits.is_empty());
+    }
+
+    #[test]
+    fn test_rollback_delta() {
+        let delta = LinuxResources {
+            cpu: Some(LinuxCpu {
+                shares: Some(512),
+                ..Default::default()
+            }),
+            block_io: Some(LinuxBlockIo {
+                weight: Some(100),
+                ..Default::default()
+            }),
+            hugepage_limits: vec![LinuxHugepageLimit {
+                page_size: "2MB".to_string(),
+                limit: 1 << 22,
+            }],
+            unified: [("memory.high".to_string(), "max".to_string())]
+                .iter()
+                .cloned()
+                .collect(),
+            ..Default::default()
+        };
+
+        let rollback = rollback_delta(&applied(), &delta);
+        assert_eq!(rollback.cpu, applied().cpu);
+        assert_eq!(rollback.hugepage_limits, applied().hugepage_limits);
+        assert!(rollback.memory.is_none());
+        assert!(rollback.devices.is_empty());
+        // never applied
+        assert!(rollback.block_io.is_none());
+        assert!(rollback.unified.is_empty());
+    }
+}
@@ -0,0 +1,1454 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Resource setters for the cgroup v2 unified hierarchy.
+//
+// The OCI spec is modelled on cgroup v1, so every section is translated
+// here into the corresponding unified interface file, see
+// https://www.kernel.org/doc/html/latest/admin-guide/cgroup-v2.html
+
+use crate::cgroups::{CgroupError, MemoryUsage};
+use crate::container::DEFAULT_DEVICES;
+use anyhow::{anyhow, Context, Result};
+use cgroups::freezer::FreezerState;
+use nix::errno::Errno;
+use oci::{
+    LinuxBlockIo, LinuxCpu, LinuxDeviceCgroup, LinuxHugepageLimit, LinuxMemory, LinuxNetwork,
+    LinuxPids, LinuxResources, LinuxThrottleDevice,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use super::{
+    convert_memory_swap_to_v2_value, convert_shares_to_v2_value, lines_to_map,
+    set_cpu_burst_and_idle, validate_swappiness, ResourceReport, CGROUP_PROCS, CGROUP_THREADS,
+    DEFAULT_ALLOWED_DEVICES, NO_CFS_BANDWIDTH, NO_SWAP_ACCOUNTING,
+};
+use super::{
+    devicefilter, existing_interface_priorities, misc, netprio, set_hugepages_rsvd_limits,
+};
+use crate::cgroups::stats::{
+    BlkioStats, BlkioStatsEntry, CgroupStats, CpuStats, CpuUsage, HugetlbStats, MemoryData,
+    MemoryEvents, MemoryStats, PidsStats, ThrottlingData,
+};
+
+pub const CGROUP2_ROOT: &str = "/sys/fs/cgroup";
+
+pub const CPU_MAX: &str = "cpu.max";
+const CPU_MAX_BURST: &str = "cpu.max.burst";
+const CPU_WEIGHT: &str = "cpu.weight";
+const CPUSET_CPUS: &str = "cpuset.cpus";
+const CPUSET_MEMS: &str = "cpuset.mems";
+const CPUSET_CPUS_EXCLUSIVE: &str = "cpuset.cpus.exclusive";
+const CPUSET_CPUS_PARTITION: &str = "cpuset.cpus.partition";
+const CPUSET_PARTITION_TYPES: &[&str] = &["member", "root", "isolated"];
+const MEMORY_MAX: &str = "memory.max";
+const MEMORY_LOW: &str = "memory.low";
+const MEMORY_MIN: &str = "memory.min";
+const MEMORY_HIGH: &str = "memory.high";
+pub const MEMORY_SWAP_MAX: &str = "memory.swap.max";
+const PIDS_MAX: &str = "pids.max";
+const IO_WEIGHT: &str = "io.weight";
+const IO_BFQ_WEIGHT: &str = "io.bfq.weight";
+const IO_COST_QOS: &str = "io.cost.qos";
+const IO_COST_MODEL: &str = "io.cost.model";
+pub const CGROUP_CONTROLLERS: &str = "cgroup.controllers";
+const CGROUP_FREEZE: &str = "cgroup.freeze";
+pub const CGROUP_EVENTS: &str = "cgroup.events";
+const IO_MAX: &str = "io.max";
+const CPU_STAT: &str = "cpu.stat";
+const MEMORY_STAT: &str = "memory.stat";
+const MEMORY_CURRENT: &str = "memory.current";
+const MEMORY_PEAK: &str = "memory.peak";
+const MEMORY_EVENTS: &str = "memory.events";
+const MEMORY_OOM_GROUP: &str = "memory.oom.group";
+const MEMORY_SWAP_CURRENT: &str = "memory.swap.current";
+const PIDS_CURRENT: &str = "pids.current";
+const PIDS_EVENTS: &str = "pids.events";
+const IO_STAT: &str = "io.stat";
+pub const HUGETLB_PREFIX: &str = "hugetlb.";
+
+const MAX: &str = "max";
+const DEFAULT_CPU_PERIOD: u64 = 100000;
+
+// Convenience macro to obtain the scope logger
+macro_rules! sl {
+    () => {
+        slog_scope::logger().new(o!("subsystem" => "cgroups_v2"))
+    };
+}
+
+// write_cgroup_file writes `value` into the interface file `file` of the
+// cgroup located at `dir`.
+pub fn write_cgroup_file(dir: &Path, file: &str, value: &str) -> Result<()> {
+    let path = dir.join(file);
+    info!(sl!(), "write {:?} to {:?}", value, &path);
+    fs::write(&path, value)
+        .map_err(|e| CgroupError::io(&path, e))
+        .with_context(|| format!("failed to write {} to {:?}", value, path))
+}
+
+// set_resources applies all the OCI resource sections to the
+// unified cgroup located at `dir`. Settings cgroup v2 has no equivalent
+// for are skipped through `report`, which fails them in strict mode.
+pub fn set_resources(dir: &Path, r: &LinuxResources, report: &mut ResourceReport) -> Result<()> {
+    info!(sl!(), "cgroup v2 set resources for {:?}", dir);
+
+    if let Some(cpu) = &r.cpu {
+        set_cpu_resources(dir, cpu, report)?;
+    }
+
+    if let Some(memory) = &r.memory {
+        set_memory_resources(dir, memory, report)?;
+    }
+
+    if let Some(pids) = &r.pids {
+        set_pids_resources(dir, pids)?;
+    }
+
+    if let Some(blkio) = &r.block_io {
+        set_block_io_resources(dir, blkio, report)?;
+    }
+
+    if !r.hugepage_limits.is_empty() {
+        if is_controller_enabled(dir, "hugetlb") {
+            set_hugepages_resources(dir, &r.hugepage_limits)?;
+        } else {
+            report.skip("hugepageLimits", "hugetlb controller not enabled")?;
+        }
+    }
+
+    if let Some(network) = &r.network {
+        set_network_resources(dir, network, report)?;
+    }
+
+    if !r.misc.is_empty() {
+        if is_controller_enabled(dir, misc::MISC) {
+            misc::set_misc_limits(dir, &r.misc)?;
+        } else {
+            report.skip("misc", "misc controller not enabled")?;
+        }
+    }
+
+    set_devices_resources(dir, &r.devices)?;
+
+    // the raw unified keys go last so they can override the values
+    // derived from the structured sections above
+    if !r.unified.is_empty() {
+        set_unified_resources(dir, &r.unified)?;
+    }
+
+    Ok(())
+}
+
+// set_oom_group makes the OOM killer kill all the processes of the cgroup
+// at `dir` at once.
+pub fn set_oom_group(dir: &Path, report: &mut ResourceReport) -> Result<()> {
+    if !dir.join(MEMORY_OOM_GROUP).exists() {
+        return report.skip("oomGroup", "memory controller not enabled");
+    }
+
+    write_cgroup_file(dir, MEMORY_OOM_GROUP, "1")
+}
+
+// set_frozen freezes or thaws the cgroup at `dir`.
+pub fn set_frozen(dir: &Path, state: FreezerState) -> Result<()> {
+    let value = match state {
+        FreezerState::Frozen => "1",
+        FreezerState::Thawed => "0",
+        _ => return Err(nix::Error::Sys(Errno::EINVAL).into()),
+    };
+
+    write_cgroup_file(dir, CGROUP_FREEZE, value)
+}
+
+// get_frozen returns the freezer state of the cgroup at `dir`, which is
+// frozen as well when an ancestor is, and Freezing until all the processes
+// asked to freeze are stopped.
+pub fn get_frozen(dir: &Path) -> FreezerState {
+    let frozen = read_flat_keyed(dir, CGROUP_EVENTS).get("frozen") == Some(&1);
+    let freeze = read_single_value(dir, CGROUP_FREEZE) == Some(1);
+
+    match (freeze, frozen) {
+        (_, true) => FreezerState::Frozen,
+        (true, false) => FreezerState::Freezing,
+        (false, false) => FreezerState::Thawed,
+    }
+}
+
+// get_controllers returns the controllers available to the cgroup at `dir`.
+pub fn get_controllers(dir: &Path) -> Result<Vec<String>> {
+    let controllers = fs::read_to_string(dir.join(CGROUP_CONTROLLERS))
+        .with_context(|| format!("failed to read controllers of {:?}", dir))?;
+
+    Ok(controllers
+        .split_whitespace()
+        .map(|c| c.to_string())
+        .collect())
+}
+
+// is_controller_enabled tells whether `controller` is available to the
+// cgroup at `dir`.
+fn is_controller_enabled(dir: &Path, controller: &str) -> bool {
+    fs::read_to_string(dir.join(CGROUP_CONTROLLERS))
+        .map(|c| c.split_whitespace().any(|x| x == controller))
+        .unwrap_or(false)
+}
+
+fn set_cpu_resources(dir: &Path, cpu: &LinuxCpu, report: &mut ResourceReport) -> Result<()> {
+    if !cpu.cpus.is_empty() {
+        if let Err(e) = write_cgroup_file(dir, CPUSET_CPUS, &cpu.cpus) {
+            report.record("cpu.cpus", &e.to_string());
+        }
+    }
+
+    if !cpu.mems.is_empty() {
+        write_cgroup_file(dir, CPUSET_MEMS, &cpu.mems)?;
+    }
+
+    if !cpu.cpus_partition.is_empty() {
+        set_cpus_partition(dir, cpu, report)?;
+    }
+
+    if let Some(shares) = cpu.shares {
+        let weight = convert_shares_to_v2_value(shares);
+        if weight != 0 {
+            write_cgroup_file(dir, CPU_WEIGHT, &weight.to_string())?;
+        }
+    }
+
+    if let Some(value) = build_cpu_max(cpu.quota, cpu.period) {
+        if dir.join(CPU_MAX).exists() {
+            write_cgroup_file(dir, CPU_MAX, &value)?;
+        } else {
+            report.skip("cpu.quota", NO_CFS_BANDWIDTH)?;
+        }
+    }
+
+    if cpu.realtime_runtime.unwrap_or(0) != 0 || cpu.realtime_period.unwrap_or(0) != 0 {
+        report.skip("cpu.realtime", "not supported by cgroup v2")?;
+    }
+
+    set_cpu_burst_and_idle(dir, CPU_MAX_BURST, cpu, report)?;
+
+    Ok(())
+}
+
+// set_cpus_partition makes the cpuset of the cgroup at `dir` a partition,
+// whose cpus are taken from the parent, e.g. for the real-time workloads.
+// A partition is only valid under partition roots, which is up to the
+// sandbox setup, not the container: the kernel accepts an invalid partition
+// and only tells it when the type is read back.
+fn set_cpus_partition(dir: &Path, cpu: &LinuxCpu, report: &mut ResourceReport) -> Result<()> {
+    let partition = cpu.cpus_partition.as_str();
+    if !CPUSET_PARTITION_TYPES.contains(&partition) {
+        return Err(
+            CgroupError::invalid_value("cpu.cpusPartition", format!("{:?}", partition)).into(),
+        );
+    }
+
+    if !dir.join(CPUSET_CPUS_PARTITION).exists() {
+        return report.skip("cpu.cpusPartition", "kernel has no cpuset partitions");
+    }
+
+    // the partitions get their cpus from cpuset.cpus.exclusive since
+    // Linux 6.7, and from cpuset.cpus before
+    if partition != "member" && !cpu.cpus.is_empty() && dir.join(CPUSET_CPUS_EXCLUSIVE).exists() {
+        if let Err(e) = write_cgroup_file(dir, CPUSET_CPUS_EXCLUSIVE, &cpu.cpus) {
+            report.record("cpu.cpusPartition", &e.to_string());
+            return Ok(());
+        }
+    }
+
+    if let Err(e) = write_cgroup_file(dir, CPUSET_CPUS_PARTITION, partition) {
+        report.record("cpu.cpusPartition", &e.to_string());
+        return Ok(());
+    }
+
+    check_cpus_partition(dir, report)
+}
+
+// check_cpus_partition reads back the partition type of the cgroup at `dir`
+// and reports an invalid one as not applied.
+fn check_cpus_partition(dir: &Path, report: &mut ResourceReport) -> Result<()> {
+    // e.g. "root invalid (Parent is not a partition root)"
+    let path = dir.join(CPUSET_CPUS_PARTITION);
+    let state = fs::read_to_string(&path).map_err(|e| CgroupError::io(&path, e))?;
+    if state.contains("invalid") {
+        return report.skip("cpu.cpusPartition", state.trim());
+    }
+
+    Ok(())
+}
+
+// build_cpu_max returns the content of `cpu.max` ("$MAX $PERIOD"),
+// or None if neither quota nor period is set.
+fn build_cpu_max(quota: Option<i64>, period: Option<u64>) -> Option<String> {
+    let quota = quota.filter(|q| *q != 0);
+    let period = period.filter(|p| *p != 0);
+
+    if quota.is_none() && period.is_none() {
+        return None;
+    }
+
+    let max = match quota {
+        Some(q) if q > 0 => q.to_string(),
+        _ => MAX.to_string(),
+    };
+
+    Some(format!("{} {}", max, period.unwrap_or(DEFAULT_CPU_PERIOD)))
+}
+
+fn set_memory_resources(
+    dir: &Path,
+    memory: &LinuxMemory,
+    report: &mut ResourceReport,
+) -> Result<()> {
+    let limit = memory.limit.unwrap_or(0);
+    let mut swap = convert_memory_swap_to_v2_value(memory.swap.unwrap_or(0), limit)?;
+
+    // an unlimited swap is all there is without swap accounting
+    if swap != 0 && !dir.join(MEMORY_SWAP_MAX).exists() {
+        if swap > 0 {
+            report.skip("memory.swap", NO_SWAP_ACCOUNTING)?;
+        }
+        swap = 0;
+    }
+
+    // When updating, the new swap limit could be refused if memory.max
+    // is still lower, so write memory.max first, and memory.swap.max after.
+    if limit != 0 {
+        write_cgroup_file(dir, MEMORY_MAX, &limit_to_v2_value(limit))?;
+    }
+
+    if swap != 0 {
+        write_cgroup_file(dir, MEMORY_SWAP_MAX, &limit_to_v2_value(swap))?;
+    }
+
+    let high = memory.high.unwrap_or(0);
+    if high != 0 {
+        write_cgroup_file(dir, MEMORY_HIGH, &limit_to_v2_value(high))?;
+    }
+
+    let reservation = memory.reservation.unwrap_or(0);
+    if reservation != 0 {
+        write_cgroup_file(dir, MEMORY_LOW, &limit_to_v2_value(reservation))?;
+    }
+
+    if memory.kernel.unwrap_or(0) != 0 || memory.kernel_tcp.unwrap_or(0) != 0 {
+        report.skip("memory.kernel", "not supported by cgroup v2")?;
+    }
+
+    // cgroup v2 has no swappiness knob, a value of 0 (or unset) matches the
+    // default behaviour so only warn when a different value was asked for.
+    match validate_swappiness(memory.swappiness)? {
+        None | Some(0) => {}
+        Some(_) => report.skip("memory.swappiness", "not supported by cgroup v2")?,
+    }
+
+    if memory.disable_oom_killer.unwrap_or(false) {
+        report.skip("memory.disableOOMKiller", "not supported by cgroup v2")?;
+    }
+
+    Ok(())
+}
+
+// limit_to_v2_value converts a v1 style limit (-1 is unlimited)
+// into the unified format.
+fn limit_to_v2_value(limit: i64) -> String {
+    if limit < 0 {
+        return MAX.to_string();
+    }
+    limit.to_string()
+}
+
+fn set_pids_resources(dir: &Path, pids: &LinuxPids) -> Result<()> {
+    let value = if pids.limit > 0 {
+        pids.limit.to_string()
+    } else {
+        MAX.to_string()
+    };
+
+    write_cgroup_file(dir, PIDS_MAX, &value).context("failed to set pids resources")
+}
+
+// Since the OCI spec is designed for cgroup v1, the blkio weight
+// has to be converted from [10-1000] to [1-10000]
+// from https://github.com/opencontainers/runc/blob/a5847db387ae28c0ca4ebe4beee1a76900c86414/libcontainer/cgroups/utils.go#L400
+pub fn convert_blkio_to_v2_value(weight: u16) -> u64 {
+    if weight == 0 {
+        return 0;
+    }
+    1 + (weight.saturating_sub(10) as u64 * 9999) / 990
+}
+
+fn set_block_io_resources(
+    dir: &Path,
+    blkio: &LinuxBlockIo,
+    report: &mut ResourceReport,
+) -> Result<()> {
+    // The BFQ scheduler has its own weights, in the same [1-1000] range
+    // as the v1 ones, which io.weight doesn't affect.
+    let bfq = dir.join(IO_BFQ_WEIGHT).exists();
+    let (file, convert): (&str, fn(u16) -> u64) = if bfq {
+        (IO_BFQ_WEIGHT, |w| w as u64)
+    } else {
+        (IO_WEIGHT, convert_blkio_to_v2_value)
+    };
+
+    if let Some(weight) = blkio.weight {
+        let weight = convert(weight);
+        if weight != 0 {
+            write_cgroup_file(dir, file, &format!("default {}", weight))?;
+        }
+    }
+
+    for d in blkio.weight_device.iter() {
+        if let Some(weight) = d.weight {
+            let value = format!("{}:{} {}", d.blk.major, d.blk.minor, convert(weight));
+            write_cgroup_file(dir, file, &value)?;
+        }
+    }
+
+    if blkio.leaf_weight.is_some() {
+        report.skip("blockIO.leafWeight", "not supported by cgroup v2")?;
+    }
+
+    for (key, devices) in &[
+        ("rbps", &blkio.throttle_read_bps_device),
+        ("wbps", &blkio.throttle_write_bps_device),
+        ("riops", &blkio.throttle_read_iops_device),
+        ("wiops", &blkio.throttle_write_iops_device),
+    ] {
+        for value in build_io_max(key, devices) {
+            write_cgroup_file(dir, IO_MAX, &value)?;
+        }
+    }
+
+    Ok(())
+}
+
+// enable_io_cost turns on the iocost controller, with the kernel cost model
+// and automatic QoS parameters, for the devices `blkio` sets a weight for.
+// Without iocost or BFQ, nothing enforces io.weight. The interface files
+// only exist in the root cgroup at `root`.
+pub fn enable_io_cost(
+    root: &Path,
+    blkio: &LinuxBlockIo,
+    report: &mut ResourceReport,
+) -> Result<()> {
+    let devices: Vec<String> = blkio
+        .weight_device
+        .iter()
+        .filter(|d| d.weight.is_some())
+        .map(|d| format!("{}:{}", d.blk.major, d.blk.minor))
+        .collect();
+
+    if devices.is_empty() {
+        return Ok(());
+    }
+
+    if !root.join(IO_COST_QOS).exists() {
+        return report.skip("blockIO.ioCost", "kernel has no iocost controller");
+    }
+
+    for dev in devices {
+        write_cgroup_file(root, IO_COST_MODEL, &format!("{} ctrl=auto", dev))?;
+        write_cgroup_file(root, IO_COST_QOS, &format!("{} enable=1 ctrl=auto", dev))?;
+    }
+
+    Ok(())
+}
+
+// build_io_max returns one `io.max` line ("$MAJ:$MIN $KEY=$RATE") per device.
+fn build_io_max(key: &str, devices: &[LinuxThrottleDevice]) -> Vec<String> {
+    devices
+        .iter()
+        .map(|d| {
+            let rate = if d.rate == 0 {
+                MAX.to_string()
+            } else {
+                d.rate.to_string()
+            };
+            format!("{}:{} {}={}", d.blk.major, d.blk.minor, key, rate)
+        })
+        .collect()
+}
+
+fn set_hugepages_resources(dir: &Path, hugepage_limits: &[LinuxHugepageLimit]) -> Result<()> {
+    for l in hugepage_limits.iter() {
+        if l.page_size.is_empty() {
+            return Err(
+                CgroupError::invalid_value("hugepageLimits.pageSize", "empty".to_string()).into(),
+            );
+        }
+        let file = format!("hugetlb.{}.max", l.page_size);
+        write_cgroup_file(dir, &file, &l.limit.to_string())?;
+    }
+
+    set_hugepages_rsvd_limits(dir, hugepage_limits, "max")
+}
+
+// set_unified_resources writes the raw `unified` key/value pairs, only
+// accepting keys that belong to a controller enabled for the cgroup.
+fn set_unified_resources(dir: &Path, unified: &HashMap<String, String>) -> Result<()> {
+    let controllers = fs::read_to_string(dir.join(CGROUP_CONTROLLERS))
+        .with_context(|| format!("failed to read controllers of {:?}", dir))?;
+    let controllers: Vec<&str> = controllers.split_whitespace().collect();
+
+    for (key, value) in unified {
+        check_unified_key(key, &controllers)?;
+        write_cgroup_file(dir, key, value)?;
+    }
+
+    Ok(())
+}
+
+fn check_unified_key(key: &str, controllers: &[&str]) -> Result<()> {
+    let fields: Vec<&str> = key.splitn(2, '.').collect();
+    if key.contains('/') || fields.len() != 2 || fields[0].is_empty() || fields[1].is_empty() {
+        return Err(CgroupError::invalid_value("unified", format!("key {:?}", key)).into());
+    }
+
+    let controller = fields[0];
+    if controller != "cgroup" && !controllers.contains(&controller) {
+        return Err(CgroupError::invalid_value(
+            "unified",
+            format!(
+                "{:?} requires the {} controller, which is not enabled",
+                key, controller
+            ),
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+fn set_network_resources(
+    dir: &Path,
+    network: &LinuxNetwork,
+    report: &mut ResourceReport,
+) -> Result<()> {
+    if !network.priorities.is_empty() {
+        let priorities = existing_interface_priorities(&network.priorities, report)?;
+        // the kernel may lack the program type
+        if let Err(e) = netprio::set_network_priorities(dir, &priorities) {
+            report.skip("network.priorities", &format!("{:#}", e))?;
+        }
+    }
+
+    if network.class_id.unwrap_or(0) != 0 {
+        report.skip("network.classID", "not supported by cgroup v2")?;
+    }
+
+    Ok(())
+}
+
+// set_devices_resources installs the device filter program built from the
+// spec rules and the default devices, in the same order as the cgroup v1
+// devices controller gets them. An update without any device rule keeps
+// the filter installed when the container was created.
+fn set_devices_resources(dir: &Path, devices: &[LinuxDeviceCgroup]) -> Result<()> {
+    if devices.is_empty() {
+        return Ok(());
+    }
+
+    devicefilter::set_device_filter(dir, &device_rules(devices))
+}
+
+// device_rules returns the rules of the device filter for `devices`, the
+// default devices being always allowed.
+pub fn device_rules(devices: &[LinuxDeviceCgroup]) -> Vec<LinuxDeviceCgroup> {
+    let mut rules = devices.to_vec();
+
+    rules.extend(DEFAULT_DEVICES.iter().map(|d| LinuxDeviceCgroup {
+        allow: true,
+        r#type: d.r#type.clone(),
+        major: Some(d.major),
+        minor: Some(d.minor),
+        access: "rwm".to_string(),
+    }));
+
+    rules.extend(DEFAULT_ALLOWED_DEVICES.iter().cloned());
+
+    rules
+}
+
+// get_stats collects the statistics of the unified cgroup located at `dir`
+// into the same structure as the cgroup v1 controllers do.
+pub fn get_stats(dir: &Path) -> CgroupStats {
+    let mut stats = CgroupStats::default();
+
+    stats.cpu_stats = get_cpu_stats(dir);
+    stats.memory_stats = get_memory_stats(dir);
+    stats.pids_stats = get_pids_stats(dir);
+    stats.blkio_stats = get_blkio_stats(dir);
+    stats.hugetlb_stats = get_hugetlb_stats(dir);
+    stats.misc_stats = misc::get_misc_stats(dir);
+
+    stats
+}
+
+// get_exec_stats returns the stats of the exec child at `dir`. No
+// controller can be enabled below the container cgroup, which has
+// processes, so the exec child only has the cpu stats: the memory, io and
+// pids ones are summed from the processes of the child, as of the procfs
+// at `proc_root`, and only cover the exec'd processes still running. The
+// io of a process isn't told by device, it is reported for device 0:0.
+pub fn get_exec_stats(dir: &Path, proc_root: &Path) -> CgroupStats {
+    let mut stats = get_stats(dir);
+
+    let read_pids = |file: &str| -> Vec<u64> {
+        fs::read_to_string(dir.join(file))
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|l| l.trim().parse().ok())
+            .collect()
+    };
+
+    if stats.pids_stats.is_none() {
+        // the limit of the container cgroup applies
+        let limit = dir
+            .parent()
+            .and_then(|p| read_single_value(p, PIDS_MAX))
+            .unwrap_or(u64::MAX);
+        stats.pids_stats = Some(PidsStats {
+            current: read_pids(CGROUP_THREADS).len() as u64,
+            limit,
+            ..Default::default()
+        });
+    }
+
+    let procs = read_pids(CGROUP_PROCS);
+    // statm is in pages
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(0) as u64;
+
+    if stats.memory_stats.is_none() {
+        let rss: u64 = procs
+            .iter()
+            .filter_map(|pid| {
+                let statm =
+                    fs::read_to_string(proc_root.join(pid.to_string()).join("statm")).ok()?;
+                statm.split_whitespace().nth(1)?.parse::<u64>().ok()
+            })
+            .sum();
+
+        stats.memory_stats = Some(MemoryStats {
+            usage: Some(MemoryData {
+                usage: rss * page_size,
+                limit: u64::MAX,
+                ..Default::default()
+            }),
+            use_hierarchy: true,
+            ..Default::default()
+        });
+    }
+
+    if stats.blkio_stats.is_none() {
+        let (mut read, mut write) = (0, 0);
+        for pid in procs.iter() {
+            let io = read_flat_keyed(&proc_root.join(pid.to_string()), "io");
+            read += io.get("read_bytes:").copied().unwrap_or(0);
+            write += io.get("write_bytes:").copied().unwrap_or(0);
+        }
+
+        stats.blkio_stats = Some(BlkioStats {
+            io_service_bytes_recursive: vec![
+                build_entry(0, 0, "read", read),
+                build_entry(0, 0, "write", write),
+            ],
+            ..Default::default()
+        });
+    }
+
+    stats
+}
+
+// get_memory_usage reads the memory usage of the unified cgroup located at
+// `dir`, without the rest of the stats.
+pub fn get_memory_usage(dir: &Path) -> Result<MemoryUsage> {
+    let usage = read_single_value(dir, MEMORY_CURRENT)
+        .ok_or_else(|| anyhow!("failed to read {:?}", dir.join(MEMORY_CURRENT)))?;
+
+    Ok(MemoryUsage {
+        usage,
+        limit: read_single_value(dir, MEMORY_MAX).unwrap_or(u64::MAX),
+        swap_usage: read_single_value(dir, MEMORY_SWAP_CURRENT).unwrap_or(0),
+    })
+}
+
+// read_flat_keyed reads a `Flat keyed` interface file,
+// an empty map is returned if it doesn't exist.
+fn read_flat_keyed(dir: &Path, file: &str) -> HashMap<String, u64> {
+    fs::read_to_string(dir.join(file))
+        .map(|c| lines_to_map(&c))
+        .unwrap_or_default()
+}
+
+// read_single_value reads an interface file holding a single value,
+// where "max" stands for no limit.
+pub fn read_single_value(dir: &Path, file: &str) -> Option<u64> {
+    let content = fs::read_to_string(dir.join(file)).ok()?;
+    let content = content.trim();
+    if content == MAX {
+        return Some(u64::MAX);
+    }
+    content.parse::<u64>().ok()
+}
+
+fn get_cpu_stats(dir: &Path) -> Option<CpuStats> {
+    let h = read_flat_keyed(dir, CPU_STAT);
+    if h.is_empty() {
+        return None;
+    }
+
+    let value = |k: &str| *h.get(k).unwrap_or(&0);
+
+    // cpu.stat is in microseconds, while v1 reports nanoseconds. The
+    // kernel doesn't account the usage per cpu on cgroup v2: cpu.stat.local
+    // only has the throttled time, and schedstat is per task, not per cpu,
+    // so percpu_usage is left empty rather than made up.
+    let mut cpu_usage = CpuUsage::default();
+    cpu_usage.total_usage = value("usage_usec") * 1000;
+    cpu_usage.usage_in_usermode = value("user_usec") * 1000;
+    cpu_usage.usage_in_kernelmode = value("system_usec") * 1000;
+
+    let mut throttling_data = ThrottlingData::default();
+    throttling_data.periods = value("nr_periods");
+    throttling_data.throttled_periods = value("nr_throttled");
+    throttling_data.throttled_time = value("throttled_usec") * 1000;
+    throttling_data.nr_bursts = value("nr_bursts");
+    throttling_data.burst_time = value("burst_usec") * 1000;
+
+    let mut cpu_stats = CpuStats::default();
+    cpu_stats.cpu_usage = Some(cpu_usage);
+    cpu_stats.throttling_data = Some(throttling_data);
+
+    Some(cpu_stats)
+}
+
+fn get_memory_stats(dir: &Path) -> Option<MemoryStats> {
+    let current = match read_single_value(dir, MEMORY_CURRENT) {
+        Some(v) => v,
+        None => return None,
+    };
+
+    let stat = read_flat_keyed(dir, MEMORY_STAT);
+    let events = read_flat_keyed(dir, MEMORY_EVENTS);
+
+    let mut usage = MemoryData::default();
+    usage.usage = current;
+    usage.max_usage = read_single_value(dir, MEMORY_PEAK).unwrap_or(0);
+    usage.failcnt = *events.get("max").unwrap_or(&0);
+    usage.limit = read_single_value(dir, MEMORY_MAX).unwrap_or(u64::MAX);
+
+    let mut swap_usage = MemoryData::default();
+    swap_usage.usage = read_single_value(dir, MEMORY_SWAP_CURRENT).unwrap_or(0);
+    swap_usage.limit = read_single_value(dir, MEMORY_SWAP_MAX).unwrap_or(u64::MAX);
+    let swap_supported = dir.join(MEMORY_SWAP_MAX).exists();
+
+    // kernel memory is accounted as a whole in memory.current
+    let mut kernel_usage = MemoryData::default();
+    kernel_usage.usage = *stat.get("kernel_stack").unwrap_or(&0) + *stat.get("slab").unwrap_or(&0);
+
+    let mut memory_stats = MemoryStats::default();
+    memory_stats.cache = *stat.get("file").unwrap_or(&0);
+    memory_stats.usage = Some(usage);
+    memory_stats.swap_usage = Some(swap_usage);
+    memory_stats.kernel_usage = Some(kernel_usage);
+    // the unified hierarchy is always hierarchical
+    memory_stats.use_hierarchy = true;
+    memory_stats.stats = stat;
+    memory_stats.low = read_single_value(dir, MEMORY_LOW).unwrap_or(0);
+    memory_stats.min = read_single_value(dir, MEMORY_MIN).unwrap_or(0);
+    memory_stats.high = read_single_value(dir, MEMORY_HIGH).unwrap_or(u64::MAX);
+    memory_stats.oom_group_kill = *events.get("oom_group_kill").unwrap_or(&0);
+    memory_stats.swap_supported = swap_supported;
+    memory_stats.events = Some(get_memory_events(&events));
+
+    Some(memory_stats)
+}
+
+// get_memory_events returns the counters of memory.events, cgroup v2 not
+// telling whether the cgroup is under OOM.
+fn get_memory_events(events: &HashMap<String, u64>) -> MemoryEvents {
+    let value = |k: &str| *events.get(k).unwrap_or(&0);
+
+    let mut memory_events = MemoryEvents::default();
+    memory_events.oom = value("oom");
+    memory_events.oom_kill = value("oom_kill");
+    memory_events.high = value("high");
+    memory_events.max = value("max");
+
+    memory_events
+}
+
+fn get_pids_stats(dir: &Path) -> Option<PidsStats> {
+    let current = match read_single_value(dir, PIDS_CURRENT) {
+        Some(v) => v,
+        None => return None,
+    };
+
+    let mut pids_stats = PidsStats::default();
+    pids_stats.current = current;
+    pids_stats.limit = read_single_value(dir, PIDS_MAX).unwrap_or(u64::MAX);
+    pids_stats.max_events = *read_flat_keyed(dir, PIDS_EVENTS).get("max").unwrap_or(&0);
+
+    Some(pids_stats)
+}
+
+/*
+io.stat example:
+
+    8:16 rbytes=1459200 wbytes=314773504 rios=192 wios=353 dbytes=0 dios=0
+    8:0 rbytes=90430464 wbytes=299008000 rios=8950 wios=1252 dbytes=50331648 dios=3021
+*/
+
+fn get_blkio_stats(dir: &Path) -> Option<BlkioStats> {
+    let content = match fs::read_to_string(dir.join(IO_STAT)) {
+        Ok(c) => c,
+        Err(_) => return None,
+    };
+
+    let mut blkio_stats = BlkioStats::default();
+    let mut service_bytes = Vec::new();
+    let mut serviced = Vec::new();
+
+    for line in content.lines() {
+        let mut fields = line.split_whitespace();
+        let (major, minor) = match fields.next().and_then(parse_device) {
+            Some(d) => d,
+            None => continue,
+        };
+
+        for field in fields {
+            let kv: Vec<&str> = field.splitn(2, '=').collect();
+            if kv.len() != 2 {
+                continue;
+            }
+            let value = match kv[1].parse::<u64>() {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+
+            match kv[0] {
+                "rbytes" => service_bytes.push(build_entry(major, minor, "read", value)),
+                "wbytes" => service_bytes.push(build_entry(major, minor, "write", value)),
+                "rios" => serviced.push(build_entry(major, minor, "read", value)),
+                "wios" => serviced.push(build_entry(major, minor, "write", value)),
+                _ => {}
+            }
+        }
+    }
+
+    blkio_stats.io_service_bytes_recursive = service_bytes;
+    blkio_stats.io_serviced_recursive = serviced;
+
+    Some(blkio_stats)
+}
+
+// parse_device parses a "$MAJ:$MIN" device number.
+fn parse_device(dev: &str) -> Option<(u64, u64)> {
+    let mut it = dev.splitn(2, ':');
+    let major = it.next()?.parse::<u64>().ok()?;
+    let minor = it.next()?.parse::<u64>().ok()?;
+    Some((major, minor))
+}
+
+fn build_entry(major: u64, minor: u64, op: &str, value: u64) -> BlkioStatsEntry {
+    let mut entry = BlkioStatsEntry::default();
+    entry.major = major;
+    entry.minor = minor;
+    entry.op = op.to_string();
+    entry.value = value;
+    entry
+}
+
+pub fn get_hugetlb_stats(dir: &Path) -> HashMap<String, HugetlbStats> {
+    let mut h = HashMap::new();
+
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => return h,
+    };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        // hugetlb.<size>.rsvd.current being the reservations of <size>
+        let size = match name
+            .strip_prefix(HUGETLB_PREFIX)
+            .and_then(|n| n.strip_suffix(".current"))
+        {
+            Some(s) if !s.ends_with(".rsvd") => s.to_string(),
+            _ => continue,
+        };
+
+        let events = read_flat_keyed(dir, &format!("{}{}.events", HUGETLB_PREFIX, size));
+
+        let mut stats = HugetlbStats::default();
+        stats.usage = read_single_value(dir, &name).unwrap_or(0);
+        stats.failcnt = *events.get("max").unwrap_or(&0);
+        stats.rsvd_usage =
+            read_single_value(dir, &format!("{}{}.rsvd.current", HUGETLB_PREFIX, size))
+                .unwrap_or(0);
+
+        h.insert(size, stats);
+    }
+
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci::{LinuxBlockIoDevice, LinuxWeightDevice};
+
+    #[test]
+    fn test_get_exec_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let proc_root = tempfile::tempdir().unwrap();
+        let write = |dir: &Path, file: &str, content: &str| {
+            fs::create_dir_all(dir).unwrap();
+            fs::write(dir.join(file), content).unwrap();
+        };
+
+        // the exec child only has the cpu stats
+        write(dir.path(), CPU_STAT, "usage_usec 10\n");
+        write(dir.path(), CGROUP_PROCS, "7\n8\n");
+        write(dir.path(), CGROUP_THREADS, "7\n8\n9\n");
+        let pid = proc_root.path().join("7");
+        write(&pid, "statm", "1000 25 10 1 0 20 0\n");
+        write(
+            &pid,
+            "io",
+            "rchar: 1\nread_bytes: 4096\nwrite_bytes: 8192\n",
+        );
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+
+        let stats = get_exec_stats(dir.path(), proc_root.path());
+        assert_eq!(
+            stats.cpu_stats.unwrap().cpu_usage.unwrap().total_usage,
+            10000
+        );
+        assert_eq!(stats.pids_stats.unwrap().current, 3);
+        let memory = stats.memory_stats.unwrap();
+        assert_eq!(memory.usage.unwrap().usage, 25 * page_size);
+        assert_eq!(
+            stats.blkio_stats.unwrap().io_service_bytes_recursive,
+            vec![
+                build_entry(0, 0, "read", 4096),
+                build_entry(0, 0, "write", 8192),
+            ]
+        );
+
+        // the controller files are read when the child has them
+        write(dir.path(), PIDS_CURRENT, "5\n");
+        write(dir.path(), MEMORY_CURRENT, "4096\n");
+        let stats = get_exec_stats(dir.path(), proc_root.path());
+        assert_eq!(stats.pids_stats.unwrap().current, 5);
+        assert_eq!(stats.memory_stats.unwrap().usage.unwrap().usage, 4096);
+    }
+
+    #[test]
+    fn test_build_cpu_max() {
+        let test_cases = vec![
+            (None, None, None),
+            (Some(0), Some(0), None),
+            (Some(50000), None, Some("50000 100000")),
+            (Some(-1), Some(20000), Some("max 20000")),
+            (None, Some(20000), Some("max 20000")),
+            (Some(10000), Some(20000), Some("10000 20000")),
+        ];
+
+        for (quota, period, expected) in test_cases {
+            let result = build_cpu_max(quota, period);
+            assert_eq!(
+                result.as_deref(),
+                expected,
+                "quota: {:?}, period: {:?}",
+                quota,
+                period
+            );
+        }
+    }
+
+    #[test]
+    fn test_convert_blkio_to_v2_value() {
+        assert_eq!(convert_blkio_to_v2_value(0), 0);
+        assert_eq!(convert_blkio_to_v2_value(10), 1);
+        assert_eq!(convert_blkio_to_v2_value(1000), 10000);
+    }
+
+    #[test]
+    fn test_set_resources() {
+        let dir = tempfile::tempdir().unwrap();
+        let r = LinuxResources {
+            cpu: Some(LinuxCpu {
+                shares: Some(1024),
+                quota: Some(50000),
+                ..Default::default()
+            }),
+            memory: Some(LinuxMemory {
+                limit: Some(1 << 20),
+                swap: Some(2 << 20),
+                high: Some(1 << 19),
+                ..Default::default()
+            }),
+            pids: Some(LinuxPids { limit: 0 }),
+            block_io: Some(LinuxBlockIo {
+                throttle_read_bps_device: vec![LinuxThrottleDevice {
+                    blk: LinuxBlockIoDevice { major: 8, minor: 0 },
+                    rate: 1024,
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        // the cpu and swap limits need the kernel support
+        let mut report = ResourceReport::new(false);
+        set_resources(dir.path(), &r, &mut report).unwrap();
+        assert_eq!(
+            report.unapplied,
+            vec![
+                "cpu.quota: kernel has no CFS bandwidth control",
+                "memory.swap: kernel has no swap accounting",
+            ]
+        );
+        assert!(!dir.path().join(CPU_MAX).exists());
+        assert!(!dir.path().join(MEMORY_SWAP_MAX).exists());
+        assert!(set_resources(dir.path(), &r, &mut ResourceReport::new(true)).is_err());
+
+        fs::write(dir.path().join(MEMORY_SWAP_MAX), "max\n").unwrap();
+        fs::write(dir.path().join(CPU_MAX), "max 100000\n").unwrap();
+        let mut report = ResourceReport::new(false);
+        set_resources(dir.path(), &r, &mut report).unwrap();
+        assert!(report.unapplied.is_empty());
+
+        let read = |f: &str| fs::read_to_string(dir.path().join(f)).unwrap();
+        assert_eq!(read(CPU_WEIGHT), "39");
+        assert_eq!(read(CPU_MAX), "50000 100000");
+        assert_eq!(read(MEMORY_MAX), (1 << 20).to_string());
+        assert_eq!(read(MEMORY_SWAP_MAX), (1 << 20).to_string());
+        assert_eq!(read(MEMORY_HIGH), (1 << 19).to_string());
+        assert_eq!(read(PIDS_MAX), "max");
+        assert_eq!(read(IO_MAX), "8:0 rbps=1024");
+    }
+
+    #[test]
+    fn test_set_block_io_resources() {
+        let dir = tempfile::tempdir().unwrap();
+        let blkio = LinuxBlockIo {
+            weight: Some(500),
+            weight_device: vec![LinuxWeightDevice {
+                blk: LinuxBlockIoDevice {
+                    major: 253,
+                    minor: 0,
+                },
+                weight: Some(1000),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let read = |f: &str| fs::read_to_string(dir.path().join(f)).unwrap();
+
+        set_block_io_resources(dir.path(), &blkio, &mut ResourceReport::new(true)).unwrap();
+        // the last write wins in the test, the kernel keeps both
+        assert_eq!(read(IO_WEIGHT), "253:0 10000");
+
+        // BFQ in use
+        fs::write(dir.path().join(IO_BFQ_WEIGHT), "default 100").unwrap();
+        set_block_io_resources(dir.path(), &blkio, &mut ResourceReport::new(true)).unwrap();
+        assert_eq!(read(IO_BFQ_WEIGHT), "253:0 1000");
+
+        let mut report = ResourceReport::new(false);
+        enable_io_cost(dir.path(), &blkio, &mut report).unwrap();
+        assert_eq!(
+            report.unapplied,
+            vec!["blockIO.ioCost: kernel has no iocost controller"]
+        );
+
+        fs::write(dir.path().join(IO_COST_QOS), "").unwrap();
+        enable_io_cost(dir.path(), &blkio, &mut ResourceReport::new(true)).unwrap();
+        assert_eq!(read(IO_COST_QOS), "253:0 enable=1 ctrl=auto");
+        assert_eq!(read(IO_COST_MODEL), "253:0 ctrl=auto");
+    }
+
+    #[test]
+    fn test_set_resources_strict() {
+        let dir = tempfile::tempdir().unwrap();
+        let r = LinuxResources {
+            network: Some(LinuxNetwork {
+                class_id: Some(1),
+                ..Default::default()
+            }),
+            hugepage_limits: vec![LinuxHugepageLimit {
+                page_size: "2MB".to_string(),
+                limit: 1 << 21,
+            }],
+            misc: [("sev".to_string(), 4)].iter().cloned().collect(),
+            ..Default::default()
+        };
+
+        let mut report = ResourceReport::new(false);
+        assert!(set_resources(dir.path(), &r, &mut report).is_ok());
+        assert_eq!(
+            report.unapplied,
+            vec![
+                "hugepageLimits: hugetlb controller not enabled",
+                "network.classID: not supported by cgroup v2",
+                "misc: misc controller not enabled",
+            ]
+        );
+        assert!(!dir.path().join("hugetlb.2MB.max").exists());
+
+        let mut report = ResourceReport::new(true);
+        assert!(set_resources(dir.path(), &r, &mut report).is_err());
+    }
+
+    #[test]
+    fn test_set_cpu_burst_and_idle() {
+        let dir = tempfile::tempdir().unwrap();
+        let cpu = LinuxCpu {
+            burst: Some(20000),
+            idle: Some(1),
+            ..Default::default()
+        };
+
+        // an older kernel
+        let mut report = ResourceReport::new(false);
+        set_cpu_resources(dir.path(), &cpu, &mut report).unwrap();
+        assert_eq!(
+            report.unapplied,
+            vec![
+                "cpu.burst: kernel has no CFS burst",
+                "cpu.idle: kernel has no idle cgroups",
+            ]
+        );
+
+        fs::write(dir.path().join(CPU_MAX_BURST), "0").unwrap();
+        fs::write(dir.path().join("cpu.idle"), "0").unwrap();
+
+        let mut report = ResourceReport::new(true);
+        set_cpu_resources(dir.path(), &cpu, &mut report).unwrap();
+
+        let read = |f: &str| fs::read_to_string(dir.path().join(f)).unwrap();
+        assert_eq!(read(CPU_MAX_BURST), "20000");
+        assert_eq!(read("cpu.idle"), "1");
+
+        // and reset
+        let cpu = LinuxCpu {
+            burst: Some(0),
+            idle: Some(0),
+            ..Default::default()
+        };
+        set_cpu_resources(dir.path(), &cpu, &mut report).unwrap();
+        assert_eq!(read(CPU_MAX_BURST), "0");
+        assert_eq!(read("cpu.idle"), "0");
+    }
+
+    #[test]
+    fn test_set_oom_group() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut report = ResourceReport::new(false);
+        set_oom_group(dir.path(), &mut report).unwrap();
+        assert_eq!(
+            report.unapplied,
+            vec!["oomGroup: memory controller not enabled"]
+        );
+
+        fs::write(dir.path().join(MEMORY_OOM_GROUP), "0").unwrap();
+        set_oom_group(dir.path(), &mut ResourceReport::new(true)).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join(MEMORY_OOM_GROUP)).unwrap(),
+            "1"
+        );
+    }
+
+    #[test]
+    fn test_get_controllers() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(get_controllers(dir.path()).is_err());
+
+        fs::write(dir.path().join(CGROUP_CONTROLLERS), "cpu memory pids\n").unwrap();
+        assert_eq!(
+            get_controllers(dir.path()).unwrap(),
+            vec!["cpu", "memory", "pids"]
+        );
+    }
+
+    #[test]
+    fn test_set_cpus_partition() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cpu = LinuxCpu {
+            cpus: "2-3".to_string(),
+            cpus_partition: "isolated".to_string(),
+            ..Default::default()
+        };
+
+        // an older kernel
+        let mut report = ResourceReport::new(false);
+        set_cpus_partition(dir.path(), &cpu, &mut report).unwrap();
+        assert_eq!(
+            report.unapplied,
+            vec!["cpu.cpusPartition: kernel has no cpuset partitions"]
+        );
+        assert!(set_cpus_partition(dir.path(), &cpu, &mut ResourceReport::new(true)).is_err());
+
+        fs::write(dir.path().join(CPUSET_CPUS_PARTITION), "member").unwrap();
+        fs::write(dir.path().join(CPUSET_CPUS_EXCLUSIVE), "").unwrap();
+
+        let mut report = ResourceReport::new(true);
+        set_cpus_partition(dir.path(), &cpu, &mut report).unwrap();
+        assert!(report.unapplied.is_empty());
+
+        let read = |f: &str| fs::read_to_string(dir.path().join(f)).unwrap();
+        assert_eq!(read(CPUSET_CPUS_EXCLUSIVE), "2-3");
+        assert_eq!(read(CPUSET_CPUS_PARTITION), "isolated");
+
+        cpu.cpus_partition = "exclusive".to_string();
+        assert!(set_cpus_partition(dir.path(), &cpu, &mut report).is_err());
+    }
+
+    #[test]
+    fn test_set_cpus_partition_ancestors() {
+        let dir = tempfile::tempdir().unwrap();
+        let sb = dir.path().join("sb");
+        let c1 = sb.join("c1");
+        fs::create_dir_all(&c1).unwrap();
+
+        fs::write(sb.join(CPUSET_CPUS_PARTITION), "member").unwrap();
+        fs::write(sb.join(CPUSET_CPUS_EXCLUSIVE), "").unwrap();
+        fs::write(c1.join(CPUSET_CPUS_PARTITION), "member").unwrap();
+        fs::write(c1.join(CPUSET_CPUS_EXCLUSIVE), "").unwrap();
+
+        let cpu = LinuxCpu {
+            cpus: "2-3".to_string(),
+            cpus_partition: "isolated".to_string(),
+            ..Default::default()
+        };
+        set_cpus_partition(&c1, &cpu, &mut ResourceReport::new(true)).unwrap();
+
+        // only the container's own cpuset is written
+        let read = |d: &Path, f: &str| fs::read_to_string(d.join(f)).unwrap();
+        assert_eq!(read(&sb, CPUSET_CPUS_PARTITION), "member");
+        assert_eq!(read(&sb, CPUSET_CPUS_EXCLUSIVE), "");
+        assert_eq!(read(&c1, CPUSET_CPUS_PARTITION), "isolated");
+        assert_eq!(read(&c1, CPUSET_CPUS_EXCLUSIVE), "2-3");
+    }
+
+    #[test]
+    fn test_check_cpus_partition() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(CPUSET_CPUS_PARTITION), "isolated\n").unwrap();
+
+        let mut report = ResourceReport::new(true);
+        check_cpus_partition(dir.path(), &mut report).unwrap();
+        assert!(report.unapplied.is_empty());
+
+        // how the kernel tells the parent isn't a partition root
+        fs::write(
+            dir.path().join(CPUSET_CPUS_PARTITION),
+            "isolated invalid (Parent is not a partition root)\n",
+        )
+        .unwrap();
+
+        let mut report = ResourceReport::new(false);
+        check_cpus_partition(dir.path(), &mut report).unwrap();
+        assert_eq!(
+            report.unapplied,
+            vec!["cpu.cpusPartition: isolated invalid (Parent is not a partition root)"]
+        );
+        assert!(check_cpus_partition(dir.path(), &mut ResourceReport::new(true)).is_err());
+    }
+
+    #[test]
+    fn test_set_unified_resources() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(CGROUP_CONTROLLERS), "cpu memory pids\n").unwrap();
+
+        let mut r = LinuxResources {
+            memory: Some(LinuxMemory {
+                limit: Some(1 << 20),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        r.unified
+            .insert("memory.high".to_string(), "524288".to_string());
+        r.unified
+            .insert(MEMORY_MAX.to_string(), "2097152".to_string());
+        set_resources(dir.path(), &r, &mut ResourceReport::default()).unwrap();
+
+        let read = |f: &str| fs::read_to_string(dir.path().join(f)).unwrap();
+        assert_eq!(read("memory.high"), "524288");
+        // unified values override the structured ones
+        assert_eq!(read(MEMORY_MAX), "2097152");
+
+        let controllers = ["cpu", "memory"];
+        let test_cases = vec![
+            ("memory.high", true),
+            ("cpu.max.burst", true),
+            ("cgroup.freeze", true),
+            ("io.max", false),
+            ("memory", false),
+            (".max", false),
+            ("memory.", false),
+            ("../memory.high", false),
+        ];
+
+        for (key, ok) in test_cases {
+            assert_eq!(
+                check_unified_key(key, &controllers).is_ok(),
+                ok,
+                "key: {}",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_memory_usage() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(get_memory_usage(dir.path()).is_err());
+
+        let write = |f: &str, c: &str| fs::write(dir.path().join(f), c).unwrap();
+        write(MEMORY_CURRENT, "4096\n");
+        write(MEMORY_MAX, "max\n");
+        assert_eq!(
+            get_memory_usage(dir.path()).unwrap(),
+            MemoryUsage {
+                usage: 4096,
+                limit: u64::MAX,
+                swap_usage: 0,
+            }
+        );
+
+        write(MEMORY_MAX, "8192\n");
+        write(MEMORY_SWAP_CURRENT, "1024\n");
+        assert_eq!(
+            get_memory_usage(dir.path()).unwrap(),
+            MemoryUsage {
+                usage: 4096,
+                limit: 8192,
+                swap_usage: 1024,
+            }
+        );
+    }
+
+    #[test]
+    fn test_get_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |f: &str, c: &str| fs::write(dir.path().join(f), c).unwrap();
+
+        write(
+            CPU_STAT,
+            "usage_usec 300\nuser_usec 200\nsystem_usec 100\nnr_periods 4\nnr_throttled 2\nthrottled_usec 10\n",
+        );
+        write(MEMORY_CURRENT, "4096\n");
+        write(MEMORY_MAX, "max\n");
+        write(MEMORY_STAT, "anon 1024\nfile 2048\n");
+        write(MEMORY_LOW, "1048576\n");
+        write(MEMORY_MIN, "0\n");
+        write(MEMORY_HIGH, "max\n");
+        write(
+            MEMORY_EVENTS,
+            "low 0\nhigh 7\nmax 4\noom 2\noom_kill 1\noom_group_kill 0\n",
+        );
+        write(PIDS_CURRENT, "3\n");
+        write(PIDS_MAX, "max\n");
+        write(PIDS_EVENTS, "max 5\n");
+        write(
+            IO_STAT,
+            "8:0 rbytes=512 wbytes=1024 rios=1 wios=2 dbytes=0 dios=0\n",
+        );
+        write("hugetlb.2MB.current", "2097152\n");
+        write("hugetlb.2MB.events", "max 1\n");
+        write("hugetlb.2MB.rsvd.current", "4194304\n");
+
+        let stats = get_stats(dir.path());
+
+        let cpu_usage = stats
+            .cpu_stats
+            .as_ref()
+            .unwrap()
+            .cpu_usage
+            .as_ref()
+            .unwrap();
+        assert_eq!(cpu_usage.total_usage, 300000);
+        assert_eq!(cpu_usage.usage_in_usermode, 200000);
+        assert_eq!(cpu_usage.usage_in_kernelmode, 100000);
+        let throttling_data = stats
+            .cpu_stats
+            .as_ref()
+            .unwrap()
+            .throttling_data
+            .as_ref()
+            .unwrap();
+        assert_eq!(throttling_data.throttled_periods, 2);
+        assert_eq!(throttling_data.throttled_time, 10000);
+
+        let memory_stats = stats.memory_stats.as_ref().unwrap();
+        assert_eq!(memory_stats.usage.as_ref().unwrap().usage, 4096);
+        assert_eq!(memory_stats.usage.as_ref().unwrap().limit, u64::MAX);
+        assert_eq!(memory_stats.cache, 2048);
+        assert!(!memory_stats.swap_supported);
+        assert_eq!(
+            (memory_stats.low, memory_stats.min, memory_stats.high),
+            (1048576, 0, u64::MAX)
+        );
+        assert_eq!(memory_stats.usage.as_ref().unwrap().failcnt, 4);
+        let events = memory_stats.events.as_ref().unwrap();
+        assert_eq!(
+            (events.oom, events.oom_kill, events.high, events.max),
+            (2, 1, 7, 4)
+        );
+        assert!(!events.under_oom);
+
+        assert_eq!(stats.pids_stats.as_ref().unwrap().current, 3);
+        assert_eq!(stats.pids_stats.as_ref().unwrap().limit, u64::MAX);
+        assert_eq!(stats.pids_stats.as_ref().unwrap().max_events, 5);
+
+        let blkio_stats = stats.blkio_stats.as_ref().unwrap();
+        assert_eq!(blkio_stats.io_service_bytes_recursive.len(), 2);
+        assert_eq!(blkio_stats.io_serviced_recursive.len(), 2);
+        assert_eq!(blkio_stats.io_service_bytes_recursive[1].value, 1024);
+
+        assert_eq!(stats.hugetlb_stats.len(), 1);
+        let hugetlb = stats.hugetlb_stats.get("2MB").unwrap();
+        assert_eq!(hugetlb.usage, 2097152);
+        assert_eq!(hugetlb.failcnt, 1);
+        assert_eq!(hugetlb.rsvd_usage, 4194304);
+    }
+
+    #[test]
+    fn test_set_hugepages_resources() {
+        let dir = tempfile::tempdir().unwrap();
+        let read = |f: &str| fs::read_to_string(dir.path().join(f)).unwrap();
+        // the kernel accounts the reservations of 2MB pages only
+        fs::write(dir.path().join("hugetlb.2MB.rsvd.max"), "max\n").unwrap();
+
+        let limits = vec![
+            LinuxHugepageLimit {
+                page_size: "2MB".to_string(),
+                limit: 1 << 22,
+            },
+            LinuxHugepageLimit {
+                page_size: "1GB".to_string(),
+                limit: 1 << 30,
+            },
+        ];
+        set_hugepages_resources(dir.path(), &limits).unwrap();
+
+        assert_eq!(read("hugetlb.2MB.max"), "4194304");
+        assert_eq!(read("hugetlb.2MB.rsvd.max"), "4194304");
+        assert_eq!(read("hugetlb.1GB.max"), "1073741824");
+        assert!(!dir.path().join("hugetlb.1GB.rsvd.max").exists());
+    }
+}
@@ -0,0 +1,677 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Validation of the container resources against what the guest supports,
+// before anything is written, so that a spec which can't be applied fails
+// as a whole rather than halfway through, with the cgroup partially
+// configured.
+//
+// The capabilities are probed from the interface files of the container
+// cgroup, which only exist when the kernel supports them, e.g. the swap
+// limits need swap accounting, the real-time runtime CONFIG_RT_GROUP_SCHED.
+// The settings which would fail the request are errors, the ones which
+// would be skipped are reported, and fail in strict mode.
+
+use crate::cgroups::CgroupError;
+use anyhow::Result;
+use oci::{LinuxBlockIo, LinuxCpu, LinuxHugepageLimit, LinuxMemory, LinuxPids, LinuxResources};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::v2::{get_controllers, CPU_MAX, HUGETLB_PREFIX, MEMORY_SWAP_MAX};
+use super::{
+    convert_memory_swap_to_v2_value, validate_swappiness, ResourceReport, CPU_CFS_QUOTA_US,
+    CPU_RT_RUNTIME_US, MEMORY_MEMSW_LIMIT_IN_BYTES, NO_CFS_BANDWIDTH, NO_SWAP_ACCOUNTING,
+};
+
+// the bounds of the CFS quota and period, in microseconds
+const MIN_CFS_QUOTA: i64 = 1000;
+const MIN_CFS_PERIOD: u64 = 1000;
+const MAX_CFS_PERIOD: u64 = 1_000_000;
+
+// the bounds of the blkio weights, as cgroup v1 takes them
+const MIN_BLKIO_WEIGHT: u16 = 10;
+const MAX_BLKIO_WEIGHT: u16 = 1000;
+
+// the agent doesn't set the rdma limits
+pub const NO_RDMA: &str = "not supported by the agent";
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Capabilities {
+    pub v2: bool,
+    pub memory: bool,
+    // memory+swap limits, with CONFIG_MEMCG_SWAP and swap accounting on
+    pub swap_accounting: bool,
+    // CFS quota and period, with CONFIG_CFS_BANDWIDTH
+    pub cfs_bandwidth: bool,
+    // real-time runtime limits, with CONFIG_RT_GROUP_SCHED on cgroup v1
+    pub rt_group_sched: bool,
+    // the page sizes of the hugetlb controller, e.g. "2MB", None without it
+    pub hugepage_sizes: Option<Vec<String>>,
+    pub pids: bool,
+    // the blkio controller on cgroup v1, the io one on cgroup v2
+    pub blkio: bool,
+}
+
+// probe_v1 returns the capabilities of a cgroup v1 cgroup, from its
+// directories in the hierarchies of the controllers involved, which `dir`
+// returns by controller.
+pub fn probe_v1(dir: impl Fn(&str) -> Option<PathBuf>) -> Capabilities {
+    let (memory, cpu, hugetlb) = (dir("memory"), dir("cpu"), dir("hugetlb"));
+    let exists = |d: Option<PathBuf>| d.map_or(false, |d| d.is_dir());
+
+    Capabilities {
+        v2: false,
+        memory: memory.as_ref().map_or(false, |d| d.is_dir()),
+        swap_accounting: memory.map_or(false, |d| d.join(MEMORY_MEMSW_LIMIT_IN_BYTES).exists()),
+        cfs_bandwidth: cpu
+            .as_ref()
+            .map_or(false, |d| d.join(CPU_CFS_QUOTA_US).exists()),
+        rt_group_sched: cpu.map_or(false, |d| d.join(CPU_RT_RUNTIME_US).exists()),
+        hugepage_sizes: hugetlb.and_then(|d| hugepage_sizes(&d, ".limit_in_bytes")),
+        pids: exists(dir("pids")),
+        blkio: exists(dir("blkio")),
+    }
+}
+
+// probe_v2 returns the capabilities of the cgroup v2 cgroup at `dir`.
+pub fn probe_v2(dir: &Path) -> Capabilities {
+    let controllers = get_controllers(dir).unwrap_or_default();
+    let enabled = |c: &str| controllers.iter().any(|x| x == c);
+
+    Capabilities {
+        v2: true,
+        memory: enabled("memory"),
+        swap_accounting: dir.join(MEMORY_SWAP_MAX).exists(),
+        cfs_bandwidth: dir.join(CPU_MAX).exists(),
+        rt_group_sched: false,
+        hugepage_sizes: if enabled("hugetlb") {
+            hugepage_sizes(dir, ".max")
+        } else {
+            None
+        },
+        pids: enabled("pids"),
+        blkio: enabled("io"),
+    }
+}
+
+// hugepage_sizes returns the page sizes of the hugetlb limit files of
+// `dir`, named hugetlb.<size><suffix>.
+fn hugepage_sizes(dir: &Path, suffix: &str) -> Option<Vec<String>> {
+    let entries = fs::read_dir(dir).ok()?;
+
+    let mut sizes: Vec<String> = entries
+        .flatten()
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().to_string();
+            name.strip_prefix(HUGETLB_PREFIX)
+                .and_then(|n| n.strip_suffix(suffix))
+                // the reservation limits, e.g. hugetlb.2MB.rsvd.max
+                .filter(|s| !s.contains('.'))
+                .map(|s| s.to_string())
+        })
+        .collect();
+    sizes.sort();
+
+    Some(sizes)
+}
+
+// validate checks the resources to write against the capabilities.
+pub fn validate(
+    caps: &Capabilities,
+    r: &LinuxResources,
+    report: &mut ResourceReport,
+) -> Result<()> {
+    if let Some(cpu) = r.cpu.as_ref() {
+        validate_cpu(caps, cpu, report)?;
+    }
+
+    if let Some(memory) = r.memory.as_ref() {
+        validate_memory(caps, memory, report)?;
+    }
+
+    if !r.hugepage_limits.is_empty() {
+        validate_hugepages(caps, &r.hugepage_limits, report)?;
+    }
+
+    if let Some(pids) = r.pids.as_ref() {
+        validate_pids(caps, pids)?;
+    }
+
+    if let Some(blkio) = r.block_io.as_ref() {
+        validate_block_io(caps, blkio, report)?;
+    }
+
+    if !r.rdma.is_empty() {
+        report.skip("rdma", NO_RDMA)?;
+    }
+
+    Ok(())
+}
+
+fn validate_cpu(caps: &Capabilities, cpu: &LinuxCpu, report: &mut ResourceReport) -> Result<()> {
+    if cpu.quota.unwrap_or(0) != 0 || cpu.period.unwrap_or(0) != 0 {
+        if caps.cfs_bandwidth {
+            validate_cfs_bandwidth(cpu)?;
+        } else {
+            report.skip("cpu.quota", NO_CFS_BANDWIDTH)?;
+        }
+    }
+
+    let runtime = cpu.realtime_runtime.unwrap_or(0);
+    let period = cpu.realtime_period.unwrap_or(0);
+    if runtime == 0 && period == 0 {
+        return Ok(());
+    }
+
+    if !caps.rt_group_sched {
+        let reason = if caps.v2 {
+            "not supported by cgroup v2"
+        } else {
+            "kernel has no RT group scheduling"
+        };
+        return report.skip("cpu.realtime", reason);
+    }
+
+    if runtime > 0 && period > 0 && runtime as u64 > period {
+        return Err(CgroupError::invalid_value(
+            "cpu.realtimeRuntime",
+            format!("{} exceeds cpu.realtimePeriod {}", runtime, period),
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+fn validate_cfs_bandwidth(cpu: &LinuxCpu) -> Result<()> {
+    if let Some(quota) = cpu.quota.filter(|q| *q > 0 && *q < MIN_CFS_QUOTA) {
+        return Err(CgroupError::invalid_value(
+            "cpu.quota",
+            format!("{} below the minimum of {}", quota, MIN_CFS_QUOTA),
+        )
+        .into());
+    }
+
+    if let Some(period) = cpu
+        .period
+        .filter(|p| *p != 0 && !(MIN_CFS_PERIOD..=MAX_CFS_PERIOD).contains(p))
+    {
+        return Err(CgroupError::invalid_value(
+            "cpu.period",
+            format!(
+                "{} out of range [{}, {}]",
+                period, MIN_CFS_PERIOD, MAX_CFS_PERIOD
+            ),
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+fn validate_memory(
+    caps: &Capabilities,
+    memory: &LinuxMemory,
+    report: &mut ResourceReport,
+) -> Result<()> {
+    validate_swappiness(memory.swappiness)?;
+
+    // the missing controller is handled when the resources are set
+    if !caps.memory {
+        return Ok(());
+    }
+
+    let limit = memory.limit.unwrap_or(0);
+    let swap = memory.swap.unwrap_or(0);
+    // an unlimited memory makes the swap unlimited on cgroup v1
+    if swap <= 0 || (!caps.v2 && limit == -1) {
+        return Ok(());
+    }
+
+    if !caps.swap_accounting {
+        return report.skip("memory.swap", NO_SWAP_ACCOUNTING);
+    }
+
+    if caps.v2 {
+        convert_memory_swap_to_v2_value(swap, limit)?;
+    } else if limit > 0 && swap < limit {
+        return Err(CgroupError::invalid_value(
+            "memory.swap",
+            "memory+swap limit should be >= memory limit".to_string(),
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+fn validate_hugepages(
+    caps: &Capabilities,
+    limits: &[LinuxHugepageLimit],
+    report: &mut ResourceReport,
+) -> Result<()> {
+    let sizes = match caps.hugepage_sizes.as_ref() {
+        Some(s) => s,
+        None => return report.skip("hugepageLimits", "hugetlb controller not available"),
+    };
+
+    for l in limits {
+        if !sizes.contains(&l.page_size) {
+            return Err(CgroupError::KernelUnsupported {
+                feature: format!("hugepage size {:?}, the guest has {:?}", l.page_size, sizes),
+            }
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_pids(caps: &Capabilities, pids: &LinuxPids) -> Result<()> {
+    // 0 and -1 are unlimited
+    if pids.limit < -1 {
+        return Err(CgroupError::invalid_value(
+            "pids.limit",
+            format!("{} is negative", pids.limit),
+        )
+        .into());
+    }
+
+    if pids.limit > 0 && !caps.pids {
+        return Err(CgroupError::controller_missing("pids").into());
+    }
+
+    Ok(())
+}
+
+fn validate_block_io(
+    caps: &Capabilities,
+    blkio: &LinuxBlockIo,
+    report: &mut ResourceReport,
+) -> Result<()> {
+    let weights = [
+        ("blockIO.weight", blkio.weight),
+        ("blockIO.leafWeight", blkio.leaf_weight),
+    ];
+    let device_weights = blkio.weight_device.iter().flat_map(|d| {
+        vec![
+            ("blockIO.weightDevice.weight", d.weight),
+            ("blockIO.weightDevice.leafWeight", d.leaf_weight),
+        ]
+    });
+    for (field, weight) in weights.iter().cloned().chain(device_weights) {
+        if let Some(w) = weight.filter(|w| *w != 0) {
+            if !(MIN_BLKIO_WEIGHT..=MAX_BLKIO_WEIGHT).contains(&w) {
+                return Err(CgroupError::invalid_value(
+                    field,
+                    format!(
+                        "{} out of range [{}, {}]",
+                        w, MIN_BLKIO_WEIGHT, MAX_BLKIO_WEIGHT
+                    ),
+                )
+                .into());
+            }
+        }
+    }
+
+    let throttled = !blkio.throttle_read_bps_device.is_empty()
+        || !blkio.throttle_write_bps_device.is_empty()
+        || !blkio.throttle_read_iops_device.is_empty()
+        || !blkio.throttle_write_iops_device.is_empty();
+    let weighted = blkio.weight.unwrap_or(0) != 0 || !blkio.weight_device.is_empty();
+    if (throttled || weighted) && !caps.blkio {
+        let controller = if caps.v2 { "io" } else { "blkio" };
+        return Err(CgroupError::controller_missing(controller).into());
+    }
+
+    if caps.v2 && blkio.leaf_weight.is_some() {
+        report.skip("blockIO.leafWeight", "not supported by cgroup v2")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci::{LinuxBlockIoDevice, LinuxRdma, LinuxThrottleDevice, LinuxWeightDevice};
+
+    fn touch(dir: &Path, files: &[&str]) {
+        for f in files {
+            fs::write(dir.join(f), "").unwrap();
+        }
+    }
+
+    #[test]
+    fn test_probe_v1() {
+        let dir = tempfile::tempdir().unwrap();
+        let (memory, cpu, hugetlb) = (
+            dir.path().join("memory"),
+            dir.path().join("cpu"),
+            dir.path().join("hugetlb"),
+        );
+        for d in &[&memory, &cpu, &hugetlb] {
+            fs::create_dir(d).unwrap();
+        }
+        touch(&memory, &[MEMORY_MEMSW_LIMIT_IN_BYTES]);
+        touch(&cpu, &[CPU_CFS_QUOTA_US]);
+        touch(
+            &hugetlb,
+            &[
+                "hugetlb.2MB.limit_in_bytes",
+                "hugetlb.2MB.rsvd.limit_in_bytes",
+                "hugetlb.1GB.limit_in_bytes",
+                "hugetlb.1GB.usage_in_bytes",
+            ],
+        );
+
+        let caps = probe_v1(|c| Some(dir.path().join(c)));
+        assert_eq!(
+            caps,
+            Capabilities {
+                v2: false,
+                memory: true,
+                swap_accounting: true,
+                cfs_bandwidth: true,
+                rt_group_sched: false,
+                hugepage_sizes: Some(vec!["1GB".to_string(), "2MB".to_string()]),
+                pids: false,
+                blkio: false,
+            }
+        );
+
+        assert_eq!(probe_v1(|_| None), Capabilities::default());
+    }
+
+    #[test]
+    fn test_probe_v2() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("cgroup.controllers"), "cpu memory\n").unwrap();
+        touch(dir.path(), &["hugetlb.2MB.max"]);
+
+        // hugetlb isn't enabled
+        let caps = probe_v2(dir.path());
+        assert!(caps.v2 && caps.memory);
+        assert!(!caps.swap_accounting && !caps.cfs_bandwidth);
+        assert_eq!(caps.hugepage_sizes, None);
+
+        fs::write(dir.path().join("cgroup.controllers"), "memory hugetlb\n").unwrap();
+        touch(dir.path(), &[MEMORY_SWAP_MAX, CPU_MAX]);
+        let caps = probe_v2(dir.path());
+        assert!(caps.swap_accounting && caps.cfs_bandwidth);
+        assert_eq!(caps.hugepage_sizes, Some(vec!["2MB".to_string()]));
+    }
+
+    #[test]
+    fn test_validate() {
+        let caps = Capabilities {
+            v2: false,
+            memory: true,
+            swap_accounting: true,
+            cfs_bandwidth: true,
+            rt_group_sched: true,
+            hugepage_sizes: Some(vec!["2MB".to_string()]),
+            pids: true,
+            blkio: true,
+        };
+        let r = LinuxResources {
+            cpu: Some(LinuxCpu {
+                quota: Some(50000),
+                period: Some(100000),
+                realtime_runtime: Some(950000),
+                realtime_period: Some(1000000),
+                ..Default::default()
+            }),
+            memory: Some(LinuxMemory {
+                limit: Some(1 << 30),
+                swap: Some(2 << 30),
+                swappiness: Some(60),
+                ..Default::default()
+            }),
+            hugepage_limits: vec![LinuxHugepageLimit {
+                page_size: "2MB".to_string(),
+                limit: 1 << 21,
+            }],
+            ..Default::default()
+        };
+
+        let mut report = ResourceReport::new(true);
+        validate(&caps, &r, &mut report).unwrap();
+        assert!(report.unapplied.is_empty());
+
+        // what the guest lacks is skipped
+        let lacking = Capabilities {
+            cfs_bandwidth: false,
+            rt_group_sched: false,
+            hugepage_sizes: None,
+            ..caps.clone()
+        };
+        let mut report = ResourceReport::new(false);
+        validate(&lacking, &r, &mut report).unwrap();
+        assert_eq!(
+            report.unapplied,
+            vec![
+                "cpu.quota: kernel has no CFS bandwidth control",
+                "cpu.realtime: kernel has no RT group scheduling",
+                "hugepageLimits: hugetlb controller not available",
+            ]
+        );
+        let err = validate(&lacking, &r, &mut ResourceReport::new(true)).unwrap_err();
+        assert!(
+            matches!(
+                CgroupError::find(&err),
+                Some(CgroupError::KernelUnsupported { .. })
+            ),
+            "{:?}",
+            err
+        );
+
+        // the swap limit included
+        let no_swap = Capabilities {
+            swap_accounting: false,
+            ..caps.clone()
+        };
+        let mut report = ResourceReport::new(false);
+        validate(&no_swap, &r, &mut report).unwrap();
+        assert_eq!(
+            report.unapplied,
+            vec!["memory.swap: kernel has no swap accounting"]
+        );
+        assert!(validate(&no_swap, &r, &mut ResourceReport::new(true)).is_err());
+
+        // the values the cgroup can't take fail even when not strict, as
+        // setting them would
+        let no_1gb = LinuxResources {
+            hugepage_limits: vec![LinuxHugepageLimit {
+                page_size: "1GB".to_string(),
+                limit: 1 << 30,
+            }],
+            ..Default::default()
+        };
+        assert!(validate(&caps, &no_1gb, &mut ResourceReport::new(false)).is_err());
+
+        for (cpu, memory) in &[
+            (
+                LinuxCpu {
+                    quota: Some(500),
+                    ..Default::default()
+                },
+                LinuxMemory::default(),
+            ),
+            (
+                LinuxCpu {
+                    period: Some(2000000),
+                    ..Default::default()
+                },
+                LinuxMemory::default(),
+            ),
+            (
+                LinuxCpu {
+                    realtime_runtime: Some(2000),
+                    realtime_period: Some(1000),
+                    ..Default::default()
+                },
+                LinuxMemory::default(),
+            ),
+            (
+                LinuxCpu::default(),
+                LinuxMemory {
+                    swappiness: Some(101),
+                    ..Default::default()
+                },
+            ),
+            (
+                LinuxCpu::default(),
+                LinuxMemory {
+                    limit: Some(2 << 30),
+                    swap: Some(1 << 30),
+                    ..Default::default()
+                },
+            ),
+        ] {
+            let r = LinuxResources {
+                cpu: Some(cpu.clone()),
+                memory: Some(memory.clone()),
+                ..Default::default()
+            };
+            let err = validate(&caps, &r, &mut ResourceReport::new(false)).unwrap_err();
+            assert!(
+                matches!(
+                    CgroupError::find(&err),
+                    Some(CgroupError::InvalidValue { .. })
+                ),
+                "{:?}: {:?}",
+                r,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_pids_block_io_rdma() {
+        let caps = Capabilities {
+            pids: true,
+            blkio: true,
+            ..Default::default()
+        };
+        let r = LinuxResources {
+            pids: Some(LinuxPids { limit: 100 }),
+            block_io: Some(LinuxBlockIo {
+                weight: Some(500),
+                throttle_read_bps_device: vec![LinuxThrottleDevice {
+                    blk: LinuxBlockIoDevice { major: 8, minor: 0 },
+                    rate: 1 << 20,
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut report = ResourceReport::new(true);
+        validate(&caps, &r, &mut report).unwrap();
+        assert!(report.unapplied.is_empty());
+
+        // the controllers are missing
+        for (caps, controller) in &[
+            (
+                Capabilities {
+                    blkio: true,
+                    ..Default::default()
+                },
+                "pids",
+            ),
+            (
+                Capabilities {
+                    pids: true,
+                    ..Default::default()
+                },
+                "blkio",
+            ),
+        ] {
+            let err = validate(caps, &r, &mut ResourceReport::new(false)).unwrap_err();
+            assert!(
+                matches!(CgroupError::find(&err), Some(CgroupError::ControllerMissing { name }) if name == controller),
+                "{:?}",
+                err
+            );
+        }
+
+        for r in &[
+            LinuxResources {
+                pids: Some(LinuxPids { limit: -2 }),
+                ..Default::default()
+            },
+            LinuxResources {
+                block_io: Some(LinuxBlockIo {
+                    weight: Some(5),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            LinuxResources {
+                block_io: Some(LinuxBlockIo {
+                    weight_device: vec![LinuxWeightDevice {
+                        weight: Some(2000),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        ] {
+            let err = validate(&caps, r, &mut ResourceReport::new(false)).unwrap_err();
+            assert!(
+                matches!(
+                    CgroupError::find(&err),
+                    Some(CgroupError::InvalidValue { .. })
+                ),
+                "{:?}: {:?}",
+                r,
+                err
+            );
+        }
+
+        let mut r = LinuxResources::default();
+        r.rdma.insert("mlx5_0".to_string(), LinuxRdma::default());
+        let mut report = ResourceReport::new(false);
+        validate(&caps, &r, &mut report).unwrap();
+        assert_eq!(report.unapplied, vec!["rdma: not supported by the agent"]);
+        assert!(validate(&caps, &r, &mut ResourceReport::new(true)).is_err());
+    }
+
+    #[test]
+    fn test_validate_v2() {
+        let caps = Capabilities {
+            v2: true,
+            memory: true,
+            swap_accounting: true,
+            ..Default::default()
+        };
+
+        // cgroup v2 needs a memory limit for the swap one
+        let r = LinuxResources {
+            memory: Some(LinuxMemory {
+                swap: Some(1 << 30),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(validate(&caps, &r, &mut ResourceReport::new(false)).is_err());
+
+        let r = LinuxResources {
+            cpu: Some(LinuxCpu {
+                realtime_runtime: Some(1000),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut report = ResourceReport::new(false);
+        validate(&caps, &r, &mut report).unwrap();
+        assert_eq!(
+            report.unapplied,
+            vec!["cpu.realtime: not supported by cgroup v2"]
+        );
+    }
+}
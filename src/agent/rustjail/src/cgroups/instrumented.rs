@@ -0,0 +1,204 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Instrumented wraps the cgroup manager of a container to tell an observer,
+// e.g. the metrics of the agent, of the apply, set, destroy and stats
+// operations: how long they took and the kind of error they failed with,
+// whatever the backend. The other operations are passed through.
+
+use anyhow::Result;
+use cgroups::freezer::FreezerState;
+use oci::{LinuxDeviceCgroup, LinuxResources};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use crate::cgroups::stats::CgroupStats;
+use crate::cgroups::{CgroupError, DeviceRules, FsManagerExt, Manager, MemoryUsage};
+
+pub const OP_APPLY: &str = "apply";
+pub const OP_SET: &str = "set";
+pub const OP_DESTROY: &str = "destroy";
+pub const OP_STATS: &str = "stats";
+
+// the error kind of the failures which aren't a CgroupError
+pub const ERROR_OTHER: &str = "other";
+
+// Observer is told of each operation, with its duration and the kind of
+// its error if it failed.
+pub type Observer = fn(op: &str, duration: Duration, error: Option<&str>);
+
+lazy_static! {
+    static ref OBSERVER: RwLock<Option<Observer>> = RwLock::new(None);
+}
+
+// set_observer sets the observer of the operations of all the managers.
+pub fn set_observer(observer: Observer) {
+    *OBSERVER.write().unwrap() = Some(observer);
+}
+
+// error_kind returns the kind of a cgroup operation error.
+pub fn error_kind(e: &anyhow::Error) -> &'static str {
+    CgroupError::find(e).map_or(ERROR_OTHER, |c| c.kind())
+}
+
+fn observe<T>(op: &str, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let start = Instant::now();
+    let result = f();
+
+    let observer = *OBSERVER.read().unwrap();
+    if let Some(observer) = observer {
+        observer(op, start.elapsed(), result.as_ref().err().map(error_kind));
+    }
+
+    result
+}
+
+#[derive(Debug)]
+pub struct Instrumented {
+    inner: Box<dyn Manager>,
+}
+
+impl Instrumented {
+    pub fn new(inner: Box<dyn Manager>) -> Self {
+        Instrumented { inner }
+    }
+}
+
+impl Manager for Instrumented {
+    fn apply(&self, pid: i32) -> Result<()> {
+        observe(OP_APPLY, || self.inner.apply(pid))
+    }
+
+    fn get_procs(&self) -> Result<Vec<i32>> {
+        self.inner.get_procs()
+    }
+
+    fn get_threads(&self) -> Result<Vec<i32>> {
+        self.inner.get_threads()
+    }
+
+    fn get_stats(&self) -> Result<CgroupStats> {
+        observe(OP_STATS, || self.inner.get_stats())
+    }
+
+    fn get_memory_usage(&self) -> Result<MemoryUsage> {
+        self.inner.get_memory_usage()
+    }
+
+    fn freeze(&self, state: FreezerState) -> Result<()> {
+        self.inner.freeze(state)
+    }
+
+    fn state(&self) -> Result<FreezerState> {
+        self.inner.state()
+    }
+
+    fn destroy(&mut self) -> Result<()> {
+        observe(OP_DESTROY, || self.inner.destroy())
+    }
+
+    fn set(&self, container: &LinuxResources, update: bool) -> Result<()> {
+        observe(OP_SET, || self.inner.set(container, update))
+    }
+
+    fn apply_exec(&self, pid: i32) -> Result<()> {
+        self.inner.apply_exec(pid)
+    }
+
+    fn get_exec_stats(&self) -> Option<CgroupStats> {
+        self.inner.get_exec_stats()
+    }
+
+    fn unapplied_resources(&self) -> Vec<String> {
+        self.inner.unapplied_resources()
+    }
+
+    fn validate(&self, r: &LinuxResources, update: bool) -> Result<Vec<String>> {
+        self.inner.validate(r, update)
+    }
+
+    fn add_device_rule(&self, rule: &LinuxDeviceCgroup) -> Result<()> {
+        self.inner.add_device_rule(rule)
+    }
+
+    fn remove_device_rule(&self, rule: &LinuxDeviceCgroup) -> Result<()> {
+        self.inner.remove_device_rule(rule)
+    }
+
+    fn list_device_rules(&self) -> Result<DeviceRules> {
+        self.inner.list_device_rules()
+    }
+
+    fn update_cpuset_path(&self, guest_cpuset: &str, container_cpuset: &str) -> Result<()> {
+        self.inner
+            .update_cpuset_path(guest_cpuset, container_cpuset)
+    }
+
+    fn get_cg_path(&self, cg: &str) -> Option<String> {
+        self.inner.get_cg_path(cg)
+    }
+
+    fn get_guest_cpuset(&self) -> Result<String> {
+        self.inner.get_guest_cpuset()
+    }
+
+    fn as_fs(&self) -> Option<&dyn FsManagerExt> {
+        self.inner.as_fs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    lazy_static! {
+        static ref OBSERVED: Mutex<Vec<(String, Option<String>)>> = Mutex::new(vec![]);
+    }
+
+    fn record(op: &str, _: Duration, error: Option<&str>) {
+        OBSERVED
+            .lock()
+            .unwrap()
+            .push((op.to_string(), error.map(|e| e.to_string())));
+    }
+
+    // Failing implements none of the operations
+    #[derive(Debug)]
+    struct Failing;
+
+    impl Manager for Failing {}
+
+    #[test]
+    fn test_instrumented() {
+        set_observer(record);
+
+        let mut m = Instrumented::new(Box::new(Failing));
+        assert!(m.apply(1).is_err());
+        assert!(m.set(&LinuxResources::default(), false).is_err());
+        assert!(m.destroy().is_err());
+        assert!(m.get_procs().is_err());
+
+        // the managers of the other tests may be observed meanwhile
+        let observed: Vec<_> = OBSERVED
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, e)| e.is_some())
+            .cloned()
+            .collect();
+        let not_supported = Some(CgroupError::NotSupported.kind().to_string());
+        assert_eq!(
+            observed,
+            vec![
+                (OP_APPLY.to_string(), not_supported.clone()),
+                (OP_SET.to_string(), not_supported.clone()),
+                (OP_DESTROY.to_string(), not_supported),
+            ]
+        );
+
+        assert_eq!(error_kind(&anyhow::anyhow!("failed")), ERROR_OTHER);
+    }
+}
@@ -3,15 +3,19 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use protobuf::{CachedSize, SingularPtrField, UnknownFields};
-
-use crate::cgroups::Manager as CgroupManager;
-use crate::protocols::agent::{BlkioStats, CgroupStats, CpuStats, MemoryStats, PidsStats};
+use crate::cgroups::fs::psi::Pressure;
+use crate::cgroups::stats::{
+    BlkioStats, CgroupStats, CpuStats, HugetlbStats, MemoryStats, PidsStats,
+};
+use crate::cgroups::{
+    DeviceMap, FsManagerExt, Manager as CgroupManager, MemoryUsage, Sample, Sampler,
+};
 use anyhow::Result;
 use cgroups::freezer::FreezerState;
 use libc::{self, pid_t};
 use oci::LinuxResources;
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::string::String;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -32,27 +36,139 @@ impl CgroupManager for Manager {
 
     fn get_stats(&self) -> Result<CgroupStats> {
         Ok(CgroupStats {
-            cpu_stats: SingularPtrField::some(CpuStats::default()),
-            memory_stats: SingularPtrField::some(MemoryStats::new()),
-            pids_stats: SingularPtrField::some(PidsStats::new()),
-            blkio_stats: SingularPtrField::some(BlkioStats::new()),
+            cpu_stats: Some(CpuStats::default()),
+            memory_stats: Some(MemoryStats::default()),
+            pids_stats: Some(PidsStats::default()),
+            blkio_stats: Some(BlkioStats::default()),
             hugetlb_stats: HashMap::new(),
-            unknown_fields: UnknownFields::default(),
-            cached_size: CachedSize::default(),
+            misc_stats: HashMap::new(),
         })
     }
 
+    fn get_memory_usage(&self) -> Result<MemoryUsage> {
+        Ok(MemoryUsage::default())
+    }
+
     fn freeze(&self, _: FreezerState) -> Result<()> {
         Ok(())
     }
 
+    fn state(&self) -> Result<FreezerState> {
+        Ok(FreezerState::Thawed)
+    }
+
     fn destroy(&mut self) -> Result<()> {
         Ok(())
     }
 
-    fn get_pids(&self) -> Result<Vec<pid_t>> {
+    fn get_procs(&self) -> Result<Vec<pid_t>> {
         Ok(Vec::new())
     }
+
+    fn get_threads(&self) -> Result<Vec<pid_t>> {
+        Ok(Vec::new())
+    }
+
+    fn apply_exec(&self, _: pid_t) -> Result<()> {
+        Ok(())
+    }
+
+    fn update_cpuset_path(&self, _: &str, _: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_cg_path(&self, _: &str) -> Option<String> {
+        Some("".to_string())
+    }
+
+    fn as_fs(&self) -> Option<&dyn FsManagerExt> {
+        Some(self)
+    }
+}
+
+impl FsManagerExt for Manager {
+    fn cgroup_dirs(&self) -> Vec<PathBuf> {
+        vec![]
+    }
+
+    fn get_paths(&self) -> HashMap<String, String> {
+        self.paths.clone()
+    }
+
+    fn get_mounts(&self) -> HashMap<String, String> {
+        self.mounts.clone()
+    }
+
+    fn save(&self, _: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn add_notifier(&self, _: &str) {}
+
+    fn get_hugetlb_stats(&self) -> Result<HashMap<String, HugetlbStats>> {
+        Ok(HashMap::new())
+    }
+
+    fn get_pressure(&self) -> Vec<(&'static str, Pressure)> {
+        vec![]
+    }
+
+    fn sampler(&self) -> Sampler {
+        Box::new(Sample::default)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ManagerBuilder {
+    cpath: String,
+}
+
+impl ManagerBuilder {
+    pub fn new(cpath: &str) -> Self {
+        ManagerBuilder {
+            cpath: cpath.to_string(),
+        }
+    }
+
+    pub fn cgroup_root(self, _: &str) -> Self {
+        self
+    }
+
+    pub fn disable_kmem_accounting(self, _: bool) -> Self {
+        self
+    }
+
+    pub fn strict_resources(self, _: bool) -> Self {
+        self
+    }
+
+    pub fn precreated(self, _: bool) -> Self {
+        self
+    }
+
+    pub fn oom_group(self, _: bool) -> Self {
+        self
+    }
+
+    pub fn io_cost(self, _: bool) -> Self {
+        self
+    }
+
+    pub fn default_swappiness(self, _: Option<u64>) -> Self {
+        self
+    }
+
+    pub fn device_map(self, _: DeviceMap) -> Self {
+        self
+    }
+
+    pub fn rootless(self, _: bool) -> Self {
+        self
+    }
+
+    pub fn build(self) -> Result<Manager> {
+        Manager::new(&self.cpath)
+    }
 }
 
 impl Manager {
@@ -63,12 +179,4 @@ impl Manager {
             cpath: cpath.to_string(),
         })
     }
-
-    pub fn update_cpuset_path(&self, _: &str, _: &str) -> Result<()> {
-        Ok(())
-    }
-
-    pub fn get_cg_path(&self, _: &str) -> Option<String> {
-        Some("".to_string())
-    }
 }
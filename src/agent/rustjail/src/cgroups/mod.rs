@@ -3,39 +3,414 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use anyhow::{anyhow, Result};
-use oci::LinuxResources;
-use protocols::agent::CgroupStats;
+use anyhow::Result;
+use oci::{LinuxDeviceCgroup, LinuxResources};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
 
 use cgroups::freezer::FreezerState;
 
+use fs::psi::Pressure;
+use stats::{CgroupStats, HugetlbStats};
+
 pub mod fs;
+pub mod instrumented;
 pub mod mock;
 pub mod notifier;
+pub mod resctrl;
+pub mod sandbox;
+pub mod stats;
 pub mod systemd;
 
-pub trait Manager {
+// DeviceMap maps the (major, minor) of the host block devices to the ones
+// they have in the guest, the blkio limits of the OCI spec referring to the
+// host devices.
+pub type DeviceMap = HashMap<(i64, i64), (i64, i64)>;
+
+// DEFAULT_CGROUP_ROOT is the cgroup the container cgroups are created below,
+// apart from the ones of the guest services.
+pub const DEFAULT_CGROUP_ROOT: &str = "/kata";
+
+// clean_cgroup_path returns `path` as an absolute path made of its normal
+// components, "/" when it has none. The ".." components are refused, so
+// that a path can't lead out of the cgroup it is taken relative to.
+pub fn clean_cgroup_path(path: &str) -> Result<String> {
+    let mut components = vec![];
+
+    for c in path.split('/') {
+        match c {
+            "" | "." => {}
+            ".." => {
+                return Err(CgroupError::invalid_value(
+                    "cgroupsPath",
+                    format!("{:?} leads out of its root", path),
+                )
+                .into())
+            }
+            c => components.push(c),
+        }
+    }
+
+    Ok(format!("/{}", components.join("/")))
+}
+
+// cgroup_path returns the path of the cgroup `cpath` below the cgroup
+// `root`, `cpath` being taken relative to `root` even when absolute, so
+// that a spec can't reach the cgroups outside of it. The root itself is
+// refused.
+pub fn cgroup_path(root: &str, cpath: &str) -> Result<String> {
+    let root = clean_cgroup_path(root)?;
+    let cpath = clean_cgroup_path(cpath)?;
+
+    if cpath == "/" {
+        return Err(CgroupError::invalid_value(
+            "cgroupsPath",
+            format!("the cgroup {} itself can't be used", root),
+        )
+        .into());
+    }
+
+    if root == "/" {
+        return Ok(cpath);
+    }
+
+    Ok(format!("{}{}", root, cpath))
+}
+
+// MemoryUsage is the memory usage of a cgroup in bytes, cheaper to read than
+// the whole stats for the frequent polls.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MemoryUsage {
+    pub usage: u64,
+    // u64::MAX when unlimited, whatever the cgroup version
+    pub limit: u64,
+    pub swap_usage: u64,
+}
+
+// Sample is the pressure and the hugetlb usage of a cgroup, which the
+// metrics sample periodically.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Sample {
+    pub pressure: Vec<(&'static str, Pressure)>,
+    pub hugetlb: HashMap<String, HugetlbStats>,
+}
+
+// Sampler reads a Sample of a cgroup without borrowing its manager, so
+// that the container can be left meanwhile.
+pub type Sampler = Box<dyn FnOnce() -> Sample + Send>;
+
+// DeviceRules are the device rules in effect for a container, and whether
+// they were checked against what the kernel enforces.
+#[derive(Debug, Default, PartialEq)]
+pub struct DeviceRules {
+    pub rules: Vec<LinuxDeviceCgroup>,
+    pub verified: bool,
+}
+
+// CgroupError is the error of the cgroup operations which the callers
+// need to tell apart, e.g. to map them to the ttrpc status codes, carried
+// by the anyhow errors of the managers and downcast from them.
+#[derive(Debug)]
+pub enum CgroupError {
+    // the operation isn't implemented by the cgroup manager
+    NotSupported,
+    // the controller isn't available in the guest
+    ControllerMissing { name: String },
+    // a resource of the spec has a value the cgroup can't take
+    InvalidValue { field: String, reason: String },
+    // the guest kernel, or the cgroup version, lacks what a resource needs
+    KernelUnsupported { feature: String },
+    // the operation is refused while the cgroup is frozen, as it would
+    // block or take effect only once thawed, e.g. a process joining the
+    // cgroup stops right away
+    Frozen { cgroup: String },
+    // an interface file of the cgroup couldn't be accessed
+    Io { path: PathBuf, source: io::Error },
+}
+
+impl CgroupError {
+    pub fn controller_missing(name: &str) -> Self {
+        CgroupError::ControllerMissing {
+            name: name.to_string(),
+        }
+    }
+
+    // kernel_unsupported is the error of a resource which can't be set in
+    // the guest, and why.
+    pub fn kernel_unsupported(resource: &str, reason: &str) -> Self {
+        CgroupError::KernelUnsupported {
+            feature: format!("{}: {}", resource, reason),
+        }
+    }
+
+    pub fn invalid_value(field: &str, reason: String) -> Self {
+        CgroupError::InvalidValue {
+            field: field.to_string(),
+            reason,
+        }
+    }
+
+    pub fn io(path: &Path, source: io::Error) -> Self {
+        CgroupError::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    // kind names the variant, e.g. to label the metrics of the failures.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CgroupError::NotSupported => "not_supported",
+            CgroupError::ControllerMissing { .. } => "controller_missing",
+            CgroupError::InvalidValue { .. } => "invalid_value",
+            CgroupError::KernelUnsupported { .. } => "kernel_unsupported",
+            CgroupError::Frozen { .. } => "frozen",
+            CgroupError::Io { .. } => "io",
+        }
+    }
+
+    // find returns the CgroupError an error was made of, if any, whatever
+    // the context added since.
+    pub fn find(e: &anyhow::Error) -> Option<&CgroupError> {
+        e.chain().find_map(|c| c.downcast_ref::<CgroupError>())
+    }
+}
+
+impl Error for CgroupError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CgroupError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CgroupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CgroupError::NotSupported => write!(f, "not supported!"),
+            CgroupError::ControllerMissing { name } => {
+                write!(f, "cgroup controller {} is not available", name)
+            }
+            CgroupError::InvalidValue { field, reason } => {
+                write!(f, "invalid {}: {}", field, reason)
+            }
+            CgroupError::KernelUnsupported { feature } => {
+                write!(f, "unsupported resource {}", feature)
+            }
+            CgroupError::Frozen { cgroup } => write!(f, "cgroup {} is frozen", cgroup),
+            CgroupError::Io { path, source } => write!(f, "{:?}: {}", path, source),
+        }
+    }
+}
+
+// Manager is a cgroup backend, which the containers hold as a trait
+// object so that the backend can be chosen at runtime. The operations a
+// backend doesn't implement fail with CgroupError::NotSupported, or return
+// nothing for the optional ones.
+pub trait Manager: fmt::Debug + Send + Sync {
     fn apply(&self, _pid: i32) -> Result<()> {
-        Err(anyhow!("not supported!".to_string()))
+        Err(CgroupError::NotSupported.into())
+    }
+
+    // get_procs returns the pids of the processes of the cgroup
+    fn get_procs(&self) -> Result<Vec<i32>> {
+        Err(CgroupError::NotSupported.into())
     }
 
-    fn get_pids(&self) -> Result<Vec<i32>> {
-        Err(anyhow!("not supported!"))
+    // get_threads returns the ids of the threads of the cgroup
+    fn get_threads(&self) -> Result<Vec<i32>> {
+        Err(CgroupError::NotSupported.into())
     }
 
     fn get_stats(&self) -> Result<CgroupStats> {
-        Err(anyhow!("not supported!"))
+        Err(CgroupError::NotSupported.into())
+    }
+
+    fn get_memory_usage(&self) -> Result<MemoryUsage> {
+        Err(CgroupError::NotSupported.into())
     }
 
     fn freeze(&self, _state: FreezerState) -> Result<()> {
-        Err(anyhow!("not supported!"))
+        Err(CgroupError::NotSupported.into())
+    }
+
+    // state returns the freezer state of the cgroup, Freezing until all
+    // its processes are stopped
+    fn state(&self) -> Result<FreezerState> {
+        Err(CgroupError::NotSupported.into())
     }
 
     fn destroy(&mut self) -> Result<()> {
-        Err(anyhow!("not supported!"))
+        Err(CgroupError::NotSupported.into())
     }
 
     fn set(&self, _container: &LinuxResources, _update: bool) -> Result<()> {
-        Err(anyhow!("not supported!"))
+        Err(CgroupError::NotSupported.into())
+    }
+
+    // apply_exec adds an exec'd process to the cgroup
+    fn apply_exec(&self, _pid: i32) -> Result<()> {
+        Err(CgroupError::NotSupported.into())
+    }
+
+    // get_exec_stats returns the stats of the exec'd processes, if they
+    // are accounted apart
+    fn get_exec_stats(&self) -> Option<CgroupStats> {
+        None
+    }
+
+    // unapplied_resources returns the resource settings the last create or
+    // update skipped
+    fn unapplied_resources(&self) -> Vec<String> {
+        vec![]
+    }
+
+    // validate returns the resource settings which would be skipped,
+    // without applying anything
+    fn validate(&self, _r: &LinuxResources, _update: bool) -> Result<Vec<String>> {
+        Err(CgroupError::NotSupported.into())
+    }
+
+    fn add_device_rule(&self, _rule: &LinuxDeviceCgroup) -> Result<()> {
+        Err(CgroupError::NotSupported.into())
+    }
+
+    fn remove_device_rule(&self, _rule: &LinuxDeviceCgroup) -> Result<()> {
+        Err(CgroupError::NotSupported.into())
+    }
+
+    fn list_device_rules(&self) -> Result<DeviceRules> {
+        Err(CgroupError::NotSupported.into())
+    }
+
+    // update_cpuset_path widens the cpuset of the cgroup and of its
+    // ancestors to the guest cpus
+    fn update_cpuset_path(&self, _guest_cpuset: &str, _container_cpuset: &str) -> Result<()> {
+        Err(CgroupError::NotSupported.into())
+    }
+
+    // get_cg_path returns the directory of the cgroup in the hierarchy of
+    // a controller
+    fn get_cg_path(&self, _cg: &str) -> Option<String> {
+        None
+    }
+
+    // get_guest_cpuset returns the online cpus of the guest
+    fn get_guest_cpuset(&self) -> Result<String> {
+        Err(CgroupError::NotSupported.into())
+    }
+
+    // as_fs returns the operations of the backends writing the cgroup
+    // filesystem directly
+    fn as_fs(&self) -> Option<&dyn FsManagerExt> {
+        None
+    }
+}
+
+// FsManagerExt is implemented by the backends writing the cgroup filesystem
+// directly, on top of Manager, for the operations which need its layout.
+pub trait FsManagerExt: Manager {
+    // cgroup_dirs returns the directories of the cgroup
+    fn cgroup_dirs(&self) -> Vec<PathBuf>;
+
+    // get_paths returns the directories of the cgroup by controller, and
+    // get_mounts the mount points of the controllers, for the cgroup
+    // filesystem of the container
+    fn get_paths(&self) -> HashMap<String, String>;
+
+    fn get_mounts(&self) -> HashMap<String, String>;
+
+    // save persists the state of the manager of a container
+    fn save(&self, cid: &str) -> Result<()>;
+
+    // add_notifier records a cgroup event watched for the container
+    fn add_notifier(&self, name: &str);
+
+    fn get_hugetlb_stats(&self) -> Result<HashMap<String, HugetlbStats>>;
+
+    fn get_pressure(&self) -> Vec<(&'static str, Pressure)>;
+
+    // sampler returns a reader of the pressure and the hugetlb usage of
+    // the cgroup
+    fn sampler(&self) -> Sampler;
+}
+
+// Driver is the backend the cgroups of the containers are managed with,
+// as chosen by the use_systemd_cgroup of the create options.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Driver {
+    Fs,
+    Systemd,
+}
+
+impl Driver {
+    pub fn of(use_systemd_cgroup: bool) -> Self {
+        if use_systemd_cgroup {
+            Driver::Systemd
+        } else {
+            Driver::Fs
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cgroup_path() {
+        let tests = &[
+            ("/kata", "/c1", Some("/kata/c1")),
+            ("/kata", "c1", Some("/kata/c1")),
+            ("/kata/", "//sb1/./c1/", Some("/kata/sb1/c1")),
+            ("/", "/c1", Some("/c1")),
+            ("", "/system.slice/c1", Some("/system.slice/c1")),
+            ("/kata", "/", None),
+            ("/kata", "", None),
+            ("/kata", "/../c1", None),
+            ("/kata", "/c1/../../c2", None),
+            ("/kata/..", "/c1", None),
+        ];
+
+        for (root, cpath, expected) in tests {
+            let result = cgroup_path(root, cpath);
+            let msg = format!("root: {:?}, cpath: {:?}, result: {:?}", root, cpath, result);
+            assert_eq!(
+                result.as_ref().ok().map(|p| p.as_str()),
+                *expected,
+                "{}",
+                msg
+            );
+
+            if let Err(e) = result {
+                assert!(
+                    matches!(
+                        CgroupError::find(&e),
+                        Some(CgroupError::InvalidValue { .. })
+                    ),
+                    "{}",
+                    msg
+                );
+            }
+        }
+
+        assert_eq!(clean_cgroup_path("").unwrap(), "/");
+        assert_eq!(clean_cgroup_path("/kata//pods/").unwrap(), "/kata/pods");
+    }
+
+    #[test]
+    fn test_driver() {
+        assert_eq!(Driver::of(false), Driver::Fs);
+        assert_eq!(Driver::of(true), Driver::Systemd);
+
+        let err = systemd::Manager::new("/c1").unwrap_err();
+        assert!(matches!(
+            CgroupError::find(&err),
+            Some(CgroupError::NotSupported)
+        ));
     }
 }
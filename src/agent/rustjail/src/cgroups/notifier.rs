@@ -3,18 +3,24 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{Context, Result};
 use eventfd::{eventfd, EfdFlags};
 use nix::sys::eventfd;
 use std::fs::{self, File};
 use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+use crate::cgroups::CgroupError;
 use crate::pipestream::PipeStream;
 use futures::StreamExt as _;
 use inotify::{Inotify, WatchMask};
+use std::future::Future;
 use tokio::io::AsyncReadExt;
-use tokio::sync::mpsc::{channel, Receiver};
+use tokio::sync::broadcast::{self, Receiver, Sender};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::instrument;
 
 // Convenience macro to obtain the scope logger
 macro_rules! sl {
@@ -23,13 +29,163 @@ macro_rules! sl {
     };
 }
 
-pub async fn notify_oom(cid: &str, cg_dir: String) -> Result<Receiver<String>> {
+// the cgroup v1 pressure level to be notified of, "low" fires as soon as
+// the kernel starts reclaiming which is too noisy to be useful
+const MEMORY_PRESSURE_LEVEL: &str = "medium";
+
+// the names of the watches, as recorded by the cgroup managers to watch
+// the events again once restored
+pub const WATCH_OOM: &str = "oom";
+pub const WATCH_MEMORY_PRESSURE: &str = "memory_pressure";
+pub const WATCH_PIDS_MAX: &str = "pids_max";
+
+// the events a subscriber can fall behind by, the older ones are dropped
+const EVENT_CHANNEL_SIZE: usize = 100;
+
+// the watches whose task is still running
+static ACTIVE_WATCHES: AtomicUsize = AtomicUsize::new(0);
+
+// active_watches returns the number of watches running, which grows with
+// the containers if their watches leak.
+pub fn active_watches() -> usize {
+    ACTIVE_WATCHES.load(Ordering::Relaxed)
+}
+
+// ActiveWatch counts a watch as running until its task is done or aborted,
+// which drops it.
+struct ActiveWatch;
+
+impl ActiveWatch {
+    fn new() -> Self {
+        ACTIVE_WATCHES.fetch_add(1, Ordering::Relaxed);
+        ActiveWatch
+    }
+}
+
+impl Drop for ActiveWatch {
+    fn drop(&mut self) {
+        ACTIVE_WATCHES.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Watch is a cgroup event watch, whose events, the id of the container, are
+// broadcast to all its subscribers. The watch only reads the events once
+// started, after its subscribers subscribed so that they miss none, the
+// kernel keeping them meanwhile. The watch stops on its own when the
+// cgroup is gone, and is stopped when cancelled or dropped, which releases
+// its eventfd or inotify instance. The subscribers see the end of the
+// events once the watch is dropped.
+#[derive(Debug)]
+pub struct Watch {
+    tx: Sender<String>,
+    handle: JoinHandle<()>,
+    start: Option<oneshot::Sender<()>>,
+}
+
+impl Watch {
+    fn new<F, T>(f: F) -> Self
+    where
+        F: FnOnce(Sender<String>) -> T,
+        T: Future<Output = ()> + Send + 'static,
+    {
+        let (tx, _) = broadcast::channel(EVENT_CHANNEL_SIZE);
+        let (start, started) = oneshot::channel::<()>();
+        let watch = f(tx.clone());
+        let active = ActiveWatch::new();
+        let handle = tokio::spawn(async move {
+            let _active = active;
+            if started.await.is_ok() {
+                watch.await
+            }
+        });
+
+        Watch {
+            tx,
+            handle,
+            start: Some(start),
+        }
+    }
+
+    pub fn subscribe(&self) -> Receiver<String> {
+        self.tx.subscribe()
+    }
+
+    // start starts reading the events, for the subscribers so far.
+    pub fn start(&mut self) {
+        if let Some(start) = self.start.take() {
+            let _ = start.send(());
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.handle.abort();
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+// notify sends an event to the subscribers, if any.
+fn notify(tx: &Sender<String>, cid: &str) {
+    if tx.send(cid.to_string()).is_err() {
+        warn!(sl!(), "no subscriber for the event of container {}", cid);
+    }
+}
+
+#[instrument]
+pub async fn notify_oom(cid: &str, cg_dir: String) -> Result<Watch> {
     if cgroups::hierarchies::is_cgroup2_unified_mode() {
         return notify_on_oom_v2(cid, cg_dir).await;
     }
     notify_on_oom(cid, cg_dir).await
 }
 
+// notify_memory_pressure returns channel on which you can expect an event
+// each time the container memory comes under pressure, that is when the
+// memory.high limit is exceeded on cgroup v2.
+#[instrument]
+pub async fn notify_memory_pressure(cid: &str, cg_dir: String) -> Result<Watch> {
+    if cgroups::hierarchies::is_cgroup2_unified_mode() {
+        return register_counter_event(
+            cid,
+            cg_dir,
+            "memory.events",
+            Some("cgroup.events"),
+            "high",
+            false,
+        )
+        .await;
+    }
+
+    if cg_dir.is_empty() {
+        return Err(CgroupError::controller_missing("memory").into());
+    }
+
+    register_memory_event(cid, cg_dir, "memory.pressure_level", MEMORY_PRESSURE_LEVEL).await
+}
+
+// notify_pids_max returns a watch with an event each time a fork fails in
+// the container because of its pids limit, which would go unnoticed
+// otherwise, as the processes just see EAGAIN.
+#[instrument]
+pub async fn notify_pids_max(cid: &str, cg_dir: String) -> Result<Watch> {
+    if cg_dir.is_empty() {
+        return Err(CgroupError::controller_missing("pids").into());
+    }
+
+    // cgroup v1 has no cgroup.events, the watch ends with the cgroup
+    let cgroup_event_name = if cgroups::hierarchies::is_cgroup2_unified_mode() {
+        Some("cgroup.events")
+    } else {
+        None
+    };
+
+    register_counter_event(cid, cg_dir, "pids.events", cgroup_event_name, "max", false).await
+}
+
 // get_value_from_cgroup parse cgroup file with `Flat keyed`
 // and get the value of `key`.
 // Flat keyed file format:
@@ -54,41 +210,59 @@ fn get_value_from_cgroup(path: &Path, key: &str) -> Result<i64> {
 
 // notify_on_oom returns channel on which you can expect event about OOM,
 // if process died without OOM this channel will be closed.
-pub async fn notify_on_oom_v2(containere_id: &str, cg_dir: String) -> Result<Receiver<String>> {
-    register_memory_event_v2(containere_id, cg_dir, "memory.events", "cgroup.events").await
+pub async fn notify_on_oom_v2(containere_id: &str, cg_dir: String) -> Result<Watch> {
+    register_counter_event(
+        containere_id,
+        cg_dir,
+        "memory.events",
+        Some("cgroup.events"),
+        "oom_kill",
+        true,
+    )
+    .await
 }
 
-async fn register_memory_event_v2(
+// register_counter_event sends an event each time the `key` counter of
+// the events file grows, only for its first increase if `once`.
+async fn register_counter_event(
     containere_id: &str,
     cg_dir: String,
-    memory_event_name: &str,
-    cgroup_event_name: &str,
-) -> Result<Receiver<String>> {
-    let event_control_path = Path::new(&cg_dir).join(memory_event_name);
-    let cgroup_event_control_path = Path::new(&cg_dir).join(cgroup_event_name);
+    event_name: &str,
+    cgroup_event_name: Option<&str>,
+    key: &str,
+    once: bool,
+) -> Result<Watch> {
+    let event_control_path = Path::new(&cg_dir).join(event_name);
+    let cgroup_event_control_path = cgroup_event_name.map(|n| Path::new(&cg_dir).join(n));
     info!(
         sl!(),
-        "register_memory_event_v2 event_control_path: {:?}", &event_control_path
+        "register_counter_event event_control_path: {:?}", &event_control_path
     );
     info!(
         sl!(),
-        "register_memory_event_v2 cgroup_event_control_path: {:?}", &cgroup_event_control_path
+        "register_counter_event cgroup_event_control_path: {:?}", &cgroup_event_control_path
     );
 
     let mut inotify = Inotify::init().context("Failed to initialize inotify")?;
 
-    // watching oom kill
-    let ev_wd = inotify.add_watch(&event_control_path, WatchMask::MODIFY)?;
+    // watching the counter
+    let ev_wd = inotify
+        .add_watch(&event_control_path, WatchMask::MODIFY)
+        .map_err(|e| CgroupError::io(&event_control_path, e))?;
     // Because no `unix.IN_DELETE|unix.IN_DELETE_SELF` event for cgroup file system, so watching all process exited
-    let cg_wd = inotify.add_watch(&cgroup_event_control_path, WatchMask::MODIFY)?;
+    let cg_wd = match &cgroup_event_control_path {
+        Some(p) => Some(inotify.add_watch(p, WatchMask::MODIFY)?),
+        None => None,
+    };
 
     info!(sl!(), "ev_wd: {:?}", ev_wd);
     info!(sl!(), "cg_wd: {:?}", cg_wd);
 
-    let (sender, receiver) = channel(100);
     let containere_id = containere_id.to_string();
+    let key = key.to_string();
+    let mut last = get_value_from_cgroup(&event_control_path, &key).unwrap_or(0);
 
-    tokio::spawn(async move {
+    Ok(Watch::new(|sender| async move {
         let mut buffer = [0; 32];
         let mut stream = inotify
             .event_stream(&mut buffer)
@@ -104,15 +278,19 @@ async fn register_memory_event_v2(
             info!(sl!(), "event.wd: {:?}", event.wd);
 
             if event.wd == ev_wd {
-                let oom = get_value_from_cgroup(&event_control_path, "oom_kill");
-                if oom.unwrap_or(0) > 0 {
-                    let _ = sender.send(containere_id.clone()).await.map_err(|e| {
-                        error!(sl!(), "send containere_id failed, error: {:?}", e);
-                    });
-                    return;
+                let count = get_value_from_cgroup(&event_control_path, &key).unwrap_or(0);
+                if count > last {
+                    last = count;
+                    notify(&sender, &containere_id);
+                    if once {
+                        return;
+                    }
                 }
-            } else if event.wd == cg_wd {
-                let pids = get_value_from_cgroup(&cgroup_event_control_path, "populated");
+            } else if let Some(p) = cgroup_event_control_path
+                .as_ref()
+                .filter(|_| Some(&event.wd) == cg_wd.as_ref())
+            {
+                let pids = get_value_from_cgroup(p, "populated");
                 if pids.unwrap_or(-1) == 0 {
                     return;
                 }
@@ -124,16 +302,14 @@ async fn register_memory_event_v2(
                 return;
             }
         }
-    });
-
-    Ok(receiver)
+    }))
 }
 
 // notify_on_oom returns channel on which you can expect event about OOM,
 // if process died without OOM this channel will be closed.
-async fn notify_on_oom(cid: &str, dir: String) -> Result<Receiver<String>> {
+async fn notify_on_oom(cid: &str, dir: String) -> Result<Watch> {
     if dir.is_empty() {
-        return Err(anyhow!("memory controller missing"));
+        return Err(CgroupError::controller_missing("memory").into());
     }
 
     register_memory_event(cid, dir, "memory.oom_control", "").await
@@ -144,11 +320,13 @@ async fn register_memory_event(
     cg_dir: String,
     event_name: &str,
     arg: &str,
-) -> Result<Receiver<String>> {
+) -> Result<Watch> {
     let path = Path::new(&cg_dir).join(event_name);
-    let event_file = File::open(path.clone())?;
+    let event_file = File::open(&path).map_err(|e| CgroupError::io(&path, e))?;
 
     let eventfd = eventfd(0, EfdFlags::EFD_CLOEXEC)?;
+    // owned by the stream right away, so that it is closed on any error
+    let mut eventfd_stream = unsafe { PipeStream::from_raw_fd(eventfd) };
 
     let event_control_path = Path::new(&cg_dir).join("cgroup.event_control");
     let data;
@@ -158,16 +336,12 @@ async fn register_memory_event(
         data = format!("{} {} {}", eventfd, event_file.as_raw_fd(), arg);
     }
 
-    fs::write(&event_control_path, data)?;
-
-    let mut eventfd_stream = unsafe { PipeStream::from_raw_fd(eventfd) };
+    fs::write(&event_control_path, data).map_err(|e| CgroupError::io(&event_control_path, e))?;
 
-    let (sender, receiver) = tokio::sync::mpsc::channel(100);
     let containere_id = cid.to_string();
 
-    tokio::spawn(async move {
+    Ok(Watch::new(|sender| async move {
         loop {
-            let sender = sender.clone();
             let mut buf = [0u8; 8];
             match eventfd_stream.read(&mut buf).await {
                 Err(err) => {
@@ -192,11 +366,42 @@ async fn register_memory_event(
                 return;
             }
 
-            let _ = sender.send(containere_id.clone()).await.map_err(|e| {
-                error!(sl!(), "send containere_id failed, error: {:?}", e);
-            });
+            notify(&sender, &containere_id);
         }
-    });
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::broadcast::error::RecvError;
+
+    #[tokio::test]
+    async fn test_watch() {
+        let active = active_watches();
+        let mut w = Watch::new(|tx| async move {
+            notify(&tx, "c1");
+            futures::future::pending::<()>().await;
+        });
+
+        // the first event isn't missed by the subscribers
+        tokio::task::yield_now().await;
+        let mut rx1 = w.subscribe();
+        let mut rx2 = w.subscribe();
+        w.start();
 
-    Ok(receiver)
+        assert_eq!(rx1.recv().await.unwrap(), "c1");
+        assert_eq!(rx2.recv().await.unwrap(), "c1");
+        assert_eq!(active_watches(), active + 1);
+
+        drop(w);
+        assert!(matches!(rx1.recv().await, Err(RecvError::Closed)));
+        assert_eq!(active_watches(), active);
+
+        // nor stays running if never started
+        let w = Watch::new(|_| futures::future::pending::<()>());
+        let mut rx = w.subscribe();
+        drop(w);
+        assert!(matches!(rx.recv().await, Err(RecvError::Closed)));
+    }
 }
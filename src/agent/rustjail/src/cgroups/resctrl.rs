@@ -0,0 +1,186 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Intel RDT support through the resctrl filesystem, see
+// https://www.kernel.org/doc/html/latest/x86/resctrl.html
+//
+// The container processes are moved into a resource group (CLOS) named
+// after the closID of the spec, or after the container id, whose schemata
+// holds the L3 cache and memory bandwidth allocations.
+
+use crate::cgroups::CgroupError;
+use anyhow::{anyhow, Context, Result};
+use oci::LinuxIntelRdt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const RESCTRL_ROOT: &str = "/sys/fs/resctrl";
+
+const SCHEMATA: &str = "schemata";
+const TASKS: &str = "tasks";
+const INFO_L3: &str = "info/L3";
+const INFO_MB: &str = "info/MB";
+
+// Convenience macro to obtain the scope logger
+macro_rules! sl {
+    () => {
+        slog_scope::logger().new(o!("subsystem" => "resctrl"))
+    };
+}
+
+#[derive(Debug)]
+pub struct Manager {
+    path: PathBuf,
+    schemata: String,
+    // a group named by closID may already exist and be shared with other
+    // containers, so only a group created here is set up and removed
+    created: bool,
+}
+
+impl Manager {
+    pub fn new(root: &Path, id: &str, rdt: &LinuxIntelRdt) -> Result<Self> {
+        if !root.join(SCHEMATA).exists() {
+            return Err(anyhow!("resctrl is not mounted at {:?}", root));
+        }
+
+        if !rdt.l3_cache_schema.is_empty() && !root.join(INFO_L3).exists() {
+            return Err(CgroupError::kernel_unsupported(
+                "intelRdt.l3CacheSchema",
+                "no L3 cache allocation in the guest",
+            )
+            .into());
+        }
+
+        if !rdt.mem_bw_schema.is_empty() && !root.join(INFO_MB).exists() {
+            return Err(CgroupError::kernel_unsupported(
+                "intelRdt.memBwSchema",
+                "no memory bandwidth allocation in the guest",
+            )
+            .into());
+        }
+
+        let name = if rdt.clos_id.is_empty() {
+            id
+        } else {
+            rdt.clos_id.as_str()
+        };
+
+        if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+            return Err(anyhow!("invalid resctrl group name {:?}", name));
+        }
+
+        Ok(Manager {
+            path: root.join(name),
+            schemata: build_schemata(rdt),
+            created: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // apply moves `pid` into the resource group, which is created and
+    // given the schemata of the spec if it doesn't exist yet.
+    pub fn apply(&mut self, pid: i32) -> Result<()> {
+        if !self.path.exists() {
+            fs::create_dir(&self.path)
+                .with_context(|| format!("failed to create {:?}", self.path))?;
+            self.created = true;
+
+            if !self.schemata.is_empty() {
+                let file = self.path.join(SCHEMATA);
+                fs::write(&file, &self.schemata)
+                    .with_context(|| format!("failed to write {:?}", file))?;
+            }
+        } else {
+            info!(sl!(), "using existing resctrl group {:?}", self.path);
+        }
+
+        let file = self.path.join(TASKS);
+        fs::write(&file, pid.to_string())
+            .with_context(|| format!("failed to add {} to {:?}", pid, file))
+    }
+
+    pub fn destroy(&mut self) -> Result<()> {
+        if self.created {
+            fs::remove_dir(&self.path)
+                .with_context(|| format!("failed to remove {:?}", self.path))?;
+            self.created = false;
+        }
+
+        Ok(())
+    }
+}
+
+// build_schemata returns the schemata lines of the spec, which are
+// written at once.
+fn build_schemata(rdt: &LinuxIntelRdt) -> String {
+    [&rdt.l3_cache_schema, &rdt.mem_bw_schema]
+        .iter()
+        .filter(|s| !s.is_empty())
+        .map(|s| format!("{}\n", s))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resctrl_manager() {
+        let root = tempfile::tempdir().unwrap();
+        let rdt = LinuxIntelRdt {
+            l3_cache_schema: "L3:0=f0".to_string(),
+            mem_bw_schema: "MB:0=50".to_string(),
+            ..Default::default()
+        };
+
+        // not mounted
+        assert!(Manager::new(root.path(), "c1", &rdt).is_err());
+
+        fs::write(root.path().join(SCHEMATA), "").unwrap();
+        fs::create_dir_all(root.path().join(INFO_L3)).unwrap();
+
+        // no memory bandwidth allocation
+        assert!(Manager::new(root.path(), "c1", &rdt).is_err());
+
+        fs::create_dir_all(root.path().join(INFO_MB)).unwrap();
+
+        let mut m = Manager::new(root.path(), "c1", &rdt).unwrap();
+        assert_eq!(m.path(), root.path().join("c1"));
+        assert_eq!(m.schemata, "L3:0=f0\nMB:0=50\n");
+
+        m.apply(100).unwrap();
+        let read = |f: &str| fs::read_to_string(m.path().join(f)).unwrap();
+        assert_eq!(read(SCHEMATA), "L3:0=f0\nMB:0=50\n");
+        assert_eq!(read(TASKS), "100");
+
+        // unlike on resctrl, the interface files have to go for the
+        // directory to be removed
+        fs::remove_file(m.path().join(SCHEMATA)).unwrap();
+        fs::remove_file(m.path().join(TASKS)).unwrap();
+        m.destroy().unwrap();
+        assert!(!root.path().join("c1").exists());
+
+        // a shared group is left as is
+        let shared = LinuxIntelRdt {
+            clos_id: "shared".to_string(),
+            ..rdt
+        };
+        fs::create_dir(root.path().join("shared")).unwrap();
+        let mut m = Manager::new(root.path(), "c2", &shared).unwrap();
+        m.apply(200).unwrap();
+        assert!(!m.path().join(SCHEMATA).exists());
+        m.destroy().unwrap();
+        assert!(root.path().join("shared").exists());
+
+        let invalid = LinuxIntelRdt {
+            clos_id: "../c1".to_string(),
+            ..Default::default()
+        };
+        assert!(Manager::new(root.path(), "c3", &invalid).is_err());
+    }
+}
@@ -0,0 +1,150 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// The sandbox cgroup is the common parent of the cgroups of all the
+// containers of a pod, <root>/<sandbox-id>/<container-id>, so that the pod
+// can be capped as a whole inside the guest. The kernel charges the usage
+// of the children to their parents, hence the stats of the sandbox cgroup
+// are the stats of the pod.
+
+use crate::cgroups::fs::remove::remove_cgroup;
+#[cfg(not(test))]
+use crate::cgroups::fs::ManagerBuilder as FsManagerBuilder;
+#[cfg(test)]
+use crate::cgroups::mock::ManagerBuilder as FsManagerBuilder;
+use crate::cgroups::stats::CgroupStats;
+use crate::cgroups::{cgroup_path, Manager};
+use anyhow::{anyhow, Result};
+use oci::LinuxResources;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug)]
+pub struct SandboxCgroupManager {
+    sid: String,
+    cpath: String,
+    manager: Box<dyn Manager>,
+}
+
+impl SandboxCgroupManager {
+    // new creates the cgroup of the sandbox `sid` below the cgroup `root`,
+    // the one the container cgroups are created below.
+    pub fn new(root: &str, sid: &str) -> Result<Self> {
+        if sid.is_empty() || sid.contains('/') {
+            return Err(anyhow!("invalid sandbox id {:?}", sid));
+        }
+
+        let cpath = cgroup_path(root, sid)?;
+        let manager = Box::new(FsManagerBuilder::new(&cpath).build()?);
+
+        Ok(SandboxCgroupManager {
+            sid: sid.to_string(),
+            cpath,
+            manager,
+        })
+    }
+
+    pub fn cpath(&self) -> &str {
+        &self.cpath
+    }
+
+    // container_path returns the cgroup path of a container of the pod,
+    // relative to the root the container cgroups are created below.
+    pub fn container_path(&self, cid: &str) -> String {
+        format!("/{}/{}", self.sid, cid)
+    }
+
+    // set applies pod level limits, merged into the ones set before.
+    pub fn set(&self, r: &LinuxResources) -> Result<()> {
+        self.manager.set(r, true)
+    }
+
+    // get_stats returns the usage of all the containers of the pod.
+    pub fn get_stats(&self) -> Result<CgroupStats> {
+        self.manager.get_stats()
+    }
+
+    pub fn unapplied_resources(&self) -> Vec<String> {
+        self.manager.unapplied_resources()
+    }
+
+    // destroy removes the sandbox cgroup along with the container cgroups
+    // still below it, e.g. the ones of containers which failed to start.
+    // The children go first, since a cgroup with children or processes
+    // can't be removed.
+    pub fn destroy(&mut self) -> Result<()> {
+        let mut errors = vec![];
+        let dirs = self.manager.as_fs().map(|f| f.cgroup_dirs());
+        for dir in dirs.unwrap_or_default() {
+            if let Err(e) = remove_children(&dir) {
+                errors.push(format!("{:#}", e));
+            }
+        }
+
+        if let Err(e) = self.manager.destroy() {
+            errors.push(format!("{:#}", e));
+        }
+        if !errors.is_empty() {
+            return Err(anyhow!("{}", errors.join("; ")));
+        }
+
+        Ok(())
+    }
+}
+
+// remove_children removes the cgroups below dir, killing the processes
+// left in them.
+fn remove_children(dir: &Path) -> Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    let mut errors = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            if let Err(e) = remove_cgroup(&entry.path()) {
+                errors.push(format!("{:#}", e));
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(anyhow!("{}", errors.join("; ")));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sandbox_cgroup_manager() {
+        let m = SandboxCgroupManager::new("/kata", "sb1").unwrap();
+        assert_eq!(m.cpath(), "/kata/sb1");
+        assert_eq!(m.container_path("c1"), "/sb1/c1");
+
+        let m = SandboxCgroupManager::new("/", "sb1").unwrap();
+        assert_eq!(m.cpath(), "/sb1");
+
+        assert!(SandboxCgroupManager::new("/kata", "").is_err());
+        assert!(SandboxCgroupManager::new("/kata", "a/b").is_err());
+        assert!(SandboxCgroupManager::new("/kata", "..").is_err());
+    }
+
+    #[test]
+    fn test_remove_children() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("c1/sub")).unwrap();
+        fs::create_dir_all(dir.path().join("c2")).unwrap();
+
+        remove_children(dir.path()).unwrap();
+        assert!(dir.path().is_dir());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        assert!(remove_children(&dir.path().join("none")).is_ok());
+    }
+}
@@ -0,0 +1,337 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// The statistics of a cgroup, as the managers return them. They mirror the
+// messages of the agent protocol, which they are converted to by the
+// callers serving them, so that the managers can be used without the
+// protocol types.
+
+use protobuf::{RepeatedField, SingularPtrField};
+use protocols::agent;
+use std::collections::HashMap;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CpuUsage {
+    // in nanoseconds
+    pub total_usage: u64,
+    pub percpu_usage: Vec<u64>,
+    pub usage_in_kernelmode: u64,
+    pub usage_in_usermode: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ThrottlingData {
+    pub periods: u64,
+    pub throttled_periods: u64,
+    pub throttled_time: u64,
+    pub nr_bursts: u64,
+    pub burst_time: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CpuStats {
+    pub cpu_usage: Option<CpuUsage>,
+    pub throttling_data: Option<ThrottlingData>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PidsStats {
+    pub current: u64,
+    pub limit: u64,
+    pub max_events: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MemoryData {
+    pub usage: u64,
+    pub max_usage: u64,
+    pub failcnt: u64,
+    pub limit: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MemoryEvents {
+    pub oom: u64,
+    pub oom_kill: u64,
+    pub under_oom: bool,
+    pub high: u64,
+    pub max: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MemoryStats {
+    pub cache: u64,
+    pub usage: Option<MemoryData>,
+    pub swap_usage: Option<MemoryData>,
+    pub kernel_usage: Option<MemoryData>,
+    pub use_hierarchy: bool,
+    pub stats: HashMap<String, u64>,
+    pub low: u64,
+    pub min: u64,
+    pub high: u64,
+    pub oom_group_kill: u64,
+    pub swap_supported: bool,
+    pub events: Option<MemoryEvents>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BlkioStatsEntry {
+    pub major: u64,
+    pub minor: u64,
+    pub op: String,
+    pub value: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BlkioStats {
+    pub io_service_bytes_recursive: Vec<BlkioStatsEntry>,
+    pub io_serviced_recursive: Vec<BlkioStatsEntry>,
+    pub io_queued_recursive: Vec<BlkioStatsEntry>,
+    pub io_service_time_recursive: Vec<BlkioStatsEntry>,
+    pub io_wait_time_recursive: Vec<BlkioStatsEntry>,
+    pub io_merged_recursive: Vec<BlkioStatsEntry>,
+    pub io_time_recursive: Vec<BlkioStatsEntry>,
+    pub sectors_recursive: Vec<BlkioStatsEntry>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct HugetlbStats {
+    pub usage: u64,
+    pub max_usage: u64,
+    pub failcnt: u64,
+    pub rsvd_usage: u64,
+    pub rsvd_max_usage: u64,
+    pub rsvd_failcnt: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MiscStats {
+    pub usage: u64,
+    pub limit: u64,
+    pub failcnt: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CgroupStats {
+    pub cpu_stats: Option<CpuStats>,
+    pub memory_stats: Option<MemoryStats>,
+    pub pids_stats: Option<PidsStats>,
+    pub blkio_stats: Option<BlkioStats>,
+    // by page size, e.g. "2MB"
+    pub hugetlb_stats: HashMap<String, HugetlbStats>,
+    // by misc resource, e.g. "sev"
+    pub misc_stats: HashMap<String, MiscStats>,
+}
+
+fn some<T, U: Into<T>>(v: Option<U>) -> SingularPtrField<T> {
+    SingularPtrField::from_option(v.map(Into::into))
+}
+
+fn repeated<T, U: Into<T>>(v: Vec<U>) -> RepeatedField<T> {
+    RepeatedField::from_vec(v.into_iter().map(Into::into).collect())
+}
+
+fn map<T, U: Into<T>>(m: HashMap<String, U>) -> HashMap<String, T> {
+    m.into_iter().map(|(k, v)| (k, v.into())).collect()
+}
+
+impl From<CpuUsage> for agent::CpuUsage {
+    fn from(u: CpuUsage) -> Self {
+        agent::CpuUsage {
+            total_usage: u.total_usage,
+            percpu_usage: u.percpu_usage,
+            usage_in_kernelmode: u.usage_in_kernelmode,
+            usage_in_usermode: u.usage_in_usermode,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<ThrottlingData> for agent::ThrottlingData {
+    fn from(t: ThrottlingData) -> Self {
+        agent::ThrottlingData {
+            periods: t.periods,
+            throttled_periods: t.throttled_periods,
+            throttled_time: t.throttled_time,
+            nr_bursts: t.nr_bursts,
+            burst_time: t.burst_time,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<CpuStats> for agent::CpuStats {
+    fn from(s: CpuStats) -> Self {
+        agent::CpuStats {
+            cpu_usage: some(s.cpu_usage),
+            throttling_data: some(s.throttling_data),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<PidsStats> for agent::PidsStats {
+    fn from(s: PidsStats) -> Self {
+        agent::PidsStats {
+            current: s.current,
+            limit: s.limit,
+            max_events: s.max_events,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<MemoryData> for agent::MemoryData {
+    fn from(d: MemoryData) -> Self {
+        agent::MemoryData {
+            usage: d.usage,
+            max_usage: d.max_usage,
+            failcnt: d.failcnt,
+            limit: d.limit,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<MemoryEvents> for agent::MemoryEvents {
+    fn from(e: MemoryEvents) -> Self {
+        agent::MemoryEvents {
+            oom: e.oom,
+            oom_kill: e.oom_kill,
+            under_oom: e.under_oom,
+            high: e.high,
+            max: e.max,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<MemoryStats> for agent::MemoryStats {
+    fn from(s: MemoryStats) -> Self {
+        agent::MemoryStats {
+            cache: s.cache,
+            usage: some(s.usage),
+            swap_usage: some(s.swap_usage),
+            kernel_usage: some(s.kernel_usage),
+            use_hierarchy: s.use_hierarchy,
+            stats: s.stats,
+            low: s.low,
+            min: s.min,
+            high: s.high,
+            oom_group_kill: s.oom_group_kill,
+            swap_supported: s.swap_supported,
+            events: some(s.events),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<BlkioStatsEntry> for agent::BlkioStatsEntry {
+    fn from(e: BlkioStatsEntry) -> Self {
+        agent::BlkioStatsEntry {
+            major: e.major,
+            minor: e.minor,
+            op: e.op,
+            value: e.value,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<BlkioStats> for agent::BlkioStats {
+    fn from(s: BlkioStats) -> Self {
+        agent::BlkioStats {
+            io_service_bytes_recursive: repeated(s.io_service_bytes_recursive),
+            io_serviced_recursive: repeated(s.io_serviced_recursive),
+            io_queued_recursive: repeated(s.io_queued_recursive),
+            io_service_time_recursive: repeated(s.io_service_time_recursive),
+            io_wait_time_recursive: repeated(s.io_wait_time_recursive),
+            io_merged_recursive: repeated(s.io_merged_recursive),
+            io_time_recursive: repeated(s.io_time_recursive),
+            sectors_recursive: repeated(s.sectors_recursive),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<HugetlbStats> for agent::HugetlbStats {
+    fn from(s: HugetlbStats) -> Self {
+        agent::HugetlbStats {
+            usage: s.usage,
+            max_usage: s.max_usage,
+            failcnt: s.failcnt,
+            rsvd_usage: s.rsvd_usage,
+            rsvd_max_usage: s.rsvd_max_usage,
+            rsvd_failcnt: s.rsvd_failcnt,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<MiscStats> for agent::MiscStats {
+    fn from(s: MiscStats) -> Self {
+        agent::MiscStats {
+            usage: s.usage,
+            limit: s.limit,
+            failcnt: s.failcnt,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<CgroupStats> for agent::CgroupStats {
+    fn from(s: CgroupStats) -> Self {
+        agent::CgroupStats {
+            cpu_stats: some(s.cpu_stats),
+            memory_stats: some(s.memory_stats),
+            pids_stats: some(s.pids_stats),
+            blkio_stats: some(s.blkio_stats),
+            hugetlb_stats: map(s.hugetlb_stats),
+            misc_stats: map(s.misc_stats),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_into_protocol() {
+        let stats = CgroupStats {
+            memory_stats: Some(MemoryStats {
+                usage: Some(MemoryData {
+                    usage: 1 << 20,
+                    ..Default::default()
+                }),
+                events: Some(MemoryEvents {
+                    oom_kill: 2,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            hugetlb_stats: [(
+                "2MB".to_string(),
+                HugetlbStats {
+                    usage: 1 << 21,
+                    ..Default::default()
+                },
+            )]
+            .iter()
+            .cloned()
+            .collect(),
+            ..Default::default()
+        };
+
+        let s: agent::CgroupStats = stats.into();
+        assert!(s.cpu_stats.is_none());
+        let memory = s.memory_stats.as_ref().unwrap();
+        assert_eq!(memory.usage.as_ref().unwrap().usage, 1 << 20);
+        assert_eq!(memory.events.as_ref().unwrap().oom_kill, 2);
+        assert!(memory.swap_usage.is_none());
+        assert_eq!(s.hugetlb_stats["2MB"].usage, 1 << 21);
+    }
+}
@@ -3,8 +3,18 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
-use crate::cgroups::Manager as CgroupManager;
+use crate::cgroups::{CgroupError, Manager as CgroupManager};
+use anyhow::{Context, Result};
 
+#[derive(Debug)]
 pub struct Manager {}
 
+impl Manager {
+    // new fails until the systemd driver is implemented, rather than
+    // leaving the container without a cgroup.
+    pub fn new(_cpath: &str) -> Result<Self> {
+        Err(CgroupError::NotSupported).context("the systemd cgroup driver")
+    }
+}
+
 impl CgroupManager for Manager {}
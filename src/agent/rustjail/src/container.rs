@@ -18,13 +18,19 @@ use std::time::SystemTime;
 use cgroups::freezer::FreezerState;
 
 use crate::capabilities;
+use crate::cgroups::fs::update::merge_resources;
 #[cfg(not(test))]
-use crate::cgroups::fs::Manager as FsManager;
+use crate::cgroups::fs::ManagerBuilder as FsManagerBuilder;
+use crate::cgroups::instrumented::Instrumented;
 #[cfg(test)]
-use crate::cgroups::mock::Manager as FsManager;
-use crate::cgroups::Manager;
+use crate::cgroups::mock::{Manager as FsManager, ManagerBuilder as FsManagerBuilder};
+use crate::cgroups::resctrl;
+use crate::cgroups::systemd::Manager as SystemdManager;
+use crate::cgroups::{Driver, Manager};
 use crate::log_child;
+use crate::netstats;
 use crate::process::Process;
+use crate::sched;
 use crate::specconv::CreateOpts;
 use crate::{mount, validator};
 
@@ -42,7 +48,7 @@ use nix::unistd::{self, fork, ForkResult, Gid, Pid, Uid};
 use std::os::unix::fs::MetadataExt;
 use std::os::unix::io::AsRawFd;
 
-use protobuf::SingularPtrField;
+use protobuf::{RepeatedField, SingularPtrField};
 
 use oci::State as OCIState;
 use std::collections::HashMap;
@@ -55,10 +61,12 @@ use slog::{info, o, Logger};
 use crate::pipestream::PipeStream;
 use crate::sync::{read_sync, write_count, write_sync, SYNC_DATA, SYNC_FAILED, SYNC_SUCCESS};
 use crate::sync_with_async::{read_async, write_async};
+use crate::unconfined;
 use async_trait::async_trait;
 use rlimit::{setrlimit, Resource, Rlim};
 use tokio::io::AsyncBufReadExt;
 use tokio::sync::Mutex;
+use tracing::instrument;
 
 use crate::utils;
 
@@ -73,6 +81,24 @@ const FIFO_FD: &str = "FIFO_FD";
 const HOME_ENV_KEY: &str = "HOME";
 const PIDNS_FD: &str = "PIDNS_FD";
 
+// SIGNAL_SCOPE_ANNOTATION selects which processes receive a signal
+// sent to all the processes of a container, see SignalScope.
+pub const SIGNAL_SCOPE_ANNOTATION: &str = "io.katacontainers.container.signal_scope";
+
+// OOM_POLICY_ANNOTATION selects what the agent does when the container
+// hits an OOM, see OomPolicy.
+pub const OOM_POLICY_ANNOTATION: &str = "io.katacontainers.container.oom_policy";
+
+// CGROUP_PRECREATED_ANNOTATION tells the cgroup of the container was
+// created beforehand, e.g. by systemd in the guest, so that it is only
+// joined and configured. Its path is still taken below the cgroup root of
+// the agent, the annotation coming from the spec.
+pub const CGROUP_PRECREATED_ANNOTATION: &str = "io.katacontainers.container.cgroup_precreated";
+
+// OOM_GROUP_ANNOTATION makes an OOM kill the whole container rather than
+// a single process, on cgroup v2.
+pub const OOM_GROUP_ANNOTATION: &str = "io.katacontainers.container.oom_group";
+
 #[derive(Debug)]
 pub struct ContainerStatus {
     pre_status: ContainerState,
@@ -189,6 +215,15 @@ lazy_static! {
     };
 }
 
+// CgroupMounts are what the child needs of the cgroup manager to set up
+// the cgroup filesystem of the container: the directories of the cgroup by
+// controller and the mount points of the controllers.
+#[derive(Serialize, Deserialize, Debug)]
+struct CgroupMounts {
+    paths: HashMap<String, String>,
+    mounts: HashMap<String, String>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BaseState {
     #[serde(default, skip_serializing_if = "String::is_empty")]
@@ -217,6 +252,67 @@ pub trait BaseContainer {
     fn exec(&mut self) -> Result<()>;
 }
 
+// SignalScope describes how a signal sent to all the processes of a
+// container is delivered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignalScope {
+    // Signal the tracked processes and the process group of the init process.
+    ProcessGroup,
+    // Freeze the container cgroup, signal every member and thaw it, so that
+    // detached children which left the process group are reached too.
+    Cgroup,
+}
+
+impl Default for SignalScope {
+    fn default() -> Self {
+        SignalScope::ProcessGroup
+    }
+}
+
+impl FromStr for SignalScope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "process_group" => Ok(SignalScope::ProcessGroup),
+            "cgroup" => Ok(SignalScope::Cgroup),
+            _ => Err(anyhow!("invalid signal scope {}", s)),
+        }
+    }
+}
+
+// OomPolicy is the action taken on top of reporting the OOM events of a
+// container.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OomPolicy {
+    // Only report the event.
+    Notify,
+    // Freeze the container, to keep its state around for debugging.
+    Freeze,
+    // Kill all the processes of the container, so that the runtime
+    // restarts it according to its restart policy.
+    Restart,
+}
+
+impl Default for OomPolicy {
+    fn default() -> Self {
+        OomPolicy::Notify
+    }
+}
+
+impl FromStr for OomPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "notify" => Ok(OomPolicy::Notify),
+            "freeze" => Ok(OomPolicy::Freeze),
+            "restart" => Ok(OomPolicy::Restart),
+            _ => Err(anyhow!("invalid OOM policy {}", s)),
+        }
+    }
+}
+
 // LinuxContainer protected by Mutex
 // Arc<Mutex<Innercontainer>> or just Mutex<InnerContainer>?
 // Or use Mutex<xx> as a member of struct, like C?
@@ -226,7 +322,8 @@ pub struct LinuxContainer {
     pub id: String,
     pub root: String,
     pub config: Config,
-    pub cgroup_manager: Option<FsManager>,
+    pub cgroup_manager: Option<Box<dyn Manager>>,
+    pub intel_rdt_manager: Option<resctrl::Manager>,
     pub init_process_pid: pid_t,
     pub init_process_start_time: u64,
     pub uid_map_path: String,
@@ -235,6 +332,8 @@ pub struct LinuxContainer {
     pub status: ContainerStatus,
     pub created: SystemTime,
     pub logger: Logger,
+    pub signal_scope: SignalScope,
+    pub oom_policy: OomPolicy,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -376,7 +475,7 @@ fn do_init_child(cwfd: RawFd) -> Result<()> {
     let buf = read_sync(crfd)?;
     let cm_str = std::str::from_utf8(&buf)?;
 
-    let cm: FsManager = serde_json::from_str(cm_str)?;
+    let cm: CgroupMounts = serde_json::from_str(cm_str)?;
 
     let p = if spec.process.is_some() {
         spec.process.as_ref().unwrap()
@@ -439,6 +538,11 @@ fn do_init_child(cwfd: RawFd) -> Result<()> {
         )?;
     }
 
+    if let Some(s) = oci_process.scheduler.as_ref() {
+        log_child!(cfd_log, "set scheduler: {:?}", s);
+        sched::set_scheduler(s)?;
+    }
+
     // set rlimit
     for rl in p.rlimits.iter() {
         log_child!(cfd_log, "set resource limit: {:?}", rl);
@@ -666,7 +770,11 @@ fn do_init_child(cwfd: RawFd) -> Result<()> {
         )?;
         unistd::close(fifofd)?;
         let mut buf: &mut [u8] = &mut [0];
-        unistd::read(fd, &mut buf)?;
+        // the fifo is closed without being written when the start of the
+        // container is aborted
+        if unistd::read(fd, &mut buf)? == 0 {
+            return Err(anyhow!("exec fifo closed, container start aborted"));
+        }
     }
 
     do_exec(&args);
@@ -775,34 +883,44 @@ impl BaseContainer for LinuxContainer {
         Err(anyhow!("invalid eid {}", eid))
     }
 
+    #[instrument(skip(self), fields(container_id = %self.id))]
     fn stats(&self) -> Result<StatsContainerResponse> {
         let mut r = StatsContainerResponse::default();
 
-        if self.cgroup_manager.is_some() {
-            r.cgroup_stats =
-                SingularPtrField::some(self.cgroup_manager.as_ref().unwrap().get_stats()?);
+        if let Some(cgm) = self.cgroup_manager.as_ref() {
+            r.cgroup_stats = SingularPtrField::some(cgm.get_stats()?.into());
+            r.unapplied_resources = RepeatedField::from_vec(cgm.unapplied_resources());
+            r.exec_cgroup_stats =
+                SingularPtrField::from_option(cgm.get_exec_stats().map(Into::into));
         }
 
-        // what about network interface stats?
+        // the init process is gone once the container is stopped
+        if self.init_process_pid > 0 && self.status() != ContainerState::Stopped {
+            match netstats::get_network_stats(self.init_process_pid) {
+                Ok(stats) => r.network_stats = RepeatedField::from_vec(stats),
+                Err(e) => {
+                    warn!(self.logger, "failed to get the network stats"; "error" => format!("{:?}", e))
+                }
+            }
+        }
 
         Ok(r)
     }
 
+    #[instrument(skip(self, r), fields(container_id = %self.id))]
     fn set(&mut self, r: LinuxResources) -> Result<()> {
         if self.cgroup_manager.is_some() {
             self.cgroup_manager.as_ref().unwrap().set(&r, true)?;
         }
-        self.config
-            .spec
-            .as_mut()
-            .unwrap()
-            .linux
-            .as_mut()
-            .unwrap()
-            .resources = Some(r);
+        let linux = self.config.spec.as_mut().unwrap().linux.as_mut().unwrap();
+        linux.resources = Some(match &linux.resources {
+            Some(applied) => merge_resources(applied, &r),
+            None => r,
+        });
         Ok(())
     }
 
+    #[instrument(skip(self, p), fields(container_id = %self.id, exec_id = %p.exec_id))]
     async fn start(&mut self, mut p: Process) -> Result<()> {
         let logger = self.logger.new(o!("eid" => p.exec_id.clone()));
         let tty = p.tty;
@@ -885,7 +1003,7 @@ impl BaseContainer for LinuxContainer {
 
         let exec_path = std::env::current_exe()?;
         let mut child = std::process::Command::new(exec_path);
-        let mut child = child
+        child
             .arg("init")
             .stdin(child_stdin)
             .stdout(child_stdout)
@@ -897,14 +1015,14 @@ impl BaseContainer for LinuxContainer {
             .env(CLOG_FD, format!("{}", cfd_log));
 
         if p.init {
-            child = child.env(FIFO_FD, format!("{}", fifofd));
+            child.env(FIFO_FD, format!("{}", fifofd));
         }
 
         if pidns.is_some() {
-            child = child.env(PIDNS_FD, format!("{}", pidns.unwrap()));
+            child.env(PIDNS_FD, format!("{}", pidns.unwrap()));
         }
 
-        child.spawn()?;
+        unconfined::spawn(child)?;
 
         unistd::close(crfd)?;
         unistd::close(cwfd)?;
@@ -941,7 +1059,8 @@ impl BaseContainer for LinuxContainer {
             &logger,
             &spec,
             &p,
-            self.cgroup_manager.as_ref().unwrap(),
+            self.cgroup_manager.as_deref().unwrap(),
+            self.intel_rdt_manager.as_mut(),
             &st,
             &mut pipe_w,
             &mut pipe_r,
@@ -986,6 +1105,7 @@ impl BaseContainer for LinuxContainer {
         Ok(())
     }
 
+    #[instrument(skip(self), fields(container_id = %self.id))]
     async fn destroy(&mut self) -> Result<()> {
         let spec = self.config.spec.as_ref().unwrap();
         let st = self.oci_state()?;
@@ -994,6 +1114,14 @@ impl BaseContainer for LinuxContainer {
             signal::kill(Pid::from_raw(*pid), Some(Signal::SIGKILL))?;
         }
 
+        // the processes which left the process tree, e.g. the daemons,
+        // would keep the cgroup from being removed
+        if self.cgroup_manager.is_some() {
+            if let Err(e) = self.signal_cgroup(Signal::SIGKILL) {
+                warn!(self.logger, "failed to kill the cgroup members"; "error" => format!("{:?}", e));
+            }
+        }
+
         if spec.hooks.is_some() {
             info!(self.logger, "poststop");
             let hooks = spec.hooks.as_ref().unwrap();
@@ -1012,14 +1140,27 @@ impl BaseContainer for LinuxContainer {
         if let Some(cgm) = self.cgroup_manager.as_mut() {
             cgm.destroy().context("destroy cgroups")?;
         }
+
+        if let Some(rdt) = self.intel_rdt_manager.as_mut() {
+            rdt.destroy().context("destroy resctrl group")?;
+        }
         Ok(())
     }
 
     fn signal(&self, sig: Signal, all: bool) -> Result<()> {
         if all {
+            if self.signal_scope == SignalScope::Cgroup {
+                return self.signal_cgroup(sig);
+            }
+
             for pid in self.processes.keys() {
                 signal::kill(Pid::from_raw(*pid), Some(sig))?;
             }
+
+            if let Some(pgid) = self.init_process_group() {
+                signal::killpg(pgid, Some(sig))?;
+                return Ok(());
+            }
         }
 
         signal::kill(Pid::from_raw(self.init_process_pid), Some(sig))?;
@@ -1028,10 +1169,30 @@ impl BaseContainer for LinuxContainer {
     }
 
     fn exec(&mut self) -> Result<()> {
+        let fd = self.prepare_exec()?;
+        self.release_exec(fd)
+    }
+}
+
+impl LinuxContainer {
+    // prepare_exec opens the exec fifo the init process waits on before
+    // running the container process, so that several containers can be
+    // checked ready first and then released together.
+    pub fn prepare_exec(&self) -> Result<RawFd> {
         let fifo = format!("{}/{}", &self.root, EXEC_FIFO_FILENAME);
         let fd = fcntl::open(fifo.as_str(), OFlag::O_WRONLY, Mode::from_bits_truncate(0))?;
+
+        Ok(fd)
+    }
+
+    // release_exec lets the init process run the container process, `fd`
+    // being the exec fifo returned by prepare_exec.
+    pub fn release_exec(&mut self, fd: RawFd) -> Result<()> {
         let data: &[u8] = &[0];
-        unistd::write(fd, &data)?;
+        let ret = unistd::write(fd, &data);
+        let _ = unistd::close(fd);
+        ret?;
+
         info!(self.logger, "container started");
         self.init_process_start_time = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
@@ -1039,7 +1200,6 @@ impl BaseContainer for LinuxContainer {
             .as_secs();
 
         self.status.transition(ContainerState::Running);
-        unistd::close(fd)?;
 
         Ok(())
     }
@@ -1181,7 +1341,8 @@ async fn join_namespaces(
     logger: &Logger,
     spec: &Spec,
     p: &Process,
-    cm: &FsManager,
+    cm: &dyn Manager,
+    rdt: Option<&mut resctrl::Manager>,
     st: &OCIState,
     pipe_w: &mut PipeStream,
     pipe_r: &mut PipeStream,
@@ -1208,7 +1369,13 @@ async fn join_namespaces(
     info!(logger, "wait child received oci process");
     read_async(pipe_r).await?;
 
-    let cm_str = serde_json::to_string(cm)?;
+    // the cgroup filesystem of the container is only mounted from the
+    // layout of the backends writing it directly
+    let fs = cm.as_fs();
+    let cm_str = serde_json::to_string(&CgroupMounts {
+        paths: fs.map(|f| f.get_paths()).unwrap_or_default(),
+        mounts: fs.map(|f| f.get_mounts()).unwrap_or_default(),
+    })?;
     write_async(pipe_w, SYNC_DATA, cm_str.as_str()).await?;
 
     // wait child setup user namespace
@@ -1237,7 +1404,16 @@ async fn join_namespaces(
     }
 
     if res.is_some() {
-        cm.apply(p.pid)?;
+        if p.init {
+            cm.apply(p.pid)?;
+        } else {
+            cm.apply_exec(p.pid)?;
+        }
+    }
+
+    if let Some(rdt) = rdt {
+        info!(logger, "apply intel rdt!");
+        rdt.apply(p.pid)?;
     }
 
     info!(logger, "notify child to continue");
@@ -1373,13 +1549,62 @@ impl LinuxContainer {
             linux.cgroups_path.clone()
         };
 
-        let cgroup_manager = FsManager::new(cpath.as_str())?;
+        let precreated = match spec.annotations.get(CGROUP_PRECREATED_ANNOTATION) {
+            Some(v) => v
+                .parse::<bool>()
+                .with_context(|| format!("invalid {} annotation", CGROUP_PRECREATED_ANNOTATION))?,
+            None => false,
+        };
+
+        let oom_group = match spec.annotations.get(OOM_GROUP_ANNOTATION) {
+            Some(v) => v
+                .parse::<bool>()
+                .with_context(|| format!("invalid {} annotation", OOM_GROUP_ANNOTATION))?,
+            None => false,
+        };
+
+        let cgroup_manager: Box<dyn Manager> = match Driver::of(config.use_systemd_cgroup) {
+            Driver::Fs => Box::new(
+                FsManagerBuilder::new(cpath.as_str())
+                    .cgroup_root(&config.cgroup_root)
+                    .disable_kmem_accounting(config.disable_kmem_accounting)
+                    .strict_resources(config.strict_resources)
+                    .precreated(precreated)
+                    .oom_group(oom_group)
+                    .io_cost(config.io_cost)
+                    .rootless(config.rootless_cgroup)
+                    .default_swappiness(config.default_swappiness)
+                    .device_map(config.device_map.clone())
+                    .build()?,
+            ),
+            Driver::Systemd => Box::new(SystemdManager::new(cpath.as_str())?),
+        };
         info!(logger, "new cgroup_manager {:?}", &cgroup_manager);
 
+        let intel_rdt_manager = match linux.intel_rdt.as_ref() {
+            Some(rdt) => Some(resctrl::Manager::new(
+                Path::new(resctrl::RESCTRL_ROOT),
+                &id,
+                rdt,
+            )?),
+            None => None,
+        };
+
+        let signal_scope = match spec.annotations.get(SIGNAL_SCOPE_ANNOTATION) {
+            Some(v) => v.parse::<SignalScope>()?,
+            None => SignalScope::default(),
+        };
+
+        let oom_policy = match spec.annotations.get(OOM_POLICY_ANNOTATION) {
+            Some(v) => v.parse::<OomPolicy>()?,
+            None => OomPolicy::default(),
+        };
+
         Ok(LinuxContainer {
             id: id.clone(),
             root,
-            cgroup_manager: Some(cgroup_manager),
+            cgroup_manager: Some(Box::new(Instrumented::new(cgroup_manager))),
+            intel_rdt_manager,
             status: ContainerStatus::new(),
             uid_map_path: String::from(""),
             gid_map_path: "".to_string(),
@@ -1392,10 +1617,74 @@ impl LinuxContainer {
                 .unwrap()
                 .as_secs(),
             logger: logger.new(o!("module" => "rustjail", "subsystem" => "container", "cid" => id)),
+            signal_scope,
+            oom_policy,
         })
     }
 }
 
+impl LinuxContainer {
+    // init_process_group returns the process group of the init process,
+    // unless it is shared with the agent itself.
+    fn init_process_group(&self) -> Option<Pid> {
+        let pgid = unistd::getpgid(Some(Pid::from_raw(self.init_process_pid))).ok()?;
+        if pgid == unistd::getpgrp() {
+            return None;
+        }
+        Some(pgid)
+    }
+
+    // signal_cgroup delivers the signal to every member of the container
+    // cgroup. The cgroup is frozen meanwhile so that no new process can
+    // escape by forking, unless the container was already paused.
+    fn signal_cgroup(&self, sig: Signal) -> Result<()> {
+        let cgm = self
+            .cgroup_manager
+            .as_ref()
+            .ok_or_else(|| anyhow!("failed to get container's cgroup manager"))?;
+
+        let paused = self.status() == ContainerState::Paused;
+        if !paused {
+            cgm.freeze(FreezerState::Frozen)?;
+        }
+
+        let result = cgm.get_procs().and_then(|pids| {
+            for pid in pids {
+                match signal::kill(Pid::from_raw(pid), Some(sig)) {
+                    Err(nix::Error::Sys(Errno::ESRCH)) | Ok(_) => {}
+                    Err(e) => return Err(anyhow!(e).context(format!("signal pid {}", pid))),
+                }
+            }
+            Ok(())
+        });
+
+        if !paused {
+            cgm.freeze(FreezerState::Thawed)?;
+        }
+
+        result
+    }
+
+    // renice sets the nice value of every member of the container cgroup,
+    // the processes started later keeping the one of their parent.
+    pub fn renice(&self, nice: i32) -> Result<()> {
+        let cgm = self
+            .cgroup_manager
+            .as_ref()
+            .ok_or_else(|| anyhow!("failed to get container's cgroup manager"))?;
+
+        // the threads of each process are reniced by sched::renice
+        for pid in cgm.get_procs()? {
+            match sched::renice(pid, nice) {
+                Err(e) if sched::is_esrch(&e) => {}
+                r => r.with_context(|| format!("renice pid {}", pid))?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
 fn setgroups(grps: &[libc::gid_t]) -> Result<()> {
     let ret = unsafe { libc::setgroups(grps.len(), grps.as_ptr() as *const libc::gid_t) };
     Errno::result(ret).map(drop)?;
@@ -1531,6 +1820,7 @@ async fn execute_hook(logger: &Logger, h: &Hook, st: &OCIState) -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cgroups::DeviceMap;
     use crate::process::Process;
     use crate::skip_if_not_root;
     use std::fs;
@@ -1719,12 +2009,18 @@ mod tests {
 
         CreateOpts {
             cgroup_name: "".to_string(),
+            cgroup_root: "".to_string(),
             use_systemd_cgroup: false,
             no_pivot_root: false,
             no_new_keyring: false,
             spec: Some(spec),
             rootless_euid: false,
             rootless_cgroup: false,
+            disable_kmem_accounting: false,
+            strict_resources: false,
+            io_cost: false,
+            default_swappiness: None,
+            device_map: DeviceMap::new(),
         }
     }
 
@@ -1778,7 +2074,9 @@ mod tests {
     #[test]
     fn test_linuxcontainer_pause() {
         let ret = new_linux_container_and_then(|mut c: LinuxContainer| {
-            c.cgroup_manager = FsManager::new("").ok();
+            c.cgroup_manager = FsManager::new("")
+                .ok()
+                .map(|m| Box::new(m) as Box<dyn Manager>);
             c.pause().map_err(|e| anyhow!(e))
         });
 
@@ -1811,7 +2109,9 @@ mod tests {
     #[test]
     fn test_linuxcontainer_resume() {
         let ret = new_linux_container_and_then(|mut c: LinuxContainer| {
-            c.cgroup_manager = FsManager::new("").ok();
+            c.cgroup_manager = FsManager::new("")
+                .ok()
+                .map(|m| Box::new(m) as Box<dyn Manager>);
             // Change status to paused, this way we can resume it
             c.status.transition(ContainerState::Paused);
             c.resume().map_err(|e| anyhow!(e))
@@ -1940,6 +2240,27 @@ mod tests {
         assert!(ret.is_ok(), "Expecting Ok, Got {:?}", ret);
     }
 
+    #[test]
+    fn test_signal_scope_from_str() {
+        assert_eq!(
+            "process_group".parse::<SignalScope>().unwrap(),
+            SignalScope::ProcessGroup
+        );
+        assert_eq!(
+            "cgroup".parse::<SignalScope>().unwrap(),
+            SignalScope::Cgroup
+        );
+        assert!("all".parse::<SignalScope>().is_err());
+    }
+
+    #[test]
+    fn test_oom_policy_from_str() {
+        assert_eq!("notify".parse::<OomPolicy>().unwrap(), OomPolicy::Notify);
+        assert_eq!("freeze".parse::<OomPolicy>().unwrap(), OomPolicy::Freeze);
+        assert_eq!("restart".parse::<OomPolicy>().unwrap(), OomPolicy::Restart);
+        assert!("kill".parse::<OomPolicy>().is_err());
+    }
+
     #[test]
     fn test_linuxcontainer_exec() {
         let ret = new_linux_container_and_then(|mut c: LinuxContainer| c.exec());
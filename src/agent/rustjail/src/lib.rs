@@ -32,11 +32,14 @@ pub mod capabilities;
 pub mod cgroups;
 pub mod container;
 pub mod mount;
+pub mod netstats;
 pub mod pipestream;
 pub mod process;
+pub mod sched;
 pub mod specconv;
 pub mod sync;
 pub mod sync_with_async;
+pub mod unconfined;
 pub mod utils;
 pub mod validator;
 
@@ -88,6 +91,12 @@ pub fn process_grpc_to_oci(p: &grpc::Process) -> oci::Process {
         None
     };
 
+    let scheduler = p.Scheduler.as_ref().map(|s| oci::Scheduler {
+        policy: s.Policy.clone(),
+        nice: s.Nice,
+        priority: s.Priority,
+    });
+
     let rlimits = {
         let mut r = Vec::new();
         for lm in p.Rlimits.iter() {
@@ -112,6 +121,7 @@ pub fn process_grpc_to_oci(p: &grpc::Process) -> oci::Process {
         no_new_privileges: p.NoNewPrivileges,
         apparmor_profile: p.ApparmorProfile.clone(),
         oom_score_adj: Some(p.OOMScoreAdj as i32),
+        scheduler,
         selinux_label: p.SelinuxLabel.clone(),
     }
 }
@@ -229,42 +239,64 @@ fn blockio_grpc_to_oci(blk: &grpc::LinuxBlockIO) -> oci::LinuxBlockIo {
     }
 }
 
-pub fn resources_grpc_to_oci(res: &grpc::LinuxResources) -> oci::LinuxResources {
-    let devices = {
-        let mut d = Vec::new();
-        for dev in res.Devices.iter() {
-            let major = if dev.Major == -1 {
-                None
-            } else {
-                Some(dev.Major)
-            };
+pub fn device_cgroup_grpc_to_oci(dev: &grpc::LinuxDeviceCgroup) -> oci::LinuxDeviceCgroup {
+    let major = if dev.Major == -1 {
+        None
+    } else {
+        Some(dev.Major)
+    };
 
-            let minor = if dev.Minor == -1 {
-                None
-            } else {
-                Some(dev.Minor)
-            };
-            d.push(oci::LinuxDeviceCgroup {
-                allow: dev.Allow,
-                r#type: dev.Type.clone(),
-                major,
-                minor,
-                access: dev.Access.clone(),
-            });
-        }
-        d
+    let minor = if dev.Minor == -1 {
+        None
+    } else {
+        Some(dev.Minor)
     };
 
+    oci::LinuxDeviceCgroup {
+        allow: dev.Allow,
+        r#type: dev.Type.clone(),
+        major,
+        minor,
+        access: dev.Access.clone(),
+    }
+}
+
+pub fn device_cgroup_oci_to_grpc(dev: &oci::LinuxDeviceCgroup) -> grpc::LinuxDeviceCgroup {
+    grpc::LinuxDeviceCgroup {
+        Allow: dev.allow,
+        Type: dev.r#type.clone(),
+        Major: dev.major.unwrap_or(-1),
+        Minor: dev.minor.unwrap_or(-1),
+        Access: dev.access.clone(),
+        ..Default::default()
+    }
+}
+
+// present returns the value of a field having a presence flag, proto3 not
+// telling an unset field from 0 otherwise. The older runtimes set no flag,
+// their values being taken as set unless 0.
+fn present<T: Default + PartialEq>(v: T, set: bool) -> Option<T> {
+    if set || v != T::default() {
+        Some(v)
+    } else {
+        None
+    }
+}
+
+pub fn resources_grpc_to_oci(res: &grpc::LinuxResources) -> oci::LinuxResources {
+    let devices = res.Devices.iter().map(device_cgroup_grpc_to_oci).collect();
+
     let memory = if res.Memory.is_some() {
         let mem = res.Memory.as_ref().unwrap();
         Some(oci::LinuxMemory {
-            limit: Some(mem.Limit),
-            reservation: Some(mem.Reservation),
-            swap: Some(mem.Swap),
-            kernel: Some(mem.Kernel),
-            kernel_tcp: Some(mem.KernelTCP),
-            swappiness: Some(mem.Swappiness as i64),
+            limit: present(mem.Limit, mem.LimitSet),
+            reservation: present(mem.Reservation, mem.ReservationSet),
+            swap: present(mem.Swap, mem.SwapSet),
+            kernel: present(mem.Kernel, mem.KernelSet),
+            kernel_tcp: present(mem.KernelTCP, mem.KernelTCPSet),
+            swappiness: present(mem.Swappiness, mem.SwappinessSet).map(|s| s as i64),
             disable_oom_killer: Some(mem.DisableOOMKiller),
+            high: present(mem.High, mem.HighSet),
         })
     } else {
         None
@@ -280,6 +312,9 @@ pub fn resources_grpc_to_oci(res: &grpc::LinuxResources) -> oci::LinuxResources
             realtime_period: Some(c.RealtimePeriod),
             cpus: c.Cpus.clone(),
             mems: c.Mems.clone(),
+            burst: if c.BurstSet { Some(c.Burst) } else { None },
+            idle: if c.IdleSet { Some(c.Idle) } else { None },
+            cpus_partition: c.CpusPartition.clone(),
         })
     } else {
         None
@@ -340,6 +375,8 @@ pub fn resources_grpc_to_oci(res: &grpc::LinuxResources) -> oci::LinuxResources
         hugepage_limits,
         network,
         rdma: HashMap::new(),
+        unified: res.Unified.clone(),
+        misc: res.Misc.clone(),
     }
 }
 
@@ -433,7 +470,9 @@ fn linux_grpc_to_oci(l: &grpc::Linux) -> oci::Linux {
         let rdt = l.IntelRdt.as_ref().unwrap();
 
         Some(oci::LinuxIntelRdt {
+            clos_id: rdt.ClosID.clone(),
             l3_cache_schema: rdt.L3CacheSchema.clone(),
+            mem_bw_schema: rdt.MemBwSchema.clone(),
         })
     } else {
         None
@@ -511,6 +550,8 @@ pub fn grpc_to_oci(grpc: &grpc::Spec) -> oci::Spec {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     #[macro_export]
     macro_rules! skip_if_not_root {
         () => {
@@ -520,4 +561,22 @@ mod tests {
             }
         };
     }
+
+    #[test]
+    fn test_resources_grpc_to_oci_memory() {
+        let mut r = grpc::LinuxResources::default();
+        r.set_Memory(grpc::LinuxMemory {
+            Limit: 1 << 30,
+            Swappiness: 0,
+            SwappinessSet: true,
+            ..Default::default()
+        });
+
+        let memory = resources_grpc_to_oci(&r).memory.unwrap();
+        assert_eq!(memory.limit, Some(1 << 30));
+        assert_eq!(memory.swappiness, Some(0));
+        assert_eq!(memory.reservation, None);
+        assert_eq!(memory.swap, None);
+        assert_eq!(memory.high, None);
+    }
 }
@@ -175,6 +175,13 @@ pub fn init_rootfs(
         .to_str()
         .ok_or_else(|| anyhow!("Could not convert rootfs path to string"))?;
 
+    // the container has its own cgroup namespace, which was entered before
+    // setting up the rootfs
+    let cgroupns = linux
+        .namespaces
+        .iter()
+        .any(|ns| ns.r#type == oci::CGROUPNAMESPACE);
+
     mount(None::<&str>, "/", None::<&str>, flags, None::<&str>)?;
 
     rootfs_parent_mount_private(rootfs)?;
@@ -198,7 +205,7 @@ pub fn init_rootfs(
         }
 
         if m.r#type == "cgroup" {
-            mount_cgroups(cfd_log, &m, rootfs, flags, &data, cpath, mounts)?;
+            mount_cgroups(cfd_log, &m, rootfs, flags, &data, cpath, mounts, cgroupns)?;
         } else {
             if m.destination == "/dev" {
                 if m.r#type == "bind" {
@@ -318,6 +325,8 @@ fn mount_cgroups_v2(cfd_log: RawFd, m: &Mount, rootfs: &str, flags: MsFlags) ->
     let olddir = unistd::getcwd()?;
     unistd::chdir(rootfs)?;
 
+    // In a cgroup namespace, the root of the new mount is the cgroup of the
+    // container.
     // https://github.com/opencontainers/runc/blob/09ddc63afdde16d5fb859a1d3ab010bd45f08497/libcontainer/rootfs_linux.go#L287
     let bm = Mount {
         source: "cgroup".to_string(),
@@ -346,6 +355,22 @@ fn mount_cgroups_v2(cfd_log: RawFd, m: &Mount, rootfs: &str, flags: MsFlags) ->
     Ok(())
 }
 
+// cgroup_subsystems returns the mount options selecting the cgroup v1
+// hierarchy of `key`, mounted at a directory named `base`. The hierarchy
+// named "name=systemd" in /proc/self/cgroup has the key "systemd", see
+// get_paths.
+fn cgroup_subsystems(key: &str, base: &str) -> String {
+    if key == "systemd" {
+        "none,name=systemd".to_string()
+    } else if key.starts_with("name=") {
+        // named hierarchy without controller
+        format!("none,{}", key)
+    } else {
+        base.to_string()
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn mount_cgroups(
     cfd_log: RawFd,
     m: &Mount,
@@ -354,6 +379,7 @@ fn mount_cgroups(
     _data: &str,
     cpath: &HashMap<String, String>,
     mounts: &HashMap<String, String>,
+    cgroupns: bool,
 ) -> Result<()> {
     if cgroups::hierarchies::is_cgroup2_unified_mode() {
         return mount_cgroups_v2(cfd_log, &m, rootfs, flags);
@@ -405,18 +431,42 @@ fn mount_cgroups(
 
         log_child!(cfd_log, "mount destination: {}", destination.as_str());
 
-        let bm = Mount {
-            source: source.to_string(),
-            r#type: "bind".to_string(),
-            destination: destination.clone(),
-            options: Vec::new(),
-        };
+        if cgroupns {
+            // a new mount of the hierarchy is rooted at the cgroup of the
+            // container, like its /proc/self/cgroup
+            let cm = Mount {
+                source: "cgroup".to_string(),
+                r#type: "cgroup".to_string(),
+                destination: destination.clone(),
+                options: Vec::new(),
+            };
 
-        let mut mount_flags: MsFlags = flags | MsFlags::MS_REC | MsFlags::MS_BIND;
-        if key.contains("systemd") {
-            mount_flags &= !MsFlags::MS_RDONLY;
+            let mut mount_flags = flags;
+            if key.contains("systemd") {
+                mount_flags &= !MsFlags::MS_RDONLY;
+            }
+            mount_from(
+                cfd_log,
+                &cm,
+                rootfs,
+                mount_flags,
+                &cgroup_subsystems(key, base),
+                "",
+            )?;
+        } else {
+            let bm = Mount {
+                source: source.to_string(),
+                r#type: "bind".to_string(),
+                destination: destination.clone(),
+                options: Vec::new(),
+            };
+
+            let mut mount_flags: MsFlags = flags | MsFlags::MS_REC | MsFlags::MS_BIND;
+            if key.contains("systemd") {
+                mount_flags &= !MsFlags::MS_RDONLY;
+            }
+            mount_from(cfd_log, &bm, rootfs, mount_flags, "", "")?;
         }
-        mount_from(cfd_log, &bm, rootfs, mount_flags, "", "")?;
 
         if key != base {
             let src = format!("{}/{}", m.destination.as_str(), key);
@@ -1146,10 +1196,52 @@ mod tests {
             "",
             &cpath,
             &cgroup_mounts,
+            false,
         );
         assert!(ret.is_ok(), "Should pass. Got: {:?}", ret);
     }
 
+    #[test]
+    fn test_cgroup_subsystems() {
+        // as in a guest with systemd
+        let paths = crate::cgroups::fs::parse_paths(
+            "12:pids:/kata/c1
+11:cpu,cpuacct:/kata/c1
+10:memory:/kata/c1
+9:net_cls,net_prio:/kata/c1
+1:name=systemd:/kata/c1
+0::/kata/c1
+",
+        );
+
+        let mut options: Vec<String> = paths
+            .keys()
+            .filter(|k| !k.is_empty())
+            .map(|k| {
+                let base = match k.as_str() {
+                    "cpu" | "cpuacct" => "cpu,cpuacct",
+                    "net_cls" | "net_prio" => "net_cls,net_prio",
+                    k => k,
+                };
+                cgroup_subsystems(k, base)
+            })
+            .collect();
+        options.sort();
+        options.dedup();
+
+        assert_eq!(
+            options,
+            vec![
+                "cpu,cpuacct",
+                "memory",
+                "net_cls,net_prio",
+                "none,name=systemd",
+                "pids",
+            ]
+        );
+        assert_eq!(cgroup_subsystems("name=foo", "name=foo"), "none,name=foo");
+    }
+
     #[test]
     #[serial(chdir)]
     fn test_pivot_root() {
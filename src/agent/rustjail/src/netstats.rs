@@ -0,0 +1,115 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Network interface statistics of a container, read from the
+// /proc/<pid>/net/dev of its init process, i.e. for the interfaces of the
+// network namespace it is in, the one of the sandbox unless the spec sets
+// up another. The loopback is left out, as cAdvisor does.
+
+use anyhow::{Context, Result};
+use protocols::agent::NetworkStats;
+use std::fs;
+
+const LOOPBACK: &str = "lo";
+
+// the counters of a /proc/net/dev line, 8 received then 8 transmitted
+const NET_DEV_COUNTERS: usize = 16;
+
+// parse_net_dev parses the content of /proc/net/dev, made of two header
+// lines and one line per interface, e.g.
+//
+//   eth0:  1296  16  0  0  0  0  0  0  1296  16  0  0  0  0  0  0
+fn parse_net_dev(content: &str) -> Vec<NetworkStats> {
+    content
+        .lines()
+        .skip(2)
+        .filter_map(parse_net_dev_line)
+        .filter(|s| s.name != LOOPBACK)
+        .collect()
+}
+
+fn parse_net_dev_line(line: &str) -> Option<NetworkStats> {
+    let mut fields = line.splitn(2, ':');
+    let name = fields.next()?.trim();
+    let counters: Vec<u64> = fields
+        .next()?
+        .split_whitespace()
+        .map(|c| c.parse().ok())
+        .collect::<Option<_>>()?;
+
+    if name.is_empty() || counters.len() < NET_DEV_COUNTERS {
+        return None;
+    }
+
+    let mut stats = NetworkStats::new();
+    stats.name = name.to_string();
+    stats.rx_bytes = counters[0];
+    stats.rx_packets = counters[1];
+    stats.rx_errors = counters[2];
+    stats.rx_dropped = counters[3];
+    stats.tx_bytes = counters[8];
+    stats.tx_packets = counters[9];
+    stats.tx_errors = counters[10];
+    stats.tx_dropped = counters[11];
+
+    Some(stats)
+}
+
+// get_network_stats returns the statistics of the interfaces of the network
+// namespace of the process `pid`.
+pub fn get_network_stats(pid: libc::pid_t) -> Result<Vec<NetworkStats>> {
+    let path = format!("/proc/{}/net/dev", pid);
+    let content = fs::read_to_string(&path).with_context(|| format!("failed to read {}", path))?;
+
+    Ok(parse_net_dev(&content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_net_dev() {
+        let content = "Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo:     100       2    0    0    0     0          0         0      100       2    0    0    0     0       0          0
+  eth0:    1296      16    1    2    0     0          0         0     2048      20    3    4    0     0       0          0
+ bogus: 1 2 3
+";
+
+        let stats = parse_net_dev(content);
+        assert_eq!(stats.len(), 1);
+
+        let eth0 = &stats[0];
+        assert_eq!(eth0.name, "eth0");
+        assert_eq!(
+            (
+                eth0.rx_bytes,
+                eth0.rx_packets,
+                eth0.rx_errors,
+                eth0.rx_dropped
+            ),
+            (1296, 16, 1, 2)
+        );
+        assert_eq!(
+            (
+                eth0.tx_bytes,
+                eth0.tx_packets,
+                eth0.tx_errors,
+                eth0.tx_dropped
+            ),
+            (2048, 20, 3, 4)
+        );
+    }
+
+    #[test]
+    fn test_get_network_stats() {
+        // the loopback, found in any network namespace, is left out
+        let stats = get_network_stats(std::process::id() as libc::pid_t).unwrap();
+        assert!(stats.iter().all(|s| s.name != LOOPBACK));
+
+        assert!(get_network_stats(-1).is_err());
+    }
+}
@@ -7,7 +7,7 @@
 
 use nix::unistd;
 use std::{
-    fmt, io,
+    cmp, fmt, io,
     io::{Read, Result, Write},
     mem,
     os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd},
@@ -16,7 +16,11 @@ use std::{
 };
 
 use futures::ready;
-use tokio::io::{unix::AsyncFd, AsyncRead, AsyncWrite, ReadBuf};
+use tokio::io::{unix::AsyncFd, AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+
+// bounds of the adaptive read size of SizedReader
+const MIN_READ_SIZE: usize = 4 * 1024;
+const MAX_READ_SIZE: usize = 1024 * 1024;
 
 fn set_nonblocking(fd: RawFd) {
     unsafe {
@@ -168,6 +172,68 @@ impl AsyncWrite for PipeStream {
     }
 }
 
+// SizedReader reads chunks of a stream into a buffer it keeps across the
+// reads, sized after the previous reads instead of the most the caller
+// accepts: the size doubles each time a read fills the buffer and halves
+// when reads stay well below it, so that bulk output grows to fewer, larger
+// reads while the chunks returned are only as large as what was read.
+// Only the reads are sized so: the stdio and CopyFile requests each carry a
+// single buffer, which a vectored write wouldn't save a syscall or a copy on.
+#[derive(Debug)]
+pub struct SizedReader<R> {
+    inner: R,
+    size: usize,
+    buf: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> SizedReader<R> {
+    pub fn new(inner: R) -> Self {
+        SizedReader {
+            inner,
+            size: MIN_READ_SIZE,
+            buf: Vec::new(),
+        }
+    }
+
+    fn next_size(&self, max: usize) -> usize {
+        cmp::min(self.size, max)
+    }
+
+    fn observe(&mut self, n: usize, size: usize) {
+        if n == size {
+            self.size = cmp::min(self.size * 2, MAX_READ_SIZE);
+        } else if n < self.size / 4 {
+            self.size = cmp::max(self.size / 2, MIN_READ_SIZE);
+        }
+    }
+
+    // read_chunk reads at most max bytes, an empty chunk meaning eof.
+    pub async fn read_chunk(&mut self, max: usize) -> Result<Vec<u8>> {
+        if max == 0 {
+            return Ok(Vec::new());
+        }
+
+        let size = self.next_size(max);
+
+        // give the memory of a buffer grown for bulk output back once the
+        // reads shrank
+        if self.buf.capacity() > self.size * 2 {
+            self.buf = Vec::new();
+        }
+        self.buf.clear();
+        self.buf.reserve(size);
+
+        // read_buf reads into the spare capacity, without zeroing it first
+        let n = (&mut self.inner)
+            .take(size as u64)
+            .read_buf(&mut self.buf)
+            .await?;
+        self.observe(n, size);
+
+        Ok(self.buf[..n].to_vec())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -200,4 +266,53 @@ mod tests {
         // Will Block here if shutdown close the fd.
         let _ = reader2.read(&mut content).await;
     }
+
+    #[test]
+    fn test_sized_reader_size() {
+        let mut r = SizedReader::new(tokio::io::empty());
+        assert_eq!(r.next_size(1024), 1024);
+        assert_eq!(r.next_size(1 << 20), MIN_READ_SIZE);
+
+        // full reads grow the size up to the bound
+        for _ in 0..20 {
+            let size = r.next_size(usize::MAX);
+            r.observe(size, size);
+        }
+        assert_eq!(r.next_size(usize::MAX), MAX_READ_SIZE);
+
+        // a short read close to the size keeps it
+        r.observe(MAX_READ_SIZE / 2, MAX_READ_SIZE);
+        assert_eq!(r.next_size(usize::MAX), MAX_READ_SIZE);
+
+        // small reads shrink it down to the bound
+        for _ in 0..20 {
+            r.observe(1, r.next_size(usize::MAX));
+        }
+        assert_eq!(r.next_size(usize::MAX), MIN_READ_SIZE);
+    }
+
+    #[tokio::test]
+    async fn test_sized_reader_read_chunk() {
+        let (rfd, wfd) = unistd::pipe2(OFlag::O_CLOEXEC).unwrap();
+        let mut reader = SizedReader::new(PipeStream::new(rfd).unwrap());
+        let mut writer = PipeStream::new(wfd).unwrap();
+
+        writer.write_all(&[1u8; 10000]).await.unwrap();
+
+        // the chunks are only as large as what was read, the buffer being
+        // kept for the next reads
+        let chunk = reader.read_chunk(100).await.unwrap();
+        assert_eq!(chunk.len(), 100);
+        assert_eq!(chunk.capacity(), 100);
+        assert!(reader.buf.capacity() >= 100);
+
+        // the full read doubled the size
+        let chunk = reader.read_chunk(usize::MAX).await.unwrap();
+        assert_eq!(chunk.len(), MIN_READ_SIZE * 2);
+
+        drop(writer);
+        let chunk = reader.read_chunk(usize::MAX).await.unwrap();
+        assert_eq!(chunk.len(), 10000 - 100 - MIN_READ_SIZE * 2);
+        assert!(reader.read_chunk(usize::MAX).await.unwrap().is_empty());
+    }
 }
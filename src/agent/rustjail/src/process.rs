@@ -17,7 +17,7 @@ use nix::Result;
 use oci::Process as OCIProcess;
 use slog::Logger;
 
-use crate::pipestream::PipeStream;
+use crate::pipestream::{PipeStream, SizedReader};
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::io::{split, ReadHalf, WriteHalf};
@@ -35,7 +35,7 @@ pub enum StreamType {
     ParentStderr,
 }
 
-type Reader = Arc<Mutex<ReadHalf<PipeStream>>>;
+type Reader = Arc<Mutex<SizedReader<ReadHalf<PipeStream>>>>;
 type Writer = Arc<Mutex<WriteHalf<PipeStream>>>;
 
 #[derive(Debug)]
@@ -164,7 +164,7 @@ impl Process {
         let stream = PipeStream::from_fd(fd);
 
         let (reader, writer) = split(stream);
-        let reader = Arc::new(Mutex::new(reader));
+        let reader = Arc::new(Mutex::new(SizedReader::new(reader)));
         let writer = Arc::new(Mutex::new(writer));
 
         self.readers.insert(stream_type.clone(), reader.clone());
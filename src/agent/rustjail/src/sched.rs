@@ -0,0 +1,188 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Scheduling policy and nice value of the container processes, from the
+// scheduler of the OCI process, e.g. to deprioritize a sidecar against the
+// main container of a pod.
+
+use anyhow::{anyhow, Context, Result};
+use nix::errno::Errno;
+use oci::Scheduler;
+use std::fs;
+
+pub const MIN_NICE: i32 = -20;
+pub const MAX_NICE: i32 = 19;
+
+const SCHED_POLICIES: &[(&str, libc::c_int)] = &[
+    ("SCHED_OTHER", libc::SCHED_OTHER),
+    ("SCHED_BATCH", libc::SCHED_BATCH),
+    ("SCHED_IDLE", libc::SCHED_IDLE),
+    ("SCHED_FIFO", libc::SCHED_FIFO),
+    ("SCHED_RR", libc::SCHED_RR),
+];
+
+// parse_policy returns the policy named by the OCI spec, e.g. SCHED_BATCH.
+pub fn parse_policy(name: &str) -> Result<libc::c_int> {
+    SCHED_POLICIES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, p)| *p)
+        .ok_or_else(|| anyhow!("unsupported scheduling policy {:?}", name))
+}
+
+fn is_realtime(policy: libc::c_int) -> bool {
+    policy == libc::SCHED_FIFO || policy == libc::SCHED_RR
+}
+
+pub fn check_nice(nice: i32) -> Result<()> {
+    if !(MIN_NICE..=MAX_NICE).contains(&nice) {
+        return Err(anyhow!(
+            "nice value {} out of range [{}, {}]",
+            nice,
+            MIN_NICE,
+            MAX_NICE
+        ));
+    }
+
+    Ok(())
+}
+
+// validate checks the scheduler settings, the priority being for the
+// real-time policies only.
+pub fn validate(s: &Scheduler) -> Result<()> {
+    let policy = parse_policy(&s.policy)?;
+    check_nice(s.nice)?;
+
+    let (min, max) = if is_realtime(policy) {
+        unsafe {
+            (
+                libc::sched_get_priority_min(policy),
+                libc::sched_get_priority_max(policy),
+            )
+        }
+    } else {
+        (0, 0)
+    };
+    if !(min..=max).contains(&s.priority) {
+        return Err(anyhow!(
+            "priority {} out of range [{}, {}] for {}",
+            s.priority,
+            min,
+            max,
+            s.policy
+        ));
+    }
+
+    Ok(())
+}
+
+// set_scheduler applies the scheduler settings to the calling process,
+// inherited by the process it executes.
+pub fn set_scheduler(s: &Scheduler) -> Result<()> {
+    validate(s)?;
+    let policy = parse_policy(&s.policy)?;
+
+    let param = libc::sched_param {
+        sched_priority: s.priority,
+    };
+    Errno::result(unsafe { libc::sched_setscheduler(0, policy, &param) })
+        .with_context(|| format!("failed to set the scheduling policy {}", s.policy))?;
+
+    set_nice(0, s.nice)
+}
+
+// set_nice sets the nice value of a single thread, 0 being the calling one.
+fn set_nice(tid: libc::pid_t, nice: i32) -> Result<()> {
+    Errno::result(unsafe { libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, nice) })
+        .map(drop)
+        .with_context(|| format!("failed to set the nice value {} of {}", nice, tid))
+}
+
+// renice sets the nice value of every thread of a process, as Linux keeps
+// one per thread. It fails with ESRCH if the process is gone.
+pub fn renice(pid: libc::pid_t, nice: i32) -> Result<()> {
+    check_nice(nice)?;
+
+    let tasks = match fs::read_dir(format!("/proc/{}/task", pid)) {
+        Ok(t) => t,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(anyhow!(nix::Error::from_errno(Errno::ESRCH)))
+        }
+        Err(e) => return Err(anyhow!(e).context(format!("failed to list threads of {}", pid))),
+    };
+
+    for task in tasks {
+        let tid = match task?.file_name().to_string_lossy().parse::<libc::pid_t>() {
+            Ok(tid) => tid,
+            Err(_) => continue,
+        };
+
+        // the thread may be gone already
+        match set_nice(tid, nice) {
+            Err(e) if is_esrch(&e) && tid != pid => {}
+            r => r?,
+        }
+    }
+
+    Ok(())
+}
+
+pub fn is_esrch(e: &anyhow::Error) -> bool {
+    matches!(
+        e.root_cause().downcast_ref::<nix::Error>(),
+        Some(nix::Error::Sys(Errno::ESRCH))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduler(policy: &str, nice: i32, priority: i32) -> Scheduler {
+        Scheduler {
+            policy: policy.to_string(),
+            nice,
+            priority,
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        validate(&scheduler("SCHED_BATCH", 10, 0)).unwrap();
+        validate(&scheduler("SCHED_IDLE", MAX_NICE, 0)).unwrap();
+        validate(&scheduler("SCHED_FIFO", 0, 10)).unwrap();
+
+        for s in &[
+            scheduler("", 0, 0),
+            scheduler("SCHED_DEADLINE", 0, 0),
+            scheduler("SCHED_OTHER", MIN_NICE - 1, 0),
+            scheduler("SCHED_OTHER", MAX_NICE + 1, 0),
+            scheduler("SCHED_OTHER", 0, 1),
+            scheduler("SCHED_RR", 0, 0),
+        ] {
+            assert!(validate(s).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn test_renice() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        let pid = child.id() as libc::pid_t;
+
+        // raising the nice value needs no privilege
+        renice(pid, 5).unwrap();
+        let prio = unsafe { libc::getpriority(libc::PRIO_PROCESS, pid as libc::id_t) };
+        assert_eq!(prio, 5);
+
+        assert!(renice(pid, MAX_NICE + 1).is_err());
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert!(is_esrch(&renice(pid, 5).unwrap_err()));
+    }
+}
@@ -3,15 +3,25 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
+use crate::cgroups::DeviceMap;
 use oci::Spec;
 
 #[derive(Debug)]
 pub struct CreateOpts {
     pub cgroup_name: String,
+    // the cgroup the container cgroups are created below
+    pub cgroup_root: String,
     pub use_systemd_cgroup: bool,
     pub no_pivot_root: bool,
     pub no_new_keyring: bool,
     pub spec: Option<Spec>,
     pub rootless_euid: bool,
     pub rootless_cgroup: bool,
+    pub disable_kmem_accounting: bool,
+    pub strict_resources: bool,
+    pub io_cost: bool,
+    // swappiness of the containers whose spec sets none, if any
+    pub default_swappiness: Option<u64>,
+    // host -> guest numbers of the block devices of the volumes
+    pub device_map: DeviceMap,
 }
@@ -0,0 +1,81 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// The processes of the containers are spawned from a thread the agent
+// starts before confining itself, see src/confinement.rs of the agent. Its
+// seccomp filter and capability drops are per thread and can't be undone
+// once inherited, so the containers only get the ones of their spec.
+
+use anyhow::{Context, Result};
+use std::io;
+use std::process::{Child, Command};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+lazy_static! {
+    static ref SPAWNER: Mutex<Option<Sender<Job>>> = Mutex::new(None);
+}
+
+fn exited() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "the unconfined thread exited")
+}
+
+// start starts the thread spawning the processes of the containers. It has
+// to be called before the calling thread is confined.
+pub fn start() -> Result<()> {
+    let (tx, rx) = mpsc::channel::<Job>();
+
+    thread::Builder::new()
+        .name("unconfined".to_string())
+        .spawn(move || {
+            for job in rx {
+                job();
+            }
+        })
+        .context("failed to start the unconfined thread")?;
+
+    *SPAWNER.lock().unwrap() = Some(tx);
+
+    Ok(())
+}
+
+// spawn spawns `cmd` from the unconfined thread, or from the calling thread
+// if it wasn't started.
+pub fn spawn(mut cmd: Command) -> io::Result<Child> {
+    let spawner = SPAWNER.lock().unwrap().clone();
+    let spawner = match spawner {
+        Some(s) => s,
+        None => return cmd.spawn(),
+    };
+
+    let (tx, rx) = mpsc::channel();
+    spawner
+        .send(Box::new(move || {
+            let _ = tx.send(cmd.spawn());
+        }))
+        .map_err(|_| exited())?;
+
+    rx.recv().map_err(|_| exited())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spawn() {
+        // spawned from the calling thread
+        let status = spawn(Command::new("true")).unwrap().wait().unwrap();
+        assert!(status.success());
+
+        start().unwrap();
+        let status = spawn(Command::new("true")).unwrap().wait().unwrap();
+        assert!(status.success());
+        assert!(spawn(Command::new("/nonexistent")).is_err());
+    }
+}
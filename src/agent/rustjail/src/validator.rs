@@ -4,6 +4,7 @@
 //
 
 use crate::container::Config;
+use crate::sched;
 use anyhow::{anyhow, Context, Error, Result};
 use nix::errno::Errno;
 use oci::{Linux, LinuxIdMapping, LinuxNamespace, Spec};
@@ -242,6 +243,13 @@ fn rootless_euid_mount(oci: &Spec) -> Result<()> {
     Ok(())
 }
 
+fn scheduler(oci: &Spec) -> Result<()> {
+    match oci.process.as_ref().and_then(|p| p.scheduler.as_ref()) {
+        Some(s) => sched::validate(s),
+        None => Ok(()),
+    }
+}
+
 fn rootless_euid(oci: &Spec) -> Result<()> {
     rootless_euid_mapping(oci).context("rootless euid mapping")?;
     rootless_euid_mount(oci).context("rotless euid mount")?;
@@ -267,6 +275,7 @@ pub fn validate(conf: &Config) -> Result<()> {
     usernamespace(oci).context("usernamespace")?;
     cgroupnamespace(oci).context("cgroupnamespace")?;
     sysctl(&oci).context("sysctl")?;
+    scheduler(oci).context("scheduler")?;
 
     if conf.rootless_euid {
         rootless_euid(oci).context("rootless euid")?;
@@ -506,6 +515,27 @@ mod tests {
         sysctl(&spec).unwrap();
     }
 
+    #[test]
+    fn test_scheduler() {
+        let mut spec = Spec::default();
+        scheduler(&spec).unwrap();
+
+        let mut s = oci::Scheduler {
+            policy: "SCHED_BATCH".to_string(),
+            nice: 10,
+            priority: 0,
+        };
+        spec.process = Some(oci::Process {
+            scheduler: Some(s.clone()),
+            ..Default::default()
+        });
+        scheduler(&spec).unwrap();
+
+        s.nice = 20;
+        spec.process.as_mut().unwrap().scheduler = Some(s);
+        scheduler(&spec).unwrap_err();
+    }
+
     #[test]
     fn test_validate() {
         let spec = Spec::default();
@@ -0,0 +1,99 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Optional jemalloc global allocator, enabled with the "jemalloc" feature,
+// which keeps the agent footprint predictable inside small guests by
+// returning unused dirty pages to the kernel from a background thread.
+
+use anyhow::Result;
+
+#[cfg(feature = "jemalloc")]
+use anyhow::anyhow;
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+// AllocatorStats is a snapshot of the allocator statistics, in bytes.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AllocatorStats {
+    // bytes allocated by the application
+    pub allocated: u64,
+    // bytes in active pages, a multiple of the page size
+    pub active: u64,
+    // bytes in physically resident data pages mapped by the allocator
+    pub resident: u64,
+    // bytes in active extents mapped by the allocator
+    pub mapped: u64,
+    // bytes retained in virtual memory mappings
+    pub retained: u64,
+}
+
+impl AllocatorStats {
+    // fragmentation returns the ratio of the active pages which
+    // are not used by any allocation.
+    pub fn fragmentation(&self) -> f64 {
+        if self.active == 0 {
+            return 0.0;
+        }
+        (self.active.saturating_sub(self.allocated)) as f64 / self.active as f64
+    }
+}
+
+// init configures the allocator, it must be called once at startup.
+#[cfg(feature = "jemalloc")]
+pub fn init() -> Result<()> {
+    // purge dirty pages asynchronously instead of on the allocation path
+    tikv_jemalloc_ctl::background_thread::write(true)
+        .map_err(|e| anyhow!("failed to enable jemalloc background thread: {}", e))
+}
+
+#[cfg(not(feature = "jemalloc"))]
+pub fn init() -> Result<()> {
+    Ok(())
+}
+
+// stats returns the allocator statistics, or None if the
+// system allocator is used.
+#[cfg(feature = "jemalloc")]
+pub fn stats() -> Result<Option<AllocatorStats>> {
+    use tikv_jemalloc_ctl::{epoch, stats};
+
+    // statistics are cached, advance the epoch to refresh them
+    epoch::advance().map_err(|e| anyhow!("failed to refresh jemalloc stats: {}", e))?;
+
+    let read_err = |e| anyhow!("failed to read jemalloc stats: {}", e);
+
+    Ok(Some(AllocatorStats {
+        allocated: stats::allocated::read().map_err(read_err)? as u64,
+        active: stats::active::read().map_err(read_err)? as u64,
+        resident: stats::resident::read().map_err(read_err)? as u64,
+        mapped: stats::mapped::read().map_err(read_err)? as u64,
+        retained: stats::retained::read().map_err(read_err)? as u64,
+    }))
+}
+
+#[cfg(not(feature = "jemalloc"))]
+pub fn stats() -> Result<Option<AllocatorStats>> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fragmentation() {
+        let s = AllocatorStats::default();
+        assert_eq!(s.fragmentation(), 0.0);
+
+        let s = AllocatorStats {
+            allocated: 3072,
+            active: 4096,
+            ..Default::default()
+        };
+        assert_eq!(s.fragmentation(), 0.25);
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Early boot logs capture, so that the host can diagnose a sandbox
+// which failed to start before the agent came up.
+//
+// The bundle is a gzip compressed tar archive holding the kernel log
+// since boot and the initramfs and systemd logs found in the guest.
+
+use anyhow::{anyhow, Context, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use nix::errno::Errno;
+use nix::fcntl::{self, OFlag};
+use nix::sys::stat::Mode;
+use nix::unistd;
+use std::fs;
+use std::path::Path;
+use tracing::instrument;
+
+const KMSG_PATH: &str = "/dev/kmsg";
+const DMESG_ENTRY: &str = "dmesg.log";
+
+// Largest record of /dev/kmsg, see CONSOLE_EXT_LOG_MAX in the kernel.
+const KMSG_RECORD_SIZE: usize = 8192;
+
+// Files larger than this are not included in the bundle.
+const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+// size of the chunks the bundle is sent to the host in, well below the
+// message size limit of ttrpc
+pub const CHUNK_SIZE: usize = 1024 * 1024;
+
+const BOOT_LOG_PATHS: &[&str] = &[
+    "/run/initramfs/rdsosreport.txt",
+    "/run/initramfs/init.log",
+    "/run/log/journal",
+];
+
+// collect returns the compressed bundle of the early boot logs.
+#[instrument]
+pub fn collect() -> Result<Vec<u8>> {
+    let encoder = GzEncoder::new(Vec::new(), Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+
+    let dmesg = read_kmsg(KMSG_PATH).unwrap_or_else(|e| format!("{:?}", e).into_bytes());
+    append_data(&mut builder, DMESG_ENTRY, &dmesg)?;
+
+    for path in BOOT_LOG_PATHS {
+        append_path(&mut builder, Path::new(path))?;
+    }
+
+    let encoder = builder
+        .into_inner()
+        .context("failed to build boot logs archive")?;
+    let data = encoder.finish().context("failed to compress boot logs")?;
+
+    Ok(data)
+}
+
+// chunk returns the part of the bundle `data` at offset, and whether it is
+// the last chunk.
+pub fn chunk(data: &[u8], offset: u64) -> Result<(&[u8], bool)> {
+    let offset = offset as usize;
+    if offset > data.len() {
+        return Err(anyhow!(
+            "offset {} beyond the boot logs size {}",
+            offset,
+            data.len()
+        ));
+    }
+
+    let end = std::cmp::min(offset + CHUNK_SIZE, data.len());
+
+    Ok((&data[offset..end], end == data.len()))
+}
+
+// read_kmsg reads all the kernel log records available without blocking.
+fn read_kmsg(path: &str) -> Result<Vec<u8>> {
+    let fd = fcntl::open(path, OFlag::O_RDONLY | OFlag::O_NONBLOCK, Mode::empty())
+        .with_context(|| format!("failed to open {}", path))?;
+    defer!(unistd::close(fd).unwrap_or(()));
+
+    let mut logs = Vec::new();
+    let mut buf = [0u8; KMSG_RECORD_SIZE];
+
+    loop {
+        match unistd::read(fd, &mut buf) {
+            Ok(0) => break,
+            Ok(n) => logs.extend_from_slice(&buf[..n]),
+            // the record was overwritten in the ring buffer, skip it.
+            Err(nix::Error::Sys(Errno::EPIPE)) => continue,
+            Err(nix::Error::Sys(Errno::EAGAIN)) => break,
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path)),
+        }
+    }
+
+    Ok(logs)
+}
+
+fn append_data<W: std::io::Write>(
+    builder: &mut tar::Builder<W>,
+    name: &str,
+    data: &[u8],
+) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+
+    builder
+        .append_data(&mut header, name, data)
+        .with_context(|| format!("failed to add {} to boot logs", name))
+}
+
+// append_path adds the file, or the files of the directory, found at path.
+fn append_path<W: std::io::Write>(builder: &mut tar::Builder<W>, path: &Path) -> Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(m) => m,
+        Err(_) => return Ok(()),
+    };
+
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)?.flatten() {
+            append_path(builder, &entry.path())?;
+        }
+        return Ok(());
+    }
+
+    if !metadata.is_file() || metadata.len() > MAX_FILE_SIZE {
+        return Ok(());
+    }
+
+    let data = fs::read(path)?;
+    let name = path.to_string_lossy();
+    append_data(builder, name.trim_start_matches('/'), &data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_append_path() {
+        let dir = tempdir().unwrap();
+        let sub = dir.path().join("journal");
+        fs::create_dir(&sub).unwrap();
+        fs::write(dir.path().join("init.log"), "init").unwrap();
+        fs::write(sub.join("system.journal"), "journal").unwrap();
+
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        append_data(&mut builder, DMESG_ENTRY, b"dmesg").unwrap();
+        append_path(&mut builder, dir.path()).unwrap();
+        append_path(&mut builder, &dir.path().join("enoent")).unwrap();
+        let data = builder.into_inner().unwrap().finish().unwrap();
+
+        let mut archive = tar::Archive::new(GzDecoder::new(data.as_slice()));
+        let mut entries: Vec<(String, String)> = archive
+            .entries()
+            .unwrap()
+            .map(|e| {
+                let mut e = e.unwrap();
+                let mut content = String::new();
+                e.read_to_string(&mut content).unwrap();
+                (e.path().unwrap().to_string_lossy().to_string(), content)
+            })
+            .collect();
+        entries.sort();
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0], (DMESG_ENTRY.to_string(), "dmesg".to_string()));
+        assert!(entries[1].0.ends_with("init.log"));
+        assert!(entries[2].0.ends_with("journal/system.journal"));
+    }
+
+    #[test]
+    fn test_chunk() {
+        let data = vec![7u8; CHUNK_SIZE + 10];
+
+        let (c, eof) = chunk(&data, 0).unwrap();
+        assert_eq!((c.len(), eof), (CHUNK_SIZE, false));
+
+        let (c, eof) = chunk(&data, CHUNK_SIZE as u64).unwrap();
+        assert_eq!((c.len(), eof), (10, true));
+
+        let (c, eof) = chunk(&[], 0).unwrap();
+        assert_eq!((c.len(), eof), (0, true));
+
+        assert!(chunk(&data, data.len() as u64 + 1).is_err());
+    }
+}
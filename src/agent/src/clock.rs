@@ -0,0 +1,797 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Timezone and RTC mode of the guest, set from the pod spec since the guest
+// image runs in UTC whatever the workload expects.
+//
+// The timezone data is copied out of the zoneinfo of the guest and bind
+// mounted on /etc/localtime, as for /etc/resolv.conf, the guest rootfs being
+// read-only, and in the containers, which don't see the one of the guest.
+// The RTC mode tells the kernel whether the hardware clock keeps UTC or the
+// local time, through the kernel timezone, which is the current offset of
+// the timezone, moved along at the DST changes. The guest gets its time in
+// UTC from kvm-clock or the host whatever the mode, so the kernel must never
+// move the system time by the offset as it does for a local RTC read at boot.
+
+use anyhow::{anyhow, Context, Result};
+use nix::errno::Errno;
+use nix::mount::{self, MsFlags};
+use oci::{Mount, Spec};
+use protocols::agent::{ClockConfig, RtcMode};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+
+// Convenience macro to obtain the scope logger
+macro_rules! sl {
+    () => {
+        slog_scope::logger().new(o!("subsystem" => "clock"))
+    };
+}
+
+const ZONEINFO_DIR: &str = "/usr/share/zoneinfo";
+const KATA_GUEST_SANDBOX_LOCALTIME_FILE: &str = "/run/kata-containers/sandbox/localtime";
+const GUEST_LOCALTIME_FILE: &str = "/etc/localtime";
+
+// size of the TZif header, see tzfile(5)
+const TZIF_HEADER_SIZE: usize = 44;
+
+const SECS_PER_DAY: i64 = 86400;
+
+lazy_static! {
+    // the task moving the kernel timezone along with the local time offset
+    static ref DST_TASK: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+
+    static ref KERNEL_TZ: Mutex<KernelTimezone> = Mutex::new(KernelTimezone::default());
+}
+
+// set_clock applies the clock configuration of the sandbox, an empty
+// timezone keeping the current one.
+pub fn set_clock(clock: &ClockConfig) -> Result<()> {
+    let mut tz = KERNEL_TZ.lock().unwrap();
+    tz.check(clock.rtc_mode)?;
+
+    if !clock.timezone.is_empty() {
+        let zone = zoneinfo_path(Path::new(ZONEINFO_DIR), &clock.timezone)?;
+        set_localtime(
+            &zone,
+            Path::new(KATA_GUEST_SANDBOX_LOCALTIME_FILE),
+            Path::new(GUEST_LOCALTIME_FILE),
+        )?;
+    }
+
+    let mut task = DST_TASK.lock().unwrap();
+    if let Some(t) = task.take() {
+        t.abort();
+    }
+
+    match clock.rtc_mode {
+        RtcMode::RTC_UTC => tz.set_utc(set_kernel_timezone),
+        RtcMode::RTC_LOCAL => {
+            if let Some(next) = set_local_offset(&mut tz)? {
+                *task = Some(tokio::spawn(follow_local_offset(next)));
+            }
+            Ok(())
+        }
+    }
+}
+
+// add_localtime_mount bind mounts the timezone of the sandbox on the
+// /etc/localtime of a container, unless none was set or the container has
+// its own.
+pub fn add_localtime_mount(spec: &mut Spec) {
+    add_localtime_mount_from(spec, Path::new(KATA_GUEST_SANDBOX_LOCALTIME_FILE))
+}
+
+fn add_localtime_mount_from(spec: &mut Spec, src: &Path) {
+    if !src.is_file()
+        || spec
+            .mounts
+            .iter()
+            .any(|m| Path::new(&m.destination) == Path::new(GUEST_LOCALTIME_FILE))
+    {
+        return;
+    }
+
+    spec.mounts.push(Mount {
+        destination: GUEST_LOCALTIME_FILE.to_string(),
+        r#type: "bind".to_string(),
+        source: src.to_string_lossy().to_string(),
+        options: vec!["rbind".to_string(), "ro".to_string()],
+    });
+}
+
+fn unix_now() -> Result<i64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64)
+}
+
+// KernelTimezone is the state of the kernel timezone set by the agent.
+#[derive(Debug, Default)]
+struct KernelTimezone {
+    // whether the first call, the only one on which the kernel moves the
+    // system time, was made
+    primed: bool,
+    // whether the hardware clock was told to keep the local time
+    local: bool,
+}
+
+impl KernelTimezone {
+    // check fails for the changes of RTC mode which can't be made: once the
+    // hardware clock was told to keep the local time, the guest may have
+    // written it as such and the kernel has no way back.
+    fn check(&self, mode: RtcMode) -> Result<()> {
+        if mode == RtcMode::RTC_UTC && self.local {
+            return Err(anyhow!(
+                "the RTC mode can't be changed from local to UTC after boot"
+            ));
+        }
+
+        Ok(())
+    }
+
+    // prime makes the first call with a UTC offset, which leaves the system
+    // time alone, unless it was made already.
+    fn prime<F>(&mut self, settz: &mut F) -> Result<()>
+    where
+        F: FnMut(i32) -> Result<()>,
+    {
+        if !self.primed {
+            settz(0)?;
+            self.primed = true;
+        }
+
+        Ok(())
+    }
+
+    fn set_utc<F>(&mut self, mut settz: F) -> Result<()>
+    where
+        F: FnMut(i32) -> Result<()>,
+    {
+        self.check(RtcMode::RTC_UTC)?;
+        self.prime(&mut settz)?;
+
+        settz(0)
+    }
+
+    fn set_local<F>(&mut self, offset: i32, mut settz: F) -> Result<()>
+    where
+        F: FnMut(i32) -> Result<()>,
+    {
+        self.prime(&mut settz)?;
+        settz(offset)?;
+        self.local = true;
+
+        Ok(())
+    }
+}
+
+// set_local_offset sets the kernel timezone to the current offset of the
+// local time, returning when the offset changes next.
+fn set_local_offset(tz: &mut KernelTimezone) -> Result<Option<i64>> {
+    let data = fs::read(GUEST_LOCALTIME_FILE)
+        .with_context(|| format!("failed to read {}", GUEST_LOCALTIME_FILE))?;
+    let (offset, next) = utc_offset(&data, unix_now()?)?;
+    tz.set_local(offset, set_kernel_timezone)?;
+
+    Ok(next)
+}
+
+// follow_local_offset sets the kernel timezone again each time the offset
+// of the local time changes, starting at `next`.
+async fn follow_local_offset(mut next: i64) {
+    loop {
+        let delay = next - unix_now().unwrap_or(next);
+        tokio::time::sleep(Duration::from_secs(delay.max(0) as u64 + 1)).await;
+
+        let res = set_local_offset(&mut KERNEL_TZ.lock().unwrap());
+        match res {
+            Ok(Some(n)) => next = n,
+            Ok(None) => return,
+            Err(e) => {
+                warn!(sl!(), "failed to follow the local time offset"; "error" => format!("{:?}", e));
+                return;
+            }
+        }
+    }
+}
+
+// zoneinfo_path returns the timezone data file of an IANA timezone name,
+// e.g. "Europe/Paris".
+fn zoneinfo_path(dir: &Path, tz: &str) -> Result<PathBuf> {
+    let p = Path::new(tz);
+    if !p.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(anyhow!("invalid timezone {:?}", tz));
+    }
+
+    let zone = dir.join(p);
+    if !zone.is_file() {
+        return Err(anyhow!("unknown timezone {:?}", tz));
+    }
+
+    Ok(zone)
+}
+
+// set_localtime copies the timezone data to src, and bind mounts it on dst
+// unless it is already, the following updates going through the mount.
+fn set_localtime(zone: &Path, src: &Path, dst: &Path) -> Result<()> {
+    if let Some(dir) = src.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(src, fs::read(zone)?).with_context(|| format!("failed to write {:?}", src))?;
+
+    if !dst.exists() {
+        fs::write(dst, "").with_context(|| format!("failed to create {:?}", dst))?;
+    }
+
+    let (s, d) = (fs::metadata(src)?, fs::metadata(dst)?);
+    if s.dev() == d.dev() && s.ino() == d.ino() {
+        return Ok(());
+    }
+
+    mount::mount(Some(src), dst, Some("bind"), MsFlags::MS_BIND, None::<&str>)
+        .map_err(|e| anyhow!(e).context("failed to set up the guest timezone"))
+}
+
+// set_kernel_timezone sets the offset to UTC, in seconds, of the hardware
+// clock. The first call ever moves the system time by the offset, see
+// KernelTimezone.
+fn set_kernel_timezone(offset: i32) -> Result<()> {
+    let tz = libc::timezone {
+        tz_minuteswest: -offset / 60,
+        tz_dsttime: 0,
+    };
+
+    let ret = unsafe {
+        libc::settimeofday(
+            std::ptr::null::<libc::timeval>(),
+            &tz as *const libc::timezone,
+        )
+    };
+
+    Errno::result(ret)
+        .map(drop)
+        .context("failed to set the kernel timezone")
+}
+
+fn be_u32(b: &[u8]) -> usize {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize
+}
+
+fn be_i64(b: &[u8]) -> i64 {
+    let mut a = [0u8; 8];
+    a.copy_from_slice(&b[..8]);
+    i64::from_be_bytes(a)
+}
+
+// block_size returns the size of the data block following a TZif header,
+// for times of time_size bytes.
+fn block_size(header: &[u8], time_size: usize) -> usize {
+    let counts: Vec<usize> = header[20..TZIF_HEADER_SIZE].chunks(4).map(be_u32).collect();
+    let (isut, isstd, leap, time, typ, chars) = (
+        counts[0], counts[1], counts[2], counts[3], counts[4], counts[5],
+    );
+
+    time * (time_size + 1) + typ * 6 + chars + leap * (time_size + 4) + isstd + isut
+}
+
+// utc_offset returns the offset to UTC, in seconds, of the timezone data
+// at time `now`, and when it changes next if it does. The offset comes
+// from the transition times of the TZif data, or from the TZ rule of its
+// footer after the last transition.
+fn utc_offset(data: &[u8], now: i64) -> Result<(i32, Option<i64>)> {
+    let err = || anyhow!("invalid timezone data");
+
+    if data.len() < TZIF_HEADER_SIZE || &data[..4] != b"TZif" {
+        return Err(err());
+    }
+
+    // the version 1 data block, with 32 bits times, is followed by a
+    // version 2 header and block, with 64 bits times, and the footer
+    let (data, time_size, rule) = if data[4] >= b'2' {
+        let v2 = data
+            .get(TZIF_HEADER_SIZE + block_size(data, 4)..)
+            .ok_or_else(err)?;
+        if v2.len() < TZIF_HEADER_SIZE || &v2[..4] != b"TZif" {
+            return Err(err());
+        }
+
+        let footer = v2
+            .get(TZIF_HEADER_SIZE + block_size(v2, 8)..)
+            .unwrap_or_default();
+        let rule = std::str::from_utf8(footer)
+            .ok()
+            .and_then(|f| f.lines().nth(1))
+            .filter(|tz| !tz.is_empty())
+            .map(|tz| TzRule::parse(tz).ok_or_else(|| anyhow!("invalid TZ rule {:?}", tz)))
+            .transpose()?;
+
+        (v2, 8, rule)
+    } else {
+        (data, 4, None)
+    };
+
+    let timecnt = be_u32(&data[32..36]);
+    let typecnt = be_u32(&data[36..40]);
+    if typecnt == 0 {
+        return Err(err());
+    }
+
+    let times = TZIF_HEADER_SIZE;
+    let indices = times + timecnt * time_size;
+    let types = indices + timecnt;
+    if data.len() < types + typecnt * 6 {
+        return Err(err());
+    }
+
+    let time_at = |i: usize| {
+        let b = &data[times + i * time_size..];
+        if time_size == 8 {
+            be_i64(b)
+        } else {
+            be_u32(b) as i32 as i64
+        }
+    };
+
+    let mut typ = 0;
+    let mut next = None;
+    for i in 0..timecnt {
+        if time_at(i) > now {
+            next = Some(time_at(i));
+            break;
+        }
+        typ = data[indices + i] as usize;
+    }
+    if typ >= typecnt {
+        return Err(err());
+    }
+
+    match rule {
+        Some(rule) if next.is_none() => Ok(rule.offset_at(now)),
+        _ => Ok((be_u32(&data[types + typ * 6..]) as i32, next)),
+    }
+}
+
+fn is_leap(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+// days_from_civil returns the days from the epoch to a date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146097 + doe - 719468
+}
+
+// year_of returns the year of a time.
+fn year_of(t: i64) -> i64 {
+    let mut year = 1970 + t.div_euclid(SECS_PER_DAY) / 365;
+    while days_from_civil(year, 1, 1) * SECS_PER_DAY > t {
+        year -= 1;
+    }
+    while days_from_civil(year + 1, 1, 1) * SECS_PER_DAY <= t {
+        year += 1;
+    }
+
+    year
+}
+
+// RuleDate is the date of a DST change in a TZ rule.
+#[derive(Debug, PartialEq)]
+enum RuleDate {
+    // Jn, the day of the year from 1 to 365, without February 29
+    Julian(i64),
+    // n, the day of the year from 0 to 365
+    Day(i64),
+    // Mm.w.d, the day d of the week w, 5 being the last, of the month m
+    Month(i64, i64, i64),
+}
+
+impl RuleDate {
+    // day returns the days from the epoch to the date in `year`.
+    fn day(&self, year: i64) -> i64 {
+        let jan1 = days_from_civil(year, 1, 1);
+
+        match *self {
+            RuleDate::Julian(n) if is_leap(year) && n >= 60 => jan1 + n,
+            RuleDate::Julian(n) => jan1 + n - 1,
+            RuleDate::Day(n) => jan1 + n,
+            RuleDate::Month(m, w, d) => {
+                let first = days_from_civil(year, m, 1);
+                let end = if m == 12 {
+                    days_from_civil(year + 1, 1, 1)
+                } else {
+                    days_from_civil(year, m + 1, 1)
+                };
+
+                // the epoch was a Thursday
+                let weekday = (first + 4).rem_euclid(7);
+                let day = first + (d - weekday).rem_euclid(7) + (w - 1) * 7;
+                if day >= end {
+                    day - 7
+                } else {
+                    day
+                }
+            }
+        }
+    }
+}
+
+// TzRule is the POSIX TZ rule of the footer of the TZif data, e.g.
+// "CET-1CEST,M3.5.0,M10.5.0/3", the offsets being east of UTC.
+#[derive(Debug, PartialEq)]
+struct TzRule {
+    std_offset: i32,
+    // the DST offset, and its start and end, as the date and the local
+    // time of the day
+    dst: Option<(i32, (RuleDate, i64), (RuleDate, i64))>,
+}
+
+impl TzRule {
+    fn parse(s: &str) -> Option<Self> {
+        let mut p = s;
+
+        skip_name(&mut p)?;
+        let std_offset = -parse_time(&mut p)? as i32;
+        if p.is_empty() {
+            return Some(TzRule {
+                std_offset,
+                dst: None,
+            });
+        }
+
+        skip_name(&mut p)?;
+        let dst_offset = if p.starts_with(',') {
+            std_offset + 3600
+        } else {
+            -parse_time(&mut p)? as i32
+        };
+
+        p = p.strip_prefix(',')?;
+        let start = parse_date(&mut p)?;
+        p = p.strip_prefix(',')?;
+        let end = parse_date(&mut p)?;
+        if !p.is_empty() {
+            return None;
+        }
+
+        Some(TzRule {
+            std_offset,
+            dst: Some((dst_offset, start, end)),
+        })
+    }
+
+    // offset_at returns the offset of the rule at time `now`, and when it
+    // changes next if it does.
+    fn offset_at(&self, now: i64) -> (i32, Option<i64>) {
+        let (dst_offset, (start, start_time), (end, end_time)) = match &self.dst {
+            Some(dst) => dst,
+            None => return (self.std_offset, None),
+        };
+
+        // the changes around now, the start being given in standard time
+        // and the end in DST
+        let year = year_of(now);
+        let mut changes = vec![];
+        for y in year - 1..=year + 1 {
+            changes.push((
+                start.day(y) * SECS_PER_DAY + start_time - self.std_offset as i64,
+                *dst_offset,
+            ));
+            changes.push((
+                end.day(y) * SECS_PER_DAY + end_time - *dst_offset as i64,
+                self.std_offset,
+            ));
+        }
+        changes.sort();
+
+        let mut offset = self.std_offset;
+        for (t, o) in changes {
+            if t > now {
+                return (offset, Some(t));
+            }
+            offset = o;
+        }
+
+        (offset, None)
+    }
+}
+
+// skip_name skips the name of a timezone, e.g. "CET" or "<+0330>".
+fn skip_name(p: &mut &str) -> Option<()> {
+    let len = if p.starts_with('<') {
+        p.find('>')? + 1
+    } else {
+        p.find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(p.len())
+    };
+    if len < 3 {
+        return None;
+    }
+
+    *p = &p[len..];
+    Some(())
+}
+
+fn parse_number(p: &mut &str) -> Option<i64> {
+    let len = p.find(|c: char| !c.is_ascii_digit()).unwrap_or(p.len());
+    let n = p[..len].parse().ok()?;
+
+    *p = &p[len..];
+    Some(n)
+}
+
+// parse_time parses a time of the form [+-]hh[:mm[:ss]], in seconds.
+fn parse_time(p: &mut &str) -> Option<i64> {
+    let sign = if p.starts_with('-') { -1 } else { 1 };
+    if p.starts_with('-') || p.starts_with('+') {
+        *p = &p[1..];
+    }
+
+    let mut secs = parse_number(p)? * 3600;
+    for unit in &[60, 1] {
+        match p.strip_prefix(':') {
+            Some(rest) => {
+                *p = rest;
+                secs += parse_number(p)? * unit;
+            }
+            None => break,
+        }
+    }
+
+    Some(sign * secs)
+}
+
+// parse_date parses the date of a DST change with its optional time, 2am
+// by default.
+fn parse_date(p: &mut &str) -> Option<(RuleDate, i64)> {
+    let date = if let Some(rest) = p.strip_prefix('M') {
+        *p = rest;
+        let m = parse_number(p)?;
+        *p = p.strip_prefix('.')?;
+        let w = parse_number(p)?;
+        *p = p.strip_prefix('.')?;
+        let d = parse_number(p)?;
+        if !(1..=12).contains(&m) || !(1..=5).contains(&w) || d > 6 {
+            return None;
+        }
+        RuleDate::Month(m, w, d)
+    } else if let Some(rest) = p.strip_prefix('J') {
+        *p = rest;
+        let n = parse_number(p)?;
+        if !(1..=365).contains(&n) {
+            return None;
+        }
+        RuleDate::Julian(n)
+    } else {
+        let n = parse_number(p)?;
+        if n > 365 {
+            return None;
+        }
+        RuleDate::Day(n)
+    };
+
+    let time = match p.strip_prefix('/') {
+        Some(rest) => {
+            *p = rest;
+            parse_time(p)?
+        }
+        None => 7200,
+    };
+
+    Some((date, time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // tzif builds version 2 timezone data with the given transitions, as
+    // (time, offset), and TZ rule, with an empty version 1 block.
+    fn tzif(transitions: &[(i64, i32)], rule: &str) -> Vec<u8> {
+        let header = |timecnt: usize, typecnt: usize| {
+            let mut h = b"TZif2".to_vec();
+            h.extend_from_slice(&[0u8; 15]);
+            for c in &[0, 0, 0, timecnt, typecnt, 0] {
+                h.extend_from_slice(&(*c as u32).to_be_bytes());
+            }
+            h
+        };
+
+        // a version 1 block with one type
+        let mut d = header(0, 1);
+        d.extend_from_slice(&[0u8; 6]);
+
+        d.extend(header(transitions.len(), transitions.len() + 1));
+        for (t, _) in transitions {
+            d.extend_from_slice(&t.to_be_bytes());
+        }
+        for i in 0..transitions.len() {
+            d.push(i as u8 + 1);
+        }
+        // the type before the first transition is UTC
+        d.extend_from_slice(&[0u8; 6]);
+        for (_, off) in transitions {
+            d.extend_from_slice(&off.to_be_bytes());
+            d.extend_from_slice(&[0, 0]);
+        }
+        if !rule.is_empty() {
+            d.extend(format!("\n{}\n", rule).into_bytes());
+        }
+        d
+    }
+
+    // at returns the time of a UTC date and hour.
+    fn at(year: i64, month: i64, day: i64, hour: i64) -> i64 {
+        days_from_civil(year, month, day) * SECS_PER_DAY + hour * 3600
+    }
+
+    #[test]
+    fn test_utc_offset() {
+        let d = tzif(&[(1000, 3600), (2000, 7200)], "");
+        assert_eq!(utc_offset(&d, 500).unwrap(), (0, Some(1000)));
+        assert_eq!(utc_offset(&d, 1000).unwrap(), (3600, Some(2000)));
+        assert_eq!(utc_offset(&d, 1999).unwrap(), (3600, Some(2000)));
+        assert_eq!(utc_offset(&d, 5000).unwrap(), (7200, None));
+
+        let d = tzif(&[(1000, -18000)], "");
+        assert_eq!(utc_offset(&d, 5000).unwrap(), (-18000, None));
+
+        // the rule takes over after the last transition
+        let d = tzif(&[(1000, 3600)], "CET-1CEST,M3.5.0,M10.5.0/3");
+        assert_eq!(utc_offset(&d, 500).unwrap(), (0, Some(1000)));
+        assert_eq!(
+            utc_offset(&d, at(2021, 7, 1, 0)).unwrap(),
+            (7200, Some(at(2021, 10, 31, 1)))
+        );
+
+        assert!(utc_offset(&tzif(&[], "CET-1CEST,M3"), 0).is_err());
+        assert!(utc_offset(b"TZif", 0).is_err());
+        assert!(utc_offset(&d[..40], 0).is_err());
+        assert!(utc_offset(&[0u8; 64], 0).is_err());
+    }
+
+    #[test]
+    fn test_days_from_civil() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+
+        assert_eq!(year_of(0), 1970);
+        assert_eq!(year_of(-1), 1969);
+        assert_eq!(year_of(at(2024, 12, 31, 23)), 2024);
+        assert_eq!(year_of(at(2025, 1, 1, 0)), 2025);
+    }
+
+    #[test]
+    fn test_tz_rule() {
+        let cet = TzRule::parse("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        assert_eq!(cet.std_offset, 3600);
+
+        // the last Sundays of March and October 2021 were the 28th and 31st
+        let start = at(2021, 3, 28, 1);
+        let end = at(2021, 10, 31, 1);
+        assert_eq!(cet.offset_at(at(2021, 1, 1, 0)), (3600, Some(start)));
+        assert_eq!(cet.offset_at(start - 1), (3600, Some(start)));
+        assert_eq!(cet.offset_at(start), (7200, Some(end)));
+        assert_eq!(cet.offset_at(end), (3600, Some(at(2022, 3, 27, 1))));
+
+        // DST over the new year
+        let aest = TzRule::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(aest.offset_at(at(2021, 1, 15, 0)).0, 39600);
+        assert_eq!(aest.offset_at(at(2021, 7, 15, 0)).0, 36000);
+
+        let est = TzRule::parse("EST5EDT,M3.2.0,M11.1.0").unwrap();
+        assert_eq!(est.offset_at(at(2021, 1, 15, 0)).0, -18000);
+        assert_eq!(est.offset_at(at(2021, 7, 15, 0)).0, -14400);
+
+        let ist = TzRule::parse("<+0330>-3:30").unwrap();
+        assert_eq!(ist.offset_at(0), (12600, None));
+
+        let julian = TzRule::parse("XXX0YYY,J60/0,300").unwrap();
+        assert_eq!(
+            julian.offset_at(at(2020, 3, 1, 0)),
+            (3600, Some(at(2020, 10, 27, 1)))
+        );
+
+        for tz in &[
+            "CE",
+            "CET-1CEST",
+            "CET-1CEST,M13.1.0,M10.5.0",
+            "CET-1CEST,M3.5.0,J0",
+        ] {
+            assert!(TzRule::parse(tz).is_none(), "{}", tz);
+        }
+    }
+
+    #[test]
+    fn test_kernel_timezone() {
+        let mut calls = vec![];
+        let mut tz = KernelTimezone::default();
+
+        // the first call leaves the system time alone
+        tz.set_local(3600, |o| {
+            calls.push(o);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(calls, vec![0, 3600]);
+
+        // moved along at the DST changes
+        tz.set_local(7200, |o| {
+            calls.push(o);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(calls, vec![0, 3600, 7200]);
+
+        assert!(tz.check(RtcMode::RTC_LOCAL).is_ok());
+        assert!(tz.check(RtcMode::RTC_UTC).is_err());
+        assert!(tz.set_utc(|_| panic!("set to UTC")).is_err());
+
+        let mut calls = vec![];
+        let mut tz = KernelTimezone::default();
+        tz.set_utc(|o| {
+            calls.push(o);
+            Ok(())
+        })
+        .unwrap();
+        tz.set_local(-18000, |o| {
+            calls.push(o);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(calls, vec![0, 0, -18000]);
+
+        // not primed by a failed call
+        let mut tz = KernelTimezone::default();
+        assert!(tz.set_local(3600, |_| Err(anyhow!("EPERM"))).is_err());
+        assert!(!tz.primed && !tz.local);
+    }
+
+    #[test]
+    fn test_add_localtime_mount() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("localtime");
+
+        // no timezone set
+        let mut spec = Spec::default();
+        add_localtime_mount_from(&mut spec, &src);
+        assert!(spec.mounts.is_empty());
+
+        fs::write(&src, "TZif").unwrap();
+        add_localtime_mount_from(&mut spec, &src);
+        assert_eq!(spec.mounts.len(), 1);
+        assert_eq!(spec.mounts[0].destination, GUEST_LOCALTIME_FILE);
+        assert_eq!(spec.mounts[0].source, src.to_str().unwrap());
+
+        // already there
+        add_localtime_mount_from(&mut spec, &src);
+        assert_eq!(spec.mounts.len(), 1);
+    }
+
+    #[test]
+    fn test_zoneinfo_path() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("Europe")).unwrap();
+        fs::write(dir.path().join("Europe/Paris"), "").unwrap();
+
+        assert_eq!(
+            zoneinfo_path(dir.path(), "Europe/Paris").unwrap(),
+            dir.path().join("Europe/Paris")
+        );
+        assert!(zoneinfo_path(dir.path(), "Europe/Nowhere").is_err());
+        assert!(zoneinfo_path(dir.path(), "Europe").is_err());
+        assert!(zoneinfo_path(dir.path(), "../etc/passwd").is_err());
+        assert!(zoneinfo_path(dir.path(), "/etc/passwd").is_err());
+        assert!(zoneinfo_path(dir.path(), "").is_err());
+    }
+}
@@ -2,10 +2,21 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 //
+use crate::confinement::Confinement;
+use crate::dns;
+use crate::io_limits::{self, IoLimits};
+use crate::profile::Profile;
+use crate::static_dev::{self, StaticDevice};
 use crate::tracer;
+use crate::virtiofs_watchdog::WatchdogPolicy;
 use anyhow::{bail, ensure, Context, Result};
+use oci::Scheduler;
+use rustjail::cgroups::fs::validate_swappiness;
+use rustjail::cgroups::{self, DEFAULT_CGROUP_ROOT};
+use rustjail::sched;
 use std::env;
 use std::fs;
+use std::net::SocketAddr;
 use std::time;
 use tracing::instrument;
 
@@ -19,10 +30,42 @@ const DEBUG_CONSOLE_VPORT_OPTION: &str = "agent.debug_console_vport";
 const LOG_VPORT_OPTION: &str = "agent.log_vport";
 const CONTAINER_PIPE_SIZE_OPTION: &str = "agent.container_pipe_size";
 const UNIFIED_CGROUP_HIERARCHY_OPTION: &str = "agent.unified_cgroup_hierarchy";
+const REQUIRE_VERITY_ROOTFS_OPTION: &str = "agent.require_verity_rootfs";
+const BOOT_LOG_CAPTURE_FLAG: &str = "agent.boot_log_capture";
+const DISABLE_KMEM_ACCOUNTING_FLAG: &str = "agent.disable_kmem_accounting";
+const STRICT_RESOURCES_FLAG: &str = "agent.strict_resources";
+const IO_COST_FLAG: &str = "agent.io_cost";
+const ROOTLESS_CGROUPS_FLAG: &str = "agent.rootless_cgroups";
+const MULTI_SANDBOX_FLAG: &str = "agent.multi_sandbox";
+const METRICS_MAX_CONTAINERS_OPTION: &str = "agent.metrics_max_containers";
+const USAGE_HISTORY_INTERVAL_OPTION: &str = "agent.usage_history_interval";
+const USAGE_HISTORY_SIZE_OPTION: &str = "agent.usage_history_size";
+const PROFILE_OPTION: &str = "agent.profile";
+const CONFINEMENT_OPTION: &str = "agent.confinement";
+const MEMORY_DUMP_MAX_SIZE_OPTION: &str = "agent.memory_dump_max_size";
+const STATIC_DEVICES_OPTION: &str = "agent.static_devices";
+const DEFAULT_IO_LIMITS_OPTION: &str = "agent.default_io_limits";
+const CONTAINER_NICE_OPTION: &str = "agent.container_nice";
+const CONTAINER_SCHED_POLICY_OPTION: &str = "agent.container_sched_policy";
+const FD_SAMPLE_INTERVAL_OPTION: &str = "agent.fd_sample_interval";
+const VIRTIOFS_WATCHDOG_INTERVAL_OPTION: &str = "agent.virtiofs_watchdog_interval";
+const VIRTIOFS_WATCHDOG_POLICY_OPTION: &str = "agent.virtiofs_watchdog_policy";
+const FROZEN_WAIT_OPTION: &str = "agent.frozen_wait";
+const GRPC_SERVER_ADDR_OPTION: &str = "agent.grpc_server_addr";
+const DEBUG_HTTP_ADDR_OPTION: &str = "agent.debug_http_addr";
+const DNS_UPSTREAMS_OPTION: &str = "agent.dns_upstreams";
+const CGROUP_ROOT_OPTION: &str = "agent.cgroup_root";
+const DEFAULT_SWAPPINESS_OPTION: &str = "agent.default_swappiness";
 
 const DEFAULT_LOG_LEVEL: slog::Level = slog::Level::Info;
 const DEFAULT_HOTPLUG_TIMEOUT: time::Duration = time::Duration::from_secs(3);
 const DEFAULT_CONTAINER_PIPE_SIZE: i32 = 0;
+const DEFAULT_METRICS_MAX_CONTAINERS: usize = 256;
+// five minutes of container usage history
+const DEFAULT_USAGE_HISTORY_INTERVAL: usize = 10;
+const DEFAULT_USAGE_HISTORY_SIZE: usize = 30;
+const DEFAULT_FD_SAMPLE_INTERVAL: usize = 0;
+const DEFAULT_VIRTIOFS_WATCHDOG_INTERVAL: usize = 0;
 const VSOCK_ADDR: &str = "vsock://-1";
 const VSOCK_PORT: u16 = 1024;
 
@@ -59,6 +102,58 @@ pub struct AgentConfig {
     pub server_addr: String,
     pub unified_cgroup_hierarchy: bool,
     pub tracing: tracer::TraceType,
+    pub require_verity_rootfs: bool,
+    pub boot_log_capture: bool,
+    pub disable_kmem_accounting: bool,
+    pub strict_resources: bool,
+    pub io_cost: bool,
+    pub rootless_cgroups: bool,
+    pub metrics_max_containers: usize,
+    pub usage_history_interval: usize,
+    pub usage_history_size: usize,
+    pub profile: Profile,
+    // policy the agent confines itself with, the one of the profile if
+    // None
+    pub confinement: Option<Confinement>,
+    // bytes of memory a process dump holds at most, no dump if 0
+    pub memory_dump_max_size: usize,
+    // device nodes created at startup, for the guests without udev
+    pub static_devices: Vec<StaticDevice>,
+    // IO limits of the containers setting none, per device class
+    pub default_io_limits: Vec<IoLimits>,
+    // nice value and scheduling policy of the container init processes
+    // whose spec sets no scheduler, left alone if 0 and empty
+    pub container_nice: i32,
+    pub container_sched_policy: String,
+    // seconds between the samples of the container fds, none if 0, as
+    // they walk the /proc of every container process
+    pub fd_sample_interval: usize,
+    // seconds between the checks of the virtio-fs mounts, none if 0
+    pub virtiofs_watchdog_interval: usize,
+    // what is done with the stalled virtio-fs mounts
+    pub virtiofs_watchdog_policy: WatchdogPolicy,
+    // seconds the exec and update of a paused container wait for it to be
+    // resumed, failing at once if 0
+    pub frozen_wait: usize,
+    // address of the gRPC server serving the agent services too, none if
+    // empty
+    pub grpc_server_addr: String,
+    // address of the read-only HTTP endpoint serving the agent state as
+    // JSON, none if empty
+    pub debug_http_addr: String,
+    // upstreams of the caching DNS stub resolver, which isn't started if
+    // there are none
+    pub dns_upstreams: Vec<SocketAddr>,
+    // several logical sandboxes can be created in the guest, the agent
+    // exiting once the last one is destroyed
+    pub multi_sandbox: bool,
+    // cgroup the sandbox and container cgroups are created below, the top
+    // of the guest hierarchy if "/"
+    pub cgroup_root: String,
+    // swappiness of the containers whose spec sets none, which inherit the
+    // one of the guest if None. A swappiness of 0 is only told apart from
+    // none with the SwappinessSet of the runtimes setting it.
+    pub default_swappiness: Option<u64>,
 }
 
 // parse_cmdline_param parse commandline parameters.
@@ -104,6 +199,32 @@ impl AgentConfig {
             server_addr: format!("{}:{}", VSOCK_ADDR, VSOCK_PORT),
             unified_cgroup_hierarchy: false,
             tracing: tracer::TraceType::Disabled,
+            require_verity_rootfs: false,
+            boot_log_capture: false,
+            disable_kmem_accounting: false,
+            strict_resources: false,
+            io_cost: false,
+            rootless_cgroups: false,
+            metrics_max_containers: DEFAULT_METRICS_MAX_CONTAINERS,
+            usage_history_interval: DEFAULT_USAGE_HISTORY_INTERVAL,
+            usage_history_size: DEFAULT_USAGE_HISTORY_SIZE,
+            profile: Profile::default(),
+            confinement: None,
+            memory_dump_max_size: 0,
+            static_devices: vec![],
+            default_io_limits: vec![],
+            container_nice: 0,
+            container_sched_policy: String::from(""),
+            fd_sample_interval: DEFAULT_FD_SAMPLE_INTERVAL,
+            virtiofs_watchdog_interval: DEFAULT_VIRTIOFS_WATCHDOG_INTERVAL,
+            virtiofs_watchdog_policy: WatchdogPolicy::default(),
+            frozen_wait: 0,
+            grpc_server_addr: String::from(""),
+            debug_http_addr: String::from(""),
+            dns_upstreams: vec![],
+            multi_sandbox: false,
+            cgroup_root: DEFAULT_CGROUP_ROOT.to_string(),
+            default_swappiness: None,
         }
     }
 
@@ -115,6 +236,16 @@ impl AgentConfig {
             // parse cmdline flags
             parse_cmdline_param!(param, DEBUG_CONSOLE_FLAG, self.debug_console);
             parse_cmdline_param!(param, DEV_MODE_FLAG, self.dev_mode);
+            parse_cmdline_param!(param, BOOT_LOG_CAPTURE_FLAG, self.boot_log_capture);
+            parse_cmdline_param!(
+                param,
+                DISABLE_KMEM_ACCOUNTING_FLAG,
+                self.disable_kmem_accounting
+            );
+            parse_cmdline_param!(param, STRICT_RESOURCES_FLAG, self.strict_resources);
+            parse_cmdline_param!(param, IO_COST_FLAG, self.io_cost);
+            parse_cmdline_param!(param, ROOTLESS_CGROUPS_FLAG, self.rootless_cgroups);
+            parse_cmdline_param!(param, MULTI_SANDBOX_FLAG, self.multi_sandbox);
 
             // Support "bare" tracing option for backwards compatibility with
             // Kata 1.x.
@@ -171,6 +302,106 @@ impl AgentConfig {
                 self.unified_cgroup_hierarchy,
                 get_bool_value
             );
+            parse_cmdline_param!(
+                param,
+                REQUIRE_VERITY_ROOTFS_OPTION,
+                self.require_verity_rootfs,
+                get_bool_value
+            );
+            parse_cmdline_param!(
+                param,
+                METRICS_MAX_CONTAINERS_OPTION,
+                self.metrics_max_containers,
+                get_usize_value
+            );
+            parse_cmdline_param!(
+                param,
+                USAGE_HISTORY_INTERVAL_OPTION,
+                self.usage_history_interval,
+                get_usize_value
+            );
+            parse_cmdline_param!(
+                param,
+                USAGE_HISTORY_SIZE_OPTION,
+                self.usage_history_size,
+                get_usize_value
+            );
+            parse_cmdline_param!(param, PROFILE_OPTION, self.profile, get_profile);
+            parse_cmdline_param!(param, CONFINEMENT_OPTION, self.confinement, get_confinement);
+            parse_cmdline_param!(
+                param,
+                MEMORY_DUMP_MAX_SIZE_OPTION,
+                self.memory_dump_max_size,
+                get_usize_value
+            );
+            parse_cmdline_param!(
+                param,
+                STATIC_DEVICES_OPTION,
+                self.static_devices,
+                get_static_devices
+            );
+            parse_cmdline_param!(
+                param,
+                DEFAULT_IO_LIMITS_OPTION,
+                self.default_io_limits,
+                get_io_limits
+            );
+            parse_cmdline_param!(
+                param,
+                CONTAINER_NICE_OPTION,
+                self.container_nice,
+                get_nice_value
+            );
+            parse_cmdline_param!(
+                param,
+                CONTAINER_SCHED_POLICY_OPTION,
+                self.container_sched_policy,
+                get_sched_policy
+            );
+            parse_cmdline_param!(
+                param,
+                FD_SAMPLE_INTERVAL_OPTION,
+                self.fd_sample_interval,
+                get_usize_value
+            );
+            parse_cmdline_param!(
+                param,
+                VIRTIOFS_WATCHDOG_INTERVAL_OPTION,
+                self.virtiofs_watchdog_interval,
+                get_usize_value
+            );
+            parse_cmdline_param!(
+                param,
+                VIRTIOFS_WATCHDOG_POLICY_OPTION,
+                self.virtiofs_watchdog_policy,
+                get_virtiofs_watchdog_policy
+            );
+            parse_cmdline_param!(param, FROZEN_WAIT_OPTION, self.frozen_wait, get_usize_value);
+            parse_cmdline_param!(
+                param,
+                GRPC_SERVER_ADDR_OPTION,
+                self.grpc_server_addr,
+                get_string_value
+            );
+            parse_cmdline_param!(
+                param,
+                DEBUG_HTTP_ADDR_OPTION,
+                self.debug_http_addr,
+                get_string_value
+            );
+            parse_cmdline_param!(
+                param,
+                DNS_UPSTREAMS_OPTION,
+                self.dns_upstreams,
+                get_dns_upstreams
+            );
+            parse_cmdline_param!(param, CGROUP_ROOT_OPTION, self.cgroup_root, get_cgroup_root);
+            parse_cmdline_param!(
+                param,
+                DEFAULT_SWAPPINESS_OPTION,
+                self.default_swappiness,
+                get_swappiness
+            );
         }
 
         if let Ok(addr) = env::var(SERVER_ADDR_ENV_VAR) {
@@ -191,6 +422,32 @@ impl AgentConfig {
 
         Ok(())
     }
+
+    // default_scheduler returns the scheduler of the container init
+    // processes whose spec sets none.
+    pub fn default_scheduler(&self) -> Option<Scheduler> {
+        if self.container_nice == 0 && self.container_sched_policy.is_empty() {
+            return None;
+        }
+
+        let policy = if self.container_sched_policy.is_empty() {
+            "SCHED_OTHER".to_string()
+        } else {
+            self.container_sched_policy.clone()
+        };
+
+        Some(Scheduler {
+            policy,
+            nice: self.container_nice,
+            priority: 0,
+        })
+    }
+
+    // confinement returns the policy the agent confines itself with.
+    pub fn confinement(&self) -> Confinement {
+        self.confinement
+            .unwrap_or_else(|| self.profile.confinement())
+    }
 }
 
 #[instrument]
@@ -310,6 +567,83 @@ fn get_string_value(param: &str) -> Result<String> {
     Ok(value)
 }
 
+#[instrument]
+fn get_profile(param: &str) -> Result<Profile> {
+    get_string_value(param)?.parse::<Profile>()
+}
+
+#[instrument]
+fn get_confinement(param: &str) -> Result<Option<Confinement>> {
+    Ok(Some(get_string_value(param)?.parse::<Confinement>()?))
+}
+
+#[instrument]
+fn get_virtiofs_watchdog_policy(param: &str) -> Result<WatchdogPolicy> {
+    get_string_value(param)?.parse::<WatchdogPolicy>()
+}
+
+#[instrument]
+fn get_static_devices(param: &str) -> Result<Vec<StaticDevice>> {
+    static_dev::parse_static_devices(&get_string_value(param)?)
+}
+
+#[instrument]
+fn get_dns_upstreams(param: &str) -> Result<Vec<SocketAddr>> {
+    dns::parse_upstreams(&get_string_value(param)?)
+}
+
+#[instrument]
+fn get_cgroup_root(param: &str) -> Result<String> {
+    cgroups::clean_cgroup_path(&get_string_value(param)?)
+}
+
+// get_swappiness returns the swappiness set, none if -1.
+#[instrument]
+fn get_swappiness(param: &str) -> Result<Option<u64>> {
+    let swappiness = get_string_value(param)?
+        .parse::<i64>()
+        .with_context(|| format!("invalid value for {}", param))?;
+
+    Ok(validate_swappiness(Some(swappiness))?)
+}
+
+#[instrument]
+fn get_io_limits(param: &str) -> Result<Vec<IoLimits>> {
+    io_limits::parse_io_limits(&get_string_value(param)?)
+}
+
+#[instrument]
+fn get_nice_value(param: &str) -> Result<i32> {
+    let nice = get_string_value(param)?
+        .parse::<i32>()
+        .with_context(|| format!("invalid value for {}", param))?;
+    sched::check_nice(nice)?;
+
+    Ok(nice)
+}
+
+#[instrument]
+fn get_sched_policy(param: &str) -> Result<String> {
+    let policy = get_string_value(param)?;
+
+    // the real-time policies need a priority, which only the spec can set
+    sched::validate(&Scheduler {
+        policy: policy.clone(),
+        ..Default::default()
+    })?;
+
+    Ok(policy)
+}
+
+#[instrument]
+fn get_usize_value(param: &str) -> Result<usize> {
+    let value = get_string_value(param)?;
+
+    value
+        .parse::<usize>()
+        .with_context(|| format!("invalid value for {}", param))
+}
+
 #[instrument]
 fn get_container_pipe_size(param: &str) -> Result<i32> {
     let fields: Vec<&str> = param.split('=').collect();
@@ -394,6 +728,32 @@ mod tests {
             server_addr: &'a str,
             unified_cgroup_hierarchy: bool,
             tracing: tracer::TraceType,
+            require_verity_rootfs: bool,
+            boot_log_capture: bool,
+            disable_kmem_accounting: bool,
+            strict_resources: bool,
+            io_cost: bool,
+            rootless_cgroups: bool,
+            metrics_max_containers: usize,
+            usage_history_interval: usize,
+            usage_history_size: usize,
+            profile: Profile,
+            confinement: Option<Confinement>,
+            memory_dump_max_size: usize,
+            static_devices: Vec<StaticDevice>,
+            default_io_limits: Vec<IoLimits>,
+            container_nice: i32,
+            container_sched_policy: &'a str,
+            fd_sample_interval: usize,
+            virtiofs_watchdog_interval: usize,
+            virtiofs_watchdog_policy: WatchdogPolicy,
+            frozen_wait: usize,
+            grpc_server_addr: &'a str,
+            debug_http_addr: &'a str,
+            dns_upstreams: Vec<SocketAddr>,
+            multi_sandbox: bool,
+            cgroup_root: &'a str,
+            default_swappiness: Option<u64>,
         }
 
         impl Default for TestData<'_> {
@@ -409,6 +769,32 @@ mod tests {
                     server_addr: TEST_SERVER_ADDR,
                     unified_cgroup_hierarchy: false,
                     tracing: tracer::TraceType::Disabled,
+                    require_verity_rootfs: false,
+                    boot_log_capture: false,
+                    disable_kmem_accounting: false,
+                    strict_resources: false,
+                    io_cost: false,
+                    rootless_cgroups: false,
+                    metrics_max_containers: DEFAULT_METRICS_MAX_CONTAINERS,
+                    usage_history_interval: DEFAULT_USAGE_HISTORY_INTERVAL,
+                    usage_history_size: DEFAULT_USAGE_HISTORY_SIZE,
+                    profile: Profile::default(),
+                    confinement: None,
+                    memory_dump_max_size: 0,
+                    static_devices: vec![],
+                    default_io_limits: vec![],
+                    container_nice: 0,
+                    container_sched_policy: "",
+                    fd_sample_interval: DEFAULT_FD_SAMPLE_INTERVAL,
+                    virtiofs_watchdog_interval: DEFAULT_VIRTIOFS_WATCHDOG_INTERVAL,
+                    virtiofs_watchdog_policy: WatchdogPolicy::Report,
+                    frozen_wait: 0,
+                    grpc_server_addr: "",
+                    debug_http_addr: "",
+                    dns_upstreams: vec![],
+                    multi_sandbox: false,
+                    cgroup_root: DEFAULT_CGROUP_ROOT,
+                    default_swappiness: None,
                 }
             }
         }
@@ -712,6 +1098,176 @@ mod tests {
                 tracing: tracer::TraceType::Disabled,
                 ..Default::default()
             },
+            TestData {
+                contents: "agent.require_verity_rootfs=true",
+                require_verity_rootfs: true,
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.require_verity_rootfs=0",
+                require_verity_rootfs: false,
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.boot_log_capture",
+                boot_log_capture: true,
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.disable_kmem_accounting",
+                disable_kmem_accounting: true,
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.strict_resources",
+                strict_resources: true,
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.io_cost",
+                io_cost: true,
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.rootless_cgroups",
+                rootless_cgroups: true,
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.metrics_max_containers=16",
+                metrics_max_containers: 16,
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.usage_history_interval=0 agent.usage_history_size=120",
+                usage_history_interval: 0,
+                usage_history_size: 120,
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.profile=micro",
+                profile: Profile::Micro,
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.profile=full",
+                profile: Profile::Full,
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.confinement=strict",
+                confinement: Some(Confinement::Strict),
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.memory_dump_max_size=1048576",
+                memory_dump_max_size: 1048576,
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.static_devices=fuse,mydev:c:10:125:0666",
+                static_devices: vec![
+                    StaticDevice {
+                        path: "fuse".to_string(),
+                        kind: nix::sys::stat::SFlag::S_IFCHR,
+                        major: 10,
+                        minor: 229,
+                        mode: 0o666,
+                    },
+                    StaticDevice {
+                        path: "mydev".to_string(),
+                        kind: nix::sys::stat::SFlag::S_IFCHR,
+                        major: 10,
+                        minor: 125,
+                        mode: 0o666,
+                    },
+                ],
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.default_io_limits=virtio-blk:wbps=1048576",
+                default_io_limits: vec![IoLimits {
+                    class: "virtio-blk".to_string(),
+                    wbps: Some(1048576),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.container_nice=10 agent.container_sched_policy=SCHED_BATCH",
+                container_nice: 10,
+                container_sched_policy: "SCHED_BATCH",
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.container_nice=-5",
+                container_nice: -5,
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.fd_sample_interval=30",
+                fd_sample_interval: 30,
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.virtiofs_watchdog_interval=10",
+                virtiofs_watchdog_interval: 10,
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.virtiofs_watchdog_interval=10 agent.virtiofs_watchdog_policy=abort",
+                virtiofs_watchdog_interval: 10,
+                virtiofs_watchdog_policy: WatchdogPolicy::Abort,
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.frozen_wait=30",
+                frozen_wait: 30,
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.grpc_server_addr=vsock://-1:1025",
+                grpc_server_addr: "vsock://-1:1025",
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.debug_http_addr=tcp://127.0.0.1:8090",
+                debug_http_addr: "tcp://127.0.0.1:8090",
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.dns_upstreams=10.0.2.3,8.8.8.8:5353",
+                dns_upstreams: vec![
+                    "10.0.2.3:53".parse().unwrap(),
+                    "8.8.8.8:5353".parse().unwrap(),
+                ],
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.multi_sandbox",
+                multi_sandbox: true,
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.cgroup_root=/kata/pods/",
+                cgroup_root: "/kata/pods",
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.cgroup_root=/",
+                cgroup_root: "/",
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.default_swappiness=0",
+                default_swappiness: Some(0),
+                ..Default::default()
+            },
+            TestData {
+                contents: "agent.default_swappiness=-1",
+                default_swappiness: None,
+                ..Default::default()
+            },
         ];
 
         let dir = tempdir().expect("failed to create tmpdir");
@@ -782,6 +1338,64 @@ mod tests {
             assert_eq!(d.container_pipe_size, config.container_pipe_size, "{}", msg);
             assert_eq!(d.server_addr, config.server_addr, "{}", msg);
             assert_eq!(d.tracing, config.tracing, "{}", msg);
+            assert_eq!(
+                d.require_verity_rootfs, config.require_verity_rootfs,
+                "{}",
+                msg
+            );
+            assert_eq!(d.boot_log_capture, config.boot_log_capture, "{}", msg);
+            assert_eq!(
+                d.disable_kmem_accounting, config.disable_kmem_accounting,
+                "{}",
+                msg
+            );
+            assert_eq!(d.strict_resources, config.strict_resources, "{}", msg);
+            assert_eq!(d.io_cost, config.io_cost, "{}", msg);
+            assert_eq!(d.rootless_cgroups, config.rootless_cgroups, "{}", msg);
+            assert_eq!(
+                d.metrics_max_containers, config.metrics_max_containers,
+                "{}",
+                msg
+            );
+            assert_eq!(
+                d.usage_history_interval, config.usage_history_interval,
+                "{}",
+                msg
+            );
+            assert_eq!(d.usage_history_size, config.usage_history_size, "{}", msg);
+            assert_eq!(d.profile, config.profile, "{}", msg);
+            assert_eq!(d.confinement, config.confinement, "{}", msg);
+            assert_eq!(
+                d.memory_dump_max_size, config.memory_dump_max_size,
+                "{}",
+                msg
+            );
+            assert_eq!(d.static_devices, config.static_devices, "{}", msg);
+            assert_eq!(d.default_io_limits, config.default_io_limits, "{}", msg);
+            assert_eq!(d.container_nice, config.container_nice, "{}", msg);
+            assert_eq!(
+                d.container_sched_policy, config.container_sched_policy,
+                "{}",
+                msg
+            );
+            assert_eq!(d.fd_sample_interval, config.fd_sample_interval, "{}", msg);
+            assert_eq!(
+                d.virtiofs_watchdog_interval, config.virtiofs_watchdog_interval,
+                "{}",
+                msg
+            );
+            assert_eq!(
+                d.virtiofs_watchdog_policy, config.virtiofs_watchdog_policy,
+                "{}",
+                msg
+            );
+            assert_eq!(d.frozen_wait, config.frozen_wait, "{}", msg);
+            assert_eq!(d.grpc_server_addr, config.grpc_server_addr, "{}", msg);
+            assert_eq!(d.debug_http_addr, config.debug_http_addr, "{}", msg);
+            assert_eq!(d.dns_upstreams, config.dns_upstreams, "{}", msg);
+            assert_eq!(d.multi_sandbox, config.multi_sandbox, "{}", msg);
+            assert_eq!(d.cgroup_root, config.cgroup_root, "{}", msg);
+            assert_eq!(d.default_swappiness, config.default_swappiness, "{}", msg);
 
             for v in vars_to_unset {
                 env::remove_var(v);
@@ -1276,4 +1890,48 @@ Caused by:
             assert_result!(d.result, result, msg);
         }
     }
+
+    #[test]
+    fn test_get_cgroup_root() {
+        assert_eq!(get_cgroup_root("agent.cgroup_root=kata").unwrap(), "/kata");
+        assert!(get_cgroup_root("agent.cgroup_root=").is_err());
+        assert!(get_cgroup_root("agent.cgroup_root=/kata/../system.slice").is_err());
+    }
+
+    #[test]
+    fn test_get_swappiness() {
+        assert_eq!(
+            get_swappiness("agent.default_swappiness=60").unwrap(),
+            Some(60)
+        );
+        assert!(get_swappiness("agent.default_swappiness=101").is_err());
+        assert!(get_swappiness("agent.default_swappiness=foo").is_err());
+    }
+
+    #[test]
+    fn test_get_container_scheduler() {
+        assert_eq!(get_nice_value("agent.container_nice=-20").unwrap(), -20);
+        assert!(get_nice_value("agent.container_nice=20").is_err());
+        assert!(get_nice_value("agent.container_nice=foo").is_err());
+
+        assert_eq!(
+            get_sched_policy("agent.container_sched_policy=SCHED_IDLE").unwrap(),
+            "SCHED_IDLE"
+        );
+        assert!(get_sched_policy("agent.container_sched_policy=SCHED_FIFO").is_err());
+        assert!(get_sched_policy("agent.container_sched_policy=batch").is_err());
+
+        let mut config = AgentConfig::new();
+        assert_eq!(config.default_scheduler(), None);
+
+        config.container_nice = 10;
+        assert_eq!(
+            config.default_scheduler(),
+            Some(Scheduler {
+                policy: "SCHED_OTHER".to_string(),
+                nice: 10,
+                priority: 0,
+            })
+        );
+    }
 }
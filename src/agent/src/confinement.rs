@@ -0,0 +1,309 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Self-confinement of the agent: a seccomp filter and a reduced capability
+// bounding set applied to the agent process at startup, so that a
+// compromised agent, e.g. through a malicious OCI spec or image, can't use
+// the guest kernel interfaces no sandbox needs.
+//
+// Both are per thread, and applied to the main thread before the runtime
+// starts the others, which inherit them. The processes of the containers
+// are spawned from a thread started beforehand, see
+// rustjail/src/unconfined.rs, so they only get the restrictions of their
+// spec, while the OCI hooks and the other commands the agent runs are
+// confined along with it. The policy defaults to the one of the agent
+// profile, and can be changed with agent.confinement:
+//
+// - "none" applies nothing.
+// - "baseline" denies the syscalls to replace the kernel, to open files by
+//   handle, and to manage the swap and the process accounting, along with
+//   the foreign syscall ABIs, e.g. the 32-bit x86 one.
+// - "strict" also denies the kernel modules, the perf events and
+//   userfaultfd, and drops the matching capabilities. The kernel modules of
+//   CreateSandbox can't be loaded then. BPF is left alone, the device
+//   cgroup of cgroup v2 being a BPF program.
+//
+// The denied syscalls fail with EPERM.
+
+use anyhow::{anyhow, Context, Result};
+use capctl::caps::{bounding, Cap, CapState};
+use std::str::FromStr;
+
+const CONFINEMENT_NONE: &str = "none";
+const CONFINEMENT_BASELINE: &str = "baseline";
+const CONFINEMENT_STRICT: &str = "strict";
+
+const BASELINE_SYSCALLS: &[(&str, libc::c_long)] = &[
+    ("kexec_load", libc::SYS_kexec_load),
+    #[cfg(any(target_arch = "x86_64", target_arch = "powerpc64"))]
+    ("kexec_file_load", libc::SYS_kexec_file_load),
+    ("open_by_handle_at", libc::SYS_open_by_handle_at),
+    ("acct", libc::SYS_acct),
+    ("swapon", libc::SYS_swapon),
+    ("swapoff", libc::SYS_swapoff),
+];
+
+const STRICT_SYSCALLS: &[(&str, libc::c_long)] = &[
+    ("init_module", libc::SYS_init_module),
+    ("finit_module", libc::SYS_finit_module),
+    ("delete_module", libc::SYS_delete_module),
+    ("perf_event_open", libc::SYS_perf_event_open),
+    ("userfaultfd", libc::SYS_userfaultfd),
+];
+
+const STRICT_CAPS: &[Cap] = &[
+    Cap::SYS_MODULE,
+    Cap::SYS_RAWIO,
+    Cap::SYS_BOOT,
+    Cap::SYS_PACCT,
+    Cap::MAC_ADMIN,
+    Cap::MAC_OVERRIDE,
+];
+
+// the audit architecture of the native syscall ABI
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+#[cfg(all(target_arch = "powerpc64", target_endian = "little"))]
+const AUDIT_ARCH: u32 = 0xc000_0015;
+#[cfg(all(target_arch = "powerpc64", target_endian = "big"))]
+const AUDIT_ARCH: u32 = 0x8000_0015;
+#[cfg(target_arch = "s390x")]
+const AUDIT_ARCH: u32 = 0x8000_0016;
+
+// the x32 syscalls share the audit architecture of x86_64
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+// see linux/filter.h and linux/seccomp.h
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_JMP_JEQ_K: u16 = 0x15;
+#[cfg(target_arch = "x86_64")]
+const BPF_JMP_JGE_K: u16 = 0x35;
+const BPF_RET_K: u16 = 0x06;
+
+const SECCOMP_DATA_NR: u32 = 0;
+const SECCOMP_DATA_ARCH: u32 = 4;
+
+const SECCOMP_SET_MODE_FILTER: libc::c_ulong = 1;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SockFilter {
+    code: u16,
+    jt: u8,
+    jf: u8,
+    k: u32,
+}
+
+#[repr(C)]
+struct SockFprog {
+    len: libc::c_ushort,
+    filter: *const SockFilter,
+}
+
+fn stmt(code: u16, k: u32) -> SockFilter {
+    SockFilter {
+        code,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+fn jump(code: u16, k: u32, jt: u8, jf: u8) -> SockFilter {
+    SockFilter { code, jt, jf, k }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Confinement {
+    None,
+    Baseline,
+    Strict,
+}
+
+impl FromStr for Confinement {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            CONFINEMENT_NONE => Ok(Confinement::None),
+            CONFINEMENT_BASELINE => Ok(Confinement::Baseline),
+            CONFINEMENT_STRICT => Ok(Confinement::Strict),
+            _ => Err(anyhow!("invalid agent confinement {:?}", s)),
+        }
+    }
+}
+
+impl Confinement {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Confinement::None => CONFINEMENT_NONE,
+            Confinement::Baseline => CONFINEMENT_BASELINE,
+            Confinement::Strict => CONFINEMENT_STRICT,
+        }
+    }
+
+    // denied_syscalls lists the names and numbers of the syscalls the
+    // filter denies.
+    fn denied_syscalls(&self) -> Vec<(&'static str, libc::c_long)> {
+        match self {
+            Confinement::None => vec![],
+            Confinement::Baseline => BASELINE_SYSCALLS.to_vec(),
+            Confinement::Strict => BASELINE_SYSCALLS
+                .iter()
+                .chain(STRICT_SYSCALLS.iter())
+                .copied()
+                .collect(),
+        }
+    }
+
+    fn dropped_caps(&self) -> &'static [Cap] {
+        match self {
+            Confinement::Strict => STRICT_CAPS,
+            _ => &[],
+        }
+    }
+}
+
+// build_filter returns the program of a seccomp filter denying the foreign
+// syscall ABIs and the `syscalls`.
+fn build_filter(syscalls: &[libc::c_long]) -> Vec<SockFilter> {
+    let deny = stmt(BPF_RET_K, SECCOMP_RET_ERRNO | libc::EPERM as u32);
+
+    let mut prog = vec![
+        stmt(BPF_LD_W_ABS, SECCOMP_DATA_ARCH),
+        jump(BPF_JMP_JEQ_K, AUDIT_ARCH, 1, 0),
+        deny,
+        stmt(BPF_LD_W_ABS, SECCOMP_DATA_NR),
+    ];
+
+    #[cfg(target_arch = "x86_64")]
+    prog.extend(vec![jump(BPF_JMP_JGE_K, X32_SYSCALL_BIT, 0, 1), deny]);
+
+    for nr in syscalls {
+        prog.push(jump(BPF_JMP_JEQ_K, *nr as u32, 0, 1));
+        prog.push(deny);
+    }
+
+    prog.push(stmt(BPF_RET_K, SECCOMP_RET_ALLOW));
+
+    prog
+}
+
+fn install_filter(prog: &[SockFilter]) -> Result<()> {
+    let fprog = SockFprog {
+        len: prog.len() as libc::c_ushort,
+        filter: prog.as_ptr(),
+    };
+
+    // not synchronized to the other threads, the unconfined one being left
+    // out, the threads of the runtime are started afterwards
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            SECCOMP_SET_MODE_FILTER,
+            0,
+            &fprog as *const SockFprog,
+        )
+    };
+    if ret != 0 {
+        return Err(anyhow!(
+            "failed to install the seccomp filter: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+
+    Ok(())
+}
+
+fn drop_caps(caps: &[Cap]) -> Result<()> {
+    for cap in caps {
+        bounding::drop(*cap).with_context(|| format!("failed to drop {:?}", cap))?;
+    }
+
+    let mut state = CapState::get_current().context("failed to get the capabilities")?;
+    for cap in caps {
+        state.effective.drop(*cap);
+        state.permitted.drop(*cap);
+        state.inheritable.drop(*cap);
+    }
+    state
+        .set_current()
+        .context("failed to set the capabilities")
+}
+
+// apply confines the calling thread, and the threads and processes it
+// starts afterwards but the processes of the containers.
+pub fn apply(confinement: Confinement) -> Result<()> {
+    if confinement == Confinement::None {
+        return Ok(());
+    }
+
+    rustjail::unconfined::start()?;
+
+    drop_caps(confinement.dropped_caps())?;
+
+    let syscalls: Vec<libc::c_long> = confinement
+        .denied_syscalls()
+        .iter()
+        .map(|(_, nr)| *nr)
+        .collect();
+    install_filter(&build_filter(&syscalls))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_confinement() {
+        for c in &[
+            Confinement::None,
+            Confinement::Baseline,
+            Confinement::Strict,
+        ] {
+            assert_eq!(c.as_str().parse::<Confinement>().unwrap(), *c);
+        }
+        assert!("seccomp".parse::<Confinement>().is_err());
+        assert!("".parse::<Confinement>().is_err());
+
+        assert!(Confinement::None.denied_syscalls().is_empty());
+        let baseline = Confinement::Baseline.denied_syscalls();
+        let strict = Confinement::Strict.denied_syscalls();
+        assert!(baseline.contains(&("kexec_load", libc::SYS_kexec_load)));
+        assert!(!strict.iter().any(|(name, _)| *name == "bpf"));
+        assert!(strict
+            .iter()
+            .all(|s| baseline.contains(s) || STRICT_SYSCALLS.contains(s)));
+        assert_eq!(strict.len(), baseline.len() + STRICT_SYSCALLS.len());
+
+        assert!(Confinement::Baseline.dropped_caps().is_empty());
+        assert!(Confinement::Strict
+            .dropped_caps()
+            .contains(&Cap::SYS_MODULE));
+    }
+
+    #[test]
+    fn test_build_filter() {
+        let prog = build_filter(&[libc::SYS_acct, libc::SYS_bpf]);
+
+        assert_eq!(prog[0], stmt(BPF_LD_W_ABS, SECCOMP_DATA_ARCH));
+        assert_eq!(prog[1].k, AUDIT_ARCH);
+        assert_eq!(*prog.last().unwrap(), stmt(BPF_RET_K, SECCOMP_RET_ALLOW));
+
+        // each syscall is checked, and denied if it matches
+        let checks: Vec<&[SockFilter]> = prog[prog.len() - 5..prog.len() - 1].chunks(2).collect();
+        for (check, nr) in checks.iter().zip(&[libc::SYS_acct, libc::SYS_bpf]) {
+            assert_eq!(check[0], jump(BPF_JMP_JEQ_K, *nr as u32, 0, 1));
+            assert_eq!(check[1].k, SECCOMP_RET_ERRNO | libc::EPERM as u32);
+        }
+
+        assert_eq!(build_filter(&[]).len(), prog.len() - 4);
+    }
+}
@@ -0,0 +1,444 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Read-only HTTP endpoint of the agent, serving its state as JSON for a
+// quick look with curl, from the debug console or a diagnostic container,
+// when agent.debug_http_addr is set, e.g. to tcp://127.0.0.1:8090 or
+// vsock://-1:1026. A TCP address has to be a loopback one.
+//
+// Only GET is served, one request per connection:
+//   /health                  the versions of the agent
+//   /containers              the OCI state of every container
+//   /containers/<id>         the OCI state of a container, with the
+//                            resources it was denied
+//   /containers/<id>/stats   the cgroup stats of a container
+//   /metrics                 the agent metrics, in the Prometheus format
+
+use anyhow::{anyhow, Context, Result};
+use futures::stream::{self, Stream, StreamExt};
+use nix::sys::socket::{self, AddressFamily, SockAddr, SockFlag, SockType};
+use protocols::agent::{CgroupStats, GetMetricsRequest};
+use rustjail::cgroups::Manager as CgroupManager;
+use rustjail::container::{BaseContainer, LinuxContainer};
+use serde_json::{json, Map, Value};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::select;
+use tokio::sync::watch::Receiver;
+use tokio::sync::Mutex;
+
+use crate::grpc::parse_vsock_addr;
+use crate::metrics;
+use crate::sandbox::Sandbox;
+use crate::util;
+use crate::version::{AGENT_VERSION, API_VERSION};
+
+const TCP_SCHEME: &str = "tcp://";
+const VSOCK_SCHEME: &str = "vsock://";
+
+// the request line and headers, the requests having no body
+const MAX_REQUEST_SIZE: u64 = 8192;
+
+const JSON_CONTENT_TYPE: &str = "application/json";
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+// Convenience macro to obtain the scope logger
+macro_rules! sl {
+    () => {
+        slog_scope::logger().new(o!("subsystem" => "debug_http"))
+    };
+}
+
+#[derive(Debug)]
+struct Reply {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+fn json_reply(status: u16, v: Value) -> Reply {
+    Reply {
+        status,
+        content_type: JSON_CONTENT_TYPE,
+        body: v.to_string(),
+    }
+}
+
+fn error_reply(status: u16, msg: &str) -> Reply {
+    json_reply(status, json!({ "error": msg }))
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    }
+}
+
+// parse_request_line returns the method and path of a request line, e.g.
+// "GET /health HTTP/1.1", the query of the path being dropped.
+fn parse_request_line(line: &str) -> Result<(&str, &str)> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    match fields.as_slice() {
+        [method, target, version] if version.starts_with("HTTP/") => {
+            let path = target.split('?').next().unwrap_or_default();
+            Ok((*method, path))
+        }
+        _ => Err(anyhow!("invalid request line {:?}", line)),
+    }
+}
+
+fn container_state(ctr: &LinuxContainer) -> Value {
+    match ctr.oci_state() {
+        Ok(state) => serde_json::to_value(state).unwrap_or(Value::Null),
+        // no spec
+        Err(_) => json!({ "id": ctr.id, "status": ctr.status() }),
+    }
+}
+
+fn stats_to_json(s: &CgroupStats) -> Value {
+    let cpu = s.get_cpu_stats();
+    let memory = s.get_memory_stats();
+    let pids = s.get_pids_stats();
+
+    let hugetlb: Map<String, Value> = s
+        .hugetlb_stats
+        .iter()
+        .map(|(size, h)| {
+            let v = json!({
+                "usage": h.usage,
+                "max_usage": h.max_usage,
+                "failcnt": h.failcnt,
+                "rsvd_usage": h.rsvd_usage,
+            });
+            (size.clone(), v)
+        })
+        .collect();
+
+    json!({
+        "cpu": {
+            "total_usage": cpu.get_cpu_usage().total_usage,
+            "usage_in_kernelmode": cpu.get_cpu_usage().usage_in_kernelmode,
+            "usage_in_usermode": cpu.get_cpu_usage().usage_in_usermode,
+            "throttled_periods": cpu.get_throttling_data().throttled_periods,
+            "throttled_time": cpu.get_throttling_data().throttled_time,
+        },
+        "memory": {
+            "usage": memory.get_usage().usage,
+            "max_usage": memory.get_usage().max_usage,
+            "limit": memory.get_usage().limit,
+            "failcnt": memory.get_usage().failcnt,
+            "cache": memory.cache,
+            "swap_usage": memory.get_swap_usage().usage,
+        },
+        "pids": {
+            "current": pids.current,
+            "limit": pids.limit,
+            "max_events": pids.max_events,
+        },
+        "hugetlb": hugetlb,
+    })
+}
+
+async fn route(sandbox: &Arc<Mutex<Sandbox>>, path: &str) -> Reply {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    match segments.as_slice() {
+        ["health"] => json_reply(
+            200,
+            json!({
+                "status": "SERVING",
+                "agent_version": AGENT_VERSION,
+                "api_version": API_VERSION,
+            }),
+        ),
+        ["metrics"] => match metrics::get_metrics(&GetMetricsRequest::new()) {
+            Ok(m) => Reply {
+                status: 200,
+                content_type: METRICS_CONTENT_TYPE,
+                body: m.metrics,
+            },
+            Err(e) => error_reply(500, &format!("{:?}", e)),
+        },
+        ["containers"] => {
+            let s = sandbox.lock().await;
+            let mut ids: Vec<&String> = s.containers.keys().collect();
+            ids.sort();
+
+            let states: Vec<Value> = ids
+                .into_iter()
+                .map(|id| container_state(&s.containers[id]))
+                .collect();
+            json_reply(200, Value::Array(states))
+        }
+        ["containers", cid] => {
+            let s = sandbox.lock().await;
+            let ctr = match s.containers.get(*cid) {
+                Some(ctr) => ctr,
+                None => return error_reply(404, "no such container"),
+            };
+
+            let unapplied = ctr
+                .cgroup_manager
+                .as_ref()
+                .map(|cgm| cgm.unapplied_resources())
+                .unwrap_or_default();
+            json_reply(
+                200,
+                json!({
+                    "state": container_state(ctr),
+                    "unapplied_resources": unapplied,
+                }),
+            )
+        }
+        ["containers", cid, "stats"] => {
+            let s = sandbox.lock().await;
+            let cgm = match s.containers.get(*cid) {
+                Some(ctr) => ctr.cgroup_manager.as_ref(),
+                None => return error_reply(404, "no such container"),
+            };
+
+            match cgm.map(|cgm| cgm.get_stats()) {
+                Some(Ok(stats)) => json_reply(200, stats_to_json(&stats.into())),
+                Some(Err(e)) => error_reply(500, &format!("{:?}", e)),
+                None => json_reply(200, json!({})),
+            }
+        }
+        _ => error_reply(404, "not found"),
+    }
+}
+
+async fn handle_connection<S>(sandbox: Arc<Mutex<Sandbox>>, stream: S) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut reader = BufReader::new(stream.take(MAX_REQUEST_SIZE));
+
+    // the headers are read up to the empty line ending them, to be ignored
+    let mut request_line = String::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(anyhow!("incomplete request"));
+        }
+        if line.trim_end().is_empty() {
+            break;
+        }
+        if request_line.is_empty() {
+            request_line = line;
+        }
+    }
+
+    let reply = match parse_request_line(&request_line) {
+        Ok(("GET", path)) => route(&sandbox, path).await,
+        Ok(_) => error_reply(405, "only GET is allowed"),
+        Err(e) => error_reply(400, &e.to_string()),
+    };
+
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        reply.status,
+        reason(reply.status),
+        reply.content_type,
+        reply.body.len()
+    );
+
+    let mut stream = reader.into_inner().into_inner();
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(reply.body.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+async fn serve<I, S>(
+    sandbox: Arc<Mutex<Sandbox>>,
+    mut incoming: I,
+    mut shutdown: Receiver<bool>,
+) -> Result<()>
+where
+    I: Stream<Item = io::Result<S>> + Unpin,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    loop {
+        select! {
+            _ = shutdown.changed() => {
+                info!(sl!(), "debug HTTP server got shutdown request");
+                return Ok(());
+            }
+            conn = incoming.next() => {
+                let stream = match conn {
+                    Some(Ok(stream)) => stream,
+                    Some(Err(e)) => {
+                        warn!(sl!(), "failed to accept a connection"; "error" => format!("{:?}", e));
+                        continue;
+                    }
+                    None => return Ok(()),
+                };
+
+                let sandbox = sandbox.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(sandbox, stream).await {
+                        warn!(sl!(), "failed to handle a request"; "error" => format!("{:?}", e));
+                    }
+                });
+            }
+        }
+    }
+}
+
+// parse_tcp_addr returns the address of a "tcp://<ip>:<port>" address,
+// which has to be a loopback one, the state of the sandbox being for its
+// own eyes only.
+fn parse_tcp_addr(addr: &str) -> Result<SocketAddr> {
+    let sa: SocketAddr = addr
+        .parse()
+        .with_context(|| format!("invalid TCP address {:?}", addr))?;
+    if !sa.ip().is_loopback() {
+        return Err(anyhow!("TCP address {} is not a loopback one", addr));
+    }
+
+    Ok(sa)
+}
+
+// run_server serves the state of the sandbox at `addr` until the agent
+// shuts down.
+pub async fn run_server(
+    addr: String,
+    sandbox: Arc<Mutex<Sandbox>>,
+    shutdown: Receiver<bool>,
+) -> Result<()> {
+    if let Some(tcp) = addr.strip_prefix(TCP_SCHEME) {
+        let sa = parse_tcp_addr(tcp)?;
+        let listener = TcpListener::bind(sa)
+            .await
+            .with_context(|| format!("failed to bind {}", sa))?;
+
+        info!(sl!(), "debug HTTP server started"; "address" => &addr);
+
+        let incoming = Box::pin(stream::unfold(listener, |l| async move {
+            let conn = l.accept().await.map(|(stream, _)| stream);
+            Some((conn, l))
+        }));
+        return serve(sandbox, incoming, shutdown).await;
+    }
+
+    if let Some(vsock) = addr.strip_prefix(VSOCK_SCHEME) {
+        let (cid, port) = parse_vsock_addr(vsock)?;
+
+        let listenfd = socket::socket(
+            AddressFamily::Vsock,
+            SockType::Stream,
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )?;
+        socket::bind(listenfd, &SockAddr::new_vsock(cid, port))?;
+        socket::listen(listenfd, 128)?;
+
+        info!(sl!(), "debug HTTP server started"; "address" => &addr);
+
+        return serve(sandbox, util::get_vsock_incoming(listenfd), shutdown).await;
+    }
+
+    Err(anyhow!("unsupported debug HTTP address {:?}", addr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sandbox() -> Arc<Mutex<Sandbox>> {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        Arc::new(Mutex::new(Sandbox::new(&logger).unwrap()))
+    }
+
+    async fn request(sandbox: Arc<Mutex<Sandbox>>, req: &str) -> String {
+        let (server, mut client) = tokio::net::UnixStream::pair().unwrap();
+        tokio::spawn(handle_connection(sandbox, server));
+
+        client.write_all(req.as_bytes()).await.unwrap();
+        let mut resp = String::new();
+        client.read_to_string(&mut resp).await.unwrap();
+        resp
+    }
+
+    #[test]
+    fn test_parse_request_line() {
+        assert_eq!(
+            parse_request_line("GET /health HTTP/1.1\r\n").unwrap(),
+            ("GET", "/health")
+        );
+        assert_eq!(
+            parse_request_line("GET /containers?x=1 HTTP/1.0").unwrap(),
+            ("GET", "/containers")
+        );
+
+        for line in &["", "GET /health", "GET /health FTP/1.0", "GET / HTTP/1.1 x"] {
+            assert!(parse_request_line(line).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_parse_tcp_addr() {
+        assert_eq!(
+            parse_tcp_addr("127.0.0.1:8090").unwrap(),
+            "127.0.0.1:8090".parse::<SocketAddr>().unwrap()
+        );
+        parse_tcp_addr("[::1]:8090").unwrap();
+
+        for addr in &[
+            "",
+            "127.0.0.1",
+            "0.0.0.0:8090",
+            "10.0.0.1:8090",
+            "localhost:8090",
+        ] {
+            assert!(parse_tcp_addr(addr).is_err(), "{}", addr);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_route() {
+        let sandbox = sandbox();
+
+        let reply = route(&sandbox, "/health").await;
+        assert_eq!(reply.status, 200);
+        let v: Value = serde_json::from_str(&reply.body).unwrap();
+        assert_eq!(v["agent_version"], AGENT_VERSION);
+
+        let reply = route(&sandbox, "/containers/").await;
+        assert_eq!(reply.status, 200);
+        assert_eq!(reply.body, "[]");
+
+        for path in &["/containers/c1", "/containers/c1/stats", "/", "/health/x"] {
+            assert_eq!(route(&sandbox, path).await.status, 404, "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_connection() {
+        let resp = request(
+            sandbox(),
+            "GET /containers HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        )
+        .await;
+        assert_eq!(
+            resp,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 2\r\nConnection: close\r\n\r\n[]"
+        );
+
+        let resp = request(sandbox(), "POST /health HTTP/1.1\r\n\r\n").await;
+        assert!(resp.starts_with("HTTP/1.1 405 "), "{}", resp);
+
+        let resp = request(sandbox(), "hello\r\n\r\n").await;
+        assert!(resp.starts_with("HTTP/1.1 400 "), "{}", resp);
+    }
+}
@@ -24,9 +24,10 @@ use crate::mount::{
 use crate::pci;
 use crate::sandbox::Sandbox;
 use crate::uevent::{wait_for_uevent, Uevent, UeventMatcher};
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use oci::{LinuxDeviceCgroup, LinuxResources, Spec};
 use protocols::agent::Device;
+use rustjail::cgroups::DeviceMap;
 use tracing::instrument;
 
 // Convenience macro to obtain the scope logger
@@ -42,6 +43,8 @@ const VM_ROOTFS: &str = "/";
 struct DevIndexEntry {
     idx: usize,
     residx: Vec<usize>,
+    // the host numbers of a block device, which its blkio limits refer to
+    blk: Option<(i64, i64)>,
 }
 
 #[derive(Debug)]
@@ -357,6 +360,7 @@ fn update_spec_device_list(device: &Device, spec: &mut Spec, devidx: &DevIndex)
                 "set resources for device major: {} minor: {}\n", major_id, minor_id
             );
         }
+
         Ok(())
     } else {
         Err(anyhow!(
@@ -469,26 +473,66 @@ impl DevIndex {
                         }
                     }
                 }
-                map.insert(d.path.clone(), DevIndexEntry { idx: i, residx });
+                map.insert(
+                    d.path.clone(),
+                    DevIndexEntry {
+                        idx: i,
+                        residx,
+                        blk: Some((d.major, d.minor)).filter(|_| d.r#type == "b"),
+                    },
+                );
             }
         }
         DevIndex(map)
     }
 }
 
+// add_devices updates the devices of the spec to the guest ones, and
+// returns the host -> guest numbers of the block devices, which the cgroup
+// manager translates the blkio limits with.
 #[instrument]
 pub async fn add_devices(
     devices: &[Device],
     spec: &mut Spec,
     sandbox: &Arc<Mutex<Sandbox>>,
-) -> Result<()> {
+) -> Result<DeviceMap> {
     let devidx = DevIndex::new(spec);
 
     for device in devices.iter() {
         add_device(device, spec, sandbox, &devidx).await?;
     }
 
-    Ok(())
+    Ok(block_device_map(devices, spec, &devidx))
+}
+
+// block_device_map returns the host -> guest numbers of the block devices
+// of the spec which were updated.
+fn block_device_map(devices: &[Device], spec: &Spec, devidx: &DevIndex) -> DeviceMap {
+    let spec_devices = spec.linux.as_ref().map_or(&[][..], |l| &l.devices);
+
+    devices
+        .iter()
+        .filter_map(|d| devidx.0.get(d.container_path.as_str()))
+        .filter_map(|e| {
+            let guest = spec_devices.get(e.idx)?;
+            Some((e.blk?, (guest.major, guest.minor)))
+        })
+        .collect()
+}
+
+// parse_major_minor parses the "<major>:<minor>" numbers of a device.
+pub fn parse_major_minor(s: &str) -> Result<(i64, i64)> {
+    let fields: Vec<&str> = s.split(':').collect();
+    if fields.len() != 2 {
+        return Err(anyhow!("invalid device numbers {:?}", s));
+    }
+
+    let parse = |f: &str| {
+        f.parse::<i64>()
+            .with_context(|| format!("invalid device numbers {:?}", s))
+    };
+
+    Ok((parse(fields[0])?, parse(fields[1])?))
 }
 
 #[instrument]
@@ -842,6 +886,89 @@ mod tests {
         assert_eq!(Some(host_minor), specresources.devices[1].minor);
     }
 
+    #[test]
+    fn test_block_device_map() {
+        let null_rdev = fs::metadata("/dev/null").unwrap().rdev();
+        let guest_major = stat::major(null_rdev) as i64;
+        let guest_minor = stat::minor(null_rdev) as i64;
+        let (host_major, host_minor) = (253, 0);
+
+        let throttle = oci::LinuxThrottleDevice {
+            blk: oci::LinuxBlockIoDevice {
+                major: host_major,
+                minor: host_minor,
+            },
+            rate: 1 << 20,
+        };
+
+        let mut spec = Spec {
+            linux: Some(Linux {
+                devices: vec![
+                    oci::LinuxDevice {
+                        path: "/dev/xvda".to_string(),
+                        r#type: "b".to_string(),
+                        major: host_major,
+                        minor: host_minor,
+                        ..oci::LinuxDevice::default()
+                    },
+                    oci::LinuxDevice {
+                        path: "/dev/char".to_string(),
+                        r#type: "c".to_string(),
+                        major: 10,
+                        minor: 200,
+                        ..oci::LinuxDevice::default()
+                    },
+                ],
+                resources: Some(LinuxResources {
+                    block_io: Some(oci::LinuxBlockIo {
+                        throttle_read_bps_device: vec![throttle.clone()],
+                        ..oci::LinuxBlockIo::default()
+                    }),
+                    ..LinuxResources::default()
+                }),
+                ..Linux::default()
+            }),
+            ..Spec::default()
+        };
+
+        let devidx = DevIndex::new(&spec);
+
+        let devices: Vec<Device> = ["/dev/xvda", "/dev/char"]
+            .iter()
+            .map(|p| Device {
+                container_path: p.to_string(),
+                vm_path: "/dev/null".to_string(),
+                ..Device::default()
+            })
+            .collect();
+        for dev in devices.iter() {
+            update_spec_device_list(dev, &mut spec, &devidx).unwrap();
+        }
+
+        // the blkio limits are translated by the cgroup manager
+        let blkio = spec
+            .linux
+            .as_ref()
+            .and_then(|l| l.resources.as_ref())
+            .and_then(|r| r.block_io.as_ref())
+            .unwrap();
+        assert_eq!(blkio.throttle_read_bps_device[0], throttle);
+
+        let mut expected = DeviceMap::new();
+        expected.insert((host_major, host_minor), (guest_major, guest_minor));
+        assert_eq!(block_device_map(&devices, &spec, &devidx), expected);
+        assert!(block_device_map(&[], &spec, &devidx).is_empty());
+    }
+
+    #[test]
+    fn test_parse_major_minor() {
+        assert_eq!(parse_major_minor("8:16").unwrap(), (8, 16));
+        assert!(parse_major_minor("8").is_err());
+        assert!(parse_major_minor("8:16:0").is_err());
+        assert!(parse_major_minor("a:16").is_err());
+        assert!(parse_major_minor("").is_err());
+    }
+
     #[test]
     fn test_pcipath_to_sysfs() {
         let testdir = tempdir().expect("failed to create tmpdir");
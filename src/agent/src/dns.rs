@@ -0,0 +1,591 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Caching DNS stub resolver, for the chatty workloads whose lookups all
+// cross the host otherwise, adding latency and failures. When
+// agent.dns_upstreams is set, e.g. to "10.0.2.3,8.8.8.8:53", the agent
+// answers on 127.0.0.53:53 in the sandbox network namespace, which the
+// resolv.conf of the containers has to point to, forwarding the queries it
+// has no fresh answer for to the upstreams in turn.
+//
+// The answers are cached by their question, and the DNSSEC bits of their
+// query, for the lowest TTL of their records, the TTLs being decreased by
+// the time spent in the cache and the question name given the case of each
+// query. The failures and the truncated answers aren't cached. Only UDP is served, so
+// the upstreams are better listed in resolv.conf after the stub, for the
+// clients retrying a truncated answer over TCP.
+
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::select;
+use tokio::sync::watch::Receiver;
+
+use crate::metrics;
+
+pub const STUB_ADDR: &str = "127.0.0.53:53";
+const DNS_PORT: u16 = 53;
+
+const HEADER_LEN: usize = 12;
+// the largest UDP message, as advertised by EDNS
+const MAX_MESSAGE_SIZE: usize = 4096;
+
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_CACHE_ENTRIES: usize = 4096;
+// the records of the longer TTLs are refreshed anyway
+const MAX_CACHE_TTL: u32 = 3600;
+
+const FLAG_TC: u16 = 0x0200;
+// checking disabled, the query asks for the answers DNSSEC validation fails
+const FLAG_CD: u16 = 0x0010;
+const RCODE_MASK: u16 = 0x000f;
+const RCODE_NOERROR: u16 = 0;
+const RCODE_NXDOMAIN: u16 = 3;
+// the TTL of an OPT record holds the EDNS flags
+const TYPE_OPT: u16 = 41;
+// DNSSEC OK, the query asks for the DNSSEC records
+const EDNS_DO: u32 = 0x8000;
+
+// Convenience macro to obtain the scope logger
+macro_rules! sl {
+    () => {
+        slog_scope::logger().new(o!("subsystem" => "dns"))
+    };
+}
+
+// parse_upstreams returns the addresses of a comma separated list of
+// upstreams, the port being 53 if not given, e.g. "10.0.2.3,[::1]:5353".
+pub fn parse_upstreams(s: &str) -> Result<Vec<SocketAddr>> {
+    s.split(',')
+        .map(|u| u.trim())
+        .filter(|u| !u.is_empty())
+        .map(|u| {
+            u.parse::<SocketAddr>()
+                .or_else(|_| u.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, DNS_PORT)))
+                .map_err(|_| anyhow!("invalid DNS upstream {:?}", u))
+        })
+        .collect()
+}
+
+fn read_u16(msg: &[u8], off: usize) -> Result<u16> {
+    msg.get(off..off + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or_else(|| anyhow!("truncated DNS message"))
+}
+
+fn read_u32(msg: &[u8], off: usize) -> Result<u32> {
+    msg.get(off..off + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| anyhow!("truncated DNS message"))
+}
+
+// skip_name returns the offset following the name at `off`, which may end
+// with a compression pointer.
+fn skip_name(msg: &[u8], mut off: usize) -> Result<usize> {
+    loop {
+        let len = *msg.get(off).ok_or_else(|| anyhow!("truncated DNS name"))? as usize;
+
+        match len & 0xc0 {
+            0x00 if len == 0 => return Ok(off + 1),
+            0x00 => off += 1 + len,
+            0xc0 if off + 2 <= msg.len() => return Ok(off + 2),
+            _ => return Err(anyhow!("invalid DNS name")),
+        }
+    }
+}
+
+// dnssec_bits returns the CD and DO bits of a query, which change its
+// answer.
+fn dnssec_bits(msg: &[u8]) -> Result<u8> {
+    let cd = read_u16(msg, 2)? & FLAG_CD != 0;
+
+    let mut dnssec_ok = false;
+    let mut off = skip_name(msg, HEADER_LEN)? + 4;
+    let records =
+        read_u16(msg, 6)? as usize + read_u16(msg, 8)? as usize + read_u16(msg, 10)? as usize;
+    for _ in 0..records {
+        off = skip_name(msg, off)?;
+        if read_u16(msg, off)? == TYPE_OPT {
+            dnssec_ok = read_u32(msg, off + 4)? & EDNS_DO != 0;
+        }
+        off += 10 + read_u16(msg, off + 8)? as usize;
+    }
+
+    Ok(cd as u8 | (dnssec_ok as u8) << 1)
+}
+
+// question_key returns the question of a query, along with its DNSSEC bits,
+// which its answer is cached by, the names being case insensitive.
+fn question_key(msg: &[u8]) -> Result<Vec<u8>> {
+    if read_u16(msg, 4)? != 1 {
+        return Err(anyhow!("a single DNS question is expected"));
+    }
+
+    let name_end = skip_name(msg, HEADER_LEN)?;
+    let end = name_end + 4;
+    if end > msg.len() {
+        return Err(anyhow!("truncated DNS question"));
+    }
+
+    let mut key = vec![dnssec_bits(msg)?];
+    // the type and class bytes aren't case
+    key.extend(msg[HEADER_LEN..name_end].to_ascii_lowercase());
+    key.extend_from_slice(&msg[name_end..end]);
+
+    Ok(key)
+}
+
+// cacheable returns how long an answer can be cached for, the lowest TTL
+// of its records, and the offsets of their TTLs, None if it can't be.
+fn cacheable(msg: &[u8]) -> Result<Option<(u32, Vec<usize>)>> {
+    let flags = read_u16(msg, 2)?;
+    let rcode = flags & RCODE_MASK;
+    if flags & FLAG_TC != 0 || (rcode != RCODE_NOERROR && rcode != RCODE_NXDOMAIN) {
+        return Ok(None);
+    }
+
+    let mut off = HEADER_LEN;
+    for _ in 0..read_u16(msg, 4)? {
+        off = skip_name(msg, off)? + 4;
+    }
+
+    let records =
+        read_u16(msg, 6)? as usize + read_u16(msg, 8)? as usize + read_u16(msg, 10)? as usize;
+    let mut ttl = MAX_CACHE_TTL;
+    let mut offsets = vec![];
+    for _ in 0..records {
+        off = skip_name(msg, off)?;
+        if read_u16(msg, off)? != TYPE_OPT {
+            ttl = ttl.min(read_u32(msg, off + 4)?);
+            offsets.push(off + 4);
+        }
+        off += 10 + read_u16(msg, off + 8)? as usize;
+    }
+    if off > msg.len() {
+        return Err(anyhow!("truncated DNS record"));
+    }
+
+    // a negative answer without SOA has no TTL
+    if offsets.is_empty() || ttl == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some((ttl, offsets)))
+}
+
+#[derive(Debug)]
+struct Entry {
+    msg: Vec<u8>,
+    ttl: u32,
+    ttl_offsets: Vec<usize>,
+    stored: Instant,
+}
+
+#[derive(Debug)]
+struct Cache {
+    entries: HashMap<Vec<u8>, Entry>,
+    max: usize,
+}
+
+impl Cache {
+    fn new(max: usize) -> Self {
+        Cache {
+            entries: HashMap::new(),
+            max,
+        }
+    }
+
+    // get returns the answer to a question as of `now`, for `query`, which
+    // it gets the id and the question name of.
+    fn get(&self, key: &[u8], query: &[u8], now: Instant) -> Option<Vec<u8>> {
+        let e = self.entries.get(key)?;
+        let elapsed = now.saturating_duration_since(e.stored).as_secs() as u32;
+        if elapsed >= e.ttl {
+            return None;
+        }
+
+        let mut msg = e.msg.clone();
+        msg[..2].copy_from_slice(&query[..2]);
+
+        // some clients check the name is the one they asked for, case
+        // included, e.g. for the 0x20 encoding
+        let name = &query[HEADER_LEN..skip_name(query, HEADER_LEN).ok()?];
+        if let Some(cached) = msg.get_mut(HEADER_LEN..HEADER_LEN + name.len()) {
+            if cached.eq_ignore_ascii_case(name) {
+                cached.copy_from_slice(name);
+            }
+        }
+
+        for off in e.ttl_offsets.iter() {
+            let ttl = read_u32(&msg, *off).ok()?.saturating_sub(elapsed);
+            msg[*off..*off + 4].copy_from_slice(&ttl.to_be_bytes());
+        }
+
+        Some(msg)
+    }
+
+    fn insert(
+        &mut self,
+        key: Vec<u8>,
+        msg: Vec<u8>,
+        ttl: u32,
+        ttl_offsets: Vec<usize>,
+        now: Instant,
+    ) {
+        if self.entries.len() >= self.max && !self.entries.contains_key(&key) {
+            self.entries
+                .retain(|_, e| now.saturating_duration_since(e.stored).as_secs() < e.ttl as u64);
+        }
+        // any entry goes when none has expired
+        if self.entries.len() >= self.max && !self.entries.contains_key(&key) {
+            if let Some(k) = self.entries.keys().next().cloned() {
+                self.entries.remove(&k);
+            }
+        }
+
+        self.entries.insert(
+            key,
+            Entry {
+                msg,
+                ttl,
+                ttl_offsets,
+                stored: now,
+            },
+        );
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+async fn forward_to(upstream: SocketAddr, query: &[u8], id: u16) -> Result<Vec<u8>> {
+    let local: SocketAddr = if upstream.is_ipv4() {
+        "0.0.0.0:0".parse()?
+    } else {
+        "[::]:0".parse()?
+    };
+    let sock = UdpSocket::bind(local).await?;
+    sock.connect(upstream).await?;
+    sock.send(query).await?;
+
+    let recv = async {
+        let mut buf = vec![0; MAX_MESSAGE_SIZE];
+        loop {
+            let n = sock.recv(&mut buf).await?;
+            // the spoofed answers are dropped
+            if n >= HEADER_LEN && read_u16(&buf, 0)? == id {
+                buf.truncate(n);
+                return Ok::<_, anyhow::Error>(buf);
+            }
+        }
+    };
+
+    tokio::time::timeout(UPSTREAM_TIMEOUT, recv)
+        .await
+        .map_err(|_| anyhow!("DNS upstream {} timed out", upstream))?
+}
+
+// forward sends a query to the upstreams in turn, until one answers.
+async fn forward(upstreams: &[SocketAddr], query: &[u8]) -> Result<Vec<u8>> {
+    let id = read_u16(query, 0)?;
+
+    let mut err = anyhow!("no DNS upstream");
+    for upstream in upstreams.iter() {
+        match forward_to(*upstream, query, id).await {
+            Ok(answer) => return Ok(answer),
+            Err(e) => err = e,
+        }
+    }
+
+    Err(err)
+}
+
+async fn resolve(cache: &Mutex<Cache>, upstreams: &[SocketAddr], query: &[u8]) -> Result<Vec<u8>> {
+    let key = question_key(query)?;
+
+    if let Some(answer) = cache.lock().unwrap().get(&key, query, Instant::now()) {
+        metrics::inc_dns_queries(metrics::DNS_CACHE_HIT);
+        return Ok(answer);
+    }
+    metrics::inc_dns_queries(metrics::DNS_CACHE_MISS);
+
+    let answer = forward(upstreams, query).await.map_err(|e| {
+        metrics::inc_dns_queries(metrics::DNS_UPSTREAM_ERROR);
+        e
+    })?;
+
+    if let Some((ttl, offsets)) = cacheable(&answer)? {
+        let mut cache = cache.lock().unwrap();
+        cache.insert(key, answer.clone(), ttl, offsets, Instant::now());
+        metrics::set_dns_cache_entries(cache.len());
+    }
+
+    Ok(answer)
+}
+
+// run_stub_resolver answers the queries sent to STUB_ADDR until the agent
+// shuts down.
+pub async fn run_stub_resolver(
+    upstreams: Vec<SocketAddr>,
+    mut shutdown: Receiver<bool>,
+) -> Result<()> {
+    let sock = Arc::new(
+        UdpSocket::bind(STUB_ADDR)
+            .await
+            .with_context(|| format!("failed to bind {}", STUB_ADDR))?,
+    );
+    let cache = Arc::new(Mutex::new(Cache::new(MAX_CACHE_ENTRIES)));
+    let upstreams = Arc::new(upstreams);
+
+    info!(sl!(), "DNS stub resolver started"; "upstreams" => format!("{:?}", upstreams));
+
+    let mut buf = vec![0; MAX_MESSAGE_SIZE];
+    loop {
+        select! {
+            _ = shutdown.changed() => {
+                info!(sl!(), "DNS stub resolver got shutdown request");
+                return Ok(());
+            }
+            r = sock.recv_from(&mut buf) => {
+                let (n, peer) = match r {
+                    Ok(r) => r,
+                    Err(e) => {
+                        warn!(sl!(), "failed to receive a query"; "error" => format!("{:?}", e));
+                        continue;
+                    }
+                };
+
+                let query = buf[..n].to_vec();
+                let sock = sock.clone();
+                let cache = cache.clone();
+                let upstreams = upstreams.clone();
+                tokio::spawn(async move {
+                    match resolve(&cache, &upstreams, &query).await {
+                        Ok(answer) => {
+                            if let Err(e) = sock.send_to(&answer, peer).await {
+                                warn!(sl!(), "failed to send an answer"; "error" => format!("{:?}", e));
+                            }
+                        }
+                        // the client retries or gives up
+                        Err(e) => debug!(sl!(), "failed to resolve a query"; "error" => format!("{:?}", e)),
+                    }
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TYPE_A: u16 = 1;
+
+    fn query(id: u16, name: &str) -> Vec<u8> {
+        let mut msg = vec![];
+        msg.extend_from_slice(&id.to_be_bytes());
+        msg.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+        for label in name.split('.') {
+            msg.push(label.len() as u8);
+            msg.extend_from_slice(label.as_bytes());
+        }
+        msg.push(0);
+        msg.extend_from_slice(&TYPE_A.to_be_bytes());
+        msg.extend_from_slice(&1u16.to_be_bytes());
+        msg
+    }
+
+    // answer answers a query with an A record per TTL, and an OPT record
+    fn answer(query: &[u8], rcode: u16, ttls: &[u32]) -> Vec<u8> {
+        let mut msg = query.to_vec();
+        msg[2..4].copy_from_slice(&(0x8180 | rcode).to_be_bytes());
+        msg[6..8].copy_from_slice(&(ttls.len() as u16).to_be_bytes());
+        msg[10..12].copy_from_slice(&1u16.to_be_bytes());
+
+        for ttl in ttls {
+            // a pointer to the name of the question
+            msg.extend_from_slice(&[0xc0, 12]);
+            msg.extend_from_slice(&TYPE_A.to_be_bytes());
+            msg.extend_from_slice(&1u16.to_be_bytes());
+            msg.extend_from_slice(&ttl.to_be_bytes());
+            msg.extend_from_slice(&4u16.to_be_bytes());
+            msg.extend_from_slice(&[10, 0, 0, 1]);
+        }
+
+        msg.push(0);
+        msg.extend_from_slice(&TYPE_OPT.to_be_bytes());
+        msg.extend_from_slice(&4096u16.to_be_bytes());
+        msg.extend_from_slice(&0x8000u32.to_be_bytes());
+        msg.extend_from_slice(&0u16.to_be_bytes());
+        msg
+    }
+
+    // with_opt adds an OPT record of the EDNS flags `flags` to a query
+    fn with_opt(query: &[u8], flags: u32) -> Vec<u8> {
+        let mut msg = query.to_vec();
+        msg[10..12].copy_from_slice(&1u16.to_be_bytes());
+        msg.push(0);
+        msg.extend_from_slice(&TYPE_OPT.to_be_bytes());
+        msg.extend_from_slice(&4096u16.to_be_bytes());
+        msg.extend_from_slice(&flags.to_be_bytes());
+        msg.extend_from_slice(&0u16.to_be_bytes());
+        msg
+    }
+
+    #[test]
+    fn test_parse_upstreams() {
+        assert_eq!(
+            parse_upstreams("10.0.2.3, 8.8.8.8:5353,[::1]:53,::1").unwrap(),
+            vec![
+                "10.0.2.3:53".parse::<SocketAddr>().unwrap(),
+                "8.8.8.8:5353".parse().unwrap(),
+                "[::1]:53".parse().unwrap(),
+                "[::1]:53".parse().unwrap(),
+            ]
+        );
+        assert!(parse_upstreams("").unwrap().is_empty());
+
+        for s in &["dns.google", "10.0.2.3:x", "10.0.2.3,:53"] {
+            assert!(parse_upstreams(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_question_key() {
+        let key = question_key(&query(1, "Example.COM")).unwrap();
+        assert_eq!(key, question_key(&query(2, "example.com")).unwrap());
+        assert_ne!(key, question_key(&query(1, "example.org")).unwrap());
+
+        // the DNSSEC bits of the query, CD then DO
+        let mut q = query(1, "example.com");
+        q[3] |= FLAG_CD as u8;
+        assert_ne!(key, question_key(&q).unwrap());
+        let q = with_opt(&query(1, "example.com"), EDNS_DO);
+        assert_ne!(key, question_key(&q).unwrap());
+        assert_eq!(
+            key,
+            question_key(&with_opt(&query(1, "example.com"), 0)).unwrap()
+        );
+
+        let mut q = query(1, "example.com");
+        q.truncate(q.len() - 1);
+        assert!(question_key(&q).is_err());
+        assert!(question_key(&q[..HEADER_LEN]).is_err());
+
+        // two questions
+        let mut q = query(1, "example.com");
+        q[5] = 2;
+        assert!(question_key(&q).is_err());
+    }
+
+    #[test]
+    fn test_cacheable() {
+        let q = query(1, "example.com");
+
+        let a = answer(&q, RCODE_NOERROR, &[300, 60]);
+        let (ttl, offsets) = cacheable(&a).unwrap().unwrap();
+        assert_eq!(ttl, 60);
+        assert_eq!(offsets.len(), 2);
+        assert_eq!(read_u32(&a, offsets[1]).unwrap(), 60);
+
+        assert_eq!(
+            cacheable(&answer(&q, RCODE_NOERROR, &[7200]))
+                .unwrap()
+                .unwrap()
+                .0,
+            MAX_CACHE_TTL
+        );
+
+        // SERVFAIL, no record, zero TTL, truncated
+        assert!(cacheable(&answer(&q, 2, &[300])).unwrap().is_none());
+        assert!(cacheable(&answer(&q, RCODE_NXDOMAIN, &[]))
+            .unwrap()
+            .is_none());
+        assert!(cacheable(&answer(&q, RCODE_NOERROR, &[0]))
+            .unwrap()
+            .is_none());
+        let mut a = answer(&q, RCODE_NOERROR, &[300]);
+        a[2] |= (FLAG_TC >> 8) as u8;
+        assert!(cacheable(&a).unwrap().is_none());
+
+        let a = answer(&q, RCODE_NOERROR, &[300]);
+        assert!(cacheable(&a[..a.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_cache() {
+        let now = Instant::now();
+        let mut cache = Cache::new(2);
+
+        let q = query(1, "example.com");
+        let key = question_key(&q).unwrap();
+        let a = answer(&q, RCODE_NOERROR, &[300]);
+        let (ttl, offsets) = cacheable(&a).unwrap().unwrap();
+        cache.insert(key.clone(), a, ttl, offsets.clone(), now);
+
+        // the id and the name of the query, and the remaining TTL
+        let q = query(7, "eXample.com");
+        let cached = cache.get(&key, &q, now + Duration::from_secs(100)).unwrap();
+        assert_eq!(read_u16(&cached, 0).unwrap(), 7);
+        assert_eq!(
+            &cached[HEADER_LEN..q.len() - 4],
+            &q[HEADER_LEN..q.len() - 4]
+        );
+        assert_eq!(read_u32(&cached, offsets[0]).unwrap(), 200);
+        assert!(cache
+            .get(&key, &q, now + Duration::from_secs(300))
+            .is_none());
+
+        // the expired entries go first
+        let keys: Vec<Vec<u8>> = ["a.com", "b.com"]
+            .iter()
+            .map(|n| question_key(&query(1, n)).unwrap())
+            .collect();
+        let later = now + Duration::from_secs(400);
+        cache.insert(keys[0].clone(), vec![0; HEADER_LEN], 10, vec![], later);
+        cache.insert(keys[1].clone(), vec![0; HEADER_LEN], 10, vec![], later);
+        assert_eq!(cache.len(), 2);
+        assert!(!cache.entries.contains_key(&key));
+
+        // then any entry
+        cache.insert(key.clone(), vec![0; HEADER_LEN], 10, vec![], later);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&key, &query(1, "example.com"), later).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_resolve() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let upstreams = vec![upstream.local_addr().unwrap()];
+        let cache = Mutex::new(Cache::new(MAX_CACHE_ENTRIES));
+
+        let server = tokio::spawn(async move {
+            let mut buf = vec![0; MAX_MESSAGE_SIZE];
+            let (n, peer) = upstream.recv_from(&mut buf).await.unwrap();
+            let a = answer(&buf[..n], RCODE_NOERROR, &[300]);
+            upstream.send_to(&a, peer).await.unwrap();
+        });
+
+        let a = resolve(&cache, &upstreams, &query(1, "example.com"))
+            .await
+            .unwrap();
+        assert_eq!(read_u16(&a, 0).unwrap(), 1);
+        server.await.unwrap();
+
+        // answered by the cache, the upstream being gone
+        let a = resolve(&cache, &upstreams, &query(2, "EXAMPLE.com"))
+            .await
+            .unwrap();
+        assert_eq!(read_u16(&a, 0).unwrap(), 2);
+
+        assert!(resolve(&cache, &upstreams, &query(3, "example.org"))
+            .await
+            .is_err());
+    }
+}
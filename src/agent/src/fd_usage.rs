@@ -0,0 +1,240 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Open file descriptors of the containers, sampled against their
+// RLIMIT_NOFILE, as an fd leak inside the guest otherwise only shows up as
+// EMFILE failures of the container processes.
+//
+// The fds of the processes of each container cgroup are counted every
+// agent.fd_sample_interval seconds, if set, and the ones of the process
+// closest to its limit are exported as metrics against that limit, with the
+// highest count seen. A warning is sent to the host when a process gets
+// close to its limit, once until it gets back below.
+
+use anyhow::{anyhow, Context, Result};
+use rustjail::cgroups::Manager as CgroupManager;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::select;
+use tokio::sync::watch::Receiver;
+use tokio::sync::Mutex;
+
+use crate::metrics;
+use crate::sandbox::Sandbox;
+
+// the share of its limit a process gets a warning at
+const FD_ALERT_RATIO: f64 = 0.9;
+
+// FdUsage is the usage of the process of a container which uses the highest
+// share of its limit, or which has the most fds open when none has a limit.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FdUsage {
+    // fds open by the process
+    pub open: u64,
+    // its RLIMIT_NOFILE soft limit, 0 if unlimited
+    pub limit: u64,
+    // the share of its limit it uses
+    pub max_ratio: f64,
+}
+
+// parse_nofile_limit returns the soft "Max open files" limit of a
+// /proc/<pid>/limits content, None if unlimited.
+fn parse_nofile_limit(limits: &str) -> Result<Option<u64>> {
+    let line = limits
+        .lines()
+        .find(|l| l.starts_with("Max open files"))
+        .ok_or_else(|| anyhow!("no open files limit"))?;
+
+    let soft = line
+        .trim_start_matches("Max open files")
+        .split_whitespace()
+        .next()
+        .ok_or_else(|| anyhow!("invalid open files limit {:?}", line))?;
+    if soft == "unlimited" {
+        return Ok(None);
+    }
+
+    soft.parse::<u64>()
+        .map(Some)
+        .with_context(|| format!("invalid open files limit {:?}", line))
+}
+
+// process_fds returns the number of fds open by a process and its limit.
+fn process_fds(proc_dir: &str, pid: i32) -> Result<(u64, Option<u64>)> {
+    let dir = format!("{}/{}", proc_dir, pid);
+
+    let open = fs::read_dir(format!("{}/fd", dir))?.count() as u64;
+    let limit = parse_nofile_limit(&fs::read_to_string(format!("{}/limits", dir))?)?;
+
+    Ok((open, limit))
+}
+
+// get_fd_usage returns the usage of the process closest to its limit, the
+// processes gone meanwhile being skipped.
+pub fn get_fd_usage(proc_dir: &str, pids: &[i32]) -> FdUsage {
+    let mut usage = FdUsage::default();
+
+    for pid in pids {
+        let (open, limit) = match process_fds(proc_dir, *pid) {
+            Ok(fds) => fds,
+            Err(_) => continue,
+        };
+
+        let limit = limit.unwrap_or(0);
+        let ratio = if limit > 0 {
+            open as f64 / limit as f64
+        } else {
+            0.0
+        };
+
+        if (ratio, open).partial_cmp(&(usage.max_ratio, usage.open)) == Some(Ordering::Greater) {
+            usage = FdUsage {
+                open,
+                limit,
+                max_ratio: ratio,
+            };
+        }
+    }
+
+    usage
+}
+
+async fn sample_fds(sandbox: &Arc<Mutex<Sandbox>>, alerted: &mut HashSet<String>) {
+    // only the processes are listed with the sandbox locked, /proc is
+    // walked once it is released
+    let procs: Vec<(String, Vec<i32>)> = {
+        let s = sandbox.lock().await;
+
+        // the removed containers are forgotten
+        alerted.retain(|cid| s.containers.contains_key(cid));
+
+        s.containers
+            .iter()
+            .filter_map(|(cid, ctr)| {
+                let pids = ctr.cgroup_manager.as_ref()?.get_procs().ok()?;
+                Some((cid.clone(), pids))
+            })
+            .collect()
+    };
+
+    let usages = match tokio::task::spawn_blocking(move || {
+        procs
+            .into_iter()
+            .map(|(cid, pids)| (cid, get_fd_usage("/proc", &pids)))
+            .collect::<Vec<_>>()
+    })
+    .await
+    {
+        Ok(usages) => usages,
+        Err(_) => return,
+    };
+
+    let s = sandbox.lock().await;
+    for (cid, usage) in usages.iter() {
+        metrics::set_container_fds(cid, usage);
+
+        if usage.max_ratio < FD_ALERT_RATIO {
+            alerted.remove(cid);
+        } else if alerted.insert(cid.clone()) {
+            s.warnings.add(
+                "FdUsage",
+                cid,
+                &format!(
+                    "a process uses {:.0}% of its open files limit",
+                    usage.max_ratio * 100.0
+                ),
+            );
+        }
+    }
+}
+
+// run_fd_sampler samples the fds of all the containers every `interval`
+// until the agent shuts down.
+pub async fn run_fd_sampler(
+    sandbox: Arc<Mutex<Sandbox>>,
+    interval: Duration,
+    mut shutdown: Receiver<bool>,
+) -> Result<()> {
+    let mut ticker = tokio::time::interval(interval);
+    let mut alerted = HashSet::new();
+
+    loop {
+        select! {
+            _ = shutdown.changed() => {
+                return Ok(());
+            }
+            _ = ticker.tick() => {
+                sample_fds(&sandbox, &mut alerted).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: &str = "Limit                     Soft Limit           Hard Limit           Units
+Max cpu time              unlimited            unlimited            seconds
+Max open files            1024                 524288               files
+Max locked memory         8388608              8388608              bytes
+";
+
+    #[test]
+    fn test_parse_nofile_limit() {
+        assert_eq!(parse_nofile_limit(LIMITS).unwrap(), Some(1024));
+        assert_eq!(
+            parse_nofile_limit(
+                "Max open files            unlimited            unlimited            files\n"
+            )
+            .unwrap(),
+            None
+        );
+        assert!(parse_nofile_limit("Max cpu time unlimited unlimited seconds\n").is_err());
+        assert!(parse_nofile_limit("Max open files x y files\n").is_err());
+    }
+
+    #[test]
+    fn test_get_fd_usage() {
+        let dir = tempfile::tempdir().unwrap();
+        for (pid, fds, limit) in &[
+            (10, 3, "100"),
+            (11, 45, "50"),
+            (12, 70, "unlimited"),
+            (13, 7, "unlimited"),
+        ] {
+            let p = dir.path().join(pid.to_string());
+            fs::create_dir_all(p.join("fd")).unwrap();
+            for fd in 0..*fds {
+                fs::write(p.join("fd").join(fd.to_string()), "").unwrap();
+            }
+            fs::write(
+                p.join("limits"),
+                format!("Max open files {} {} files\n", limit, limit),
+            )
+            .unwrap();
+        }
+
+        // 14 is gone
+        let proc_dir = dir.path().to_str().unwrap();
+        let usage = get_fd_usage(proc_dir, &[10, 11, 12, 13, 14]);
+        assert_eq!(usage.open, 45);
+        assert_eq!(usage.limit, 50);
+        assert!((usage.max_ratio - 0.9).abs() < f64::EPSILON);
+
+        // the most fds, without a limit
+        let usage = get_fd_usage(proc_dir, &[12, 13]);
+        assert_eq!(usage.open, 70);
+        assert_eq!(usage.limit, 0);
+
+        assert_eq!(
+            get_fd_usage(dir.path().to_str().unwrap(), &[]),
+            FdUsage::default()
+        );
+    }
+}
@@ -0,0 +1,177 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// fs-verity digests of the image layers, checked before the layers are
+// mounted when the host supplies them, e.g. from the metadata of a block
+// based image snapshotter.
+//
+// The digest is computed by reading the whole device or file, as the kernel
+// does when fs-verity is enabled on a file, so the layers neither need a
+// file system supporting fs-verity on the host nor the guest kernel support.
+// As the check happens once, it protects against a wrong or corrupted layer
+// given by the host, not against the changes of the device after it is
+// mounted.
+//
+// see https://www.kernel.org/doc/html/latest/filesystems/fsverity.html
+
+use anyhow::{anyhow, Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+pub const SHA256_PREFIX: &str = "sha256:";
+
+const BLOCK_SIZE: usize = 4096;
+const LOG_BLOCK_SIZE: u8 = 12;
+const HASH_SIZE: usize = 32;
+const FS_VERITY_HASH_ALG_SHA256: u8 = 1;
+const DESCRIPTOR_SIZE: usize = 256;
+
+// measure returns the "sha256:<hex>" fs-verity digest of a file or device.
+pub fn measure(path: &Path) -> Result<String> {
+    let f = File::open(path).with_context(|| format!("failed to open {:?}", path))?;
+    let digest = digest(f).with_context(|| format!("failed to read {:?}", path))?;
+
+    Ok(format!("{}{}", SHA256_PREFIX, to_hex(&digest)))
+}
+
+// verify checks the fs-verity digest of a file or device.
+pub fn verify(path: &Path, expected: &str) -> Result<()> {
+    if !expected.starts_with(SHA256_PREFIX) {
+        return Err(anyhow!("unsupported fs-verity digest {:?}", expected));
+    }
+
+    let digest = measure(path)?;
+    if digest != expected.to_lowercase() {
+        return Err(anyhow!(
+            "fs-verity digest mismatch for {:?}: expected {}, got {}",
+            path,
+            expected,
+            digest
+        ));
+    }
+
+    Ok(())
+}
+
+fn hash_block(block: &[u8]) -> [u8; HASH_SIZE] {
+    let mut h = Sha256::new();
+    h.update(block);
+    if block.len() < BLOCK_SIZE {
+        h.update(&[0u8; BLOCK_SIZE][..BLOCK_SIZE - block.len()]);
+    }
+
+    let mut out = [0u8; HASH_SIZE];
+    out.copy_from_slice(&h.finalize());
+    out
+}
+
+// digest returns the fs-verity digest of the data, with 4K blocks, SHA-256
+// and no salt, as fsverity-utils does by default.
+fn digest<R: Read>(mut r: R) -> io::Result<[u8; HASH_SIZE]> {
+    let mut hashes = vec![];
+    let mut data_size: u64 = 0;
+    let mut block = vec![0u8; BLOCK_SIZE];
+
+    loop {
+        let n = read_block(&mut r, &mut block)?;
+        if n == 0 {
+            break;
+        }
+        data_size += n as u64;
+        hashes.extend_from_slice(&hash_block(&block[..n]));
+        if n < BLOCK_SIZE {
+            break;
+        }
+    }
+
+    // the Merkle tree levels are hashed up to a single hash, the root hash,
+    // which is all zeros for empty data
+    let mut root = [0u8; HASH_SIZE];
+    while hashes.len() > HASH_SIZE {
+        hashes = hashes
+            .chunks(BLOCK_SIZE)
+            .flat_map(|b| hash_block(b).to_vec())
+            .collect();
+    }
+    root[..hashes.len()].copy_from_slice(&hashes);
+
+    // struct fsverity_descriptor
+    let mut desc = [0u8; DESCRIPTOR_SIZE];
+    desc[0] = 1;
+    desc[1] = FS_VERITY_HASH_ALG_SHA256;
+    desc[2] = LOG_BLOCK_SIZE;
+    desc[8..16].copy_from_slice(&data_size.to_le_bytes());
+    desc[16..16 + HASH_SIZE].copy_from_slice(&root);
+
+    let mut out = [0u8; HASH_SIZE];
+    out.copy_from_slice(&Sha256::digest(&desc));
+    Ok(out)
+}
+
+// read_block fills block unless the end of the data is reached.
+fn read_block<R: Read>(r: &mut R, block: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+
+    while n < block.len() {
+        match r.read(&mut block[n..]) {
+            Ok(0) => break,
+            Ok(len) => n += len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(n)
+}
+
+fn to_hex(b: &[u8]) -> String {
+    b.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digest() {
+        let tests: &[(Vec<u8>, &str)] = &[
+            (
+                vec![],
+                "3d248ca542a24fc62d1c43b916eae5016878e2533c88238480b26128a1f1af95",
+            ),
+            (
+                vec![b'a'; 5000],
+                "918347c69490f04c08ed15c9711f5da336fac318892ef517e47f6c5c3f1c5811",
+            ),
+            // 128 blocks, whose hashes fill a single tree block
+            (
+                (0..=255u8).cycle().take(256 * 2048).collect(),
+                "9dd419a91daffdea8eaec180f2725d772a597de0dfac88e5a813b06fac84271d",
+            ),
+        ];
+
+        for (data, expected) in tests {
+            assert_eq!(to_hex(&digest(data.as_slice()).unwrap()), *expected);
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("layer.erofs");
+        std::fs::write(&path, "").unwrap();
+
+        let expected = "sha256:3d248ca542a24fc62d1c43b916eae5016878e2533c88238480b26128a1f1af95";
+        assert_eq!(measure(&path).unwrap(), expected);
+        verify(&path, expected).unwrap();
+        verify(&path, &expected.to_uppercase().replace("SHA256", "sha256")).unwrap();
+
+        assert!(verify(&path, "sha256:00").is_err());
+        assert!(verify(&path, "sha512:00").is_err());
+        assert!(verify(&dir.path().join("none"), expected).is_err());
+    }
+}
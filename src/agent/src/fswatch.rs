@@ -0,0 +1,370 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Filesystem watches on paths inside the containers, whose inotify events
+// are forwarded to the host, so that host tools can follow the changes of
+// the guest files without polling them through ExecProcess.
+//
+// A watch covers a path and, for a directory, its subdirectories up to the
+// requested depth, including the ones created afterwards. The number of
+// inotify watches a single registration can hold is bounded, as well as
+// the number of registrations.
+
+use anyhow::{anyhow, Context, Result};
+use nix::errno::Errno;
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, InotifyEvent, WatchDescriptor};
+use nix::unistd;
+use protocols::agent::{AddFsWatchRequest, FsEvent};
+use slog::Logger;
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::unix::AsyncFd;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+// registrations at most
+const MAX_FS_WATCHES: usize = 64;
+// inotify watches, i.e. directories, at most per registration
+const MAX_WATCHED_DIRS: usize = 1024;
+const MAX_DEPTH: u32 = 8;
+
+const OVERFLOW: &str = "overflow";
+
+const EVENTS: &[(AddWatchFlags, &str)] = &[
+    (AddWatchFlags::IN_CREATE, "create"),
+    (AddWatchFlags::IN_DELETE, "delete"),
+    (AddWatchFlags::IN_MODIFY, "modify"),
+    (AddWatchFlags::IN_ATTRIB, "attrib"),
+    (AddWatchFlags::IN_CLOSE_WRITE, "close_write"),
+    (AddWatchFlags::IN_MOVED_FROM, "moved_from"),
+    (AddWatchFlags::IN_MOVED_TO, "moved_to"),
+    (AddWatchFlags::IN_DELETE_SELF, "delete_self"),
+    (AddWatchFlags::IN_MOVE_SELF, "move_self"),
+];
+
+#[derive(Debug)]
+pub struct FsWatcher {
+    // watch id -> (container id, task forwarding the events)
+    watches: HashMap<String, (String, JoinHandle<()>)>,
+    event_tx: Sender<FsEvent>,
+    pub event_rx: Arc<Mutex<Receiver<FsEvent>>>,
+}
+
+impl FsWatcher {
+    pub fn new() -> Self {
+        let (event_tx, event_rx) = channel::<FsEvent>(100);
+
+        FsWatcher {
+            watches: HashMap::new(),
+            event_tx,
+            event_rx: Arc::new(Mutex::new(event_rx)),
+        }
+    }
+
+    // add starts watching a path of the container whose init process is
+    // `pid`, replacing the watch with the same id if any.
+    pub fn add(&mut self, logger: &Logger, req: &AddFsWatchRequest, pid: i32) -> Result<()> {
+        if req.id.is_empty() {
+            return Err(anyhow!("empty watch id"));
+        }
+        if req.depth > MAX_DEPTH {
+            return Err(anyhow!("watch depth {} above {}", req.depth, MAX_DEPTH));
+        }
+
+        self.remove(&req.id);
+
+        if self.watches.len() >= MAX_FS_WATCHES {
+            return Err(anyhow!("too many watches, at most {}", MAX_FS_WATCHES));
+        }
+
+        let root = container_path(pid, &req.path)?;
+        let mut watch = Watch::new(root, &req.path, req.depth)?;
+        watch.add_tree(Path::new(""), 0)?;
+
+        let logger =
+            logger.new(o!("watch" => req.id.clone(), "container" => req.container_id.clone()));
+        let handle = tokio::spawn(forward_events(
+            logger,
+            watch,
+            req.id.clone(),
+            req.container_id.clone(),
+            self.event_tx.clone(),
+        ));
+
+        self.watches
+            .insert(req.id.clone(), (req.container_id.clone(), handle));
+
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: &str) -> bool {
+        match self.watches.remove(id) {
+            Some((_, handle)) => {
+                handle.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // remove_container stops all the watches of a container.
+    pub fn remove_container(&mut self, cid: &str) {
+        let ids: Vec<String> = self
+            .watches
+            .iter()
+            .filter(|(_, (c, _))| c == cid)
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for id in ids {
+            self.remove(&id);
+        }
+    }
+}
+
+// container_path returns the agent side path of `path` inside the
+// container, through the root of its init process.
+fn container_path(pid: i32, path: &str) -> Result<PathBuf> {
+    let p = Path::new(path);
+    if !p.is_absolute() || p.components().any(|c| c == Component::ParentDir) {
+        return Err(anyhow!("invalid watch path {:?}", path));
+    }
+
+    Ok(Path::new(&format!("/proc/{}/root", pid)).join(p.strip_prefix("/")?))
+}
+
+// InotifyFd closes the inotify instance, which nix doesn't do.
+struct InotifyFd(Inotify);
+
+impl AsRawFd for InotifyFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl Drop for InotifyFd {
+    fn drop(&mut self) {
+        let _ = unistd::close(self.0.as_raw_fd());
+    }
+}
+
+struct Watch {
+    inotify: InotifyFd,
+    // agent side path of the watched path
+    root: PathBuf,
+    // watched path inside the container, which events are reported against
+    path: PathBuf,
+    depth: u32,
+    // watched directory, relative to root, and its depth
+    dirs: HashMap<WatchDescriptor, (PathBuf, u32)>,
+}
+
+impl Watch {
+    fn new(root: PathBuf, path: &str, depth: u32) -> Result<Self> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)
+            .context("failed to create inotify instance")?;
+
+        Ok(Watch {
+            inotify: InotifyFd(inotify),
+            root,
+            path: PathBuf::from(path),
+            depth,
+            dirs: HashMap::new(),
+        })
+    }
+
+    // add_tree watches `rel` and its subdirectories down to the depth of
+    // the watch.
+    fn add_tree(&mut self, rel: &Path, depth: u32) -> Result<()> {
+        if self.dirs.len() >= MAX_WATCHED_DIRS {
+            return Err(anyhow!(
+                "too many directories to watch, at most {}",
+                MAX_WATCHED_DIRS
+            ));
+        }
+
+        let full = self.root.join(rel);
+        let mut mask = EVENTS
+            .iter()
+            .fold(AddWatchFlags::empty(), |m, (f, _)| m | *f);
+        // only the watched path itself may be a symlink
+        if depth > 0 {
+            mask |= AddWatchFlags::IN_DONT_FOLLOW;
+        }
+
+        let wd = self
+            .inotify
+            .0
+            .add_watch(&full, mask)
+            .with_context(|| format!("failed to watch {:?}", self.path.join(rel)))?;
+        self.dirs.insert(wd, (rel.to_path_buf(), depth));
+
+        if depth >= self.depth || !full.is_dir() {
+            return Ok(());
+        }
+
+        for entry in fs::read_dir(&full)?.flatten() {
+            if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                self.add_tree(&rel.join(entry.file_name()), depth + 1)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // handle turns an inotify event into the event sent to the host, and
+    // follows the directories created within the watch depth.
+    fn handle(&mut self, logger: &Logger, ev: InotifyEvent) -> Option<FsEvent> {
+        let mut event = FsEvent::new();
+
+        if ev.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+            event.ops = vec![OVERFLOW.to_string()].into();
+            return Some(event);
+        }
+
+        let (rel, depth) = match self.dirs.get(&ev.wd) {
+            Some((rel, depth)) => (rel.clone(), *depth),
+            None => return None,
+        };
+
+        if ev.mask.contains(AddWatchFlags::IN_IGNORED) {
+            self.dirs.remove(&ev.wd);
+            return None;
+        }
+
+        let rel = match &ev.name {
+            Some(name) => rel.join(name),
+            None => rel,
+        };
+
+        if ev.mask.contains(AddWatchFlags::IN_ISDIR)
+            && ev
+                .mask
+                .intersects(AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO)
+            && depth < self.depth
+        {
+            if let Err(e) = self.add_tree(&rel, depth + 1) {
+                warn!(logger, "failed to watch new directory"; "error" => format!("{:?}", e));
+            }
+        }
+
+        event.path = self.path.join(&rel).to_string_lossy().to_string();
+        event.ops = EVENTS
+            .iter()
+            .filter(|(f, _)| ev.mask.contains(*f))
+            .map(|(_, op)| op.to_string())
+            .collect();
+
+        Some(event)
+    }
+}
+
+async fn forward_events(
+    logger: Logger,
+    mut watch: Watch,
+    id: String,
+    cid: String,
+    tx: Sender<FsEvent>,
+) {
+    let fd = watch.inotify.0;
+    let afd = match AsyncFd::new(fd.as_raw_fd()) {
+        Ok(afd) => afd,
+        Err(e) => {
+            warn!(logger, "failed to poll inotify"; "error" => format!("{:?}", e));
+            return;
+        }
+    };
+
+    loop {
+        let mut guard = match afd.readable().await {
+            Ok(g) => g,
+            Err(e) => {
+                warn!(logger, "failed to poll inotify"; "error" => format!("{:?}", e));
+                return;
+            }
+        };
+
+        let events = match fd.read_events() {
+            Ok(events) => events,
+            Err(nix::Error::Sys(Errno::EAGAIN)) => {
+                guard.clear_ready();
+                continue;
+            }
+            Err(e) => {
+                warn!(logger, "failed to read inotify events"; "error" => format!("{:?}", e));
+                return;
+            }
+        };
+
+        for ev in events {
+            if let Some(mut event) = watch.handle(&logger, ev) {
+                event.watch_id = id.clone();
+                event.container_id = cid.clone();
+
+                if tx.send(event).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_container_path() {
+        assert_eq!(
+            container_path(10, "/etc/app").unwrap(),
+            PathBuf::from("/proc/10/root/etc/app")
+        );
+        assert!(container_path(10, "etc/app").is_err());
+        assert!(container_path(10, "/etc/../../app").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fs_watcher() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        let logger = slog::Logger::root(slog::Discard, o!());
+
+        let mut watch = Watch::new(dir.path().to_path_buf(), "/data", 1).unwrap();
+        watch.add_tree(Path::new(""), 0).unwrap();
+        // "a/b" is below the depth
+        assert_eq!(watch.dirs.len(), 2);
+
+        let (tx, mut rx) = channel(10);
+        let handle = tokio::spawn(forward_events(
+            logger,
+            watch,
+            "w1".to_string(),
+            "c1".to_string(),
+            tx,
+        ));
+
+        fs::write(dir.path().join("a/config"), "x").unwrap();
+
+        let event = rx.recv().await.unwrap();
+        assert_eq!(event.watch_id, "w1");
+        assert_eq!(event.container_id, "c1");
+        assert_eq!(event.path, "/data/a/config");
+        assert_eq!(event.ops.to_vec(), vec!["create"]);
+
+        handle.abort();
+
+        let mut w = FsWatcher::new();
+        let mut req = AddFsWatchRequest::new();
+        let logger = slog::Logger::root(slog::Discard, o!());
+        assert!(w.add(&logger, &req, 1).is_err());
+        req.id = "w1".to_string();
+        req.depth = MAX_DEPTH + 1;
+        assert!(w.add(&logger, &req, 1).is_err());
+    }
+}
@@ -0,0 +1,586 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// gRPC server of the agent, for the tools and languages which have no
+// ttRPC client. It serves the same services as the ttRPC server, alongside
+// it, when agent.grpc_server_addr is set, e.g. to vsock://-1:1025.
+//
+// gRPC and ttRPC share the protobuf messages and the method paths, e.g.
+// "/grpc.AgentService/CreateContainer", so the requests are handed to the
+// ttRPC method handlers, carried over HTTP/2 rather than the ttRPC framing.
+// The agent methods are all unary: a request holds a single message, which
+// can't be compressed.
+
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use futures::stream::{self, Stream, StreamExt};
+use h2::server::SendResponse;
+use h2::RecvStream;
+use http::{HeaderMap, HeaderValue, Request, Response};
+use nix::sys::socket::{self, AddressFamily, SockAddr, SockFlag, SockType};
+use protobuf::ProtobufEnum;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::UnixListener;
+use tokio::select;
+use tokio::sync::watch::Receiver;
+use ttrpc::r#async::TtrpcContext;
+use ttrpc::{Code, MessageHeader};
+
+use crate::rpc::Methods;
+use crate::util;
+
+const VSOCK_SCHEME: &str = "vsock://";
+const UNIX_SCHEME: &str = "unix://";
+
+const CONTENT_TYPE: &str = "content-type";
+const GRPC_CONTENT_TYPE: &str = "application/grpc";
+const GRPC_STATUS: &str = "grpc-status";
+const GRPC_MESSAGE: &str = "grpc-message";
+const GRPC_TIMEOUT: &str = "grpc-timeout";
+
+// the headers of the HTTP/2 and gRPC layers, which aren't metadata
+const RESERVED_HEADERS: &[&str] = &[CONTENT_TYPE, "te", "user-agent"];
+const RESERVED_HEADER_PREFIX: &str = "grpc-";
+
+// the compressed flag and the length which prefix a message
+const PREFIX_LEN: usize = 5;
+
+// the largest request body, the ttRPC message limit
+const MAX_BODY_SIZE: usize = 4 << 20;
+
+// Convenience macro to obtain the scope logger
+macro_rules! sl {
+    () => {
+        slog_scope::logger().new(o!("subsystem" => "grpc"))
+    };
+}
+
+type Status = (Code, String);
+
+fn status(code: Code, message: &str) -> Status {
+    (code, message.to_string())
+}
+
+// encode_message returns a message with its gRPC prefix.
+fn encode_message(payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(PREFIX_LEN + payload.len());
+    buf.push(0);
+    buf.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    buf.extend_from_slice(payload);
+    buf
+}
+
+// decode_message returns the single message of a request body.
+fn decode_message(body: &[u8]) -> std::result::Result<&[u8], Status> {
+    if body.len() < PREFIX_LEN {
+        return Err(status(
+            Code::INVALID_ARGUMENT,
+            &format!("truncated message of {} bytes", body.len()),
+        ));
+    }
+    if body[0] != 0 {
+        return Err(status(
+            Code::UNIMPLEMENTED,
+            "compressed messages aren't supported",
+        ));
+    }
+
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&body[1..PREFIX_LEN]);
+    let len = u32::from_be_bytes(len) as usize;
+    if body.len() - PREFIX_LEN != len {
+        return Err(status(
+            Code::INVALID_ARGUMENT,
+            &format!("message of {} bytes in a body of {}", len, body.len()),
+        ));
+    }
+
+    Ok(&body[PREFIX_LEN..])
+}
+
+// parse_timeout returns the nanoseconds of a grpc-timeout header, e.g.
+// "100m" for 100 milliseconds.
+fn parse_timeout(value: &str) -> Option<i64> {
+    if value.len() < 2 {
+        return None;
+    }
+
+    let (amount, unit) = value.split_at(value.len() - 1);
+    let amount = amount.parse::<i64>().ok()?;
+    let nanos: i64 = match unit {
+        "H" => 3_600_000_000_000,
+        "M" => 60_000_000_000,
+        "S" => 1_000_000_000,
+        "m" => 1_000_000,
+        "u" => 1_000,
+        "n" => 1,
+        _ => return None,
+    };
+
+    amount.checked_mul(nanos)
+}
+
+// get_metadata returns the custom metadata of a request, which the ttRPC
+// handlers get as the metadata of their context.
+fn get_metadata(headers: &HeaderMap) -> HashMap<String, Vec<String>> {
+    let mut metadata: HashMap<String, Vec<String>> = HashMap::new();
+
+    for (name, value) in headers {
+        let name = name.as_str();
+        if RESERVED_HEADERS.contains(&name) || name.starts_with(RESERVED_HEADER_PREFIX) {
+            continue;
+        }
+
+        if let Ok(value) = value.to_str() {
+            metadata
+                .entry(name.to_string())
+                .or_default()
+                .push(value.to_string());
+        }
+    }
+
+    metadata
+}
+
+// encode_status_message percent-encodes a grpc-message header value.
+fn encode_status_message(message: &str) -> String {
+    let mut s = String::with_capacity(message.len());
+
+    for b in message.bytes() {
+        if (b' '..=b'~').contains(&b) && b != b'%' {
+            s.push(b as char);
+        } else {
+            s.push_str(&format!("%{:02X}", b));
+        }
+    }
+
+    s
+}
+
+// call hands a request to the ttRPC handler of its method, and returns the
+// response message.
+async fn call(
+    methods: &Methods,
+    path: &str,
+    metadata: HashMap<String, Vec<String>>,
+    timeout_nano: i64,
+    body: &[u8],
+) -> std::result::Result<Vec<u8>, Status> {
+    let handler = methods
+        .get(path)
+        .ok_or_else(|| status(Code::UNIMPLEMENTED, &format!("unknown method {}", path)))?;
+    let payload = decode_message(body)?;
+
+    let names: Vec<&str> = path.trim_start_matches('/').splitn(2, '/').collect();
+    let mut req = ttrpc::Request::new();
+    req.set_service(names[0].to_string());
+    req.set_method(names.get(1).unwrap_or(&"").to_string());
+    req.set_payload(payload.to_vec());
+    req.set_timeout_nano(timeout_nano);
+
+    let ctx = TtrpcContext {
+        fd: -1,
+        mh: MessageHeader::default(),
+        metadata,
+    };
+
+    let (_, buf) = handler
+        .handler(ctx, req)
+        .await
+        .map_err(|e| status(Code::INTERNAL, &e.to_string()))?;
+    let resp: ttrpc::Response =
+        protobuf::parse_from_bytes(&buf).map_err(|e| status(Code::INTERNAL, &e.to_string()))?;
+
+    let s = resp.get_status();
+    if s.get_code() != Code::OK {
+        return Err(status(s.get_code(), s.get_message()));
+    }
+
+    Ok(resp.payload)
+}
+
+// read_body returns the data of a body, None if it is over `max` bytes.
+async fn read_body(body: &mut RecvStream, max: usize) -> Result<Option<Vec<u8>>> {
+    let mut data = vec![];
+
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        body.flow_control().release_capacity(chunk.len())?;
+        if data.len() + chunk.len() > max {
+            return Ok(None);
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    Ok(Some(data))
+}
+
+async fn handle_request(
+    methods: Arc<Methods>,
+    req: Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+) -> Result<()> {
+    let path = req.uri().path().to_string();
+    let metadata = get_metadata(req.headers());
+    let timeout_nano = req
+        .headers()
+        .get(GRPC_TIMEOUT)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_timeout)
+        .unwrap_or(0);
+
+    let result = match read_body(&mut req.into_body(), MAX_BODY_SIZE).await? {
+        Some(body) => call(&methods, &path, metadata, timeout_nano, &body).await,
+        None => Err(status(
+            Code::RESOURCE_EXHAUSTED,
+            &format!("request over {} bytes", MAX_BODY_SIZE),
+        )),
+    };
+
+    let response = Response::builder()
+        .header(CONTENT_TYPE, GRPC_CONTENT_TYPE)
+        .body(())?;
+    let mut send = respond.send_response(response, false)?;
+
+    let mut trailers = HeaderMap::new();
+    match result {
+        Ok(payload) => {
+            send.send_data(Bytes::from(encode_message(&payload)), false)?;
+            trailers.insert(GRPC_STATUS, HeaderValue::from(Code::OK.value()));
+        }
+        Err((code, message)) => {
+            trailers.insert(GRPC_STATUS, HeaderValue::from(code.value()));
+            trailers.insert(
+                GRPC_MESSAGE,
+                HeaderValue::from_str(&encode_status_message(&message))?,
+            );
+        }
+    }
+    send.send_trailers(trailers)?;
+
+    Ok(())
+}
+
+// serve_connection serves the requests of a connection until it is closed,
+// each in its own task.
+async fn serve_connection<S>(methods: Arc<Methods>, stream: S) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut conn = h2::server::handshake(stream).await?;
+
+    while let Some(request) = conn.accept().await {
+        let (req, respond) = request?;
+        let methods = methods.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = handle_request(methods, req, respond).await {
+                warn!(sl!(), "failed to handle a request"; "error" => format!("{:?}", e));
+            }
+        });
+    }
+
+    Ok(())
+}
+
+async fn serve<I, S>(methods: Methods, mut incoming: I, mut shutdown: Receiver<bool>) -> Result<()>
+where
+    I: Stream<Item = io::Result<S>> + Unpin,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let methods = Arc::new(methods);
+
+    loop {
+        select! {
+            _ = shutdown.changed() => {
+                info!(sl!(), "gRPC server got shutdown request");
+                return Ok(());
+            }
+            conn = incoming.next() => {
+                let stream = match conn {
+                    Some(Ok(stream)) => stream,
+                    Some(Err(e)) => {
+                        warn!(sl!(), "failed to accept a connection"; "error" => format!("{:?}", e));
+                        continue;
+                    }
+                    None => return Ok(()),
+                };
+
+                let methods = methods.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_connection(methods, stream).await {
+                        warn!(sl!(), "connection failed"; "error" => format!("{:?}", e));
+                    }
+                });
+            }
+        }
+    }
+}
+
+// parse_vsock_addr returns the cid and port of a "vsock://<cid>:<port>"
+// address, -1 being any cid.
+pub fn parse_vsock_addr(addr: &str) -> Result<(u32, u32)> {
+    let fields: Vec<&str> = addr.splitn(2, ':').collect();
+    if fields.len() != 2 {
+        return Err(anyhow!("invalid vsock address {:?}", addr));
+    }
+
+    let cid = match fields[0] {
+        "-1" => libc::VMADDR_CID_ANY,
+        cid => cid
+            .parse::<u32>()
+            .with_context(|| format!("invalid vsock cid {:?}", cid))?,
+    };
+    let port = fields[1]
+        .parse::<u32>()
+        .with_context(|| format!("invalid vsock port {:?}", fields[1]))?;
+
+    Ok((cid, port))
+}
+
+// remove_socket removes the unix socket at path, if any.
+fn remove_socket(path: &str) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(m) if m.file_type().is_socket() => {
+            fs::remove_file(path).with_context(|| format!("failed to remove {}", path))
+        }
+        Ok(_) => Err(anyhow!("{} isn't a socket", path)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(anyhow!(e).context(format!("failed to stat {}", path))),
+    }
+}
+
+// run_server serves the methods at addr, a vsock:// or unix:// address,
+// until the agent shuts down.
+pub async fn run_server(addr: String, methods: Methods, shutdown: Receiver<bool>) -> Result<()> {
+    if let Some(vsock) = addr.strip_prefix(VSOCK_SCHEME) {
+        let (cid, port) = parse_vsock_addr(vsock)?;
+
+        let listenfd = socket::socket(
+            AddressFamily::Vsock,
+            SockType::Stream,
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )?;
+        socket::bind(listenfd, &SockAddr::new_vsock(cid, port))?;
+        socket::listen(listenfd, 128)?;
+
+        info!(sl!(), "gRPC server started"; "address" => &addr);
+
+        return serve(methods, util::get_vsock_incoming(listenfd), shutdown).await;
+    }
+
+    if let Some(path) = addr.strip_prefix(UNIX_SCHEME) {
+        // the socket of a previous agent is in the way
+        remove_socket(path)?;
+        let listener =
+            UnixListener::bind(path).with_context(|| format!("failed to bind {}", path))?;
+
+        info!(sl!(), "gRPC server started"; "address" => &addr);
+
+        let incoming = Box::pin(stream::unfold(listener, |l| async move {
+            let conn = l.accept().await.map(|(stream, _)| stream);
+            Some((conn, l))
+        }));
+        let result = serve(methods, incoming, shutdown).await;
+
+        if let Err(e) = remove_socket(path) {
+            warn!(sl!(), "failed to remove the socket"; "error" => format!("{:?}", e));
+        }
+        return result;
+    }
+
+    Err(anyhow!("unsupported gRPC server address {:?}", addr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc;
+    use crate::sandbox::Sandbox;
+    use protobuf::Message;
+    use protocols::health::{CheckRequest, HealthCheckResponse, HealthCheckResponse_ServingStatus};
+    use std::path::Path;
+    use tokio::sync::Mutex;
+
+    #[test]
+    fn test_encode_decode_message() {
+        let buf = encode_message(b"hello");
+        assert_eq!(buf, b"\0\0\0\0\x05hello");
+        assert_eq!(decode_message(&buf).unwrap(), b"hello");
+        assert_eq!(decode_message(&encode_message(b"")).unwrap(), b"");
+
+        for (body, code) in &[
+            (&b"\0\0\0"[..], Code::INVALID_ARGUMENT),
+            (&b"\x01\0\0\0\x05hello"[..], Code::UNIMPLEMENTED),
+            (&b"\0\0\0\0\x06hello"[..], Code::INVALID_ARGUMENT),
+            (&b"\0\0\0\0\x04hello"[..], Code::INVALID_ARGUMENT),
+        ] {
+            assert_eq!(decode_message(body).unwrap_err().0, *code, "{:?}", body);
+        }
+    }
+
+    #[test]
+    fn test_parse_timeout() {
+        assert_eq!(parse_timeout("100m"), Some(100_000_000));
+        assert_eq!(parse_timeout("2S"), Some(2_000_000_000));
+        assert_eq!(parse_timeout("1H"), Some(3_600_000_000_000));
+        assert_eq!(parse_timeout("5n"), Some(5));
+
+        for v in &["", "m", "10", "10x", "-m"] {
+            assert_eq!(parse_timeout(v), None, "{}", v);
+        }
+    }
+
+    #[test]
+    fn test_get_metadata() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(GRPC_CONTENT_TYPE));
+        headers.insert(GRPC_TIMEOUT, HeaderValue::from_static("1S"));
+        headers.insert("traceparent", HeaderValue::from_static("00-abc-01"));
+        headers.append("x-tag", HeaderValue::from_static("a"));
+        headers.append("x-tag", HeaderValue::from_static("b"));
+
+        let metadata = get_metadata(&headers);
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata["traceparent"], vec!["00-abc-01"]);
+        assert_eq!(metadata["x-tag"], vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_encode_status_message() {
+        assert_eq!(encode_status_message("invalid id"), "invalid id");
+        assert_eq!(encode_status_message("100%\ndone"), "100%25%0Adone");
+        assert_eq!(encode_status_message("é"), "%C3%A9");
+    }
+
+    #[test]
+    fn test_parse_vsock_addr() {
+        assert_eq!(
+            parse_vsock_addr("-1:1025").unwrap(),
+            (libc::VMADDR_CID_ANY, 1025)
+        );
+        assert_eq!(parse_vsock_addr("3:1025").unwrap(), (3, 1025));
+
+        for addr in &["", "1025", "-1:", "x:1025", "-1:x"] {
+            assert!(parse_vsock_addr(addr).is_err(), "{}", addr);
+        }
+    }
+
+    async fn send_request(
+        client: &mut h2::client::SendRequest<Bytes>,
+        path: &str,
+        body: Vec<u8>,
+    ) -> (Vec<u8>, HeaderMap) {
+        let req = Request::builder()
+            .method("POST")
+            .uri(format!("http://localhost{}", path))
+            .header(CONTENT_TYPE, GRPC_CONTENT_TYPE)
+            .body(())
+            .unwrap();
+
+        let (resp, mut send) = client.send_request(req, false).unwrap();
+        send.send_data(Bytes::from(body), true).unwrap();
+
+        let resp = resp.await.unwrap();
+        assert_eq!(resp.headers()[CONTENT_TYPE], GRPC_CONTENT_TYPE);
+
+        let mut body = resp.into_body();
+        let data = read_body(&mut body, MAX_BODY_SIZE).await.unwrap().unwrap();
+        let trailers = body.trailers().await.unwrap().unwrap();
+
+        (data, trailers)
+    }
+
+    #[tokio::test]
+    async fn test_serve_connection() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let sandbox = Arc::new(Mutex::new(Sandbox::new(&logger).unwrap()));
+        let (aservice, hservice) = rpc::create_services(sandbox);
+        let methods: Methods = aservice.into_iter().chain(hservice).collect();
+
+        let (server, client) = tokio::net::UnixStream::pair().unwrap();
+        tokio::spawn(serve_connection(Arc::new(methods), server));
+
+        let (client, conn) = h2::client::handshake(client).await.unwrap();
+        tokio::spawn(conn);
+        let mut client = client.ready().await.unwrap();
+
+        let req = CheckRequest::new().write_to_bytes().unwrap();
+        let (data, trailers) =
+            send_request(&mut client, "/grpc.Health/Check", encode_message(&req)).await;
+        assert_eq!(trailers[GRPC_STATUS], "0");
+        let resp: HealthCheckResponse =
+            protobuf::parse_from_bytes(decode_message(&data).unwrap()).unwrap();
+        assert_eq!(resp.status, HealthCheckResponse_ServingStatus::SERVING);
+
+        // the errors of the handlers are passed on
+        let (data, trailers) = send_request(
+            &mut client,
+            "/grpc.AgentService/PauseContainer",
+            encode_message(&[]),
+        )
+        .await;
+        assert!(data.is_empty());
+        assert_eq!(
+            trailers[GRPC_STATUS],
+            Code::INVALID_ARGUMENT.value().to_string()
+        );
+
+        let (_, trailers) =
+            send_request(&mut client, "/grpc.Health/Unknown", encode_message(&req)).await;
+        assert_eq!(
+            trailers[GRPC_STATUS],
+            Code::UNIMPLEMENTED.value().to_string()
+        );
+
+        let (_, trailers) = send_request(&mut client, "/grpc.Health/Check", vec![1, 0]).await;
+        assert_eq!(
+            trailers[GRPC_STATUS],
+            Code::INVALID_ARGUMENT.value().to_string()
+        );
+
+        let (_, trailers) =
+            send_request(&mut client, "/grpc.Health/Check", vec![1, 0, 0, 0, 0]).await;
+        assert_eq!(
+            trailers[GRPC_STATUS],
+            Code::UNIMPLEMENTED.value().to_string()
+        );
+
+        let (_, trailers) = send_request(
+            &mut client,
+            "/grpc.Health/Check",
+            vec![0; MAX_BODY_SIZE + 1],
+        )
+        .await;
+        assert_eq!(
+            trailers[GRPC_STATUS],
+            Code::RESOURCE_EXHAUSTED.value().to_string()
+        );
+    }
+
+    #[test]
+    fn test_remove_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agent.sock");
+        let path = path.to_str().unwrap();
+
+        remove_socket(path).unwrap();
+
+        std::os::unix::net::UnixListener::bind(path).unwrap();
+        remove_socket(path).unwrap();
+        assert!(!Path::new(path).exists());
+
+        // anything else is left alone
+        fs::write(path, "").unwrap();
+        assert!(remove_socket(path).is_err());
+        assert!(Path::new(path).exists());
+    }
+}
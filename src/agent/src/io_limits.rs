@@ -0,0 +1,293 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Default IO limits of the containers setting none, so that a container of
+// a multi-container sandbox can't monopolize the disks it shares with the
+// others.
+//
+// The limits are set per device class with agent.default_io_limits, as
+// "<class>:<setting>=<value>" entries, the settings being the weight and
+// the rbps, wbps, riops and wiops limits of io.max, e.g.
+//   agent.default_io_limits=virtio-blk:wbps=52428800,virtio-blk:weight=100
+// They apply to the disks of the class present when the container is
+// created.
+
+use anyhow::{anyhow, Context, Result};
+use oci::{LinuxBlockIo, LinuxBlockIoDevice, LinuxThrottleDevice, LinuxWeightDevice, Spec};
+use std::fs;
+use std::path::Path;
+
+use crate::device::parse_major_minor;
+
+pub const SYSFS_BLOCK_DIR: &str = "/sys/block";
+
+// device classes, by the prefix of their disk names
+const DEVICE_CLASSES: &[(&str, &str)] = &[
+    ("virtio-blk", "vd"),
+    ("scsi", "sd"),
+    ("nvme", "nvme"),
+    ("pmem", "pmem"),
+];
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct IoLimits {
+    pub class: String,
+    pub weight: Option<u16>,
+    pub rbps: Option<u64>,
+    pub wbps: Option<u64>,
+    pub riops: Option<u64>,
+    pub wiops: Option<u64>,
+}
+
+impl IoLimits {
+    fn prefix(&self) -> &'static str {
+        DEVICE_CLASSES
+            .iter()
+            .find(|(c, _)| *c == self.class)
+            .map(|(_, p)| *p)
+            .unwrap_or_default()
+    }
+}
+
+// parse_io_limits parses a comma separated list of
+// "<class>:<setting>=<value>" entries into the limits of each class.
+pub fn parse_io_limits(s: &str) -> Result<Vec<IoLimits>> {
+    let mut limits: Vec<IoLimits> = vec![];
+
+    for entry in s.split(',').filter(|e| !e.is_empty()) {
+        let err = || anyhow!("invalid IO limit {:?}", entry);
+
+        let fields: Vec<&str> = entry.splitn(2, ':').collect();
+        let setting: Vec<&str> = fields.get(1).ok_or_else(err)?.splitn(2, '=').collect();
+        if setting.len() != 2 {
+            return Err(err());
+        }
+        let (class, key, value) = (fields[0], setting[0], setting[1]);
+
+        if !DEVICE_CLASSES.iter().any(|(c, _)| *c == class) {
+            return Err(anyhow!("unknown device class {:?}", class));
+        }
+
+        let i = match limits.iter().position(|l| l.class == class) {
+            Some(i) => i,
+            None => {
+                limits.push(IoLimits {
+                    class: class.to_string(),
+                    ..Default::default()
+                });
+                limits.len() - 1
+            }
+        };
+        let l = &mut limits[i];
+
+        if key == "weight" {
+            let weight = value
+                .parse::<u16>()
+                .ok()
+                .filter(|w| *w != 0)
+                .ok_or_else(err)?;
+            l.weight = Some(weight);
+            continue;
+        }
+
+        let limit = value
+            .parse::<u64>()
+            .ok()
+            .filter(|v| *v != 0)
+            .ok_or_else(err)?;
+        match key {
+            "rbps" => l.rbps = Some(limit),
+            "wbps" => l.wbps = Some(limit),
+            "riops" => l.riops = Some(limit),
+            "wiops" => l.wiops = Some(limit),
+            _ => return Err(err()),
+        }
+    }
+
+    Ok(limits)
+}
+
+// has_block_io_settings tells whether the container sets any IO limit, the
+// unused fields from the host being zero.
+fn has_block_io_settings(blkio: &LinuxBlockIo) -> bool {
+    blkio.weight.unwrap_or(0) != 0
+        || !blkio.weight_device.is_empty()
+        || !blkio.throttle_read_bps_device.is_empty()
+        || !blkio.throttle_write_bps_device.is_empty()
+        || !blkio.throttle_read_iops_device.is_empty()
+        || !blkio.throttle_write_iops_device.is_empty()
+}
+
+// apply_default_io_limits sets the default limits of the disks in sys_block
+// to the container, unless it sets some.
+pub fn apply_default_io_limits(
+    spec: &mut Spec,
+    limits: &[IoLimits],
+    sys_block: &Path,
+) -> Result<()> {
+    if limits.is_empty() {
+        return Ok(());
+    }
+
+    let resources = match spec.linux.as_mut() {
+        Some(linux) => linux.resources.get_or_insert_with(Default::default),
+        None => return Ok(()),
+    };
+    if resources
+        .block_io
+        .as_ref()
+        .map_or(false, has_block_io_settings)
+    {
+        return Ok(());
+    }
+
+    let blkio = resources.block_io.get_or_insert_with(Default::default);
+    for (name, blk) in get_disks(sys_block)? {
+        let l = match limits.iter().find(|l| name.starts_with(l.prefix())) {
+            Some(l) => l,
+            None => continue,
+        };
+
+        if let Some(weight) = l.weight {
+            blkio.weight_device.push(LinuxWeightDevice {
+                blk: blk.clone(),
+                weight: Some(weight),
+                leaf_weight: None,
+            });
+        }
+
+        add_throttle(&mut blkio.throttle_read_bps_device, &blk, l.rbps);
+        add_throttle(&mut blkio.throttle_write_bps_device, &blk, l.wbps);
+        add_throttle(&mut blkio.throttle_read_iops_device, &blk, l.riops);
+        add_throttle(&mut blkio.throttle_write_iops_device, &blk, l.wiops);
+    }
+
+    Ok(())
+}
+
+fn add_throttle(
+    devices: &mut Vec<LinuxThrottleDevice>,
+    blk: &LinuxBlockIoDevice,
+    limit: Option<u64>,
+) {
+    if let Some(rate) = limit {
+        devices.push(LinuxThrottleDevice {
+            blk: blk.clone(),
+            rate,
+        });
+    }
+}
+
+// get_disks returns the disks of sys_block with their device numbers, by
+// name.
+fn get_disks(sys_block: &Path) -> Result<Vec<(String, LinuxBlockIoDevice)>> {
+    let mut disks = vec![];
+
+    for entry in
+        fs::read_dir(sys_block).with_context(|| format!("failed to read {:?}", sys_block))?
+    {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        let dev = fs::read_to_string(entry.path().join("dev"))?;
+        let (major, minor) =
+            parse_major_minor(dev.trim()).with_context(|| format!("invalid device {}", name))?;
+
+        disks.push((name, LinuxBlockIoDevice { major, minor }));
+    }
+
+    disks.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Ok(disks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci::{Linux, LinuxResources};
+
+    #[test]
+    fn test_parse_io_limits() {
+        let limits =
+            parse_io_limits("virtio-blk:wbps=1048576,scsi:riops=1000,virtio-blk:weight=100")
+                .unwrap();
+        assert_eq!(
+            limits,
+            vec![
+                IoLimits {
+                    class: "virtio-blk".to_string(),
+                    weight: Some(100),
+                    wbps: Some(1048576),
+                    ..Default::default()
+                },
+                IoLimits {
+                    class: "scsi".to_string(),
+                    riops: Some(1000),
+                    ..Default::default()
+                },
+            ]
+        );
+        assert!(parse_io_limits("").unwrap().is_empty());
+
+        for s in &[
+            "virtio-blk",
+            "virtio-blk:wbps",
+            "virtio-blk:wbps=0",
+            "virtio-blk:wbps=x",
+            "virtio-blk:weight=70000",
+            "virtio-blk:rate=10",
+            "ide:wbps=10",
+        ] {
+            assert!(parse_io_limits(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_apply_default_io_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, dev) in &[("vda", "254:0"), ("vdb", "254:16"), ("sda", "8:0")] {
+            fs::create_dir(dir.path().join(name)).unwrap();
+            fs::write(dir.path().join(name).join("dev"), format!("{}\n", dev)).unwrap();
+        }
+
+        let limits = parse_io_limits("virtio-blk:wbps=1048576,virtio-blk:weight=100").unwrap();
+        let mut spec = Spec {
+            linux: Some(Linux::default()),
+            ..Default::default()
+        };
+        apply_default_io_limits(&mut spec, &limits, dir.path()).unwrap();
+
+        let blkio = spec.linux.as_ref().unwrap().resources.as_ref().unwrap();
+        let blkio = blkio.block_io.as_ref().unwrap();
+        let devices: Vec<(i64, i64, u64)> = blkio
+            .throttle_write_bps_device
+            .iter()
+            .map(|d| (d.blk.major, d.blk.minor, d.rate))
+            .collect();
+        assert_eq!(devices, vec![(254, 0, 1048576), (254, 16, 1048576)]);
+        assert_eq!(blkio.weight_device.len(), 2);
+        assert_eq!(blkio.weight_device[1].weight, Some(100));
+        assert!(blkio.throttle_read_bps_device.is_empty());
+
+        // the container's own limits win
+        let own = LinuxBlockIo {
+            weight: Some(500),
+            ..Default::default()
+        };
+        let mut spec = Spec {
+            linux: Some(Linux {
+                resources: Some(LinuxResources {
+                    block_io: Some(own.clone()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        apply_default_io_limits(&mut spec, &limits, dir.path()).unwrap();
+        let r = spec.linux.unwrap().resources.unwrap();
+        assert_eq!(r.block_io, Some(own));
+    }
+}
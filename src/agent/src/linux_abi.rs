@@ -88,6 +88,8 @@ pub const SYSFS_CGROUPPATH: &str = "/sys/fs/cgroup";
 pub const SYSFS_ONLINE_FILE: &str = "online";
 
 pub const PROC_MOUNTSTATS: &str = "/proc/self/mountstats";
+pub const PROC_MOUNTINFO: &str = "/proc/self/mountinfo";
+pub const PROC_CMDLINE: &str = "/proc/cmdline";
 pub const PROC_CGROUPS: &str = "/proc/cgroups";
 
 pub const SYSTEM_DEV_PATH: &str = "/dev";
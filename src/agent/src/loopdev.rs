@@ -0,0 +1,192 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Loop devices, to mount file system images, e.g. squashfs or erofs ones
+// on a shared volume, inside the guest rather than from a privileged
+// container.
+//
+// The devices are attached with the autoclear flag, so the kernel detaches
+// them once their last user is gone. A device is kept open until it is
+// mounted, after which the mount holds it until it is unmounted, so there is
+// nothing to keep track of, including when the mount fails.
+
+use anyhow::{anyhow, Context, Result};
+use nix::errno::Errno;
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+const LOOP_CONTROL: &str = "/dev/loop-control";
+
+// see linux/loop.h
+const LOOP_SET_FD: libc::c_ulong = 0x4C00;
+const LOOP_CLR_FD: libc::c_ulong = 0x4C01;
+const LOOP_SET_STATUS64: libc::c_ulong = 0x4C04;
+const LOOP_CTL_GET_FREE: libc::c_ulong = 0x4C82;
+
+const LO_FLAGS_AUTOCLEAR: u32 = 4;
+const LO_FLAGS_PARTSCAN: u32 = 8;
+const LO_NAME_SIZE: usize = 64;
+const LO_KEY_SIZE: usize = 32;
+
+// the free device may be taken by someone else before it is attached
+const ATTACH_RETRIES: usize = 5;
+
+#[repr(C)]
+struct LoopInfo64 {
+    lo_device: u64,
+    lo_inode: u64,
+    lo_rdevice: u64,
+    lo_offset: u64,
+    lo_sizelimit: u64,
+    lo_number: u32,
+    lo_encrypt_type: u32,
+    lo_encrypt_key_size: u32,
+    lo_flags: u32,
+    lo_file_name: [u8; LO_NAME_SIZE],
+    lo_crypt_name: [u8; LO_NAME_SIZE],
+    lo_encrypt_key: [u8; LO_KEY_SIZE],
+    lo_init: [u64; 2],
+}
+
+// LoopDevice is an attached loop device, detached once dropped unless it is
+// in use, e.g. mounted.
+#[derive(Debug)]
+pub struct LoopDevice {
+    pub path: String,
+    _file: File,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct LoopOptions {
+    // the device is read-only, as is the file
+    pub read_only: bool,
+    // the partitions of the image get a device each, /dev/loop<n>p<m>
+    pub partscan: bool,
+}
+
+// attach attaches file to a free loop device.
+pub fn attach(file: &Path, options: LoopOptions) -> Result<LoopDevice> {
+    let meta = file
+        .metadata()
+        .with_context(|| format!("failed to stat {:?}", file))?;
+    if !meta.is_file() && !meta.file_type().is_block_device() {
+        return Err(anyhow!("{:?} is not a file", file));
+    }
+
+    // the kernel makes the device read-only when the file is
+    let backing = OpenOptions::new()
+        .read(true)
+        .write(!options.read_only)
+        .open(file)
+        .with_context(|| format!("failed to open {:?}", file))?;
+
+    let control =
+        File::open(LOOP_CONTROL).with_context(|| format!("failed to open {}", LOOP_CONTROL))?;
+
+    for _ in 0..ATTACH_RETRIES {
+        let n = Errno::result(unsafe { libc::ioctl(control.as_raw_fd(), LOOP_CTL_GET_FREE) })
+            .context("failed to get a free loop device")?;
+        let path = format!("/dev/loop{}", n);
+
+        let dev = OpenOptions::new()
+            .read(true)
+            .write(!options.read_only)
+            .open(&path)
+            .with_context(|| format!("failed to open {}", path))?;
+
+        match Errno::result(unsafe {
+            libc::ioctl(dev.as_raw_fd(), LOOP_SET_FD, backing.as_raw_fd())
+        }) {
+            Ok(_) => {}
+            Err(nix::Error::Sys(Errno::EBUSY)) => continue,
+            Err(e) => return Err(anyhow!(e).context(format!("failed to attach {}", path))),
+        }
+
+        if let Err(e) = set_status(&dev, file, options) {
+            unsafe { libc::ioctl(dev.as_raw_fd(), LOOP_CLR_FD) };
+            return Err(e.context(format!("failed to set up {}", path)));
+        }
+
+        return Ok(LoopDevice { path, _file: dev });
+    }
+
+    Err(anyhow!("no free loop device for {:?}", file))
+}
+
+fn set_status(dev: &File, file: &Path, options: LoopOptions) -> Result<()> {
+    let mut info: LoopInfo64 = unsafe { std::mem::zeroed() };
+    info.lo_flags = LO_FLAGS_AUTOCLEAR;
+    if options.partscan {
+        info.lo_flags |= LO_FLAGS_PARTSCAN;
+    }
+
+    // informative only, truncated as the kernel does
+    let name = file.to_string_lossy();
+    let len = std::cmp::min(name.len(), LO_NAME_SIZE - 1);
+    info.lo_file_name[..len].copy_from_slice(&name.as_bytes()[..len]);
+
+    Errno::result(unsafe {
+        libc::ioctl(
+            dev.as_raw_fd(),
+            LOOP_SET_STATUS64,
+            &info as *const LoopInfo64,
+        )
+    })
+    .map(drop)
+    .map_err(|e| anyhow!(e))
+}
+
+// partition_path returns the device of a partition of a loop device.
+pub fn partition_path(dev: &str, partition: u32) -> String {
+    format!("{}p{}", dev, partition)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::skip_if_not_root;
+
+    #[test]
+    fn test_loop_info_size() {
+        // the size is part of the ioctl number
+        assert_eq!(std::mem::size_of::<LoopInfo64>(), 232);
+    }
+
+    #[test]
+    fn test_partition_path() {
+        assert_eq!(partition_path("/dev/loop3", 1), "/dev/loop3p1");
+    }
+
+    #[test]
+    fn test_attach() {
+        skip_if_not_root!();
+
+        let dir = tempfile::tempdir().unwrap();
+        assert!(attach(dir.path(), LoopOptions::default()).is_err());
+        assert!(attach(&dir.path().join("none"), LoopOptions::default()).is_err());
+
+        if !Path::new(LOOP_CONTROL).exists() {
+            return;
+        }
+
+        let image = dir.path().join("image");
+        std::fs::write(&image, vec![0u8; 1 << 20]).unwrap();
+
+        let options = LoopOptions {
+            read_only: true,
+            partscan: false,
+        };
+        let dev = attach(&image, options).unwrap();
+        let name = dev.path.trim_start_matches("/dev/");
+        let sys = format!("/sys/block/{}/loop", name);
+
+        let backing = std::fs::read_to_string(format!("{}/backing_file", sys)).unwrap();
+        assert_eq!(backing.trim(), image.to_str().unwrap());
+        let ro = std::fs::read_to_string(format!("/sys/block/{}/ro", name)).unwrap();
+        assert_eq!(ro.trim(), "1");
+    }
+}
@@ -32,35 +32,65 @@ use std::os::unix::io::AsRawFd;
 use std::path::Path;
 use std::process::exit;
 use std::sync::Arc;
+use std::time::Duration;
 use tracing::{instrument, span};
 
+mod allocator;
+mod bootlog;
 #[cfg(target_arch = "s390x")]
 mod ccw;
+mod clock;
 mod config;
+mod confinement;
 mod console;
+mod debug_http;
 mod device;
+mod dns;
+mod fd_usage;
+mod fsverity;
+mod fswatch;
+mod grpc;
+mod io_limits;
 mod linux_abi;
+mod loopdev;
+mod memdump;
 mod metrics;
+mod metrics_push;
 mod mount;
+mod mountinfo;
 mod namespace;
 mod netlink;
 mod network;
 mod pci;
+mod postmortem;
+mod preflight;
+mod probe;
+mod profile;
 pub mod random;
 mod sandbox;
 mod signal;
+mod static_dev;
+mod sysctl;
 #[cfg(test)]
 mod test_utils;
 mod uevent;
+mod usage_history;
 mod util;
+mod verity;
 mod version;
+mod virtiofs_watchdog;
+mod vsock_ports;
+mod warnings;
 mod watcher;
 
+use fd_usage::run_fd_sampler;
 use mount::{cgroups_mount, general_mount};
 use sandbox::Sandbox;
 use signal::setup_signal_handler;
 use slog::{error, info, o, warn, Logger};
 use uevent::watch_uevents;
+use usage_history::{run_usage_sampler, UsageHistory};
+use virtiofs_watchdog::run_virtiofs_watchdog;
 
 use futures::future::join_all;
 use rustjail::pipestream::PipeStream;
@@ -126,24 +156,14 @@ async fn create_logger_task(rfd: RawFd, vsock_port: u32, shutdown: Receiver<bool
     Ok(())
 }
 
-async fn real_main() -> std::result::Result<(), Box<dyn std::error::Error>> {
-    env::set_var("RUST_BACKTRACE", "full");
-
-    // List of tasks that need to be stopped for a clean shutdown
-    let mut tasks: Vec<JoinHandle<Result<()>>> = vec![];
-
-    console::initialize();
-
+// load_config parses the agent config from the kernel command line, after
+// the base mounts when the agent runs as the init process.
+fn load_config(wfd: RawFd, init_mode: bool) -> Result<()> {
     lazy_static::initialize(&AGENT_CONFIG);
 
-    // support vsock log
-    let (rfd, wfd) = unistd::pipe2(OFlag::O_CLOEXEC)?;
-
-    let (shutdown_tx, shutdown_rx) = channel(true);
+    // the runtime isn't started yet, nothing else holds the lock
+    let mut config = futures::executor::block_on(AGENT_CONFIG.write());
 
-    let agent_config = AGENT_CONFIG.clone();
-
-    let init_mode = unistd::getpid() == Pid::from_raw(1);
     if init_mode {
         // dup a new file descriptor for this temporary logger writer,
         // since this logger would be dropped and it's writer would
@@ -163,18 +183,29 @@ async fn real_main() -> std::result::Result<(), Box<dyn std::error::Error>> {
             e
         })?;
 
-        let mut config = agent_config.write().await;
         config.parse_cmdline(KERNEL_CMDLINE_FILE)?;
 
         init_agent_as_init(&logger, config.unified_cgroup_hierarchy)?;
         drop(logger_async_guard);
     } else {
-        // once parsed cmdline and set the config, release the write lock
-        // as soon as possible in case other thread would get read lock on
-        // it.
-        let mut config = agent_config.write().await;
         config.parse_cmdline(KERNEL_CMDLINE_FILE)?;
     }
+
+    Ok(())
+}
+
+async fn real_main(
+    rfd: RawFd,
+    wfd: RawFd,
+    init_mode: bool,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    // List of tasks that need to be stopped for a clean shutdown
+    let mut tasks: Vec<JoinHandle<Result<()>>> = vec![];
+
+    let (shutdown_tx, shutdown_rx) = channel(true);
+
+    let agent_config = AGENT_CONFIG.clone();
+
     let config = agent_config.read().await;
 
     let log_vport = config.log_vport as u32;
@@ -191,6 +222,35 @@ async fn real_main() -> std::result::Result<(), Box<dyn std::error::Error>> {
 
     announce(&logger, &config);
 
+    if config.require_verity_rootfs {
+        match verity::get_rootfs_integrity(KERNEL_CMDLINE_FILE, linux_abi::PROC_MOUNTINFO) {
+            Ok(i) if i.verified => {
+                info!(logger, "guest rootfs is verity protected"; "root-hash" => i.root_hash)
+            }
+            Ok(_) => warn!(
+                logger,
+                "guest rootfs is not verity protected, refusing to release secrets"
+            ),
+            Err(e) => {
+                warn!(logger, "failed to check guest rootfs integrity"; "error" => format!("{:?}", e))
+            }
+        }
+    }
+
+    if let Err(e) = allocator::init() {
+        warn!(logger, "failed to configure allocator"; "error" => format!("{:?}", e));
+    }
+
+    if config.profile.metrics() {
+        metrics::set_max_tracked_containers(config.metrics_max_containers);
+        rustjail::cgroups::instrumented::set_observer(metrics::observe_cgroup_op);
+    } else {
+        metrics::set_enabled(false);
+        metrics::set_max_tracked_containers(0);
+    }
+    postmortem::init(config.profile.metrics());
+    info!(logger, "agent confined"; "confinement" => config.confinement().as_str());
+
     // This variable is required as it enables the global (and crucially static) logger,
     // which is required to satisfy the the lifetime constraints of the auto-generated gRPC code.
     let global_logger = slog_scope::set_global_logger(logger.new(o!("subsystem" => "rpc")));
@@ -205,7 +265,12 @@ async fn real_main() -> std::result::Result<(), Box<dyn std::error::Error>> {
         ttrpc_log_guard = Ok(slog_stdlog::init().map_err(|e| e)?);
     }
 
-    if config.tracing != tracer::TraceType::Disabled {
+    let trace_enabled = config.tracing != tracer::TraceType::Disabled && config.profile.tracing();
+    if config.tracing != tracer::TraceType::Disabled && !trace_enabled {
+        warn!(logger, "tracing is disabled by the agent profile"; "profile" => config.profile.as_str());
+    }
+
+    if trace_enabled {
         let _ = tracer::setup_tracing(NAME, &logger, &config)?;
     }
 
@@ -244,7 +309,7 @@ async fn real_main() -> std::result::Result<(), Box<dyn std::error::Error>> {
         }
     }
 
-    if config.tracing != tracer::TraceType::Disabled {
+    if trace_enabled {
         tracer::end_tracing();
     }
 
@@ -274,11 +339,36 @@ fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
         exit(0);
     }
 
-    let rt = tokio::runtime::Builder::new_multi_thread()
-        .enable_all()
-        .build()?;
+    env::set_var("RUST_BACKTRACE", "full");
+
+    console::initialize();
+
+    // support vsock log
+    let (rfd, wfd) = unistd::pipe2(OFlag::O_CLOEXEC)?;
+
+    let init_mode = unistd::getpid() == Pid::from_raw(1);
+    load_config(wfd, init_mode)?;
+
+    let (confinement, profile) = {
+        let config = futures::executor::block_on(AGENT_CONFIG.read());
+        (config.confinement(), config.profile)
+    };
+
+    // the capabilities and the seccomp filter are per thread, the agent is
+    // confined before the runtime starts its threads
+    confinement::apply(confinement)
+        .with_context(|| format!("failed to apply the {} confinement", confinement.as_str()))?;
 
-    rt.block_on(real_main())
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    if let Some(n) = profile.worker_threads() {
+        builder.worker_threads(n);
+    }
+    if let Some(n) = profile.max_blocking_threads() {
+        builder.max_blocking_threads(n);
+    }
+    let rt = builder.enable_all().build()?;
+
+    rt.block_on(real_main(rfd, wfd, init_mode))
 }
 
 #[instrument]
@@ -301,14 +391,49 @@ async fn start_sandbox(
         tasks.push(debug_console_task);
     }
 
+    // Create the device nodes missing in the guests without udev
+    static_dev::create_static_devices(
+        &logger,
+        Path::new(static_dev::DEV_DIR),
+        &config.static_devices,
+    );
+
     // Initialize unique sandbox structure.
-    let s = Sandbox::new(&logger).context("Failed to create sandbox")?;
+    let mut s = Sandbox::new(&logger).context("Failed to create sandbox")?;
+
+    // keep the ports set on the kernel command line out of the dynamic ones
+    if config.debug_console_vport > 0 {
+        s.vsock_ports
+            .reserve("debug_console", config.debug_console_vport as u32)?;
+    }
+    if config.log_vport > 0 {
+        s.vsock_ports.reserve("log", config.log_vport as u32)?;
+    }
+
+    let usage_history = config.profile.usage_history()
+        && config.usage_history_interval > 0
+        && config.usage_history_size > 0;
+
+    if usage_history {
+        s.usage_history = UsageHistory::new(config.usage_history_size);
+    }
+
+    // Capture the early boot logs before the kernel ring buffer wraps.
+    if config.boot_log_capture {
+        match bootlog::collect() {
+            Ok(logs) => s.boot_logs = Some(logs),
+            Err(e) => warn!(logger, "failed to capture boot logs"; "error" => format!("{:?}", e)),
+        }
+    }
     if init_mode {
         s.rtnl.handle_localhost().await?;
     }
 
     let sandbox = Arc::new(Mutex::new(s));
 
+    // re-attach to the cgroups of the containers of a previous agent
+    rpc::restore_cgroups(&sandbox).await;
+
     let signal_handler_task = tokio::spawn(setup_signal_handler(
         logger.clone(),
         sandbox.clone(),
@@ -321,6 +446,67 @@ async fn start_sandbox(
 
     tasks.push(uevents_handler_task);
 
+    if usage_history {
+        let usage_sampler_task = tokio::spawn(run_usage_sampler(
+            sandbox.clone(),
+            Duration::from_secs(config.usage_history_interval as u64),
+            shutdown.clone(),
+        ));
+
+        tasks.push(usage_sampler_task);
+    }
+
+    if config.profile.metrics() && config.fd_sample_interval > 0 {
+        let fd_sampler_task = tokio::spawn(run_fd_sampler(
+            sandbox.clone(),
+            Duration::from_secs(config.fd_sample_interval as u64),
+            shutdown.clone(),
+        ));
+
+        tasks.push(fd_sampler_task);
+    }
+
+    if config.virtiofs_watchdog_interval > 0 {
+        let virtiofs_watchdog_task = tokio::spawn(run_virtiofs_watchdog(
+            sandbox.clone(),
+            Duration::from_secs(config.virtiofs_watchdog_interval as u64),
+            config.virtiofs_watchdog_policy,
+            shutdown.clone(),
+        ));
+
+        tasks.push(virtiofs_watchdog_task);
+    }
+
+    if !config.grpc_server_addr.is_empty() {
+        let (aservice, hservice) = rpc::create_services(sandbox.clone());
+        let grpc_server_task = tokio::spawn(grpc::run_server(
+            config.grpc_server_addr.clone(),
+            aservice.into_iter().chain(hservice).collect(),
+            shutdown.clone(),
+        ));
+
+        tasks.push(grpc_server_task);
+    }
+
+    if !config.debug_http_addr.is_empty() {
+        let debug_http_task = tokio::spawn(debug_http::run_server(
+            config.debug_http_addr.clone(),
+            sandbox.clone(),
+            shutdown.clone(),
+        ));
+
+        tasks.push(debug_http_task);
+    }
+
+    if !config.dns_upstreams.is_empty() {
+        let dns_task = tokio::spawn(dns::run_stub_resolver(
+            config.dns_upstreams.clone(),
+            shutdown.clone(),
+        ));
+
+        tasks.push(dns_task);
+    }
+
     let (tx, rx) = tokio::sync::oneshot::channel();
     sandbox.lock().await.sender = Some(tx);
 
@@ -0,0 +1,255 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Memory dumps of the container processes, to debug hung processes when
+// there is no debug console. A dump is a gzip compressed tar archive with
+// the maps and status of the process, and an entry per memory region named
+// after its address range, read through /proc/<pid>/mem. It is held by the
+// agent until its last chunk is sent, its container is removed, or the
+// host stops reading it.
+//
+// The dumps expose the memory of the containers, so they are disabled
+// unless agent.memory_dump_max_size is set, which also caps the memory a
+// dump holds. As the kernel does for core dumps, the file backed regions
+// which aren't writable are left out, their content being the file's.
+// The process keeps running while it is dumped, so the regions may not be
+// consistent with each other.
+
+use anyhow::{anyhow, Context, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::fs::FileExt;
+use std::time::{Duration, Instant};
+
+// size of the chunks the dump is sent to the host in
+pub const CHUNK_SIZE: usize = 1024 * 1024;
+
+// how long a dump is kept without any of its chunks being read, so that
+// the memory of an abandoned transfer is released
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+// pseudo regions which can't be read through /proc/<pid>/mem
+const SPECIAL_REGIONS: &[&str] = &["[vvar]", "[vsyscall]"];
+
+#[derive(Debug, PartialEq)]
+struct Region {
+    start: u64,
+    end: u64,
+    perms: String,
+    inode: u64,
+    path: String,
+}
+
+impl Region {
+    fn dumpable(&self) -> bool {
+        if !self.perms.starts_with('r') || SPECIAL_REGIONS.contains(&self.path.as_str()) {
+            return false;
+        }
+
+        self.inode == 0 || self.perms.as_bytes().get(1) == Some(&b'w')
+    }
+}
+
+// parse_maps parses the lines of /proc/<pid>/maps, e.g.
+//   7f0e2c021000-7f0e2c042000 rw-p 00000000 00:00 0      [heap]
+fn parse_maps(maps: &str) -> Result<Vec<Region>> {
+    maps.lines()
+        .map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 5 {
+                return Err(anyhow!("invalid maps line {:?}", line));
+            }
+
+            let range: Vec<&str> = fields[0].splitn(2, '-').collect();
+            if range.len() != 2 {
+                return Err(anyhow!("invalid address range {:?}", fields[0]));
+            }
+
+            Ok(Region {
+                start: u64::from_str_radix(range[0], 16)?,
+                end: u64::from_str_radix(range[1], 16)?,
+                perms: fields[1].to_string(),
+                inode: fields[4].parse()?,
+                path: fields[5..].join(" "),
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct MemoryDump {
+    pub container_id: String,
+    pub exec_id: String,
+    pub data: Vec<u8>,
+    // some regions were cut short by the size limit
+    pub truncated: bool,
+    last_read: Instant,
+}
+
+impl MemoryDump {
+    // chunk returns the data at offset, and whether it is the last chunk.
+    pub fn chunk(&mut self, offset: u64) -> Result<(&[u8], bool)> {
+        self.last_read = Instant::now();
+
+        let offset = offset as usize;
+        if offset > self.data.len() {
+            return Err(anyhow!(
+                "offset {} beyond the dump size {}",
+                offset,
+                self.data.len()
+            ));
+        }
+
+        let end = std::cmp::min(offset + CHUNK_SIZE, self.data.len());
+
+        Ok((&self.data[offset..end], end == self.data.len()))
+    }
+
+    // idle tells whether no chunk was read for IDLE_TIMEOUT.
+    pub fn idle(&self) -> bool {
+        self.last_read.elapsed() >= IDLE_TIMEOUT
+    }
+}
+
+// dump returns the dump of the process `pid`, holding at most max_size
+// bytes of its memory.
+pub fn dump(cid: &str, eid: &str, pid: i32, max_size: u64) -> Result<MemoryDump> {
+    let proc_dir = format!("/proc/{}", pid);
+    let maps = fs::read_to_string(format!("{}/maps", proc_dir))
+        .with_context(|| format!("failed to read the maps of process {}", pid))?;
+    let status = fs::read(format!("{}/status", proc_dir)).unwrap_or_default();
+    let mem = File::open(format!("{}/mem", proc_dir))
+        .with_context(|| format!("failed to open the memory of process {}", pid))?;
+
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    append_data(&mut builder, "maps", maps.as_bytes())?;
+    append_data(&mut builder, "status", &status)?;
+
+    let mut left = max_size;
+    let mut truncated = false;
+
+    for r in parse_maps(&maps)?.iter().filter(|r| r.dumpable()) {
+        let size = r.end - r.start;
+        if size > left {
+            truncated = true;
+        }
+
+        let data = read_region(&mem, r.start, std::cmp::min(size, left));
+        left -= data.len() as u64;
+
+        // an empty entry for the regions which couldn't be read, so that
+        // they can be told from the skipped ones
+        let name = format!("regions/{:x}-{:x}", r.start, r.end);
+        append_data(&mut builder, &name, &data)?;
+
+        if left == 0 {
+            break;
+        }
+    }
+
+    let data = builder
+        .into_inner()
+        .and_then(|e| e.finish())
+        .context("failed to build the memory dump")?;
+
+    Ok(MemoryDump {
+        container_id: cid.to_string(),
+        exec_id: eid.to_string(),
+        data,
+        truncated,
+        last_read: Instant::now(),
+    })
+}
+
+// read_region reads up to size bytes of memory at addr, stopping at the
+// first page which can't be read, e.g. a guard page.
+fn read_region(mem: &File, addr: u64, size: u64) -> Vec<u8> {
+    let mut data = vec![0u8; size as usize];
+    let mut n = 0;
+
+    while n < data.len() {
+        match mem.read_at(&mut data[n..], addr + n as u64) {
+            Ok(0) | Err(_) => break,
+            Ok(len) => n += len,
+        }
+    }
+
+    data.truncate(n);
+    data
+}
+
+fn append_data<W: Write>(builder: &mut tar::Builder<W>, name: &str, data: &[u8]) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o400);
+    header.set_cksum();
+
+    builder.append_data(&mut header, name, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn test_parse_maps() {
+        let regions = parse_maps(
+            "55d0c8a00000-55d0c8a21000 r-xp 00000000 fd:01 1234     /usr/bin/app\n\
+             55d0c8c21000-55d0c8c22000 rw-p 00021000 fd:01 1234     /usr/bin/app\n\
+             55d0c9000000-55d0c9021000 rw-p 00000000 00:00 0        [heap]\n\
+             7ffd1c5f0000-7ffd1c5f4000 r--p 00000000 00:00 0        [vvar]\n\
+             7f0e2c000000-7f0e2c021000 ---p 00000000 00:00 0\n",
+        )
+        .unwrap();
+
+        assert_eq!(regions.len(), 5);
+        assert_eq!(regions[0].start, 0x55d0c8a00000);
+        assert_eq!(regions[0].end, 0x55d0c8a21000);
+        assert_eq!(regions[0].path, "/usr/bin/app");
+        assert_eq!(regions[4].path, "");
+
+        let dumpable: Vec<bool> = regions.iter().map(|r| r.dumpable()).collect();
+        assert_eq!(dumpable, vec![false, true, true, false, false]);
+
+        assert!(parse_maps("55d0c8a00000 r-xp 00000000 fd:01 1234").is_err());
+    }
+
+    #[test]
+    fn test_dump() {
+        let pid = std::process::id() as i32;
+        let mut d = dump("c1", "", pid, 64 * 1024).unwrap();
+        assert!(d.truncated);
+        assert!(!d.idle());
+
+        let mut archive = tar::Archive::new(GzDecoder::new(d.data.as_slice()));
+        let mut names = vec![];
+        let mut size = 0;
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().to_string_lossy().to_string();
+            if name.starts_with("regions/") {
+                let mut buf = vec![];
+                entry.read_to_end(&mut buf).unwrap();
+                size += buf.len();
+            }
+            names.push(name);
+        }
+        assert_eq!(&names[..2], &["maps", "status"]);
+        assert!(names.len() > 2);
+        assert!(size <= 64 * 1024);
+
+        let len = d.data.len();
+        let (chunk, eof) = d.chunk(0).unwrap();
+        assert_eq!(chunk.len(), len);
+        assert!(eof);
+        assert!(d.chunk(d.data.len() as u64 + 1).is_err());
+
+        assert!(dump("c1", "", -1, 1024).is_err());
+    }
+}
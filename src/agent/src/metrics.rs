@@ -5,13 +5,227 @@
 
 extern crate procfs;
 
-use prometheus::{Encoder, Gauge, GaugeVec, IntCounter, TextEncoder};
+use prometheus::proto::{MetricFamily, MetricType};
+use prometheus::{
+    Encoder, Gauge, GaugeVec, HistogramVec, IntCounter, IntCounterVec, ProtobufEncoder,
+    TextEncoder, PROTOBUF_FORMAT, TEXT_FORMAT,
+};
 
-use anyhow::Result;
+use crate::allocator::AllocatorStats;
+use crate::fd_usage::FdUsage;
+use crate::preflight;
+use anyhow::{anyhow, Result};
+use nix::sys::statvfs;
+use protocols::agent::{CgroupEventType, GetMetricsRequest, Metrics, MetricsFormat};
+use rustjail::cgroups::fs::psi::{Pressure, PSI_RESOURCES};
+use rustjail::cgroups::notifier;
+use rustjail::cgroups::stats::HugetlbStats;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
 use tracing::instrument;
 
 const NAMESPACE_KATA_AGENT: &str = "kata_agent";
 const NAMESPACE_KATA_GUEST: &str = "kata_guest";
+const NAMESPACE_KATA_CONTAINER: &str = "kata_container";
+
+const CGROUP_EVENT_OOM: &str = "oom";
+const CGROUP_EVENT_MEMORY_PRESSURE: &str = "memory_pressure";
+const CGROUP_EVENT_PIDS_MAX: &str = "pids_max";
+
+const FDS_OPEN: &str = "open";
+const FDS_LIMIT: &str = "limit";
+const FDS_HIGH_WATER: &str = "high_water";
+
+const PSI_SOME: &str = "some";
+const PSI_FULL: &str = "full";
+const PSI_ITEMS: &[&str] = &["avg10", "avg60", "avg300", "total"];
+
+const HUGETLB_USAGE: &str = "usage";
+const HUGETLB_MAX_USAGE: &str = "max_usage";
+const HUGETLB_FAILCNT: &str = "failcnt";
+
+const OPENMETRICS_FORMAT: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+const PROC_SOCKSTAT: &str = "/proc/net/sockstat";
+const PROC_SNMP: &str = "/proc/net/snmp";
+const PROC_INTERRUPTS: &str = "/proc/interrupts";
+const PROC_SOFTIRQS: &str = "/proc/softirqs";
+const CONNTRACK_COUNT: &str = "/proc/sys/net/netfilter/nf_conntrack_count";
+const CONNTRACK_MAX: &str = "/proc/sys/net/netfilter/nf_conntrack_max";
+
+// the counters of /proc/net/snmp exported, by protocol
+const SNMP_COUNTERS: &[(&str, &[&str])] = &[
+    (
+        "Ip",
+        &[
+            "InReceives",
+            "InDiscards",
+            "OutRequests",
+            "OutDiscards",
+            "ReasmFails",
+            "FragFails",
+        ],
+    ),
+    ("Icmp", &["InMsgs", "InErrors", "OutMsgs", "OutErrors"]),
+    (
+        "Tcp",
+        &[
+            "ActiveOpens",
+            "PassiveOpens",
+            "AttemptFails",
+            "EstabResets",
+            "CurrEstab",
+            "InSegs",
+            "OutSegs",
+            "RetransSegs",
+            "InErrs",
+            "OutRsts",
+        ],
+    ),
+    (
+        "Udp",
+        &[
+            "InDatagrams",
+            "NoPorts",
+            "InErrors",
+            "OutDatagrams",
+            "RcvbufErrors",
+            "SndbufErrors",
+        ],
+    ),
+];
+
+pub const COLLECTOR_AGENT: &str = "agent";
+pub const COLLECTOR_GUEST_FS: &str = "guest_fs";
+pub const COLLECTOR_CONTAINER_CGROUPS: &str = "container_cgroups";
+
+// Collector is a group of metrics a scrape can ask for alone, by name, so
+// that the frequent scrapes can skip the expensive ones.
+struct Collector {
+    name: &'static str,
+    metrics: &'static [&'static str],
+}
+
+const COLLECTORS: &[Collector] = &[
+    Collector {
+        name: COLLECTOR_AGENT,
+        metrics: &[
+            "kata_agent_scrape_count",
+            "kata_agent_threads",
+            "kata_agent_total_time",
+            "kata_agent_total_vm",
+            "kata_agent_total_rss",
+            "kata_agent_proc_status",
+            "kata_agent_io_stat",
+            "kata_agent_proc_stat",
+            "kata_agent_allocator_stat",
+        ],
+    },
+    Collector {
+        name: "agent_rpc",
+        metrics: &["kata_agent_rpc_duration_seconds", "kata_agent_rpc_errors"],
+    },
+    Collector {
+        name: "agent_cgroups",
+        metrics: &[
+            "kata_agent_cgroup_op_duration_seconds",
+            "kata_agent_cgroup_op_errors",
+            "kata_agent_cgroup_watches",
+        ],
+    },
+    Collector {
+        name: "agent_dns",
+        metrics: &["kata_agent_dns_queries", "kata_agent_dns_cache_entries"],
+    },
+    Collector {
+        name: "guest_load",
+        metrics: &["kata_guest_load", "kata_guest_tasks"],
+    },
+    Collector {
+        name: "guest_cpu",
+        metrics: &["kata_guest_cpu_time"],
+    },
+    Collector {
+        name: "guest_vm",
+        metrics: &["kata_guest_vm_stat"],
+    },
+    Collector {
+        name: "guest_net",
+        metrics: &["kata_guest_netdev_stat"],
+    },
+    Collector {
+        name: "guest_disk",
+        metrics: &["kata_guest_diskstat"],
+    },
+    Collector {
+        name: "guest_mem",
+        metrics: &["kata_guest_meminfo"],
+    },
+    Collector {
+        name: "guest_hugepages",
+        metrics: &["kata_guest_hugepages"],
+    },
+    Collector {
+        name: "guest_sockets",
+        metrics: &[
+            "kata_guest_sockstat",
+            "kata_guest_snmp",
+            "kata_guest_conntrack",
+        ],
+    },
+    Collector {
+        name: "guest_interrupts",
+        metrics: &["kata_guest_interrupts", "kata_guest_softirqs"],
+    },
+    Collector {
+        name: COLLECTOR_GUEST_FS,
+        metrics: &["kata_guest_fs_usage"],
+    },
+    Collector {
+        name: COLLECTOR_CONTAINER_CGROUPS,
+        metrics: &[
+            "kata_container_cgroup_events",
+            "kata_container_pressure",
+            "kata_container_hugetlb",
+        ],
+    },
+    Collector {
+        name: "container_fds",
+        metrics: &["kata_container_fds"],
+    },
+];
+
+pub const DNS_CACHE_HIT: &str = "cache_hit";
+pub const DNS_CACHE_MISS: &str = "cache_miss";
+pub const DNS_UPSTREAM_ERROR: &str = "upstream_error";
+
+const FS_USED_BYTES: &str = "used_bytes";
+const FS_FREE_BYTES: &str = "free_bytes";
+const FS_USED_INODES: &str = "used_inodes";
+const FS_FREE_INODES: &str = "free_inodes";
+
+// the longest the usage of a filesystem is waited for
+const FS_USAGE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// the buckets of the RPC latencies, in seconds, up to the long running
+// RPCs, e.g. WaitProcess
+const RPC_DURATION_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+// the buckets of the container cgroup operation latencies, in seconds, a
+// slow one being a few writes stuck on a busy cgroup
+const CGROUP_OP_DURATION_BUCKETS: &[f64] = &[
+    0.0001, 0.0005, 0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0,
+];
+
+// the guest filesystems whose usage is reported along with the mounts of
+// the sandbox and containers
+pub const GUEST_FS_MOUNTS: &[&str] = &["/run"];
 
 // Convenience macro to obtain the scope logger
 macro_rules! sl {
@@ -46,6 +260,30 @@ lazy_static! {
     static ref     AGENT_PROC_STAT: GaugeVec =
     prometheus::register_gauge_vec!(format!("{}_{}",NAMESPACE_KATA_AGENT,"proc_stat").as_ref(), "Agent process statistics.", &["item"]).unwrap();
 
+    static ref     AGENT_ALLOCATOR_STAT: GaugeVec =
+    prometheus::register_gauge_vec!(format!("{}_{}",NAMESPACE_KATA_AGENT,"allocator_stat").as_ref(), "Agent memory allocator statistics.", &["item"]).unwrap();
+
+    static ref     AGENT_DNS_QUERIES: IntCounterVec =
+    prometheus::register_int_counter_vec!(format!("{}_{}",NAMESPACE_KATA_AGENT,"dns_queries").as_ref(), "Queries of the DNS stub resolver.", &["result"]).unwrap();
+
+    static ref     AGENT_DNS_CACHE_ENTRIES: Gauge =
+    prometheus::register_gauge!(format!("{}_{}",NAMESPACE_KATA_AGENT,"dns_cache_entries").as_ref(), "Answers cached by the DNS stub resolver").unwrap();
+
+    static ref     AGENT_RPC_DURATION: HistogramVec =
+    prometheus::register_histogram_vec!(format!("{}_{}",NAMESPACE_KATA_AGENT,"rpc_duration_seconds").as_ref(), "Agent RPC latencies.", &["method"], RPC_DURATION_BUCKETS.to_vec()).unwrap();
+
+    static ref     AGENT_RPC_ERRORS: IntCounterVec =
+    prometheus::register_int_counter_vec!(format!("{}_{}",NAMESPACE_KATA_AGENT,"rpc_errors").as_ref(), "Agent RPCs failed, by status code.", &["method","code"]).unwrap();
+
+    static ref     AGENT_CGROUP_OP_DURATION: HistogramVec =
+    prometheus::register_histogram_vec!(format!("{}_{}",NAMESPACE_KATA_AGENT,"cgroup_op_duration_seconds").as_ref(), "Container cgroup operation latencies.", &["op"], CGROUP_OP_DURATION_BUCKETS.to_vec()).unwrap();
+
+    static ref     AGENT_CGROUP_OP_ERRORS: IntCounterVec =
+    prometheus::register_int_counter_vec!(format!("{}_{}",NAMESPACE_KATA_AGENT,"cgroup_op_errors").as_ref(), "Container cgroup operations failed, by error kind.", &["op","kind"]).unwrap();
+
+    static ref     AGENT_CGROUP_WATCHES: Gauge =
+    prometheus::register_gauge!(format!("{}_{}",NAMESPACE_KATA_AGENT,"cgroup_watches").as_ref(), "Cgroup event watches running").unwrap();
+
     // guest os metrics
     static ref     GUEST_LOAD: GaugeVec =
     prometheus::register_gauge_vec!(format!("{}_{}",NAMESPACE_KATA_GUEST,"load").as_ref() , "Guest system load.", &["item"]).unwrap();
@@ -67,26 +305,421 @@ lazy_static! {
 
     static ref     GUEST_MEMINFO: GaugeVec =
     prometheus::register_gauge_vec!(format!("{}_{}",NAMESPACE_KATA_GUEST,"meminfo").as_ref() , "Statistics about memory usage in the system.", &["item"]).unwrap();
+
+    static ref     GUEST_HUGEPAGES: GaugeVec =
+    prometheus::register_gauge_vec!(format!("{}_{}",NAMESPACE_KATA_GUEST,"hugepages").as_ref() , "Guest hugepage pools, in pages.", &["size","item"]).unwrap();
+
+    static ref     GUEST_SOCKSTAT: GaugeVec =
+    prometheus::register_gauge_vec!(format!("{}_{}",NAMESPACE_KATA_GUEST,"sockstat").as_ref() , "Guest sockets statistics.", &["protocol","item"]).unwrap();
+
+    static ref     GUEST_SNMP: GaugeVec =
+    prometheus::register_gauge_vec!(format!("{}_{}",NAMESPACE_KATA_GUEST,"snmp").as_ref() , "Guest network protocols counters.", &["protocol","item"]).unwrap();
+
+    static ref     GUEST_CONNTRACK: GaugeVec =
+    prometheus::register_gauge_vec!(format!("{}_{}",NAMESPACE_KATA_GUEST,"conntrack").as_ref() , "Guest connection tracking table entries.", &["item"]).unwrap();
+
+    static ref     GUEST_INTERRUPTS: GaugeVec =
+    prometheus::register_gauge_vec!(format!("{}_{}",NAMESPACE_KATA_GUEST,"interrupts").as_ref() , "Guest interrupts of all the cpus.", &["irq"]).unwrap();
+
+    static ref     GUEST_SOFTIRQS: GaugeVec =
+    prometheus::register_gauge_vec!(format!("{}_{}",NAMESPACE_KATA_GUEST,"softirqs").as_ref() , "Guest softirqs of all the cpus.", &["type"]).unwrap();
+
+    static ref     GUEST_FS_USAGE: GaugeVec =
+    prometheus::register_gauge_vec!(format!("{}_{}",NAMESPACE_KATA_GUEST,"fs_usage").as_ref() , "Usage of the filesystems mounted in the guest.", &["mountpoint","container_id","item"]).unwrap();
+
+    // container metrics, the label values of a container are removed
+    // once it is destroyed
+    static ref     CONTAINER_CGROUP_EVENTS: IntCounterVec =
+    prometheus::register_int_counter_vec!(format!("{}_{}",NAMESPACE_KATA_CONTAINER,"cgroup_events").as_ref() , "Container cgroup events.", &["container_id","type"]).unwrap();
+
+    static ref     CONTAINER_FDS: GaugeVec =
+    prometheus::register_gauge_vec!(format!("{}_{}",NAMESPACE_KATA_CONTAINER,"fds").as_ref() , "Container open file descriptors.", &["container_id","item"]).unwrap();
+
+    static ref     CONTAINER_PRESSURE: GaugeVec =
+    prometheus::register_gauge_vec!(format!("{}_{}",NAMESPACE_KATA_CONTAINER,"pressure").as_ref() , "Container pressure stall information.", &["container_id","resource","kind","item"]).unwrap();
+
+    static ref     CONTAINER_HUGETLB: GaugeVec =
+    prometheus::register_gauge_vec!(format!("{}_{}",NAMESPACE_KATA_CONTAINER,"hugetlb").as_ref() , "Container hugetlb usage, by page size.", &["container_id","size","item"]).unwrap();
+
+    static ref     TRACKED_CONTAINERS: Mutex<ContainerTracker> = Mutex::new(ContainerTracker::default());
+}
+
+// ContainerTracker bounds the number of containers metrics are recorded
+// for, so that the registry doesn't grow with the containers churn.
+#[derive(Debug, Default)]
+struct ContainerTracker {
+    max: usize,
+    ids: HashSet<String>,
+}
+
+impl ContainerTracker {
+    fn track(&mut self, cid: &str) -> bool {
+        if self.ids.contains(cid) {
+            return true;
+        }
+
+        if self.ids.len() >= self.max {
+            return false;
+        }
+
+        self.ids.insert(cid.to_string())
+    }
+
+    fn untrack(&mut self, cid: &str) -> bool {
+        self.ids.remove(cid)
+    }
+
+    fn is_tracked(&self, cid: &str) -> bool {
+        self.ids.contains(cid)
+    }
+}
+
+// whether the agent records metrics at all, the metrics being registered
+// on their first use
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+// set_enabled turns the recording of the metrics on or off, e.g. for the
+// profiles without metrics, which then don't allocate any.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// set_max_tracked_containers sets how many containers at most get
+// per container metrics, zero disabling them.
+pub fn set_max_tracked_containers(max: usize) {
+    TRACKED_CONTAINERS.lock().unwrap().max = max;
+}
+
+// track_container starts recording the metrics of a container, unless
+// the maximum number of tracked containers is reached.
+pub fn track_container(cid: &str) {
+    let mut tracked = TRACKED_CONTAINERS.lock().unwrap();
+    if tracked.max > 0 && !tracked.track(cid) {
+        warn!(sl!(), "too many containers, no metrics recorded"; "container" => cid);
+    }
+}
+
+// remove_container_metrics drops the metrics of a destroyed container.
+pub fn remove_container_metrics(cid: &str) {
+    if !TRACKED_CONTAINERS.lock().unwrap().untrack(cid) {
+        return;
+    }
+
+    for t in &[
+        CGROUP_EVENT_OOM,
+        CGROUP_EVENT_MEMORY_PRESSURE,
+        CGROUP_EVENT_PIDS_MAX,
+    ] {
+        // the label values only exist once an event has been counted
+        let _ = CONTAINER_CGROUP_EVENTS.remove_label_values(&[cid, t]);
+    }
+
+    for item in &[FDS_OPEN, FDS_LIMIT, FDS_HIGH_WATER] {
+        let _ = CONTAINER_FDS.remove_label_values(&[cid, item]);
+    }
+
+    for resource in PSI_RESOURCES {
+        for kind in &[PSI_SOME, PSI_FULL] {
+            for item in PSI_ITEMS {
+                let _ = CONTAINER_PRESSURE.remove_label_values(&[cid, *resource, *kind, *item]);
+            }
+        }
+    }
+
+    // the page sizes are the ones of the guest hugepage pools
+    for pool in preflight::hugepage_pools(Path::new("/")) {
+        for item in &[HUGETLB_USAGE, HUGETLB_MAX_USAGE, HUGETLB_FAILCNT] {
+            let _ = CONTAINER_HUGETLB.remove_label_values(&[cid, pool.size.as_str(), *item]);
+        }
+    }
+}
+
+pub fn inc_container_cgroup_event(cid: &str, event_type: CgroupEventType) {
+    if !TRACKED_CONTAINERS.lock().unwrap().is_tracked(cid) {
+        return;
+    }
+
+    let t = match event_type {
+        CgroupEventType::OOM => CGROUP_EVENT_OOM,
+        CgroupEventType::MEMORY_PRESSURE => CGROUP_EVENT_MEMORY_PRESSURE,
+        CgroupEventType::PIDS_MAX => CGROUP_EVENT_PIDS_MAX,
+    };
+
+    CONTAINER_CGROUP_EVENTS.with_label_values(&[cid, t]).inc();
+}
+
+// set_container_pressure records the pressure stall information of a
+// container, the averages in percents and the total in microseconds.
+pub fn set_container_pressure(cid: &str, pressure: &[(&str, Pressure)]) {
+    if !TRACKED_CONTAINERS.lock().unwrap().is_tracked(cid) {
+        return;
+    }
+
+    for (resource, p) in pressure {
+        let kinds = std::iter::once((PSI_SOME, p.some)).chain(p.full.map(|f| (PSI_FULL, f)));
+        for (kind, data) in kinds {
+            let values = [data.avg10, data.avg60, data.avg300, data.total as f64];
+            for (item, value) in PSI_ITEMS.iter().zip(values.iter()) {
+                CONTAINER_PRESSURE
+                    .with_label_values(&[cid, *resource, kind, *item])
+                    .set(*value);
+            }
+        }
+    }
+}
+
+// set_container_hugetlb records the hugetlb usage of a container in bytes,
+// and the number of allocations which failed on its hugetlb limits, by
+// page size.
+pub fn set_container_hugetlb(cid: &str, stats: &HashMap<String, HugetlbStats>) {
+    if !TRACKED_CONTAINERS.lock().unwrap().is_tracked(cid) {
+        return;
+    }
+
+    for (size, s) in stats {
+        let values = [
+            (HUGETLB_USAGE, s.usage),
+            (HUGETLB_MAX_USAGE, s.max_usage),
+            (HUGETLB_FAILCNT, s.failcnt),
+        ];
+        for (item, value) in values.iter() {
+            CONTAINER_HUGETLB
+                .with_label_values(&[cid, size.as_str(), *item])
+                .set(*value as f64);
+        }
+    }
+}
+
+// statvfs_timeout returns the usage of the filesystem at a mount point,
+// giving up after FS_USAGE_TIMEOUT, e.g. on a virtio-fs share whose host
+// side is stalled.
+async fn statvfs_timeout(mountpoint: &str) -> Result<statvfs::Statvfs> {
+    let path = mountpoint.to_string();
+    let st = tokio::time::timeout(
+        FS_USAGE_TIMEOUT,
+        tokio::task::spawn_blocking(move || statvfs::statvfs(path.as_str())),
+    )
+    .await
+    .map_err(|_| anyhow!("timed out"))??;
+
+    Ok(st?)
+}
+
+// set_fs_usage records the space and inodes used and free on the
+// filesystems at the mount points, each with the container it's mounted
+// for, empty for the ones of the sandbox. The mount points gone since the
+// last call are dropped.
+pub async fn set_fs_usage(mounts: Vec<(String, String)>) {
+    let mounts: Vec<(String, String)> = {
+        let tracked = TRACKED_CONTAINERS.lock().unwrap();
+        mounts
+            .into_iter()
+            .filter(|(_, cid)| cid.is_empty() || tracked.is_tracked(cid))
+            .collect()
+    };
+
+    let usage = futures::future::join_all(mounts.iter().map(|(m, _)| statvfs_timeout(m))).await;
+
+    GUEST_FS_USAGE.reset();
+    for ((mountpoint, cid), st) in mounts.iter().zip(usage) {
+        let st = match st {
+            Ok(st) => st,
+            Err(e) => {
+                warn!(sl!(), "failed to get the filesystem usage"; "mountpoint" => mountpoint, "error" => format!("{:?}", e));
+                continue;
+            }
+        };
+
+        let frsize = st.fragment_size() as f64;
+        let values = [
+            (
+                FS_USED_BYTES,
+                st.blocks().saturating_sub(st.blocks_free()) as f64 * frsize,
+            ),
+            (FS_FREE_BYTES, st.blocks_available() as f64 * frsize),
+            (
+                FS_USED_INODES,
+                st.files().saturating_sub(st.files_free()) as f64,
+            ),
+            (FS_FREE_INODES, st.files_free() as f64),
+        ];
+        for (item, value) in values.iter() {
+            GUEST_FS_USAGE
+                .with_label_values(&[mountpoint.as_str(), cid.as_str(), *item])
+                .set(*value);
+        }
+    }
+}
+
+pub fn inc_dns_queries(result: &str) {
+    if !enabled() {
+        return;
+    }
+
+    AGENT_DNS_QUERIES.with_label_values(&[result]).inc();
+}
+
+pub fn set_dns_cache_entries(entries: usize) {
+    if !enabled() {
+        return;
+    }
+
+    AGENT_DNS_CACHE_ENTRIES.set(entries as f64);
+}
+
+// observe_rpc records the latency of an RPC, and counts it as failed when
+// it returned an error code.
+pub fn observe_rpc(method: &str, duration: Duration, code: Option<&str>) {
+    if !enabled() {
+        return;
+    }
+
+    AGENT_RPC_DURATION
+        .with_label_values(&[method])
+        .observe(duration.as_secs_f64());
+
+    if let Some(code) = code {
+        AGENT_RPC_ERRORS.with_label_values(&[method, code]).inc();
+    }
+}
+
+// observe_cgroup_op records the latency of a container cgroup operation,
+// and counts it as failed when it returned an error, by kind.
+pub fn observe_cgroup_op(op: &str, duration: Duration, kind: Option<&str>) {
+    AGENT_CGROUP_OP_DURATION
+        .with_label_values(&[op])
+        .observe(duration.as_secs_f64());
+
+    if let Some(kind) = kind {
+        AGENT_CGROUP_OP_ERRORS.with_label_values(&[op, kind]).inc();
+    }
+}
+
+// set_container_fds records a sample of the fds of the process of a
+// container closest to its limit, along with the highest number of fds seen
+// open.
+pub fn set_container_fds(cid: &str, usage: &FdUsage) {
+    if !TRACKED_CONTAINERS.lock().unwrap().is_tracked(cid) {
+        return;
+    }
+
+    let open = usage.open as f64;
+    CONTAINER_FDS.with_label_values(&[cid, FDS_OPEN]).set(open);
+    CONTAINER_FDS
+        .with_label_values(&[cid, FDS_LIMIT])
+        .set(usage.limit as f64);
+
+    let high_water = CONTAINER_FDS.with_label_values(&[cid, FDS_HIGH_WATER]);
+    if open > high_water.get() {
+        high_water.set(open);
+    }
+}
+
+// check_request checks the collectors a request asks for exist.
+pub fn check_request(req: &GetMetricsRequest) -> Result<()> {
+    match req
+        .collectors
+        .iter()
+        .find(|c| !COLLECTORS.iter().any(|k| k.name == c.as_str()))
+    {
+        Some(c) => Err(anyhow!("unknown metrics collector {:?}", c)),
+        None => Ok(()),
+    }
+}
+
+// collector_selected returns whether a request asks for the collector
+// `name`, all of them being when it names none.
+pub fn collector_selected(req: &GetMetricsRequest, name: &str) -> bool {
+    req.collectors.is_empty() || req.collectors.iter().any(|c| c == name)
 }
 
 #[instrument]
-pub fn get_metrics(_: &protocols::agent::GetMetricsRequest) -> Result<String> {
+pub fn get_metrics(req: &GetMetricsRequest) -> Result<Metrics> {
+    check_request(req)?;
+
     AGENT_SCRAPE_COUNT.inc();
 
     // update agent process metrics
-    update_agent_metrics();
+    if collector_selected(req, COLLECTOR_AGENT) {
+        update_agent_metrics();
+    }
+
+    if collector_selected(req, "agent_cgroups") {
+        AGENT_CGROUP_WATCHES.set(notifier::active_watches() as f64);
+    }
 
     // update guest os metrics
-    update_guest_metrics();
+    update_guest_metrics(req);
+
+    let selected: HashSet<&str> = COLLECTORS
+        .iter()
+        .filter(|c| collector_selected(req, c.name))
+        .flat_map(|c| c.metrics.iter().copied())
+        .collect();
+    let metric_families: Vec<MetricFamily> = prometheus::gather()
+        .into_iter()
+        .filter(|mf| req.collectors.is_empty() || selected.contains(mf.get_name()))
+        .collect();
+
+    let mut metrics = Metrics::new();
+    match req.format {
+        MetricsFormat::METRICS_TEXT => {
+            let mut buffer = Vec::new();
+            TextEncoder::new().encode(&metric_families, &mut buffer)?;
+            metrics.metrics = String::from_utf8(buffer)?;
+            metrics.content_type = TEXT_FORMAT.to_string();
+        }
+        MetricsFormat::METRICS_OPENMETRICS => {
+            metrics.metrics = encode_openmetrics(&metric_families)?;
+            metrics.content_type = OPENMETRICS_FORMAT.to_string();
+        }
+        MetricsFormat::METRICS_PROTOBUF => {
+            ProtobufEncoder::new().encode(&metric_families, &mut metrics.encoded_metrics)?;
+            metrics.content_type = PROTOBUF_FORMAT.to_string();
+        }
+    }
 
-    // gather all metrics and return as a String
-    let metric_families = prometheus::gather();
+    Ok(metrics)
+}
 
-    let mut buffer = Vec::new();
+// encode_openmetrics encodes the metrics in the OpenMetrics text format,
+// which is the Prometheus one but for the samples of the counters, named
+// after their family with a _total suffix, and the EOF marker.
+fn encode_openmetrics(metric_families: &[MetricFamily]) -> Result<String> {
     let encoder = TextEncoder::new();
-    encoder.encode(&metric_families, &mut buffer).unwrap();
+    let mut out = String::new();
 
-    Ok(String::from_utf8(buffer).unwrap())
+    for mf in metric_families {
+        let mut buffer = Vec::new();
+        encoder.encode(std::slice::from_ref(mf), &mut buffer)?;
+        let text = String::from_utf8(buffer)?;
+
+        if mf.get_field_type() != MetricType::COUNTER {
+            out.push_str(&text);
+            continue;
+        }
+
+        let name = mf.get_name();
+        let family = name.strip_suffix("_total").unwrap_or(name);
+        for line in text.lines() {
+            if line.starts_with('#') {
+                out.push_str(&line.replacen(&format!(" {} ", name), &format!(" {} ", family), 1));
+            } else {
+                out.push_str(family);
+                out.push_str("_total");
+                out.push_str(&line[name.len()..]);
+            }
+            out.push('\n');
+        }
+    }
+
+    out.push_str("# EOF\n");
+
+    Ok(out)
 }
 
 #[instrument]
@@ -137,89 +770,299 @@ fn update_agent_metrics() {
         }
         Ok(status) => set_gauge_vec_proc_status(&AGENT_PROC_STATUS, &status),
     }
+
+    match crate::allocator::stats() {
+        Err(err) => {
+            info!(sl!(), "failed to get allocator stats: {:?}", err);
+        }
+        Ok(Some(stats)) => set_gauge_vec_allocator_stats(&AGENT_ALLOCATOR_STAT, &stats),
+        Ok(None) => {}
+    }
 }
 
 #[instrument]
-fn update_guest_metrics() {
+fn update_guest_metrics(req: &GetMetricsRequest) {
+    let selected = |name: &str| collector_selected(req, name);
+
     // try get load and task info
-    match procfs::LoadAverage::new() {
-        Err(err) => {
-            info!(sl!(), "failed to get guest LoadAverage: {:?}", err);
-        }
-        Ok(load) => {
-            GUEST_LOAD
-                .with_label_values(&["load1"])
-                .set(load.one as f64);
-            GUEST_LOAD
-                .with_label_values(&["load5"])
-                .set(load.five as f64);
-            GUEST_LOAD
-                .with_label_values(&["load15"])
-                .set(load.fifteen as f64);
-            GUEST_TASKS.with_label_values(&["cur"]).set(load.cur as f64);
-            GUEST_TASKS.with_label_values(&["max"]).set(load.max as f64);
+    if selected("guest_load") {
+        match procfs::LoadAverage::new() {
+            Err(err) => {
+                info!(sl!(), "failed to get guest LoadAverage: {:?}", err);
+            }
+            Ok(load) => {
+                GUEST_LOAD
+                    .with_label_values(&["load1"])
+                    .set(load.one as f64);
+                GUEST_LOAD
+                    .with_label_values(&["load5"])
+                    .set(load.five as f64);
+                GUEST_LOAD
+                    .with_label_values(&["load15"])
+                    .set(load.fifteen as f64);
+                GUEST_TASKS.with_label_values(&["cur"]).set(load.cur as f64);
+                GUEST_TASKS.with_label_values(&["max"]).set(load.max as f64);
+            }
         }
     }
 
     // try to get disk stats
-    match procfs::diskstats() {
-        Err(err) => {
-            info!(sl!(), "failed to get guest diskstats: {:?}", err);
-        }
-        Ok(diskstats) => {
-            for diskstat in diskstats {
-                set_gauge_vec_diskstat(&GUEST_DISKSTAT, &diskstat);
+    if selected("guest_disk") {
+        match procfs::diskstats() {
+            Err(err) => {
+                info!(sl!(), "failed to get guest diskstats: {:?}", err);
+            }
+            Ok(diskstats) => {
+                for diskstat in diskstats {
+                    set_gauge_vec_diskstat(&GUEST_DISKSTAT, &diskstat);
+                }
             }
         }
     }
 
     // try to get vm stats
-    match procfs::vmstat() {
-        Err(err) => {
-            info!(sl!(), "failed to get guest vmstat: {:?}", err);
-        }
-        Ok(vmstat) => {
-            for (k, v) in vmstat {
-                GUEST_VM_STAT.with_label_values(&[k.as_str()]).set(v as f64);
+    if selected("guest_vm") {
+        match procfs::vmstat() {
+            Err(err) => {
+                info!(sl!(), "failed to get guest vmstat: {:?}", err);
+            }
+            Ok(vmstat) => {
+                for (k, v) in vmstat {
+                    GUEST_VM_STAT.with_label_values(&[k.as_str()]).set(v as f64);
+                }
             }
         }
     }
 
     // cpu stat
-    match procfs::KernelStats::new() {
-        Err(err) => {
-            info!(sl!(), "failed to get guest KernelStats: {:?}", err);
-        }
-        Ok(kernel_stats) => {
-            set_gauge_vec_cpu_time(&GUEST_CPU_TIME, "total", &kernel_stats.total);
-            for (i, cpu_time) in kernel_stats.cpu_time.iter().enumerate() {
-                set_gauge_vec_cpu_time(&GUEST_CPU_TIME, format!("{}", i).as_str(), &cpu_time);
+    if selected("guest_cpu") {
+        match procfs::KernelStats::new() {
+            Err(err) => {
+                info!(sl!(), "failed to get guest KernelStats: {:?}", err);
+            }
+            Ok(kernel_stats) => {
+                set_gauge_vec_cpu_time(&GUEST_CPU_TIME, "total", &kernel_stats.total);
+                for (i, cpu_time) in kernel_stats.cpu_time.iter().enumerate() {
+                    set_gauge_vec_cpu_time(&GUEST_CPU_TIME, format!("{}", i).as_str(), &cpu_time);
+                }
             }
         }
     }
 
     // try to get net device stats
-    match procfs::net::dev_status() {
+    // netdev: map[string]procfs::net::DeviceStatus
+    if selected("guest_net") {
+        match procfs::net::dev_status() {
+            Err(err) => {
+                info!(sl!(), "failed to get guest net::dev_status: {:?}", err);
+            }
+            Ok(devs) => {
+                for (_, status) in devs {
+                    set_gauge_vec_netdev(&GUEST_NETDEV_STAT, &status);
+                }
+            }
+        }
+    }
+
+    // get statistics about memory from /proc/meminfo
+    if selected("guest_mem") {
+        match procfs::Meminfo::new() {
+            Err(err) => {
+                info!(sl!(), "failed to get guest Meminfo: {:?}", err);
+            }
+            Ok(meminfo) => {
+                set_gauge_vec_meminfo(&GUEST_MEMINFO, &meminfo);
+            }
+        }
+    }
+
+    if selected("guest_hugepages") {
+        update_guest_hugepage_metrics();
+    }
+
+    if selected("guest_sockets") {
+        update_guest_socket_metrics();
+    }
+
+    if selected("guest_interrupts") {
+        update_guest_interrupt_metrics();
+    }
+}
+
+#[instrument]
+fn update_guest_hugepage_metrics() {
+    for pool in preflight::hugepage_pools(Path::new("/")) {
+        let values = [
+            ("total", pool.total),
+            ("free", pool.free),
+            ("reserved", pool.reserved),
+            ("surplus", pool.surplus),
+        ];
+        for (item, value) in values.iter() {
+            GUEST_HUGEPAGES
+                .with_label_values(&[pool.size.as_str(), *item])
+                .set(*value as f64);
+        }
+    }
+}
+
+#[instrument]
+fn update_guest_socket_metrics() {
+    match fs::read_to_string(PROC_SOCKSTAT) {
         Err(err) => {
-            info!(sl!(), "failed to get guest net::dev_status: {:?}", err);
+            info!(sl!(), "failed to get guest sockstat: {:?}", err);
         }
-        Ok(devs) => {
-            // netdev: map[string]procfs::net::DeviceStatus
-            for (_, status) in devs {
-                set_gauge_vec_netdev(&GUEST_NETDEV_STAT, &status);
+        Ok(content) => {
+            for (protocol, item, value) in parse_sockstat(&content) {
+                GUEST_SOCKSTAT
+                    .with_label_values(&[protocol, item])
+                    .set(value as f64);
             }
         }
     }
 
-    // get statistics about memory from /proc/meminfo
-    match procfs::Meminfo::new() {
+    match fs::read_to_string(PROC_SNMP) {
         Err(err) => {
-            info!(sl!(), "failed to get guest Meminfo: {:?}", err);
+            info!(sl!(), "failed to get guest snmp: {:?}", err);
+        }
+        Ok(content) => {
+            for (protocol, item, value) in parse_snmp(&content) {
+                let keep = SNMP_COUNTERS
+                    .iter()
+                    .any(|(p, items)| *p == protocol && items.contains(&item));
+                if keep {
+                    GUEST_SNMP
+                        .with_label_values(&[protocol, item])
+                        .set(value as f64);
+                }
+            }
+        }
+    }
+
+    // the conntrack module may not be loaded
+    for (item, path) in &[("count", CONNTRACK_COUNT), ("max", CONNTRACK_MAX)] {
+        if let Some(value) = fs::read_to_string(path)
+            .ok()
+            .and_then(|v| v.trim().parse::<u64>().ok())
+        {
+            GUEST_CONNTRACK
+                .with_label_values(&[*item])
+                .set(value as f64);
+        }
+    }
+}
+
+#[instrument]
+fn update_guest_interrupt_metrics() {
+    for (path, gv) in &[
+        (PROC_INTERRUPTS, &*GUEST_INTERRUPTS),
+        (PROC_SOFTIRQS, &*GUEST_SOFTIRQS),
+    ] {
+        match fs::read_to_string(path) {
+            Err(err) => {
+                info!(sl!(), "failed to read {}: {:?}", path, err);
+            }
+            Ok(content) => {
+                for (name, count) in parse_interrupts(&content) {
+                    gv.with_label_values(&[name.as_str()]).set(count as f64);
+                }
+            }
         }
-        Ok(meminfo) => {
-            set_gauge_vec_meminfo(&GUEST_MEMINFO, &meminfo);
+    }
+}
+
+// parse_sockstat parses /proc/net/sockstat, with a line of "name value"
+// pairs by protocol, e.g. "TCP: inuse 4 orphan 0 tw 0 alloc 4 mem 1".
+fn parse_sockstat(content: &str) -> Vec<(&str, &str, u64)> {
+    let mut stats = vec![];
+
+    for line in content.lines() {
+        let mut fields = line.split_whitespace();
+        let protocol = match fields.next().and_then(|p| p.strip_suffix(':')) {
+            Some(p) => p,
+            None => continue,
+        };
+
+        while let (Some(item), Some(value)) = (fields.next(), fields.next()) {
+            if let Ok(value) = value.parse() {
+                stats.push((protocol, item, value));
+            }
         }
     }
+
+    stats
+}
+
+// parse_snmp parses /proc/net/snmp, with a line of names followed by a
+// line of values by protocol, e.g. "Tcp: ActiveOpens PassiveOpens" and
+// "Tcp: 3 1". Some values are signed, e.g. Tcp MaxConn.
+fn parse_snmp(content: &str) -> Vec<(&str, &str, i64)> {
+    let mut stats = vec![];
+    let lines: Vec<&str> = content.lines().collect();
+
+    for pair in lines.chunks(2) {
+        let (names, values) = match pair {
+            [names, values] => (names, values),
+            _ => continue,
+        };
+
+        let mut names = names.split_whitespace();
+        let mut values = values.split_whitespace();
+        let protocol = match (names.next(), values.next()) {
+            (Some(n), Some(v)) if n == v => n.trim_end_matches(':'),
+            _ => continue,
+        };
+
+        for (name, value) in names.zip(values) {
+            if let Ok(value) = value.parse() {
+                stats.push((protocol, name, value));
+            }
+        }
+    }
+
+    stats
+}
+
+// parse_interrupts parses /proc/interrupts or /proc/softirqs, summing the
+// counts of all the cpus. The numbered interrupts are named after their
+// device, e.g. "virtio0-input.0", the interrupts of a device or of a type
+// being added up.
+fn parse_interrupts(content: &str) -> Vec<(String, u64)> {
+    let mut lines = content.lines();
+    let cpus = lines
+        .next()
+        .map(|header| header.split_whitespace().count())
+        .unwrap_or(0);
+
+    let mut counts: Vec<(String, u64)> = vec![];
+    for line in lines {
+        let (key, rest) = match line.find(':') {
+            Some(i) => (line[..i].trim(), &line[i + 1..]),
+            None => continue,
+        };
+
+        let mut fields = rest.split_whitespace().peekable();
+        let mut count = 0;
+        for _ in 0..cpus {
+            match fields.peek().and_then(|f| f.parse::<u64>().ok()) {
+                Some(c) => count += c,
+                None => break,
+            }
+            fields.next();
+        }
+
+        let name = match (key.parse::<u32>(), fields.last()) {
+            (Ok(_), Some(device)) => device,
+            _ => key,
+        };
+
+        match counts.iter_mut().find(|(n, _)| n == name) {
+            Some((_, c)) => *c += count,
+            None => counts.push((name.to_string(), count)),
+        }
+    }
+
+    counts
 }
 
 #[instrument]
@@ -500,3 +1343,247 @@ fn set_gauge_vec_proc_stat(gv: &prometheus::GaugeVec, stat: &procfs::process::St
     gv.with_label_values(&["cutime"]).set(stat.cutime as f64);
     gv.with_label_values(&["cstime"]).set(stat.cstime as f64);
 }
+
+// set_gauge_vec_allocator_stats set gauge for AllocatorStats
+#[instrument]
+fn set_gauge_vec_allocator_stats(gv: &prometheus::GaugeVec, stats: &AllocatorStats) {
+    gv.with_label_values(&["allocated"])
+        .set(stats.allocated as f64);
+    gv.with_label_values(&["active"]).set(stats.active as f64);
+    gv.with_label_values(&["resident"])
+        .set(stats.resident as f64);
+    gv.with_label_values(&["mapped"]).set(stats.mapped as f64);
+    gv.with_label_values(&["retained"])
+        .set(stats.retained as f64);
+    gv.with_label_values(&["fragmentation"])
+        .set(stats.fragmentation());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_container_tracker() {
+        let mut tracker = ContainerTracker {
+            max: 2,
+            ..Default::default()
+        };
+
+        assert!(tracker.track("c1"));
+        assert!(tracker.track("c2"));
+        assert!(tracker.track("c1"));
+        assert!(!tracker.track("c3"));
+        assert!(!tracker.is_tracked("c3"));
+
+        assert!(tracker.untrack("c1"));
+        assert!(!tracker.untrack("c1"));
+        assert!(tracker.track("c3"));
+        assert!(tracker.is_tracked("c3"));
+    }
+
+    #[test]
+    fn test_set_container_hugetlb() {
+        let mut stats = HashMap::new();
+        stats.insert(
+            "2MB".to_string(),
+            HugetlbStats {
+                usage: 4 << 20,
+                max_usage: 8 << 20,
+                failcnt: 3,
+                ..Default::default()
+            },
+        );
+
+        // nothing is recorded for the untracked containers
+        set_container_hugetlb("hugetlb-untracked", &stats);
+        assert!(CONTAINER_HUGETLB
+            .remove_label_values(&["hugetlb-untracked", "2MB", HUGETLB_FAILCNT])
+            .is_err());
+
+        TRACKED_CONTAINERS
+            .lock()
+            .unwrap()
+            .ids
+            .insert("hugetlb".to_string());
+        set_container_hugetlb("hugetlb", &stats);
+        let value = |item| {
+            CONTAINER_HUGETLB
+                .with_label_values(&["hugetlb", "2MB", item])
+                .get()
+        };
+        assert_eq!(value(HUGETLB_USAGE), (4 << 20) as f64);
+        assert_eq!(value(HUGETLB_MAX_USAGE), (8 << 20) as f64);
+        assert_eq!(value(HUGETLB_FAILCNT), 3.0);
+    }
+
+    #[tokio::test]
+    async fn test_set_fs_usage() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap().to_string();
+
+        set_fs_usage(vec![
+            (path.clone(), "".to_string()),
+            ("/nonexistent".to_string(), "".to_string()),
+            (path.clone(), "untracked".to_string()),
+        ])
+        .await;
+
+        for item in &[FS_USED_BYTES, FS_FREE_BYTES, FS_USED_INODES, FS_FREE_INODES] {
+            assert!(GUEST_FS_USAGE
+                .remove_label_values(&[path.as_str(), "", *item])
+                .is_ok());
+        }
+        assert!(GUEST_FS_USAGE
+            .remove_label_values(&["/nonexistent", "", FS_FREE_BYTES])
+            .is_err());
+        assert!(GUEST_FS_USAGE
+            .remove_label_values(&[path.as_str(), "untracked", FS_FREE_BYTES])
+            .is_err());
+    }
+
+    #[test]
+    fn test_collectors() {
+        lazy_static::initialize(&AGENT_RPC_DURATION);
+        lazy_static::initialize(&AGENT_RPC_ERRORS);
+        lazy_static::initialize(&AGENT_CGROUP_OP_DURATION);
+        lazy_static::initialize(&AGENT_CGROUP_OP_ERRORS);
+        lazy_static::initialize(&AGENT_DNS_QUERIES);
+        lazy_static::initialize(&AGENT_DNS_CACHE_ENTRIES);
+        lazy_static::initialize(&CONTAINER_CGROUP_EVENTS);
+        lazy_static::initialize(&CONTAINER_FDS);
+        lazy_static::initialize(&CONTAINER_PRESSURE);
+        lazy_static::initialize(&CONTAINER_HUGETLB);
+        observe_rpc("Check", Duration::from_millis(1), None);
+        observe_cgroup_op("set", Duration::from_millis(1), Some("invalid_value"));
+
+        // every metric belongs to a collector
+        get_metrics(&GetMetricsRequest::new()).unwrap();
+        for mf in prometheus::gather() {
+            assert!(
+                COLLECTORS
+                    .iter()
+                    .any(|c| c.metrics.contains(&mf.get_name())),
+                "{}",
+                mf.get_name()
+            );
+        }
+
+        let mut req = GetMetricsRequest::new();
+        req.collectors = vec!["agent_rpc".to_string()].into();
+        let m = get_metrics(&req).unwrap();
+        assert!(m
+            .metrics
+            .contains("kata_agent_rpc_duration_seconds_bucket{"));
+        assert!(!m.metrics.contains("kata_agent_scrape_count"));
+        assert_eq!(m.content_type, TEXT_FORMAT);
+
+        req.collectors.push("nonexistent".to_string());
+        assert!(check_request(&req).is_err());
+        assert!(get_metrics(&req).is_err());
+    }
+
+    #[test]
+    fn test_encode_openmetrics() {
+        let counter = IntCounterVec::new(
+            prometheus::Opts::new("test_requests", "Test requests."),
+            &["code"],
+        )
+        .unwrap();
+        counter.with_label_values(&["ok"]).inc();
+        let total = IntCounter::new("test_errors_total", "Test errors.").unwrap();
+        total.inc_by(2);
+        let gauge = Gauge::new("test_gauge", "Test gauge.").unwrap();
+        gauge.set(1.5);
+
+        let mut mfs = prometheus::core::Collector::collect(&counter);
+        mfs.extend(prometheus::core::Collector::collect(&total));
+        mfs.extend(prometheus::core::Collector::collect(&gauge));
+
+        assert_eq!(
+            encode_openmetrics(&mfs).unwrap(),
+            "# HELP test_requests Test requests.\n\
+             # TYPE test_requests counter\n\
+             test_requests_total{code=\"ok\"} 1\n\
+             # HELP test_errors Test errors.\n\
+             # TYPE test_errors counter\n\
+             test_errors_total 2\n\
+             # HELP test_gauge Test gauge.\n\
+             # TYPE test_gauge gauge\n\
+             test_gauge 1.5\n\
+             # EOF\n"
+        );
+    }
+
+    #[test]
+    fn test_get_metrics_protobuf() {
+        let mut req = GetMetricsRequest::new();
+        req.format = MetricsFormat::METRICS_PROTOBUF;
+        req.collectors = vec![COLLECTOR_AGENT.to_string()].into();
+
+        let m = get_metrics(&req).unwrap();
+        assert!(m.metrics.is_empty());
+        assert!(!m.encoded_metrics.is_empty());
+        assert_eq!(m.content_type, PROTOBUF_FORMAT);
+    }
+
+    #[test]
+    fn test_parse_sockstat() {
+        let stats = parse_sockstat(
+            "sockets: used 16\nTCP: inuse 4 orphan 1 tw 2 alloc 4 mem 3\nFRAG: inuse 0 memory 0\n",
+        );
+        assert_eq!(stats[0], ("sockets", "used", 16));
+        assert!(stats.contains(&("TCP", "orphan", 1)));
+        assert!(stats.contains(&("TCP", "tw", 2)));
+        assert!(stats.contains(&("TCP", "mem", 3)));
+        assert_eq!(stats.len(), 8);
+    }
+
+    #[test]
+    fn test_parse_snmp() {
+        let stats = parse_snmp(
+            "Tcp: RtoAlgorithm MaxConn ActiveOpens\nTcp: 1 -1 42\n\
+             Udp: InDatagrams NoPorts\nUdp: 7 3\n\
+             Bogus: A\nOther: 1\n",
+        );
+        assert_eq!(
+            stats,
+            vec![
+                ("Tcp", "RtoAlgorithm", 1),
+                ("Tcp", "MaxConn", -1),
+                ("Tcp", "ActiveOpens", 42),
+                ("Udp", "InDatagrams", 7),
+                ("Udp", "NoPorts", 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_interrupts() {
+        let counts = parse_interrupts(
+            "           CPU0       CPU1\n  \
+               0:         40          2   IO-APIC   2-edge      timer\n \
+              24:          1          0   PCI-MSI 65536-edge      virtio0-input.0\n \
+              25:          3          4   PCI-MSI 65537-edge      virtio0-input.0\n\
+             NMI:          0          5   Non-maskable interrupts\n\
+             ERR:          9\n",
+        );
+        assert_eq!(
+            counts,
+            vec![
+                ("timer".to_string(), 42),
+                ("virtio0-input.0".to_string(), 8),
+                ("NMI".to_string(), 5),
+                ("ERR".to_string(), 9),
+            ]
+        );
+
+        let counts = parse_interrupts(
+            "                    CPU0       CPU1\n          HI:          0          1\n      NET_RX:        100        200\n",
+        );
+        assert_eq!(
+            counts,
+            vec![("HI".to_string(), 1), ("NET_RX".to_string(), 300)]
+        );
+    }
+}
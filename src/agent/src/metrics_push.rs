@@ -0,0 +1,268 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Push mode of the metrics: rather than the shim polling GetMetrics, which
+// is costly with many sandboxes per node and misses the spikes between
+// two scrapes, the agent samples the metrics on the period set with
+// SetMetricsPush, and queues them until the shim reads them, all the
+// pending ones at once, with GetMetricsPush.
+//
+// The queue is bounded: past max_pending samples, the oldest ones are
+// dropped and counted, so that a shim which reads too slowly gets fewer
+// samples rather than an agent which grows.
+
+use protocols::agent::{GetMetricsRequest, Metrics, MetricsPush, MetricsSample};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, Notify};
+use tokio::task::JoinHandle;
+
+use crate::metrics;
+use crate::rpc::update_sandbox_metrics;
+use crate::sandbox::Sandbox;
+
+// Convenience macro to obtain the scope logger
+macro_rules! sl {
+    () => {
+        slog_scope::logger().new(o!("subsystem" => "metrics_push"))
+    };
+}
+
+pub const DEFAULT_MAX_PENDING: usize = 16;
+pub const MAX_PENDING_LIMIT: usize = 1024;
+
+#[derive(Debug, Default)]
+struct Pending {
+    samples: VecDeque<MetricsSample>,
+    // samples dropped since the last read
+    dropped: u64,
+    sequence: u64,
+    max_pending: usize,
+    running: bool,
+}
+
+#[derive(Debug)]
+pub struct PushQueue {
+    pending: std::sync::Mutex<Pending>,
+    notify: Notify,
+}
+
+impl PushQueue {
+    fn new() -> Self {
+        PushQueue {
+            pending: std::sync::Mutex::new(Pending::default()),
+            notify: Notify::new(),
+        }
+    }
+
+    fn push(&self, metrics: Metrics) {
+        let mut p = self.pending.lock().unwrap();
+
+        p.sequence += 1;
+        while p.samples.len() >= p.max_pending.max(1) {
+            p.samples.pop_front();
+            p.dropped += 1;
+        }
+
+        let mut sample = MetricsSample::new();
+        sample.sequence = p.sequence;
+        sample.timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        sample.set_metrics(metrics);
+        p.samples.push_back(sample);
+
+        drop(p);
+        self.notify.notify_one();
+    }
+
+    fn set_running(&self, running: bool, max_pending: usize) {
+        let mut p = self.pending.lock().unwrap();
+        p.running = running;
+        p.max_pending = max_pending;
+
+        drop(p);
+        // all the waiters return once stopped
+        if !running {
+            self.notify.notify_waiters();
+        }
+    }
+
+    // wait returns the pending samples, waiting for the next one if there
+    // are none, or None once the push is stopped.
+    pub async fn wait(&self) -> Option<MetricsPush> {
+        loop {
+            // created before the checks, for a notification sent meanwhile
+            // to be kept for this wait
+            let notified = self.notify.notified();
+
+            {
+                let mut p = self.pending.lock().unwrap();
+                if !p.samples.is_empty() {
+                    let mut push = MetricsPush::new();
+                    push.set_samples(p.samples.drain(..).collect());
+                    push.dropped = p.dropped;
+                    p.dropped = 0;
+                    return Some(push);
+                }
+
+                if !p.running {
+                    return None;
+                }
+            }
+
+            notified.await;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MetricsPusher {
+    queue: Arc<PushQueue>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl MetricsPusher {
+    pub fn new() -> Self {
+        MetricsPusher {
+            queue: Arc::new(PushQueue::new()),
+            task: None,
+        }
+    }
+
+    pub fn queue(&self) -> Arc<PushQueue> {
+        self.queue.clone()
+    }
+
+    // start samples the metrics of `req` every `period`, replacing the push
+    // already set up if any. The samples still pending are kept.
+    pub fn start(
+        &mut self,
+        sandbox: Arc<Mutex<Sandbox>>,
+        period: Duration,
+        max_pending: usize,
+        req: GetMetricsRequest,
+    ) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+
+        self.queue.set_running(true, max_pending);
+        self.task = Some(tokio::spawn(run_push(
+            sandbox,
+            period,
+            req,
+            self.queue.clone(),
+        )));
+    }
+
+    // stop stops sampling, the pending samples being left to read.
+    pub fn stop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+
+        self.queue.set_running(false, 0);
+    }
+}
+
+async fn run_push(
+    sandbox: Arc<Mutex<Sandbox>>,
+    period: Duration,
+    req: GetMetricsRequest,
+    queue: Arc<PushQueue>,
+) {
+    let mut ticker = tokio::time::interval(period);
+
+    loop {
+        ticker.tick().await;
+
+        update_sandbox_metrics(&sandbox, &req).await;
+
+        match metrics::get_metrics(&req) {
+            Ok(m) => queue.push(m),
+            Err(e) => {
+                warn!(sl!(), "failed to sample the metrics"; "error" => format!("{:?}", e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics(s: &str) -> Metrics {
+        let mut m = Metrics::new();
+        m.metrics = s.to_string();
+        m
+    }
+
+    #[tokio::test]
+    async fn test_push_queue() {
+        let queue = PushQueue::new();
+        assert!(queue.wait().await.is_none());
+
+        queue.set_running(true, 2);
+        for s in &["a", "b", "c"] {
+            queue.push(metrics(s));
+        }
+
+        let push = queue.wait().await.unwrap();
+        assert_eq!(push.dropped, 1);
+        let samples: Vec<(u64, &str)> = push
+            .samples
+            .iter()
+            .map(|s| (s.sequence, s.get_metrics().metrics.as_str()))
+            .collect();
+        assert_eq!(samples, vec![(2, "b"), (3, "c")]);
+        assert!(push.samples[0].timestamp_ms > 0);
+
+        // the drops are only reported once
+        queue.push(metrics("d"));
+        let push = queue.wait().await.unwrap();
+        assert_eq!(push.dropped, 0);
+        assert_eq!(push.samples[0].sequence, 4);
+
+        // the pending samples are read after the stop
+        queue.push(metrics("e"));
+        queue.set_running(false, 0);
+        assert_eq!(queue.wait().await.unwrap().samples.len(), 1);
+        assert!(queue.wait().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_push_queue_wait() {
+        let queue = Arc::new(PushQueue::new());
+        queue.set_running(true, DEFAULT_MAX_PENDING);
+
+        let waiter = {
+            let queue = queue.clone();
+            tokio::spawn(async move { queue.wait().await })
+        };
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        queue.push(metrics("a"));
+
+        let push = waiter.await.unwrap().unwrap();
+        assert_eq!(push.samples.len(), 1);
+
+        // the stop ends all the waits
+        let waiters: Vec<_> = (0..2)
+            .map(|_| {
+                let queue = queue.clone();
+                tokio::spawn(async move { queue.wait().await })
+            })
+            .collect();
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        queue.set_running(false, 0);
+        for waiter in waiters {
+            assert!(waiter.await.unwrap().is_none());
+        }
+    }
+}
@@ -15,19 +15,24 @@ use std::path::Path;
 use std::ptr::null;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use tokio::sync::Mutex;
 
 use libc::{c_void, mount};
 use nix::mount::{self, MsFlags};
+use nix::sys::stat;
 use nix::unistd::Gid;
 
 use regex::Regex;
 
 use crate::device::{
-    get_scsi_device_name, get_virtio_blk_pci_device_name, online_device, wait_for_pmem_device,
+    get_scsi_device_name, get_virtio_blk_pci_device_name, online_device, parse_major_minor,
+    wait_for_pmem_device,
 };
+use crate::fsverity;
 use crate::linux_abi::*;
+use crate::loopdev::{self, LoopOptions};
 use crate::pci;
 use crate::protocols::agent::Storage;
 use crate::Sandbox;
@@ -47,6 +52,8 @@ pub const DRIVER_NVDIMM_TYPE: &str = "nvdimm";
 pub const DRIVER_EPHEMERAL_TYPE: &str = "ephemeral";
 pub const DRIVER_LOCAL_TYPE: &str = "local";
 pub const DRIVER_WATCHABLE_BIND_TYPE: &str = "watchable-bind";
+pub const DRIVER_LOOP_TYPE: &str = "loop";
+pub const DRIVER_OVERLAYFS_TYPE: &str = "overlayfs";
 
 pub const TYPE_ROOTFS: &str = "rootfs";
 
@@ -55,6 +62,21 @@ pub const MOUNT_GUEST_TAG: &str = "kataShared";
 // Allocating an FSGroup that owns the pod's volumes
 const FS_GID: &str = "fsgid";
 
+// Numbers of the host device behind a block volume, which the blkio limits
+// of the containers refer to, e.g. "host_device=8:16"
+const HOST_DEVICE: &str = "host_device=";
+
+// Options of the loop driver, to scan the partitions of the image and mount
+// one of them, e.g. "partition=1"
+const LOOP_PARTSCAN: &str = "partscan";
+const LOOP_PARTITION: &str = "partition=";
+// time for the partition devices to show up once the image is attached
+const LOOP_PARTITION_TIMEOUT: Duration = Duration::from_secs(3);
+
+// fs-verity digest of the device or file of a layer, checked before it is
+// mounted, e.g. "fsverity_digest=sha256:<hex>"
+const FSVERITY_DIGEST: &str = "fsverity_digest=";
+
 #[rustfmt::skip]
 lazy_static! {
     pub static ref FLAGS: HashMap<&'static str, (bool, MsFlags)> = {
@@ -147,6 +169,8 @@ pub const STORAGE_HANDLER_LIST: &[&str] = &[
     DRIVER_SCSI_TYPE,
     DRIVER_NVDIMM_TYPE,
     DRIVER_WATCHABLE_BIND_TYPE,
+    DRIVER_LOOP_TYPE,
+    DRIVER_OVERLAYFS_TYPE,
 ];
 
 #[derive(Debug, Clone)]
@@ -263,7 +287,7 @@ async fn ephemeral_storage_handler(
         // ephemeral_storage didn't support mount options except fsGroup.
         let mut new_storage = storage.clone();
         new_storage.options = protobuf::RepeatedField::default();
-        common_storage_handler(logger, &new_storage)?;
+        common_storage_handler(logger, &new_storage).await?;
 
         let opts_vec: Vec<String> = storage.options.to_vec();
 
@@ -282,7 +306,7 @@ async fn ephemeral_storage_handler(
             fs::set_permissions(&storage.mount_point, permission)?;
         }
     } else {
-        common_storage_handler(logger, &storage)?;
+        common_storage_handler(logger, &storage).await?;
     }
 
     Ok("".to_string())
@@ -341,7 +365,7 @@ async fn virtio9p_storage_handler(
     storage: &Storage,
     _sandbox: Arc<Mutex<Sandbox>>,
 ) -> Result<String> {
-    common_storage_handler(logger, storage)
+    common_storage_handler(logger, storage).await
 }
 
 // virtiommio_blk_storage_handler handles the storage for mmio blk driver.
@@ -349,10 +373,11 @@ async fn virtio9p_storage_handler(
 async fn virtiommio_blk_storage_handler(
     logger: &Logger,
     storage: &Storage,
-    _sandbox: Arc<Mutex<Sandbox>>,
+    sandbox: Arc<Mutex<Sandbox>>,
 ) -> Result<String> {
     //The source path is VmPath
-    common_storage_handler(logger, storage)
+    add_block_volume(&sandbox, storage).await?;
+    common_storage_handler(logger, storage).await
 }
 
 // virtiofs_storage_handler handles the storage for virtio-fs.
@@ -362,7 +387,7 @@ async fn virtiofs_storage_handler(
     storage: &Storage,
     _sandbox: Arc<Mutex<Sandbox>>,
 ) -> Result<String> {
-    common_storage_handler(logger, storage)
+    common_storage_handler(logger, storage).await
 }
 
 // virtio_blk_storage_handler handles the storage for blk driver.
@@ -389,7 +414,8 @@ async fn virtio_blk_storage_handler(
         storage.source = dev_path;
     }
 
-    common_storage_handler(logger, &storage)
+    add_block_volume(&sandbox, &storage).await?;
+    common_storage_handler(logger, &storage).await
 }
 
 // virtio_blk_ccw_storage_handler handles storage for the blk-ccw driver (s390x)
@@ -404,7 +430,8 @@ async fn virtio_blk_ccw_storage_handler(
     let ccw_device = ccw::Device::from_str(&storage.source)?;
     let dev_path = get_virtio_blk_ccw_device_name(&sandbox, &ccw_device).await?;
     storage.source = dev_path;
-    common_storage_handler(logger, &storage)
+    add_block_volume(&sandbox, &storage).await?;
+    common_storage_handler(logger, &storage).await
 }
 
 #[cfg(not(target_arch = "s390x"))]
@@ -430,14 +457,198 @@ async fn virtio_scsi_storage_handler(
     let dev_path = get_scsi_device_name(&sandbox, &storage.source).await?;
     storage.source = dev_path;
 
-    common_storage_handler(logger, &storage)
+    add_block_volume(&sandbox, &storage).await?;
+    common_storage_handler(logger, &storage).await
+}
+
+// add_block_volume records the guest device of a block volume against the
+// host device it was given for, so that the blkio limits of the containers
+// using the volume apply to the guest device.
+async fn add_block_volume(sandbox: &Arc<Mutex<Sandbox>>, storage: &Storage) -> Result<()> {
+    let host = match storage
+        .driver_options
+        .iter()
+        .find_map(|o| o.strip_prefix(HOST_DEVICE))
+    {
+        Some(v) => parse_major_minor(v)?,
+        None => return Ok(()),
+    };
+
+    let rdev = fs::metadata(&storage.source)
+        .with_context(|| format!("get metadata on file {:?}", &storage.source))?
+        .rdev();
+    let guest = (stat::major(rdev) as i64, stat::minor(rdev) as i64);
+
+    sandbox
+        .lock()
+        .await
+        .block_volumes
+        .insert(storage.mount_point.clone(), (host, guest));
+
+    Ok(())
+}
+
+// loop_storage_handler handles the storage for the loop driver, whose source
+// is an image file in the guest, e.g. on a shared volume.
+#[instrument]
+async fn loop_storage_handler(
+    logger: &Logger,
+    storage: &Storage,
+    _sandbox: Arc<Mutex<Sandbox>>,
+) -> Result<String> {
+    let mut storage = storage.clone();
+    let (options, partition) = parse_loop_options(&storage)?;
+
+    // the device is detached once closed, unless mounted
+    let dev = loopdev::attach(Path::new(&storage.source), options)?;
+    info!(logger, "attached the image to a loop device";
+        "image" => &storage.source,
+        "device" => &dev.path);
+
+    storage.source = match partition {
+        Some(p) => wait_for_loop_partition(&dev.path, p).await?,
+        None => dev.path.clone(),
+    };
+
+    common_storage_handler(logger, &storage).await
+}
+
+// parse_loop_options returns the loop device options of the storage, and
+// the partition to mount if any.
+fn parse_loop_options(storage: &Storage) -> Result<(LoopOptions, Option<u32>)> {
+    if !Path::new(&storage.source).is_absolute() {
+        return Err(anyhow!("invalid loop image {:?}", storage.source));
+    }
+
+    let mut options = LoopOptions {
+        read_only: storage.options.iter().any(|o| o == "ro"),
+        partscan: false,
+    };
+    let mut partition = None;
+
+    for o in storage.driver_options.iter() {
+        if o == LOOP_PARTSCAN {
+            options.partscan = true;
+        } else if let Some(p) = o.strip_prefix(LOOP_PARTITION) {
+            let p = p
+                .parse::<u32>()
+                .ok()
+                .filter(|p| *p > 0)
+                .ok_or_else(|| anyhow!("invalid loop partition {:?}", p))?;
+            partition = Some(p);
+        } else if !o.starts_with(FSVERITY_DIGEST) {
+            return Err(anyhow!("invalid loop driver option {:?}", o));
+        }
+    }
+
+    // a partition needs its device
+    if partition.is_some() {
+        options.partscan = true;
+    }
+
+    Ok((options, partition))
+}
+
+// wait_for_loop_partition waits for the device of a partition of a loop
+// device, which the kernel adds once the partitions are scanned.
+async fn wait_for_loop_partition(dev: &str, partition: u32) -> Result<String> {
+    let path = loopdev::partition_path(dev, partition);
+    let start = tokio::time::Instant::now();
+
+    while !Path::new(&path).exists() {
+        if start.elapsed() > LOOP_PARTITION_TIMEOUT {
+            return Err(anyhow!(
+                "timeout waiting for the partition {} of {}",
+                partition,
+                dev
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    Ok(path)
+}
+
+// overlayfs_storage_handler handles the storage for the overlayfs driver,
+// which stacks the layers mounted by the previous storages, e.g. erofs or
+// squashfs ones, into a rootfs.
+#[instrument]
+async fn overlayfs_storage_handler(
+    logger: &Logger,
+    storage: &Storage,
+    _sandbox: Arc<Mutex<Sandbox>>,
+) -> Result<String> {
+    let layers = parse_overlay_options(storage)?;
+
+    for dir in layers.lower.iter() {
+        if !Path::new(dir).is_dir() {
+            return Err(anyhow!("overlay lower layer {:?} doesn't exist", dir));
+        }
+    }
+
+    // a read-only rootfs has no upper layer
+    for dir in layers.upper.iter().chain(layers.work.iter()) {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {:?}", dir))?;
+    }
+
+    common_storage_handler(logger, storage).await
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct OverlayLayers {
+    lower: Vec<String>,
+    upper: Option<String>,
+    work: Option<String>,
+}
+
+// parse_overlay_options returns the layers of an overlay storage, from its
+// lowerdir, upperdir and workdir mount options.
+fn parse_overlay_options(storage: &Storage) -> Result<OverlayLayers> {
+    if storage.fstype != "overlay" {
+        return Err(anyhow!("invalid overlay fstype {:?}", storage.fstype));
+    }
+
+    let mut layers = OverlayLayers::default();
+    for o in storage.options.iter() {
+        if let Some(dirs) = o.strip_prefix("lowerdir=") {
+            layers.lower = dirs.split(':').map(String::from).collect();
+        } else if let Some(dir) = o.strip_prefix("upperdir=") {
+            layers.upper = Some(dir.to_string());
+        } else if let Some(dir) = o.strip_prefix("workdir=") {
+            layers.work = Some(dir.to_string());
+        }
+    }
+
+    if layers.lower.is_empty() || layers.lower.iter().any(|d| d.is_empty()) {
+        return Err(anyhow!(
+            "invalid overlay lower layers {:?}",
+            storage.options
+        ));
+    }
+    if layers.upper.is_some() != layers.work.is_some() {
+        return Err(anyhow!("overlay upperdir and workdir go together"));
+    }
+
+    Ok(layers)
 }
 
 #[instrument]
-fn common_storage_handler(logger: &Logger, storage: &Storage) -> Result<String> {
+async fn common_storage_handler(logger: &Logger, storage: &Storage) -> Result<String> {
     // Mount the storage device.
     let mount_point = storage.mount_point.to_string();
 
+    if let Some(digest) = storage
+        .driver_options
+        .iter()
+        .find_map(|o| o.strip_prefix(FSVERITY_DIGEST))
+    {
+        // the whole layer is read, off the async runtime
+        let (source, expected) = (storage.source.clone(), digest.to_string());
+        tokio::task::spawn_blocking(move || fsverity::verify(Path::new(&source), &expected))
+            .await??;
+        info!(logger, "verified the layer"; "source" => &storage.source, "digest" => digest);
+    }
+
     mount_storage(logger, storage).and(Ok(mount_point))
 }
 
@@ -453,7 +664,7 @@ async fn nvdimm_storage_handler(
     // Retrieve the device path from NVDIMM address.
     wait_for_pmem_device(&sandbox, &storage.source).await?;
 
-    common_storage_handler(logger, &storage)
+    common_storage_handler(logger, &storage).await
 }
 
 async fn bind_watcher_storage_handler(
@@ -603,6 +814,10 @@ pub async fn add_storages(
                 virtio_scsi_storage_handler(&logger, &storage, sandbox.clone()).await
             }
             DRIVER_NVDIMM_TYPE => nvdimm_storage_handler(&logger, &storage, sandbox.clone()).await,
+            DRIVER_LOOP_TYPE => loop_storage_handler(&logger, &storage, sandbox.clone()).await,
+            DRIVER_OVERLAYFS_TYPE => {
+                overlayfs_storage_handler(&logger, &storage, sandbox.clone()).await
+            }
             DRIVER_WATCHABLE_BIND_TYPE => {
                 bind_watcher_storage_handler(&logger, &storage, sandbox.clone()).await?;
                 // Don't register watch mounts, they're hanlded separately by the watcher.
@@ -866,6 +1081,114 @@ mod tests {
     use std::path::PathBuf;
     use tempfile::tempdir;
 
+    #[test]
+    fn test_parse_loop_options() {
+        let storage = |options: &[&str], driver_options: &[&str]| Storage {
+            driver: DRIVER_LOOP_TYPE.to_string(),
+            source: "/run/kata-containers/shared/containers/image.erofs".to_string(),
+            options: options.iter().map(|o| o.to_string()).collect(),
+            driver_options: driver_options.iter().map(|o| o.to_string()).collect(),
+            ..Default::default()
+        };
+
+        let (options, partition) = parse_loop_options(&storage(&[], &[])).unwrap();
+        assert_eq!(options, LoopOptions::default());
+        assert_eq!(partition, None);
+
+        let (options, partition) =
+            parse_loop_options(&storage(&["ro", "nodev"], &["partscan"])).unwrap();
+        assert!(options.read_only);
+        assert!(options.partscan);
+        assert_eq!(partition, None);
+
+        let (options, partition) =
+            parse_loop_options(&storage(&[], &["partition=2", "fsverity_digest=sha256:00"]))
+                .unwrap();
+        assert!(!options.read_only);
+        assert!(options.partscan);
+        assert_eq!(partition, Some(2));
+
+        for o in &["partition=0", "partition=a", "offset=512"] {
+            assert!(parse_loop_options(&storage(&[], &[o])).is_err(), "{}", o);
+        }
+
+        let mut s = storage(&[], &[]);
+        s.source = "image.erofs".to_string();
+        assert!(parse_loop_options(&s).is_err());
+    }
+
+    #[test]
+    fn test_parse_overlay_options() {
+        let storage = |fstype: &str, options: &[&str]| Storage {
+            driver: DRIVER_OVERLAYFS_TYPE.to_string(),
+            source: "overlay".to_string(),
+            fstype: fstype.to_string(),
+            options: options.iter().map(|o| o.to_string()).collect(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            parse_overlay_options(&storage("overlay", &["lowerdir=/l/2:/l/1", "ro"])).unwrap(),
+            OverlayLayers {
+                lower: vec!["/l/2".to_string(), "/l/1".to_string()],
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            parse_overlay_options(&storage(
+                "overlay",
+                &["lowerdir=/l/1", "upperdir=/r/upper", "workdir=/r/work"]
+            ))
+            .unwrap(),
+            OverlayLayers {
+                lower: vec!["/l/1".to_string()],
+                upper: Some("/r/upper".to_string()),
+                work: Some("/r/work".to_string()),
+            }
+        );
+
+        for (fstype, options) in &[
+            ("erofs", vec!["lowerdir=/l/1"]),
+            ("overlay", vec![]),
+            ("overlay", vec!["lowerdir=/l/1::/l/2"]),
+            ("overlay", vec!["lowerdir=/l/1", "upperdir=/r/upper"]),
+        ] {
+            assert!(
+                parse_overlay_options(&storage(fstype, options)).is_err(),
+                "{:?}",
+                options
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_block_volume() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let sandbox = Arc::new(Mutex::new(Sandbox::new(&logger).unwrap()));
+
+        let mut storage = Storage {
+            source: "/dev/null".to_string(),
+            mount_point: "/run/kata-containers/volumes/v1".to_string(),
+            ..Default::default()
+        };
+
+        add_block_volume(&sandbox, &storage).await.unwrap();
+        assert!(sandbox.lock().await.block_volumes.is_empty());
+
+        storage.driver_options = vec!["host_device=8:16".to_string()].into();
+        add_block_volume(&sandbox, &storage).await.unwrap();
+
+        let rdev = fs::metadata("/dev/null").unwrap().rdev();
+        let guest = (stat::major(rdev) as i64, stat::minor(rdev) as i64);
+        assert_eq!(
+            sandbox.lock().await.block_volumes.get(&storage.mount_point),
+            Some(&((8, 16), guest))
+        );
+
+        storage.driver_options = vec!["host_device=8".to_string()].into();
+        assert!(add_block_volume(&sandbox, &storage).await.is_err());
+    }
+
     #[derive(Debug, PartialEq)]
     enum TestUserType {
         RootOnly,
@@ -0,0 +1,154 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// The mount table of a container, read from the mountinfo of one of its
+// processes. The mount points are relative to the root of that process,
+// so they are the paths seen from inside the container.
+//
+// Each line of /proc/<pid>/mountinfo looks like:
+//
+//   36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue
+//
+// see https://www.kernel.org/doc/Documentation/filesystems/proc.txt
+
+use anyhow::{anyhow, Context, Result};
+use protocols::agent::ContainerMount;
+use std::fs;
+
+const PROPAGATION_SHARED: &str = "shared";
+const PROPAGATION_SLAVE: &str = "slave";
+const PROPAGATION_PRIVATE: &str = "private";
+const PROPAGATION_UNBINDABLE: &str = "unbindable";
+
+// get_container_mounts returns the mounts seen by the process `pid`.
+pub fn get_container_mounts(pid: i32) -> Result<Vec<ContainerMount>> {
+    let path = format!("/proc/{}/mountinfo", pid);
+    let mountinfo =
+        fs::read_to_string(&path).with_context(|| format!("failed to read {}", path))?;
+
+    mountinfo.lines().map(parse_mountinfo_line).collect()
+}
+
+fn parse_mountinfo_line(line: &str) -> Result<ContainerMount> {
+    let p: Vec<&str> = line.splitn(2, " - ").collect();
+    if p.len() != 2 {
+        return Err(anyhow!("invalid mountinfo line {:?}", line));
+    }
+
+    let pre: Vec<&str> = p[0].split(' ').collect();
+    let post: Vec<&str> = p[1].split(' ').collect();
+    if pre.len() < 6 || post.len() < 3 {
+        return Err(anyhow!("invalid mountinfo line {:?}", line));
+    }
+
+    let mut m = ContainerMount::new();
+    m.root = unescape(pre[3]);
+    m.target = unescape(pre[4]);
+    m.options = pre[5].split(',').map(|o| o.to_string()).collect();
+    m.propagation = propagation(&pre[6..]);
+    m.fstype = post[0].to_string();
+    m.source = unescape(post[1]);
+    m.fs_options = post[2].split(',').map(|o| o.to_string()).collect();
+
+    Ok(m)
+}
+
+// propagation returns the propagation type of a mount from its optional
+// fields, e.g. "shared,slave" for a mount both receiving and forwarding
+// events.
+fn propagation(fields: &[&str]) -> String {
+    let mut types = vec![];
+
+    for f in fields {
+        let t = match f.split(':').next() {
+            Some("shared") => PROPAGATION_SHARED,
+            Some("master") => PROPAGATION_SLAVE,
+            Some("unbindable") => PROPAGATION_UNBINDABLE,
+            _ => continue,
+        };
+        types.push(t);
+    }
+
+    if types.is_empty() {
+        return PROPAGATION_PRIVATE.to_string();
+    }
+
+    types.join(",")
+}
+
+// unescape decodes the octal escapes, e.g. "\040" for a space, the kernel
+// uses for the whitespaces and backslashes in the paths.
+pub fn unescape(s: &str) -> String {
+    let b = s.as_bytes();
+    let mut out = Vec::with_capacity(b.len());
+
+    let mut i = 0;
+    while i < b.len() {
+        if b[i] == b'\\' && i + 3 < b.len() {
+            // at most \377, so that it fits in a byte
+            let digits = &b[i + 1..i + 4];
+            if digits[0] <= b'3' && digits.iter().all(|c| (b'0'..=b'7').contains(c)) {
+                out.push(digits.iter().fold(0u8, |v, c| v * 8 + (c - b'0')));
+                i += 4;
+                continue;
+            }
+        }
+
+        out.push(b[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&out).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mountinfo_line() {
+        let m = parse_mountinfo_line(
+            "36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue",
+        )
+        .unwrap();
+        assert_eq!(m.root, "/mnt1");
+        assert_eq!(m.target, "/mnt2");
+        assert_eq!(m.fstype, "ext3");
+        assert_eq!(m.source, "/dev/root");
+        assert_eq!(m.options.to_vec(), vec!["rw", "noatime"]);
+        assert_eq!(m.fs_options.to_vec(), vec!["rw", "errors=continue"]);
+        assert_eq!(m.propagation, "slave");
+
+        let m = parse_mountinfo_line(
+            "50 36 0:45 / /my\\040volume rw shared:3 master:2 - tmpfs tmpfs rw",
+        )
+        .unwrap();
+        assert_eq!(m.target, "/my volume");
+        assert_eq!(m.propagation, "shared,slave");
+
+        let m = parse_mountinfo_line("51 36 0:46 / /proc rw - proc proc rw").unwrap();
+        assert_eq!(m.propagation, "private");
+
+        assert!(parse_mountinfo_line("51 36 0:46 / /proc rw").is_err());
+        assert!(parse_mountinfo_line("51 36 / - proc proc rw").is_err());
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("/a\\040b"), "/a b");
+        assert_eq!(unescape("/a\\011b\\134"), "/a\tb\\");
+        assert_eq!(unescape("/a\\04"), "/a\\04");
+        assert_eq!(unescape("/a\\999"), "/a\\999");
+        assert_eq!(unescape(""), "");
+    }
+
+    #[test]
+    fn test_get_container_mounts() {
+        let mounts = get_container_mounts(std::process::id() as i32).unwrap();
+        assert!(mounts.iter().any(|m| m.target == "/"));
+
+        assert!(get_container_mounts(-1).is_err());
+    }
+}
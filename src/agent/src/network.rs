@@ -34,6 +34,34 @@ impl Network {
     pub fn set_dns(&mut self, dns: String) {
         self.dns.push(dns);
     }
+
+    // set_interface records an interface set up by the agent, which is
+    // named `device` on the host and `name` in the guest.
+    pub fn set_interface(&mut self, iface: Interface) {
+        if !iface.device.is_empty() {
+            self.ifaces.insert(iface.device.clone(), iface);
+        }
+    }
+
+    // guest_interface_name returns the name in the guest of the interface
+    // named `name` on the host, `name` itself for the interfaces the agent
+    // didn't set up, e.g. lo.
+    pub fn guest_interface_name<'a>(&'a self, name: &'a str) -> &'a str {
+        match self.ifaces.get(name) {
+            Some(iface) if !iface.name.is_empty() => iface.name.as_str(),
+            _ => name,
+        }
+    }
+
+    // map_interface_priorities renames the interfaces of the network
+    // priorities of `r`, which the spec gives by their host names.
+    pub fn map_interface_priorities(&self, r: &mut oci::LinuxResources) {
+        if let Some(network) = r.network.as_mut() {
+            for p in network.priorities.iter_mut() {
+                p.name = self.guest_interface_name(&p.name).to_string();
+            }
+        }
+    }
 }
 
 pub fn setup_guest_dns(logger: Logger, dns_list: Vec<String>) -> Result<()> {
@@ -90,6 +118,38 @@ mod tests {
     use std::io::Write;
     use tempfile::tempdir;
 
+    #[test]
+    fn test_map_interface_priorities() {
+        let mut network = Network::new();
+
+        let mut iface = Interface::new();
+        iface.device = "veth0".to_string();
+        iface.name = "eth0".to_string();
+        network.set_interface(iface);
+        // no host name
+        let mut iface = Interface::new();
+        iface.name = "eth1".to_string();
+        network.set_interface(iface);
+
+        let priority = |name: &str| oci::LinuxInterfacePriority {
+            name: name.to_string(),
+            priority: 10,
+        };
+        let mut r = oci::LinuxResources {
+            network: Some(oci::LinuxNetwork {
+                class_id: None,
+                priorities: vec![priority("veth0"), priority("lo"), priority("eth1")],
+            }),
+            ..Default::default()
+        };
+        network.map_interface_priorities(&mut r);
+
+        assert_eq!(
+            r.network.unwrap().priorities,
+            vec![priority("eth0"), priority("lo"), priority("eth1")]
+        );
+    }
+
     #[test]
     fn test_setup_guest_dns() {
         skip_if_not_root!();
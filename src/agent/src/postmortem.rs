@@ -0,0 +1,242 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Post-mortem snapshot of the agent: a last dump of the metrics, along with
+// a summary of the notable events of the sandbox, e.g. the warnings, the
+// cgroup events and the processes exits. It is logged when the sandbox is
+// destroyed or the agent panics, so that the short lived pods which failed
+// leave some data behind, the metrics being otherwise only scraped by the
+// host. As the host removes the directory shared with it on teardown, the
+// snapshot is only written there too, if it is mounted, for the hosts
+// which keep it.
+//
+// On panic, the metrics are left out: gathering them takes locks the
+// panicking thread may hold.
+//
+// The events are kept in memory, the oldest ones being dropped past
+// MAX_EVENTS, while their counts by kind are kept for the whole life of the
+// agent.
+
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write as _;
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::metrics;
+use crate::mount::is_mounted;
+
+// Convenience macro to obtain the scope logger
+macro_rules! sl {
+    () => {
+        slog_scope::logger().new(o!("subsystem" => "postmortem"))
+    };
+}
+
+// the mount point of the filesystem shared with the host
+const SHARED_DIR: &str = "/run/kata-containers/shared/containers";
+const SNAPSHOT_DIR: &str = "postmortem";
+const METRICS_FILE: &str = "metrics.prom";
+const EVENTS_FILE: &str = "events.log";
+
+const MAX_EVENTS: usize = 256;
+
+pub const EVENT_WARNING: &str = "warning";
+pub const EVENT_CGROUP: &str = "cgroup";
+pub const EVENT_EXIT: &str = "exit";
+
+#[derive(Debug, Default)]
+struct EventLog {
+    events: VecDeque<String>,
+    dropped: u64,
+    counts: BTreeMap<String, u64>,
+}
+
+impl EventLog {
+    fn add(&mut self, kind: &str, cid: &str, message: &str) {
+        *self.counts.entry(kind.to_string()).or_insert(0) += 1;
+
+        if self.events.len() >= MAX_EVENTS {
+            self.events.pop_front();
+            self.dropped += 1;
+        }
+
+        let cid = if cid.is_empty() { "-" } else { cid };
+        self.events
+            .push_back(format!("{} {} {} {}", now(), kind, cid, message));
+    }
+
+    fn summary(&self, reason: &str) -> String {
+        let mut s = String::new();
+
+        let _ = writeln!(s, "reason: {}", reason);
+        let _ = writeln!(s, "time: {}", now());
+        for (kind, count) in self.counts.iter() {
+            let _ = writeln!(s, "{} events: {}", kind, count);
+        }
+        let _ = writeln!(s, "dropped events: {}", self.dropped);
+        s.push('\n');
+
+        for event in self.events.iter() {
+            s.push_str(event);
+            s.push('\n');
+        }
+
+        s
+    }
+}
+
+lazy_static! {
+    static ref EVENTS: Mutex<EventLog> = Mutex::new(EventLog::default());
+}
+
+// whether the metrics are dumped, as per the agent profile
+static METRICS_ENABLED: AtomicBool = AtomicBool::new(false);
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// record_event adds an event of the container `cid`, if any, to the
+// summary.
+pub fn record_event(kind: &str, cid: &str, message: &str) {
+    if let Ok(mut events) = EVENTS.lock() {
+        events.add(kind, cid, message);
+    }
+}
+
+fn write_file(dir: &Path, name: &str, content: &[u8]) -> Result<()> {
+    // renamed into place, not to leave a partial file if the guest dies
+    let tmp = dir.join(format!(".{}", name));
+    fs::write(&tmp, content).with_context(|| format!("failed to write {:?}", tmp))?;
+    fs::rename(&tmp, dir.join(name)).with_context(|| format!("failed to rename {:?}", tmp))
+}
+
+// write_snapshot writes the metrics, if any, and the summary of the events
+// to `dir`.
+fn write_snapshot(dir: &Path, summary: &str, metrics: Option<&str>) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("failed to create {:?}", dir))?;
+
+    if let Some(metrics) = metrics {
+        write_file(dir, METRICS_FILE, metrics.as_bytes())?;
+    }
+
+    write_file(dir, EVENTS_FILE, summary.as_bytes())
+}
+
+// snapshot_dir returns the directory the snapshot is written to, if the
+// filesystem shared with the host is mounted.
+fn snapshot_dir() -> Option<PathBuf> {
+    match is_mounted(SHARED_DIR) {
+        Ok(true) => Some(Path::new(SHARED_DIR).join(SNAPSHOT_DIR)),
+        _ => None,
+    }
+}
+
+// snapshot logs the post-mortem snapshot for `reason`, e.g. "destroy",
+// and writes it to the shared directory, the failures being only logged.
+pub fn snapshot(reason: &str) {
+    take_snapshot(reason, METRICS_ENABLED.load(Ordering::Relaxed));
+}
+
+fn take_snapshot(reason: &str, with_metrics: bool) {
+    let metrics = if with_metrics {
+        match metrics::get_metrics(&Default::default()) {
+            Ok(m) => Some(m.metrics),
+            Err(e) => {
+                warn!(sl!(), "failed to get the metrics"; "error" => format!("{:?}", e));
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // try_lock, as a panic may happen while the log is locked
+    let summary = match EVENTS.try_lock() {
+        Ok(events) => events.summary(reason),
+        Err(_) => EventLog::default().summary(reason),
+    };
+
+    info!(sl!(), "post-mortem snapshot";
+        "reason" => reason,
+        "events" => &summary,
+        "metrics" => metrics.as_deref().unwrap_or(""));
+
+    let dir = match snapshot_dir() {
+        Some(dir) => dir,
+        None => return,
+    };
+
+    match write_snapshot(&dir, &summary, metrics.as_deref()) {
+        Ok(()) => {
+            info!(sl!(), "wrote the post-mortem snapshot"; "dir" => dir.to_string_lossy().to_string(), "reason" => reason)
+        }
+        Err(e) => {
+            warn!(sl!(), "failed to write the post-mortem snapshot"; "error" => format!("{:?}", e))
+        }
+    }
+}
+
+// init enables the dump of the metrics in the snapshots and installs a
+// panic hook writing one.
+pub fn init(metrics_enabled: bool) {
+    METRICS_ENABLED.store(metrics_enabled, Ordering::Relaxed);
+
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        take_snapshot("panic", false);
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_log() {
+        let mut log = EventLog::default();
+        log.add(EVENT_WARNING, "c1", "cpu.rt_period_us: not supported");
+        log.add(EVENT_CGROUP, "c1", "OOM");
+        for i in 0..MAX_EVENTS {
+            log.add(EVENT_EXIT, "", &i.to_string());
+        }
+
+        assert_eq!(log.events.len(), MAX_EVENTS);
+        assert_eq!(log.dropped, 2);
+        assert!(log.events[0].ends_with(" exit - 0"));
+
+        let summary = log.summary("destroy");
+        assert!(summary.starts_with("reason: destroy\n"));
+        assert!(summary.contains("\ncgroup events: 1\n"));
+        assert!(summary.contains(&format!("\nexit events: {}\n", MAX_EVENTS)));
+        assert!(summary.contains("\ndropped events: 2\n"));
+        assert!(summary.ends_with(&format!(" exit - {}\n", MAX_EVENTS - 1)));
+    }
+
+    #[test]
+    fn test_write_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().join(SNAPSHOT_DIR);
+
+        write_snapshot(&dir, "reason: panic\n", None).unwrap();
+        let summary = fs::read_to_string(dir.join(EVENTS_FILE)).unwrap();
+        assert_eq!(summary, "reason: panic\n");
+        assert!(!dir.join(format!(".{}", EVENTS_FILE)).exists());
+        assert!(!dir.join(METRICS_FILE).exists());
+
+        write_snapshot(&dir, "reason: destroy\n", Some("kata_agent_up 1\n")).unwrap();
+        let metrics = fs::read_to_string(dir.join(METRICS_FILE)).unwrap();
+        assert_eq!(metrics, "kata_agent_up 1\n");
+    }
+}
@@ -0,0 +1,384 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Preflight check of the resources of a container against the capacity of
+// the guest, before the container is created, so that a container which
+// can't fit fails with a precise error rather than being OOM killed or
+// failing with EINVAL once its cgroup is written.
+//
+// The memory and hugepage limits of the containers of the sandbox are
+// summed, and the cpuset has to be within the online cpus and memory nodes.
+// As the runtime hotplugs the memory and the cpus of a container after
+// creating it, the memory limits and the cpus and nodes not online yet are
+// only warned about; the hugepage pools aren't hotplugged, so exceeding
+// them, like an invalid cpuset, fails.
+
+use anyhow::{anyhow, Context, Result};
+use oci::LinuxResources;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// the files read, relative to the root of the guest
+const PROC_MEMINFO: &str = "proc/meminfo";
+const SYSFS_CPU_ONLINE: &str = "sys/devices/system/cpu/online";
+const SYSFS_NODE_ONLINE: &str = "sys/devices/system/node/online";
+const SYSFS_HUGEPAGES_DIR: &str = "sys/kernel/mm/hugepages";
+
+const INSUFFICIENT: &str = "insufficient guest resources";
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GuestCapacity {
+    // MemTotal, in bytes
+    pub memory: u64,
+    pub cpus: Vec<u32>,
+    pub mems: Vec<u32>,
+    // the size of the hugepage pools in bytes, by page size, e.g. "2MB"
+    pub hugepages: HashMap<String, u64>,
+}
+
+// parse_list parses a cpu or memory node list, e.g. "0-3,6".
+fn parse_list(s: &str) -> Result<Vec<u32>> {
+    let mut list = vec![];
+
+    for range in s.trim().split(',').filter(|r| !r.is_empty()) {
+        let err = || anyhow!("invalid list {:?}", s);
+
+        let mut bounds = range.splitn(2, '-');
+        let start: u32 = bounds
+            .next()
+            .unwrap_or_default()
+            .parse()
+            .map_err(|_| err())?;
+        let end: u32 = match bounds.next() {
+            Some(e) => e.parse().map_err(|_| err())?,
+            None => start,
+        };
+        if end < start {
+            return Err(err());
+        }
+
+        list.extend(start..=end);
+    }
+
+    Ok(list)
+}
+
+// page_size names the page size of a hugepages-<size>kB directory as the
+// OCI hugepage limits do.
+fn page_size(kb: u64) -> String {
+    if kb % (1024 * 1024) == 0 {
+        format!("{}GB", kb / (1024 * 1024))
+    } else if kb % 1024 == 0 {
+        format!("{}MB", kb / 1024)
+    } else {
+        format!("{}KB", kb)
+    }
+}
+
+fn get_mem_total(root: &Path) -> Result<u64> {
+    let path = root.join(PROC_MEMINFO);
+    let meminfo =
+        fs::read_to_string(&path).with_context(|| format!("failed to read {:?}", path))?;
+
+    meminfo
+        .lines()
+        .find_map(|l| l.strip_prefix("MemTotal:"))
+        .and_then(|v| v.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+        .map(|kb| kb * 1024)
+        .ok_or_else(|| anyhow!("no MemTotal in {:?}", path))
+}
+
+// HugepagePool is the state of the pool of hugepages of a size, in pages.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct HugepagePool {
+    // the page size, e.g. "2MB"
+    pub size: String,
+    pub bytes_per_page: u64,
+    pub total: u64,
+    pub free: u64,
+    pub reserved: u64,
+    pub surplus: u64,
+}
+
+// hugepage_pools returns the hugepage pools of the guest whose root is
+// `root`, none if the kernel has no hugetlbfs.
+pub fn hugepage_pools(root: &Path) -> Vec<HugepagePool> {
+    let entries = match fs::read_dir(root.join(SYSFS_HUGEPAGES_DIR)) {
+        Ok(e) => e,
+        Err(_) => return vec![],
+    };
+
+    entries
+        .flatten()
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().to_string();
+            let kb: u64 = name
+                .strip_prefix("hugepages-")?
+                .strip_suffix("kB")?
+                .parse()
+                .ok()?;
+            let read = |f: &str| -> Option<u64> {
+                fs::read_to_string(e.path().join(f))
+                    .ok()?
+                    .trim()
+                    .parse()
+                    .ok()
+            };
+
+            Some(HugepagePool {
+                size: page_size(kb),
+                bytes_per_page: kb * 1024,
+                total: read("nr_hugepages")?,
+                free: read("free_hugepages").unwrap_or(0),
+                reserved: read("resv_hugepages").unwrap_or(0),
+                surplus: read("surplus_hugepages").unwrap_or(0),
+            })
+        })
+        .collect()
+}
+
+fn get_hugepages(root: &Path) -> HashMap<String, u64> {
+    hugepage_pools(root)
+        .into_iter()
+        .map(|p| (p.size, p.total * p.bytes_per_page))
+        .collect()
+}
+
+// probe returns the capacity of the guest whose root is `root`.
+pub fn probe(root: &Path) -> Result<GuestCapacity> {
+    let path = root.join(SYSFS_CPU_ONLINE);
+    let cpus = fs::read_to_string(&path).with_context(|| format!("failed to read {:?}", path))?;
+
+    // the node directory only exists with CONFIG_NUMA
+    let mems = fs::read_to_string(root.join(SYSFS_NODE_ONLINE)).unwrap_or_else(|_| "0".into());
+
+    Ok(GuestCapacity {
+        memory: get_mem_total(root)?,
+        cpus: parse_list(&cpus)?,
+        mems: parse_list(&mems)?,
+        hugepages: get_hugepages(root),
+    })
+}
+
+// check checks the resources of a new container, along with the ones of
+// the containers already running, against the capacity of the guest. It
+// returns the warnings about what the hotplugged resources may yet provide.
+pub fn check(
+    cap: &GuestCapacity,
+    r: &LinuxResources,
+    others: &[&LinuxResources],
+) -> Result<Vec<String>> {
+    let mut warnings = vec![];
+
+    let memory_limit = |r: &LinuxResources| {
+        r.memory
+            .as_ref()
+            .and_then(|m| m.limit)
+            .filter(|l| *l > 0)
+            .map_or(0, |l| l as u64)
+    };
+
+    let limit = memory_limit(r);
+    let total = limit + others.iter().map(|o| memory_limit(o)).sum::<u64>();
+    if limit > cap.memory {
+        warnings.push(format!(
+            "{}: memory limit of {} bytes, the guest has {} bytes",
+            INSUFFICIENT, limit, cap.memory
+        ));
+    } else if limit > 0 && total > cap.memory {
+        warnings.push(format!(
+            "{}: the memory limits of the containers total {} bytes, the guest has {} bytes",
+            INSUFFICIENT, total, cap.memory
+        ));
+    }
+
+    for l in r.hugepage_limits.iter().filter(|l| l.limit > 0) {
+        let pool = cap.hugepages.get(&l.page_size).copied().unwrap_or(0);
+        let total: u64 = others
+            .iter()
+            .flat_map(|o| o.hugepage_limits.iter())
+            .filter(|o| o.page_size == l.page_size)
+            .map(|o| o.limit)
+            .sum::<u64>()
+            + l.limit;
+
+        if total > pool {
+            return Err(anyhow!(
+                "{}: the {} hugepage limits of the containers total {} bytes, the guest has {} bytes",
+                INSUFFICIENT,
+                l.page_size,
+                total,
+                pool
+            ));
+        }
+    }
+
+    if let Some(cpu) = r.cpu.as_ref() {
+        warnings.extend(check_cpuset("cpus", &cpu.cpus, &cap.cpus)?);
+        warnings.extend(check_cpuset("mems", &cpu.mems, &cap.mems)?);
+    }
+
+    Ok(warnings)
+}
+
+fn check_cpuset(name: &str, requested: &str, online: &[u32]) -> Result<Option<String>> {
+    let list = parse_list(requested).with_context(|| format!("invalid cpuset.{}", name))?;
+
+    Ok(list.iter().find(|i| !online.contains(i)).map(|i| {
+        format!(
+            "{}: cpuset.{} {:?} has {} which isn't online in the guest",
+            INSUFFICIENT, name, requested, i
+        )
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci::{LinuxCpu, LinuxHugepageLimit, LinuxMemory};
+
+    #[test]
+    fn test_parse_list() {
+        assert_eq!(parse_list("0-3,6\n").unwrap(), vec![0, 1, 2, 3, 6]);
+        assert_eq!(parse_list("0").unwrap(), vec![0]);
+        assert!(parse_list("").unwrap().is_empty());
+
+        for s in &["a", "3-1", "0-", "1,-2"] {
+            assert!(parse_list(s).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn test_probe() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+
+        assert!(probe(root).is_err());
+
+        let write = |path: &str, content: &str| {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        };
+        write(
+            PROC_MEMINFO,
+            "MemTotal:        2048000 kB\nMemFree:          100000 kB\n",
+        );
+        write(SYSFS_CPU_ONLINE, "0-1\n");
+        write(
+            &format!("{}/hugepages-2048kB/nr_hugepages", SYSFS_HUGEPAGES_DIR),
+            "4\n",
+        );
+        write(
+            &format!("{}/hugepages-1048576kB/nr_hugepages", SYSFS_HUGEPAGES_DIR),
+            "0\n",
+        );
+
+        let cap = probe(root).unwrap();
+        assert_eq!(cap.memory, 2048000 * 1024);
+        assert_eq!(cap.cpus, vec![0, 1]);
+        assert_eq!(cap.mems, vec![0]);
+        assert_eq!(cap.hugepages.get("2MB"), Some(&(8 << 20)));
+        assert_eq!(cap.hugepages.get("1GB"), Some(&0));
+
+        write(
+            &format!("{}/hugepages-2048kB/free_hugepages", SYSFS_HUGEPAGES_DIR),
+            "3\n",
+        );
+        write(
+            &format!("{}/hugepages-2048kB/resv_hugepages", SYSFS_HUGEPAGES_DIR),
+            "1\n",
+        );
+        let pools = hugepage_pools(root);
+        let pool = pools.iter().find(|p| p.size == "2MB").unwrap();
+        assert_eq!(
+            pool,
+            &HugepagePool {
+                size: "2MB".to_string(),
+                bytes_per_page: 2 << 20,
+                total: 4,
+                free: 3,
+                reserved: 1,
+                surplus: 0,
+            }
+        );
+        assert_eq!(pools.len(), 2);
+    }
+
+    #[test]
+    fn test_check() {
+        let cap = GuestCapacity {
+            memory: 4 << 30,
+            cpus: vec![0, 1, 2, 3],
+            mems: vec![0],
+            hugepages: [("2MB".to_string(), 64 << 20)].iter().cloned().collect(),
+        };
+
+        let resources = |memory: i64, hugepages: u64, cpus: &str| LinuxResources {
+            memory: Some(LinuxMemory {
+                limit: Some(memory),
+                ..Default::default()
+            }),
+            hugepage_limits: vec![LinuxHugepageLimit {
+                page_size: "2MB".to_string(),
+                limit: hugepages,
+            }],
+            cpu: Some(LinuxCpu {
+                cpus: cpus.to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let r = resources(1 << 30, 32 << 20, "0-1");
+        assert!(check(&cap, &r, &[]).unwrap().is_empty());
+        assert!(check(&cap, &r, &[&r]).unwrap().is_empty());
+        let empty = LinuxResources::default();
+        assert!(check(&cap, &empty, &[&r, &r, &r, &r, &r])
+            .unwrap()
+            .is_empty());
+
+        // unlimited memory doesn't count
+        let unlimited = resources(-1, 0, "");
+        assert!(check(&cap, &unlimited, &[&r, &r, &r, &r])
+            .unwrap()
+            .is_empty());
+
+        // the memory and the cpus may yet be hotplugged
+        for (r, others) in &[
+            (resources(5 << 30, 0, ""), vec![]),
+            (resources(2 << 30, 0, ""), vec![resources(3 << 30, 0, "")]),
+            (resources(0, 0, "2-4"), vec![]),
+        ] {
+            let others: Vec<&LinuxResources> = others.iter().collect();
+            let warnings = check(&cap, r, &others).unwrap();
+            assert_eq!(warnings.len(), 1);
+            assert!(warnings[0].starts_with(INSUFFICIENT), "{:?}", warnings);
+        }
+
+        // the hugepage pools may not
+        for (r, others) in &[
+            (resources(0, 128 << 20, ""), vec![]),
+            (resources(0, 32 << 20, ""), vec![resources(0, 48 << 20, "")]),
+        ] {
+            let others: Vec<&LinuxResources> = others.iter().collect();
+            let err = check(&cap, r, &others).unwrap_err();
+            assert!(err.to_string().starts_with(INSUFFICIENT), "{:?}", err);
+        }
+
+        let mut r = resources(0, 0, "");
+        r.hugepage_limits[0].page_size = "1GB".to_string();
+        r.hugepage_limits[0].limit = 1 << 30;
+        assert!(check(&cap, &r, &[]).is_err());
+
+        r.cpu.as_mut().unwrap().mems = "1".to_string();
+        r.hugepage_limits.clear();
+        assert_eq!(check(&cap, &r, &[]).unwrap().len(), 1);
+
+        r.cpu.as_mut().unwrap().mems = "x".to_string();
+        assert!(check(&cap, &r, &[]).is_err());
+    }
+}
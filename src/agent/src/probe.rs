@@ -0,0 +1,461 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Readiness and liveness probes of the containers, run by the agent on a
+// schedule so that probe-heavy pods don't need a round trip to the host,
+// and a new process through ExecProcess, for every check. Only the changes
+// of the probe status are reported. The commands of the exec probes are run
+// as the processes of ExecProcess are, in the container, with its process
+// settings.
+
+use anyhow::{anyhow, Context, Result};
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use protocols::agent::{Probe, ProbeEvent, ProbeType};
+use rustjail::container::BaseContainer;
+use rustjail::pipestream::{PipeStream, SizedReader};
+use rustjail::process::{Process, StreamType};
+use slog::Logger;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadHalf};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time;
+
+use crate::rpc::cleanup_process;
+use crate::sandbox::Sandbox;
+use crate::AGENT_CONFIG;
+
+const DEFAULT_PERIOD_SECS: u32 = 10;
+const DEFAULT_TIMEOUT_SECS: u32 = 1;
+
+// The stderr of a failed exec probe kept for its message.
+const MAX_EXEC_STDERR: usize = 1024;
+
+// How long the output of an exec probe is read for once it exited, as the
+// processes it left behind may hold its pipes.
+const EXEC_OUTPUT_WAIT: Duration = Duration::from_millis(100);
+
+// Enough for the status line of any sane HTTP server.
+const MAX_STATUS_LINE: usize = 1024;
+
+#[derive(Debug)]
+pub struct ProbeManager {
+    // probe id -> (container id, task running the probe)
+    probes: HashMap<String, (String, JoinHandle<()>)>,
+    event_tx: Sender<ProbeEvent>,
+    pub event_rx: Arc<Mutex<Receiver<ProbeEvent>>>,
+}
+
+impl ProbeManager {
+    pub fn new() -> Self {
+        let (event_tx, event_rx) = channel::<ProbeEvent>(100);
+
+        ProbeManager {
+            probes: HashMap::new(),
+            event_tx,
+            event_rx: Arc::new(Mutex::new(event_rx)),
+        }
+    }
+
+    // add starts running the probe against its container of the sandbox,
+    // replacing the probe with the same id if any.
+    pub fn add(
+        &mut self,
+        logger: &Logger,
+        probe: Probe,
+        sandbox: Arc<Mutex<Sandbox>>,
+    ) -> Result<()> {
+        validate(&probe)?;
+
+        self.remove(&probe.id);
+
+        let id = probe.id.clone();
+        let cid = probe.container_id.clone();
+        let logger = logger.new(o!("probe" => id.clone(), "container" => cid.clone()));
+        let handle = tokio::spawn(run_probe(logger, probe, sandbox, self.event_tx.clone()));
+
+        self.probes.insert(id, (cid, handle));
+
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: &str) -> bool {
+        match self.probes.remove(id) {
+            Some((_, handle)) => {
+                handle.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // event_sender returns a sender of events along the ones of the
+    // probes, for the checks the agent runs on its own.
+    pub fn event_sender(&self) -> Sender<ProbeEvent> {
+        self.event_tx.clone()
+    }
+
+    // remove_container stops all the probes of a container.
+    pub fn remove_container(&mut self, cid: &str) {
+        let ids: Vec<String> = self
+            .probes
+            .iter()
+            .filter(|(_, (c, _))| c == cid)
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for id in ids {
+            self.remove(&id);
+        }
+    }
+}
+
+fn validate(probe: &Probe) -> Result<()> {
+    if probe.id.is_empty() {
+        return Err(anyhow!("empty probe id"));
+    }
+
+    match probe.field_type {
+        ProbeType::EXEC if probe.command.is_empty() => Err(anyhow!("exec probe without command")),
+        ProbeType::TCP | ProbeType::HTTP if probe.port == 0 || probe.port > u16::MAX as u32 => {
+            Err(anyhow!("invalid probe port {}", probe.port))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn or_default(v: u32, default: u32) -> u32 {
+    if v == 0 {
+        default
+    } else {
+        v
+    }
+}
+
+async fn run_probe(
+    logger: Logger,
+    probe: Probe,
+    sandbox: Arc<Mutex<Sandbox>>,
+    tx: Sender<ProbeEvent>,
+) {
+    let period = Duration::from_secs(or_default(probe.period_seconds, DEFAULT_PERIOD_SECS) as u64);
+    let timeout =
+        Duration::from_secs(or_default(probe.timeout_seconds, DEFAULT_TIMEOUT_SECS) as u64);
+    let success_threshold = probe.success_threshold.max(1);
+    let failure_threshold = probe.failure_threshold.max(1);
+
+    time::sleep(Duration::from_secs(probe.initial_delay_seconds as u64)).await;
+
+    let mut interval = time::interval(period);
+    let mut healthy = None;
+    let mut successes = 0;
+    let mut failures = 0;
+
+    loop {
+        interval.tick().await;
+
+        let result = check(&logger, &probe, &sandbox, timeout).await;
+
+        let message = match result {
+            Ok(()) => {
+                successes += 1;
+                failures = 0;
+                String::new()
+            }
+            Err(e) => {
+                debug!(logger, "probe failed"; "error" => format!("{:?}", e));
+                failures += 1;
+                successes = 0;
+                format!("{:?}", e)
+            }
+        };
+
+        let status = if successes >= success_threshold {
+            true
+        } else if failures >= failure_threshold {
+            false
+        } else {
+            continue;
+        };
+
+        if healthy == Some(status) {
+            continue;
+        }
+        healthy = Some(status);
+
+        info!(logger, "probe status changed"; "healthy" => status);
+
+        let mut ev = ProbeEvent::new();
+        ev.probe_id = probe.id.clone();
+        ev.container_id = probe.container_id.clone();
+        ev.healthy = status;
+        ev.message = message;
+
+        if tx.send(ev).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn check(
+    logger: &Logger,
+    probe: &Probe,
+    sandbox: &Arc<Mutex<Sandbox>>,
+    timeout: Duration,
+) -> Result<()> {
+    // the containers share the network namespace of the guest, so the tcp
+    // and http probes are run from the agent
+    match probe.field_type {
+        ProbeType::EXEC => check_exec(logger, probe, sandbox, timeout).await,
+        ProbeType::TCP => with_timeout(timeout, check_tcp(probe.port as u16)).await,
+        ProbeType::HTTP => with_timeout(timeout, check_http(probe.port as u16, &probe.path)).await,
+    }
+}
+
+async fn with_timeout(timeout: Duration, f: impl Future<Output = Result<()>>) -> Result<()> {
+    match time::timeout(timeout, f).await {
+        Ok(r) => r,
+        Err(_) => Err(anyhow!("timed out after {:?}", timeout)),
+    }
+}
+
+async fn check_exec(
+    logger: &Logger,
+    probe: &Probe,
+    sandbox: &Arc<Mutex<Sandbox>>,
+    timeout: Duration,
+) -> Result<()> {
+    let command = probe.command.to_vec();
+    let exec_id = format!("probe-{}", probe.id);
+    let pipe_size = AGENT_CONFIG.read().await.container_pipe_size;
+
+    let (pid, exit_rx, stdout, stderr) = {
+        let mut s = sandbox.lock().await;
+        let ctr = s
+            .get_container(&probe.container_id)
+            .ok_or_else(|| anyhow!("invalid container id"))?;
+
+        // the process settings of the container, e.g. its user, env and
+        // capabilities, but the command
+        let mut ocip = ctr
+            .config
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.process.clone())
+            .ok_or_else(|| anyhow!("container without process"))?;
+        ocip.args = command.clone();
+        ocip.terminal = false;
+
+        let p = Process::new(logger, &ocip, &exec_id, false, pipe_size)?;
+        ctr.run(p)
+            .await
+            .with_context(|| format!("failed to run {:?}", command))?;
+
+        let p = ctr.get_process(&exec_id)?;
+        let pid = p.pid;
+        let exit_rx = p.exit_rx.clone();
+        let stdout = p.get_reader(StreamType::ParentStdout);
+        let stderr = p.get_reader(StreamType::ParentStderr);
+
+        s.watch_process_exit(pid);
+
+        (pid, exit_rx, stdout, stderr)
+    };
+
+    // the output is read meanwhile, so that the command doesn't block on
+    // a full pipe
+    let stdout = stdout.map(|r| tokio::spawn(read_output(r, 0)));
+    let stderr = stderr.map(|r| tokio::spawn(read_output(r, MAX_EXEC_STDERR)));
+
+    let exited = async move {
+        if let Some(mut exit_rx) = exit_rx {
+            while exit_rx.changed().await.is_ok() {}
+        }
+    };
+    tokio::pin!(exited);
+
+    let timed_out = time::timeout(timeout, &mut exited).await.is_err();
+    if timed_out {
+        let _ = signal::kill(Pid::from_raw(pid), Signal::SIGKILL);
+        exited.await;
+    }
+
+    if let Some(h) = stdout {
+        h.abort();
+    }
+    let output = match stderr {
+        Some(mut h) => match time::timeout(EXEC_OUTPUT_WAIT, &mut h).await {
+            Ok(r) => r.unwrap_or_default(),
+            Err(_) => {
+                h.abort();
+                vec![]
+            }
+        },
+        None => vec![],
+    };
+
+    let exit_code = {
+        let mut s = sandbox.lock().await;
+        let ctr = s
+            .get_container(&probe.container_id)
+            .ok_or_else(|| anyhow!("invalid container id"))?;
+
+        let p = ctr
+            .processes
+            .get_mut(&pid)
+            .ok_or_else(|| anyhow!("lost the process of {:?}", command))?;
+        let _ = cleanup_process(p);
+        let exit_code = p.exit_code;
+
+        ctr.processes.remove(&pid);
+        exit_code
+    };
+
+    if timed_out {
+        return Err(anyhow!("{:?} timed out after {:?}", command, timeout));
+    }
+
+    if exit_code != 0 {
+        return Err(anyhow!(
+            "{:?} exited with {}: {}",
+            command,
+            exit_code,
+            String::from_utf8_lossy(&output).trim()
+        ));
+    }
+
+    Ok(())
+}
+
+// read_output reads the output of a process until its end, keeping at most
+// `max` bytes of it.
+async fn read_output(reader: Arc<Mutex<SizedReader<ReadHalf<PipeStream>>>>, max: usize) -> Vec<u8> {
+    let mut output = vec![];
+    let mut reader = reader.lock().await;
+
+    loop {
+        match reader.read_chunk(MAX_EXEC_STDERR).await {
+            Ok(chunk) if !chunk.is_empty() => {
+                let n = chunk.len().min(max.saturating_sub(output.len()));
+                output.extend_from_slice(&chunk[..n]);
+            }
+            _ => return output,
+        }
+    }
+}
+
+async fn check_tcp(port: u16) -> Result<()> {
+    TcpStream::connect(("127.0.0.1", port))
+        .await
+        .with_context(|| format!("failed to connect to port {}", port))?;
+
+    Ok(())
+}
+
+async fn check_http(port: u16, path: &str) -> Result<()> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port))
+        .await
+        .with_context(|| format!("failed to connect to port {}", port))?;
+
+    let path = if path.is_empty() { "/" } else { path };
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: localhost:{}\r\nUser-Agent: kata-agent\r\n\r\n",
+        path, port
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = vec![];
+    let mut buf = [0u8; 256];
+    while !response.windows(2).any(|w| w == b"\r\n") && response.len() < MAX_STATUS_LINE {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        response.extend_from_slice(&buf[..n]);
+    }
+
+    let status = parse_status_line(&String::from_utf8_lossy(&response))?;
+
+    // as with kubelet, any redirect is a success
+    if (200..400).contains(&status) {
+        Ok(())
+    } else {
+        Err(anyhow!("HTTP probe failed with status {}", status))
+    }
+}
+
+fn parse_status_line(response: &str) -> Result<u16> {
+    let line = response.lines().next().unwrap_or_default();
+    let mut fields = line.split_whitespace();
+
+    match (fields.next(), fields.next()) {
+        (Some(version), Some(status)) if version.starts_with("HTTP/") => status
+            .parse::<u16>()
+            .map_err(|_| anyhow!("invalid HTTP status line {:?}", line)),
+        _ => Err(anyhow!("invalid HTTP status line {:?}", line)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_validate() {
+        let mut probe = Probe::new();
+        assert!(validate(&probe).is_err());
+
+        probe.id = "readiness".to_string();
+        assert!(validate(&probe).is_err());
+
+        probe.command = vec!["true".to_string()].into();
+        validate(&probe).unwrap();
+
+        probe.field_type = ProbeType::TCP;
+        assert!(validate(&probe).is_err());
+        probe.port = 70000;
+        assert!(validate(&probe).is_err());
+        probe.port = 8080;
+        validate(&probe).unwrap();
+    }
+
+    #[test]
+    fn test_parse_status_line() {
+        assert_eq!(parse_status_line("HTTP/1.1 200 OK\r\n").unwrap(), 200);
+        assert_eq!(parse_status_line("HTTP/1.0 503").unwrap(), 503);
+        assert!(parse_status_line("").is_err());
+        assert!(parse_status_line("SSH-2.0-OpenSSH\r\n").is_err());
+        assert!(parse_status_line("HTTP/1.1 OK\r\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_check_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        check_tcp(port).await.unwrap();
+        let _ = listener.accept().await.unwrap();
+
+        tokio::spawn(async move {
+            for status in &["200 OK", "500 Internal Server Error"] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf).await.unwrap();
+                let response = format!("HTTP/1.1 {}\r\n\r\n", status);
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        check_http(port, "/healthz").await.unwrap();
+        assert!(check_http(port, "/healthz").await.is_err());
+    }
+}
@@ -0,0 +1,157 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Agent profiles trade functionality against memory footprint. The "micro"
+// profile, aimed at Firecracker-class guests, turns off the subsystems
+// which keep memory or tasks around but aren't needed to run containers:
+// the metrics, which aren't even allocated, the tracing and the usage
+// history. It also runs the agent on a single worker thread, rather than
+// one per vCPU, with fewer threads for the blocking operations. The
+// default profile is "full", or "micro" when the agent is built with the
+// "micro" feature, and can be changed with agent.profile. The "micro"
+// profile also confines the agent with the baseline policy, see
+// src/confinement.rs.
+
+use anyhow::{anyhow, Result};
+use std::str::FromStr;
+
+use crate::confinement::Confinement;
+
+const PROFILE_FULL: &str = "full";
+const PROFILE_MICRO: &str = "micro";
+
+// the threads of the tokio runtime under the micro profile
+const MICRO_WORKER_THREADS: usize = 1;
+const MICRO_MAX_BLOCKING_THREADS: usize = 16;
+
+// Names of the subsystems, as reported in the guest details.
+pub const SUBSYSTEM_METRICS: &str = "metrics";
+pub const SUBSYSTEM_TRACING: &str = "tracing";
+pub const SUBSYSTEM_USAGE_HISTORY: &str = "usage_history";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Profile {
+    Full,
+    Micro,
+}
+
+impl Default for Profile {
+    #[cfg(not(feature = "micro"))]
+    fn default() -> Self {
+        Profile::Full
+    }
+
+    #[cfg(feature = "micro")]
+    fn default() -> Self {
+        Profile::Micro
+    }
+}
+
+impl FromStr for Profile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            PROFILE_FULL => Ok(Profile::Full),
+            PROFILE_MICRO => Ok(Profile::Micro),
+            _ => Err(anyhow!("invalid agent profile {:?}", s)),
+        }
+    }
+}
+
+impl Profile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Profile::Full => PROFILE_FULL,
+            Profile::Micro => PROFILE_MICRO,
+        }
+    }
+
+    pub fn metrics(&self) -> bool {
+        *self == Profile::Full
+    }
+
+    pub fn tracing(&self) -> bool {
+        *self == Profile::Full
+    }
+
+    pub fn usage_history(&self) -> bool {
+        *self == Profile::Full
+    }
+
+    // worker_threads returns the number of worker threads of the runtime,
+    // None for one per CPU.
+    pub fn worker_threads(&self) -> Option<usize> {
+        match self {
+            Profile::Full => None,
+            Profile::Micro => Some(MICRO_WORKER_THREADS),
+        }
+    }
+
+    // max_blocking_threads returns the maximum number of threads of the
+    // runtime for the blocking operations, None for the tokio default.
+    pub fn max_blocking_threads(&self) -> Option<usize> {
+        match self {
+            Profile::Full => None,
+            Profile::Micro => Some(MICRO_MAX_BLOCKING_THREADS),
+        }
+    }
+
+    // confinement returns the policy the agent is confined with, unless
+    // agent.confinement is set.
+    pub fn confinement(&self) -> Confinement {
+        match self {
+            Profile::Full => Confinement::None,
+            Profile::Micro => Confinement::Baseline,
+        }
+    }
+
+    // disabled_subsystems lists the subsystems turned off by the profile.
+    pub fn disabled_subsystems(&self) -> Vec<&'static str> {
+        let mut v = vec![];
+
+        if !self.metrics() {
+            v.push(SUBSYSTEM_METRICS);
+        }
+        if !self.tracing() {
+            v.push(SUBSYSTEM_TRACING);
+        }
+        if !self.usage_history() {
+            v.push(SUBSYSTEM_USAGE_HISTORY);
+        }
+
+        v
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile() {
+        for p in &[Profile::Full, Profile::Micro] {
+            assert_eq!(p.as_str().parse::<Profile>().unwrap(), *p);
+        }
+        assert!("tiny".parse::<Profile>().is_err());
+        assert!("".parse::<Profile>().is_err());
+
+        assert!(Profile::Full.disabled_subsystems().is_empty());
+        assert_eq!(
+            Profile::Micro.disabled_subsystems(),
+            vec![
+                SUBSYSTEM_METRICS,
+                SUBSYSTEM_TRACING,
+                SUBSYSTEM_USAGE_HISTORY
+            ]
+        );
+
+        assert_eq!(Profile::Full.worker_threads(), None);
+        assert_eq!(Profile::Micro.worker_threads(), Some(1));
+
+        assert_eq!(Profile::Full.confinement(), Confinement::None);
+        assert_eq!(Profile::Micro.confinement(), Confinement::Baseline);
+    }
+}
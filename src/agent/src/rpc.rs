@@ -4,32 +4,49 @@
 //
 
 use async_trait::async_trait;
-use rustjail::{pipestream::PipeStream, process::StreamType};
-use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadHalf};
-use tokio::sync::Mutex;
+use rustjail::{
+    pipestream::{PipeStream, SizedReader},
+    process::StreamType,
+};
+use tokio::io::{AsyncWriteExt, ReadHalf};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::{Mutex, MutexGuard};
 
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::Instant;
 use ttrpc::{
     self,
     error::get_rpc_status as ttrpc_error,
-    r#async::{Server as TtrpcServer, TtrpcContext},
+    r#async::{MethodHandler, Server as TtrpcServer, TtrpcContext},
 };
 
 use anyhow::{anyhow, Context, Result};
-use oci::{LinuxNamespace, Root, Spec};
+use cgroups::freezer::FreezerState;
+use oci::{ContainerState, LinuxNamespace, LinuxResources, Root, Spec};
 use protobuf::{RepeatedField, SingularPtrField};
 use protocols::agent::{
-    AgentDetails, CopyFileRequest, GuestDetailsResponse, Interfaces, Metrics, OOMEvent,
-    ReadStreamResponse, Routes, StatsContainerResponse, WaitProcessResponse, WriteStreamResponse,
+    AgentDetails, BootLogs, CgroupEvent, CgroupEventType, ContainerMounts, CopyFileRequest,
+    FsEvent, GetMetricsRequest, GuestDetailsResponse, Interfaces, MemoryDumpChunk, Metrics,
+    MetricsPush, NegotiateAPIVersionResponse, OOMEvent, ProbeEvent, ReadStreamResponse,
+    ResourcesValidation, RootfsIntegrity, Routes, StatsContainerResponse, StatsContainersResponse,
+    Storage, UsageHistory, VsockPort, VsockPorts, WaitProcessResponse, Warning,
+    WriteStreamResponse,
 };
 use protocols::empty::Empty;
 use protocols::health::{
     HealthCheckResponse, HealthCheckResponse_ServingStatus, VersionCheckResponse,
 };
 use protocols::types::Interface;
+use rustjail::cgroups::fs::{get_cgroup_details, Manager as FsManager};
 use rustjail::cgroups::notifier;
-use rustjail::container::{BaseContainer, Container, LinuxContainer};
+use rustjail::cgroups::sandbox::SandboxCgroupManager;
+use rustjail::cgroups::{CgroupError, Manager as CgroupManager, Sampler};
+use rustjail::container::{
+    BaseContainer, Container, LinuxContainer, OomPolicy, CGROUP_PRECREATED_ANNOTATION,
+};
 use rustjail::process::Process;
 use rustjail::specconv::CreateOpts;
 
@@ -40,14 +57,26 @@ use nix::sys::stat;
 use nix::unistd::{self, Pid};
 use rustjail::process::ProcessOperations;
 
+use crate::bootlog;
+use crate::clock;
+use crate::confinement::Confinement;
 use crate::device::{add_devices, rescan_pci_bus, update_device_cgroup};
+use crate::io_limits;
 use crate::linux_abi::*;
-use crate::metrics::get_metrics;
+use crate::memdump;
+use crate::metrics::{self, get_metrics};
+use crate::metrics_push;
 use crate::mount::{add_storages, remove_mounts, BareMount, STORAGE_HANDLER_LIST};
+use crate::mountinfo;
 use crate::namespace::{NSTYPEIPC, NSTYPEPID, NSTYPEUTS};
 use crate::network::setup_guest_dns;
+use crate::postmortem;
+use crate::preflight;
+use crate::profile::Profile;
 use crate::random;
-use crate::sandbox::Sandbox;
+use crate::sandbox::{recv_cgroup_event, Sandbox};
+use crate::sysctl;
+use crate::verity;
 use crate::version::{AGENT_VERSION, API_VERSION};
 use crate::AGENT_CONFIG;
 
@@ -70,10 +99,17 @@ use nix::unistd::{Gid, Uid};
 use std::fs::{File, OpenOptions};
 use std::io::{BufRead, BufReader};
 use std::os::unix::fs::FileExt;
+use std::os::unix::io::RawFd;
 use std::path::PathBuf;
 
 const CONTAINER_BASE: &str = "/run/kata-containers";
 const MODPROBE_PATH: &str = "/sbin/modprobe";
+const FROZEN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// the time the processes of a container restarted on OOM have to exit on
+// SIGTERM before they are killed
+const OOM_RESTART_GRACE: Duration = Duration::from_secs(5);
+const TERMINATE_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 // Convenience macro to obtain the scope logger
 macro_rules! sl {
@@ -114,6 +150,18 @@ impl AgentService {
 
         verify_cid(&cid)?;
 
+        // each container belongs to one of the pods, with agent.multi_sandbox
+        if AGENT_CONFIG.read().await.multi_sandbox
+            && !self
+                .sandbox
+                .lock()
+                .await
+                .sandboxes
+                .contains_key(&req.sandbox_id)
+        {
+            return Err(anyhow!("Invalid sandbox id {}", req.sandbox_id));
+        }
+
         let mut oci_spec = req.OCI.clone();
         let use_sandbox_pidns = req.get_sandbox_pidns();
 
@@ -139,7 +187,7 @@ impl AgentService {
         // updates the devices listed in the OCI spec, so that they actually
         // match real devices inside the VM. This step is necessary since we
         // cannot predict everything from the caller.
-        add_devices(&req.devices.to_vec(), &mut oci, &self.sandbox).await?;
+        let mut device_map = add_devices(&req.devices.to_vec(), &mut oci, &self.sandbox).await?;
 
         // Both rootfs and volumes (invoked with --volume for instance) will
         // be processed the same way. The idea is to always mount any provided
@@ -152,17 +200,87 @@ impl AgentService {
         {
             sandbox = self.sandbox.clone();
             s = sandbox.lock().await;
+            device_map.extend(m.iter().filter_map(|mp| s.block_volumes.get(mp)).cloned());
             s.container_mounts.insert(cid.clone(), m);
         }
 
-        update_container_namespaces(&s, &mut oci, use_sandbox_pidns)?;
+        update_container_namespaces(&s, &req.sandbox_id, &mut oci, use_sandbox_pidns)?;
+
+        // The spec names the interfaces as the host does
+        if let Some(r) = oci.linux.as_mut().and_then(|l| l.resources.as_mut()) {
+            s.network.map_interface_priorities(r);
+        }
+
+        // Expand the sysctl presets requested through the annotations, the
+        // ones which aren't namespaced are set for the whole guest
+        let global_sysctls = sysctl::apply_presets(&mut oci)?;
+        sysctl::set_guest_global_sysctls(&global_sysctls)?;
 
         // Add the root partition to the device cgroup to prevent access
         update_device_cgroup(&mut oci)?;
 
+        // Share the disks of the sandbox fairly by default
+        io_limits::apply_default_io_limits(
+            &mut oci,
+            &AGENT_CONFIG.read().await.default_io_limits,
+            Path::new(io_limits::SYSFS_BLOCK_DIR),
+        )?;
+
+        // Deprioritize the containers which don't pick their scheduling
+        if let Some(p) = oci.process.as_mut() {
+            if p.scheduler.is_none() {
+                p.scheduler = AGENT_CONFIG.read().await.default_scheduler();
+            }
+        }
+
+        // Fail early when the container can't fit in the guest, and warn
+        // when it only fits once the runtime hotplugged its resources
+        if let Some(r) = oci.linux.as_ref().and_then(|l| l.resources.as_ref()) {
+            let others: Vec<&LinuxResources> = s
+                .containers
+                .values()
+                .filter(|c| c.status() != ContainerState::Stopped)
+                .filter_map(|c| c.config.spec.as_ref())
+                .filter_map(|spec| spec.linux.as_ref())
+                .filter_map(|l| l.resources.as_ref())
+                .collect();
+
+            match preflight::probe(Path::new("/")) {
+                Ok(cap) => {
+                    for w in preflight::check(&cap, r, &others)? {
+                        warn!(sl!(), "{}", w; "container" => &cid);
+                    }
+                }
+                Err(e) => {
+                    warn!(sl!(), "failed to probe the guest resources"; "error" => format!("{:?}", e))
+                }
+            }
+        }
+
+        // Group the container under the sandbox cgroup, unless its cgroup
+        // is owned by someone else or was picked by the runtime
+        if let Some(cg) = s.sandbox_cgroup(&req.sandbox_id) {
+            let precreated = match oci.annotations.get(CGROUP_PRECREATED_ANNOTATION) {
+                Some(v) => v.parse::<bool>().with_context(|| {
+                    format!("invalid {} annotation", CGROUP_PRECREATED_ANNOTATION)
+                })?,
+                None => false,
+            };
+            if let Some(linux) = oci
+                .linux
+                .as_mut()
+                .filter(|l| !precreated && l.cgroups_path.is_empty())
+            {
+                linux.cgroups_path = cg.container_path(&cid);
+            }
+        }
+
         // Append guest hooks
         append_guest_hooks(&s, &mut oci);
 
+        // The containers get the timezone of the sandbox
+        clock::add_localtime_mount(&mut oci);
+
         // write spec to bundle path, hooks might
         // read ocispec
         let olddir = setup_bundle(&cid, &mut oci)?;
@@ -171,12 +289,18 @@ impl AgentService {
 
         let opts = CreateOpts {
             cgroup_name: "".to_string(),
+            cgroup_root: AGENT_CONFIG.read().await.cgroup_root.clone(),
             use_systemd_cgroup: false,
             no_pivot_root: s.no_pivot_root,
             no_new_keyring: false,
             spec: Some(oci.clone()),
             rootless_euid: false,
-            rootless_cgroup: false,
+            rootless_cgroup: AGENT_CONFIG.read().await.rootless_cgroups,
+            disable_kmem_accounting: AGENT_CONFIG.read().await.disable_kmem_accounting,
+            strict_resources: AGENT_CONFIG.read().await.strict_resources,
+            io_cost: AGENT_CONFIG.read().await.io_cost,
+            default_swappiness: AGENT_CONFIG.read().await.default_swappiness,
+            device_map,
         };
 
         let mut ctr: LinuxContainer =
@@ -198,8 +322,20 @@ impl AgentService {
 
         ctr.start(p).await?;
 
-        s.update_shared_pidns(&ctr)?;
+        // the resource settings the guest can't apply only fail the
+        // creation with agent.strict_resources
+        if let Some(cgm) = ctr.cgroup_manager.as_ref() {
+            for u in cgm.unapplied_resources() {
+                s.warnings.add("CreateContainer", &cid, &u);
+            }
+        }
+
+        save_cgroup_state(&ctr);
+        s.update_shared_pidns(&req.sandbox_id, &ctr)?;
         s.add_container(ctr);
+        if let Some(ls) = s.sandboxes.get_mut(&req.sandbox_id) {
+            ls.containers.insert(cid.clone());
+        }
         info!(sl!(), "created container!");
 
         Ok(())
@@ -211,7 +347,7 @@ impl AgentService {
 
         let sandbox = self.sandbox.clone();
         let mut s = sandbox.lock().await;
-        let sid = s.id.clone();
+        let sandbox_container = s.is_sandbox_container(&cid);
 
         let ctr = s
             .get_container(&cid)
@@ -219,18 +355,118 @@ impl AgentService {
 
         ctr.exec()?;
 
-        // start oom event loop
-        if sid != cid && ctr.cgroup_manager.is_some() {
-            let cg_path = ctr.cgroup_manager.as_ref().unwrap().get_cg_path("memory");
-            if cg_path.is_some() {
-                let rx = notifier::notify_oom(cid.as_str(), cg_path.unwrap()).await?;
-                s.run_oom_event_monitor(rx, cid.clone()).await;
-            }
+        if !sandbox_container {
+            start_event_monitors(sandbox.clone(), &mut s, &cid).await?;
+        }
+
+        Ok(())
+    }
+
+    #[instrument]
+    async fn do_update_device_rule(
+        &self,
+        req: protocols::agent::DeviceRuleRequest,
+        add: bool,
+    ) -> Result<()> {
+        let cid = req.container_id;
+        let rule = req
+            .rule
+            .as_ref()
+            .map(rustjail::device_cgroup_grpc_to_oci)
+            .ok_or_else(|| anyhow!("missing device rule"))?;
+
+        let sandbox = self.sandbox.clone();
+        let mut s = sandbox.lock().await;
+
+        let ctr = s
+            .get_container(&cid)
+            .ok_or_else(|| anyhow!("Invalid container id"))?;
+        let cgm = ctr
+            .cgroup_manager
+            .as_ref()
+            .ok_or_else(|| anyhow!("no cgroup manager for container {}", cid))?;
+
+        if add {
+            cgm.add_device_rule(&rule)?;
+        } else {
+            cgm.remove_device_rule(&rule)?;
         }
+        save_cgroup_state(ctr);
 
         Ok(())
     }
 
+    #[instrument]
+    async fn do_start_containers(
+        &self,
+        req: protocols::agent::StartContainersRequest,
+    ) -> Result<()> {
+        let cids = req.container_ids.into_vec();
+        if cids.is_empty() {
+            return Err(anyhow!("no container to start"));
+        }
+
+        let sandbox = self.sandbox.clone();
+        let mut s = sandbox.lock().await;
+
+        let mut seen = HashSet::new();
+        for cid in cids.iter() {
+            if !seen.insert(cid) {
+                return Err(anyhow!("container {} listed more than once", cid));
+            }
+
+            let ctr = s
+                .get_container(cid)
+                .ok_or_else(|| anyhow!("Invalid container id {}", cid))?;
+            if ctr.status() != ContainerState::Created {
+                return Err(anyhow!("container {} is not in created state", cid));
+            }
+        }
+
+        // wait for all the containers to be ready to run their process
+        // before starting any of them
+        let mut fds = vec![];
+        for cid in cids.iter() {
+            let ctr = s.get_container(cid).unwrap();
+            match ctr.prepare_exec() {
+                Ok(fd) => fds.push(fd),
+                Err(e) => {
+                    // aborts the start of the containers already prepared
+                    for fd in fds {
+                        let _ = unistd::close(fd);
+                    }
+                    return Err(e.context(format!("failed to prepare container {}", cid)));
+                }
+            }
+        }
+
+        // then release them together, in the order of the request
+        let (started, res) = release_execs(cids.iter().zip(fds), |cid, fd| {
+            s.get_container(cid)
+                .unwrap()
+                .release_exec(fd)
+                .with_context(|| format!("failed to start container {}", cid))
+        });
+
+        // the containers started are monitored as after StartContainer,
+        // even if one after them failed to start
+        let mut monitor_res = Ok(());
+        for cid in started.iter() {
+            if s.is_sandbox_container(cid) {
+                continue;
+            }
+
+            if let Err(e) = start_event_monitors(sandbox.clone(), &mut s, cid).await {
+                warn!(sl!(), "failed to monitor container"; "container" => cid, "error" => format!("{:?}", e));
+                if monitor_res.is_ok() {
+                    monitor_res = Err(e);
+                }
+            }
+        }
+
+        res.and(monitor_res)
+    }
+
     #[instrument]
     async fn do_remove_container(
         &self,
@@ -240,6 +476,16 @@ impl AgentService {
         let mut cmounts: Vec<String> = vec![];
 
         let mut remove_container_resources = |sandbox: &mut Sandbox| -> Result<()> {
+            // the container is gone, whether its mounts can be removed or not
+            sandbox.cgroup_watches.remove(&cid);
+            if sandbox
+                .memory_dump
+                .as_ref()
+                .map_or(false, |d| d.container_id == cid)
+            {
+                sandbox.memory_dump = None;
+            }
+
             // Find the sandbox storage used by this container
             let mounts = sandbox.container_mounts.get(&cid);
             if let Some(mounts) = mounts {
@@ -258,6 +504,16 @@ impl AgentService {
 
             sandbox.container_mounts.remove(cid.as_str());
             sandbox.containers.remove(cid.as_str());
+            for ls in sandbox.sandboxes.values_mut() {
+                ls.containers.remove(&cid);
+            }
+            sandbox.probes.remove_container(&cid);
+            sandbox.fs_watches.remove_container(&cid);
+            sandbox.usage_history.remove(&cid);
+            metrics::remove_container_metrics(&cid);
+            if let Err(e) = FsManager::remove_state(&cid) {
+                warn!(sl!(), "failed to remove the cgroup state"; "container" => &cid, "error" => format!("{:?}", e));
+            }
             Ok(())
         };
 
@@ -267,15 +523,19 @@ impl AgentService {
 
             sandbox.bind_watcher.remove_container(&cid).await;
 
-            sandbox
+            let ctr = sandbox
                 .get_container(&cid)
-                .ok_or_else(|| anyhow!("Invalid container id"))?
-                .destroy()
-                .await?;
+                .ok_or_else(|| anyhow!("Invalid container id"))?;
+            let cgm = ctr.cgroup_manager.take();
+            if let Err(e) = ctr.destroy().await {
+                ctr.cgroup_manager = cgm;
+                return Err(e);
+            }
 
             remove_container_resources(&mut sandbox)?;
+            drop(sandbox);
 
-            return Ok(());
+            return destroy_cgroup(cgm).await;
         }
 
         // timeout != 0
@@ -286,8 +546,12 @@ impl AgentService {
         let handle = tokio::spawn(async move {
             let mut sandbox = s.lock().await;
             if let Some(ctr) = sandbox.get_container(&cid2) {
+                let cgm = ctr.cgroup_manager.take();
                 ctr.destroy().await.unwrap();
                 sandbox.bind_watcher.remove_container(&cid2).await;
+                drop(sandbox);
+
+                destroy_cgroup(cgm).await.unwrap();
                 tx.send(1).unwrap();
             };
         });
@@ -313,6 +577,31 @@ impl AgentService {
         Ok(())
     }
 
+    // do_destroy_logical_sandbox removes the containers of a pod of the
+    // guest, then the pod itself, in multi-sandbox mode. The containers are
+    // removed at best, so that the pod is removed whatever happens.
+    #[instrument]
+    async fn do_destroy_logical_sandbox(&self, sid: &str) -> Result<()> {
+        let cids: Vec<String> = {
+            let s = self.sandbox.lock().await;
+            let ls = s
+                .sandboxes
+                .get(sid)
+                .ok_or_else(|| anyhow!("Invalid sandbox id {}", sid))?;
+            ls.containers.iter().cloned().collect()
+        };
+
+        for cid in cids {
+            let mut req = protocols::agent::RemoveContainerRequest::new();
+            req.container_id = cid.clone();
+            if let Err(e) = self.do_remove_container(req).await {
+                warn!(sl!(), "failed to remove a container"; "sandbox" => sid, "container" => cid, "error" => format!("{:?}", e));
+            }
+        }
+
+        self.sandbox.lock().await.remove_logical_sandbox(sid)
+    }
+
     #[instrument]
     async fn do_exec_process(&self, req: protocols::agent::ExecProcessRequest) -> Result<()> {
         let cid = req.container_id.clone();
@@ -320,8 +609,7 @@ impl AgentService {
 
         info!(sl!(), "do_exec_process cid: {} eid: {}", cid, exec_id);
 
-        let s = self.sandbox.clone();
-        let mut sandbox = s.lock().await;
+        let mut sandbox = self.lock_thawed(&cid).await?;
 
         let process = if req.process.is_some() {
             req.process.as_ref().unwrap()
@@ -339,16 +627,57 @@ impl AgentService {
 
         ctr.run(p).await?;
 
+        let pid = ctr.get_process(exec_id.as_str())?.pid;
+        sandbox.watch_process_exit(pid);
+
         Ok(())
     }
 
+    // lock_thawed locks the sandbox once the container `cid` isn't frozen,
+    // waiting for it up to agent.frozen_wait seconds, with the sandbox
+    // unlocked meanwhile so that the container can be resumed, and fails
+    // with CgroupError::Frozen past it. Without agent.frozen_wait, the
+    // sandbox is locked right away, whatever the state of the container.
+    async fn lock_thawed(&self, cid: &str) -> Result<MutexGuard<'_, Sandbox>> {
+        let wait = Duration::from_secs(AGENT_CONFIG.read().await.frozen_wait as u64);
+        if wait == Duration::from_secs(0) {
+            return Ok(self.sandbox.lock().await);
+        }
+
+        let deadline = Instant::now() + wait;
+
+        loop {
+            let sandbox = self.sandbox.lock().await;
+            let state = match sandbox
+                .containers
+                .get(cid)
+                .and_then(|c| c.cgroup_manager.as_ref())
+            {
+                Some(cgm) => cgm.state()?,
+                None => return Ok(sandbox),
+            };
+
+            if state == FreezerState::Thawed {
+                return Ok(sandbox);
+            }
+            if Instant::now() >= deadline {
+                return Err(CgroupError::Frozen {
+                    cgroup: cid.to_string(),
+                }
+                .into());
+            }
+
+            drop(sandbox);
+            tokio::time::sleep(FROZEN_POLL_INTERVAL).await;
+        }
+    }
+
     #[instrument]
     async fn do_signal_process(&self, req: protocols::agent::SignalProcessRequest) -> Result<()> {
         let cid = req.container_id.clone();
         let eid = req.exec_id.clone();
         let s = self.sandbox.clone();
         let mut sandbox = s.lock().await;
-        let mut init = false;
 
         info!(
             sl!(),
@@ -357,13 +686,25 @@ impl AgentService {
             "exec-id" => eid.clone(),
         );
 
+        let mut signal = Signal::try_from(req.signal as i32).unwrap();
+
+        // An empty exec id means to signal all the processes of the container,
+        // the scope is decided by the container's signal scope option.
         if eid.is_empty() {
-            init = true;
-        }
+            let ctr = sandbox
+                .get_container(cid.as_str())
+                .ok_or_else(|| anyhow!("Invalid container id"))?;
+
+            // Use the same SIGTERM handling as for a single init process,
+            // so that TERM and KILL reach the same set of processes.
+            if signal == Signal::SIGTERM && !is_signal_handled(ctr.init_process_pid, req.signal) {
+                signal = Signal::SIGKILL;
+            }
 
-        let p = find_process(&mut sandbox, cid.as_str(), eid.as_str(), init)?;
+            return ctr.signal(signal, true);
+        }
 
-        let mut signal = Signal::try_from(req.signal as i32).unwrap();
+        let p = find_process(&mut sandbox, cid.as_str(), eid.as_str(), false)?;
 
         // For container initProcess, if it hasn't installed handler for "SIGTERM" signal,
         // it will ignore the "SIGTERM" signal sent to it, thus send it "SIGKILL" signal
@@ -532,7 +873,7 @@ impl protocols::agent_ttrpc::AgentService for AgentService {
     ) -> ttrpc::Result<Empty> {
         trace_rpc_call!(ctx, "create_container", req);
         match self.do_create_container(req).await {
-            Err(e) => Err(ttrpc_error(ttrpc::Code::INTERNAL, e.to_string())),
+            Err(e) => Err(ttrpc_error(cgroup_error_code(&e), e.to_string())),
             Ok(_) => Ok(Empty::new()),
         }
     }
@@ -549,6 +890,18 @@ impl protocols::agent_ttrpc::AgentService for AgentService {
         }
     }
 
+    async fn start_containers(
+        &self,
+        ctx: &TtrpcContext,
+        req: protocols::agent::StartContainersRequest,
+    ) -> ttrpc::Result<Empty> {
+        trace_rpc_call!(ctx, "start_containers", req);
+        match self.do_start_containers(req).await {
+            Err(e) => Err(ttrpc_error(ttrpc::Code::INTERNAL, e.to_string())),
+            Ok(_) => Ok(Empty::new()),
+        }
+    }
+
     async fn remove_container(
         &self,
         ctx: &TtrpcContext,
@@ -568,7 +921,7 @@ impl protocols::agent_ttrpc::AgentService for AgentService {
     ) -> ttrpc::Result<Empty> {
         trace_rpc_call!(ctx, "exec_process", req);
         match self.do_exec_process(req).await {
-            Err(e) => Err(ttrpc_error(ttrpc::Code::INTERNAL, e.to_string())),
+            Err(e) => Err(ttrpc_error(cgroup_error_code(&e), e.to_string())),
             Ok(_) => Ok(Empty::new()),
         }
     }
@@ -605,8 +958,16 @@ impl protocols::agent_ttrpc::AgentService for AgentService {
         let cid = req.container_id.clone();
         let res = req.resources;
 
-        let s = Arc::clone(&self.sandbox);
-        let mut sandbox = s.lock().await;
+        let mut sandbox = self
+            .lock_thawed(&cid)
+            .await
+            .map_err(|e| ttrpc_error(cgroup_error_code(&e), e.to_string()))?;
+
+        let oci_res = res.as_ref().map(|res| {
+            let mut oci_res = rustjail::resources_grpc_to_oci(res);
+            sandbox.network.map_interface_priorities(&mut oci_res);
+            oci_res
+        });
 
         let ctr = sandbox.get_container(&cid).ok_or_else(|| {
             ttrpc_error(
@@ -617,14 +978,19 @@ impl protocols::agent_ttrpc::AgentService for AgentService {
 
         let resp = Empty::new();
 
-        if res.is_some() {
-            let oci_res = rustjail::resources_grpc_to_oci(&res.unwrap());
-            match ctr.set(oci_res) {
-                Err(e) => {
-                    return Err(ttrpc_error(ttrpc::Code::INTERNAL, e.to_string()));
-                }
-
-                Ok(_) => return Ok(resp),
+        if let Some(oci_res) = oci_res {
+            if let Err(e) = ctr.set(oci_res) {
+                return Err(ttrpc_error(cgroup_error_code(&e), e.to_string()));
+            }
+            save_cgroup_state(ctr);
+
+            let unapplied = ctr
+                .cgroup_manager
+                .as_ref()
+                .map(|cgm| cgm.unapplied_resources())
+                .unwrap_or_default();
+            for u in unapplied {
+                sandbox.warnings.add("UpdateContainer", &cid, &u);
             }
         }
 
@@ -649,7 +1015,102 @@ impl protocols::agent_ttrpc::AgentService for AgentService {
         })?;
 
         ctr.stats()
-            .map_err(|e| ttrpc_error(ttrpc::Code::INTERNAL, e.to_string()))
+            .map_err(|e| ttrpc_error(cgroup_error_code(&e), e.to_string()))
+    }
+
+    async fn update_sandbox_resources(
+        &self,
+        ctx: &TtrpcContext,
+        req: protocols::agent::UpdateSandboxResourcesRequest,
+    ) -> ttrpc::Result<Empty> {
+        trace_rpc_call!(ctx, "update_sandbox_resources", req);
+
+        let res = req.resources.into_option().ok_or_else(|| {
+            ttrpc_error(
+                ttrpc::Code::INVALID_ARGUMENT,
+                "missing resources".to_string(),
+            )
+        })?;
+
+        let s = self.sandbox.lock().await;
+        let cg = s.sandbox_cgroup(&req.sandbox_id).ok_or_else(|| {
+            ttrpc_error(
+                ttrpc::Code::FAILED_PRECONDITION,
+                "sandbox cgroup not created".to_string(),
+            )
+        })?;
+
+        cg.set(&rustjail::resources_grpc_to_oci(&res))
+            .map_err(|e| ttrpc_error(cgroup_error_code(&e), e.to_string()))?;
+
+        for u in cg.unapplied_resources() {
+            s.warnings.add("UpdateSandboxResources", "", &u);
+        }
+
+        Ok(Empty::new())
+    }
+
+    async fn stats_sandbox(
+        &self,
+        ctx: &TtrpcContext,
+        req: protocols::agent::StatsSandboxRequest,
+    ) -> ttrpc::Result<StatsContainerResponse> {
+        trace_rpc_call!(ctx, "stats_sandbox", req);
+
+        let s = self.sandbox.lock().await;
+        let cg = s.sandbox_cgroup(&req.sandbox_id).ok_or_else(|| {
+            ttrpc_error(
+                ttrpc::Code::FAILED_PRECONDITION,
+                "sandbox cgroup not created".to_string(),
+            )
+        })?;
+
+        let stats = cg
+            .get_stats()
+            .map_err(|e| ttrpc_error(cgroup_error_code(&e), e.to_string()))?;
+
+        let mut resp = StatsContainerResponse::new();
+        resp.cgroup_stats = SingularPtrField::some(stats.into());
+        resp.unapplied_resources = RepeatedField::from_vec(cg.unapplied_resources());
+
+        Ok(resp)
+    }
+
+    async fn stats_containers(
+        &self,
+        ctx: &TtrpcContext,
+        req: protocols::agent::StatsContainersRequest,
+    ) -> ttrpc::Result<StatsContainersResponse> {
+        trace_rpc_call!(ctx, "stats_containers", req);
+        let s = Arc::clone(&self.sandbox);
+        let sandbox = s.lock().await;
+
+        let cids: Vec<String> = if req.container_ids.is_empty() {
+            sandbox.containers.keys().cloned().collect()
+        } else {
+            req.container_ids.to_vec()
+        };
+
+        // the cgroup handle of each container is loaded already, so the
+        // stats are read straight from the cgroup files
+        let mut resp = StatsContainersResponse::new();
+        for cid in cids {
+            let ctr = match sandbox.containers.get(&cid) {
+                Some(ctr) => ctr,
+                None => continue,
+            };
+
+            match ctr.stats() {
+                Ok(stats) => {
+                    resp.stats.insert(cid, stats);
+                }
+                Err(e) => {
+                    warn!(sl!(), "failed to get container stats"; "container" => &cid, "error" => format!("{:?}", e))
+                }
+            }
+        }
+
+        Ok(resp)
     }
 
     async fn pause_container(
@@ -670,7 +1131,7 @@ impl protocols::agent_ttrpc::AgentService for AgentService {
         })?;
 
         ctr.pause()
-            .map_err(|e| ttrpc_error(ttrpc::Code::INTERNAL, e.to_string()))?;
+            .map_err(|e| ttrpc_error(cgroup_error_code(&e), e.to_string()))?;
 
         Ok(Empty::new())
     }
@@ -693,7 +1154,7 @@ impl protocols::agent_ttrpc::AgentService for AgentService {
         })?;
 
         ctr.resume()
-            .map_err(|e| ttrpc_error(ttrpc::Code::INTERNAL, e.to_string()))?;
+            .map_err(|e| ttrpc_error(cgroup_error_code(&e), e.to_string()))?;
 
         Ok(Empty::new())
     }
@@ -818,15 +1279,11 @@ impl protocols::agent_ttrpc::AgentService for AgentService {
             )
         })?;
 
-        self.sandbox
-            .lock()
-            .await
-            .rtnl
-            .update_interface(&interface)
-            .await
-            .map_err(|e| {
-                ttrpc_error(ttrpc::Code::INTERNAL, format!("update interface: {:?}", e))
-            })?;
+        let mut s = self.sandbox.lock().await;
+        s.rtnl.update_interface(&interface).await.map_err(|e| {
+            ttrpc_error(ttrpc::Code::INTERNAL, format!("update interface: {:?}", e))
+        })?;
+        s.network.set_interface(interface.clone());
 
         Ok(interface)
     }
@@ -944,10 +1401,46 @@ impl protocols::agent_ttrpc::AgentService for AgentService {
     ) -> ttrpc::Result<Empty> {
         trace_rpc_call!(ctx, "create_sandbox", req);
 
+        let multi_sandbox = AGENT_CONFIG.read().await.multi_sandbox;
+        let cgroup_root = AGENT_CONFIG.read().await.cgroup_root.clone();
+        if multi_sandbox && req.sandbox_id.is_empty() {
+            return Err(ttrpc_error(
+                ttrpc::Code::INVALID_ARGUMENT,
+                "missing sandbox id".to_string(),
+            ));
+        }
+
         {
             let sandbox = self.sandbox.clone();
             let mut s = sandbox.lock().await;
 
+            if multi_sandbox {
+                s.add_logical_sandbox(&req.sandbox_id, &cgroup_root)
+                    .map_err(|e| ttrpc_error(ttrpc::Code::INTERNAL, e.to_string()))?;
+            }
+
+            // the pods packed into the guest after the first one share the
+            // guest setup, namespaces and DNS
+            if multi_sandbox && s.running {
+                // the storages another pod mounted already are shared
+                let (shared, storages): (Vec<Storage>, Vec<Storage>) = req
+                    .storages
+                    .iter()
+                    .cloned()
+                    .partition(|st| s.is_mounted(&st.mount_point));
+                drop(s);
+
+                let mut m = add_storages(sl!(), storages, self.sandbox.clone())
+                    .await
+                    .map_err(|e| ttrpc_error(ttrpc::Code::INTERNAL, e.to_string()))?;
+                m.extend(shared.into_iter().map(|st| st.mount_point));
+                if let Some(ls) = self.sandbox.lock().await.sandboxes.get_mut(&req.sandbox_id) {
+                    ls.mounts = m;
+                }
+
+                return Ok(Empty::new());
+            }
+
             let _ = fs::remove_dir_all(CONTAINER_BASE);
             let _ = fs::create_dir_all(CONTAINER_BASE);
 
@@ -963,8 +1456,12 @@ impl protocols::agent_ttrpc::AgentService for AgentService {
                 });
             }
 
-            if !req.sandbox_id.is_empty() {
+            if !req.sandbox_id.is_empty() && !multi_sandbox {
                 s.id = req.sandbox_id.clone();
+
+                let cg = SandboxCgroupManager::new(&cgroup_root, &s.id)
+                    .map_err(|e| ttrpc_error(ttrpc::Code::INTERNAL, e.to_string()))?;
+                s.cgroup = Some(cg);
             }
 
             for m in req.kernel_modules.iter() {
@@ -972,6 +1469,11 @@ impl protocols::agent_ttrpc::AgentService for AgentService {
                     .map_err(|e| ttrpc_error(ttrpc::Code::INTERNAL, e.to_string()))?;
             }
 
+            if let Some(c) = req.clock.as_ref() {
+                clock::set_clock(c)
+                    .map_err(|e| ttrpc_error(ttrpc::Code::INTERNAL, format!("{:?}", e)))?;
+            }
+
             s.setup_shared_namespaces()
                 .await
                 .map_err(|e| ttrpc_error(ttrpc::Code::INTERNAL, e.to_string()))?;
@@ -981,7 +1483,10 @@ impl protocols::agent_ttrpc::AgentService for AgentService {
             Ok(m) => {
                 let sandbox = self.sandbox.clone();
                 let mut s = sandbox.lock().await;
-                s.mounts = m
+                match s.sandboxes.get_mut(&req.sandbox_id) {
+                    Some(ls) => ls.mounts = m,
+                    None => s.mounts = m,
+                }
             }
             Err(e) => return Err(ttrpc_error(ttrpc::Code::INTERNAL, e.to_string())),
         };
@@ -1009,8 +1514,24 @@ impl protocols::agent_ttrpc::AgentService for AgentService {
     ) -> ttrpc::Result<Empty> {
         trace_rpc_call!(ctx, "destroy_sandbox", req);
 
+        if !req.sandbox_id.is_empty() && AGENT_CONFIG.read().await.multi_sandbox {
+            self.do_destroy_logical_sandbox(&req.sandbox_id)
+                .await
+                .map_err(|e| ttrpc_error(ttrpc::Code::INTERNAL, format!("{:?}", e)))?;
+
+            // the agent keeps serving the other pods
+            if !self.sandbox.lock().await.sandboxes.is_empty() {
+                return Ok(Empty::new());
+            }
+        }
+
+        // while the containers and the shared filesystem are still there
+        update_sandbox_metrics(&self.sandbox, &GetMetricsRequest::new()).await;
+        postmortem::snapshot("destroy");
+
         let s = Arc::clone(&self.sandbox);
         let mut sandbox = s.lock().await;
+
         // destroy all containers, clean up, notify agent to exit
         // etc.
         sandbox.destroy().await.unwrap();
@@ -1108,9 +1629,27 @@ impl protocols::agent_ttrpc::AgentService for AgentService {
         }
 
         // to get agent details
-        let detail = get_agent_details();
+        let config = AGENT_CONFIG.read().await;
+        let detail = get_agent_details(config.profile, config.confinement());
         resp.agent_details = SingularPtrField::some(detail);
 
+        // to get the rootfs integrity, left absent when it can't be told
+        match get_rootfs_integrity() {
+            Ok(integrity) => resp.rootfs_integrity = SingularPtrField::some(integrity),
+            Err(e) => {
+                warn!(sl!(), "fail to get rootfs integrity"; "error" => format!("{:?}", e));
+            }
+        }
+
+        // to get the cgroup mode and controllers
+        match get_cgroup_details() {
+            Ok(details) => resp.cgroup_details = SingularPtrField::some(details),
+            Err(e) => {
+                info!(sl!(), "fail to get cgroup details!");
+                return Err(ttrpc_error(ttrpc::Code::INTERNAL, e.to_string()));
+            }
+        }
+
         Ok(resp)
     }
 
@@ -1140,6 +1679,22 @@ impl protocols::agent_ttrpc::AgentService for AgentService {
         Ok(Empty::new())
     }
 
+    async fn set_guest_clock(
+        &self,
+        ctx: &TtrpcContext,
+        req: protocols::agent::SetGuestClockRequest,
+    ) -> ttrpc::Result<Empty> {
+        trace_rpc_call!(ctx, "set_guest_clock", req);
+
+        let c = req.clock.into_option().ok_or_else(|| {
+            ttrpc_error(ttrpc::Code::INVALID_ARGUMENT, "missing clock".to_string())
+        })?;
+
+        clock::set_clock(&c).map_err(|e| ttrpc_error(ttrpc::Code::INTERNAL, format!("{:?}", e)))?;
+
+        Ok(Empty::new())
+    }
+
     async fn copy_file(
         &self,
         ctx: &TtrpcContext,
@@ -1147,6 +1702,18 @@ impl protocols::agent_ttrpc::AgentService for AgentService {
     ) -> ttrpc::Result<Empty> {
         trace_rpc_call!(ctx, "copy_file", req);
 
+        // Files pushed by the host may contain secrets, only release them
+        // into a guest whose rootfs integrity is verified, if required.
+        if AGENT_CONFIG.read().await.require_verity_rootfs {
+            let verified = get_rootfs_integrity().map(|i| i.verified).unwrap_or(false);
+            if !verified {
+                return Err(ttrpc_error(
+                    ttrpc::Code::FAILED_PRECONDITION,
+                    "guest rootfs is not verity protected".to_string(),
+                ));
+            }
+        }
+
         do_copy_file(&req).map_err(|e| ttrpc_error(ttrpc::Code::INTERNAL, e.to_string()))?;
 
         Ok(Empty::new())
@@ -1155,18 +1722,18 @@ impl protocols::agent_ttrpc::AgentService for AgentService {
     async fn get_metrics(
         &self,
         ctx: &TtrpcContext,
-        req: protocols::agent::GetMetricsRequest,
+        req: GetMetricsRequest,
     ) -> ttrpc::Result<Metrics> {
         trace_rpc_call!(ctx, "get_metrics", req);
 
-        match get_metrics(&req) {
-            Err(e) => Err(ttrpc_error(ttrpc::Code::INTERNAL, e.to_string())),
-            Ok(s) => {
-                let mut metrics = Metrics::new();
-                metrics.set_metrics(s);
-                Ok(metrics)
-            }
-        }
+        check_metrics_enabled().await?;
+
+        metrics::check_request(&req)
+            .map_err(|e| ttrpc_error(ttrpc::Code::INVALID_ARGUMENT, e.to_string()))?;
+
+        update_sandbox_metrics(&self.sandbox, &req).await;
+
+        get_metrics(&req).map_err(|e| ttrpc_error(ttrpc::Code::INTERNAL, e.to_string()))
     }
 
     async fn get_oom_event(
@@ -1176,33 +1743,626 @@ impl protocols::agent_ttrpc::AgentService for AgentService {
     ) -> ttrpc::Result<OOMEvent> {
         let sandbox = self.sandbox.clone();
         let s = sandbox.lock().await;
-        let event_rx = &s.event_rx.clone();
+        let event_rx = &s.oom_event_rx.clone();
         let mut event_rx = event_rx.lock().await;
         drop(s);
         drop(sandbox);
 
-        if let Some(container_id) = event_rx.recv().await {
-            info!(sl!(), "get_oom_event return {}", &container_id);
+        while let Some(event) = recv_cgroup_event(&mut event_rx).await {
+            if event.field_type != CgroupEventType::OOM {
+                continue;
+            }
+
+            info!(sl!(), "get_oom_event return {}", &event.container_id);
 
             let mut resp = OOMEvent::new();
-            resp.container_id = container_id;
+            resp.container_id = event.container_id;
 
             return Ok(resp);
         }
 
         Err(ttrpc_error(ttrpc::Code::INTERNAL, ""))
     }
-}
-
-#[derive(Clone)]
-struct HealthService;
 
-#[async_trait]
-impl protocols::health_ttrpc::Health for HealthService {
-    async fn check(
+    async fn get_cgroup_event(
         &self,
         _ctx: &TtrpcContext,
-        _req: protocols::health::CheckRequest,
+        _req: protocols::agent::GetCgroupEventRequest,
+    ) -> ttrpc::Result<CgroupEvent> {
+        let sandbox = self.sandbox.clone();
+        let s = sandbox.lock().await;
+        let event_rx = &s.event_rx.clone();
+        let mut event_rx = event_rx.lock().await;
+        drop(s);
+        drop(sandbox);
+
+        if let Some(event) = recv_cgroup_event(&mut event_rx).await {
+            info!(sl!(), "get_cgroup_event return {:?}", &event);
+            return Ok(event);
+        }
+
+        Err(ttrpc_error(ttrpc::Code::INTERNAL, ""))
+    }
+
+    async fn add_probe(
+        &self,
+        ctx: &TtrpcContext,
+        req: protocols::agent::AddProbeRequest,
+    ) -> ttrpc::Result<Empty> {
+        trace_rpc_call!(ctx, "add_probe", req);
+
+        let probe = req.probe.into_option().ok_or_else(|| {
+            ttrpc_error(ttrpc::Code::INVALID_ARGUMENT, "missing probe".to_string())
+        })?;
+
+        let mut s = self.sandbox.lock().await;
+        let pid = s
+            .get_container(&probe.container_id)
+            .ok_or_else(|| {
+                ttrpc_error(
+                    ttrpc::Code::INVALID_ARGUMENT,
+                    "invalid container id".to_string(),
+                )
+            })?
+            .init_process_pid;
+
+        if pid <= 0 {
+            return Err(ttrpc_error(
+                ttrpc::Code::FAILED_PRECONDITION,
+                "container not started".to_string(),
+            ));
+        }
+
+        let logger = s.logger.clone();
+        s.probes
+            .add(&logger, probe, self.sandbox.clone())
+            .map_err(|e| ttrpc_error(ttrpc::Code::INVALID_ARGUMENT, e.to_string()))?;
+
+        Ok(Empty::new())
+    }
+
+    async fn remove_probe(
+        &self,
+        ctx: &TtrpcContext,
+        req: protocols::agent::RemoveProbeRequest,
+    ) -> ttrpc::Result<Empty> {
+        trace_rpc_call!(ctx, "remove_probe", req);
+
+        let mut s = self.sandbox.lock().await;
+        s.probes.remove(&req.id);
+
+        Ok(Empty::new())
+    }
+
+    async fn get_probe_event(
+        &self,
+        _ctx: &TtrpcContext,
+        _req: protocols::agent::GetProbeEventRequest,
+    ) -> ttrpc::Result<ProbeEvent> {
+        let s = self.sandbox.lock().await;
+        let event_rx = s.probes.event_rx.clone();
+        drop(s);
+
+        let mut event_rx = event_rx.lock().await;
+        if let Some(event) = event_rx.recv().await {
+            info!(sl!(), "get_probe_event return {:?}", &event);
+            return Ok(event);
+        }
+
+        Err(ttrpc_error(ttrpc::Code::INTERNAL, ""))
+    }
+
+    async fn get_usage_history(
+        &self,
+        ctx: &TtrpcContext,
+        req: protocols::agent::GetUsageHistoryRequest,
+    ) -> ttrpc::Result<UsageHistory> {
+        trace_rpc_call!(ctx, "get_usage_history", req);
+
+        let s = self.sandbox.lock().await;
+        if !s.containers.contains_key(&req.container_id) {
+            return Err(ttrpc_error(
+                ttrpc::Code::INVALID_ARGUMENT,
+                "invalid container id".to_string(),
+            ));
+        }
+
+        let mut resp = UsageHistory::new();
+        resp.samples = RepeatedField::from_vec(s.usage_history.get(&req.container_id));
+
+        Ok(resp)
+    }
+
+    async fn list_container_mounts(
+        &self,
+        ctx: &TtrpcContext,
+        req: protocols::agent::ListContainerMountsRequest,
+    ) -> ttrpc::Result<ContainerMounts> {
+        trace_rpc_call!(ctx, "list_container_mounts", req);
+
+        let mut s = self.sandbox.lock().await;
+        let ctr = s.get_container(&req.container_id).ok_or_else(|| {
+            ttrpc_error(
+                ttrpc::Code::INVALID_ARGUMENT,
+                "invalid container id".to_string(),
+            )
+        })?;
+
+        if ctr.init_process_pid <= 0 {
+            return Err(ttrpc_error(
+                ttrpc::Code::FAILED_PRECONDITION,
+                "container has no process".to_string(),
+            ));
+        }
+
+        let mounts = mountinfo::get_container_mounts(ctr.init_process_pid)
+            .map_err(|e| ttrpc_error(ttrpc::Code::INTERNAL, format!("{:?}", e)))?;
+
+        let mut resp = ContainerMounts::new();
+        resp.mounts = RepeatedField::from_vec(mounts);
+
+        Ok(resp)
+    }
+
+    async fn add_fs_watch(
+        &self,
+        ctx: &TtrpcContext,
+        req: protocols::agent::AddFsWatchRequest,
+    ) -> ttrpc::Result<Empty> {
+        trace_rpc_call!(ctx, "add_fs_watch", req);
+
+        let mut s = self.sandbox.lock().await;
+        let pid = s
+            .get_container(&req.container_id)
+            .ok_or_else(|| {
+                ttrpc_error(
+                    ttrpc::Code::INVALID_ARGUMENT,
+                    "invalid container id".to_string(),
+                )
+            })?
+            .init_process_pid;
+
+        if pid <= 0 {
+            return Err(ttrpc_error(
+                ttrpc::Code::FAILED_PRECONDITION,
+                "container not started".to_string(),
+            ));
+        }
+
+        let logger = s.logger.clone();
+        s.fs_watches
+            .add(&logger, &req, pid)
+            .map_err(|e| ttrpc_error(ttrpc::Code::INVALID_ARGUMENT, format!("{:?}", e)))?;
+
+        Ok(Empty::new())
+    }
+
+    async fn remove_fs_watch(
+        &self,
+        ctx: &TtrpcContext,
+        req: protocols::agent::RemoveFsWatchRequest,
+    ) -> ttrpc::Result<Empty> {
+        trace_rpc_call!(ctx, "remove_fs_watch", req);
+
+        let mut s = self.sandbox.lock().await;
+        s.fs_watches.remove(&req.id);
+
+        Ok(Empty::new())
+    }
+
+    async fn get_fs_event(
+        &self,
+        _ctx: &TtrpcContext,
+        _req: protocols::agent::GetFsEventRequest,
+    ) -> ttrpc::Result<FsEvent> {
+        let s = self.sandbox.lock().await;
+        let event_rx = s.fs_watches.event_rx.clone();
+        drop(s);
+
+        let mut event_rx = event_rx.lock().await;
+        if let Some(event) = event_rx.recv().await {
+            return Ok(event);
+        }
+
+        Err(ttrpc_error(ttrpc::Code::INTERNAL, ""))
+    }
+
+    async fn renice_container(
+        &self,
+        ctx: &TtrpcContext,
+        req: protocols::agent::ReniceContainerRequest,
+    ) -> ttrpc::Result<Empty> {
+        trace_rpc_call!(ctx, "renice_container", req);
+        let cid = req.container_id.clone();
+
+        let s = Arc::clone(&self.sandbox);
+        let mut sandbox = s.lock().await;
+
+        let ctr = sandbox.get_container(&cid).ok_or_else(|| {
+            ttrpc_error(
+                ttrpc::Code::INVALID_ARGUMENT,
+                "invalid container id".to_string(),
+            )
+        })?;
+
+        rustjail::sched::check_nice(req.nice)
+            .map_err(|e| ttrpc_error(ttrpc::Code::INVALID_ARGUMENT, e.to_string()))?;
+
+        ctr.renice(req.nice)
+            .map_err(|e| ttrpc_error(ttrpc::Code::INTERNAL, format!("{:?}", e)))?;
+
+        Ok(Empty::new())
+    }
+
+    async fn validate_resources(
+        &self,
+        ctx: &TtrpcContext,
+        req: protocols::agent::UpdateContainerRequest,
+    ) -> ttrpc::Result<ResourcesValidation> {
+        trace_rpc_call!(ctx, "validate_resources", req);
+        let cid = req.container_id.clone();
+
+        let s = Arc::clone(&self.sandbox);
+        let mut sandbox = s.lock().await;
+
+        let ctr = sandbox.get_container(&cid).ok_or_else(|| {
+            ttrpc_error(
+                ttrpc::Code::INVALID_ARGUMENT,
+                "invalid container id".to_string(),
+            )
+        })?;
+
+        let mut resp = ResourcesValidation::new();
+        let (res, cgm) = match (req.resources.as_ref(), ctr.cgroup_manager.as_ref()) {
+            (Some(res), Some(cgm)) => (res, cgm),
+            _ => return Ok(resp),
+        };
+
+        // checked as an update, which is what the resources would be applied by
+        let oci_res = rustjail::resources_grpc_to_oci(res);
+        let unapplied = cgm
+            .validate(&oci_res, true)
+            .map_err(|e| ttrpc_error(ttrpc::Code::INVALID_ARGUMENT, format!("{:?}", e)))?;
+
+        for u in unapplied {
+            let mut w = Warning::new();
+            w.source = "ValidateResources".to_string();
+            w.container_id = cid.clone();
+            w.message = u;
+            resp.warnings.push(w);
+        }
+
+        Ok(resp)
+    }
+
+    async fn add_device_rule(
+        &self,
+        ctx: &TtrpcContext,
+        req: protocols::agent::DeviceRuleRequest,
+    ) -> ttrpc::Result<Empty> {
+        trace_rpc_call!(ctx, "add_device_rule", req);
+        match self.do_update_device_rule(req, true).await {
+            Err(e) => Err(ttrpc_error(ttrpc::Code::INTERNAL, format!("{:?}", e))),
+            Ok(_) => Ok(Empty::new()),
+        }
+    }
+
+    async fn remove_device_rule(
+        &self,
+        ctx: &TtrpcContext,
+        req: protocols::agent::DeviceRuleRequest,
+    ) -> ttrpc::Result<Empty> {
+        trace_rpc_call!(ctx, "remove_device_rule", req);
+        match self.do_update_device_rule(req, false).await {
+            Err(e) => Err(ttrpc_error(ttrpc::Code::INTERNAL, format!("{:?}", e))),
+            Ok(_) => Ok(Empty::new()),
+        }
+    }
+
+    async fn list_device_rules(
+        &self,
+        ctx: &TtrpcContext,
+        req: protocols::agent::ListDeviceRulesRequest,
+    ) -> ttrpc::Result<protocols::agent::DeviceRules> {
+        trace_rpc_call!(ctx, "list_device_rules", req);
+
+        let mut s = self.sandbox.lock().await;
+        let ctr = s.get_container(&req.container_id).ok_or_else(|| {
+            ttrpc_error(
+                ttrpc::Code::INVALID_ARGUMENT,
+                "invalid container id".to_string(),
+            )
+        })?;
+        let cgm = ctr.cgroup_manager.as_ref().ok_or_else(|| {
+            ttrpc_error(
+                ttrpc::Code::FAILED_PRECONDITION,
+                "container has no cgroup manager".to_string(),
+            )
+        })?;
+
+        let rules = cgm
+            .list_device_rules()
+            .map_err(|e| ttrpc_error(cgroup_error_code(&e), format!("{:?}", e)))?;
+
+        let mut resp = protocols::agent::DeviceRules::new();
+        resp.rules = RepeatedField::from_vec(
+            rules
+                .rules
+                .iter()
+                .map(rustjail::device_cgroup_oci_to_grpc)
+                .collect(),
+        );
+        resp.verified = rules.verified;
+
+        Ok(resp)
+    }
+
+    async fn set_metrics_push(
+        &self,
+        ctx: &TtrpcContext,
+        req: protocols::agent::SetMetricsPushRequest,
+    ) -> ttrpc::Result<Empty> {
+        trace_rpc_call!(ctx, "set_metrics_push", req);
+
+        check_metrics_enabled().await?;
+
+        let metrics_req = req.get_request().clone();
+        metrics::check_request(&metrics_req)
+            .map_err(|e| ttrpc_error(ttrpc::Code::INVALID_ARGUMENT, e.to_string()))?;
+
+        let max_pending = match req.max_pending as usize {
+            0 => metrics_push::DEFAULT_MAX_PENDING,
+            n if n > metrics_push::MAX_PENDING_LIMIT => {
+                return Err(ttrpc_error(
+                    ttrpc::Code::INVALID_ARGUMENT,
+                    format!(
+                        "at most {} pending samples, not {}",
+                        metrics_push::MAX_PENDING_LIMIT,
+                        n
+                    ),
+                ));
+            }
+            n => n,
+        };
+
+        let mut s = self.sandbox.lock().await;
+        if req.period_seconds == 0 {
+            s.metrics_push.stop();
+        } else {
+            s.metrics_push.start(
+                self.sandbox.clone(),
+                Duration::from_secs(req.period_seconds as u64),
+                max_pending,
+                metrics_req,
+            );
+        }
+
+        Ok(Empty::new())
+    }
+
+    async fn get_metrics_push(
+        &self,
+        _ctx: &TtrpcContext,
+        _req: protocols::agent::GetMetricsPushRequest,
+    ) -> ttrpc::Result<MetricsPush> {
+        let queue = self.sandbox.lock().await.metrics_push.queue();
+
+        queue.wait().await.ok_or_else(|| {
+            ttrpc_error(
+                ttrpc::Code::FAILED_PRECONDITION,
+                "the metrics push isn't set up",
+            )
+        })
+    }
+
+    async fn get_warning(
+        &self,
+        _ctx: &TtrpcContext,
+        _req: protocols::agent::GetWarningRequest,
+    ) -> ttrpc::Result<Warning> {
+        let s = self.sandbox.lock().await;
+        let rx = s.warnings.rx.clone();
+        drop(s);
+
+        let mut rx = rx.lock().await;
+        if let Some(w) = rx.recv().await {
+            return Ok(w);
+        }
+
+        Err(ttrpc_error(ttrpc::Code::INTERNAL, ""))
+    }
+
+    async fn get_boot_logs(
+        &self,
+        ctx: &TtrpcContext,
+        req: protocols::agent::GetBootLogsRequest,
+    ) -> ttrpc::Result<BootLogs> {
+        trace_rpc_call!(ctx, "get_boot_logs", req);
+
+        let s = self.sandbox.lock().await;
+        let data = s.boot_logs.as_ref().ok_or_else(|| {
+            ttrpc_error(
+                ttrpc::Code::FAILED_PRECONDITION,
+                "boot log capture is disabled".to_string(),
+            )
+        })?;
+
+        let (data, eof) = bootlog::chunk(data, req.offset)
+            .map_err(|e| ttrpc_error(ttrpc::Code::INVALID_ARGUMENT, e.to_string()))?;
+
+        let mut resp = BootLogs::new();
+        resp.data = data.to_vec();
+        resp.eof = eof;
+
+        Ok(resp)
+    }
+
+    async fn dump_process_memory(
+        &self,
+        ctx: &TtrpcContext,
+        req: protocols::agent::DumpProcessMemoryRequest,
+    ) -> ttrpc::Result<MemoryDumpChunk> {
+        trace_rpc_call!(ctx, "dump_process_memory", req);
+
+        let max_size = AGENT_CONFIG.read().await.memory_dump_max_size;
+        if max_size == 0 {
+            return Err(ttrpc_error(
+                ttrpc::Code::PERMISSION_DENIED,
+                "memory dumps are disabled".to_string(),
+            ));
+        }
+
+        let cid = req.container_id.as_str();
+        let eid = req.exec_id.as_str();
+
+        // a new dump is taken at offset 0, without holding the sandbox lock
+        // while the memory is read
+        if req.offset == 0 {
+            let mut s = self.sandbox.lock().await;
+            let pid = find_process(&mut s, cid, eid, false)
+                .map_err(|e| ttrpc_error(ttrpc::Code::INVALID_ARGUMENT, e.to_string()))?
+                .pid;
+            s.memory_dump = None;
+            drop(s);
+
+            let (c, e) = (cid.to_string(), eid.to_string());
+            let dump =
+                tokio::task::spawn_blocking(move || memdump::dump(&c, &e, pid, max_size as u64))
+                    .await
+                    .map_err(|e| ttrpc_error(ttrpc::Code::INTERNAL, e.to_string()))?
+                    .map_err(|e| ttrpc_error(ttrpc::Code::INTERNAL, format!("{:?}", e)))?;
+
+            self.sandbox.lock().await.memory_dump = Some(dump);
+            expire_memory_dump(self.sandbox.clone());
+        }
+
+        let mut s = self.sandbox.lock().await;
+        let dump = s
+            .memory_dump
+            .as_mut()
+            .filter(|d| d.container_id == cid && d.exec_id == eid)
+            .ok_or_else(|| {
+                ttrpc_error(
+                    ttrpc::Code::FAILED_PRECONDITION,
+                    "no memory dump of the process".to_string(),
+                )
+            })?;
+
+        let truncated = dump.truncated;
+        let (data, eof) = match dump.chunk(req.offset) {
+            Ok((data, eof)) => (data.to_vec(), eof),
+            Err(e) => {
+                // the transfer can't go on
+                s.memory_dump = None;
+                return Err(ttrpc_error(ttrpc::Code::INVALID_ARGUMENT, e.to_string()));
+            }
+        };
+
+        let mut resp = MemoryDumpChunk::new();
+        resp.data = data;
+        resp.eof = eof;
+        resp.truncated = truncated;
+
+        if eof {
+            s.memory_dump = None;
+        }
+
+        Ok(resp)
+    }
+
+    async fn negotiate_api_version(
+        &self,
+        ctx: &TtrpcContext,
+        req: protocols::agent::NegotiateAPIVersionRequest,
+    ) -> ttrpc::Result<NegotiateAPIVersionResponse> {
+        trace_rpc_call!(ctx, "negotiate_api_version", req);
+
+        let resp = protocols::version::negotiate(&req)
+            .map_err(|e| ttrpc_error(ttrpc::Code::FAILED_PRECONDITION, e))?;
+
+        info!(sl!(), "negotiated API version"; "version" => format!("{:?}", resp.get_version()), "features" => format!("{:?}", resp.get_features()));
+
+        Ok(resp)
+    }
+
+    async fn allocate_vsock_port(
+        &self,
+        ctx: &TtrpcContext,
+        req: protocols::agent::AllocateVsockPortRequest,
+    ) -> ttrpc::Result<VsockPort> {
+        trace_rpc_call!(ctx, "allocate_vsock_port", req);
+
+        if req.service.is_empty() {
+            return Err(ttrpc_error(
+                ttrpc::Code::INVALID_ARGUMENT,
+                "empty vsock service name".to_string(),
+            ));
+        }
+
+        let mut s = self.sandbox.lock().await;
+        let port = s
+            .vsock_ports
+            .allocate(&req.service)
+            .map_err(|e| ttrpc_error(ttrpc::Code::RESOURCE_EXHAUSTED, e.to_string()))?;
+
+        info!(sl!(), "vsock port allocated"; "service" => &req.service, "port" => port);
+
+        let mut resp = VsockPort::new();
+        resp.service = req.service;
+        resp.port = port;
+
+        Ok(resp)
+    }
+
+    async fn release_vsock_port(
+        &self,
+        ctx: &TtrpcContext,
+        req: protocols::agent::ReleaseVsockPortRequest,
+    ) -> ttrpc::Result<Empty> {
+        trace_rpc_call!(ctx, "release_vsock_port", req);
+
+        let mut s = self.sandbox.lock().await;
+        s.vsock_ports.release(&req.service);
+
+        Ok(Empty::new())
+    }
+
+    async fn list_vsock_ports(
+        &self,
+        ctx: &TtrpcContext,
+        req: protocols::agent::ListVsockPortsRequest,
+    ) -> ttrpc::Result<VsockPorts> {
+        trace_rpc_call!(ctx, "list_vsock_ports", req);
+
+        let s = self.sandbox.lock().await;
+        let ports = s
+            .vsock_ports
+            .list()
+            .into_iter()
+            .map(|(service, port)| {
+                let mut p = VsockPort::new();
+                p.service = service;
+                p.port = port;
+                p
+            })
+            .collect();
+
+        let mut resp = VsockPorts::new();
+        resp.ports = RepeatedField::from_vec(ports);
+
+        Ok(resp)
+    }
+}
+
+#[derive(Clone)]
+struct HealthService;
+
+#[async_trait]
+impl protocols::health_ttrpc::Health for HealthService {
+    async fn check(
+        &self,
+        _ctx: &TtrpcContext,
+        _req: protocols::health::CheckRequest,
     ) -> ttrpc::Result<HealthCheckResponse> {
         let mut resp = HealthCheckResponse::new();
         resp.set_status(HealthCheckResponse_ServingStatus::SERVING);
@@ -1272,7 +2432,7 @@ fn get_memory_info(block_size: bool, hotplug: bool) -> Result<(u64, bool)> {
     Ok((size, plug))
 }
 
-fn get_agent_details() -> AgentDetails {
+fn get_agent_details(profile: Profile, confinement: Confinement) -> AgentDetails {
     let mut detail = AgentDetails::new();
 
     detail.set_version(AGENT_VERSION.to_string());
@@ -1288,17 +2448,36 @@ fn get_agent_details() -> AgentDetails {
             .collect(),
     );
 
+    detail.set_profile(profile.as_str().to_string());
+    detail.disabled_subsystems = profile
+        .disabled_subsystems()
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    detail.set_confinement(confinement.as_str().to_string());
+
     detail
 }
 
-async fn read_stream(reader: Arc<Mutex<ReadHalf<PipeStream>>>, l: usize) -> Result<Vec<u8>> {
-    let mut content = vec![0u8; l];
+fn get_rootfs_integrity() -> Result<RootfsIntegrity> {
+    let i = verity::get_rootfs_integrity(PROC_CMDLINE, PROC_MOUNTINFO)?;
+
+    let mut integrity = RootfsIntegrity::new();
+    integrity.verified = i.verified;
+    integrity.device = i.device;
+    integrity.hash_algorithm = i.hash_algorithm;
+    integrity.root_hash = i.root_hash;
 
-    let mut reader = reader.lock().await;
-    let len = reader.read(&mut content).await?;
-    content.resize(len, 0);
+    Ok(integrity)
+}
+
+async fn read_stream(
+    reader: Arc<Mutex<SizedReader<ReadHalf<PipeStream>>>>,
+    l: usize,
+) -> Result<Vec<u8>> {
+    let content = reader.lock().await.read_chunk(l).await?;
 
-    if len == 0 {
+    if content.is_empty() {
         return Err(anyhow!("read meet eof"));
     }
 
@@ -1325,7 +2504,416 @@ fn find_process<'a>(
     ctr.get_process(eid).map_err(|_| anyhow!("Invalid exec id"))
 }
 
-pub fn start(s: Arc<Mutex<Sandbox>>, server_address: &str) -> TtrpcServer {
+// release_execs releases the exec fifos of the containers in order, with
+// `release`. The containers after a failure are aborted, their fifo being
+// closed. It returns the containers started, along with the error if any.
+fn release_execs<'a, I, F>(prepared: I, mut release: F) -> (Vec<String>, Result<()>)
+where
+    I: IntoIterator<Item = (&'a String, RawFd)>,
+    F: FnMut(&str, RawFd) -> Result<()>,
+{
+    let mut started = vec![];
+    let mut res = Ok(());
+
+    for (cid, fd) in prepared {
+        if res.is_err() {
+            let _ = unistd::close(fd);
+            continue;
+        }
+
+        res = release(cid, fd);
+        if res.is_ok() {
+            started.push(cid.clone());
+        }
+    }
+
+    (started, res)
+}
+
+// start_event_monitors starts the oom, memory pressure and pids limit event
+// loops of a started container.
+async fn start_event_monitors(
+    sandbox: Arc<Mutex<Sandbox>>,
+    s: &mut Sandbox,
+    cid: &str,
+) -> Result<()> {
+    let ctr = s
+        .get_container(cid)
+        .ok_or_else(|| anyhow!("Invalid container id"))?;
+    let oom_policy = ctr.oom_policy;
+    let pids_path = ctr
+        .cgroup_manager
+        .as_ref()
+        .and_then(|cgm| cgm.get_cg_path("pids"));
+    let cg_path = match ctr
+        .cgroup_manager
+        .as_ref()
+        .and_then(|cgm| cgm.get_cg_path("memory"))
+    {
+        Some(cg_path) => cg_path,
+        None => return Ok(()),
+    };
+
+    metrics::track_container(cid);
+
+    let mut oom = notifier::notify_oom(cid, cg_path.clone()).await?;
+    enforce_oom_policy(sandbox, cid, oom_policy, oom.subscribe());
+    s.run_cgroup_event_monitor(oom.subscribe(), cid.to_string(), CgroupEventType::OOM)
+        .await;
+    oom.start();
+    let mut watches = vec![oom];
+    let mut notifiers = vec![notifier::WATCH_OOM];
+
+    // memory pressure events are only informative, so the container still
+    // starts if they can't be watched
+    match notifier::notify_memory_pressure(cid, cg_path).await {
+        Ok(mut w) => {
+            s.run_cgroup_event_monitor(
+                w.subscribe(),
+                cid.to_string(),
+                CgroupEventType::MEMORY_PRESSURE,
+            )
+            .await;
+            w.start();
+            watches.push(w);
+            notifiers.push(notifier::WATCH_MEMORY_PRESSURE);
+        }
+        Err(e) => s.warnings.add(
+            "StartContainer",
+            cid,
+            &format!("failed to watch memory pressure: {:?}", e),
+        ),
+    }
+
+    // as are the pids limit ones
+    if let Some(pids_path) = pids_path {
+        match notifier::notify_pids_max(cid, pids_path).await {
+            Ok(mut w) => {
+                s.run_cgroup_event_monitor(
+                    w.subscribe(),
+                    cid.to_string(),
+                    CgroupEventType::PIDS_MAX,
+                )
+                .await;
+                w.start();
+                watches.push(w);
+                notifiers.push(notifier::WATCH_PIDS_MAX);
+            }
+            Err(e) => s.warnings.add(
+                "StartContainer",
+                cid,
+                &format!("failed to watch the pids limit: {:?}", e),
+            ),
+        }
+    }
+
+    // stopped when the container is removed
+    s.cgroup_watches.insert(cid.to_string(), watches);
+
+    // recorded to be watched again after an agent restart
+    if let Some(ctr) = s.get_container(cid) {
+        if let Some(cgm) = ctr.cgroup_manager.as_ref().and_then(|c| c.as_fs()) {
+            for n in notifiers {
+                cgm.add_notifier(n);
+            }
+        }
+        save_cgroup_state(ctr);
+    }
+
+    Ok(())
+}
+
+// restore_cgroups restores the cgroup managers of the containers the agent
+// ran before it was restarted, watching their cgroup events again. Their
+// OOM policies aren't enforced anymore, the events being only reported.
+pub async fn restore_cgroups(sandbox: &Arc<Mutex<Sandbox>>) {
+    let cids = match FsManager::saved_containers() {
+        Ok(cids) => cids,
+        Err(e) => {
+            warn!(sl!(), "failed to list the cgroup states"; "error" => format!("{:?}", e));
+            return;
+        }
+    };
+
+    let mut s = sandbox.lock().await;
+    for cid in cids {
+        let cgm = match FsManager::restore(&cid) {
+            Ok(cgm) => cgm,
+            Err(e) => {
+                // the container is gone
+                warn!(sl!(), "failed to restore a cgroup"; "container" => &cid, "error" => format!("{:?}", e));
+                let _ = FsManager::remove_state(&cid);
+                continue;
+            }
+        };
+
+        let mut watches = vec![];
+        for name in cgm.notifiers() {
+            let (path, event_type) = match name.as_str() {
+                notifier::WATCH_OOM => (cgm.get_cg_path("memory"), CgroupEventType::OOM),
+                notifier::WATCH_MEMORY_PRESSURE => {
+                    (cgm.get_cg_path("memory"), CgroupEventType::MEMORY_PRESSURE)
+                }
+                notifier::WATCH_PIDS_MAX => (cgm.get_cg_path("pids"), CgroupEventType::PIDS_MAX),
+                _ => continue,
+            };
+            let path = match path {
+                Some(path) => path,
+                None => continue,
+            };
+
+            let watch = match name.as_str() {
+                notifier::WATCH_OOM => notifier::notify_oom(&cid, path).await,
+                notifier::WATCH_MEMORY_PRESSURE => {
+                    notifier::notify_memory_pressure(&cid, path).await
+                }
+                _ => notifier::notify_pids_max(&cid, path).await,
+            };
+            match watch {
+                Ok(mut w) => {
+                    s.run_cgroup_event_monitor(w.subscribe(), cid.clone(), event_type)
+                        .await;
+                    w.start();
+                    watches.push(w);
+                }
+                Err(e) => {
+                    warn!(sl!(), "failed to watch a restored cgroup"; "container" => &cid, "event" => &name, "error" => format!("{:?}", e))
+                }
+            }
+        }
+
+        info!(sl!(), "restored the cgroup"; "container" => &cid);
+        s.cgroup_watches.insert(cid.clone(), watches);
+        s.restored_cgroups.insert(cid, Box::new(cgm));
+    }
+}
+
+// cgroup_error_code maps the cgroup errors to the ttrpc status codes, so
+// that the runtime can tell e.g. a paused container or an invalid resource
+// apart from the other failures, which are internal. UNIMPLEMENTED and
+// NOT_FOUND are left out, the runtime reading them as an older agent and
+// as a missing container.
+fn cgroup_error_code(e: &anyhow::Error) -> ttrpc::Code {
+    match CgroupError::find(e) {
+        Some(CgroupError::InvalidValue { .. }) => ttrpc::Code::INVALID_ARGUMENT,
+        Some(CgroupError::NotSupported)
+        | Some(CgroupError::KernelUnsupported { .. })
+        | Some(CgroupError::ControllerMissing { .. })
+        | Some(CgroupError::Frozen { .. }) => ttrpc::Code::FAILED_PRECONDITION,
+        _ => ttrpc::Code::INTERNAL,
+    }
+}
+
+// save_cgroup_state persists the cgroup manager of a container, a failure
+// only preventing its restore after an agent restart.
+fn save_cgroup_state(ctr: &LinuxContainer) {
+    if let Some(cgm) = ctr.cgroup_manager.as_ref().and_then(|c| c.as_fs()) {
+        if let Err(e) = cgm.save(&ctr.id) {
+            warn!(sl!(), "failed to save the cgroup state"; "container" => &ctr.id, "error" => format!("{:?}", e));
+        }
+    }
+}
+
+// expire_memory_dump releases the memory dump of the sandbox once no chunk
+// of it was read for a while, the host having given up on the transfer.
+fn expire_memory_dump(sandbox: Arc<Mutex<Sandbox>>) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(memdump::IDLE_TIMEOUT).await;
+
+            let mut s = sandbox.lock().await;
+            match s.memory_dump.as_ref() {
+                Some(d) if d.idle() => {
+                    warn!(sl!(), "dropping an abandoned memory dump"; "container" => &d.container_id);
+                    s.memory_dump = None;
+                    return;
+                }
+                Some(_) => {}
+                None => return,
+            }
+        }
+    });
+}
+
+// enforce_oom_policy applies the OOM policy of a container on each of the
+// OOM events of `events`. The events are reported to the host by another
+// subscriber, which doesn't wait for the sandbox lock, so that the host
+// knows why the container is frozen or gone.
+fn enforce_oom_policy(
+    sandbox: Arc<Mutex<Sandbox>>,
+    cid: &str,
+    policy: OomPolicy,
+    mut events: broadcast::Receiver<String>,
+) {
+    if policy == OomPolicy::Notify {
+        return;
+    }
+
+    let cid = cid.to_string();
+
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            }
+
+            if !sandbox.lock().await.containers.contains_key(&cid) {
+                return;
+            }
+
+            info!(sl!(), "enforcing OOM policy"; "container" => &cid, "policy" => format!("{:?}", policy));
+
+            let result = match policy {
+                OomPolicy::Freeze => match sandbox.lock().await.get_container(&cid) {
+                    Some(ctr) => ctr.pause(),
+                    None => return,
+                },
+                // the sandbox is unlocked meanwhile
+                OomPolicy::Restart => {
+                    terminate(
+                        |sig| signal_container(&sandbox, &cid, sig),
+                        || container_running(&sandbox, &cid),
+                        OOM_RESTART_GRACE,
+                    )
+                    .await
+                }
+                OomPolicy::Notify => Ok(()),
+            };
+
+            if let Err(e) = result {
+                warn!(sl!(), "failed to enforce OOM policy"; "container" => &cid, "error" => format!("{:?}", e));
+            }
+        }
+    });
+}
+
+// terminate asks the processes to exit with SIGTERM, and kills them with
+// SIGKILL if any is still running after `grace`.
+async fn terminate<S, SF, R, RF>(signal: S, running: R, grace: Duration) -> Result<()>
+where
+    S: Fn(Signal) -> SF,
+    SF: Future<Output = Result<()>>,
+    R: Fn() -> RF,
+    RF: Future<Output = bool>,
+{
+    if signal(Signal::SIGTERM).await.is_ok() {
+        let deadline = Instant::now() + grace;
+        while Instant::now() < deadline {
+            if !running().await {
+                return Ok(());
+            }
+            tokio::time::sleep(TERMINATE_POLL_INTERVAL).await;
+        }
+    }
+
+    if !running().await {
+        return Ok(());
+    }
+
+    signal(Signal::SIGKILL).await
+}
+
+async fn signal_container(sandbox: &Arc<Mutex<Sandbox>>, cid: &str, sig: Signal) -> Result<()> {
+    sandbox
+        .lock()
+        .await
+        .get_container(cid)
+        .ok_or_else(|| anyhow!("container {} is gone", cid))?
+        .signal(sig, true)
+}
+
+// container_running tells whether processes are left in the cgroup of the
+// container.
+async fn container_running(sandbox: &Arc<Mutex<Sandbox>>, cid: &str) -> bool {
+    let s = sandbox.lock().await;
+
+    s.containers
+        .get(cid)
+        .and_then(|ctr| ctr.cgroup_manager.as_ref())
+        .and_then(|cgm| cgm.get_procs().ok())
+        .map_or(false, |pids| !pids.is_empty())
+}
+
+// destroy_cgroup removes the cgroup of a destroyed container, out of the
+// sandbox lock as the removal waits for the killed processes to exit.
+async fn destroy_cgroup(cgm: Option<Box<dyn CgroupManager>>) -> Result<()> {
+    let mut cgm = match cgm {
+        Some(cgm) => cgm,
+        None => return Ok(()),
+    };
+
+    tokio::task::spawn_blocking(move || cgm.destroy())
+        .await
+        .map_err(|e| anyhow!(e))?
+        .context("destroy cgroups")
+}
+
+// check_metrics_enabled fails if the metrics are disabled by the profile.
+async fn check_metrics_enabled() -> ttrpc::Result<()> {
+    let profile = AGENT_CONFIG.read().await.profile;
+    if !profile.metrics() {
+        return Err(ttrpc_error(
+            ttrpc::Code::UNIMPLEMENTED,
+            format!("metrics are disabled by the {} profile", profile.as_str()),
+        ));
+    }
+
+    Ok(())
+}
+
+// update_sandbox_metrics updates the metrics read at the scrape, which
+// the request asks for: the kernel averages the pressure already, and the
+// filesystems usage is only needed then. The files are read out of the
+// sandbox lock, so that a slow read doesn't block the other RPCs.
+#[instrument(skip(sandbox))]
+pub async fn update_sandbox_metrics(sandbox: &Arc<Mutex<Sandbox>>, req: &GetMetricsRequest) {
+    let s = sandbox.lock().await;
+
+    let samplers: Vec<(String, Sampler)> =
+        if metrics::collector_selected(req, metrics::COLLECTOR_CONTAINER_CGROUPS) {
+            s.containers
+                .iter()
+                .filter_map(|(cid, ctr)| {
+                    let cgm = ctr.cgroup_manager.as_ref()?.as_fs()?;
+                    Some((cid.clone(), cgm.sampler()))
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+
+    let mounts = if metrics::collector_selected(req, metrics::COLLECTOR_GUEST_FS) {
+        Some(s.fs_mounts())
+    } else {
+        None
+    };
+
+    drop(s);
+
+    let sampled = tokio::task::spawn_blocking(move || {
+        for (cid, sampler) in samplers {
+            let sample = sampler();
+            metrics::set_container_pressure(&cid, &sample.pressure);
+            metrics::set_container_hugetlb(&cid, &sample.hugetlb);
+        }
+    })
+    .await;
+    if let Err(e) = sampled {
+        warn!(sl!(), "failed to sample the cgroups"; "error" => format!("{:?}", e));
+    }
+
+    if let Some(mounts) = mounts {
+        metrics::set_fs_usage(mounts).await;
+    }
+}
+
+// Methods are the handlers of the methods of a service, by path, e.g.
+// "/grpc.AgentService/CreateContainer".
+pub type Methods = HashMap<String, Box<dyn MethodHandler + Send + Sync>>;
+
+// create_services returns the methods of the agent and health services.
+pub fn create_services(s: Arc<Mutex<Sandbox>>) -> (Methods, Methods) {
     let agent_service = Box::new(AgentService { sandbox: s })
         as Box<dyn protocols::agent_ttrpc::AgentService + Send + Sync>;
 
@@ -1339,6 +2927,68 @@ pub fn start(s: Arc<Mutex<Sandbox>>, server_address: &str) -> TtrpcServer {
 
     let hservice = protocols::health_ttrpc::create_health(health_worker);
 
+    (instrument_methods(aservice), instrument_methods(hservice))
+}
+
+// InstrumentedMethod records the latency and the errors of the calls of a
+// method in the agent metrics.
+struct InstrumentedMethod {
+    name: String,
+    handler: Box<dyn MethodHandler + Send + Sync>,
+}
+
+#[async_trait]
+impl MethodHandler for InstrumentedMethod {
+    async fn handler(
+        &self,
+        ctx: TtrpcContext,
+        req: ttrpc::Request,
+    ) -> ttrpc::Result<(u32, Vec<u8>)> {
+        let start = Instant::now();
+        let result = self.handler.handler(ctx, req).await;
+
+        // the errors of the method are encoded in the response, the ones
+        // returned are failures to encode it
+        let code = match &result {
+            Ok((_, buf)) => response_code(buf),
+            Err(_) => ttrpc::Code::INTERNAL,
+        };
+        let code = if code == ttrpc::Code::OK {
+            None
+        } else {
+            Some(format!("{:?}", code))
+        };
+        metrics::observe_rpc(&self.name, start.elapsed(), code.as_deref());
+
+        result
+    }
+}
+
+// response_code returns the status code of an encoded ttrpc response.
+fn response_code(buf: &[u8]) -> ttrpc::Code {
+    match protobuf::parse_from_bytes::<ttrpc::Response>(buf) {
+        Ok(resp) => resp.get_status().get_code(),
+        Err(_) => ttrpc::Code::INTERNAL,
+    }
+}
+
+// instrument_methods wraps the methods of a service to record their calls,
+// labelled by their name, e.g. "CreateContainer".
+fn instrument_methods(methods: Methods) -> Methods {
+    methods
+        .into_iter()
+        .map(|(path, handler)| {
+            let name = path.rsplit('/').next().unwrap_or_default().to_string();
+            let handler: Box<dyn MethodHandler + Send + Sync> =
+                Box::new(InstrumentedMethod { name, handler });
+            (path, handler)
+        })
+        .collect()
+}
+
+pub fn start(s: Arc<Mutex<Sandbox>>, server_address: &str) -> TtrpcServer {
+    let (aservice, hservice) = create_services(s);
+
     let server = TtrpcServer::new()
         .bind(server_address)
         .unwrap()
@@ -1363,6 +3013,7 @@ pub fn start(s: Arc<Mutex<Sandbox>>, server_address: &str) -> TtrpcServer {
 // are different inside the guest.
 fn update_container_namespaces(
     sandbox: &Sandbox,
+    sid: &str,
     spec: &mut Spec,
     sandbox_pidns: bool,
 ) -> Result<()> {
@@ -1392,8 +3043,8 @@ fn update_container_namespaces(
     // the create_sandbox request or create_container request.
     // Else set this to empty string so that a new pid namespace is
     // created for the container.
-    if sandbox_pidns && sandbox.sandbox_pidns.is_some() {
-        pid_ns.path = String::from(sandbox.sandbox_pidns.as_ref().unwrap().path.as_str());
+    if let Some(ns) = sandbox.shared_pidns(sid).filter(|_| sandbox_pidns) {
+        pid_ns.path = String::from(ns.path.as_str());
     }
 
     linux.namespaces.push(pid_ns);
@@ -1585,7 +3236,7 @@ fn setup_bundle(cid: &str, spec: &mut Spec) -> Result<PathBuf> {
     Ok(olddir)
 }
 
-fn cleanup_process(p: &mut Process) -> Result<()> {
+pub fn cleanup_process(p: &mut Process) -> Result<()> {
     if p.parent_stdin.is_some() {
         p.close_stream(StreamType::ParentStdin);
         unistd::close(p.parent_stdin.unwrap())?;
@@ -1663,6 +3314,78 @@ mod tests {
     use oci::{Hook, Hooks};
     use ttrpc::{r#async::TtrpcContext, MessageHeader};
 
+    #[test]
+    fn test_release_execs() {
+        let cids: Vec<String> = vec!["c1".to_string(), "c2".to_string(), "c3".to_string()];
+        let pipes: Vec<(RawFd, RawFd)> = (0..3).map(|_| unistd::pipe().unwrap()).collect();
+
+        // c2 fails to start
+        let prepared = cids.iter().zip(pipes.iter().map(|(_, w)| *w));
+        let (started, res) = release_execs(prepared, |cid, fd| {
+            let ret = unistd::write(fd, &[0]);
+            let _ = unistd::close(fd);
+            ret?;
+
+            if cid == "c2" {
+                return Err(anyhow!("failed to start {}", cid));
+            }
+            Ok(())
+        });
+
+        assert_eq!(started, vec!["c1".to_string()]);
+        assert!(res.is_err());
+
+        // c3 was aborted without being released
+        let mut buf = [0u8; 1];
+        assert_eq!(unistd::read(pipes[0].0, &mut buf).unwrap(), 1);
+        assert_eq!(unistd::read(pipes[2].0, &mut buf).unwrap(), 0);
+
+        for (r, _) in pipes {
+            let _ = unistd::close(r);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_terminate() {
+        let sent = Arc::new(std::sync::Mutex::new(vec![]));
+        let signal = |sig| {
+            let sent = sent.clone();
+            async move {
+                sent.lock().unwrap().push(sig);
+                Ok(())
+            }
+        };
+
+        // exits on SIGTERM
+        let running = || {
+            let sent = sent.clone();
+            async move { !sent.lock().unwrap().contains(&Signal::SIGTERM) }
+        };
+        terminate(signal, running, Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert_eq!(*sent.lock().unwrap(), vec![Signal::SIGTERM]);
+
+        // ignores it
+        sent.lock().unwrap().clear();
+        terminate(signal, || async { true }, Duration::from_millis(200))
+            .await
+            .unwrap();
+        assert_eq!(
+            *sent.lock().unwrap(),
+            vec![Signal::SIGTERM, Signal::SIGKILL]
+        );
+
+        // gone already
+        terminate(
+            |_| async { Err(anyhow!("no such process")) },
+            || async { false },
+            Duration::from_secs(10),
+        )
+        .await
+        .unwrap();
+    }
+
     fn mk_ttrpc_context() -> TtrpcContext {
         TtrpcContext {
             fd: -1,
@@ -1989,4 +3712,79 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_cgroup_error_code() {
+        let cases: Vec<(anyhow::Error, ttrpc::Code)> = vec![
+            (
+                CgroupError::NotSupported.into(),
+                ttrpc::Code::FAILED_PRECONDITION,
+            ),
+            (
+                CgroupError::KernelUnsupported {
+                    feature: "memory.swap".to_string(),
+                }
+                .into(),
+                ttrpc::Code::FAILED_PRECONDITION,
+            ),
+            (
+                CgroupError::invalid_value("cpu.quota", "500".to_string()).into(),
+                ttrpc::Code::INVALID_ARGUMENT,
+            ),
+            (
+                anyhow::Error::from(CgroupError::Frozen {
+                    cgroup: "c1".to_string(),
+                })
+                .context("failed to exec"),
+                ttrpc::Code::FAILED_PRECONDITION,
+            ),
+            (
+                CgroupError::io(
+                    Path::new("/sys/fs/cgroup/kata/c1/cgroup.procs"),
+                    std::io::Error::from_raw_os_error(libc::ENOENT),
+                )
+                .into(),
+                ttrpc::Code::INTERNAL,
+            ),
+            (anyhow!("failed"), ttrpc::Code::INTERNAL),
+        ];
+
+        for (e, code) in cases {
+            assert_eq!(cgroup_error_code(&e), code, "{:?}", e);
+        }
+    }
+
+    #[test]
+    fn test_response_code() {
+        let mut resp = ttrpc::Response::new();
+        resp.set_payload(vec![1, 2, 3]);
+        let buf = protobuf::Message::write_to_bytes(&resp).unwrap();
+        assert_eq!(response_code(&buf), ttrpc::Code::OK);
+
+        let mut status = ttrpc::Status::new();
+        status.set_code(ttrpc::Code::NOT_FOUND);
+        resp.set_status(status);
+        let buf = protobuf::Message::write_to_bytes(&resp).unwrap();
+        assert_eq!(response_code(&buf), ttrpc::Code::NOT_FOUND);
+
+        assert_eq!(response_code(&[0xff]), ttrpc::Code::INTERNAL);
+    }
+
+    #[tokio::test]
+    async fn test_create_services() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let sandbox = Sandbox::new(&logger).unwrap();
+
+        let (aservice, hservice) = create_services(Arc::new(Mutex::new(sandbox)));
+        assert!(aservice.contains_key("/grpc.AgentService/CreateContainer"));
+
+        let mut req = ttrpc::Request::new();
+        req.set_service("grpc.Health".to_string());
+        req.set_method("Check".to_string());
+        let (_, buf) = hservice["/grpc.Health/Check"]
+            .handler(mk_ttrpc_context(), req)
+            .await
+            .unwrap();
+        assert_eq!(response_code(&buf), ttrpc::Code::OK);
+    }
 }
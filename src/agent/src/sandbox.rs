@@ -3,36 +3,64 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
+use crate::fswatch::FsWatcher;
 use crate::linux_abi::*;
+use crate::memdump::MemoryDump;
+use crate::metrics;
+use crate::metrics_push::MetricsPusher;
 use crate::mount::{get_mount_fs_type, remove_mounts, TYPE_ROOTFS};
 use crate::namespace::Namespace;
 use crate::netlink::Handle;
 use crate::network::Network;
+use crate::postmortem;
+use crate::probe::ProbeManager;
 use crate::uevent::{Uevent, UeventMatcher};
+use crate::usage_history::UsageHistory;
+use crate::vsock_ports::VsockPorts;
+use crate::warnings::Warnings;
 use crate::watcher::BindWatcher;
 use anyhow::{anyhow, Context, Result};
 use libc::pid_t;
 use oci::{Hook, Hooks};
-use protocols::agent::OnlineCPUMemRequest;
+use protocols::agent::{CgroupEvent, CgroupEventType, OnlineCPUMemRequest};
 use regex::Regex;
-use rustjail::cgroups as rustjail_cgroups;
+use rustjail::cgroups::fs::Manager as FsManager;
+use rustjail::cgroups::notifier::Watch;
+use rustjail::cgroups::sandbox::SandboxCgroupManager;
+use rustjail::cgroups::Manager as CgroupManager;
 use rustjail::container::BaseContainer;
 use rustjail::container::LinuxContainer;
 use rustjail::process::Process;
 use slog::Logger;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 use std::sync::Arc;
 use std::{thread, time};
-use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio::sync::oneshot;
 use tokio::sync::Mutex;
 use tracing::instrument;
 
 type UeventWatcher = (Box<dyn UeventMatcher>, oneshot::Sender<Uevent>);
 
+// LogicalSandbox is one of the pods an agent in multi-sandbox mode packs
+// into a pre-booted guest. The pods share the guest kernel and network, as
+// well as the shared IPC and UTS namespaces, and each has its own cgroup
+// parent, storages, shared pid namespace and containers. There is no
+// network namespace per pod yet, so the pods of a guest have to trust each
+// other on the network. The storages mounted at the same mount point are
+// shared by the pods, and only unmounted along with the last one.
+#[derive(Debug)]
+pub struct LogicalSandbox {
+    pub cgroup: SandboxCgroupManager,
+    pub mounts: Vec<String>,
+    pub containers: HashSet<String>,
+    pub pidns: Option<Namespace>,
+}
+
 #[derive(Debug)]
 pub struct Sandbox {
     pub logger: Logger,
@@ -48,14 +76,40 @@ pub struct Sandbox {
     pub shared_ipcns: Namespace,
     pub sandbox_pidns: Option<Namespace>,
     pub storages: HashMap<String, u32>,
+    // mount point -> (host, guest) numbers of the block volumes
+    pub block_volumes: HashMap<String, ((i64, i64), (i64, i64))>,
     pub running: bool,
     pub no_pivot_root: bool,
     pub sender: Option<tokio::sync::oneshot::Sender<i32>>,
     pub rtnl: Handle,
     pub hooks: Option<Hooks>,
-    pub event_rx: Arc<Mutex<Receiver<String>>>,
-    pub event_tx: Option<Sender<String>>,
+    // the cgroup events, each of get_cgroup_event and get_oom_event has
+    // its own receiver so that neither takes the events of the other
+    pub event_rx: Arc<Mutex<broadcast::Receiver<CgroupEvent>>>,
+    pub oom_event_rx: Arc<Mutex<broadcast::Receiver<CgroupEvent>>>,
+    pub event_tx: Option<broadcast::Sender<CgroupEvent>>,
     pub bind_watcher: BindWatcher,
+    pub reaper_tx: UnboundedSender<pid_t>,
+    pub reaper_rx: Option<UnboundedReceiver<pid_t>>,
+    pub boot_logs: Option<Vec<u8>>,
+    // the memory dump being sent to the host
+    pub memory_dump: Option<MemoryDump>,
+    pub vsock_ports: VsockPorts,
+    pub probes: ProbeManager,
+    pub fs_watches: FsWatcher,
+    pub warnings: Warnings,
+    // parent cgroup of the containers, set up with the sandbox
+    pub cgroup: Option<SandboxCgroupManager>,
+    // oom and memory pressure watches of the containers
+    pub cgroup_watches: HashMap<String, Vec<Watch>>,
+    // the cgroup managers of the containers the agent ran before it was
+    // restarted, restored from their state
+    pub restored_cgroups: HashMap<String, Box<dyn CgroupManager>>,
+    pub usage_history: UsageHistory,
+    // the metrics sampled for the host, with SetMetricsPush
+    pub metrics_push: MetricsPusher,
+    // the logical sandboxes by id, with agent.multi_sandbox
+    pub sandboxes: HashMap<String, LogicalSandbox>,
 }
 
 impl Sandbox {
@@ -63,8 +117,10 @@ impl Sandbox {
     pub fn new(logger: &Logger) -> Result<Self> {
         let fs_type = get_mount_fs_type("/")?;
         let logger = logger.new(o!("subsystem" => "sandbox"));
-        let (tx, rx) = channel::<String>(100);
+        let (tx, rx) = broadcast::channel::<CgroupEvent>(100);
         let event_rx = Arc::new(Mutex::new(rx));
+        let oom_event_rx = Arc::new(Mutex::new(tx.subscribe()));
+        let (reaper_tx, reaper_rx) = unbounded_channel::<pid_t>();
 
         Ok(Sandbox {
             logger: logger.clone(),
@@ -80,14 +136,30 @@ impl Sandbox {
             shared_ipcns: Namespace::new(&logger),
             sandbox_pidns: None,
             storages: HashMap::new(),
+            block_volumes: HashMap::new(),
             running: false,
             no_pivot_root: fs_type.eq(TYPE_ROOTFS),
             sender: None,
             rtnl: Handle::new()?,
             hooks: None,
             event_rx,
+            oom_event_rx,
             event_tx: Some(tx),
             bind_watcher: BindWatcher::new(),
+            reaper_tx,
+            reaper_rx: Some(reaper_rx),
+            boot_logs: None,
+            memory_dump: None,
+            vsock_ports: VsockPorts::new(),
+            probes: ProbeManager::new(),
+            fs_watches: FsWatcher::new(),
+            warnings: Warnings::new(),
+            cgroup: None,
+            cgroup_watches: HashMap::new(),
+            restored_cgroups: HashMap::new(),
+            usage_history: UsageHistory::new(0),
+            metrics_push: MetricsPusher::new(),
+            sandboxes: HashMap::new(),
         })
     }
 
@@ -130,6 +202,7 @@ impl Sandbox {
                 *count -= 1;
                 if *count < 1 {
                     self.storages.remove(path);
+                    self.block_volumes.remove(path);
                     return Ok(true);
                 }
                 Ok(false)
@@ -184,18 +257,159 @@ impl Sandbox {
         Ok(true)
     }
 
+    // add_logical_sandbox adds a pod to the ones the guest runs, in
+    // multi-sandbox mode, its cgroup being created below `cgroup_root`.
+    pub fn add_logical_sandbox(&mut self, sid: &str, cgroup_root: &str) -> Result<()> {
+        if self.sandboxes.contains_key(sid) {
+            return Err(anyhow!("sandbox {} already exists", sid));
+        }
+
+        let ls = LogicalSandbox {
+            cgroup: SandboxCgroupManager::new(cgroup_root, sid)?,
+            mounts: vec![],
+            containers: HashSet::new(),
+            pidns: None,
+        };
+        self.sandboxes.insert(sid.to_string(), ls);
+
+        Ok(())
+    }
+
+    // remove_logical_sandbox removes a pod whose containers are gone, along
+    // with its storages and cgroup. The storages are removed at best, so
+    // that the cgroup isn't leaked when one of them fails.
+    pub fn remove_logical_sandbox(&mut self, sid: &str) -> Result<()> {
+        let mut ls = self
+            .sandboxes
+            .remove(sid)
+            .ok_or_else(|| anyhow!("sandbox {} not found", sid))?;
+
+        for m in ls.mounts.iter() {
+            let res = if self.storages.contains_key(m) {
+                self.unset_and_remove_sandbox_storage(m)
+            } else if self.is_mounted(m) {
+                // still used by another pod
+                Ok(())
+            } else {
+                remove_mounts(&[m.clone()])
+            };
+
+            if let Err(e) = res {
+                warn!(self.logger, "failed to remove a storage"; "sandbox" => sid, "mount-point" => m, "error" => format!("{:?}", e));
+            }
+        }
+
+        ls.cgroup.destroy()
+    }
+
+    // is_mounted tells if a storage is mounted at `mount_point` for the
+    // guest or one of the pods.
+    pub fn is_mounted(&self, mount_point: &str) -> bool {
+        self.mounts.iter().any(|m| m == mount_point)
+            || self
+                .sandboxes
+                .values()
+                .any(|ls| ls.mounts.iter().any(|m| m == mount_point))
+    }
+
+    // sandbox_cgroup returns the cgroup of a logical sandbox, or the one of
+    // the sandbox the agent was created for if sid isn't one.
+    pub fn sandbox_cgroup(&self, sid: &str) -> Option<&SandboxCgroupManager> {
+        match self.sandboxes.get(sid) {
+            Some(ls) => Some(&ls.cgroup),
+            None => self.cgroup.as_ref(),
+        }
+    }
+
+    // logical_sandbox_of returns the id of the logical sandbox a container
+    // belongs to.
+    pub fn logical_sandbox_of(&self, cid: &str) -> Option<&str> {
+        self.sandboxes
+            .iter()
+            .find(|(_, ls)| ls.containers.contains(cid))
+            .map(|(sid, _)| sid.as_str())
+    }
+
+    // is_sandbox_container tells if a container is the one of a sandbox,
+    // e.g. its pause container, which is named after the sandbox.
+    pub fn is_sandbox_container(&self, cid: &str) -> bool {
+        cid == self.id || self.sandboxes.contains_key(cid)
+    }
+
+    // fs_mounts returns the mount points whose filesystem usage is
+    // reported, each with the container it's mounted for, empty for the
+    // ones of the guest and sandboxes and the ones shared by several
+    // containers: the storages, e.g. the virtiofs shares, and the rootfs of
+    // the containers.
+    pub fn fs_mounts(&self) -> Vec<(String, String)> {
+        let sandbox_mounts = metrics::GUEST_FS_MOUNTS
+            .iter()
+            .map(|m| m.to_string())
+            .chain(self.mounts.iter().cloned())
+            .chain(self.sandboxes.values().flat_map(|ls| ls.mounts.clone()))
+            .map(|m| (m, String::new()));
+
+        let container_mounts = self
+            .container_mounts
+            .iter()
+            .flat_map(|(cid, mounts)| mounts.iter().map(move |m| (m.clone(), cid.clone())));
+
+        let rootfs = self.containers.iter().filter_map(|(cid, ctr)| {
+            let root = ctr.config.spec.as_ref()?.root.as_ref()?;
+            Some((root.path.clone(), cid.clone()))
+        });
+
+        let mut mounts: Vec<(String, String)> = sandbox_mounts
+            .chain(container_mounts)
+            .chain(rootfs)
+            .filter(|(m, _)| m.starts_with('/'))
+            .collect();
+        mounts.sort();
+        mounts.dedup_by(|next, kept| {
+            if next.0 != kept.0 {
+                return false;
+            }
+            if next.1 != kept.1 {
+                kept.1.clear();
+            }
+            true
+        });
+
+        mounts
+    }
+
     pub fn add_container(&mut self, c: LinuxContainer) {
+        self.watch_process_exit(c.init_process_pid);
         self.containers.insert(c.id.clone(), c);
     }
 
+    // watch_process_exit hands the process over to the reaper task, which
+    // polls a pidfd to be notified as soon as the process exits.
+    pub fn watch_process_exit(&self, pid: pid_t) {
+        if pid <= 0 {
+            return;
+        }
+
+        if let Err(e) = self.reaper_tx.send(pid) {
+            warn!(self.logger, "failed to watch process exit"; "pid" => pid, "error" => format!("{:?}", e));
+        }
+    }
+
     #[instrument]
-    pub fn update_shared_pidns(&mut self, c: &LinuxContainer) -> Result<()> {
+    pub fn update_shared_pidns(&mut self, sid: &str, c: &LinuxContainer) -> Result<()> {
+        // each logical sandbox shares the pid namespace of its own first
+        // container
+        let (sandbox_pidns, first) = match self.sandboxes.get_mut(sid) {
+            Some(ls) => (&mut ls.pidns, ls.containers.is_empty()),
+            None => (&mut self.sandbox_pidns, self.containers.is_empty()),
+        };
+
         // Populate the shared pid path only if this is an infra container and
         // sandbox_pidns has not been passed in the create_sandbox request.
         // This means a separate pause process has not been created. We treat the
         // first container created as the infra container in that case
         // and use its pid namespace in case pid namespace needs to be shared.
-        if self.sandbox_pidns.is_none() && self.containers.is_empty() {
+        if sandbox_pidns.is_none() && first {
             let init_pid = c.init_process_pid;
             if init_pid == -1 {
                 return Err(anyhow!(
@@ -206,12 +420,21 @@ impl Sandbox {
             let mut pid_ns = Namespace::new(&self.logger).get_pid();
             pid_ns.path = format!("/proc/{}/ns/pid", init_pid);
 
-            self.sandbox_pidns = Some(pid_ns);
+            *sandbox_pidns = Some(pid_ns);
         }
 
         Ok(())
     }
 
+    // shared_pidns returns the shared pid namespace of a logical sandbox,
+    // or the one of the sandbox the agent was created for if sid isn't one.
+    pub fn shared_pidns(&self, sid: &str) -> Option<&Namespace> {
+        match self.sandboxes.get(sid) {
+            Some(ls) => ls.pidns.as_ref(),
+            None => self.sandbox_pidns.as_ref(),
+        }
+    }
+
     pub fn get_container(&mut self, id: &str) -> Option<&mut LinuxContainer> {
         self.containers.get_mut(id)
     }
@@ -228,9 +451,28 @@ impl Sandbox {
 
     #[instrument]
     pub async fn destroy(&mut self) -> Result<()> {
+        self.metrics_push.stop();
+        self.cgroup_watches.clear();
+        for (cid, mut cgm) in self.restored_cgroups.drain() {
+            if let Err(e) = cgm.destroy() {
+                warn!(self.logger, "failed to destroy a restored cgroup"; "container" => cid, "error" => format!("{:?}", e));
+            }
+        }
+        // the cgroup states are of no use once the sandbox is gone
+        if let Err(e) = FsManager::remove_all_states() {
+            warn!(self.logger, "failed to remove the cgroup states"; "error" => format!("{:?}", e));
+        }
         for ctr in self.containers.values_mut() {
             ctr.destroy().await?;
         }
+        if let Some(mut cg) = self.cgroup.take() {
+            cg.destroy()?;
+        }
+        for (sid, mut ls) in self.sandboxes.drain() {
+            if let Err(e) = ls.cgroup.destroy() {
+                warn!(self.logger, "failed to destroy the cgroup of a sandbox"; "sandbox" => sid, "error" => format!("{:?}", e));
+            }
+        }
         Ok(())
     }
 
@@ -250,9 +492,13 @@ impl Sandbox {
             return Ok(());
         }
 
-        let guest_cpuset = rustjail_cgroups::fs::get_guest_cpuset()?;
-
         for (_, ctr) in self.containers.iter() {
+            let cgm = match ctr.cgroup_manager.as_ref() {
+                Some(cgm) => cgm,
+                None => continue,
+            };
+            let guest_cpuset = cgm.get_guest_cpuset()?;
+
             let cpu = ctr
                 .config
                 .spec
@@ -269,10 +515,7 @@ impl Sandbox {
             let container_cpust = if let Some(c) = cpu { &c.cpus } else { "" };
 
             info!(self.logger, "updating {}", ctr.id.as_str());
-            ctr.cgroup_manager
-                .as_ref()
-                .unwrap()
-                .update_cpuset_path(guest_cpuset.as_str(), &container_cpust)?;
+            cgm.update_cpuset_path(guest_cpuset.as_str(), &container_cpust)?;
         }
 
         Ok(())
@@ -332,38 +575,74 @@ impl Sandbox {
     }
 
     #[instrument]
-    pub async fn run_oom_event_monitor(&self, mut rx: Receiver<String>, container_id: String) {
+    pub async fn run_cgroup_event_monitor(
+        &self,
+        mut rx: broadcast::Receiver<String>,
+        container_id: String,
+        event_type: CgroupEventType,
+    ) {
         let logger = self.logger.clone();
 
         if self.event_tx.is_none() {
             error!(
                 logger,
-                "sandbox.event_tx not found in run_oom_event_monitor"
+                "sandbox.event_tx not found in run_cgroup_event_monitor"
             );
             return;
         }
 
         let tx = self.event_tx.as_ref().unwrap().clone();
+        let sandbox_id = self
+            .logical_sandbox_of(&container_id)
+            .unwrap_or(&self.id)
+            .to_string();
 
         tokio::spawn(async move {
             loop {
-                let event = rx.recv().await;
-                // None means the container has exited,
-                // and sender in OOM notifier is dropped.
-                if event.is_none() {
-                    return;
-                }
-                info!(logger, "got an OOM event {:?}", event);
-
-                let _ = tx
-                    .send(container_id.clone())
-                    .await
-                    .map_err(|e| error!(logger, "failed to send message: {:?}", e));
+                let event = match rx.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(n)) => {
+                        warn!(logger, "missed {} cgroup events", n; "type" => format!("{:?}", event_type));
+                        continue;
+                    }
+                    // the watch was stopped, the container is gone
+                    Err(RecvError::Closed) => return,
+                };
+                info!(logger, "got a cgroup event {:?}", event; "type" => format!("{:?}", event_type));
+
+                metrics::inc_container_cgroup_event(&container_id, event_type);
+                postmortem::record_event(
+                    postmortem::EVENT_CGROUP,
+                    &container_id,
+                    &format!("{:?}", event_type),
+                );
+
+                let mut ev = CgroupEvent::new();
+                ev.container_id = container_id.clone();
+                ev.sandbox_id = sandbox_id.clone();
+                ev.field_type = event_type;
+
+                // nobody listens once the sandbox is destroyed
+                let _ = tx.send(ev);
             }
         });
     }
 }
 
+// recv_cgroup_event waits for the next cgroup event of `rx`, None once the
+// sandbox is destroyed. The events a receiver was too slow for are dropped.
+pub async fn recv_cgroup_event(rx: &mut broadcast::Receiver<CgroupEvent>) -> Option<CgroupEvent> {
+    loop {
+        match rx.recv().await {
+            Ok(event) => return Some(event),
+            Err(RecvError::Lagged(n)) => {
+                warn!(slog_scope::logger(), "dropped {} cgroup events", n);
+            }
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
 #[instrument]
 fn online_resources(logger: &Logger, path: &str, pattern: &str, num: i32) -> Result<i32> {
     let mut count = 0;
@@ -448,11 +727,13 @@ fn online_memory(logger: &Logger) -> Result<()> {
 
 #[cfg(test)]
 mod tests {
-    use super::Sandbox;
+    use super::{recv_cgroup_event, Sandbox};
     use crate::{mount::BareMount, skip_if_not_root};
     use anyhow::Error;
     use nix::mount::MsFlags;
     use oci::{Linux, Root, Spec};
+    use protocols::agent::{CgroupEvent, CgroupEventType};
+    use rustjail::cgroups::{DeviceMap, DEFAULT_CGROUP_ROOT};
     use rustjail::container::LinuxContainer;
     use rustjail::specconv::CreateOpts;
     use slog::Logger;
@@ -673,12 +954,18 @@ mod tests {
 
         CreateOpts {
             cgroup_name: "".to_string(),
+            cgroup_root: "".to_string(),
             use_systemd_cgroup: false,
             no_pivot_root: false,
             no_new_keyring: false,
             spec: Some(spec),
             rootless_euid: false,
             rootless_cgroup: false,
+            disable_kmem_accounting: false,
+            strict_resources: false,
+            io_cost: false,
+            default_swappiness: None,
+            device_map: DeviceMap::new(),
         }
     }
 
@@ -735,7 +1022,7 @@ mod tests {
         let mut linux_container = create_linuxcontainer();
         linux_container.init_process_pid = test_pid;
 
-        s.update_shared_pidns(&linux_container).unwrap();
+        s.update_shared_pidns("", &linux_container).unwrap();
 
         assert!(s.sandbox_pidns.is_some());
 
@@ -766,6 +1053,103 @@ mod tests {
         assert!(s.hooks.as_ref().unwrap().poststop.is_empty());
     }
 
+    #[tokio::test]
+    async fn test_fs_mounts() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let mut s = Sandbox::new(&logger).unwrap();
+        s.mounts = vec!["/run/kata-containers/shared".to_string(), "".to_string()];
+        s.container_mounts.insert(
+            "c1".to_string(),
+            vec![
+                "/run/kata-containers/c1/rootfs".to_string(),
+                "/run/kata-containers/shared".to_string(),
+            ],
+        );
+        s.container_mounts.insert(
+            "c2".to_string(),
+            vec![
+                "/run/kata-containers/shared".to_string(),
+                "/run/kata-containers/c2/data".to_string(),
+            ],
+        );
+        s.container_mounts.insert(
+            "c3".to_string(),
+            vec!["/run/kata-containers/c2/data".to_string()],
+        );
+
+        let mount = |m: &str, cid: &str| (m.to_string(), cid.to_string());
+        assert_eq!(
+            s.fs_mounts(),
+            vec![
+                mount("/run", ""),
+                mount("/run/kata-containers/c1/rootfs", "c1"),
+                mount("/run/kata-containers/c2/data", ""),
+                mount("/run/kata-containers/shared", ""),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_logical_sandboxes() {
+        skip_if_not_root!();
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let mut s = Sandbox::new(&logger).unwrap();
+        s.id = "sb0".to_string();
+
+        s.add_logical_sandbox("sb1", DEFAULT_CGROUP_ROOT).unwrap();
+        assert!(s.add_logical_sandbox("sb1", DEFAULT_CGROUP_ROOT).is_err());
+        s.sandboxes
+            .get_mut("sb1")
+            .unwrap()
+            .containers
+            .insert("c1".to_string());
+
+        assert_eq!(s.logical_sandbox_of("c1"), Some("sb1"));
+        assert_eq!(s.logical_sandbox_of("c2"), None);
+        assert!(s.is_sandbox_container("sb0"));
+        assert!(s.is_sandbox_container("sb1"));
+        assert!(!s.is_sandbox_container("c1"));
+        assert_eq!(s.sandbox_cgroup("sb1").unwrap().cpath(), "/kata/sb1");
+        assert!(s.sandbox_cgroup("sb0").is_none());
+
+        s.remove_logical_sandbox("sb1").unwrap();
+        assert!(s.remove_logical_sandbox("sb1").is_err());
+        assert!(!s.is_sandbox_container("sb1"));
+
+        // a storage shared by the pods stays mounted until the last one is
+        // gone, and failing to unmount it doesn't leak the cgroup
+        let shared = "/run/kata-containers/test-shared".to_string();
+        for sid in &["sb1", "sb2"] {
+            s.add_logical_sandbox(sid, DEFAULT_CGROUP_ROOT).unwrap();
+            s.sandboxes.get_mut(*sid).unwrap().mounts = vec![shared.clone()];
+        }
+        assert!(s.is_mounted(&shared));
+        s.remove_logical_sandbox("sb1").unwrap();
+        assert!(s.is_mounted(&shared));
+        s.remove_logical_sandbox("sb2").unwrap();
+        assert!(!s.is_mounted(&shared));
+    }
+
+    #[tokio::test]
+    async fn test_cgroup_events() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let mut s = Sandbox::new(&logger).unwrap();
+
+        let mut event = CgroupEvent::new();
+        event.container_id = "c1".to_string();
+        event.field_type = CgroupEventType::OOM;
+        s.event_tx.as_ref().unwrap().send(event.clone()).unwrap();
+
+        // both get_cgroup_event and get_oom_event see the event
+        let mut rx = s.event_rx.lock().await;
+        assert_eq!(recv_cgroup_event(&mut rx).await, Some(event.clone()));
+        let mut oom_rx = s.oom_event_rx.lock().await;
+        assert_eq!(recv_cgroup_event(&mut oom_rx).await, Some(event));
+
+        s.event_tx.take();
+        assert_eq!(recv_cgroup_event(&mut rx).await, None);
+    }
+
     #[tokio::test]
     async fn test_sandbox_set_destroy() {
         let logger = slog::Logger::root(slog::Discard, o!());
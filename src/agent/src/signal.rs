@@ -4,81 +4,177 @@
 // SPDX-License-Identifier: Apache-2.0
 //
 
+use crate::postmortem;
 use crate::sandbox::Sandbox;
 use anyhow::{anyhow, Result};
 use capctl::prctl::set_subreaper;
+use futures::stream::{FuturesUnordered, StreamExt};
+use libc::pid_t;
+use nix::errno::Errno;
 use nix::sys::wait::WaitPidFlag;
 use nix::sys::wait::{self, WaitStatus};
 use nix::unistd;
 use slog::{error, info, o, Logger};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::os::unix::io::{FromRawFd, RawFd};
 use std::sync::Arc;
+use tokio::io::unix::AsyncFd;
 use tokio::select;
 use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::watch::Receiver;
 use tokio::sync::Mutex;
 use unistd::Pid;
 
-async fn handle_sigchild(logger: Logger, sandbox: Arc<Mutex<Sandbox>>) -> Result<()> {
+// handle_sigchild reaps the exited children which aren't `watched` by a
+// pidfd, one pid at a time: waiting for any child would take the exit
+// status of the watched processes from their watch.
+async fn handle_sigchild(
+    logger: &Logger,
+    sandbox: &Arc<Mutex<Sandbox>>,
+    watched: &HashSet<pid_t>,
+) -> Result<()> {
     info!(logger, "handling signal"; "signal" => "SIGCHLD");
 
-    loop {
-        // Avoid reaping the undesirable child's signal, e.g., execute_hook's
-        // The lock should be released immediately.
-        rustjail::container::WAIT_PID_LOCKER.lock().await;
-        let result = wait::waitpid(
-            Some(Pid::from_raw(-1)),
-            Some(WaitPidFlag::WNOHANG | WaitPidFlag::__WALL),
-        );
-
-        let wait_status = match result {
-            Ok(s) => {
-                if s == WaitStatus::StillAlive {
-                    return Ok(());
-                }
-                s
-            }
-            Err(e) => return Err(anyhow!(e).context("waitpid reaper failed")),
-        };
+    for pid in zombie_children() {
+        if watched.contains(&pid) {
+            continue;
+        }
+
+        reap_process(logger, sandbox, pid).await?;
+    }
 
-        info!(logger, "wait_status"; "wait_status result" => format!("{:?}", wait_status));
+    Ok(())
+}
 
-        if let Some(pid) = wait_status.pid() {
-            let raw_pid = pid.as_raw();
-            let child_pid = format!("{}", raw_pid);
+// zombie_children returns the exited children of the agent not reaped yet,
+// the orphans the agent is the subreaper of included.
+fn zombie_children() -> Vec<pid_t> {
+    let agent = unistd::getpid().as_raw();
+    let entries = match fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
 
-            let logger = logger.new(o!("child-pid" => child_pid));
+    entries
+        .flatten()
+        .filter_map(|e| e.file_name().to_str()?.parse::<pid_t>().ok())
+        .filter(|pid| {
+            fs::read_to_string(format!("/proc/{}/stat", pid))
+                .ok()
+                .and_then(|stat| parse_stat(&stat))
+                == Some(('Z', agent))
+        })
+        .collect()
+}
 
-            let sandbox_ref = sandbox.clone();
-            let mut sandbox = sandbox_ref.lock().await;
+// parse_stat returns the state and the parent pid out of the content of
+// /proc/<pid>/stat, after the command name which may hold any character.
+fn parse_stat(stat: &str) -> Option<(char, pid_t)> {
+    let mut fields = stat[stat.rfind(')')? + 1..].split_whitespace();
+    let state = fields.next()?.chars().next()?;
+    let ppid = fields.next()?.parse().ok()?;
 
-            let process = sandbox.find_process(raw_pid);
-            if process.is_none() {
-                info!(logger, "child exited unexpectedly");
-                continue;
-            }
+    Some((state, ppid))
+}
 
-            let mut p = process.unwrap();
-            let ret: i32;
+// handle_wait_status records the exit code of a reaped process
+// and notifies its waiters.
+async fn handle_wait_status(
+    logger: &Logger,
+    sandbox: &Arc<Mutex<Sandbox>>,
+    wait_status: WaitStatus,
+) {
+    info!(logger, "wait_status"; "wait_status result" => format!("{:?}", wait_status));
 
-            match wait_status {
-                WaitStatus::Exited(_, c) => ret = c,
-                WaitStatus::Signaled(_, sig, _) => ret = sig as i32,
-                _ => {
-                    info!(logger, "got wrong status for process";
-                                  "child-status" => format!("{:?}", wait_status));
-                    continue;
-                }
-            }
+    let pid = match wait_status.pid() {
+        Some(pid) => pid,
+        None => return,
+    };
 
-            p.exit_code = ret;
-            let _ = p.exit_tx.take();
+    let raw_pid = pid.as_raw();
+    let child_pid = format!("{}", raw_pid);
 
-            info!(logger, "notify term to close");
-            // close the socket file to notify readStdio to close terminal specifically
-            // in case this process's terminal has been inherited by its children.
-            p.notify_term_close();
+    let logger = logger.new(o!("child-pid" => child_pid));
+
+    let mut sandbox = sandbox.lock().await;
+
+    let cid = sandbox
+        .containers
+        .iter()
+        .find(|(_, c)| c.processes.contains_key(&raw_pid))
+        .map(|(id, _)| id.clone())
+        .unwrap_or_default();
+
+    let process = sandbox.find_process(raw_pid);
+    if process.is_none() {
+        info!(logger, "child exited unexpectedly");
+        return;
+    }
+
+    let mut p = process.unwrap();
+    let ret: i32;
+
+    match wait_status {
+        WaitStatus::Exited(_, c) => ret = c,
+        WaitStatus::Signaled(_, sig, _) => ret = sig as i32,
+        _ => {
+            info!(logger, "got wrong status for process";
+                          "child-status" => format!("{:?}", wait_status));
+            return;
         }
     }
+
+    p.exit_code = ret;
+    let _ = p.exit_tx.take();
+
+    postmortem::record_event(
+        postmortem::EVENT_EXIT,
+        &cid,
+        &format!("pid {} exec {:?} exited with {}", raw_pid, p.exec_id, ret),
+    );
+
+    info!(logger, "notify term to close");
+    // close the socket file to notify readStdio to close terminal specifically
+    // in case this process's terminal has been inherited by its children.
+    p.notify_term_close();
+}
+
+// open_pidfd returns a pollable pidfd for the process, which becomes
+// readable when the process exits. None is returned if the kernel
+// doesn't support pidfd_open(2) (< 5.3), the SIGCHLD handler
+// reaps the process in that case.
+fn open_pidfd(pid: pid_t) -> Option<AsyncFd<File>> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+    if fd < 0 {
+        return None;
+    }
+
+    let file = unsafe { File::from_raw_fd(fd as RawFd) };
+    AsyncFd::new(file).ok()
+}
+
+// reap_process reaps a process which exited.
+async fn reap_process(logger: &Logger, sandbox: &Arc<Mutex<Sandbox>>, pid: pid_t) -> Result<()> {
+    // Avoid reaping the undesirable child's signal, e.g., execute_hook's
+    // The lock should be released immediately.
+    rustjail::container::WAIT_PID_LOCKER.lock().await;
+    let result = wait::waitpid(
+        Some(Pid::from_raw(pid)),
+        Some(WaitPidFlag::WNOHANG | WaitPidFlag::__WALL),
+    );
+
+    let wait_status = match result {
+        Ok(WaitStatus::StillAlive) => return Ok(()),
+        Ok(s) => s,
+        // already reaped, e.g. by the waiter of a hook
+        Err(nix::Error::Sys(Errno::ECHILD)) => return Ok(()),
+        Err(e) => return Err(anyhow!(e).context("waitpid reaper failed")),
+    };
+
+    handle_wait_status(logger, sandbox, wait_status).await;
+
+    Ok(())
 }
 
 pub async fn setup_signal_handler(
@@ -93,6 +189,17 @@ pub async fn setup_signal_handler(
 
     let mut sigchild_stream = signal(SignalKind::child())?;
 
+    // All the tracked processes are watched from this single task, the
+    // SIGCHLD handler is kept to reap the untracked children.
+    let mut reaper_rx = sandbox
+        .lock()
+        .await
+        .reaper_rx
+        .take()
+        .ok_or_else(|| anyhow!("process reaper already started"))?;
+    let mut exits = FuturesUnordered::new();
+    let mut watched = HashSet::new();
+
     loop {
         select! {
             _ = shutdown.changed() => {
@@ -100,8 +207,25 @@ pub async fn setup_signal_handler(
                 break;
             }
 
+            Some(pid) = reaper_rx.recv() => {
+                if let Some(pidfd) = open_pidfd(pid) {
+                    watched.insert(pid);
+                    exits.push(async move {
+                        let _ = pidfd.readable().await;
+                        pid
+                    });
+                }
+            }
+
+            Some(pid) = exits.next(), if !exits.is_empty() => {
+                watched.remove(&pid);
+                if let Err(e) = reap_process(&logger, &sandbox, pid).await {
+                    error!(logger, "failed to reap process"; "pid" => pid, "error" => format!("{:?}", e));
+                }
+            }
+
             _ = sigchild_stream.recv() => {
-                let result = handle_sigchild(logger.clone(), sandbox.clone()).await;
+                let result = handle_sigchild(&logger, &sandbox, &watched).await;
 
                 match result {
                     Ok(()) => (),
@@ -120,6 +244,7 @@ pub async fn setup_signal_handler(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::process::Command;
     use tokio::pin;
     use tokio::sync::watch::channel;
     use tokio::time::Duration;
@@ -152,4 +277,49 @@ mod tests {
             }
         }
     }
+
+    // wait_zombie waits for the child `pid` to exit, without reaping it
+    fn wait_zombie(pid: pid_t) {
+        for _ in 0..100 {
+            if zombie_children().contains(&pid) {
+                return;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        panic!("child {} didn't exit", pid);
+    }
+
+    #[test]
+    fn test_parse_stat() {
+        let test_cases = vec![
+            ("1 (init) S 0 1 1 0 -1", Some(('S', 0))),
+            ("42 (a) b) (c) Z 7 42 42 0", Some(('Z', 7))),
+            ("42 (sleep)", None),
+            ("42 sleep Z 7", None),
+        ];
+
+        for (stat, expected) in test_cases {
+            assert_eq!(parse_stat(stat), expected, "stat: {:?}", stat);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_sigchild() {
+        let logger = slog::Logger::root(slog::Discard, o!());
+        let sandbox = Arc::new(Mutex::new(Sandbox::new(&logger).unwrap()));
+
+        let child = Command::new("true").spawn().unwrap();
+        let pid = child.id() as pid_t;
+        wait_zombie(pid);
+
+        // the watched children are left to their pidfd
+        let watched: HashSet<pid_t> = vec![pid].into_iter().collect();
+        handle_sigchild(&logger, &sandbox, &watched).await.unwrap();
+        assert!(zombie_children().contains(&pid));
+
+        handle_sigchild(&logger, &sandbox, &HashSet::new())
+            .await
+            .unwrap();
+        assert!(!zombie_children().contains(&pid));
+    }
 }
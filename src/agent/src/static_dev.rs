@@ -0,0 +1,240 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Static device nodes for the minimal guest images shipping no udev, where
+// the nodes of the drivers which devtmpfs doesn't create, or creates with
+// a too strict mode, are missing for the containers, e.g. /dev/net/tun or
+// /dev/fuse.
+//
+// The nodes are listed with agent.static_devices, either by the name of a
+// known device, or as "<path>:<c|b>:<major>:<minor>[:<mode>]" for the ones
+// specific to a deployment, the path being relative to /dev and the mode
+// octal, e.g.
+//   agent.static_devices=tun,fuse,loop,sgx/enclave:c:10:125:0666
+// The existing nodes are left alone.
+
+use anyhow::{anyhow, Context, Result};
+use nix::sys::stat::{self, Mode, SFlag};
+use slog::Logger;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+pub const DEV_DIR: &str = "/dev";
+
+const DEFAULT_MODE: u32 = 0o600;
+// loop devices created for "loop", besides loop-control
+const LOOP_DEVICES: u64 = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StaticDevice {
+    // relative to /dev
+    pub path: String,
+    pub kind: SFlag,
+    pub major: u64,
+    pub minor: u64,
+    pub mode: u32,
+}
+
+impl StaticDevice {
+    fn new(path: &str, kind: SFlag, major: u64, minor: u64, mode: u32) -> Self {
+        StaticDevice {
+            path: path.to_string(),
+            kind,
+            major,
+            minor,
+            mode,
+        }
+    }
+}
+
+// known_devices returns the nodes of a known device name.
+fn known_devices(name: &str) -> Option<Vec<StaticDevice>> {
+    let chr = SFlag::S_IFCHR;
+
+    let devices = match name {
+        "tun" => vec![StaticDevice::new("net/tun", chr, 10, 200, 0o666)],
+        "fuse" => vec![StaticDevice::new("fuse", chr, 10, 229, 0o666)],
+        "kvm" => vec![StaticDevice::new("kvm", chr, 10, 232, 0o660)],
+        "vhost-net" => vec![StaticDevice::new("vhost-net", chr, 10, 238, 0o600)],
+        "vhost-vsock" => vec![StaticDevice::new("vhost-vsock", chr, 10, 241, 0o600)],
+        "loop" => {
+            let mut devices = vec![StaticDevice::new("loop-control", chr, 10, 237, 0o660)];
+            devices
+                .extend((0..LOOP_DEVICES).map(|i| {
+                    StaticDevice::new(&format!("loop{}", i), SFlag::S_IFBLK, 7, i, 0o660)
+                }));
+            devices
+        }
+        _ => return None,
+    };
+
+    Some(devices)
+}
+
+impl FromStr for StaticDevice {
+    type Err = anyhow::Error;
+
+    // from_str parses a "<path>:<c|b>:<major>:<minor>[:<mode>]" node.
+    fn from_str(s: &str) -> Result<Self> {
+        let fields: Vec<&str> = s.split(':').collect();
+        if fields.len() != 4 && fields.len() != 5 {
+            return Err(anyhow!("invalid static device {:?}", s));
+        }
+
+        let path = Path::new(fields[0]);
+        if fields[0].is_empty() || !path.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(anyhow!("invalid static device path {:?}", fields[0]));
+        }
+
+        let kind = match fields[1] {
+            "c" => SFlag::S_IFCHR,
+            "b" => SFlag::S_IFBLK,
+            t => return Err(anyhow!("invalid static device type {:?}", t)),
+        };
+
+        let number = |f: &str| {
+            f.parse::<u64>()
+                .with_context(|| format!("invalid static device {:?}", s))
+        };
+
+        let mode = match fields.get(4) {
+            Some(m) => u32::from_str_radix(m, 8)
+                .ok()
+                .filter(|m| *m <= 0o777)
+                .ok_or_else(|| anyhow!("invalid static device mode {:?}", m))?,
+            None => DEFAULT_MODE,
+        };
+
+        Ok(StaticDevice::new(
+            fields[0],
+            kind,
+            number(fields[2])?,
+            number(fields[3])?,
+            mode,
+        ))
+    }
+}
+
+// parse_static_devices parses a comma separated list of device names and
+// nodes.
+pub fn parse_static_devices(s: &str) -> Result<Vec<StaticDevice>> {
+    let mut devices = vec![];
+
+    for entry in s.split(',').filter(|e| !e.is_empty()) {
+        match known_devices(entry) {
+            Some(d) => devices.extend(d),
+            None => devices.push(entry.parse()?),
+        }
+    }
+
+    Ok(devices)
+}
+
+// create_static_devices creates the nodes missing in dir, the failures
+// being logged only, the agent being usable without them.
+pub fn create_static_devices(logger: &Logger, dir: &Path, devices: &[StaticDevice]) {
+    for d in devices {
+        if let Err(e) = create_device(dir, d) {
+            warn!(logger, "failed to create static device";
+                "path" => &d.path,
+                "error" => format!("{:?}", e));
+        }
+    }
+}
+
+fn create_device(dir: &Path, d: &StaticDevice) -> Result<()> {
+    let path = dir.join(&d.path);
+    if path.exists() {
+        return Ok(());
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    stat::mknod(
+        &path,
+        d.kind,
+        Mode::from_bits_truncate(d.mode),
+        stat::makedev(d.major, d.minor),
+    )
+    .map_err(|e| anyhow!(e).context(format!("failed to create {:?}", path)))?;
+
+    // mknod is subject to the umask
+    fs::set_permissions(&path, fs::Permissions::from_mode(d.mode))
+        .with_context(|| format!("failed to set the mode of {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::skip_if_not_root;
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+    #[test]
+    fn test_parse_static_devices() {
+        let devices = parse_static_devices("tun,loop,sgx/enclave:c:10:125:0666").unwrap();
+        assert_eq!(devices.len(), 1 + 1 + LOOP_DEVICES as usize + 1);
+        assert_eq!(
+            devices[0],
+            StaticDevice::new("net/tun", SFlag::S_IFCHR, 10, 200, 0o666)
+        );
+        assert_eq!(
+            devices[2],
+            StaticDevice::new("loop0", SFlag::S_IFBLK, 7, 0, 0o660)
+        );
+        assert_eq!(
+            devices.last().unwrap(),
+            &StaticDevice::new("sgx/enclave", SFlag::S_IFCHR, 10, 125, 0o666)
+        );
+
+        assert_eq!(
+            parse_static_devices("mydev:b:8:0").unwrap(),
+            vec![StaticDevice::new(
+                "mydev",
+                SFlag::S_IFBLK,
+                8,
+                0,
+                DEFAULT_MODE
+            )]
+        );
+        assert!(parse_static_devices("").unwrap().is_empty());
+
+        for s in &[
+            "unknown",
+            "mydev:x:8:0",
+            "mydev:c:8",
+            "mydev:c:a:0",
+            "mydev:c:8:0:999",
+            "../mydev:c:8:0",
+            "/mydev:c:8:0",
+            ":c:8:0",
+        ] {
+            assert!(parse_static_devices(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_create_static_devices() {
+        skip_if_not_root!();
+
+        let dir = tempfile::tempdir().unwrap();
+        let logger = slog::Logger::root(slog::Discard, o!());
+        fs::write(dir.path().join("fuse"), "").unwrap();
+
+        let devices = parse_static_devices("tun,fuse").unwrap();
+        create_static_devices(&logger, dir.path(), &devices);
+
+        let meta = fs::metadata(dir.path().join("net/tun")).unwrap();
+        assert!(meta.file_type().is_char_device());
+        assert_eq!(meta.rdev(), stat::makedev(10, 200));
+        assert_eq!(meta.mode() & 0o777, 0o666);
+
+        // left alone
+        assert!(fs::metadata(dir.path().join("fuse")).unwrap().is_file());
+    }
+}
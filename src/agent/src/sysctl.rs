@@ -0,0 +1,176 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use anyhow::{anyhow, Context, Result};
+use oci::Spec;
+use std::fs;
+use std::path::Path;
+
+// SYSCTL_PRESETS_ANNOTATION lists, comma separated, the presets of vetted
+// network sysctls to apply to the container. The namespaced ones are set in
+// the network namespace of the container, shared by the whole sandbox.
+pub const SYSCTL_PRESETS_ANNOTATION: &str = "io.katacontainers.container.sysctl_presets";
+
+// the sysctls of the presets the network namespaces don't have: they only
+// exist in the initial one, so they are set by the agent for the whole
+// guest, and are left as they are once the container is gone
+const GUEST_GLOBAL: &[&str] = &[
+    "net.core.rmem_max",
+    "net.core.wmem_max",
+    "net.core.busy_poll",
+    "net.core.busy_read",
+];
+
+const PROC_SYS: &str = "/proc/sys";
+
+// Convenience macro to obtain the scope logger
+macro_rules! sl {
+    () => {
+        slog_scope::logger().new(o!("subsystem" => "sysctl"))
+    };
+}
+
+// Socket buffers large enough for high throughput UDP workloads (e.g.
+// QUIC). UDP GRO is a socket option (UDP_GRO) applications enable
+// themselves, so there is no sysctl for it here.
+const UDP_THROUGHPUT_PRESET: &[(&str, &str)] = &[
+    ("net.core.rmem_max", "16777216"),
+    ("net.core.wmem_max", "16777216"),
+    ("net.ipv4.udp_rmem_min", "16384"),
+    ("net.ipv4.udp_wmem_min", "16384"),
+];
+
+// Busy polling of the device queues for latency sensitive workloads.
+const LOW_LATENCY_PRESET: &[(&str, &str)] =
+    &[("net.core.busy_poll", "50"), ("net.core.busy_read", "50")];
+
+fn get_preset(name: &str) -> Option<&'static [(&'static str, &'static str)]> {
+    match name {
+        "udp-throughput" => Some(UDP_THROUGHPUT_PRESET),
+        "low-latency" => Some(LOW_LATENCY_PRESET),
+        _ => None,
+    }
+}
+
+// apply_presets adds the namespaced sysctls of the presets requested
+// through the annotations to the spec, and returns the guest global ones.
+// The sysctls set explicitly in the spec win.
+pub fn apply_presets(spec: &mut Spec) -> Result<Vec<(String, String)>> {
+    let mut global = vec![];
+
+    let presets = match spec.annotations.get(SYSCTL_PRESETS_ANNOTATION) {
+        Some(v) => v.clone(),
+        None => return Ok(global),
+    };
+
+    let linux = spec
+        .linux
+        .as_mut()
+        .ok_or_else(|| anyhow!("Spec didn't contain linux field"))?;
+
+    for name in presets
+        .split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+    {
+        let preset = get_preset(name).ok_or_else(|| anyhow!("unknown sysctl preset {}", name))?;
+
+        for (key, value) in preset {
+            if linux.sysctl.contains_key(*key) {
+                continue;
+            }
+
+            if GUEST_GLOBAL.contains(key) {
+                global.push((key.to_string(), value.to_string()));
+            } else {
+                linux.sysctl.insert(key.to_string(), value.to_string());
+            }
+        }
+    }
+
+    Ok(global)
+}
+
+// set_guest_sysctls writes the sysctls to the guest, under `proc_sys`.
+pub fn set_guest_sysctls(proc_sys: &Path, sysctls: &[(String, String)]) -> Result<()> {
+    for (key, value) in sysctls {
+        let path = proc_sys.join(key.replace('.', "/"));
+        info!(sl!(), "setting the guest sysctl {} to {}", key, value);
+
+        fs::write(&path, value)
+            .with_context(|| format!("failed to write {} to {:?}", value, path))?;
+    }
+
+    Ok(())
+}
+
+// set_guest_global_sysctls writes the guest global sysctls of the presets.
+pub fn set_guest_global_sysctls(sysctls: &[(String, String)]) -> Result<()> {
+    set_guest_sysctls(Path::new(PROC_SYS), sysctls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci::Linux;
+
+    #[test]
+    fn test_apply_presets() {
+        let mut spec = Spec {
+            linux: Some(Linux::default()),
+            ..Default::default()
+        };
+
+        // no annotation, nothing to do
+        assert!(apply_presets(&mut spec).unwrap().is_empty());
+        assert!(spec.linux.as_ref().unwrap().sysctl.is_empty());
+
+        spec.annotations.insert(
+            SYSCTL_PRESETS_ANNOTATION.to_string(),
+            "udp-throughput, low-latency".to_string(),
+        );
+        spec.linux
+            .as_mut()
+            .unwrap()
+            .sysctl
+            .insert("net.core.rmem_max".to_string(), "4194304".to_string());
+
+        let global = apply_presets(&mut spec).unwrap();
+
+        // the guest global sysctls stay out of the spec
+        let sysctl = &spec.linux.as_ref().unwrap().sysctl;
+        assert_eq!(sysctl.len(), 3);
+        assert_eq!(sysctl["net.core.rmem_max"], "4194304");
+        assert_eq!(sysctl["net.ipv4.udp_rmem_min"], "16384");
+        assert_eq!(
+            global,
+            vec![
+                ("net.core.wmem_max".to_string(), "16777216".to_string()),
+                ("net.core.busy_poll".to_string(), "50".to_string()),
+                ("net.core.busy_read".to_string(), "50".to_string()),
+            ]
+        );
+
+        spec.annotations
+            .insert(SYSCTL_PRESETS_ANNOTATION.to_string(), "foo".to_string());
+        assert!(apply_presets(&mut spec).is_err());
+    }
+
+    #[test]
+    fn test_set_guest_sysctls() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("net/core")).unwrap();
+
+        let sysctls = vec![("net.core.busy_poll".to_string(), "50".to_string())];
+        set_guest_sysctls(dir.path(), &sysctls).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("net/core/busy_poll")).unwrap(),
+            "50"
+        );
+
+        let missing = vec![("net.foo".to_string(), "1".to_string())];
+        assert!(set_guest_sysctls(dir.path(), &missing).is_err());
+    }
+}
@@ -0,0 +1,185 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// A short history of the cpu and memory usage of each container, sampled
+// by the agent itself so that the usage leading to an OOM or to a
+// throttling incident can be retrieved even if nothing was scraping the
+// metrics at the time.
+
+use anyhow::Result;
+use protocols::agent::{CgroupStats, UsageSample};
+use rustjail::cgroups::Manager as CgroupManager;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::select;
+use tokio::sync::watch::Receiver;
+use tokio::sync::Mutex;
+
+use crate::sandbox::Sandbox;
+
+#[derive(Debug)]
+pub struct UsageHistory {
+    // samples kept per container
+    capacity: usize,
+    samples: HashMap<String, VecDeque<UsageSample>>,
+}
+
+impl UsageHistory {
+    pub fn new(capacity: usize) -> Self {
+        UsageHistory {
+            capacity,
+            samples: HashMap::new(),
+        }
+    }
+
+    // record adds a sample of the container, dropping the oldest one when
+    // the history is full.
+    pub fn record(&mut self, cid: &str, sample: UsageSample) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let samples = self
+            .samples
+            .entry(cid.to_string())
+            .or_insert_with(VecDeque::new);
+
+        if samples.len() == self.capacity {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+
+    // get returns the samples of the container, oldest first.
+    pub fn get(&self, cid: &str) -> Vec<UsageSample> {
+        self.samples
+            .get(cid)
+            .map(|s| s.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn remove(&mut self, cid: &str) {
+        self.samples.remove(cid);
+    }
+}
+
+fn build_sample(timestamp: i64, stats: &CgroupStats) -> UsageSample {
+    let cpu = stats.get_cpu_stats();
+    let memory = stats.get_memory_stats().get_usage();
+
+    let mut sample = UsageSample::new();
+    sample.timestamp = timestamp;
+    sample.cpu_usage = cpu.get_cpu_usage().get_total_usage();
+    sample.throttled_periods = cpu.get_throttling_data().get_throttled_periods();
+    sample.throttled_time = cpu.get_throttling_data().get_throttled_time();
+    sample.memory_usage = memory.get_usage();
+    sample.memory_limit = memory.get_limit();
+    sample.memory_failcnt = memory.get_failcnt();
+
+    sample
+}
+
+async fn record_samples(sandbox: &Arc<Mutex<Sandbox>>) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+
+    let mut s = sandbox.lock().await;
+
+    let mut samples = vec![];
+    for (cid, ctr) in s.containers.iter() {
+        let stats: CgroupStats = match ctr.cgroup_manager.as_ref().map(|cgm| cgm.get_stats()) {
+            Some(Ok(stats)) => stats.into(),
+            Some(Err(e)) => {
+                debug!(s.logger, "failed to sample container usage"; "container" => cid, "error" => format!("{:?}", e));
+                continue;
+            }
+            None => continue,
+        };
+
+        samples.push((cid.clone(), build_sample(timestamp, &stats)));
+    }
+
+    for (cid, sample) in samples {
+        s.usage_history.record(&cid, sample);
+    }
+}
+
+// run_usage_sampler samples the usage of all the containers every
+// `interval` until the agent shuts down.
+pub async fn run_usage_sampler(
+    sandbox: Arc<Mutex<Sandbox>>,
+    interval: Duration,
+    mut shutdown: Receiver<bool>,
+) -> Result<()> {
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        select! {
+            _ = shutdown.changed() => {
+                return Ok(());
+            }
+            _ = ticker.tick() => {
+                record_samples(&sandbox).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocols::agent::{CpuStats, MemoryData, MemoryStats};
+
+    fn sample(timestamp: i64) -> UsageSample {
+        let mut s = UsageSample::new();
+        s.timestamp = timestamp;
+        s
+    }
+
+    #[test]
+    fn test_usage_history() {
+        let mut h = UsageHistory::new(3);
+
+        for i in 0..5 {
+            h.record("c1", sample(i));
+        }
+        h.record("c2", sample(10));
+
+        let timestamps: Vec<i64> = h.get("c1").iter().map(|s| s.timestamp).collect();
+        assert_eq!(timestamps, vec![2, 3, 4]);
+        assert_eq!(h.get("c2").len(), 1);
+        assert!(h.get("c3").is_empty());
+
+        h.remove("c1");
+        assert!(h.get("c1").is_empty());
+
+        let mut h = UsageHistory::new(0);
+        h.record("c1", sample(0));
+        assert!(h.get("c1").is_empty());
+    }
+
+    #[test]
+    fn test_build_sample() {
+        let mut usage = MemoryData::new();
+        usage.usage = 1 << 20;
+        usage.limit = 1 << 30;
+
+        let mut memory = MemoryStats::new();
+        memory.set_usage(usage);
+
+        let mut stats = CgroupStats::new();
+        stats.set_memory_stats(memory);
+        stats.set_cpu_stats(CpuStats::new());
+
+        let s = build_sample(42, &stats);
+        assert_eq!(s.timestamp, 42);
+        assert_eq!(s.memory_usage, 1 << 20);
+        assert_eq!(s.memory_limit, 1 << 30);
+        assert_eq!(s.cpu_usage, 0);
+    }
+}
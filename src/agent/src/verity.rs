@@ -0,0 +1,225 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Verification that the guest rootfs is mounted read-only from a dm-verity
+// device, created by the kernel at boot from the "dm-mod.create" parameter:
+//
+//   dm-mod.create="<name>,<uuid>,<minor>,<flags>,<table>[,<table>+][;<name>,...]"
+//
+// with a verity table such as:
+//
+//   <start> <len> verity <version> <data_dev> <hash_dev> <data_block_size>
+//   <hash_block_size> <num_data_blocks> <hash_start_block> <algorithm>
+//   <root_hash> <salt> [<#opt_params> <opt_params>]
+//
+// see https://www.kernel.org/doc/html/latest/admin-guide/device-mapper/dm-init.html
+
+use anyhow::{anyhow, Result};
+use std::fs;
+use std::path::Path;
+
+const DM_MOD_CREATE_OPTION: &str = "dm-mod.create=";
+const VERITY_TARGET: &str = "verity";
+const DEV_MAPPER_PREFIX: &str = "/dev/mapper/";
+const DEV_DM_PREFIX: &str = "/dev/dm-";
+const SYSFS_BLOCK_PATH: &str = "/sys/block";
+
+// RootfsIntegrity describes how the guest rootfs is protected.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RootfsIntegrity {
+    pub verified: bool,
+    pub device: String,
+    pub hash_algorithm: String,
+    pub root_hash: String,
+}
+
+// VerityDevice is a dm-verity device created at boot.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct VerityDevice {
+    name: String,
+    hash_algorithm: String,
+    root_hash: String,
+}
+
+// get_rootfs_integrity checks if the guest rootfs is mounted read-only
+// from one of the dm-verity devices described on the kernel command line.
+pub fn get_rootfs_integrity(cmdline_file: &str, mountinfo_file: &str) -> Result<RootfsIntegrity> {
+    let device = get_root_device(mountinfo_file)?;
+    let mut integrity = RootfsIntegrity {
+        device: device.source.clone(),
+        ..Default::default()
+    };
+
+    if !device.read_only {
+        return Ok(integrity);
+    }
+
+    let name = match get_dm_name(&device.source) {
+        Some(n) => n,
+        None => return Ok(integrity),
+    };
+
+    let cmdline = fs::read_to_string(cmdline_file)?;
+    if let Some(v) = parse_verity_devices(&cmdline)
+        .into_iter()
+        .find(|v| v.name == name)
+    {
+        integrity.verified = true;
+        integrity.hash_algorithm = v.hash_algorithm;
+        integrity.root_hash = v.root_hash;
+    }
+
+    Ok(integrity)
+}
+
+#[derive(Debug, PartialEq)]
+struct RootDevice {
+    source: String,
+    read_only: bool,
+}
+
+// get_root_device returns the source of the "/" mount point.
+fn get_root_device(mountinfo_file: &str) -> Result<RootDevice> {
+    let mountinfo = fs::read_to_string(mountinfo_file)?;
+
+    // The last mount on "/" wins, as it hides the previous ones.
+    let mut root = None;
+    for line in mountinfo.lines() {
+        let p: Vec<&str> = line.splitn(2, " - ").collect();
+        if p.len() != 2 {
+            continue;
+        }
+
+        let pre: Vec<&str> = p[0].split(' ').collect();
+        let post: Vec<&str> = p[1].split(' ').collect();
+        if pre.len() < 6 || post.len() < 2 || pre[4] != "/" {
+            continue;
+        }
+
+        root = Some(RootDevice {
+            source: post[1].to_string(),
+            read_only: pre[5].split(',').any(|o| o == "ro"),
+        });
+    }
+
+    root.ok_or_else(|| anyhow!("failed to find the root mount in {}", mountinfo_file))
+}
+
+// get_dm_name returns the device-mapper name of a block device.
+fn get_dm_name(source: &str) -> Option<String> {
+    if let Some(name) = source.strip_prefix(DEV_MAPPER_PREFIX) {
+        return Some(name.to_string());
+    }
+
+    if source.starts_with(DEV_DM_PREFIX) {
+        let dev = source.trim_start_matches("/dev/");
+        let path = Path::new(SYSFS_BLOCK_PATH).join(dev).join("dm/name");
+        return fs::read_to_string(path).ok().map(|n| n.trim().to_string());
+    }
+
+    None
+}
+
+// parse_verity_devices returns the dm-verity devices created
+// with the "dm-mod.create" kernel parameter.
+fn parse_verity_devices(cmdline: &str) -> Vec<VerityDevice> {
+    let mut devices = vec![];
+
+    let start = match cmdline.find(DM_MOD_CREATE_OPTION) {
+        Some(i) => i + DM_MOD_CREATE_OPTION.len(),
+        None => return devices,
+    };
+
+    // The value is quoted as the tables contain spaces.
+    let value = &cmdline[start..];
+    let value = match value.strip_prefix('"') {
+        Some(v) => v.split('"').next().unwrap_or_default(),
+        None => value.split_ascii_whitespace().next().unwrap_or_default(),
+    };
+
+    for dev in value.split(';') {
+        let fields: Vec<&str> = dev.splitn(5, ',').collect();
+        if fields.len() != 5 {
+            continue;
+        }
+
+        for table in fields[4].split(',') {
+            let t: Vec<&str> = table.split_ascii_whitespace().collect();
+            if t.len() < 13 || t[2] != VERITY_TARGET {
+                continue;
+            }
+
+            devices.push(VerityDevice {
+                name: fields[0].to_string(),
+                hash_algorithm: t[10].to_string(),
+                root_hash: t[11].to_string(),
+            });
+        }
+    }
+
+    devices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    const TEST_CMDLINE: &str = "console=hvc0 root=/dev/dm-0 dm-mod.create=\"dm-verity,,,ro,0 2048 verity 1 /dev/vda1 /dev/vda2 4096 4096 256 1 sha256 9f8f5c1b2f8a salt\" agent.log=debug";
+
+    #[test]
+    fn test_parse_verity_devices() {
+        let devices = parse_verity_devices(TEST_CMDLINE);
+        assert_eq!(
+            devices,
+            vec![VerityDevice {
+                name: "dm-verity".to_string(),
+                hash_algorithm: "sha256".to_string(),
+                root_hash: "9f8f5c1b2f8a".to_string(),
+            }]
+        );
+
+        assert!(parse_verity_devices("console=hvc0 root=/dev/vda1").is_empty());
+        assert!(
+            parse_verity_devices("dm-mod.create=\"lin,,,ro,0 2048 linear /dev/vda1 0\"").is_empty()
+        );
+    }
+
+    #[test]
+    fn test_get_rootfs_integrity() {
+        let mut cmdline = NamedTempFile::new().unwrap();
+        write!(cmdline, "{}", TEST_CMDLINE).unwrap();
+
+        let test_cases = vec![
+            ("/dev/mapper/dm-verity", "ro,relatime", true),
+            ("/dev/mapper/dm-verity", "rw,relatime", false),
+            ("/dev/mapper/other", "ro,relatime", false),
+            ("/dev/vda1", "ro,relatime", false),
+        ];
+
+        for (source, opts, verified) in test_cases {
+            let mut mountinfo = NamedTempFile::new().unwrap();
+            writeln!(
+                mountinfo,
+                "1 1 254:0 / / {} shared:1 - ext4 {} ro",
+                opts, source
+            )
+            .unwrap();
+
+            let integrity = get_rootfs_integrity(
+                cmdline.path().to_str().unwrap(),
+                mountinfo.path().to_str().unwrap(),
+            )
+            .unwrap();
+
+            assert_eq!(integrity.verified, verified, "{} {}", source, opts);
+            assert_eq!(integrity.device, source);
+            if verified {
+                assert_eq!(integrity.root_hash, "9f8f5c1b2f8a");
+            }
+        }
+    }
+}
@@ -0,0 +1,389 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Watchdog of the virtio-fs mounts. When virtiofsd dies or stalls on the
+// host, the accesses to the mount hang forever, or fail with EIO or
+// ENOTCONN, and the containers whose rootfs or volumes live there hang
+// with them, without anything telling why.
+//
+// With agent.virtiofs_watchdog_interval set, the virtio-fs mounts are
+// statfs'ed every that many seconds, under a timeout. Past
+// FAILURE_THRESHOLD failed checks in a row, a warning is sent to the host
+// and the containers using the mount are reported unhealthy through the
+// probe events, until a check succeeds again.
+//
+// By default the mounts are only reported: all the mounts of a tag share a
+// superblock, which the bind mounts and the mount namespaces of the
+// containers keep, so that mounting the tag again would reuse the dead FUSE
+// connection, and aborting it would fail all the I/O of the containers on a
+// merely slow host. With agent.virtiofs_watchdog_policy=abort, the FUSE
+// connection of a stalled mount is aborted as well, for the sandboxes which
+// would rather have the accesses fail with ENOTCONN than hang: the mount
+// stays dead until the sandbox is restarted.
+
+use anyhow::{anyhow, Context, Result};
+use protocols::agent::ProbeEvent;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::select;
+use tokio::sync::watch::Receiver;
+use tokio::sync::Mutex;
+
+use crate::mountinfo::unescape;
+use crate::sandbox::Sandbox;
+
+// Convenience macro to obtain the scope logger
+macro_rules! sl {
+    () => {
+        slog_scope::logger().new(o!("subsystem" => "virtiofs_watchdog"))
+    };
+}
+
+const MOUNTINFO: &str = "/proc/self/mountinfo";
+const VIRTIOFS_TYPE: &str = "virtiofs";
+const FUSE_CONNECTIONS_DIR: &str = "/sys/fs/fuse/connections";
+const FUSECTL_TYPE: &str = "fusectl";
+
+const POLICY_REPORT: &str = "report";
+const POLICY_ABORT: &str = "abort";
+
+const STATFS_TIMEOUT: Duration = Duration::from_secs(5);
+const FAILURE_THRESHOLD: u32 = 3;
+
+// the source of the warnings, and the probe id of the events
+const WATCHDOG_SOURCE: &str = "VirtioFsWatchdog";
+const WATCHDOG_PROBE_ID: &str = "virtiofs-watchdog";
+
+// WatchdogPolicy tells what is done with a stalled mount.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchdogPolicy {
+    // the stall is reported
+    Report,
+    // the stall is reported and the FUSE connection aborted
+    Abort,
+}
+
+impl Default for WatchdogPolicy {
+    fn default() -> Self {
+        WatchdogPolicy::Report
+    }
+}
+
+impl FromStr for WatchdogPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            POLICY_REPORT => Ok(WatchdogPolicy::Report),
+            POLICY_ABORT => Ok(WatchdogPolicy::Abort),
+            _ => Err(anyhow!("invalid virtio-fs watchdog policy {:?}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct VirtioFsMount {
+    pub mount_point: String,
+    // the tag of the virtio-fs device, i.e. the mount source
+    pub tag: String,
+    // the number of the FUSE connection, under /sys/fs/fuse/connections
+    pub connection: u64,
+}
+
+#[derive(Debug, Default)]
+struct MountState {
+    failures: u32,
+    // the stall was reported, until a check succeeds again
+    stalled: bool,
+    // a statfs is still hung, not to pile up blocked threads
+    busy: Arc<AtomicBool>,
+}
+
+// parse_virtiofs_mounts returns the virtio-fs mounts of a mountinfo
+// content, the bind mounts of subdirectories being skipped.
+pub fn parse_virtiofs_mounts(mountinfo: &str) -> Vec<VirtioFsMount> {
+    let mut mounts = vec![];
+
+    for line in mountinfo.lines() {
+        // "<id> <parent> <major>:<minor> <root> <mount point> <options>
+        // [<optional fields>] - <fs type> <source> <super options>"
+        let (head, tail) = match line.find(" - ") {
+            Some(i) => (&line[..i], &line[i + 3..]),
+            None => continue,
+        };
+
+        let fields: Vec<&str> = head.split_whitespace().collect();
+        let tail: Vec<&str> = tail.split_whitespace().collect();
+        if fields.len() < 6 || tail.len() < 2 || tail[0] != VIRTIOFS_TYPE || fields[3] != "/" {
+            continue;
+        }
+
+        let mut dev = fields[2].splitn(2, ':');
+        let (major, minor) = match (
+            dev.next().and_then(|m| m.parse::<u64>().ok()),
+            dev.next().and_then(|m| m.parse::<u64>().ok()),
+        ) {
+            (Some(major), Some(minor)) => (major, minor),
+            _ => continue,
+        };
+
+        mounts.push(VirtioFsMount {
+            mount_point: unescape(fields[4]),
+            tag: unescape(tail[1]),
+            // the kernel encoding of the device number
+            connection: (major << 20) | minor,
+        });
+    }
+
+    mounts
+}
+
+// uses_mount tells if a container has its rootfs or a volume on the mount.
+fn uses_mount(mount_point: &str, paths: &[&str]) -> bool {
+    paths.iter().any(|p| Path::new(p).starts_with(mount_point))
+}
+
+// check statfs's the mount, failing if it hangs past STATFS_TIMEOUT.
+async fn check(mount: &VirtioFsMount, state: &MountState) -> Result<()> {
+    if state.busy.swap(true, Ordering::SeqCst) {
+        return Err(anyhow!("statfs still hung"));
+    }
+
+    let busy = state.busy.clone();
+    let path = mount.mount_point.clone();
+    let statfs = tokio::task::spawn_blocking(move || {
+        let r = nix::sys::statvfs::statvfs(path.as_str());
+        busy.store(false, Ordering::SeqCst);
+        r
+    });
+
+    match tokio::time::timeout(STATFS_TIMEOUT, statfs).await {
+        Ok(Ok(r)) => r.map(|_| ()).context("statfs failed"),
+        Ok(Err(e)) => Err(anyhow!("statfs task failed: {}", e)),
+        Err(_) => Err(anyhow!("statfs timed out after {:?}", STATFS_TIMEOUT)),
+    }
+}
+
+// abort_connection aborts the FUSE connection of a mount, failing its
+// pending and next requests with ENOTCONN, the fuse control filesystem
+// being mounted on `dir` first if it isn't.
+fn abort_connection(dir: &Path, mount: &VirtioFsMount) -> Result<()> {
+    let conn = dir.join(mount.connection.to_string());
+    if !conn.exists() {
+        nix::mount::mount(
+            Some(FUSECTL_TYPE),
+            dir,
+            Some(FUSECTL_TYPE),
+            nix::mount::MsFlags::empty(),
+            None::<&str>,
+        )
+        .with_context(|| format!("failed to mount {} on {:?}", FUSECTL_TYPE, dir))?;
+    }
+
+    let abort = conn.join("abort");
+    fs::write(&abort, "1").with_context(|| format!("failed to write {:?}", abort))
+}
+
+// report tells the host of the stall of a mount, or of its end, and
+// reports the containers using it unhealthy, or healthy again.
+async fn report(
+    sandbox: &Arc<Mutex<Sandbox>>,
+    mount: &VirtioFsMount,
+    stalled: bool,
+    message: &str,
+) {
+    let s = sandbox.lock().await;
+    s.warnings.add(WATCHDOG_SOURCE, "", message);
+
+    let events = s.probes.event_sender();
+    for (cid, ctr) in s.containers.iter() {
+        let spec = match ctr.config.spec.as_ref() {
+            Some(spec) => spec,
+            None => continue,
+        };
+
+        let paths: Vec<&str> = spec
+            .root
+            .iter()
+            .map(|r| r.path.as_str())
+            .chain(spec.mounts.iter().map(|m| m.source.as_str()))
+            .collect();
+        if !uses_mount(&mount.mount_point, &paths) {
+            continue;
+        }
+
+        let mut ev = ProbeEvent::new();
+        ev.probe_id = WATCHDOG_PROBE_ID.to_string();
+        ev.container_id = cid.clone();
+        ev.healthy = !stalled;
+        ev.message = message.to_string();
+
+        // the events are dropped if nobody reads them
+        let _ = events.try_send(ev);
+    }
+}
+
+async fn check_mounts(
+    sandbox: &Arc<Mutex<Sandbox>>,
+    policy: WatchdogPolicy,
+    states: &mut HashMap<String, MountState>,
+) {
+    let mounts = match fs::read_to_string(MOUNTINFO) {
+        Ok(m) => parse_virtiofs_mounts(&m),
+        Err(e) => {
+            warn!(sl!(), "failed to read the mounts"; "error" => format!("{:?}", e));
+            return;
+        }
+    };
+
+    // the unmounted filesystems are forgotten
+    states.retain(|mp, _| mounts.iter().any(|m| &m.mount_point == mp));
+
+    for mount in mounts.iter() {
+        let state = states.entry(mount.mount_point.clone()).or_default();
+
+        let error = match check(mount, state).await {
+            Ok(()) => {
+                state.failures = 0;
+                if state.stalled {
+                    state.stalled = false;
+                    let message = format!(
+                        "virtio-fs mount {} ({}) recovered",
+                        mount.mount_point, mount.tag
+                    );
+                    report(sandbox, mount, false, &message).await;
+                }
+                continue;
+            }
+            Err(e) => format!("{:?}", e),
+        };
+
+        state.failures += 1;
+        debug!(sl!(), "virtio-fs check failed"; "mount-point" => mount.mount_point.as_str(), "error" => error.as_str());
+        if state.failures < FAILURE_THRESHOLD || state.stalled {
+            continue;
+        }
+
+        state.stalled = true;
+        let mut message = format!(
+            "virtio-fs mount {} ({}, FUSE connection {}) stalled: {}",
+            mount.mount_point, mount.tag, mount.connection, error
+        );
+        if policy == WatchdogPolicy::Abort {
+            match abort_connection(Path::new(FUSE_CONNECTIONS_DIR), mount) {
+                Ok(()) => message.push_str(", connection aborted"),
+                Err(e) => message.push_str(&format!(", failed to abort the connection: {:?}", e)),
+            }
+        }
+        report(sandbox, mount, true, &message).await;
+    }
+}
+
+// run_virtiofs_watchdog checks the virtio-fs mounts every `interval`, with
+// `policy` for the stalled ones, until the agent shuts down.
+pub async fn run_virtiofs_watchdog(
+    sandbox: Arc<Mutex<Sandbox>>,
+    interval: Duration,
+    policy: WatchdogPolicy,
+    mut shutdown: Receiver<bool>,
+) -> Result<()> {
+    let mut ticker = tokio::time::interval(interval);
+    let mut states = HashMap::new();
+
+    loop {
+        select! {
+            _ = shutdown.changed() => {
+                return Ok(());
+            }
+            _ = ticker.tick() => {
+                check_mounts(&sandbox, policy, &mut states).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_MOUNTINFO: &str = "\
+22 1 0:20 / /proc rw,nosuid,nodev,noexec,relatime - proc proc rw
+36 22 0:42 / /run/kata-containers/shared/containers rw,relatime shared:1 - virtiofs kataShared rw
+37 36 0:42 /c1/rootfs /run/kata-containers/c1/rootfs rw,relatime - virtiofs kataShared rw
+38 22 0:43 / /mnt/a\\040b ro,nosuid,relatime - virtiofs vol1 ro,dax
+";
+
+    #[test]
+    fn test_parse_virtiofs_mounts() {
+        let mounts = parse_virtiofs_mounts(TEST_MOUNTINFO);
+        assert_eq!(mounts.len(), 2);
+
+        assert_eq!(
+            mounts[0],
+            VirtioFsMount {
+                mount_point: "/run/kata-containers/shared/containers".to_string(),
+                tag: "kataShared".to_string(),
+                connection: 42,
+            }
+        );
+        assert_eq!(mounts[1].mount_point, "/mnt/a b");
+        assert_eq!(mounts[1].connection, 43);
+
+        assert!(parse_virtiofs_mounts("36 22 0:42 / /mnt rw - virtiofs\n").is_empty());
+        assert!(parse_virtiofs_mounts("36 22 x / /mnt rw - virtiofs tag rw\n").is_empty());
+    }
+
+    #[test]
+    fn test_uses_mount() {
+        let mp = "/run/kata-containers/shared/containers";
+
+        assert!(uses_mount(
+            mp,
+            &["/run/kata-containers/shared/containers/c1/rootfs"]
+        ));
+        assert!(uses_mount(mp, &["proc", mp]));
+        assert!(!uses_mount(
+            mp,
+            &["/run/kata-containers/shared/containers-2/c1"]
+        ));
+        assert!(!uses_mount(mp, &[]));
+    }
+
+    #[test]
+    fn test_watchdog_policy() {
+        assert_eq!(WatchdogPolicy::default(), WatchdogPolicy::Report);
+        assert_eq!(
+            "report".parse::<WatchdogPolicy>().unwrap(),
+            WatchdogPolicy::Report
+        );
+        assert_eq!(
+            "abort".parse::<WatchdogPolicy>().unwrap(),
+            WatchdogPolicy::Abort
+        );
+        assert!("remount".parse::<WatchdogPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_abort_connection() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("42")).unwrap();
+
+        let mount = VirtioFsMount {
+            mount_point: "/run/kata-containers/shared/containers".to_string(),
+            tag: "kataShared".to_string(),
+            connection: 42,
+        };
+        abort_connection(dir.path(), &mount).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("42/abort")).unwrap(),
+            "1"
+        );
+    }
+}
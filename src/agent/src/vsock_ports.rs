@@ -0,0 +1,113 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+
+// The ports handed out to the auxiliary channels of the sandbox, away
+// from the agent server and the ports set on the kernel command line.
+const VSOCK_PORT_RANGE_START: u32 = 2000;
+const VSOCK_PORT_RANGE_END: u32 = 3000;
+
+// VsockPorts tracks the vsock ports of the optional services of the
+// sandbox (debug console, log forwarder, profiling...), so that they
+// don't collide and the runtime can find them.
+#[derive(Debug, Default)]
+pub struct VsockPorts {
+    ports: HashMap<String, u32>,
+}
+
+impl VsockPorts {
+    pub fn new() -> Self {
+        VsockPorts::default()
+    }
+
+    fn owner(&self, port: u32) -> Option<&str> {
+        self.ports
+            .iter()
+            .find(|(_, p)| **p == port)
+            .map(|(s, _)| s.as_str())
+    }
+
+    // reserve records the fixed port of a service.
+    pub fn reserve(&mut self, service: &str, port: u32) -> Result<()> {
+        if let Some(owner) = self.owner(port) {
+            if owner != service {
+                return Err(anyhow!("vsock port {} is used by {}", port, owner));
+            }
+        }
+
+        self.ports.insert(service.to_string(), port);
+
+        Ok(())
+    }
+
+    // allocate returns the port of a service, allocating a free one the
+    // first time.
+    pub fn allocate(&mut self, service: &str) -> Result<u32> {
+        if service.is_empty() {
+            return Err(anyhow!("empty vsock service name"));
+        }
+
+        if let Some(port) = self.ports.get(service) {
+            return Ok(*port);
+        }
+
+        let port = (VSOCK_PORT_RANGE_START..VSOCK_PORT_RANGE_END)
+            .find(|p| self.owner(*p).is_none())
+            .ok_or_else(|| anyhow!("no vsock port left"))?;
+
+        self.ports.insert(service.to_string(), port);
+
+        Ok(port)
+    }
+
+    pub fn release(&mut self, service: &str) -> Option<u32> {
+        self.ports.remove(service)
+    }
+
+    // list returns the services and their port, by port.
+    pub fn list(&self) -> Vec<(String, u32)> {
+        let mut ports: Vec<(String, u32)> =
+            self.ports.iter().map(|(s, p)| (s.clone(), *p)).collect();
+        ports.sort_by_key(|(_, p)| *p);
+        ports
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vsock_ports() {
+        let mut ports = VsockPorts::new();
+
+        ports.reserve("debug_console", 1026).unwrap();
+        ports.reserve("debug_console", 1026).unwrap();
+        assert!(ports.reserve("log", 1026).is_err());
+        ports.reserve("pprof", VSOCK_PORT_RANGE_START).unwrap();
+
+        assert_eq!(ports.allocate("pcap").unwrap(), VSOCK_PORT_RANGE_START + 1);
+        assert_eq!(ports.allocate("pcap").unwrap(), VSOCK_PORT_RANGE_START + 1);
+        assert_eq!(
+            ports.allocate("forwarder").unwrap(),
+            VSOCK_PORT_RANGE_START + 2
+        );
+        assert!(ports.allocate("").is_err());
+
+        assert_eq!(ports.release("pcap"), Some(VSOCK_PORT_RANGE_START + 1));
+        assert_eq!(ports.release("pcap"), None);
+        assert_eq!(ports.allocate("perf").unwrap(), VSOCK_PORT_RANGE_START + 1);
+
+        let list = ports.list();
+        assert_eq!(list.len(), 4);
+        assert_eq!(list[0], ("debug_console".to_string(), 1026));
+        assert_eq!(
+            list[3],
+            ("forwarder".to_string(), VSOCK_PORT_RANGE_START + 2)
+        );
+    }
+}
@@ -0,0 +1,90 @@
+// Copyright (c) 2021 Ant Group
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+
+// Warnings of the requests which succeeded in a degraded way, e.g. with
+// resource settings the guest kernel doesn't support, or without an event
+// watch. They are sent to the host through GetWarning rather than only
+// logged, so that the runtime can show them to the users.
+//
+// The warnings are kept until the host reads them, the ones exceeding the
+// channel size being dropped, so an agent whose host never reads them
+// doesn't grow.
+
+use crate::postmortem;
+use protocols::agent::Warning;
+use std::sync::Arc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::Mutex;
+
+// Convenience macro to obtain the scope logger
+macro_rules! sl {
+    () => {
+        slog_scope::logger().new(o!("subsystem" => "warnings"))
+    };
+}
+
+const WARNING_CHANNEL_SIZE: usize = 100;
+
+#[derive(Debug)]
+pub struct Warnings {
+    tx: Sender<Warning>,
+    pub rx: Arc<Mutex<Receiver<Warning>>>,
+}
+
+impl Warnings {
+    pub fn new() -> Self {
+        let (tx, rx) = channel::<Warning>(WARNING_CHANNEL_SIZE);
+
+        Warnings {
+            tx,
+            rx: Arc::new(Mutex::new(rx)),
+        }
+    }
+
+    // add queues a warning of the request `source`, the name of the RPC,
+    // about the container `cid` if any.
+    pub fn add(&self, source: &str, cid: &str, message: &str) {
+        warn!(sl!(), "{}", message; "source" => source, "container" => cid);
+        postmortem::record_event(postmortem::EVENT_WARNING, cid, message);
+
+        let mut w = Warning::new();
+        w.source = source.to_string();
+        w.container_id = cid.to_string();
+        w.message = message.to_string();
+
+        if let Err(TrySendError::Full(_)) = self.tx.try_send(w) {
+            warn!(sl!(), "too many pending warnings, dropping one");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_warnings() {
+        let w = Warnings::new();
+        w.add("UpdateContainer", "c1", "cpu.rt_period_us: not supported");
+
+        let rx = w.rx.clone();
+        let mut rx = rx.lock().await;
+        let warning = rx.recv().await.unwrap();
+        assert_eq!(warning.source, "UpdateContainer");
+        assert_eq!(warning.container_id, "c1");
+        assert_eq!(warning.message, "cpu.rt_period_us: not supported");
+
+        for i in 0..WARNING_CHANNEL_SIZE + 1 {
+            w.add("CreateContainer", "c1", &i.to_string());
+        }
+        for i in 0..WARNING_CHANNEL_SIZE {
+            assert_eq!(rx.recv().await.unwrap().message, i.to_string());
+        }
+        // the last one was dropped
+        drop(w);
+        assert!(rx.recv().await.is_none());
+    }
+}
@@ -36,11 +36,12 @@ func statsToMetrics(stats *vc.ContainerStats) *cgroupsv1.Metrics {
 
 	if stats.CgroupStats != nil {
 		metrics = &cgroupsv1.Metrics{
-			Hugetlb: setHugetlbStats(stats.CgroupStats.HugetlbStats),
-			Pids:    setPidsStats(stats.CgroupStats.PidsStats),
-			CPU:     setCPUStats(stats.CgroupStats.CPUStats),
-			Memory:  setMemoryStats(stats.CgroupStats.MemoryStats),
-			Blkio:   setBlkioStats(stats.CgroupStats.BlkioStats),
+			Hugetlb:          setHugetlbStats(stats.CgroupStats.HugetlbStats),
+			Pids:             setPidsStats(stats.CgroupStats.PidsStats),
+			CPU:              setCPUStats(stats.CgroupStats.CPUStats),
+			Memory:           setMemoryStats(stats.CgroupStats.MemoryStats),
+			Blkio:            setBlkioStats(stats.CgroupStats.BlkioStats),
+			MemoryOomControl: setMemoryOomControl(stats.CgroupStats.MemoryStats.Events),
 		}
 	}
 
@@ -95,6 +96,23 @@ func setCPUStats(vcCPU vc.CPUStats) *cgroupsv1.CPUStat {
 	return cpuStats
 }
 
+// setMemoryOomControl reports the OOM kills of the memory events, which the
+// older agents don't send.
+func setMemoryOomControl(vcEvents *vc.MemoryEvents) *cgroupsv1.MemoryOomControl {
+	if vcEvents == nil {
+		return nil
+	}
+
+	oomControl := &cgroupsv1.MemoryOomControl{
+		OomKill: vcEvents.OomKill,
+	}
+	if vcEvents.UnderOom {
+		oomControl.UnderOom = 1
+	}
+
+	return oomControl
+}
+
 func setMemoryStats(vcMemory vc.MemoryStats) *cgroupsv1.MemoryStat {
 	memoryStats := &cgroupsv1.MemoryStat{
 		Usage: &cgroupsv1.MemoryEntry{
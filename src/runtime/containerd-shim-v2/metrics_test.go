@@ -57,3 +57,23 @@ func TestStatNetworkMetric(t *testing.T) {
 	metrics := statsToMetrics(&resp)
 	assert.Equal(expectedNetwork, metrics.Network)
 }
+
+func TestStatMemoryOomControl(t *testing.T) {
+	assert := assert.New(t)
+
+	stats := vc.ContainerStats{
+		CgroupStats: &vc.CgroupStats{},
+	}
+	assert.Nil(statsToMetrics(&stats).MemoryOomControl)
+
+	stats.CgroupStats.MemoryStats.Events = &vc.MemoryEvents{
+		Oom:      3,
+		OomKill:  2,
+		UnderOom: true,
+	}
+	expected := &v1.MemoryOomControl{
+		UnderOom: 1,
+		OomKill:  2,
+	}
+	assert.Equal(expected, statsToMetrics(&stats).MemoryOomControl)
+}
@@ -155,6 +155,20 @@ type MemoryStats struct {
 	UseHierarchy bool `json:"use_hierarchy"`
 
 	Stats map[string]uint64 `json:"stats,omitempty"`
+	// memory events of the cgroup, nil with an older agent
+	Events *MemoryEvents `json:"events,omitempty"`
+}
+
+// MemoryEvents counts the memory events of a cgroup since it was created
+type MemoryEvents struct {
+	// OOMs the limit of the cgroup caused, and the processes they killed
+	Oom     uint64 `json:"oom,omitempty"`
+	OomKill uint64 `json:"oom_kill,omitempty"`
+	// if true, the cgroup is under OOM, only known with cgroup v1
+	UnderOom bool `json:"under_oom,omitempty"`
+	// throttlings over memory.high, and hits of the limit
+	High uint64 `json:"high,omitempty"`
+	Max  uint64 `json:"max,omitempty"`
 }
 
 // PidsStats describes the pids stats
@@ -856,7 +856,19 @@ type LinuxMemory struct {
 	// How aggressive the kernel will swap memory pages.
 	Swappiness uint64 `protobuf:"varint,6,opt,name=Swappiness,json=swappiness,proto3" json:"Swappiness,omitempty"`
 	// DisableOOMKiller disables the OOM killer for out of memory conditions
-	DisableOOMKiller     bool     `protobuf:"varint,7,opt,name=DisableOOMKiller,json=disableOOMKiller,proto3" json:"DisableOOMKiller,omitempty"`
+	DisableOOMKiller bool `protobuf:"varint,7,opt,name=DisableOOMKiller,json=disableOOMKiller,proto3" json:"DisableOOMKiller,omitempty"`
+	// Memory usage throttle limit of cgroup v2 (in bytes).
+	High int64 `protobuf:"varint,8,opt,name=High,json=high,proto3" json:"High,omitempty"`
+	// Whether the fields above are set, as 0 is a value of its own, e.g. a
+	// swappiness of 0 avoiding the swap. The runtimes sending none of them
+	// only send the values which are not 0.
+	LimitSet             bool     `protobuf:"varint,9,opt,name=LimitSet,json=limitSet,proto3" json:"LimitSet,omitempty"`
+	ReservationSet       bool     `protobuf:"varint,10,opt,name=ReservationSet,json=reservationSet,proto3" json:"ReservationSet,omitempty"`
+	SwapSet              bool     `protobuf:"varint,11,opt,name=SwapSet,json=swapSet,proto3" json:"SwapSet,omitempty"`
+	KernelSet            bool     `protobuf:"varint,12,opt,name=KernelSet,json=kernelSet,proto3" json:"KernelSet,omitempty"`
+	KernelTCPSet         bool     `protobuf:"varint,13,opt,name=KernelTCPSet,json=kernelTCPSet,proto3" json:"KernelTCPSet,omitempty"`
+	SwappinessSet        bool     `protobuf:"varint,14,opt,name=SwappinessSet,json=swappinessSet,proto3" json:"SwappinessSet,omitempty"`
+	HighSet              bool     `protobuf:"varint,15,opt,name=HighSet,json=highSet,proto3" json:"HighSet,omitempty"`
 	XXX_NoUnkeyedLiteral struct{} `json:"-"`
 	XXX_unrecognized     []byte   `json:"-"`
 	XXX_sizecache        int32    `json:"-"`
@@ -2519,6 +2531,30 @@ func (this *LinuxMemory) Equal(that interface{}) bool {
 	if this.DisableOOMKiller != that1.DisableOOMKiller {
 		return false
 	}
+	if this.High != that1.High {
+		return false
+	}
+	if this.LimitSet != that1.LimitSet {
+		return false
+	}
+	if this.ReservationSet != that1.ReservationSet {
+		return false
+	}
+	if this.SwapSet != that1.SwapSet {
+		return false
+	}
+	if this.KernelSet != that1.KernelSet {
+		return false
+	}
+	if this.KernelTCPSet != that1.KernelTCPSet {
+		return false
+	}
+	if this.SwappinessSet != that1.SwappinessSet {
+		return false
+	}
+	if this.HighSet != that1.HighSet {
+		return false
+	}
 	if !bytes.Equal(this.XXX_unrecognized, that1.XXX_unrecognized) {
 		return false
 	}
@@ -4319,6 +4355,81 @@ func (m *LinuxMemory) MarshalToSizedBuffer(dAtA []byte) (int, error) {
 		i -= len(m.XXX_unrecognized)
 		copy(dAtA[i:], m.XXX_unrecognized)
 	}
+	if m.HighSet {
+		i--
+		if m.HighSet {
+			dAtA[i] = 1
+		} else {
+			dAtA[i] = 0
+		}
+		i--
+		dAtA[i] = 0x78
+	}
+	if m.SwappinessSet {
+		i--
+		if m.SwappinessSet {
+			dAtA[i] = 1
+		} else {
+			dAtA[i] = 0
+		}
+		i--
+		dAtA[i] = 0x70
+	}
+	if m.KernelTCPSet {
+		i--
+		if m.KernelTCPSet {
+			dAtA[i] = 1
+		} else {
+			dAtA[i] = 0
+		}
+		i--
+		dAtA[i] = 0x68
+	}
+	if m.KernelSet {
+		i--
+		if m.KernelSet {
+			dAtA[i] = 1
+		} else {
+			dAtA[i] = 0
+		}
+		i--
+		dAtA[i] = 0x60
+	}
+	if m.SwapSet {
+		i--
+		if m.SwapSet {
+			dAtA[i] = 1
+		} else {
+			dAtA[i] = 0
+		}
+		i--
+		dAtA[i] = 0x58
+	}
+	if m.ReservationSet {
+		i--
+		if m.ReservationSet {
+			dAtA[i] = 1
+		} else {
+			dAtA[i] = 0
+		}
+		i--
+		dAtA[i] = 0x50
+	}
+	if m.LimitSet {
+		i--
+		if m.LimitSet {
+			dAtA[i] = 1
+		} else {
+			dAtA[i] = 0
+		}
+		i--
+		dAtA[i] = 0x48
+	}
+	if m.High != 0 {
+		i = encodeVarintOci(dAtA, i, uint64(m.High))
+		i--
+		dAtA[i] = 0x40
+	}
 	if m.DisableOOMKiller {
 		i--
 		if m.DisableOOMKiller {
@@ -5508,8 +5619,19 @@ func NewPopulatedLinuxMemory(r randyOci, easy bool) *LinuxMemory {
 	}
 	this.Swappiness = uint64(uint64(r.Uint32()))
 	this.DisableOOMKiller = bool(bool(r.Intn(2) == 0))
+	this.High = int64(r.Int63())
+	if r.Intn(2) == 0 {
+		this.High *= -1
+	}
+	this.LimitSet = bool(bool(r.Intn(2) == 0))
+	this.ReservationSet = bool(bool(r.Intn(2) == 0))
+	this.SwapSet = bool(bool(r.Intn(2) == 0))
+	this.KernelSet = bool(bool(r.Intn(2) == 0))
+	this.KernelTCPSet = bool(bool(r.Intn(2) == 0))
+	this.SwappinessSet = bool(bool(r.Intn(2) == 0))
+	this.HighSet = bool(bool(r.Intn(2) == 0))
 	if !easy && r.Intn(10) != 0 {
-		this.XXX_unrecognized = randUnrecognizedOci(r, 8)
+		this.XXX_unrecognized = randUnrecognizedOci(r, 16)
 	}
 	return this
 }
@@ -6439,6 +6561,30 @@ func (m *LinuxMemory) Size() (n int) {
 	if m.DisableOOMKiller {
 		n += 2
 	}
+	if m.High != 0 {
+		n += 1 + sovOci(uint64(m.High))
+	}
+	if m.LimitSet {
+		n += 2
+	}
+	if m.ReservationSet {
+		n += 2
+	}
+	if m.SwapSet {
+		n += 2
+	}
+	if m.KernelSet {
+		n += 2
+	}
+	if m.KernelTCPSet {
+		n += 2
+	}
+	if m.SwappinessSet {
+		n += 2
+	}
+	if m.HighSet {
+		n += 2
+	}
 	if m.XXX_unrecognized != nil {
 		n += len(m.XXX_unrecognized)
 	}
@@ -7137,6 +7283,14 @@ func (this *LinuxMemory) String() string {
 		`KernelTCP:` + fmt.Sprintf("%v", this.KernelTCP) + `,`,
 		`Swappiness:` + fmt.Sprintf("%v", this.Swappiness) + `,`,
 		`DisableOOMKiller:` + fmt.Sprintf("%v", this.DisableOOMKiller) + `,`,
+		`High:` + fmt.Sprintf("%v", this.High) + `,`,
+		`LimitSet:` + fmt.Sprintf("%v", this.LimitSet) + `,`,
+		`ReservationSet:` + fmt.Sprintf("%v", this.ReservationSet) + `,`,
+		`SwapSet:` + fmt.Sprintf("%v", this.SwapSet) + `,`,
+		`KernelSet:` + fmt.Sprintf("%v", this.KernelSet) + `,`,
+		`KernelTCPSet:` + fmt.Sprintf("%v", this.KernelTCPSet) + `,`,
+		`SwappinessSet:` + fmt.Sprintf("%v", this.SwappinessSet) + `,`,
+		`HighSet:` + fmt.Sprintf("%v", this.HighSet) + `,`,
 		`XXX_unrecognized:` + fmt.Sprintf("%v", this.XXX_unrecognized) + `,`,
 		`}`,
 	}, "")
@@ -11139,6 +11293,165 @@ func (m *LinuxMemory) Unmarshal(dAtA []byte) error {
 				}
 			}
 			m.DisableOOMKiller = bool(v != 0)
+		case 8:
+			if wireType != 0 {
+				return fmt.Errorf("proto: wrong wireType = %d for field High", wireType)
+			}
+			m.High = 0
+			for shift := uint(0); ; shift += 7 {
+				if shift >= 64 {
+					return ErrIntOverflowOci
+				}
+				if iNdEx >= l {
+					return io.ErrUnexpectedEOF
+				}
+				b := dAtA[iNdEx]
+				iNdEx++
+				m.High |= int64(b&0x7F) << shift
+				if b < 0x80 {
+					break
+				}
+			}
+		case 9:
+			if wireType != 0 {
+				return fmt.Errorf("proto: wrong wireType = %d for field LimitSet", wireType)
+			}
+			var v int
+			for shift := uint(0); ; shift += 7 {
+				if shift >= 64 {
+					return ErrIntOverflowOci
+				}
+				if iNdEx >= l {
+					return io.ErrUnexpectedEOF
+				}
+				b := dAtA[iNdEx]
+				iNdEx++
+				v |= int(b&0x7F) << shift
+				if b < 0x80 {
+					break
+				}
+			}
+			m.LimitSet = bool(v != 0)
+		case 10:
+			if wireType != 0 {
+				return fmt.Errorf("proto: wrong wireType = %d for field ReservationSet", wireType)
+			}
+			var v int
+			for shift := uint(0); ; shift += 7 {
+				if shift >= 64 {
+					return ErrIntOverflowOci
+				}
+				if iNdEx >= l {
+					return io.ErrUnexpectedEOF
+				}
+				b := dAtA[iNdEx]
+				iNdEx++
+				v |= int(b&0x7F) << shift
+				if b < 0x80 {
+					break
+				}
+			}
+			m.ReservationSet = bool(v != 0)
+		case 11:
+			if wireType != 0 {
+				return fmt.Errorf("proto: wrong wireType = %d for field SwapSet", wireType)
+			}
+			var v int
+			for shift := uint(0); ; shift += 7 {
+				if shift >= 64 {
+					return ErrIntOverflowOci
+				}
+				if iNdEx >= l {
+					return io.ErrUnexpectedEOF
+				}
+				b := dAtA[iNdEx]
+				iNdEx++
+				v |= int(b&0x7F) << shift
+				if b < 0x80 {
+					break
+				}
+			}
+			m.SwapSet = bool(v != 0)
+		case 12:
+			if wireType != 0 {
+				return fmt.Errorf("proto: wrong wireType = %d for field KernelSet", wireType)
+			}
+			var v int
+			for shift := uint(0); ; shift += 7 {
+				if shift >= 64 {
+					return ErrIntOverflowOci
+				}
+				if iNdEx >= l {
+					return io.ErrUnexpectedEOF
+				}
+				b := dAtA[iNdEx]
+				iNdEx++
+				v |= int(b&0x7F) << shift
+				if b < 0x80 {
+					break
+				}
+			}
+			m.KernelSet = bool(v != 0)
+		case 13:
+			if wireType != 0 {
+				return fmt.Errorf("proto: wrong wireType = %d for field KernelTCPSet", wireType)
+			}
+			var v int
+			for shift := uint(0); ; shift += 7 {
+				if shift >= 64 {
+					return ErrIntOverflowOci
+				}
+				if iNdEx >= l {
+					return io.ErrUnexpectedEOF
+				}
+				b := dAtA[iNdEx]
+				iNdEx++
+				v |= int(b&0x7F) << shift
+				if b < 0x80 {
+					break
+				}
+			}
+			m.KernelTCPSet = bool(v != 0)
+		case 14:
+			if wireType != 0 {
+				return fmt.Errorf("proto: wrong wireType = %d for field SwappinessSet", wireType)
+			}
+			var v int
+			for shift := uint(0); ; shift += 7 {
+				if shift >= 64 {
+					return ErrIntOverflowOci
+				}
+				if iNdEx >= l {
+					return io.ErrUnexpectedEOF
+				}
+				b := dAtA[iNdEx]
+				iNdEx++
+				v |= int(b&0x7F) << shift
+				if b < 0x80 {
+					break
+				}
+			}
+			m.SwappinessSet = bool(v != 0)
+		case 15:
+			if wireType != 0 {
+				return fmt.Errorf("proto: wrong wireType = %d for field HighSet", wireType)
+			}
+			var v int
+			for shift := uint(0); ; shift += 7 {
+				if shift >= 64 {
+					return ErrIntOverflowOci
+				}
+				if iNdEx >= l {
+					return io.ErrUnexpectedEOF
+				}
+				b := dAtA[iNdEx]
+				iNdEx++
+				v |= int(b&0x7F) << shift
+				if b < 0x80 {
+					break
+				}
+			}
+			m.HighSet = bool(v != 0)
 		default:
 			iNdEx = preIndex
 			skippy, err := skipOci(dAtA[iNdEx:])
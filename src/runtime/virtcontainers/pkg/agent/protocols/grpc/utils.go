@@ -210,9 +210,20 @@ func copyStructValue(to, from reflect.Value) error {
 			continue
 		}
 
+		// The gRPC structures tell a field set to 0 from an unset one by
+		// a <field>Set flag, which stands for a non nil OCI pointer.
+		if set, err := findStructField(from, fieldName+"Set"); err == nil &&
+			set.Kind() == reflect.Bool && !set.Bool() && v.IsZero() {
+			continue
+		}
+
 		if err := copyValue(to.Field(i), v); err != nil {
 			return err
 		}
+
+		if set, err := findStructField(to, fieldName+"Set"); err == nil && set.Kind() == reflect.Bool {
+			set.SetBool(v.Kind() != reflect.Ptr || !v.IsNil())
+		}
 	}
 
 	return nil
@@ -128,6 +128,35 @@ func TestProcessGRPCtoOCI(t *testing.T) {
 	assertProcessIsEqual(t, newOciProcess, grpcProcess)
 }
 
+func TestResourcesMemoryPresence(t *testing.T) {
+	assert := assert.New(t)
+
+	limit := int64(1 << 30)
+	swappiness := uint64(0)
+	ociResources := specs.LinuxResources{
+		Memory: &specs.LinuxMemory{
+			Limit:      &limit,
+			Swappiness: &swappiness,
+		},
+	}
+
+	grpcResources, err := ResourcesOCItoGRPC(&ociResources)
+	assert.NoError(err)
+	memory := grpcResources.Memory
+	assert.Equal(limit, memory.Limit)
+	assert.True(memory.LimitSet)
+	// an explicit 0 is told apart from an unset value
+	assert.True(memory.SwappinessSet)
+	assert.False(memory.ReservationSet)
+	assert.False(memory.SwapSet)
+
+	newOciResources, err := ResourcesGRPCtoOCI(grpcResources)
+	assert.NoError(err)
+	assert.Equal(ociResources.Memory.Limit, newOciResources.Memory.Limit)
+	assert.Equal(ociResources.Memory.Swappiness, newOciResources.Memory.Swappiness)
+	assert.Nil(newOciResources.Memory.Reservation)
+}
+
 func testCopyValue(t *testing.T, to, from interface{}) {
 	assert := assert.New(t)
 
@@ -10,11 +10,13 @@ use crate::utils;
 use anyhow::{anyhow, Result};
 use byteorder::ByteOrder;
 use nix::sys::socket::{connect, socket, AddressFamily, SockAddr, SockFlag, SockType, UnixAddr};
+use protobuf::RepeatedField;
 use protocols::agent::*;
 use protocols::agent_ttrpc::*;
 use protocols::health::*;
 use protocols::health_ttrpc::*;
 use slog::{debug, info};
+use std::fs::File;
 use std::io;
 use std::io::Write; // XXX: for flush()
 use std::os::unix::io::{IntoRawFd, RawFd};
@@ -87,6 +89,26 @@ static AGENT_CMDS: &'static [AgentCmd] = &[
         st: ServiceType::Agent,
         fp: agent_cmd_sandbox_add_arp_neighbors,
     },
+    AgentCmd {
+        name: "AddDeviceRule",
+        st: ServiceType::Agent,
+        fp: agent_cmd_container_add_device_rule,
+    },
+    AgentCmd {
+        name: "AddFsWatch",
+        st: ServiceType::Agent,
+        fp: agent_cmd_container_add_fs_watch,
+    },
+    AgentCmd {
+        name: "AddProbe",
+        st: ServiceType::Agent,
+        fp: agent_cmd_container_add_probe,
+    },
+    AgentCmd {
+        name: "AllocateVsockPort",
+        st: ServiceType::Agent,
+        fp: agent_cmd_sandbox_allocate_vsock_port,
+    },
     AgentCmd {
         name: "Check",
         st: ServiceType::Health,
@@ -122,11 +144,26 @@ static AGENT_CMDS: &'static [AgentCmd] = &[
         st: ServiceType::Agent,
         fp: agent_cmd_sandbox_destroy,
     },
+    AgentCmd {
+        name: "DumpProcessMemory",
+        st: ServiceType::Agent,
+        fp: agent_cmd_container_dump_memory,
+    },
     AgentCmd {
         name: "ExecProcess",
         st: ServiceType::Agent,
         fp: agent_cmd_container_exec,
     },
+    AgentCmd {
+        name: "GetCgroupEvent",
+        st: ServiceType::Agent,
+        fp: agent_cmd_sandbox_get_cgroup_event,
+    },
+    AgentCmd {
+        name: "GetFsEvent",
+        st: ServiceType::Agent,
+        fp: agent_cmd_sandbox_get_fs_event,
+    },
     AgentCmd {
         name: "GetGuestDetails",
         st: ServiceType::Agent,
@@ -137,11 +174,41 @@ static AGENT_CMDS: &'static [AgentCmd] = &[
         st: ServiceType::Agent,
         fp: agent_cmd_sandbox_get_metrics,
     },
+    AgentCmd {
+        name: "GetMetricsPush",
+        st: ServiceType::Agent,
+        fp: agent_cmd_sandbox_get_metrics_push,
+    },
     AgentCmd {
         name: "GetOOMEvent",
         st: ServiceType::Agent,
         fp: agent_cmd_sandbox_get_oom_event,
     },
+    AgentCmd {
+        name: "GetProbeEvent",
+        st: ServiceType::Agent,
+        fp: agent_cmd_sandbox_get_probe_event,
+    },
+    AgentCmd {
+        name: "GetUsageHistory",
+        st: ServiceType::Agent,
+        fp: agent_cmd_container_get_usage_history,
+    },
+    AgentCmd {
+        name: "GetWarning",
+        st: ServiceType::Agent,
+        fp: agent_cmd_sandbox_get_warning,
+    },
+    AgentCmd {
+        name: "ListContainerMounts",
+        st: ServiceType::Agent,
+        fp: agent_cmd_container_list_mounts,
+    },
+    AgentCmd {
+        name: "ListDeviceRules",
+        st: ServiceType::Agent,
+        fp: agent_cmd_container_list_device_rules,
+    },
     AgentCmd {
         name: "ListInterfaces",
         st: ServiceType::Agent,
@@ -152,11 +219,21 @@ static AGENT_CMDS: &'static [AgentCmd] = &[
         st: ServiceType::Agent,
         fp: agent_cmd_sandbox_list_routes,
     },
+    AgentCmd {
+        name: "ListVsockPorts",
+        st: ServiceType::Agent,
+        fp: agent_cmd_sandbox_list_vsock_ports,
+    },
     AgentCmd {
         name: "MemHotplugByProbe",
         st: ServiceType::Agent,
         fp: agent_cmd_sandbox_mem_hotplug_by_probe,
     },
+    AgentCmd {
+        name: "NegotiateAPIVersion",
+        st: ServiceType::Agent,
+        fp: agent_cmd_sandbox_negotiate_api_version,
+    },
     AgentCmd {
         name: "OnlineCPUMem",
         st: ServiceType::Agent,
@@ -177,21 +254,56 @@ static AGENT_CMDS: &'static [AgentCmd] = &[
         st: ServiceType::Agent,
         fp: agent_cmd_container_read_stdout,
     },
+    AgentCmd {
+        name: "RemoveDeviceRule",
+        st: ServiceType::Agent,
+        fp: agent_cmd_container_remove_device_rule,
+    },
+    AgentCmd {
+        name: "RemoveFsWatch",
+        st: ServiceType::Agent,
+        fp: agent_cmd_container_remove_fs_watch,
+    },
+    AgentCmd {
+        name: "RemoveProbe",
+        st: ServiceType::Agent,
+        fp: agent_cmd_container_remove_probe,
+    },
     AgentCmd {
         name: "ReseedRandomDev",
         st: ServiceType::Agent,
         fp: agent_cmd_sandbox_reseed_random_dev,
     },
+    AgentCmd {
+        name: "ReleaseVsockPort",
+        st: ServiceType::Agent,
+        fp: agent_cmd_sandbox_release_vsock_port,
+    },
     AgentCmd {
         name: "RemoveContainer",
         st: ServiceType::Agent,
         fp: agent_cmd_container_remove,
     },
+    AgentCmd {
+        name: "ReniceContainer",
+        st: ServiceType::Agent,
+        fp: agent_cmd_container_renice,
+    },
     AgentCmd {
         name: "ResumeContainer",
         st: ServiceType::Agent,
         fp: agent_cmd_container_resume,
     },
+    AgentCmd {
+        name: "SetGuestClock",
+        st: ServiceType::Agent,
+        fp: agent_cmd_sandbox_set_guest_clock,
+    },
+    AgentCmd {
+        name: "SetMetricsPush",
+        st: ServiceType::Agent,
+        fp: agent_cmd_sandbox_set_metrics_push,
+    },
     AgentCmd {
         name: "SetGuestDateTime",
         st: ServiceType::Agent,
@@ -207,6 +319,11 @@ static AGENT_CMDS: &'static [AgentCmd] = &[
         st: ServiceType::Agent,
         fp: agent_cmd_container_start,
     },
+    AgentCmd {
+        name: "StartContainers",
+        st: ServiceType::Agent,
+        fp: agent_cmd_container_start_all,
+    },
     AgentCmd {
         name: "StartTracing",
         st: ServiceType::Agent,
@@ -217,6 +334,16 @@ static AGENT_CMDS: &'static [AgentCmd] = &[
         st: ServiceType::Agent,
         fp: agent_cmd_container_stats,
     },
+    AgentCmd {
+        name: "StatsContainers",
+        st: ServiceType::Agent,
+        fp: agent_cmd_container_stats_all,
+    },
+    AgentCmd {
+        name: "StatsSandbox",
+        st: ServiceType::Agent,
+        fp: agent_cmd_sandbox_stats,
+    },
     AgentCmd {
         name: "StopTracing",
         st: ServiceType::Agent,
@@ -242,6 +369,11 @@ static AGENT_CMDS: &'static [AgentCmd] = &[
         st: ServiceType::Agent,
         fp: agent_cmd_sandbox_update_routes,
     },
+    AgentCmd {
+        name: "ValidateResources",
+        st: ServiceType::Agent,
+        fp: agent_cmd_container_validate_resources,
+    },
     AgentCmd {
         name: "WaitProcess",
         st: ServiceType::Agent,
@@ -834,13 +966,16 @@ fn agent_cmd_sandbox_destroy(
     ctx: &Context,
     client: &AgentServiceClient,
     _health: &HealthClient,
-    _options: &mut Options,
-    _args: &str,
+    options: &mut Options,
+    args: &str,
 ) -> Result<()> {
-    let req = DestroySandboxRequest::default();
+    let mut req = DestroySandboxRequest::default();
 
     let ctx = clone_context(ctx);
 
+    let sid = utils::get_option("sid", options, args);
+    req.set_sandbox_id(sid);
+
     debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
 
     let reply = client
@@ -866,6 +1001,7 @@ fn agent_cmd_container_create(
 
     let cid = utils::get_option("cid", options, args);
     let exec_id = utils::get_option("exec_id", options, args);
+    let sid = utils::get_option("sid", options, args);
 
     // FIXME: container create: add back "spec=file:///" support
 
@@ -873,6 +1009,7 @@ fn agent_cmd_container_create(
 
     req.set_container_id(cid);
     req.set_exec_id(exec_id);
+    req.set_sandbox_id(sid);
     req.set_OCI(grpc_spec);
 
     debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
@@ -987,6 +1124,60 @@ fn agent_cmd_container_stats(
     Ok(())
 }
 
+fn agent_cmd_sandbox_stats(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    _options: &mut Options,
+    _args: &str,
+) -> Result<()> {
+    let req = StatsSandboxRequest::default();
+
+    let ctx = clone_context(ctx);
+
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+    let reply = client
+        .stats_sandbox(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
+
+    Ok(())
+}
+
+fn agent_cmd_container_stats_all(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    options: &mut Options,
+    args: &str,
+) -> Result<()> {
+    let mut req = StatsContainersRequest::default();
+
+    let ctx = clone_context(ctx);
+
+    // comma separated container ids, all the containers if unset
+    let cids = utils::get_option("cids", options, args);
+    if cids != "" {
+        req.set_container_ids(RepeatedField::from_vec(
+            cids.split(',').map(|s| s.to_string()).collect(),
+        ));
+    }
+
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+    let reply = client
+        .stats_containers(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
+
+    Ok(())
+}
+
 fn agent_cmd_container_pause(
     ctx: &Context,
     client: &AgentServiceClient,
@@ -1041,6 +1232,37 @@ fn agent_cmd_container_resume(
     Ok(())
 }
 
+fn agent_cmd_container_renice(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    options: &mut Options,
+    args: &str,
+) -> Result<()> {
+    let mut req = ReniceContainerRequest::default();
+
+    let ctx = clone_context(ctx);
+
+    let cid = utils::get_option("cid", options, args);
+    let nice = utils::get_option("nice", options, args)
+        .parse::<i32>()
+        .map_err(|e| anyhow!(e).context("invalid nice"))?;
+
+    req.set_container_id(cid);
+    req.set_nice(nice);
+
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+    let reply = client
+        .renice_container(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
+
+    Ok(())
+}
+
 fn agent_cmd_container_start(
     ctx: &Context,
     client: &AgentServiceClient,
@@ -1068,6 +1290,35 @@ fn agent_cmd_container_start(
     Ok(())
 }
 
+fn agent_cmd_container_start_all(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    options: &mut Options,
+    args: &str,
+) -> Result<()> {
+    let mut req = StartContainersRequest::default();
+
+    let ctx = clone_context(ctx);
+
+    // comma separated container ids, started in that order
+    let cids = utils::get_option("cids", options, args);
+    req.set_container_ids(RepeatedField::from_vec(
+        cids.split(',').map(|s| s.to_string()).collect(),
+    ));
+
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+    let reply = client
+        .start_containers(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
+
+    Ok(())
+}
+
 fn agent_cmd_sandbox_get_guest_details(
     ctx: &Context,
     client: &AgentServiceClient,
@@ -1494,10 +1745,19 @@ fn agent_cmd_sandbox_get_metrics(
     ctx: &Context,
     client: &AgentServiceClient,
     _health: &HealthClient,
-    _options: &mut Options,
-    _args: &str,
+    options: &mut Options,
+    args: &str,
 ) -> Result<()> {
-    let req = GetMetricsRequest::default();
+    let mut req = GetMetricsRequest::default();
+
+    let collectors = utils::get_option("collectors", options, args);
+    req.set_collectors(RepeatedField::from_vec(
+        collectors
+            .split(',')
+            .filter(|c| !c.is_empty())
+            .map(|c| c.to_string())
+            .collect(),
+    ));
 
     let ctx = clone_context(ctx);
 
@@ -1513,21 +1773,44 @@ fn agent_cmd_sandbox_get_metrics(
     Ok(())
 }
 
-fn agent_cmd_sandbox_get_oom_event(
+fn agent_cmd_sandbox_set_metrics_push(
     ctx: &Context,
     client: &AgentServiceClient,
     _health: &HealthClient,
-    _options: &mut Options,
-    _args: &str,
+    options: &mut Options,
+    args: &str,
 ) -> Result<()> {
-    let req = GetOOMEventRequest::default();
+    let mut req = SetMetricsPushRequest::default();
+
+    let period = utils::get_option("period", options, args)
+        .parse::<u32>()
+        .map_err(|e| anyhow!(e).context("invalid period"))?;
+    req.set_period_seconds(period);
+
+    let max_pending = utils::get_option("max_pending", options, args);
+    if !max_pending.is_empty() {
+        req.set_max_pending(
+            max_pending
+                .parse::<u32>()
+                .map_err(|e| anyhow!(e).context("invalid max_pending"))?,
+        );
+    }
+
+    let collectors = utils::get_option("collectors", options, args);
+    req.mut_request().set_collectors(RepeatedField::from_vec(
+        collectors
+            .split(',')
+            .filter(|c| !c.is_empty())
+            .map(|c| c.to_string())
+            .collect(),
+    ));
 
     let ctx = clone_context(ctx);
 
     debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
 
     let reply = client
-        .get_oom_event(ctx, &req)
+        .set_metrics_push(ctx, &req)
         .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
 
     info!(sl!(), "response received";
@@ -1536,58 +1819,547 @@ fn agent_cmd_sandbox_get_oom_event(
     Ok(())
 }
 
-fn agent_cmd_sandbox_copy_file(
+fn agent_cmd_sandbox_get_metrics_push(
     ctx: &Context,
     client: &AgentServiceClient,
     _health: &HealthClient,
-    options: &mut Options,
-    args: &str,
+    _options: &mut Options,
+    _args: &str,
 ) -> Result<()> {
-    let mut req = CopyFileRequest::default();
+    let req = GetMetricsPushRequest::default();
 
     let ctx = clone_context(ctx);
 
-    let path = utils::get_option("path", options, args);
-    if path != "" {
-        req.set_path(path);
-    }
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
 
-    let file_size_str = utils::get_option("file_size", options, args);
+    let reply = client
+        .get_metrics_push(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
 
-    if file_size_str != "" {
-        let file_size = file_size_str
-            .parse::<i64>()
-            .map_err(|e| anyhow!(e).context("invalid file_size"))?;
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
 
-        req.set_file_size(file_size);
-    }
+    Ok(())
+}
 
-    let file_mode_str = utils::get_option("file_mode", options, args);
+fn agent_cmd_sandbox_get_oom_event(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    _options: &mut Options,
+    _args: &str,
+) -> Result<()> {
+    let req = GetOOMEventRequest::default();
 
-    if file_mode_str != "" {
-        let file_mode = file_mode_str
-            .parse::<u32>()
-            .map_err(|e| anyhow!(e).context("invalid file_mode"))?;
+    let ctx = clone_context(ctx);
 
-        req.set_file_mode(file_mode);
-    }
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
 
-    let dir_mode_str = utils::get_option("dir_mode", options, args);
+    let reply = client
+        .get_oom_event(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
 
-    if dir_mode_str != "" {
-        let dir_mode = dir_mode_str
-            .parse::<u32>()
-            .map_err(|e| anyhow!(e).context("invalid dir_mode"))?;
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
 
-        req.set_dir_mode(dir_mode);
-    }
+    Ok(())
+}
 
-    let uid_str = utils::get_option("uid", options, args);
+fn agent_cmd_sandbox_get_cgroup_event(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    _options: &mut Options,
+    _args: &str,
+) -> Result<()> {
+    let req = GetCgroupEventRequest::default();
 
-    if uid_str != "" {
-        let uid = uid_str
-            .parse::<i32>()
-            .map_err(|e| anyhow!(e).context("invalid uid"))?;
+    let ctx = clone_context(ctx);
+
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+    let reply = client
+        .get_cgroup_event(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
+
+    Ok(())
+}
+
+fn agent_cmd_sandbox_negotiate_api_version(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    _options: &mut Options,
+    _args: &str,
+) -> Result<()> {
+    let mut req = NegotiateAPIVersionRequest::default();
+
+    req.set_version(protocols::version::api_version());
+    req.set_features(RepeatedField::from_vec(
+        protocols::version::FEATURES
+            .iter()
+            .map(|f| f.to_string())
+            .collect(),
+    ));
+
+    let ctx = clone_context(ctx);
+
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+    let reply = client
+        .negotiate_api_version(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
+
+    Ok(())
+}
+
+fn agent_cmd_sandbox_allocate_vsock_port(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    options: &mut Options,
+    args: &str,
+) -> Result<()> {
+    let mut req = AllocateVsockPortRequest::default();
+
+    let ctx = clone_context(ctx);
+
+    let service = utils::get_option("service", options, args);
+    req.set_service(service);
+
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+    let reply = client
+        .allocate_vsock_port(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
+
+    Ok(())
+}
+
+fn agent_cmd_sandbox_release_vsock_port(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    options: &mut Options,
+    args: &str,
+) -> Result<()> {
+    let mut req = ReleaseVsockPortRequest::default();
+
+    let ctx = clone_context(ctx);
+
+    let service = utils::get_option("service", options, args);
+    req.set_service(service);
+
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+    let reply = client
+        .release_vsock_port(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
+
+    Ok(())
+}
+
+fn agent_cmd_sandbox_list_vsock_ports(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    _options: &mut Options,
+    _args: &str,
+) -> Result<()> {
+    let req = ListVsockPortsRequest::default();
+
+    let ctx = clone_context(ctx);
+
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+    let reply = client
+        .list_vsock_ports(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
+
+    Ok(())
+}
+
+fn agent_cmd_container_add_probe(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    options: &mut Options,
+    args: &str,
+) -> Result<()> {
+    let mut req = AddProbeRequest::default();
+    let mut probe = Probe::default();
+
+    let ctx = clone_context(ctx);
+
+    probe.set_id(utils::get_option("id", options, args));
+    probe.set_container_id(utils::get_option("cid", options, args));
+
+    let probe_type = utils::get_option("type", options, args);
+    probe.set_field_type(match probe_type.as_str() {
+        "" | "exec" => ProbeType::EXEC,
+        "tcp" => ProbeType::TCP,
+        "http" => ProbeType::HTTP,
+        _ => return Err(anyhow!("invalid probe type {:?}", probe_type)),
+    });
+
+    // space separated command of the exec probes
+    let cmd = utils::get_option("cmd", options, args);
+    probe.set_command(RepeatedField::from_vec(
+        cmd.split_whitespace().map(|s| s.to_string()).collect(),
+    ));
+
+    let port_str = utils::get_option("port", options, args);
+    if port_str != "" {
+        let port = port_str
+            .parse::<u32>()
+            .map_err(|e| anyhow!(e).context("invalid port"))?;
+        probe.set_port(port);
+    }
+
+    probe.set_path(utils::get_option("path", options, args));
+
+    let period_str = utils::get_option("period", options, args);
+    if period_str != "" {
+        let period = period_str
+            .parse::<u32>()
+            .map_err(|e| anyhow!(e).context("invalid period"))?;
+        probe.set_period_seconds(period);
+    }
+
+    req.set_probe(probe);
+
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+    let reply = client
+        .add_probe(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
+
+    Ok(())
+}
+
+fn agent_cmd_container_remove_probe(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    options: &mut Options,
+    args: &str,
+) -> Result<()> {
+    let mut req = RemoveProbeRequest::default();
+
+    let ctx = clone_context(ctx);
+
+    let id = utils::get_option("id", options, args);
+    req.set_id(id);
+
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+    let reply = client
+        .remove_probe(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
+
+    Ok(())
+}
+
+fn agent_cmd_sandbox_get_probe_event(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    _options: &mut Options,
+    _args: &str,
+) -> Result<()> {
+    let req = GetProbeEventRequest::default();
+
+    let ctx = clone_context(ctx);
+
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+    let reply = client
+        .get_probe_event(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
+
+    Ok(())
+}
+
+fn agent_cmd_container_get_usage_history(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    options: &mut Options,
+    args: &str,
+) -> Result<()> {
+    let mut req = GetUsageHistoryRequest::default();
+
+    let ctx = clone_context(ctx);
+
+    let cid = utils::get_option("cid", options, args);
+
+    req.set_container_id(cid);
+
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+    let reply = client
+        .get_usage_history(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
+
+    Ok(())
+}
+
+fn agent_cmd_container_list_mounts(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    options: &mut Options,
+    args: &str,
+) -> Result<()> {
+    let mut req = ListContainerMountsRequest::default();
+
+    let ctx = clone_context(ctx);
+
+    let cid = utils::get_option("cid", options, args);
+
+    req.set_container_id(cid);
+
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+    let reply = client
+        .list_container_mounts(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
+
+    Ok(())
+}
+
+fn agent_cmd_container_add_fs_watch(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    options: &mut Options,
+    args: &str,
+) -> Result<()> {
+    let mut req = AddFsWatchRequest::default();
+
+    let ctx = clone_context(ctx);
+
+    req.set_id(utils::get_option("id", options, args));
+    req.set_container_id(utils::get_option("cid", options, args));
+    req.set_path(utils::get_option("path", options, args));
+
+    let depth_str = utils::get_option("depth", options, args);
+    if depth_str != "" {
+        let depth = depth_str
+            .parse::<u32>()
+            .map_err(|e| anyhow!(e).context("invalid depth"))?;
+        req.set_depth(depth);
+    }
+
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+    let reply = client
+        .add_fs_watch(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
+
+    Ok(())
+}
+
+fn agent_cmd_container_remove_fs_watch(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    options: &mut Options,
+    args: &str,
+) -> Result<()> {
+    let mut req = RemoveFsWatchRequest::default();
+
+    let ctx = clone_context(ctx);
+
+    let id = utils::get_option("id", options, args);
+    req.set_id(id);
+
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+    let reply = client
+        .remove_fs_watch(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
+
+    Ok(())
+}
+
+fn agent_cmd_sandbox_get_fs_event(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    _options: &mut Options,
+    _args: &str,
+) -> Result<()> {
+    let req = GetFsEventRequest::default();
+
+    let ctx = clone_context(ctx);
+
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+    let reply = client
+        .get_fs_event(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
+
+    Ok(())
+}
+
+fn agent_cmd_sandbox_get_warning(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    _options: &mut Options,
+    _args: &str,
+) -> Result<()> {
+    let req = GetWarningRequest::default();
+
+    let ctx = clone_context(ctx);
+
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+    let reply = client
+        .get_warning(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
+
+    Ok(())
+}
+
+fn agent_cmd_container_dump_memory(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    options: &mut Options,
+    args: &str,
+) -> Result<()> {
+    let mut req = DumpProcessMemoryRequest::default();
+
+    let cid = utils::get_option("cid", options, args);
+    let exec_id = utils::get_option("exec_id", options, args);
+    let path = utils::get_option("file", options, args);
+    if path == "" {
+        return Err(anyhow!("need a file to write the dump to"));
+    }
+
+    req.set_container_id(cid);
+    req.set_exec_id(exec_id);
+
+    let mut file = File::create(&path).map_err(|e| anyhow!(e).context("failed to create file"))?;
+
+    loop {
+        let ctx = clone_context(ctx);
+
+        debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+        let reply = client
+            .dump_process_memory(ctx, &req)
+            .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+        file.write_all(&reply.data)?;
+        req.offset += reply.data.len() as u64;
+
+        if reply.eof {
+            info!(sl!(), "memory dump written";
+                "file" => &path, "size" => req.offset, "truncated" => reply.truncated);
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+fn agent_cmd_sandbox_copy_file(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    options: &mut Options,
+    args: &str,
+) -> Result<()> {
+    let mut req = CopyFileRequest::default();
+
+    let ctx = clone_context(ctx);
+
+    let path = utils::get_option("path", options, args);
+    if path != "" {
+        req.set_path(path);
+    }
+
+    let file_size_str = utils::get_option("file_size", options, args);
+
+    if file_size_str != "" {
+        let file_size = file_size_str
+            .parse::<i64>()
+            .map_err(|e| anyhow!(e).context("invalid file_size"))?;
+
+        req.set_file_size(file_size);
+    }
+
+    let file_mode_str = utils::get_option("file_mode", options, args);
+
+    if file_mode_str != "" {
+        let file_mode = file_mode_str
+            .parse::<u32>()
+            .map_err(|e| anyhow!(e).context("invalid file_mode"))?;
+
+        req.set_file_mode(file_mode);
+    }
+
+    let dir_mode_str = utils::get_option("dir_mode", options, args);
+
+    if dir_mode_str != "" {
+        let dir_mode = dir_mode_str
+            .parse::<u32>()
+            .map_err(|e| anyhow!(e).context("invalid dir_mode"))?;
+
+        req.set_dir_mode(dir_mode);
+    }
+
+    let uid_str = utils::get_option("uid", options, args);
+
+    if uid_str != "" {
+        let uid = uid_str
+            .parse::<i32>()
+            .map_err(|e| anyhow!(e).context("invalid uid"))?;
 
         req.set_uid(uid);
     }
@@ -1709,6 +2481,41 @@ fn agent_cmd_sandbox_online_cpu_mem(
     Ok(())
 }
 
+fn agent_cmd_sandbox_set_guest_clock(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    options: &mut Options,
+    args: &str,
+) -> Result<()> {
+    let mut req = SetGuestClockRequest::default();
+    let mut clock = ClockConfig::default();
+
+    let ctx = clone_context(ctx);
+
+    clock.set_timezone(utils::get_option("timezone", options, args));
+
+    let rtc = utils::get_option("rtc", options, args);
+    clock.set_rtc_mode(match rtc.as_str() {
+        "" | "utc" => RtcMode::RTC_UTC,
+        "local" => RtcMode::RTC_LOCAL,
+        _ => return Err(anyhow!("invalid rtc mode {:?}", rtc)),
+    });
+
+    req.set_clock(clock);
+
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+    let reply = client
+        .set_guest_clock(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
+
+    Ok(())
+}
+
 fn agent_cmd_sandbox_set_guest_date_time(
     ctx: &Context,
     client: &AgentServiceClient,
@@ -1808,6 +2615,123 @@ fn agent_cmd_sandbox_update_container(
     Ok(())
 }
 
+fn agent_cmd_container_validate_resources(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    options: &mut Options,
+    args: &str,
+) -> Result<()> {
+    let mut req = UpdateContainerRequest::default();
+
+    let ctx = clone_context(ctx);
+
+    let cid = utils::get_option("cid", options, args);
+
+    req.set_container_id(cid);
+
+    // FIXME: Implement fully
+    eprintln!("FIXME: 'ValidateResources' not fully implemented");
+
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+    let reply = client
+        .validate_resources(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
+
+    Ok(())
+}
+
+fn agent_cmd_container_add_device_rule(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    options: &mut Options,
+    args: &str,
+) -> Result<()> {
+    let mut req = DeviceRuleRequest::default();
+
+    let ctx = clone_context(ctx);
+
+    let cid = utils::get_option("cid", options, args);
+
+    req.set_container_id(cid);
+
+    // FIXME: Implement fully
+    eprintln!("FIXME: 'AddDeviceRule' not fully implemented");
+
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+    let reply = client
+        .add_device_rule(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
+
+    Ok(())
+}
+
+fn agent_cmd_container_remove_device_rule(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    options: &mut Options,
+    args: &str,
+) -> Result<()> {
+    let mut req = DeviceRuleRequest::default();
+
+    let ctx = clone_context(ctx);
+
+    let cid = utils::get_option("cid", options, args);
+
+    req.set_container_id(cid);
+
+    // FIXME: Implement fully
+    eprintln!("FIXME: 'RemoveDeviceRule' not fully implemented");
+
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+    let reply = client
+        .remove_device_rule(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
+
+    Ok(())
+}
+
+fn agent_cmd_container_list_device_rules(
+    ctx: &Context,
+    client: &AgentServiceClient,
+    _health: &HealthClient,
+    options: &mut Options,
+    args: &str,
+) -> Result<()> {
+    let mut req = ListDeviceRulesRequest::default();
+
+    let ctx = clone_context(ctx);
+
+    let cid = utils::get_option("cid", options, args);
+
+    req.set_container_id(cid);
+
+    debug!(sl!(), "sending request"; "request" => format!("{:?}", req));
+
+    let reply = client
+        .list_device_rules(ctx, &req)
+        .map_err(|e| anyhow!("{:?}", e).context(ERR_API_FAILED))?;
+
+    info!(sl!(), "response received";
+        "response" => format!("{:?}", reply));
+
+    Ok(())
+}
+
 fn agent_cmd_sandbox_mem_hotplug_by_probe(
     ctx: &Context,
     client: &AgentServiceClient,
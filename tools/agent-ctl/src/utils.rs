@@ -8,7 +8,8 @@ use anyhow::{anyhow, Result};
 use oci::{Process as ociProcess, Root as ociRoot, Spec as ociSpec};
 use protocols::oci::{
     Box as grpcBox, Linux as grpcLinux, LinuxCapabilities as grpcLinuxCapabilities,
-    Process as grpcProcess, Root as grpcRoot, Spec as grpcSpec, User as grpcUser,
+    Process as grpcProcess, Root as grpcRoot, Scheduler as grpcScheduler, Spec as grpcSpec,
+    User as grpcUser,
 };
 use rand::Rng;
 use slog::{debug, warn};
@@ -295,6 +296,19 @@ fn process_oci_to_grpc(p: &ociProcess) -> grpcProcess {
         None => 0,
     };
 
+    let scheduler = match &p.scheduler {
+        Some(s) => {
+            let mut sched = grpcScheduler::new();
+
+            sched.set_Policy(s.policy.clone());
+            sched.set_Nice(s.nice);
+            sched.set_Priority(s.priority);
+
+            protobuf::SingularPtrField::some(sched)
+        }
+        None => protobuf::SingularPtrField::none(),
+    };
+
     let mut user = grpcUser::new();
     user.set_UID(p.user.uid);
     user.set_GID(p.user.gid);
@@ -323,6 +337,7 @@ fn process_oci_to_grpc(p: &ociProcess) -> grpcProcess {
         ApparmorProfile: p.apparmor_profile.clone(),
         OOMScoreAdj: oom_score_adj,
         SelinuxLabel: p.selinux_label.clone(),
+        Scheduler: scheduler,
         unknown_fields: protobuf::UnknownFields::new(),
         cached_size: protobuf::CachedSize::default(),
     }